@@ -0,0 +1,194 @@
+//! Implement the PV application layer sink to total up each node's output.
+//!
+//! ```console
+//! % cargo run --example custom_sink -- bytes.bin
+//! ```
+//!
+//! [`Observer`] is one implementation of the sink traits, but it is not the only possible one. A
+//! [`Pipeline`] hands its sink both gateway transport activity, via [`TransportSink`], and PV
+//! application layer activity, via [`ApplicationSink`]. This sink ignores nearly everything, and
+//! sums the power reports.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use taptap::prelude::*;
+
+/// Totals for a single node.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct NodeTotals {
+    reports: u64,
+    /// The sum of each report's output power, in watts.
+    power: f64,
+    /// The greatest output power, in watts.
+    max_power: f64,
+}
+
+#[derive(Debug, Default)]
+struct Totals(BTreeMap<(GatewayID, NodeID), NodeTotals>);
+
+impl ApplicationSink for Totals {
+    fn power_report(&mut self, gateway_id: GatewayID, pv_node_id: NodeID, report: &PowerReport) {
+        let power = report.voltage_out() * report.current();
+
+        let totals = self.0.entry((gateway_id, pv_node_id)).or_default();
+        totals.reports += 1;
+        totals.power += power;
+        totals.max_power = totals.max_power.max(power);
+    }
+
+    fn string_request(&mut self, _gateway_id: GatewayID, _pv_node_id: NodeID, _request: LossyStr) {}
+
+    fn string_response(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _response: LossyStr,
+    ) {
+    }
+
+    fn broadcast(&mut self, _gateway_id: GatewayID, _payload: &Broadcast) {}
+
+    fn network_status(&mut self, _gateway_id: GatewayID, _status: &NetworkStatusResponse) {}
+
+    fn gateway_radio_configuration(
+        &mut self,
+        _gateway_id: GatewayID,
+        _config: &RadioConfigurationResponse,
+    ) {
+    }
+
+    fn pv_configuration(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _config: &PvConfigurationResponse,
+    ) {
+    }
+
+    fn node_table_page(
+        &mut self,
+        _gateway_id: GatewayID,
+        _start_address: NodeAddress,
+        _nodes: &[NodeTableResponseEntry],
+    ) {
+    }
+
+    fn topology_report(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _topology_report: &TopologyReport,
+    ) {
+    }
+
+    fn packet_loss_estimated(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _estimated_loss_pct: f64,
+    ) {
+    }
+}
+
+impl TransportSink for Totals {
+    fn enumeration_started(&mut self, _enumeration_gateway_id: GatewayID) {}
+
+    fn gateway_identity_observed(&mut self, _gateway_id: GatewayID, _address: LongAddress) {}
+
+    fn gateway_version_observed(&mut self, _gateway_id: GatewayID, _version: &str, _raw: &[u8]) {}
+
+    fn enumeration_ended(&mut self, _gateway_id: GatewayID) {}
+
+    fn gateway_slot_counter_captured(&mut self, _gateway_id: GatewayID) {}
+
+    fn gateway_slot_counter_observed(
+        &mut self,
+        _gateway_id: GatewayID,
+        _slot_counter: SlotCounter,
+    ) {
+    }
+
+    fn packet_received(
+        &mut self,
+        _gateway_id: GatewayID,
+        _header: &ReceivedPacketHeader,
+        _data: &[u8],
+    ) {
+    }
+
+    fn command_executed(
+        &mut self,
+        _gateway_id: GatewayID,
+        _request: (PacketType, &[u8]),
+        _response: (PacketType, &[u8]),
+    ) {
+    }
+
+    fn command_timed_out(
+        &mut self,
+        _gateway_id: GatewayID,
+        _packet_type: PacketType,
+        _sequence_number: CommandSequenceNumber,
+    ) {
+    }
+
+    fn gateway_tx_buffers_free_observed(&mut self, _gateway_id: GatewayID, _tx_buffers_free: u8) {}
+}
+
+fn total(mut input: impl Read) -> std::io::Result<Totals> {
+    let mut rx = pipeline(Totals::default());
+
+    let mut buffer = [0u8; 1024];
+    loop {
+        let n = input.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        rx.extend_from_slice(&buffer[..n]);
+    }
+
+    Ok(rx.into_inner().into_inner().into_inner())
+}
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/sample.bin")
+}
+
+fn main() -> std::io::Result<()> {
+    let path = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(fixture);
+
+    let totals = total(std::fs::File::open(path)?)?;
+    println!(
+        "{:>8} {:>5} {:>8} {:>10} {:>10}",
+        "gateway", "node", "reports", "mean W", "max W"
+    );
+    for ((gateway_id, node_id), totals) in &totals.0 {
+        println!(
+            "{:>8} {:>5} {:>8} {:>10.1} {:>10.1}",
+            gateway_id.to_string(),
+            u16::from(*node_id),
+            totals.reports,
+            totals.power / totals.reports as f64,
+            totals.max_power
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn sample() {
+    let totals = total(std::fs::File::open(fixture()).unwrap()).unwrap();
+
+    // Four nodes reporting twice each
+    assert_eq!(totals.0.len(), 4);
+    for totals in totals.0.values() {
+        assert_eq!(totals.reports, 2);
+        assert!(totals.max_power > 150.0 && totals.max_power < 250.0);
+        assert!(totals.power > totals.max_power);
+    }
+}
@@ -0,0 +1,80 @@
+//! Read a capture file and print the events an observer produces from it, as JSON.
+//!
+//! ```console
+//! % cargo run --example decode_capture -- foo.taptap
+//! ```
+//!
+//! This is what `taptap replay` does, composed from the library's parts: a capture
+//! [`Reader`](taptap::capture::Reader) supplies bytes, which pass through the gateway link and
+//! transport layers and the PV application layer before reaching an [`Observer`].
+
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use taptap::capture;
+use taptap::observer::clock::ManualClock;
+use taptap::observer::diagnostic;
+use taptap::prelude::*;
+
+fn decode(path: &Path) -> std::io::Result<Vec<Event>> {
+    let reader = capture::Reader::new(std::fs::File::open(path)?)?;
+
+    // Timestamp events as of when the data was captured, rather than now
+    let clock = ManualClock::new(UNIX_EPOCH);
+
+    // Collect events rather than printing them, and keep diagnostics out of the way
+    let (events_tx, events) = std::sync::mpsc::channel();
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_event_sink(events_tx);
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+
+    // Stack the layers: bytes -> frames -> transport activity -> PV packets -> events
+    let mut rx = pipeline(observer);
+
+    for record in reader {
+        let (bytes, timestamp) = record?;
+        clock.set(timestamp);
+        rx.extend_from_slice(&bytes);
+    }
+    rx.sink_mut().sink_mut().sink_mut().shutdown();
+
+    Ok(events.try_iter().collect())
+}
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/sample.taptap")
+}
+
+fn main() -> std::io::Result<()> {
+    let path = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(fixture);
+
+    for event in decode(&path)? {
+        println!("{}", serde_json::to_string(&event)?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn sample() {
+    let events = decode(&fixture()).unwrap();
+
+    let power_reports: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::PowerReport(report) => Some(report),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(power_reports.len(), 8);
+
+    // The capture includes an enumeration and a node table walk, so every gateway and node is
+    // identified by its hardware address
+    for report in power_reports {
+        assert!(report.gateway.address.is_some());
+        assert!(report.node.address.is_some());
+    }
+}
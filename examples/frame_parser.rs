@@ -0,0 +1,80 @@
+//! Parse raw bytes from the gateway bus into link layer frames.
+//!
+//! ```console
+//! % cargo run --example frame_parser -- bytes.bin
+//! % nc 172.21.3.44 7160 | cargo run --example frame_parser -- -
+//! ```
+//!
+//! The link layer [`Receiver`] accepts bytes in arbitrary chunks and hands each complete frame to
+//! a [`Sink`]. `Vec<Frame>` is the simplest sink: it collects them.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use taptap::gateway::link::{Counters, Receiver, Sink};
+use taptap::gateway::Frame;
+
+/// A sink which prints each frame as it arrives.
+struct Printer;
+
+impl Sink for Printer {
+    fn frame(&mut self, frame: Frame) {
+        println!(
+            "{:?} {:?} {:02X?}",
+            frame.address, frame.frame_type, frame.payload
+        );
+    }
+}
+
+/// Parse frames from a reader, handing them to a sink.
+fn parse<S: Sink>(mut input: impl Read, sink: S) -> std::io::Result<(S, Counters)> {
+    let mut rx = Receiver::new(sink);
+
+    let mut buffer = [0u8; 1024];
+    loop {
+        let n = input.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        rx.extend_from_slice(&buffer[..n]);
+    }
+
+    let counters = *rx.counters();
+    Ok((rx.into_inner(), counters))
+}
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/sample.bin")
+}
+
+fn main() -> std::io::Result<()> {
+    let path = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(fixture);
+
+    let (_, counters) = if path == Path::new("-") {
+        parse(std::io::stdin().lock(), Printer)?
+    } else {
+        parse(std::fs::File::open(path)?, Printer)?
+    };
+    eprintln!("{:#?}", counters);
+
+    Ok(())
+}
+
+#[test]
+fn sample() {
+    use taptap::gateway::link::{Address, Type};
+
+    let file = std::fs::File::open(fixture()).unwrap();
+    let (frames, counters) = parse(file, Vec::new()).unwrap();
+
+    // The controller starts by enumerating the gateways
+    assert_eq!(
+        frames[0].address,
+        Address::To(taptap::gateway::GatewayID::ZERO)
+    );
+    assert_eq!(frames[0].frame_type, Type::ENUMERATION_START_REQUEST);
+    assert_eq!(counters.frames as usize, frames.len());
+    assert_eq!(counters.checksums, 0);
+}
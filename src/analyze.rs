@@ -0,0 +1,327 @@
+//! Analysis of the events observed over a long period, such as a capture.
+//!
+//! [`Analysis`] summarizes how often each node reports. By default it retains every interval
+//! between reports so that it can compute exact statistics, which requires memory proportional to
+//! the length of the capture. In [`Mode::Sketched`], it instead estimates quantiles using
+//! [`P2Quantile`] sketches, which require constant memory per node regardless of the length of
+//! the capture. Sketched quantiles are typically within a few percent of the exact values, but
+//! are least accurate for the extreme quantiles of small or long-tailed samples.
+//!
+//! [`Matrix`] instead tabulates one field of every node's power reports over time, for export to
+//! a spreadsheet.
+
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::observer::event::{Event, Gateway, Node};
+use crate::pv::NodeID;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+mod matrix;
+mod p2;
+pub use matrix::{Field, Matrix, UnknownFieldError};
+pub use p2::{exact_quantile, P2Quantile};
+
+/// The quantiles of the interval between reports which are included in each report.
+pub const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// How an [`Analysis`] computes statistics.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Mode {
+    /// Retain every observation, and compute exact statistics.
+    #[default]
+    Exact,
+    /// Estimate statistics in constant memory.
+    Sketched,
+}
+
+/// An analysis of the events produced by an observer.
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    mode: Mode,
+    nodes: BTreeMap<(GatewayID, NodeID), NodeAnalysis>,
+}
+
+#[derive(Debug, Clone)]
+struct NodeAnalysis {
+    gateway: Gateway,
+    node: Node,
+    reports: u64,
+    last_report: DateTime<Local>,
+    intervals: Intervals,
+}
+
+#[derive(Debug, Clone)]
+enum Intervals {
+    Exact(Vec<f64>),
+    Sketched {
+        quantiles: Box<[P2Quantile; QUANTILES.len()]>,
+        max: Option<f64>,
+    },
+}
+
+impl Intervals {
+    fn new(mode: Mode) -> Self {
+        match mode {
+            Mode::Exact => Intervals::Exact(Vec::new()),
+            Mode::Sketched => Intervals::Sketched {
+                quantiles: Box::new(QUANTILES.map(P2Quantile::new)),
+                max: None,
+            },
+        }
+    }
+
+    fn push(&mut self, interval: f64) {
+        match self {
+            Intervals::Exact(intervals) => intervals.push(interval),
+            Intervals::Sketched { quantiles, max } => {
+                for quantile in quantiles.iter_mut() {
+                    quantile.push(interval);
+                }
+                *max = Some(max.map_or(interval, |max| max.max(interval)));
+            }
+        }
+    }
+
+    /// The approximate number of bytes allocated for these intervals.
+    fn approximate_bytes(&self) -> usize {
+        match self {
+            Intervals::Exact(intervals) => intervals.capacity() * std::mem::size_of::<f64>(),
+            Intervals::Sketched { .. } => std::mem::size_of::<[P2Quantile; QUANTILES.len()]>(),
+        }
+    }
+
+    fn statistics(&self) -> Option<IntervalStatistics> {
+        match self {
+            Intervals::Exact(intervals) if intervals.is_empty() => None,
+            Intervals::Exact(intervals) => {
+                let mut sorted = intervals.clone();
+                sorted.sort_by(f64::total_cmp);
+                Some(IntervalStatistics {
+                    quantiles: QUANTILES.map(|p| exact_quantile(&sorted, p)),
+                    max: *sorted.last().unwrap(),
+                })
+            }
+            Intervals::Sketched { quantiles, max } => Some(IntervalStatistics {
+                quantiles: [
+                    quantiles[0].estimate()?,
+                    quantiles[1].estimate()?,
+                    quantiles[2].estimate()?,
+                ],
+                max: (*max)?,
+            }),
+        }
+    }
+}
+
+impl Analysis {
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            nodes: Default::default(),
+        }
+    }
+
+    /// Account for an event.
+    pub fn push(&mut self, event: &Event) {
+        let Event::PowerReport(report) = event else {
+            return;
+        };
+
+        let key = (report.gateway.id, report.node.id);
+        let Some(node) = self.nodes.get_mut(&key) else {
+            self.nodes.insert(
+                key,
+                NodeAnalysis {
+                    gateway: report.gateway,
+                    node: report.node,
+                    reports: 1,
+                    last_report: report.timestamp,
+                    intervals: Intervals::new(self.mode),
+                },
+            );
+            return;
+        };
+
+        node.gateway = report.gateway;
+        node.node = report.node;
+        node.reports += 1;
+        if let Ok(interval) = (report.timestamp - node.last_report).to_std() {
+            node.intervals.push(interval.as_secs_f64());
+        }
+        node.last_report = node.last_report.max(report.timestamp);
+    }
+
+    /// The approximate number of bytes this analysis occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<(GatewayID, NodeID), NodeAnalysis>(self.nodes.len())
+            + self
+                .nodes
+                .values()
+                .map(|node| node.intervals.approximate_bytes())
+                .sum::<usize>()
+    }
+
+    /// Summarize the analysis so far.
+    pub fn report(&self) -> Report {
+        Report {
+            sketched: self.mode == Mode::Sketched,
+            nodes: self
+                .nodes
+                .values()
+                .map(|node| NodeReport {
+                    gateway: node.gateway,
+                    node: node.node,
+                    reports: node.reports,
+                    intervals: node.intervals.statistics(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A summary of an [`Analysis`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// Whether the statistics in this report are estimates rather than exact values.
+    pub sketched: bool,
+    pub nodes: Vec<NodeReport>,
+}
+
+/// A summary of a single node's reports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeReport {
+    pub gateway: Gateway,
+    pub node: Node,
+    /// The number of power reports received.
+    pub reports: u64,
+    /// Statistics describing the intervals between reports, if there were at least two reports.
+    pub intervals: Option<IntervalStatistics>,
+}
+
+/// Statistics describing the intervals between reports, in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IntervalStatistics {
+    /// The quantiles listed in [`QUANTILES`], in order.
+    pub quantiles: [f64; QUANTILES.len()],
+    pub max: f64,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>8} {:>5} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "gateway", "node", "reports", "p50", "p90", "p99", "max"
+        )?;
+        for node in &self.nodes {
+            write!(
+                f,
+                "{:>8} {:>5} {:>8}",
+                format!("{:?}", node.gateway.id),
+                format!("{:?}", node.node.id),
+                node.reports
+            )?;
+            match &node.intervals {
+                Some(intervals) => {
+                    for quantile in intervals.quantiles {
+                        write!(f, " {:>7.1}s", quantile)?;
+                    }
+                    writeln!(f, " {:>7.1}s", intervals.max)?;
+                }
+                None => writeln!(f)?,
+            }
+        }
+        if self.sketched {
+            writeln!(
+                f,
+                "Interval quantiles are estimated using constant memory, and may differ slightly \
+                 from exact values."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::event::PowerReportEvent;
+    use crate::pv::physical::RSSI;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn sketched_matches_exact() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let mut exact = Analysis::new(Mode::Exact);
+        let mut sketched = Analysis::new(Mode::Sketched);
+
+        // Three nodes reporting nominally every 20 seconds for a day, with jitter and occasional
+        // missed reports
+        let mut state = 12345u64;
+        let mut random = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 33) as u32
+        };
+        for node_id in 2..5u16 {
+            let mut time = t;
+            for _ in 0..4000 {
+                let missed = if random() % 50 == 0 { 2 } else { 1 };
+                time += Duration::from_millis(20000 * missed + (random() % 2000) as u64);
+
+                let event = Event::PowerReport(PowerReportEvent {
+                    gateway: Gateway {
+                        id: GatewayID::try_from(0x1201).unwrap(),
+                        address: None,
+                        provenance: None,
+                    },
+                    node: Node {
+                        id: NodeID::try_from(node_id).unwrap(),
+                        address: None,
+                        provenance: None,
+                        home_gateway: None,
+                    },
+                    timestamp: time.into(),
+                    voltage_in: 30.0,
+                    voltage_out: 30.0,
+                    current: 6.0,
+                    power_w: 180.0,
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                    node_unverified: false,
+                    timestamp_uncertain: false,
+                    energy_wh_today: None,
+                    seconds_since_previous_report: None,
+                    source: None,
+                });
+                exact.push(&event);
+                sketched.push(&event);
+            }
+        }
+
+        let exact = exact.report();
+        let sketched = sketched.report();
+        assert!(!exact.sketched);
+        assert!(sketched.sketched);
+        assert_eq!(exact.nodes.len(), 3);
+
+        for (exact, sketched) in exact.nodes.iter().zip(&sketched.nodes) {
+            assert_eq!(exact.reports, 4000);
+            assert_eq!(exact.reports, sketched.reports);
+
+            let exact = exact.intervals.as_ref().unwrap();
+            let sketched = sketched.intervals.as_ref().unwrap();
+            assert_eq!(exact.max, sketched.max);
+            for (exact, sketched) in exact.quantiles.iter().zip(sketched.quantiles) {
+                assert!(
+                    (exact - sketched).abs() / exact < 0.05,
+                    "exact {} vs sketched {}",
+                    exact,
+                    sketched
+                );
+            }
+        }
+    }
+}
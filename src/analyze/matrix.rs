@@ -0,0 +1,277 @@
+use crate::barcode::Barcode;
+use crate::gateway::link::GatewayID;
+use crate::observer::event::{Event, PowerReportEvent};
+use crate::pv::NodeID;
+use chrono::{DateTime, Local, TimeZone};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+
+/// A quantity reported in each power report, which can be tabulated in a [`Matrix`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    VoltageIn,
+    VoltageOut,
+    Current,
+    /// The output power in watts, i.e. `voltage_out * current`.
+    PowerOut,
+    DcDcDutyCycle,
+    Temperature,
+    Rssi,
+}
+
+impl Field {
+    /// The field's name, as it appears in power report events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::VoltageIn => "voltage_in",
+            Field::VoltageOut => "voltage_out",
+            Field::Current => "current",
+            Field::PowerOut => "power_out",
+            Field::DcDcDutyCycle => "dc_dc_duty_cycle",
+            Field::Temperature => "temperature",
+            Field::Rssi => "rssi",
+        }
+    }
+
+    /// The field's value in a power report.
+    pub fn value(&self, report: &PowerReportEvent) -> f64 {
+        match self {
+            Field::VoltageIn => report.voltage_in,
+            Field::VoltageOut => report.voltage_out,
+            Field::Current => report.current,
+            Field::PowerOut => report.voltage_out * report.current,
+            Field::DcDcDutyCycle => report.dc_dc_duty_cycle,
+            Field::Temperature => report.temperature,
+            Field::Rssi => report.rssi.0 as f64,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown field {0:?}")]
+pub struct UnknownFieldError(String);
+
+impl std::str::FromStr for Field {
+    type Err = UnknownFieldError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Field::VoltageIn,
+            Field::VoltageOut,
+            Field::Current,
+            Field::PowerOut,
+            Field::DcDcDutyCycle,
+            Field::Temperature,
+            Field::Rssi,
+        ]
+        .into_iter()
+        .find(|field| field.name() == s)
+        .ok_or_else(|| UnknownFieldError(s.into()))
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The column in which a node's values are tabulated.
+///
+/// Nodes are identified by barcode where possible, since barcodes are permanent. Nodes whose
+/// hardware address is not yet known sort after all barcodes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+enum Column {
+    Barcode(Barcode),
+    Unaddressed(GatewayID, NodeID),
+}
+
+impl std::fmt::Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Column::Barcode(barcode) => write!(f, "{}", barcode),
+            Column::Unaddressed(gateway_id, node_id) => write!(f, "{}/{}", gateway_id, node_id),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Mean {
+    sum: f64,
+    count: u32,
+}
+
+/// A time-aligned table of one field of every node's power reports.
+///
+/// Power reports are grouped into buckets of a fixed duration according to their timestamps, and
+/// averaged within each bucket. The resulting CSV has one row per bucket in which any node
+/// reported and one column per node, sorted by barcode, with blank cells where a node didn't
+/// report during a bucket.
+#[derive(Debug, Clone)]
+pub struct Matrix {
+    field: Field,
+    bucket_secs: i64,
+    columns: BTreeMap<Column, usize>,
+    // Bucket start time => column index => mean
+    rows: BTreeMap<i64, BTreeMap<usize, Mean>>,
+}
+
+impl Matrix {
+    /// Tabulate `field` in buckets of `bucket` duration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is not a whole, positive number of seconds.
+    pub fn new(field: Field, bucket: Duration) -> Self {
+        assert!(
+            bucket.as_secs() > 0 && bucket.subsec_nanos() == 0,
+            "bucket size {:?} is not a whole number of seconds",
+            bucket
+        );
+        Self {
+            field,
+            bucket_secs: bucket.as_secs() as i64,
+            columns: Default::default(),
+            rows: Default::default(),
+        }
+    }
+
+    /// Account for an event.
+    pub fn push(&mut self, event: &Event) {
+        let Event::PowerReport(report) = event else {
+            return;
+        };
+
+        let column = match report.node.address {
+            Some(address) => Column::Barcode(Barcode(address)),
+            None => Column::Unaddressed(report.gateway.id, report.node.id),
+        };
+        let next_index = self.columns.len();
+        let index = *self.columns.entry(column).or_insert(next_index);
+
+        let bucket = report.timestamp.timestamp().div_euclid(self.bucket_secs) * self.bucket_secs;
+        let mean = self
+            .rows
+            .entry(bucket)
+            .or_default()
+            .entry(index)
+            .or_default();
+        mean.sum += self.field.value(report);
+        mean.count += 1;
+    }
+
+    /// Write the matrix as CSV.
+    ///
+    /// The first row names the field and bucket size, followed by each column's barcode. Each
+    /// subsequent row starts with the time at which its bucket begins.
+    pub fn write_csv<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        write!(w, "{} mean per {}s", self.field, self.bucket_secs)?;
+        for column in self.columns.keys() {
+            write!(w, ",{}", column)?;
+        }
+        writeln!(w)?;
+
+        for (bucket, means) in &self.rows {
+            let start: DateTime<Local> = Local.timestamp_opt(*bucket, 0).unwrap();
+            write!(w, "{}", start.to_rfc3339())?;
+            for index in self.columns.values() {
+                match means.get(index) {
+                    Some(mean) => write!(w, ",{}", mean.sum / mean.count as f64)?,
+                    None => write!(w, ",")?,
+                }
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::event::{Gateway, Node};
+    use crate::pv::physical::RSSI;
+    use crate::pv::LongAddress;
+    use std::time::SystemTime;
+
+    fn barcode(n: u8) -> Barcode {
+        Barcode(LongAddress([0x04, 0xc0, 0x5b, 0x40, 0x00, 0x9a, 0x57, n]))
+    }
+
+    fn report(node_id: u16, barcode: Option<Barcode>, time: SystemTime, current: f64) -> Event {
+        Event::PowerReport(PowerReportEvent {
+            gateway: Gateway {
+                id: GatewayID::try_from(0x1201).unwrap(),
+                address: None,
+                provenance: None,
+            },
+            node: Node {
+                id: NodeID::try_from(node_id).unwrap(),
+                address: barcode.map(|barcode| barcode.0),
+                provenance: None,
+                home_gateway: None,
+            },
+            timestamp: time.into(),
+            voltage_in: 30.0,
+            voltage_out: 20.0,
+            current,
+            power_w: 30.0 * current,
+            dc_dc_duty_cycle: 1.0,
+            temperature: 25.0,
+            rssi: RSSI(120),
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        })
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!("power_out".parse(), Ok(Field::PowerOut));
+        assert_eq!("rssi".parse(), Ok(Field::Rssi));
+        assert!("power".parse::<Field>().is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let mut matrix = Matrix::new(Field::PowerOut, Duration::from_secs(60));
+
+        // Columns are sorted by barcode regardless of arrival order
+        let (a, b) = (barcode(0xa2), barcode(0xa3));
+        matrix.push(&report(3, Some(b), t, 1.0));
+        matrix.push(&report(2, Some(a), t + Duration::from_secs(10), 2.0));
+        matrix.push(&report(2, Some(a), t + Duration::from_secs(50), 4.0));
+        matrix.push(&report(4, None, t + Duration::from_secs(61), 1.5));
+        matrix.push(&report(3, Some(b), t + Duration::from_secs(130), 0.5));
+
+        let mut csv = Vec::new();
+        matrix.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let time = |offset: i64| {
+            Local
+                .timestamp_opt(1724497200 + offset, 0)
+                .unwrap()
+                .to_rfc3339()
+        };
+        assert_eq!(
+            csv,
+            format!(
+                "power_out mean per 60s,{},{},0x1201/0x0004\n\
+                 {},60,20,\n\
+                 {},,,30\n\
+                 {},,10,\n",
+                a,
+                b,
+                time(0),
+                time(60),
+                time(120)
+            )
+        );
+    }
+}
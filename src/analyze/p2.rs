@@ -0,0 +1,156 @@
+/// An estimator of a single quantile using the P² algorithm, in constant memory.
+///
+/// P² maintains five markers whose heights approximate the minimum, the maximum, the desired
+/// quantile, and the quantiles halfway between. Each observation adjusts the markers'
+/// heights using piecewise-parabolic interpolation. See Jain and Chlamtac, "The P² Algorithm for
+/// Dynamic Calculation of Quantiles and Histograms Without Storing Observations", CACM 1985.
+///
+/// Estimates converge as observations accumulate, but are not exact. Distributions with long
+/// tails or many repeated values are estimated least accurately.
+#[derive(Debug, Clone, PartialEq)]
+pub struct P2Quantile {
+    p: f64,
+    count: u64,
+    // Marker heights
+    q: [f64; 5],
+    // Marker positions
+    n: [f64; 5],
+    // Desired marker positions
+    np: [f64; 5],
+    // Increments in desired marker positions
+    dn: [f64; 5],
+}
+
+impl P2Quantile {
+    /// Estimate the `p` quantile, where `p` is between 0 and 1.
+    pub fn new(p: f64) -> Self {
+        assert!((0.0..=1.0).contains(&p), "quantile {} is out of range", p);
+        Self {
+            p,
+            count: 0,
+            q: [0.0; 5],
+            n: [0.0, 1.0, 2.0, 3.0, 4.0],
+            np: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            dn: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    /// The number of observations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Add an observation.
+    pub fn push(&mut self, x: f64) {
+        if self.count < 5 {
+            // Collect the first five observations verbatim
+            self.q[self.count as usize] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.q.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell containing x, extending the extremes if needed
+        let k = if x < self.q[0] {
+            self.q[0] = x;
+            0
+        } else if x >= self.q[4] {
+            self.q[4] = x;
+            3
+        } else {
+            (1..5).find(|i| x < self.q[*i]).unwrap() - 1
+        };
+
+        for i in k + 1..5 {
+            self.n[i] += 1.0;
+        }
+        for i in 0..5 {
+            self.np[i] += self.dn[i];
+        }
+
+        // Adjust the middle markers if they're out of position
+        for i in 1..4 {
+            let d = self.np[i] - self.n[i];
+            if (d >= 1.0 && self.n[i + 1] - self.n[i] > 1.0)
+                || (d <= -1.0 && self.n[i - 1] - self.n[i] < -1.0)
+            {
+                let d = d.signum();
+                let parabolic = self.parabolic(i, d);
+                self.q[i] = if self.q[i - 1] < parabolic && parabolic < self.q[i + 1] {
+                    parabolic
+                } else {
+                    self.linear(i, d)
+                };
+                self.n[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.q, &self.n);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = if d < 0.0 { i - 1 } else { i + 1 };
+        self.q[i] + d * (self.q[j] - self.q[i]) / (self.n[j] - self.n[i])
+    }
+
+    /// The current estimate, or `None` if there have been no observations.
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..=4 => {
+                let mut values = self.q[..self.count as usize].to_vec();
+                values.sort_by(f64::total_cmp);
+                Some(exact_quantile(&values, self.p))
+            }
+            _ => Some(self.q[2]),
+        }
+    }
+}
+
+/// The `p` quantile of sorted `values`, by nearest rank.
+pub fn exact_quantile(sorted: &[f64], p: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn few_observations() {
+        let mut p2 = P2Quantile::new(0.5);
+        assert_eq!(p2.estimate(), None);
+        for x in [3.0, 1.0, 2.0] {
+            p2.push(x);
+        }
+        assert_eq!(p2.estimate(), Some(2.0));
+    }
+
+    #[test]
+    fn uniform() {
+        // A permutation of 0..10000
+        let values: Vec<f64> = (0..10000u64).map(|i| ((i * 7919) % 10000) as f64).collect();
+
+        for p in [0.1, 0.5, 0.9, 0.99] {
+            let mut p2 = P2Quantile::new(p);
+            for x in &values {
+                p2.push(*x);
+            }
+            let estimate = p2.estimate().unwrap();
+            assert!(
+                (estimate - p * 10000.0).abs() < 100.0,
+                "p{}: {}",
+                p,
+                estimate
+            );
+        }
+    }
+}
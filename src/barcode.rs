@@ -36,6 +36,22 @@ impl std::str::FromStr for Barcode {
     }
 }
 
+/// Barcodes are serialized as they're printed, like `"4-9A57A2L"`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Barcode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Barcode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 impl std::fmt::Display for Barcode {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let bytes = &self.0 .0;
@@ -145,19 +161,19 @@ mod tests {
             super::crc(pv::LongAddress([
                 0x04, 0xC0, 0x5B, 0x40, 0x00, 0x9A, 0x57, 0xA2
             ])),
-            'L' as u8
+            b'L'
         );
         assert_eq!(
             super::crc(pv::LongAddress([
                 0x04, 0xC0, 0x5B, 0x40, 0x00, 0x79, 0xAC, 0x16
             ])),
-            'V' as u8
+            b'V'
         );
         assert_eq!(
             super::crc(pv::LongAddress([
                 0x04, 0xC0, 0x5B, 0x40, 0x00, 0x79, 0xAB, 0x99
             ])),
-            'W' as u8
+            b'W'
         );
     }
 
@@ -177,4 +193,16 @@ mod tests {
         assert_eq!(pv::LongAddress::from(Barcode(ADDR)), ADDR);
         assert_eq!(Barcode::from(ADDR), Barcode(ADDR));
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let json = serde_json::to_string(&Barcode(ADDR)).unwrap();
+        assert_eq!(json, "\"4-9A57A2L\"");
+        assert_eq!(
+            serde_json::from_str::<Barcode>(&json).unwrap(),
+            Barcode(ADDR)
+        );
+        assert!(serde_json::from_str::<Barcode>("\"4-9A57A2G\"").is_err());
+    }
 }
@@ -1,25 +1,162 @@
-use std::io::ErrorKind::UnexpectedEof;
+//! Capture files, recording the bytes read from the gateway bus along with when they were read.
+//!
+//! A capture is a gzip stream of records, each holding a timestamp and up to
+//! [`MAX_RECORD_LENGTH`] bytes. A capture may also carry [`Metadata`] describing how it was made,
+//! which is stored in the gzip header's extra field, so that anything reading only the records
+//! never sees it.
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::ErrorKind::{UnexpectedEof, WouldBlock};
 use std::io::{BufReader, Read, Write};
 use std::mem::size_of;
 use std::ops::Add;
+use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use zerocopy::{big_endian, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
 
+#[cfg(feature = "parsers")]
+pub mod merge;
+pub mod pcapng;
+
 const GZIP_HEADER_COMMENT: &[u8] = b"taptap capture";
 
+/// The gzip extra subfield ID under which metadata is stored.
+const METADATA_SUBFIELD_ID: [u8; 2] = *b"TM";
+
+/// The maximum number of data bytes in a single capture record.
+///
+/// [`Writer`] splits longer writes into multiple records.
+pub const MAX_RECORD_LENGTH: usize = u16::MAX as usize;
+
+/// Information about how a capture was made.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// The version of `taptap` which made the capture.
+    pub taptap_version: String,
+    /// The operating system on which the capture was made.
+    pub os: String,
+    /// The time at which capturing started.
+    pub started: DateTime<Local>,
+    /// A description of the source from which the capture was read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// A label for the site or the installation, as provided by whoever made the capture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// A free-form note, as provided by whoever made the capture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Metadata {
+    /// Describe a capture made by this process, starting at `started`.
+    pub fn new(started: DateTime<Local>) -> Self {
+        Self {
+            taptap_version: env!("CARGO_PKG_VERSION").into(),
+            os: std::env::consts::OS.into(),
+            started,
+            source: None,
+            label: None,
+            note: None,
+        }
+    }
+
+    /// Encode as a gzip extra field.
+    fn to_extra(&self) -> std::io::Result<Vec<u8>> {
+        let json = serde_json::to_vec(self)?;
+        let length = u16::try_from(json.len())
+            .ok()
+            // The extra field as a whole is limited to u16::MAX bytes
+            .filter(|length| usize::from(*length) <= u16::MAX as usize - 4)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "capture metadata is too long",
+                )
+            })?;
+
+        let mut extra = METADATA_SUBFIELD_ID.to_vec();
+        extra.extend_from_slice(&length.to_le_bytes());
+        extra.extend_from_slice(&json);
+        Ok(extra)
+    }
+
+    /// Decode from a gzip header, if it contains metadata.
+    fn from_header(header: &flate2::GzHeader) -> Option<Self> {
+        let mut extra = header.extra()?;
+
+        // Find our subfield among any others
+        while extra.len() >= 4 {
+            let length = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+            let data = extra.get(4..4 + length)?;
+            if extra[..2] == METADATA_SUBFIELD_ID {
+                return match serde_json::from_slice(data) {
+                    Ok(metadata) => Some(metadata),
+                    Err(e) => {
+                        log::warn!("ignoring invalid capture metadata: {}", e);
+                        None
+                    }
+                };
+            }
+            extra = &extra[4 + length..];
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for Metadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "taptap {} on {}, starting {}",
+            self.taptap_version,
+            self.os,
+            self.started.to_rfc3339()
+        )?;
+        if let Some(source) = &self.source {
+            write!(f, ", from {}", source)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, ", at {:?}", label)?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, ": {}", note)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
-pub struct Reader<R: Read>(BufReader<flate2::bufread::GzDecoder<BufReader<R>>>);
+pub struct Reader<R: Read> {
+    inner: BufReader<flate2::bufread::GzDecoder<BufReader<R>>>,
+    metadata: Option<Metadata>,
+}
 
 impl<R: Read> Reader<R> {
     pub fn new(reader: R) -> std::io::Result<Self> {
         let gz = flate2::bufread::GzDecoder::new(BufReader::new(reader));
-        if let Some(h) = gz.header() {
-            if h.comment() != Some(GZIP_HEADER_COMMENT) {
-                // warn?
-            }
-        }
+        let metadata = gz.header().and_then(Metadata::from_header);
+
+        Ok(Self {
+            inner: BufReader::new(gz),
+            metadata,
+        })
+    }
 
-        Ok(Self(BufReader::new(gz)))
+    /// The metadata describing how the capture was made, if it has any.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+}
+
+impl Reader<File> {
+    /// Follow a capture file as it is written, like `tail -f`.
+    ///
+    /// See [`Follow`].
+    pub fn follow<P: AsRef<Path>>(path: P) -> std::io::Result<Follow<File>> {
+        Ok(Follow::new(File::open(path)?))
     }
 }
 
@@ -28,7 +165,7 @@ impl<R: Read> Iterator for Reader<R> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut record = [0u8; size_of::<Record>()];
-        match self.0.read_exact(&mut record) {
+        match self.inner.read_exact(&mut record) {
             Err(e) if e.kind() == UnexpectedEof => {
                 return None;
             }
@@ -37,11 +174,132 @@ impl<R: Read> Iterator for Reader<R> {
         };
 
         let record = Record::ref_from_bytes(&record).unwrap(); // infallible
-        let mut data = vec![0; record.data_length.get() as usize];
-        Some(match self.0.read_exact(&mut data) {
-            Err(e) => Err(e),
-            Ok(_) => Ok((data, record.timestamp())),
-        })
+        let data_length = record.data_length.get() as usize;
+
+        // Read into a buffer which grows as data arrives, so that a corrupted length doesn't
+        // allocate space for data which isn't there
+        let mut data = Vec::new();
+        if let Err(e) = (&mut self.inner)
+            .take(data_length as u64)
+            .read_to_end(&mut data)
+        {
+            return Some(Err(e));
+        }
+        if data.len() < data_length {
+            return Some(Err(std::io::Error::new(
+                UnexpectedEof,
+                "capture ended within a record",
+            )));
+        }
+        Some(Ok((data, record.timestamp())))
+    }
+}
+
+/// A reader which follows a capture as it is written, like `tail -f`.
+///
+/// When the reader reaches the end of the data written so far, it polls for more. The writer must
+/// [`flush()`](Writer::flush) for its records to become visible, since the compressed stream can
+/// only be decoded up to the most recent flush point.
+///
+/// Iteration blocks until a record is available, and ends when the writer finishes the capture.
+#[derive(Debug)]
+pub struct Follow<R: Read> {
+    decoder: flate2::bufread::GzDecoder<BufReader<Tail<R>>>,
+    pending: Vec<u8>,
+    poll_interval: Duration,
+}
+
+impl<R: Read> Follow<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            decoder: flate2::bufread::GzDecoder::new(BufReader::new(Tail(reader))),
+            pending: Vec::new(),
+            poll_interval: Duration::from_millis(100),
+        }
+    }
+
+    /// The metadata describing how the capture was made, if it has any.
+    ///
+    /// This is only known once the capture's header has been written.
+    pub fn metadata(&self) -> Option<Metadata> {
+        self.decoder.header().and_then(Metadata::from_header)
+    }
+
+    /// Set the interval at which to poll for more data.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Return the next record if one is available, without waiting.
+    ///
+    /// Returns `Ok(None)` if no complete record is available yet.
+    pub fn try_next(&mut self) -> std::io::Result<Option<(Vec<u8>, SystemTime)>> {
+        loop {
+            if let Some(record) = self.take_record() {
+                return Ok(Some(record));
+            }
+
+            let mut buffer = [0u8; 4096];
+            match self.decoder.read(&mut buffer) {
+                Ok(0) if self.pending.is_empty() => {
+                    return Err(std::io::Error::new(UnexpectedEof, "capture finished"))
+                }
+                Ok(0) => {
+                    return Err(std::io::Error::new(
+                        UnexpectedEof,
+                        "capture finished within a record",
+                    ))
+                }
+                Ok(n) => self.pending.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn take_record(&mut self) -> Option<(Vec<u8>, SystemTime)> {
+        let (record, rest) = Record::ref_from_prefix(&self.pending).ok()?;
+        let data_length = record.data_length.get() as usize;
+        if rest.len() < data_length {
+            return None;
+        }
+
+        let timestamp = record.timestamp();
+        let data = rest[..data_length].to_vec();
+        self.pending.drain(..size_of::<Record>() + data_length);
+        Some((data, timestamp))
+    }
+}
+
+impl<R: Read> Iterator for Follow<R> {
+    type Item = std::io::Result<(Vec<u8>, SystemTime)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.try_next() {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => std::thread::sleep(self.poll_interval),
+                Err(e) if e.kind() == UnexpectedEof && self.pending.is_empty() => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// A reader which reports the end of its input as `WouldBlock` rather than EOF.
+///
+/// This causes the gzip decoder to preserve its state at the end of the data written so far,
+/// rather than expecting the stream's trailer.
+#[derive(Debug)]
+struct Tail<R: Read>(R);
+
+impl<R: Read> Read for Tail<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf)? {
+            0 if !buf.is_empty() => Err(WouldBlock.into()),
+            n => Ok(n),
+        }
     }
 }
 
@@ -56,15 +314,22 @@ impl<W: Write> Writer<W> {
         Ok(Self(gz))
     }
 
+    /// Start a capture described by `metadata`.
+    pub fn with_metadata(writer: W, metadata: &Metadata) -> std::io::Result<Self> {
+        let gz = flate2::GzBuilder::new()
+            .comment(GZIP_HEADER_COMMENT)
+            .extra(metadata.to_extra()?)
+            .write(writer, flate2::Compression::best());
+        Ok(Self(gz))
+    }
+
     pub fn write(&mut self, mut bytes: &[u8], timestamp: SystemTime) -> std::io::Result<()> {
-        while bytes.len() > u16::MAX as usize {
-            let (left, right) = bytes.split_at(u16::MAX as usize);
+        while bytes.len() > MAX_RECORD_LENGTH {
+            let (left, right) = bytes.split_at(MAX_RECORD_LENGTH);
             self.write(left, timestamp)?;
             bytes = right;
         }
 
-        assert!(bytes.len() <= u16::MAX as usize);
-
         let mut buffer = vec![0u8; bytes.len() + size_of::<Record>()];
         let (record, data) = buffer.as_mut_slice().split_at_mut(size_of::<Record>());
         let record = Record::mut_from_bytes(record).unwrap();
@@ -105,3 +370,153 @@ impl Record {
             .set(timestamp.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zerocopy::FromZeros;
+
+    #[test]
+    fn round_trip() {
+        let t = UNIX_EPOCH + Duration::from_millis(1723500000123);
+
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(b"hello", t).unwrap();
+        writer.write(b"", t + Duration::from_millis(1)).unwrap();
+        let buffer = writer.finish().unwrap();
+
+        let records: Vec<_> = Reader::new(buffer.as_slice())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![
+                (b"hello".to_vec(), t),
+                (vec![], t + Duration::from_millis(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let t = UNIX_EPOCH + Duration::from_millis(1723500000123);
+        let metadata = Metadata {
+            source: Some("tcp:192.0.2.1:7160".into()),
+            note: Some("inverter 2 dropping out".into()),
+            ..Metadata::new(t.into())
+        };
+
+        let mut writer = Writer::with_metadata(Vec::new(), &metadata).unwrap();
+        writer.write(b"hello", t).unwrap();
+        let buffer = writer.finish().unwrap();
+
+        // The metadata doesn't appear among the records
+        let reader = Reader::new(buffer.as_slice()).unwrap();
+        assert_eq!(reader.metadata(), Some(&metadata));
+        let records: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(records, vec![(b"hello".to_vec(), t)]);
+
+        // Nor to a plain gzip reader
+        let mut data = Vec::new();
+        flate2::read::GzDecoder::new(buffer.as_slice())
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data.len(), size_of::<Record>() + 5);
+
+        // Captures without metadata have none
+        let buffer = Writer::new(Vec::new()).unwrap().finish().unwrap();
+        assert_eq!(Reader::new(buffer.as_slice()).unwrap().metadata(), None);
+
+        // Metadata must fit in the header
+        let metadata = Metadata {
+            note: Some("x".repeat(u16::MAX as usize)),
+            ..metadata
+        };
+        assert!(Writer::with_metadata(Vec::new(), &metadata).is_err());
+    }
+
+    #[test]
+    fn long_write() {
+        let t = UNIX_EPOCH + Duration::from_millis(1723500000123);
+        let data = vec![0x55; MAX_RECORD_LENGTH * 2 + 1];
+
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(&data, t).unwrap();
+        let buffer = writer.finish().unwrap();
+
+        let lengths: Vec<_> = Reader::new(buffer.as_slice())
+            .unwrap()
+            .map(|record| record.unwrap().0.len())
+            .collect();
+        assert_eq!(lengths, [MAX_RECORD_LENGTH, MAX_RECORD_LENGTH, 1]);
+    }
+
+    #[test]
+    fn truncated_record() {
+        let t = UNIX_EPOCH + Duration::from_millis(1723500000123);
+
+        // A record claiming the maximum length but containing only a few bytes
+        let mut record = Record::new_zeroed();
+        record.set_timestamp(t);
+        record.data_length.set(u16::MAX);
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.0.write_all(record.as_bytes()).unwrap();
+        writer.0.write_all(b"hello").unwrap();
+        let buffer = writer.finish().unwrap();
+
+        let mut reader = Reader::new(buffer.as_slice()).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), UnexpectedEof);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn follow() {
+        let path = std::env::temp_dir().join(format!(
+            "taptap-follow-{}-{}.taptap",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let t = UNIX_EPOCH + Duration::from_secs(1723500000);
+
+        // Create the file before following it, but write nothing yet
+        let file = File::create(&path).unwrap();
+        let mut follow = Reader::follow(&path)
+            .unwrap()
+            .with_poll_interval(Duration::from_millis(5));
+        assert!(follow.try_next().unwrap().is_none());
+
+        // Write records in another thread, flushing after each
+        let (tx, rx) = std::sync::mpsc::channel::<u8>();
+        let writer = std::thread::spawn(move || {
+            let mut writer = Writer::new(file).unwrap();
+            for i in rx {
+                writer
+                    .write(&[i; 3], t + Duration::from_secs(i as u64))
+                    .unwrap();
+                writer.flush().unwrap();
+            }
+            writer.finish().unwrap();
+        });
+
+        for i in 0..5u8 {
+            tx.send(i).unwrap();
+
+            let start = std::time::Instant::now();
+            let record = follow.next().unwrap().unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5));
+            assert_eq!(record, (vec![i; 3], t + Duration::from_secs(i as u64)));
+        }
+
+        // Finishing the capture ends the iteration
+        drop(tx);
+        writer.join().unwrap();
+        assert!(follow.next().is_none());
+
+        std::fs::remove_file(&path).ok();
+    }
+}
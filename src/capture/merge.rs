@@ -0,0 +1,530 @@
+//! Merging captures of the same bus made from different vantage points into one timeline.
+//!
+//! Two captures of one bus chunk its bytes differently, so interleaving their records would splice
+//! frames together. Captures are instead decoded into link layer frames, each of which is
+//! timestamped with the record that completed it, and the merged capture holds one frame per
+//! record. Noise and corrupted frames are left behind. [`Frames`] decodes a capture as it's read,
+//! and [`Merge`] interleaves any number of them, so captures of any length merge in constant memory.
+//!
+//! The machines making each capture rarely agree on the time. [`estimate_offset()`] finds the
+//! offset between two captures by correlating the frames which each captured exactly once.
+
+use crate::gateway::link::{self, Frame};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// A frame, as encoded on the bus, and the time at which it was captured.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TimedFrame {
+    pub time: SystemTime,
+    pub bytes: Vec<u8>,
+}
+
+/// A signed difference between two clocks.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct Offset {
+    /// The magnitude of the offset.
+    pub duration: Duration,
+    /// Whether the offset moves times earlier rather than later.
+    pub negative: bool,
+}
+
+impl Offset {
+    /// The offset from `from` to `to`.
+    pub fn between(from: SystemTime, to: SystemTime) -> Self {
+        match to.duration_since(from) {
+            Ok(duration) => Offset {
+                duration,
+                negative: false,
+            },
+            Err(e) => Offset {
+                duration: e.duration(),
+                negative: true,
+            },
+        }
+    }
+
+    /// Parse a number of seconds, like `2.5` or `-0.25`.
+    pub fn from_secs_str(s: &str) -> Option<Self> {
+        let (negative, magnitude) = match s.strip_prefix('-') {
+            Some(magnitude) => (true, magnitude),
+            None => (false, s),
+        };
+        let duration = Duration::try_from_secs_f64(magnitude.parse().ok()?).ok()?;
+        Some(Offset { duration, negative })
+    }
+
+    pub fn apply(&self, time: SystemTime) -> SystemTime {
+        if self.negative {
+            time - self.duration
+        } else {
+            time + self.duration
+        }
+    }
+}
+
+impl std::fmt::Display for Offset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.negative { "-" } else { "+" };
+        write!(f, "{}{:.3}s", sign, self.duration.as_secs_f64())
+    }
+}
+
+/// Decode a capture's records into frames.
+pub fn frames<I>(records: I) -> std::io::Result<Vec<TimedFrame>>
+where
+    I: IntoIterator<Item = std::io::Result<(Vec<u8>, SystemTime)>>,
+{
+    Frames::new(records.into_iter()).collect()
+}
+
+/// What was read from one capture.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct InputSummary {
+    /// The number of records read.
+    pub records: u64,
+    /// The number of bytes in those records.
+    pub bytes: u64,
+    /// The number of times a record was timestamped earlier than the one before it.
+    pub steps_back: u64,
+    /// The largest of those steps.
+    pub largest_step_back: Duration,
+}
+
+/// A capture's frames, decoded as its records are read.
+pub struct Frames<I> {
+    records: I,
+    rx: link::Receiver<Vec<Frame>>,
+    pending: VecDeque<TimedFrame>,
+    offset: Offset,
+    last: Option<SystemTime>,
+    summary: InputSummary,
+}
+
+impl<I> Frames<I>
+where
+    I: Iterator<Item = std::io::Result<(Vec<u8>, SystemTime)>>,
+{
+    pub fn new(records: I) -> Self {
+        Frames {
+            records,
+            rx: link::Receiver::new(Vec::new()),
+            pending: VecDeque::new(),
+            offset: Offset::default(),
+            last: None,
+            summary: InputSummary::default(),
+        }
+    }
+
+    /// Apply `offset` to the time of every frame.
+    pub fn with_offset(mut self, offset: Offset) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// What has been read so far.
+    pub fn summary(&self) -> &InputSummary {
+        &self.summary
+    }
+}
+
+impl<I> Iterator for Frames<I>
+where
+    I: Iterator<Item = std::io::Result<(Vec<u8>, SystemTime)>>,
+{
+    type Item = std::io::Result<TimedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Some(Ok(frame));
+            }
+
+            let (data, time) = match self.records.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            self.summary.records += 1;
+            self.summary.bytes += data.len() as u64;
+            if let Some(Err(e)) = self.last.map(|last| time.duration_since(last)) {
+                self.summary.steps_back += 1;
+                self.summary.largest_step_back = self.summary.largest_step_back.max(e.duration());
+            }
+            self.last = Some(time);
+
+            let time = self.offset.apply(time);
+            self.rx.extend_from_slice(&data);
+            self.pending
+                .extend(self.rx.sink_mut().drain(..).map(|frame| TimedFrame {
+                    time,
+                    bytes: frame.encode(),
+                }));
+        }
+    }
+}
+
+/// Estimate the offset to apply to `other` to bring it into line with `reference`.
+///
+/// Only frames which appear exactly once in each capture are correlated, since repeated frames,
+/// like polls which carry no sequence number, can't be paired. Returns `None` if the captures have
+/// no such frames in common.
+pub fn estimate_offset(reference: &[TimedFrame], other: &[TimedFrame]) -> Option<Offset> {
+    fn unique(frames: &[TimedFrame]) -> HashMap<&[u8], Option<SystemTime>> {
+        let mut unique = HashMap::new();
+        for frame in frames {
+            match unique.entry(frame.bytes.as_slice()) {
+                Entry::Vacant(e) => {
+                    e.insert(Some(frame.time));
+                }
+                Entry::Occupied(mut e) => {
+                    e.insert(None);
+                }
+            }
+        }
+        unique
+    }
+
+    let reference = unique(reference);
+    let mut offsets: Vec<(bool, Duration)> = unique(other)
+        .into_iter()
+        .filter_map(|(bytes, time)| Some((time?, (*reference.get(bytes)?)?)))
+        .map(|(from, to)| {
+            let offset = Offset::between(from, to);
+            (!offset.negative, offset.duration)
+        })
+        .collect();
+    if offsets.is_empty() {
+        return None;
+    }
+
+    // Sorting by (positive, duration) would order negative offsets backwards, so sort by a signed
+    // key instead
+    offsets.sort_by(|a, b| {
+        let key = |(positive, duration): &(bool, Duration)| {
+            if *positive {
+                duration.as_nanos() as i128
+            } else {
+                -(duration.as_nanos() as i128)
+            }
+        };
+        key(a).cmp(&key(b))
+    });
+    let (positive, duration) = offsets[offsets.len() / 2];
+    Some(Offset {
+        duration,
+        negative: !positive,
+    })
+}
+
+/// What happened while merging.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct MergeSummary {
+    /// The number of frames taken from each input.
+    pub frames: Vec<u64>,
+    /// The number of frames dropped as duplicates of a frame from another input.
+    pub duplicates: u64,
+}
+
+/// Merge several captures' frames into one chronological timeline.
+///
+/// Each input must already be in chronological order, with any offset applied. With a
+/// `dedupe_window`, a frame identical to one from another input no more than that far away in
+/// time is dropped, keeping whichever came first.
+pub fn merge(
+    inputs: Vec<Vec<TimedFrame>>,
+    dedupe_window: Option<Duration>,
+) -> (Vec<TimedFrame>, MergeSummary) {
+    let mut merge = Merge::new(
+        inputs
+            .into_iter()
+            .map(|input| input.into_iter().map(Ok))
+            .collect(),
+        dedupe_window,
+    );
+    let frames = merge
+        .by_ref()
+        .collect::<std::io::Result<_>>()
+        .expect("in-memory frames can't fail");
+    (frames, merge.summary().clone())
+}
+
+/// The least number of distinct recent frames at which [`Merge`] forgets those outside its window.
+const MIN_SWEEP: usize = 1024;
+
+/// Several captures' frames, merged into one chronological timeline as they're read.
+///
+/// This is [`merge()`] for inputs which are read lazily, like [`Frames`]. The earliest frame from
+/// any input comes next, preferring earlier inputs on ties. An input which goes back in time is
+/// merged as it is, so the timeline goes back in time too.
+pub struct Merge<I> {
+    inputs: Vec<I>,
+    heads: Vec<Option<TimedFrame>>,
+    exhausted: Vec<bool>,
+    dedupe_window: Option<Duration>,
+    // Recently kept frames, each with the time it was kept and which inputs have produced it.
+    // Each occurrence absorbs at most one copy from each input, so that a frame repeated on the
+    // bus isn't mistaken for a duplicate.
+    recent: HashMap<Vec<u8>, Vec<(SystemTime, Vec<bool>)>>,
+    swept: usize,
+    summary: MergeSummary,
+}
+
+impl<I> Merge<I>
+where
+    I: Iterator<Item = std::io::Result<TimedFrame>>,
+{
+    /// Merge `inputs`, dropping duplicates within `dedupe_window` as [`merge()`] does.
+    pub fn new(inputs: Vec<I>, dedupe_window: Option<Duration>) -> Self {
+        Merge {
+            heads: inputs.iter().map(|_| None).collect(),
+            exhausted: vec![false; inputs.len()],
+            summary: MergeSummary {
+                frames: vec![0; inputs.len()],
+                duplicates: 0,
+            },
+            inputs,
+            dedupe_window,
+            recent: HashMap::new(),
+            swept: 0,
+        }
+    }
+
+    /// The inputs, in the order they were given.
+    pub fn inputs(&self) -> &[I] {
+        &self.inputs
+    }
+
+    /// What has happened so far.
+    pub fn summary(&self) -> &MergeSummary {
+        &self.summary
+    }
+
+    /// Whether `frame` from input `index` duplicates one recently kept from another input.
+    fn is_duplicate(&mut self, index: usize, frame: &TimedFrame) -> bool {
+        let Some(window) = self.dedupe_window else {
+            return false;
+        };
+        let recent = |now: SystemTime, time: &SystemTime| {
+            now.duration_since(*time).map_or(true, |age| age <= window)
+        };
+
+        let occurrences = self.recent.entry(frame.bytes.clone()).or_default();
+        occurrences.retain(|(time, _)| recent(frame.time, time));
+        if let Some((_, seen)) = occurrences.iter_mut().find(|(_, seen)| !seen[index]) {
+            seen[index] = true;
+            return true;
+        }
+        let mut seen = vec![false; self.inputs.len()];
+        seen[index] = true;
+        occurrences.push((frame.time, seen));
+
+        // Forget frames which can no longer be duplicated, once there are enough to bother
+        if self.recent.len() > self.swept.max(MIN_SWEEP) * 2 {
+            self.recent.retain(|_, occurrences| {
+                occurrences.retain(|(time, _)| recent(frame.time, time));
+                !occurrences.is_empty()
+            });
+            self.swept = self.recent.len();
+        }
+        false
+    }
+}
+
+impl<I> Iterator for Merge<I>
+where
+    I: Iterator<Item = std::io::Result<TimedFrame>>,
+{
+    type Item = std::io::Result<TimedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for (i, input) in self.inputs.iter_mut().enumerate() {
+                if self.heads[i].is_none() && !self.exhausted[i] {
+                    match input.next() {
+                        Some(Ok(frame)) => self.heads[i] = Some(frame),
+                        Some(Err(e)) => return Some(Err(e)),
+                        None => self.exhausted[i] = true,
+                    }
+                }
+            }
+
+            let (_, index) = self
+                .heads
+                .iter()
+                .enumerate()
+                .filter_map(|(i, head)| Some((head.as_ref()?.time, i)))
+                .min()?;
+            let frame = self.heads[index].take().unwrap();
+            if self.is_duplicate(index, &frame) {
+                self.summary.duplicates += 1;
+                continue;
+            }
+            self.summary.frames[index] += 1;
+            return Some(Ok(frame));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::link::{Address, Type};
+    use crate::gateway::GatewayID;
+    use std::time::UNIX_EPOCH;
+
+    fn t(ms: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1723500000) + Duration::from_millis(ms)
+    }
+
+    fn frame(ms: u64, byte: u8) -> TimedFrame {
+        TimedFrame {
+            time: t(ms),
+            bytes: vec![byte],
+        }
+    }
+
+    #[test]
+    fn offset() {
+        let reference = [
+            frame(1000, 1),
+            frame(2000, 2),
+            frame(3000, 3),
+            frame(3500, 9),
+        ];
+        // 250 ms ahead, with a repeated frame which can't be correlated
+        let other = [
+            frame(1250, 1),
+            frame(2250, 2),
+            frame(3250, 9),
+            frame(3750, 9),
+        ];
+        let offset = estimate_offset(&reference, &other).unwrap();
+        assert_eq!(offset.to_string(), "-0.250s");
+        assert_eq!(offset.apply(t(1250)), t(1000));
+
+        let behind = estimate_offset(&other, &reference).unwrap();
+        assert_eq!(behind.to_string(), "+0.250s");
+        assert_eq!(estimate_offset(&reference, &[frame(0, 7)]), None);
+
+        assert_eq!(Offset::from_secs_str("-0.25"), Some(offset));
+        assert_eq!(Offset::from_secs_str("x"), None);
+    }
+
+    #[test]
+    fn merge_and_dedupe() {
+        let a = vec![frame(0, 1), frame(100, 2), frame(5000, 3), frame(7000, 5)];
+        // Frame 5 appears twice on the bus, but only once in the first capture
+        let b = vec![
+            frame(50, 1),
+            frame(100, 4),
+            frame(200, 2),
+            frame(6000, 1),
+            frame(7000, 5),
+            frame(7100, 5),
+        ];
+
+        let (merged, summary) = merge(vec![a.clone(), b.clone()], None);
+        assert_eq!(merged.len(), 10);
+        assert!(merged.windows(2).all(|w| w[0].time <= w[1].time));
+        assert_eq!(summary.frames, [4, 6]);
+
+        let (merged, summary) = merge(vec![a, b], Some(Duration::from_millis(500)));
+        assert_eq!(
+            merged,
+            vec![
+                frame(0, 1),
+                frame(100, 2),
+                frame(100, 4),
+                frame(5000, 3),
+                frame(6000, 1),
+                frame(7000, 5),
+                frame(7100, 5),
+            ]
+        );
+        assert_eq!(
+            summary,
+            MergeSummary {
+                frames: vec![4, 3],
+                duplicates: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn streaming_frames() {
+        let encoded = |byte: u8| {
+            Frame {
+                address: Address::To(GatewayID::try_from(0x1201).unwrap()),
+                frame_type: Type::COMMAND_REQUEST,
+                payload: vec![byte],
+            }
+            .encode()
+        };
+        let (one, two) = (encoded(1), encoded(2));
+        // The second frame is split across records, and the clock steps back 1.5 s between them
+        let records = vec![
+            (one.clone(), t(1000)),
+            (two[..4].to_vec(), t(2000)),
+            (two[4..].to_vec(), t(500)),
+        ];
+
+        let mut frames = Frames::new(records.into_iter().map(Ok)).with_offset(Offset {
+            duration: Duration::from_millis(100),
+            negative: false,
+        });
+        let timed = |time, bytes: &[u8]| TimedFrame {
+            time,
+            bytes: bytes.to_vec(),
+        };
+        assert_eq!(frames.next().unwrap().unwrap(), timed(t(1100), &one));
+        assert_eq!(frames.next().unwrap().unwrap(), timed(t(600), &two));
+        assert!(frames.next().is_none());
+        assert_eq!(
+            frames.summary(),
+            &InputSummary {
+                records: 3,
+                bytes: (one.len() + two.len()) as u64,
+                steps_back: 1,
+                largest_step_back: Duration::from_millis(1500),
+            }
+        );
+    }
+
+    #[test]
+    fn exact_duplicates() {
+        // Overlapping captures from one vantage point, where one record held frame 2 twice
+        let a = vec![frame(0, 1), frame(100, 2), frame(100, 2), frame(200, 3)];
+        let b = vec![frame(100, 2), frame(100, 2), frame(200, 3), frame(300, 4)];
+
+        let inputs = vec![a.into_iter().map(Ok), b.into_iter().map(Ok)];
+        let mut merge = Merge::new(inputs, Some(Duration::ZERO));
+        let merged: Vec<_> = merge.by_ref().map(Result::unwrap).collect();
+        assert_eq!(
+            merged,
+            vec![
+                frame(0, 1),
+                frame(100, 2),
+                frame(100, 2),
+                frame(200, 3),
+                frame(300, 4)
+            ]
+        );
+        assert_eq!(merge.summary().frames, [4, 1]);
+        assert_eq!(merge.summary().duplicates, 3);
+    }
+
+    #[test]
+    fn sweep() {
+        // Far more distinct frames than are ever recent at once
+        let a = (0..10_000u64).map(|i| {
+            Ok(TimedFrame {
+                time: t(i * 10),
+                bytes: i.to_le_bytes().to_vec(),
+            })
+        });
+        let mut merge = Merge::new(vec![a], Some(Duration::from_millis(100)));
+        assert_eq!(merge.by_ref().count(), 10_000);
+        assert!(merge.recent.len() <= MIN_SWEEP * 2);
+    }
+}
@@ -0,0 +1,527 @@
+//! Reading and writing the bytes read from the gateway bus as [pcapng] files.
+//!
+//! Other tools can record RS-485 traffic as pcapng, whether directly or by converting logic
+//! analyzer exports or `socat` logs. Each enhanced packet block on an interface with one of the
+//! `LINKTYPE_USER0` through `LINKTYPE_USER15` link types is taken to hold bytes as they were read
+//! from the bus, whether a whole frame or an arbitrary chunk of the byte stream. Everything else
+//! is skipped, and counted in [`Skipped`].
+//!
+//! [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind::{InvalidData, UnexpectedEof};
+use std::io::{BufReader, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The link type under which [`Writer`] records the bytes read from the bus.
+pub const LINKTYPE_USER0: u16 = 147;
+
+/// The last of the link types reserved for private use, which are all read as raw bus bytes.
+pub const LINKTYPE_USER15: u16 = 162;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+const OPTION_END: u16 = 0;
+const OPTION_IF_TSRESOL: u16 = 9;
+const OPTION_IF_TSOFFSET: u16 = 14;
+
+/// The largest block the reader will accept, so that a corrupted length can't exhaust memory.
+const MAX_BLOCK_LENGTH: usize = 16 << 20;
+
+/// The blocks and packets skipped while reading a pcapng file.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Skipped {
+    /// The number of blocks of each type which carry no bus bytes, like name resolution or
+    /// interface statistics blocks, or simple packet blocks, which have no timestamp.
+    pub blocks: BTreeMap<u32, u64>,
+    /// The number of enhanced packet blocks on interfaces with unsupported link types.
+    pub packets: u64,
+}
+
+impl Skipped {
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.packets == 0
+    }
+}
+
+impl std::fmt::Display for Skipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} packets with unsupported link types", self.packets)?;
+        for (block_type, count) in &self.blocks {
+            write!(f, ", {} blocks of type {:#010x}", count, block_type)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Interface {
+    link_type: u16,
+    /// Timestamp units per second.
+    units_per_second: u64,
+    /// Seconds to add to each timestamp.
+    offset: i64,
+}
+
+impl Interface {
+    fn supported(&self) -> bool {
+        (LINKTYPE_USER0..=LINKTYPE_USER15).contains(&self.link_type)
+    }
+
+    fn timestamp(&self, units: u64) -> Option<SystemTime> {
+        let nanos = u128::from(units % self.units_per_second) * 1_000_000_000
+            / u128::from(self.units_per_second);
+        let since_epoch = Duration::new(units / self.units_per_second, nanos as u32);
+        let offset = Duration::from_secs(self.offset.unsigned_abs());
+        let timestamp = UNIX_EPOCH.checked_add(since_epoch)?;
+        if self.offset >= 0 {
+            timestamp.checked_add(offset)
+        } else {
+            timestamp.checked_sub(offset)
+        }
+    }
+}
+
+/// The byte order of a section, as declared by its section header block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u64(self, bytes: &[u8]) -> u64 {
+        let bytes = bytes[..8].try_into().unwrap();
+        match self {
+            ByteOrder::Little => u64::from_le_bytes(bytes),
+            ByteOrder::Big => u64::from_be_bytes(bytes),
+        }
+    }
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(InvalidData, format!("invalid pcapng: {}", message))
+}
+
+/// Reads the bus bytes from a pcapng file, yielding the same records as [`super::Reader`].
+#[derive(Debug)]
+pub struct Reader<R: Read> {
+    inner: BufReader<R>,
+    byte_order: Option<ByteOrder>,
+    interfaces: Vec<Interface>,
+    skipped: Skipped,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: BufReader::new(reader),
+            byte_order: None,
+            interfaces: Vec::new(),
+            skipped: Skipped::default(),
+        }
+    }
+
+    /// The blocks and packets skipped so far.
+    pub fn skipped(&self) -> &Skipped {
+        &self.skipped
+    }
+
+    /// Read the next block's type and body, or `None` at the end of the file.
+    fn read_block(&mut self) -> std::io::Result<Option<(u32, Vec<u8>)>> {
+        let mut header = [0u8; 8];
+        match self.inner.read_exact(&mut header[..1]) {
+            Err(e) if e.kind() == UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        self.inner.read_exact(&mut header[1..])?;
+
+        // Every section starts with a header declaring its byte order, and the header's block
+        // type reads the same either way
+        let block_type = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let mut body = Vec::new();
+        let byte_order = if block_type == SECTION_HEADER_BLOCK {
+            let mut magic = [0u8; 4];
+            self.inner.read_exact(&mut magic)?;
+            body.extend_from_slice(&magic);
+            let byte_order = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+                (BYTE_ORDER_MAGIC, _) => ByteOrder::Little,
+                (_, BYTE_ORDER_MAGIC) => ByteOrder::Big,
+                _ => return Err(invalid("unrecognized byte order")),
+            };
+            self.byte_order = Some(byte_order);
+            self.interfaces.clear();
+            byte_order
+        } else {
+            self.byte_order
+                .ok_or_else(|| invalid("not a pcapng file"))?
+        };
+        let block_type = byte_order.u32(&header[..4]);
+
+        let length = byte_order.u32(&header[4..]) as usize;
+        if length < 12 + body.len() || !length.is_multiple_of(4) || length > MAX_BLOCK_LENGTH {
+            return Err(invalid("bad block length"));
+        }
+
+        // Read the rest of the body and the trailing copy of the length
+        let remaining = length - 8 - body.len();
+        (&mut self.inner)
+            .take(remaining as u64)
+            .read_to_end(&mut body)?;
+        if body.len() < length - 8 {
+            return Err(std::io::Error::new(
+                UnexpectedEof,
+                "pcapng ended within a block",
+            ));
+        }
+        let trailer = body.split_off(body.len() - 4);
+        if byte_order.u32(&trailer) as usize != length {
+            return Err(invalid("mismatched block lengths"));
+        }
+
+        Ok(Some((block_type, body)))
+    }
+
+    fn interface_description(&mut self, body: &[u8]) -> std::io::Result<()> {
+        let byte_order = self.byte_order.unwrap();
+        if body.len() < 8 {
+            return Err(invalid("truncated interface description"));
+        }
+
+        let mut interface = Interface {
+            link_type: byte_order.u16(&body[0..2]),
+            units_per_second: 1_000_000,
+            offset: 0,
+        };
+
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = byte_order.u16(&options[0..2]);
+            let length = byte_order.u16(&options[2..4]) as usize;
+            let value = options
+                .get(4..4 + length)
+                .ok_or_else(|| invalid("truncated option"))?;
+            match (code, value) {
+                (OPTION_END, _) => break,
+                (OPTION_IF_TSRESOL, &[resolution]) => {
+                    let exponent = u32::from(resolution & 0x7f);
+                    interface.units_per_second = if resolution & 0x80 == 0 {
+                        10u64.checked_pow(exponent)
+                    } else {
+                        2u64.checked_pow(exponent)
+                    }
+                    .ok_or_else(|| invalid("unsupported timestamp resolution"))?;
+                }
+                (OPTION_IF_TSOFFSET, value) if value.len() == 8 => {
+                    interface.offset = byte_order.u64(value) as i64;
+                }
+                _ => {}
+            }
+            options = options.get(4 + length.next_multiple_of(4)..).unwrap_or(&[]);
+        }
+
+        self.interfaces.push(interface);
+        Ok(())
+    }
+
+    fn enhanced_packet(&mut self, body: &[u8]) -> std::io::Result<Option<(Vec<u8>, SystemTime)>> {
+        let byte_order = self.byte_order.unwrap();
+        if body.len() < 20 {
+            return Err(invalid("truncated enhanced packet"));
+        }
+
+        let interface = self
+            .interfaces
+            .get(byte_order.u32(&body[0..4]) as usize)
+            .ok_or_else(|| invalid("packet on an undescribed interface"))?;
+        if !interface.supported() {
+            self.skipped.packets += 1;
+            return Ok(None);
+        }
+
+        let units = (u64::from(byte_order.u32(&body[4..8])) << 32)
+            | u64::from(byte_order.u32(&body[8..12]));
+        let timestamp = interface
+            .timestamp(units)
+            .ok_or_else(|| invalid("timestamp out of range"))?;
+
+        let captured_length = byte_order.u32(&body[12..16]) as usize;
+        let data = body
+            .get(20..20 + captured_length)
+            .ok_or_else(|| invalid("truncated packet data"))?;
+        Ok(Some((data.to_vec(), timestamp)))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = std::io::Result<(Vec<u8>, SystemTime)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (block_type, body) = match self.read_block() {
+                Ok(Some(block)) => block,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let result = match block_type {
+                SECTION_HEADER_BLOCK => Ok(None),
+                INTERFACE_DESCRIPTION_BLOCK => self.interface_description(&body).map(|_| None),
+                ENHANCED_PACKET_BLOCK => self.enhanced_packet(&body),
+                block_type => {
+                    *self.skipped.blocks.entry(block_type).or_default() += 1;
+                    Ok(None)
+                }
+            };
+            match result {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Writes the bytes read from the bus as a pcapng file, with one interface of type
+/// [`LINKTYPE_USER0`] and microsecond timestamps.
+#[derive(Debug)]
+pub struct Writer<W: Write>(W);
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        // Version 1.0, with an unspecified section length
+        let mut section_header = BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        section_header.extend_from_slice(&1u16.to_le_bytes());
+        section_header.extend_from_slice(&0u16.to_le_bytes());
+        section_header.extend_from_slice(&u64::MAX.to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &section_header)?;
+
+        // No snapshot length, and the default resolution of microseconds
+        let mut interface = LINKTYPE_USER0.to_le_bytes().to_vec();
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut writer, INTERFACE_DESCRIPTION_BLOCK, &interface)?;
+
+        Ok(Self(writer))
+    }
+
+    pub fn write(&mut self, bytes: &[u8], timestamp: SystemTime) -> std::io::Result<()> {
+        let micros = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| std::io::Error::new(InvalidData, "timestamp precedes 1970"))?
+            .as_micros() as u64;
+        let length = u32::try_from(bytes.len())
+            .map_err(|_| std::io::Error::new(InvalidData, "packet is too long"))?;
+
+        let mut packet = Vec::with_capacity(20 + bytes.len() + 3);
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(micros as u32).to_le_bytes());
+        packet.extend_from_slice(&length.to_le_bytes());
+        packet.extend_from_slice(&length.to_le_bytes());
+        packet.extend_from_slice(bytes);
+        packet.resize(packet.len().next_multiple_of(4), 0);
+        write_block(&mut self.0, ENHANCED_PACKET_BLOCK, &packet)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+
+    /// The underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.0
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.0.flush()?;
+        Ok(self.0)
+    }
+}
+
+/// Write a little-endian block, whose body must already be padded to a multiple of four bytes.
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> std::io::Result<()> {
+    let length = (12 + body.len()) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&length.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t() -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(1723500000123456)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(b"hello", t()).unwrap();
+        writer.write(b"", t() + Duration::from_millis(1)).unwrap();
+        writer.write(b"ok", t() + Duration::from_secs(1)).unwrap();
+        let buffer = writer.finish().unwrap();
+
+        let mut reader = Reader::new(buffer.as_slice());
+        let records: Vec<_> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            records,
+            vec![
+                (b"hello".to_vec(), t()),
+                (vec![], t() + Duration::from_millis(1)),
+                (b"ok".to_vec(), t() + Duration::from_secs(1)),
+            ]
+        );
+        assert!(reader.skipped().is_empty());
+    }
+
+    /// Encode a block in the given byte order.
+    fn block(big_endian: bool, block_type: u32, body: &[u8]) -> Vec<u8> {
+        let u32 = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let length = 12 + body.len() as u32;
+        [&u32(block_type)[..], &u32(length), body, &u32(length)].concat()
+    }
+
+    #[test]
+    fn foreign() {
+        // A big-endian section with nanosecond timestamps offset by a day, an Ethernet interface,
+        // and blocks we don't use
+        let mut file = block(
+            true,
+            SECTION_HEADER_BLOCK,
+            &[
+                &BYTE_ORDER_MAGIC.to_be_bytes()[..],
+                &[0, 1, 0, 0],
+                &u64::MAX.to_be_bytes(),
+            ]
+            .concat(),
+        );
+        file.extend(block(
+            true,
+            INTERFACE_DESCRIPTION_BLOCK,
+            &[&1u16.to_be_bytes()[..], &[0, 0, 0, 0, 0, 0]].concat(),
+        ));
+        file.extend(block(
+            true,
+            INTERFACE_DESCRIPTION_BLOCK,
+            &[
+                &(LINKTYPE_USER0 + 1).to_be_bytes()[..],
+                &[0, 0, 0, 0, 0, 0],
+                &OPTION_IF_TSRESOL.to_be_bytes(),
+                &1u16.to_be_bytes(),
+                &[9, 0, 0, 0],
+                &OPTION_IF_TSOFFSET.to_be_bytes(),
+                &8u16.to_be_bytes(),
+                &86400u64.to_be_bytes(),
+                &[0, 0, 0, 0],
+            ]
+            .concat(),
+        ));
+        let nanos = (1723500000 - 86400) * 1_000_000_000 + 123u64;
+        let packet = |interface: u32, data: &[u8]| {
+            let mut body = [
+                &interface.to_be_bytes()[..],
+                &((nanos >> 32) as u32).to_be_bytes(),
+                &(nanos as u32).to_be_bytes(),
+                &(data.len() as u32).to_be_bytes(),
+                &(data.len() as u32).to_be_bytes(),
+                data,
+            ]
+            .concat();
+            body.resize(body.len().next_multiple_of(4), 0);
+            block(true, ENHANCED_PACKET_BLOCK, &body)
+        };
+        file.extend(packet(0, b"ethernet"));
+        file.extend(packet(1, b"hello"));
+        file.extend(block(true, 0x00000003, &[0, 0, 0, 1, 0xff, 0, 0, 0]));
+        file.extend(block(
+            true,
+            0x00000005,
+            &[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+        ));
+        file.extend(block(
+            true,
+            0x00000005,
+            &[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+        ));
+
+        // Followed by a second, little-endian section, which starts its interfaces over
+        let mut writer = Writer::new(file).unwrap();
+        writer.write(b"again", t()).unwrap();
+        let file = writer.finish().unwrap();
+
+        let mut reader = Reader::new(file.as_slice());
+        let records: Vec<_> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            records,
+            vec![
+                (
+                    b"hello".to_vec(),
+                    UNIX_EPOCH + Duration::new(1723500000, 123)
+                ),
+                (b"again".to_vec(), t()),
+            ]
+        );
+        assert_eq!(
+            reader.skipped(),
+            &Skipped {
+                blocks: [(0x00000003, 1), (0x00000005, 2)].into(),
+                packets: 1,
+            }
+        );
+        assert_eq!(
+            reader.skipped().to_string(),
+            "1 packets with unsupported link types, 1 blocks of type 0x00000003, \
+             2 blocks of type 0x00000005"
+        );
+    }
+
+    #[test]
+    fn not_pcapng() {
+        let buffer = super::super::Writer::new(Vec::new())
+            .unwrap()
+            .finish()
+            .unwrap();
+        let error = Reader::new(buffer.as_slice()).next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), InvalidData);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(b"hello", t()).unwrap();
+        let mut buffer = writer.finish().unwrap();
+        buffer.truncate(buffer.len() - 2);
+
+        let error = Reader::new(buffer.as_slice()).next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), UnexpectedEof);
+    }
+}
@@ -0,0 +1,259 @@
+//! Conventions which the `taptap` executable follows across its subcommands, for the benefit of
+//! scripts which wrap it.
+//!
+//! # Exit codes
+//!
+//! | Code | [`ExitCode`] | Meaning                                                                 |
+//! |------|--------------|-------------------------------------------------------------------------|
+//! | 0    | `Success`    | The command succeeded, or its source reached the end of its data        |
+//! | 1    | `Failure`    | The command ran, but its answer is no: an unhealthy state, a failed soak |
+//! | 2    | `Config`     | The command line or configuration is unusable, or an output can't open  |
+//! | 3    | `Connect`    | The source couldn't be opened, or failed before producing any data     |
+//! | 4    | `NoData`     | A `--fail-on` policy found no data within its time limit                |
+//! | 5    | `Io`         | Reading or writing failed after the source had produced data           |
+//! | 101  | `Internal`   | `taptap` hit a bug and panicked                                         |
+//!
+//! Command line parsing errors exit with 2, like any other configuration error, and panics exit
+//! with 101, as Rust programs do by default.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The status with which `taptap` exits.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ExitCode {
+    Success,
+    Failure,
+    Config,
+    Connect,
+    NoData,
+    Io,
+    Internal,
+}
+
+impl ExitCode {
+    /// Every exit code.
+    pub const ALL: [ExitCode; 7] = [
+        ExitCode::Success,
+        ExitCode::Failure,
+        ExitCode::Config,
+        ExitCode::Connect,
+        ExitCode::NoData,
+        ExitCode::Io,
+        ExitCode::Internal,
+    ];
+
+    /// The process exit status.
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitCode::Success => 0,
+            ExitCode::Failure => 1,
+            ExitCode::Config => 2,
+            ExitCode::Connect => 3,
+            ExitCode::NoData => 4,
+            ExitCode::Io => 5,
+            ExitCode::Internal => 101,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitCode::Success => "success",
+            ExitCode::Failure => "failure",
+            ExitCode::Config => "config",
+            ExitCode::Connect => "connect",
+            ExitCode::NoData => "no_data",
+            ExitCode::Io => "io",
+            ExitCode::Internal => "internal",
+        }
+    }
+
+    /// Exit the process with this code.
+    pub fn exit(self) -> ! {
+        std::process::exit(self.code())
+    }
+}
+
+impl std::fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A quality condition which a [`FailOn`] policy can turn into an exit.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Condition {
+    /// No valid frames were received.
+    NoFrames,
+}
+
+impl Condition {
+    /// Every condition.
+    pub const ALL: [Condition; 1] = [Condition::NoFrames];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Condition::NoFrames => "no-frames",
+        }
+    }
+
+    /// The code with which to exit when this condition persists.
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Condition::NoFrames => ExitCode::NoData,
+        }
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown condition {0:?}")]
+pub struct UnknownConditionError(String);
+
+impl FromStr for Condition {
+    type Err = UnknownConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Condition::ALL
+            .into_iter()
+            .find(|condition| condition.as_str() == s)
+            .ok_or_else(|| UnknownConditionError(s.into()))
+    }
+}
+
+/// A policy to exit when a condition persists for a given time, written like `no-frames:60`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FailOn {
+    pub condition: Condition,
+    /// How long the condition must persist.
+    pub within: Duration,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum ParseFailOnError {
+    #[error("expected CONDITION:SECONDS, like `no-frames:60`")]
+    Syntax,
+    #[error(transparent)]
+    UnknownCondition(#[from] UnknownConditionError),
+    #[error("invalid number of seconds {0:?}")]
+    InvalidSeconds(String),
+}
+
+impl FromStr for FailOn {
+    type Err = ParseFailOnError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (condition, seconds) = s.split_once(':').ok_or(ParseFailOnError::Syntax)?;
+        let condition = condition.parse()?;
+        let within = match seconds.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+            _ => return Err(ParseFailOnError::InvalidSeconds(seconds.into())),
+        };
+        Ok(Self { condition, within })
+    }
+}
+
+impl std::fmt::Display for FailOn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.condition, self.within.as_secs())
+    }
+}
+
+/// Watches a running command for the conditions named by its [`FailOn`] policies.
+#[derive(Debug, Clone)]
+pub struct Watch {
+    policies: Vec<FailOn>,
+    /// When valid frames were last received, or when watching started.
+    last_frame: Instant,
+}
+
+impl Watch {
+    pub fn new(policies: Vec<FailOn>, now: Instant) -> Self {
+        Self {
+            policies,
+            last_frame: now,
+        }
+    }
+
+    /// Note that `frames` valid frames were received at `at`.
+    pub fn frames(&mut self, frames: u64, at: Instant) {
+        if frames > 0 {
+            self.last_frame = at;
+        }
+    }
+
+    /// The first policy whose condition has persisted for its time limit as of `now`, if any.
+    pub fn check(&self, now: Instant) -> Option<FailOn> {
+        self.policies
+            .iter()
+            .copied()
+            .find(|policy| match policy.condition {
+                Condition::NoFrames => {
+                    now.saturating_duration_since(self.last_frame) >= policy.within
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        let codes: Vec<_> = ExitCode::ALL.iter().map(ExitCode::code).collect();
+        assert_eq!(codes, [0, 1, 2, 3, 4, 5, 101]);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "no-frames:60".parse(),
+            Ok(FailOn {
+                condition: Condition::NoFrames,
+                within: Duration::from_secs(60)
+            })
+        );
+        assert_eq!(
+            "no-frames:60".parse::<FailOn>().unwrap().to_string(),
+            "no-frames:60"
+        );
+        assert_eq!("no-frames".parse::<FailOn>(), Err(ParseFailOnError::Syntax));
+        assert_eq!(
+            "no-frames:0".parse::<FailOn>(),
+            Err(ParseFailOnError::InvalidSeconds("0".into()))
+        );
+        assert_eq!(
+            "no-bytes:60".parse::<FailOn>(),
+            Err(ParseFailOnError::UnknownCondition(UnknownConditionError(
+                "no-bytes".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn watch() {
+        let start = Instant::now();
+        let s = Duration::from_secs;
+        let policy = "no-frames:60".parse().unwrap();
+        let mut watch = Watch::new(vec![policy], start);
+
+        assert_eq!(watch.check(start + s(59)), None);
+        watch.frames(0, start + s(59));
+        assert_eq!(watch.check(start + s(60)), Some(policy));
+
+        // Frames push the deadline back
+        watch.frames(3, start + s(30));
+        assert_eq!(watch.check(start + s(89)), None);
+        assert_eq!(watch.check(start + s(90)), Some(policy));
+        assert_eq!(policy.condition.exit_code(), ExitCode::NoData);
+
+        // Without policies, nothing fails
+        assert_eq!(Watch::new(vec![], start).check(start + s(3600)), None);
+    }
+}
@@ -0,0 +1,350 @@
+//! A high-level client, for programs which want events from a bus without assembling the
+//! receiver stack themselves.
+//!
+//! A [`Client`] opens a source, feeds it through every layer into an [`Observer`] on a thread of
+//! its own, and yields the observer's events:
+//!
+//! ```no_run
+//! for event in taptap::Client::builder().tcp("192.0.2.10").spawn() {
+//!     println!("{}", event?.to_json());
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! With the `tokio` feature, [`Builder::spawn_async()`] runs the observer on a tokio task instead,
+//! yielding events from an [`AsyncClient`] through [`AsyncClient::next_event()`]:
+//!
+//! ```no_run
+//! # #[cfg(feature = "tokio")]
+//! # async fn example() -> std::io::Result<()> {
+//! let mut client = taptap::Client::builder().tcp("192.0.2.10").spawn_async();
+//! while let Some(event) = client.next_event().await {
+//!     println!("{}", event?.to_json());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Anything more involved, like routing events to several outputs or keeping a journal, is done by
+//! configuring an [`Observer`] directly and running it with [`run`](crate::run).
+
+use crate::config::{ConnectionMode, SourceConfig, TcpConnectionConfig};
+use crate::observer::clock::Clock;
+use crate::observer::event::Event;
+use crate::observer::{diagnostic, Config, Observer, PersistentState};
+use crate::run::{run_observe, CancellationToken};
+use std::io::Read;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+/// Where a client's bytes come from.
+enum Source {
+    Config(SourceConfig),
+    Reader(Box<dyn Read + Send>),
+    #[cfg(feature = "tokio")]
+    AsyncReader(Box<dyn tokio::io::AsyncRead + Send + Unpin>),
+}
+
+impl std::fmt::Debug for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Config(config) => f.debug_tuple("Config").field(config).finish(),
+            Source::Reader(_) => f.write_str("Reader"),
+            #[cfg(feature = "tokio")]
+            Source::AsyncReader(_) => f.write_str("AsyncReader"),
+        }
+    }
+}
+
+/// Configures and starts a [`Client`].
+#[derive(Debug, Default)]
+pub struct Builder {
+    source: Option<Source>,
+    config: Config,
+    persistent_state: PersistentState,
+    diagnostics: Option<diagnostic::Output>,
+    clock: Option<Box<dyn Clock>>,
+}
+
+impl Builder {
+    /// Read from a configured source.
+    pub fn source(mut self, source: impl Into<SourceConfig>) -> Self {
+        self.source = Some(Source::Config(source.into()));
+        self
+    }
+
+    /// Listen to a serial-to-TCP adapter on its default port, without writing to it.
+    pub fn tcp(self, hostname: impl Into<String>) -> Self {
+        self.source(TcpConnectionConfig {
+            hostname: hostname.into(),
+            port: crate::config::default_port(),
+            mode: ConnectionMode::ReadOnly,
+            reconnect: false,
+            idle_timeout_secs: None,
+        })
+    }
+
+    /// Read from a serial port.
+    #[cfg(feature = "serialport")]
+    pub fn serial(self, name: impl Into<String>) -> Self {
+        self.source(crate::config::SerialSourceConfig { name: name.into() })
+    }
+
+    /// Read from anything producing bus bytes, like a [capture](crate::capture) or a
+    /// [mock connection](crate::testing::MockConnection).
+    pub fn reader(mut self, reader: impl Read + Send + 'static) -> Self {
+        self.source = Some(Source::Reader(Box::new(reader)));
+        self
+    }
+
+    /// Read from anything producing bus bytes asynchronously, like an
+    /// [async connection](crate::gateway::physical::AsyncConnection) or one end of a
+    /// [`tokio::io::duplex()`] pipe.
+    ///
+    /// Only a client started by [`spawn_async()`](Builder::spawn_async) can read from it.
+    #[cfg(feature = "tokio")]
+    pub fn async_reader(
+        mut self,
+        reader: impl tokio::io::AsyncRead + Send + Unpin + 'static,
+    ) -> Self {
+        self.source = Some(Source::AsyncReader(Box::new(reader)));
+        self
+    }
+
+    /// Configure the observer.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Start from previously saved state, such as node tables and gateway identities, rather than
+    /// waiting to learn it again.
+    pub fn persistent_state(mut self, persistent_state: PersistentState) -> Self {
+        self.persistent_state = persistent_state;
+        self
+    }
+
+    /// Route diagnostics to a given destination, rather than to the `log` crate.
+    /// [`diagnostic::Output::Events`] yields them alongside other events.
+    pub fn diagnostics(mut self, output: diagnostic::Output) -> Self {
+        self.diagnostics = Some(output);
+        self
+    }
+
+    /// Use a given clock in place of the system clock, as when replaying a capture.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Open the source and start observing it on a new thread.
+    ///
+    /// The source is opened on that thread, so a failure to open it is the client's only item.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no source was given.
+    pub fn spawn(mut self) -> Client {
+        let source = self.source.take().expect("a source must be given");
+
+        let (tx, events) = mpsc::channel();
+        let observer = self.observer(tx);
+
+        let cancel = CancellationToken::new();
+        let thread_cancel = cancel.clone();
+        let thread = std::thread::spawn(move || {
+            let source = match source {
+                Source::Config(config) => Box::new(config.open()?) as Box<dyn Read + Send>,
+                Source::Reader(reader) => reader,
+                #[cfg(feature = "tokio")]
+                Source::AsyncReader(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "an async reader requires an async client",
+                    ))
+                }
+            };
+
+            // Drop the observer, and with it the channel, before returning
+            run_observe(source, observer, &thread_cancel).map(|_| ())
+        });
+
+        Client {
+            events,
+            cancel,
+            thread: Some(thread),
+        }
+    }
+
+    /// Open the source and start observing it on a new tokio task.
+    ///
+    /// Configured sources are opened asynchronously, as by
+    /// [`SourceConfig::open_async()`](SourceConfig::open_async), while a blocking
+    /// [reader](Builder::reader) is read on tokio's blocking thread pool. The runtime must have its
+    /// time driver enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no source was given, or if called outside a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn spawn_async(mut self) -> AsyncClient {
+        let source = self.source.take().expect("a source must be given");
+
+        let (tx, events) = tokio::sync::mpsc::unbounded_channel();
+        let observer = self.observer(tx);
+
+        let cancel = CancellationToken::new();
+        let task_cancel = cancel.clone();
+        let task = tokio::spawn(async move {
+            let source = match source {
+                Source::Config(config) => config.open_async().await?,
+                Source::AsyncReader(reader) => reader,
+                Source::Reader(reader) => {
+                    return tokio::task::spawn_blocking(move || {
+                        run_observe(reader, observer, &task_cancel).map(|_| ())
+                    })
+                    .await
+                    .unwrap_or_else(join_error);
+                }
+            };
+
+            crate::run::run_observe_async(source, observer, &task_cancel)
+                .await
+                .map(|_| ())
+        });
+
+        AsyncClient {
+            events,
+            cancel,
+            task: Some(task),
+        }
+    }
+
+    fn observer(self, sink: impl crate::observer::EventSink + 'static) -> Observer {
+        let mut observer = Observer::from_persistent_state(self.persistent_state);
+        observer.set_config(self.config);
+        observer.set_event_sink(sink);
+        if let Some(output) = self.diagnostics {
+            observer.set_diagnostics_output(output);
+        }
+        if let Some(clock) = self.clock {
+            observer.set_clock(clock);
+        }
+        observer
+    }
+}
+
+/// Observes a bus on a background thread, yielding its events.
+///
+/// Iterating yields each event as it's emitted, blocking until the next. Once the source ends or
+/// the client is [stopped](Client::stop), the observer is shut down, its remaining events are
+/// yielded, and iteration ends. If reading failed, the error is the last item.
+#[derive(Debug)]
+pub struct Client {
+    events: mpsc::Receiver<Event>,
+    cancel: CancellationToken,
+    thread: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl Client {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Ask the client to stop reading.
+    ///
+    /// Events emitted before stopping, and those emitted by shutting down, are still yielded. A
+    /// source which blocks delays stopping until it returns.
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    /// A handle with which another thread can stop the client.
+    pub fn stop_handle(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
+
+impl Iterator for Client {
+    type Item = std::io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(event) = self.events.recv() {
+            return Some(Ok(event));
+        }
+
+        // The observer is gone, so report how reading ended, once
+        match self.thread.take()?.join() {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(e)),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Let the thread finish on its own, rather than waiting on a source which may block
+        self.cancel.cancel();
+    }
+}
+
+/// Observes a bus on a tokio task, yielding its events.
+///
+/// Like [`Client`], but for async programs: events are awaited with
+/// [`next_event()`](AsyncClient::next_event) rather than iterated.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncClient {
+    events: tokio::sync::mpsc::UnboundedReceiver<Event>,
+    cancel: CancellationToken,
+    task: Option<tokio::task::JoinHandle<std::io::Result<()>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncClient {
+    /// Wait for the next event.
+    ///
+    /// Once the source ends or the client is [stopped](AsyncClient::stop), the observer is shut
+    /// down, its remaining events are returned, and then `None`. If reading failed, the error is
+    /// returned before `None`.
+    pub async fn next_event(&mut self) -> Option<std::io::Result<Event>> {
+        if let Some(event) = self.events.recv().await {
+            return Some(Ok(event));
+        }
+
+        // The observer is gone, so report how reading ended, once
+        match self.task.take()?.await.unwrap_or_else(join_error) {
+            Ok(()) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Ask the client to stop reading.
+    ///
+    /// Events emitted before stopping, and those emitted by shutting down, are still returned.
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    /// A handle with which another task or thread can stop the client.
+    pub fn stop_handle(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
+
+/// Propagate a task's panic, or report that the runtime shut down before it finished.
+#[cfg(feature = "tokio")]
+fn join_error(e: tokio::task::JoinError) -> std::io::Result<()> {
+    match e.try_into_panic() {
+        Ok(panic) => std::panic::resume_unwind(panic),
+        Err(e) => Err(std::io::Error::other(e)),
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
@@ -0,0 +1,381 @@
+//! Comparing the events decoded from a capture against a baseline.
+//!
+//! When decoding changes, replaying the same capture before and after shows exactly what the
+//! change did. [`compare()`] pairs each event in a baseline with the event it became, so that a
+//! change to one field of one event reads as that, rather than as one event removed and another
+//! added.
+//!
+//! Events are paired by [`Identity`]: the gateway, the node, the timestamp (which power reports
+//! derive from their slot counter), and whichever of a few distinguishing fields the event has.
+//! Events sharing an identity are paired in order. Both sides are expected in the form written by
+//! `taptap replay --deterministic`, so that timestamps and key order don't vary between runs.
+
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Fields which, besides the gateway, node, and timestamp, tell events apart.
+const DISCRIMINATORS: [&str; 5] = ["code", "rule", "state", "date", "packet_type"];
+
+/// A kind of difference between a baseline and the current output.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Category {
+    /// An event which the baseline lacks.
+    Added,
+    /// A baseline event which is no longer produced.
+    Removed,
+    /// An event whose fields differ from the baseline's.
+    Changed,
+}
+
+impl Category {
+    /// Every category.
+    pub const ALL: [Category; 3] = [Category::Added, Category::Removed, Category::Changed];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Added => "added",
+            Category::Removed => "removed",
+            Category::Changed => "changed",
+        }
+    }
+}
+
+impl std::fmt::Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A category name which isn't recognized.
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown difference category {0:?}; expected added, removed, or changed")]
+pub struct UnknownCategory(pub String);
+
+impl std::str::FromStr for Category {
+    type Err = UnknownCategory;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Category::ALL
+            .into_iter()
+            .find(|category| category.as_str() == s)
+            .ok_or_else(|| UnknownCategory(s.into()))
+    }
+}
+
+/// What identifies an event across runs.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Identity {
+    pub gateway: Option<u64>,
+    pub node: Option<u64>,
+    pub timestamp: Option<String>,
+    /// The values of any discriminating fields the event has, like `code` or `state`.
+    pub discriminators: Vec<(&'static str, String)>,
+}
+
+impl Identity {
+    /// Identify an event written as JSON.
+    pub fn of(event: &Value) -> Self {
+        Self {
+            gateway: event["gateway"]["id"].as_u64(),
+            node: event["node"]["id"].as_u64(),
+            timestamp: event["timestamp"].as_str().map(String::from),
+            discriminators: DISCRIMINATORS
+                .into_iter()
+                .filter_map(|field| match &event[field] {
+                    Value::Null => None,
+                    Value::String(s) => Some((field, s.clone())),
+                    value => Some((field, value.to_string())),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(gateway) = self.gateway {
+            parts.push(format!("gateway {}", gateway));
+        }
+        if let Some(node) = self.node {
+            parts.push(format!("node {}", node));
+        }
+        for (field, value) in &self.discriminators {
+            parts.push(format!("{} {}", field, value));
+        }
+        if let Some(timestamp) = &self.timestamp {
+            parts.push(format!("at {}", timestamp));
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+/// One field which differs between paired events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// The field's path, like `node.address`.
+    pub path: String,
+    /// The baseline's value, or `None` if the baseline lacks the field.
+    pub baseline: Option<Value>,
+    /// The current value, or `None` if the field is gone.
+    pub current: Option<Value>,
+}
+
+impl std::fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "(absent)".into(),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.path,
+            show(&self.baseline),
+            show(&self.current)
+        )
+    }
+}
+
+/// A difference between a baseline and the current output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    Added(Value),
+    Removed(Value),
+    Changed {
+        identity: Identity,
+        fields: Vec<FieldChange>,
+    },
+}
+
+impl Difference {
+    pub fn category(&self) -> Category {
+        match self {
+            Difference::Added(_) => Category::Added,
+            Difference::Removed(_) => Category::Removed,
+            Difference::Changed { .. } => Category::Changed,
+        }
+    }
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::Added(event) => write!(f, "+ {}", event),
+            Difference::Removed(event) => write!(f, "- {}", event),
+            Difference::Changed { identity, fields } => {
+                write!(f, "~ {}", identity)?;
+                for field in fields {
+                    write!(f, "\n    {}", field)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The outcome of comparing the current output against a baseline.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Comparison {
+    /// The number of events which are unchanged.
+    pub unchanged: u64,
+    /// Every difference: changed and added events in the current output's order, followed by
+    /// removed events in the baseline's order.
+    pub differences: Vec<Difference>,
+}
+
+impl Comparison {
+    /// The number of differences in a category.
+    pub fn count(&self, category: Category) -> usize {
+        self.differences
+            .iter()
+            .filter(|difference| difference.category() == category)
+            .count()
+    }
+
+    /// Whether every difference is in one of the allowed categories.
+    pub fn passed(&self, allowed: &BTreeSet<Category>) -> bool {
+        self.differences
+            .iter()
+            .all(|difference| allowed.contains(&difference.category()))
+    }
+}
+
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        write!(
+            f,
+            "{} unchanged, {} changed, {} added, {} removed",
+            self.unchanged,
+            self.count(Category::Changed),
+            self.count(Category::Added),
+            self.count(Category::Removed)
+        )
+    }
+}
+
+/// Compare the current output against a baseline.
+pub fn compare(baseline: &[Value], current: &[Value]) -> Comparison {
+    let mut unpaired: BTreeMap<Identity, VecDeque<usize>> = BTreeMap::new();
+    for (index, event) in baseline.iter().enumerate() {
+        unpaired
+            .entry(Identity::of(event))
+            .or_default()
+            .push_back(index);
+    }
+
+    let mut comparison = Comparison::default();
+    let mut paired = vec![false; baseline.len()];
+    for event in current {
+        let identity = Identity::of(event);
+        let Some(index) = unpaired
+            .get_mut(&identity)
+            .and_then(|indices| indices.pop_front())
+        else {
+            comparison
+                .differences
+                .push(Difference::Added(event.clone()));
+            continue;
+        };
+        paired[index] = true;
+
+        let mut fields = Vec::new();
+        diff_fields("", &baseline[index], event, &mut fields);
+        if fields.is_empty() {
+            comparison.unchanged += 1;
+        } else {
+            comparison
+                .differences
+                .push(Difference::Changed { identity, fields });
+        }
+    }
+
+    comparison.differences.extend(
+        baseline
+            .iter()
+            .zip(paired)
+            .filter(|(_, paired)| !paired)
+            .map(|(event, _)| Difference::Removed(event.clone())),
+    );
+    comparison
+}
+
+/// Collect the fields which differ between two values, descending into objects.
+fn diff_fields(path: &str, baseline: &Value, current: &Value, changes: &mut Vec<FieldChange>) {
+    let (Value::Object(baseline), Value::Object(current)) = (baseline, current) else {
+        if baseline != current {
+            changes.push(FieldChange {
+                path: path.into(),
+                baseline: Some(baseline.clone()),
+                current: Some(current.clone()),
+            });
+        }
+        return;
+    };
+
+    let keys: BTreeSet<&String> = baseline.keys().chain(current.keys()).collect();
+    for key in keys {
+        let path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match (baseline.get(key), current.get(key)) {
+            (Some(baseline), Some(current)) => diff_fields(&path, baseline, current, changes),
+            (baseline, current) => changes.push(FieldChange {
+                path,
+                baseline: baseline.cloned(),
+                current: current.cloned(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn report(node: u64, timestamp: &str, current: f64) -> Value {
+        json!({
+            "gateway": {"id": 4609},
+            "node": {"id": node},
+            "timestamp": timestamp,
+            "current": current,
+            "voltage_in": 30.0,
+        })
+    }
+
+    #[test]
+    fn compare_events() {
+        let t0 = "2024-08-24T11:00:00.000000Z";
+        let t1 = "2024-08-24T11:00:20.000000Z";
+        let diagnostic = json!({"timestamp": t0, "code": "tx_buffers_exhausted", "message": "…"});
+        let baseline = [
+            report(2, t0, 6.5),
+            report(3, t0, 6.5),
+            diagnostic.clone(),
+            report(2, t1, 6.5),
+        ];
+
+        let mut changed = report(3, t0, 6.0);
+        changed["power_w"] = json!(180.0);
+        let added = report(4, t1, 6.5);
+        let current = [report(2, t0, 6.5), changed, diagnostic, added.clone()];
+
+        let comparison = compare(&baseline, &current);
+        assert_eq!(comparison.unchanged, 2);
+        assert_eq!(
+            comparison.differences,
+            vec![
+                Difference::Changed {
+                    identity: Identity::of(&report(3, t0, 0.0)),
+                    fields: vec![
+                        FieldChange {
+                            path: "current".into(),
+                            baseline: Some(json!(6.5)),
+                            current: Some(json!(6.0)),
+                        },
+                        FieldChange {
+                            path: "power_w".into(),
+                            baseline: None,
+                            current: Some(json!(180.0)),
+                        },
+                    ],
+                },
+                Difference::Added(added),
+                Difference::Removed(report(2, t1, 6.5)),
+            ]
+        );
+
+        assert!(!comparison.passed(&BTreeSet::new()));
+        assert!(!comparison.passed(&[Category::Added, Category::Changed].into()));
+        assert!(comparison.passed(&Category::ALL.into()));
+
+        assert_eq!(
+            comparison.to_string().lines().take(3).collect::<Vec<_>>(),
+            [
+                "~ gateway 4609 node 3 at 2024-08-24T11:00:00.000000Z",
+                "    current: 6.5 -> 6.0",
+                "    power_w: (absent) -> 180.0",
+            ]
+        );
+        assert!(comparison
+            .to_string()
+            .ends_with("2 unchanged, 1 changed, 1 added, 1 removed"));
+    }
+
+    #[test]
+    fn nested_fields() {
+        let mut current = report(2, "t", 6.5);
+        current["node"]["address"] = json!([4, 192, 91, 64, 0, 162, 0, 2]);
+        let comparison = compare(&[report(2, "t", 6.5)], &[current]);
+        let [Difference::Changed { fields, .. }] = &comparison.differences[..] else {
+            panic!("unexpected differences: {:?}", comparison.differences);
+        };
+        assert_eq!(fields[0].path, "node.address");
+    }
+}
@@ -1,8 +1,12 @@
 use crate::gateway;
-use schemars::JsonSchema;
+use crate::observer::routing::Route;
+use crate::observer::EventSink;
+use crate::output::OutputFormat;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename = "snake_case")]
 pub enum SourceConfig {
     #[cfg(feature = "serialport")]
@@ -25,30 +29,104 @@ impl SourceConfig {
                     ConnectionMode::ReadOnly => true,
                 };
 
-                let conn = gateway::physical::tcp::Connection::connect(addr, readonly)?;
+                let idle_timeout = config.idle_timeout_secs.map(Duration::from_secs);
+                if config.reconnect {
+                    let conn = gateway::physical::tcp::ReconnectingConnection::connect(
+                        &config.hostname,
+                        config.port,
+                        readonly,
+                        idle_timeout,
+                    )?;
+                    return Ok(Box::new(conn));
+                }
+
+                let mut conn = gateway::physical::tcp::Connection::connect(addr, readonly)?;
+                conn.set_idle_timeout(idle_timeout)?;
+                Ok(Box::new(conn))
+            }
+        }
+    }
+
+    /// Open the source for an async program.
+    ///
+    /// Reconnecting and idle timeouts aren't available asynchronously, and serial ports need the
+    /// `tokio-serial` feature; sources asking for them fail to open with
+    /// [`Unsupported`](std::io::ErrorKind::Unsupported).
+    #[cfg(feature = "tokio")]
+    pub async fn open_async(
+        &self,
+    ) -> Result<Box<dyn gateway::physical::AsyncConnection>, std::io::Error> {
+        match self {
+            #[cfg(feature = "tokio-serial")]
+            SourceConfig::Serial(config) => {
+                let conn = gateway::physical::tokio::SerialPort::open(&config.name)?;
+                Ok(Box::new(conn))
+            }
+            #[cfg(all(feature = "serialport", not(feature = "tokio-serial")))]
+            SourceConfig::Serial(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "async serial ports require the tokio-serial feature",
+            )),
+            SourceConfig::Tcp(config) => {
+                if config.reconnect || config.idle_timeout_secs.is_some() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "async TCP connections can't reconnect or time out",
+                    ));
+                }
+
+                let readonly = match config.mode {
+                    ConnectionMode::ReadWrite => false,
+                    ConnectionMode::ReadOnly => true,
+                };
+                let conn = gateway::physical::tokio::TcpConnection::connect(
+                    (config.hostname.as_str(), config.port),
+                    readonly,
+                )
+                .await?;
                 Ok(Box::new(conn))
             }
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+/// Describe the source, as recorded in capture metadata.
+impl std::fmt::Display for SourceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "serialport")]
+            SourceConfig::Serial(config) => write!(f, "serial:{}", config.name),
+            SourceConfig::Tcp(config) => write!(f, "tcp:{}:{}", config.hostname, config.port),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[cfg(feature = "serialport")]
 pub struct SerialSourceConfig {
     pub name: String,
 }
+#[cfg(feature = "serialport")]
 impl From<SerialSourceConfig> for SourceConfig {
     fn from(value: SerialSourceConfig) -> Self {
         SourceConfig::Serial(value)
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct TcpConnectionConfig {
     pub hostname: String,
     #[serde(default = "default_port")]
     pub port: u16,
     pub mode: ConnectionMode,
+    /// Reconnect, rather than failing, whenever the connection is lost.
+    #[serde(default)]
+    pub reconnect: bool,
+    /// Treat the connection as lost when it receives nothing for this many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
 }
 impl From<TcpConnectionConfig> for SourceConfig {
     fn from(value: TcpConnectionConfig) -> Self {
@@ -56,11 +134,12 @@ impl From<TcpConnectionConfig> for SourceConfig {
     }
 }
 
-fn default_port() -> u16 {
+pub(crate) fn default_port() -> u16 {
     7160
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ConnectionMode {
     #[default]
     #[serde(rename = "readonly", alias = "ro")]
@@ -68,3 +147,71 @@ pub enum ConnectionMode {
     #[serde(rename = "readwrite", alias = "rw")]
     ReadWrite,
 }
+
+/// A destination for events, the kinds of event to send there, and how to write them.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OutputConfig {
+    pub destination: OutputDestination,
+    /// The kinds of event written to this output. Omitted or empty means every kind.
+    #[serde(default)]
+    pub route: Route,
+    /// How events are written. Omitted means JSON, one event per line.
+    #[serde(default)]
+    pub format: OutputFormat,
+}
+
+impl OutputConfig {
+    /// Open the destination, writing events to it in the configured format.
+    ///
+    /// CSV written to a file appends to a file for each day when its path contains date fields,
+    /// as described by [`CsvFile`](crate::output::csv::CsvFile).
+    pub fn open(
+        &self,
+        console: &crate::console::Console,
+    ) -> Result<Box<dyn EventSink>, std::io::Error> {
+        use crate::output::csv::{CsvFile, CsvSink};
+
+        match (&self.destination, self.format) {
+            (OutputDestination::Stdout, OutputFormat::JsonLines) => Ok(Box::new(console.out())),
+            (OutputDestination::Stderr, OutputFormat::JsonLines) => Ok(Box::new(console.err())),
+            (OutputDestination::Stdout, OutputFormat::Csv) => {
+                Ok(Box::new(CsvSink::new(console.out())))
+            }
+            (OutputDestination::Stderr, OutputFormat::Csv) => {
+                Ok(Box::new(CsvSink::new(console.err())))
+            }
+            (OutputDestination::File(path), OutputFormat::Csv) => {
+                Ok(Box::new(CsvFile::new(path.to_string_lossy())?))
+            }
+            (OutputDestination::File(path), OutputFormat::JsonLines) => {
+                let file = crate::write_behind::WriteBehind::open(path, Default::default())?;
+                if let Some(unclean_shutdown) = file.unclean_shutdown() {
+                    log::warn!("output {:?}: {}", path, unclean_shutdown);
+                }
+                Ok(Box::new(file))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum OutputDestination {
+    Stdout,
+    Stderr,
+    /// A file to which to append.
+    File(std::path::PathBuf),
+}
+
+/// Parse `stdout`, `stderr`, or otherwise the path of a file.
+impl From<&str> for OutputDestination {
+    fn from(value: &str) -> Self {
+        match value {
+            "stdout" => OutputDestination::Stdout,
+            "stderr" => OutputDestination::Stderr,
+            path => OutputDestination::File(path.into()),
+        }
+    }
+}
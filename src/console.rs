@@ -0,0 +1,203 @@
+//! Coordinated output to standard output and standard error.
+//!
+//! When standard output and standard error share a terminal, writes to each can interleave
+//! mid-line, as can writes to the same stream from different threads. A [`Console`] serializes
+//! them so that each line appears whole.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// A pair of output streams, written a line at a time.
+#[derive(Clone)]
+pub struct Console {
+    streams: Arc<Mutex<Streams>>,
+}
+
+struct Streams {
+    out: Box<dyn Write + Send>,
+    err: Box<dyn Write + Send>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Target {
+    Out,
+    Err,
+}
+
+impl Console {
+    pub fn new(out: impl Write + Send + 'static, err: impl Write + Send + 'static) -> Self {
+        Self {
+            streams: Arc::new(Mutex::new(Streams {
+                out: Box::new(out),
+                err: Box::new(err),
+            })),
+        }
+    }
+
+    /// A console writing to standard output and standard error.
+    pub fn stdio() -> Self {
+        Self::new(std::io::stdout(), std::io::stderr())
+    }
+
+    /// A writer for the output stream, which is meant for decoded data.
+    pub fn out(&self) -> Writer {
+        self.writer(Target::Out)
+    }
+
+    /// A writer for the error stream, which is meant for logs.
+    pub fn err(&self) -> Writer {
+        self.writer(Target::Err)
+    }
+
+    fn writer(&self, target: Target) -> Writer {
+        Writer {
+            streams: self.streams.clone(),
+            target,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Write a single line to the output stream.
+    pub fn println(&self, line: impl std::fmt::Display) {
+        let line = format!("{}\n", line);
+        if let Err(e) = self
+            .streams
+            .lock()
+            .unwrap()
+            .write(Target::Out, line.as_bytes())
+        {
+            log::error!("error writing output: {}", e);
+        }
+    }
+}
+
+impl std::fmt::Debug for Console {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Console")
+    }
+}
+
+impl Streams {
+    fn write(&mut self, target: Target, bytes: &[u8]) -> std::io::Result<()> {
+        let stream = match target {
+            Target::Out => &mut self.out,
+            Target::Err => &mut self.err,
+        };
+        stream.write_all(bytes)?;
+        stream.flush()
+    }
+}
+
+/// A writer for one of a [`Console`]'s streams.
+///
+/// Bytes are buffered until a line is complete, and then the line is written while holding the
+/// console's lock. `flush()` writes any partial line immediately, which is appropriate for output
+/// which isn't line-oriented.
+pub struct Writer {
+    streams: Arc<Mutex<Streams>>,
+    target: Target,
+    buffer: Vec<u8>,
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if let Some(end) = self.buffer.iter().rposition(|b| *b == b'\n') {
+            let lines: Vec<u8> = self.buffer.drain(..=end).collect();
+            self.streams.lock().unwrap().write(self.target, &lines)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            let buffer = std::mem::take(&mut self.buffer);
+            self.streams.lock().unwrap().write(self.target, &buffer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
+impl std::fmt::Debug for Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Writer")
+            .field("target", &self.target)
+            .field("buffered", &self.buffer.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::WriteLog;
+
+    #[test]
+    fn whole_lines() {
+        // Both streams lead to the same place, like a terminal
+        let terminal = WriteLog::default();
+        let console = Console::new(terminal.clone(), terminal.clone());
+
+        let mut out = console.out();
+        let mut err = console.err();
+        out.write_all(b"hello, ").unwrap();
+        err.write_all(b"log line\n").unwrap();
+        out.write_all(b"world\nand").unwrap();
+        console.println("a whole line");
+        assert_eq!(
+            String::from_utf8(terminal.contents()).unwrap(),
+            "log line\nhello, world\na whole line\n"
+        );
+
+        // Partial lines are written when flushed
+        out.flush().unwrap();
+        assert!(terminal.contents().ends_with(b"\nand"));
+    }
+
+    #[test]
+    fn contended() {
+        let terminal = WriteLog::default();
+        let console = Console::new(terminal.clone(), terminal.clone());
+
+        // Several threads dribble lines out a few bytes at a time
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let mut writer = if thread % 2 == 0 {
+                    console.out()
+                } else {
+                    console.err()
+                };
+                std::thread::spawn(move || {
+                    for line in 0..100 {
+                        let line = format!("thread {} line {}\n", thread, line);
+                        for chunk in line.as_bytes().chunks(3) {
+                            writer.write_all(chunk).unwrap();
+                            std::thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let contents = String::from_utf8(terminal.contents()).unwrap();
+        let mut lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 400);
+        lines.sort();
+        let mut expected: Vec<String> = (0..4)
+            .flat_map(|thread| (0..100).map(move |line| format!("thread {} line {}", thread, line)))
+            .collect();
+        expected.sort();
+        assert_eq!(lines, expected);
+    }
+}
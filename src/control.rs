@@ -0,0 +1,169 @@
+//! Controlling a long-running process, the same way on every platform.
+//!
+//! [`Signals`] turns the operating system's lifecycle events into requests to shut down or to
+//! dump state. Since Windows has nothing like `SIGUSR1`, a [`Server`] also accepts [`Command`]s
+//! over a loopback TCP socket, which works everywhere. [`send()`] is the client, as used by
+//! `taptap ctl`.
+//!
+//! The protocol is a single line naming the command, answered by a reply after which the server
+//! closes the connection.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::Duration;
+
+mod signals;
+pub use signals::Signals;
+
+/// The address on which a [`Server`] listens by default.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7161";
+
+/// How long either end waits for the other.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command which can be sent to a running process.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Command {
+    /// Reply with the counters of every layer, as JSON.
+    DumpCounters,
+    /// Reply with the approximate memory used by each subsystem.
+    MemoryReport,
+    /// Reply with the observer's snapshot of the system's gateways and nodes, as JSON.
+    Snapshot,
+    /// Shut down gracefully, flushing all outputs.
+    Shutdown,
+}
+
+impl Command {
+    pub const ALL: [Command; 4] = [
+        Command::DumpCounters,
+        Command::MemoryReport,
+        Command::Snapshot,
+        Command::Shutdown,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::DumpCounters => "dump-counters",
+            Command::MemoryReport => "memory-report",
+            Command::Snapshot => "snapshot",
+            Command::Shutdown => "shutdown",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown command {0:?}")]
+pub struct UnknownCommandError(String);
+
+impl std::str::FromStr for Command {
+    type Err = UnknownCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Command::ALL
+            .into_iter()
+            .find(|command| command.name() == s)
+            .ok_or_else(|| UnknownCommandError(s.into()))
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A command received by a [`Server`], awaiting a reply.
+#[derive(Debug)]
+struct Request {
+    command: Command,
+    stream: TcpStream,
+}
+
+/// A listener for [`Command`]s.
+///
+/// Connections are accepted on a background thread, but commands are only handled when the owner
+/// calls [`handle_pending()`](Self::handle_pending), so that they can be answered from the
+/// owner's state without any locking.
+#[derive(Debug)]
+pub struct Server {
+    local_addr: SocketAddr,
+    requests: mpsc::Receiver<Request>,
+}
+
+impl Server {
+    /// Listen for commands on `addr`.
+    ///
+    /// Anyone who can connect can control the process, so this should be a loopback address.
+    pub fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (tx, requests) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("taptap-control".into())
+            .spawn(move || accept(listener, tx))?;
+        Ok(Self {
+            local_addr,
+            requests,
+        })
+    }
+
+    /// The address on which the server is listening.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Reply to every command received so far with the result of `handler`.
+    pub fn handle_pending(&self, mut handler: impl FnMut(Command) -> String) {
+        for mut request in self.requests.try_iter() {
+            let reply = handler(request.command);
+            if let Err(e) = writeln!(request.stream, "{}", reply.trim_end()) {
+                log::warn!("error replying to {} command: {}", request.command, e);
+            }
+        }
+    }
+}
+
+fn accept(listener: TcpListener, tx: mpsc::Sender<Request>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("error accepting control connection: {}", e);
+                continue;
+            }
+        };
+        stream.set_read_timeout(Some(TIMEOUT)).ok();
+        stream.set_write_timeout(Some(TIMEOUT)).ok();
+
+        // Commands are short, so don't read any further than one could be
+        let mut line = String::new();
+        if let Err(e) = BufReader::new((&mut stream).take(64)).read_line(&mut line) {
+            log::warn!("error reading control command: {}", e);
+            continue;
+        }
+
+        match line.trim().parse() {
+            Ok(command) => {
+                if tx.send(Request { command, stream }).is_err() {
+                    // The server was dropped
+                    return;
+                }
+            }
+            Err(e) => {
+                writeln!(stream, "error: {}", e).ok();
+            }
+        }
+    }
+}
+
+/// Send a command to the server at `addr`, returning its reply.
+pub fn send(addr: impl ToSocketAddrs, command: Command) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    writeln!(stream, "{}", command)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
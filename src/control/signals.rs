@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN_COMPLETE: AtomicBool = AtomicBool::new(false);
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Requests from the operating system to shut down, to dump state, or to reload configuration.
+///
+/// On Unix, `SIGINT` and `SIGTERM` request shutdown, `SIGUSR1` requests a dump, and `SIGHUP`
+/// requests a reload. On Windows, Ctrl-C, Ctrl-Break, closing the console window, and system
+/// shutdown request shutdown; Windows has no equivalent to `SIGUSR1`, so dumps are requested
+/// through a [`Server`](super::Server) instead, nor to `SIGHUP`, so anything reloadable is also
+/// reloaded when its file changes.
+///
+/// A second shutdown request while the first is still being handled terminates the process
+/// immediately, so that a process which is stuck can still be stopped.
+///
+/// Dropping `Signals` indicates that shutdown is complete. On Windows, closing the console or
+/// shutting down the system terminates the process once the handler returns, so the handler waits
+/// briefly for this to happen, giving outputs a chance to be flushed.
+#[derive(Debug)]
+pub struct Signals(());
+
+impl Signals {
+    /// Install handlers for lifecycle signals.
+    pub fn install() -> Self {
+        platform::install();
+        Self(())
+    }
+
+    /// Whether shutdown has been requested.
+    pub fn shutdown_requested(&self) -> bool {
+        SHUTDOWN_REQUESTED.load(Ordering::Relaxed)
+    }
+
+    /// Request shutdown, as if the operating system had.
+    pub fn request_shutdown(&self) {
+        SHUTDOWN_REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a dump has been requested since the last call.
+    pub fn take_dump_request(&self) -> bool {
+        DUMP_REQUESTED.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether a reload has been requested since the last call.
+    pub fn take_reload_request(&self) -> bool {
+        RELOAD_REQUESTED.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        SHUTDOWN_COMPLETE.store(true, Ordering::Release);
+    }
+}
+
+/// Wait up to `timeout` for the process to finish shutting down.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn wait_for_shutdown(timeout: Duration) {
+    let start = Instant::now();
+    while !SHUTDOWN_COMPLETE.load(Ordering::Acquire) && start.elapsed() < timeout {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::*;
+
+    extern "C" fn shutdown(_: libc::c_int) {
+        if SHUTDOWN_REQUESTED.swap(true, Ordering::Relaxed) {
+            unsafe { libc::_exit(1) };
+        }
+    }
+
+    extern "C" fn dump(_: libc::c_int) {
+        DUMP_REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    extern "C" fn reload(_: libc::c_int) {
+        RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    pub fn install() {
+        handle(libc::SIGINT, shutdown);
+        handle(libc::SIGTERM, shutdown);
+        handle(libc::SIGUSR1, dump);
+        handle(libc::SIGHUP, reload);
+    }
+
+    fn handle(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+        // SA_RESTART keeps signals from interrupting reads in progress
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+
+    type Bool = i32;
+    type HandlerRoutine = unsafe extern "system" fn(u32) -> Bool;
+
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+    const CTRL_CLOSE_EVENT: u32 = 2;
+    const CTRL_SHUTDOWN_EVENT: u32 = 6;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<HandlerRoutine>, add: Bool) -> Bool;
+    }
+
+    unsafe extern "system" fn handler(ctrl_type: u32) -> Bool {
+        match ctrl_type {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+                // Returning FALSE lets the default handler terminate the process
+                !SHUTDOWN_REQUESTED.swap(true, Ordering::Relaxed) as Bool
+            }
+            CTRL_CLOSE_EVENT | CTRL_SHUTDOWN_EVENT => {
+                SHUTDOWN_REQUESTED.store(true, Ordering::Relaxed);
+                // The process is terminated when this returns, and Windows allows at most 5
+                // seconds before doing so regardless
+                wait_for_shutdown(Duration::from_secs(4));
+                1
+            }
+            // CTRL_LOGOFF_EVENT doesn't concern processes running as services
+            _ => 0,
+        }
+    }
+
+    pub fn install() {
+        unsafe {
+            SetConsoleCtrlHandler(Some(handler), 1);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    pub fn install() {}
+}
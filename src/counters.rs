@@ -0,0 +1,224 @@
+use crate::{gateway, pv};
+
+/// A snapshot of the counters from every layer of a receiver stack.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Counters {
+    pub link: gateway::link::Counters,
+    pub transport: gateway::transport::Counters,
+    pub application: pv::application::Counters,
+}
+
+impl Counters {
+    /// Capture the counters of a complete receiver stack.
+    pub fn snapshot<S>(rx: &crate::Pipeline<S>) -> Self
+    where
+        S: gateway::transport::Sink + pv::application::Sink,
+    {
+        Self {
+            link: *rx.counters(),
+            transport: *rx.sink().counters(),
+            application: *rx.sink().sink().counters(),
+        }
+    }
+
+    /// The change in each counter since an `earlier` snapshot.
+    ///
+    /// Counters which were reset in the meantime are treated as having started from zero.
+    pub fn diff(&self, earlier: &Self) -> Self {
+        Self {
+            link: self.link.diff(&earlier.link),
+            transport: self.transport.diff(&earlier.transport),
+            application: self.application.diff(&earlier.application),
+        }
+    }
+
+    /// The total of each counter across two receiver stacks, as when observing several buses.
+    pub fn sum(&self, other: &Self) -> Self {
+        Self {
+            link: self.link.sum(&other.link),
+            transport: self.transport.sum(&other.transport),
+            application: self.application.sum(&other.application),
+        }
+    }
+
+    /// Whether every counter is zero.
+    pub fn is_empty(&self) -> bool {
+        self.link.is_empty() && self.transport.is_empty() && self.application.is_empty()
+    }
+}
+
+/// Implement `diff()`, `sum()`, and `is_empty()` for a layer's counters.
+///
+/// The destructuring makes the field list exhaustive, so a field added to a struct but not here
+/// fails to compile.
+macro_rules! impl_counters {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl $ty {
+            /// The change in each counter since an `earlier` snapshot.
+            pub fn diff(&self, earlier: &Self) -> Self {
+                let Self { $($field),* } = *self;
+                Self {
+                    $($field: if $field >= earlier.$field { $field - earlier.$field } else { $field }),*
+                }
+            }
+
+            /// The total of each counter across two receiver stacks.
+            pub fn sum(&self, other: &Self) -> Self {
+                let Self { $($field),* } = *self;
+                Self {
+                    $($field: $field + other.$field),*
+                }
+            }
+
+            /// Whether every counter is zero.
+            pub fn is_empty(&self) -> bool {
+                *self == Self::default()
+            }
+        }
+    };
+}
+
+impl_counters!(gateway::link::Counters {
+    frames,
+    frames_to_gateways,
+    frames_from_gateways,
+    runts,
+    giants,
+    checksums,
+    noise,
+    foreign_frames,
+    foreign_bytes,
+    bytes_discarded_in_giants,
+    recovered_frames,
+});
+
+impl_counters!(gateway::transport::Counters {
+    frames,
+    unhandled_frame_types,
+    invalid_receive_requests,
+    receive_requests,
+    invalid_receive_responses,
+    receive_responses_from_unknown_gateways,
+    receive_responses,
+    legacy_receive_requests,
+    legacy_receive_responses,
+    packet_number_resyncs,
+    receive_packets,
+    duplicate_receive_packets,
+    unreliable_slot_counters,
+    receive_packets_too_short,
+    invalid_command_requests,
+    retransmitted_command_requests,
+    command_requests,
+    invalid_command_responses,
+    retransmitted_command_responses,
+    command_responses,
+    command_timeouts,
+    ping_requests,
+    ping_responses,
+    enumeration_start_requests,
+    invalid_enumeration_start_requests,
+    enumeration_start_responses,
+    enumeration_requests,
+    enumeration_responses,
+    invalid_enumeration_responses,
+    version_requests,
+    version_responses,
+    lossy_version_responses,
+    invalid_version_responses,
+    enumeration_end_requests,
+    enumeration_end_responses,
+    invalid_enumeration_end_responses,
+    assign_gateway_id_requests,
+    invalid_assign_gateway_id_requests,
+    assign_gateway_id_responses,
+    invalid_assign_gateway_id_responses,
+    identify_requests,
+    identify_responses,
+    invalid_identify_responses,
+});
+
+impl_counters!(pv::application::Counters {
+    invalid_received_packet_node_ids,
+    invalid_power_reports,
+    power_reports,
+    invalid_topology_reports,
+    topology_reports,
+    invalid_node_table_requests,
+    invalid_node_table_responses,
+    invalid_string_commands,
+    string_commands,
+    lossy_string_commands,
+    invalid_string_responses,
+    string_responses,
+    lossy_string_responses,
+    invalid_broadcasts,
+    broadcasts,
+    invalid_network_status_responses,
+    network_status_responses,
+    invalid_radio_configuration_responses,
+    radio_configuration_responses,
+    invalid_pv_configuration_requests,
+    invalid_pv_configuration_responses,
+    pv_configuration_responses,
+    lost_packets,
+    dsn_resets,
+});
+
+#[cfg(all(test, feature = "observer"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_and_diff() {
+        let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+            pv::application::Receiver::new(crate::observer::Observer::default()),
+        ));
+        assert!(Counters::snapshot(&rx).is_empty());
+
+        let (left, right) = crate::test_data::ENUMERATION_SEQUENCE.split_at(300);
+        rx.extend_from_slice(left);
+        let earlier = Counters::snapshot(&rx);
+        assert!(!earlier.is_empty());
+        rx.extend_from_slice(right);
+        let later = Counters::snapshot(&rx);
+
+        let diff = later.diff(&earlier);
+        assert_eq!(diff.link.frames, later.link.frames - earlier.link.frames);
+        assert_eq!(
+            diff.transport.enumeration_end_requests,
+            later.transport.enumeration_end_requests
+        );
+        assert!(later.diff(&later).is_empty());
+        assert_eq!(earlier.sum(&diff), later);
+
+        // A reset counter counts from zero
+        let reset = Counters::default();
+        assert_eq!(reset.diff(&later), reset);
+        let mut restarted = Counters::default();
+        restarted.link.frames = 2;
+        assert_eq!(restarted.diff(&later).link.frames, 2);
+    }
+
+    #[test]
+    fn serde() {
+        let mut counters = Counters::default();
+        counters.link.frames = 1;
+        counters.transport.invalid_receive_requests = 2;
+        counters.application.power_reports = 3;
+        let json = serde_json::to_value(counters).unwrap();
+        assert_eq!(json["transport"]["invalid_receive_requests"], 2);
+        assert_eq!(serde_json::from_value::<Counters>(json).unwrap(), counters);
+
+        // Fields renamed for consistency can still be read by their old names
+        let old: Counters = serde_json::from_str(
+            r#"{"transport":{"invalid_receive_request":4,"receive_packet_too_short":5}}"#,
+        )
+        .unwrap();
+        assert_eq!(old.transport.invalid_receive_requests, 4);
+        assert_eq!(old.transport.receive_packets_too_short, 5);
+    }
+}
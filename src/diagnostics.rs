@@ -0,0 +1,667 @@
+//! Diagnosing a connection to the gateway bus.
+//!
+//! Getting from "adapter connected" to "observe produces data" can fail at every layer: no bytes,
+//! bytes which never form frames, frames in only one direction, gateways which never answer, or
+//! answers which never carry power reports. A [`Collector`] at the end of a [`Pipeline`] notes what
+//! each layer delivered, and a [`Report`] combines that with the pipeline's counters into
+//! findings, each with a hint about what to try next.
+
+use crate::gateway::link::{GatewayID, WiringProblem};
+use crate::gateway::transport::{CommandSequenceNumber, InvalidFrameReason, ReceiveResponse};
+use crate::pv::application::{
+    Broadcast, NetworkStatusResponse, NodeTableResponseEntry, PowerReport, PvConfigurationResponse,
+    RadioConfigurationResponse, TopologyReport,
+};
+use crate::pv::network::{NodeAddress, ReceivedPacketHeader};
+use crate::pv::{LongAddress, NodeID, PacketType, SlotCounter};
+use crate::text::LossyStr;
+use crate::{gateway, pv, Counters, Pipeline};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The fraction of frames failing their checksum above which the line is considered unhealthy.
+const MAX_CHECKSUM_FAILURE_RATE: f64 = 0.05;
+
+/// A sink which notes what reaches the end of a pipeline, for a [`Report`].
+#[derive(Debug, Clone, Default)]
+pub struct Collector {
+    gateways: BTreeMap<GatewayID, GatewayActivity>,
+    enumeration_seen: bool,
+}
+
+/// What was heard from one gateway.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize)]
+pub struct GatewayActivity {
+    /// The number of receive responses from the gateway.
+    pub receive_responses: u64,
+    /// The number of PV packets those responses carried.
+    pub packets: u64,
+    /// The number of power reports among those packets.
+    pub power_reports: u64,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn gateway(&mut self, gateway_id: GatewayID) -> &mut GatewayActivity {
+        self.gateways.entry(gateway_id).or_default()
+    }
+}
+
+impl gateway::transport::Sink for Collector {
+    fn enumeration_started(&mut self, _enumeration_gateway_id: GatewayID) {
+        self.enumeration_seen = true;
+    }
+
+    fn gateway_identity_observed(&mut self, _gateway_id: GatewayID, _address: LongAddress) {}
+
+    fn gateway_version_observed(&mut self, _gateway_id: GatewayID, _version: &str, _raw: &[u8]) {}
+
+    fn enumeration_ended(&mut self, _gateway_id: GatewayID) {
+        self.enumeration_seen = true;
+    }
+
+    fn gateway_slot_counter_captured(&mut self, _gateway_id: GatewayID) {}
+
+    fn gateway_slot_counter_observed(
+        &mut self,
+        _gateway_id: GatewayID,
+        _slot_counter: SlotCounter,
+    ) {
+    }
+
+    fn packet_received(
+        &mut self,
+        gateway_id: GatewayID,
+        _header: &ReceivedPacketHeader,
+        _data: &[u8],
+    ) {
+        self.gateway(gateway_id).packets += 1;
+    }
+
+    fn command_executed(
+        &mut self,
+        gateway_id: GatewayID,
+        _request: (PacketType, &[u8]),
+        _response: (PacketType, &[u8]),
+    ) {
+        self.gateway(gateway_id);
+    }
+
+    fn command_timed_out(
+        &mut self,
+        _gateway_id: GatewayID,
+        _packet_type: PacketType,
+        _sequence_number: CommandSequenceNumber,
+    ) {
+    }
+
+    fn gateway_tx_buffers_free_observed(&mut self, _gateway_id: GatewayID, _tx_buffers_free: u8) {}
+
+    fn receive_status(&mut self, gateway_id: GatewayID, _status: &ReceiveResponse) {
+        self.gateway(gateway_id).receive_responses += 1;
+    }
+
+    fn invalid_frame(&mut self, frame: &gateway::link::Frame, reason: InvalidFrameReason) {
+        // A gateway answering in a format that isn't decoded is still a gateway
+        if let (InvalidFrameReason::Undecoded, gateway::link::Address::From(gateway_id)) =
+            (reason, frame.address)
+        {
+            self.gateway(gateway_id);
+        }
+    }
+}
+
+impl pv::application::Sink for Collector {
+    fn string_request(&mut self, _gateway_id: GatewayID, _pv_node_id: NodeID, _request: LossyStr) {}
+
+    fn string_response(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _response: LossyStr,
+    ) {
+    }
+
+    fn node_table_page(
+        &mut self,
+        _gateway_id: GatewayID,
+        _start_address: NodeAddress,
+        _nodes: &[NodeTableResponseEntry],
+    ) {
+    }
+
+    fn topology_report(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _topology_report: &TopologyReport,
+    ) {
+    }
+
+    fn power_report(
+        &mut self,
+        gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _power_report: &PowerReport,
+    ) {
+        self.gateway(gateway_id).power_reports += 1;
+    }
+
+    fn broadcast(&mut self, _gateway_id: GatewayID, _payload: &Broadcast) {}
+
+    fn network_status(&mut self, _gateway_id: GatewayID, _status: &NetworkStatusResponse) {}
+
+    fn gateway_radio_configuration(
+        &mut self,
+        _gateway_id: GatewayID,
+        _config: &RadioConfigurationResponse,
+    ) {
+    }
+
+    fn pv_configuration(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _config: &PvConfigurationResponse,
+    ) {
+    }
+
+    fn packet_loss_estimated(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _estimated_loss_pct: f64,
+    ) {
+    }
+}
+
+/// How a finding bears on whether `observe` will work.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// As expected.
+    Ok,
+    /// Worth knowing, but not a problem.
+    Info,
+    /// Likely to cause missing or degraded data.
+    Warning,
+    /// Prevents `observe` from producing data.
+    Problem,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Info => "info",
+            Status::Warning => "warning",
+            Status::Problem => "problem",
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One conclusion drawn from what was received.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Finding {
+    pub status: Status,
+    /// What was found.
+    pub message: String,
+    /// What to try next, in plain language.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// A gateway which was heard from, and what it said.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub struct GatewayReport {
+    pub id: GatewayID,
+    #[serde(flatten)]
+    pub activity: GatewayActivity,
+}
+
+/// What was received while listening to the bus, and what it suggests.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// How long the bus was listened to, in seconds.
+    pub duration_secs: f64,
+    pub bytes: u64,
+    pub frames: u64,
+    pub checksum_failures: u64,
+    pub noise: u64,
+    pub frames_to_gateways: u64,
+    pub frames_from_gateways: u64,
+    pub gateways: Vec<GatewayReport>,
+    pub enumeration_seen: bool,
+    pub receive_responses: u64,
+    /// Receive responses in the older polling format, which taptap can't yet decode.
+    pub legacy_receive_responses: u64,
+    pub receive_packets: u64,
+    pub power_reports: u64,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Report on a pipeline ending in a [`Collector`], which received `bytes` over `duration`.
+    pub fn new(rx: &Pipeline<Collector>, bytes: u64, duration: Duration) -> Self {
+        let counters = Counters::snapshot(rx);
+        let collector = rx.sink().sink().sink();
+        let mut report = Self {
+            duration_secs: duration.as_secs_f64(),
+            bytes,
+            frames: counters.link.frames,
+            checksum_failures: counters.link.checksums,
+            noise: counters.link.noise,
+            frames_to_gateways: counters.link.frames_to_gateways,
+            frames_from_gateways: counters.link.frames_from_gateways,
+            gateways: collector
+                .gateways
+                .iter()
+                .map(|(id, activity)| GatewayReport {
+                    id: *id,
+                    activity: *activity,
+                })
+                .collect(),
+            enumeration_seen: collector.enumeration_seen,
+            receive_responses: counters.transport.receive_responses,
+            legacy_receive_responses: counters.transport.legacy_receive_responses,
+            receive_packets: counters.transport.receive_packets,
+            power_reports: counters.application.power_reports,
+            findings: Vec::new(),
+        };
+        report.findings = report.diagnose(&counters);
+        report
+    }
+
+    /// Whether nothing was found which would stop `observe` from producing data.
+    pub fn passed(&self) -> bool {
+        self.findings
+            .iter()
+            .all(|finding| finding.status < Status::Problem)
+    }
+
+    fn diagnose(&self, counters: &Counters) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        // Bytes, and whether they form frames
+        if self.bytes == 0 {
+            findings.push(Finding::new(Status::Problem, "no data received").with_hint(
+                "check that the adapter is wired to the gateway bus and powered, and for \
+                     --tcp that the host and port are right",
+            ));
+            return findings;
+        }
+        if self.frames == 0 {
+            findings.push(
+                Finding::new(
+                    Status::Problem,
+                    format!("{} bytes received, but no valid frames", self.bytes),
+                )
+                .with_hint(WiringProblem::Unparseable.to_string()),
+            );
+            return findings;
+        }
+        findings.push(Finding::new(
+            Status::Ok,
+            format!(
+                "{} bytes received, forming {} frames",
+                self.bytes, self.frames
+            ),
+        ));
+
+        let checked = self.frames + self.checksum_failures;
+        if self.checksum_failures as f64 > checked as f64 * MAX_CHECKSUM_FAILURE_RATE {
+            findings.push(
+                Finding::new(
+                    Status::Warning,
+                    format!(
+                        "{} of {} frames failed their checksum",
+                        self.checksum_failures, checked
+                    ),
+                )
+                .with_hint(
+                    "check the bus termination and grounding, and keep the adapter's wiring short",
+                ),
+            );
+        }
+
+        // Both sides of the conversation
+        let one_sided = match (self.frames_to_gateways, self.frames_from_gateways) {
+            (_, 0) => Some(WiringProblem::OnlyToGateways),
+            (0, _) => Some(WiringProblem::OnlyFromGateways),
+            _ => None,
+        };
+        if let Some(problem) = one_sided {
+            let (message, hint) = problem.to_string().split_once("; ").map_or_else(
+                || (problem.to_string(), None),
+                |(message, hint)| (message.to_owned(), Some(hint.to_owned())),
+            );
+            let mut finding = Finding::new(Status::Problem, message);
+            finding.hint = hint;
+            findings.push(finding);
+        } else {
+            findings.push(Finding::new(
+                Status::Ok,
+                format!(
+                    "traffic seen in both directions: {} frames to gateways, {} from them",
+                    self.frames_to_gateways, self.frames_from_gateways
+                ),
+            ));
+        }
+
+        // Gateways
+        if self.gateways.is_empty() {
+            findings.push(
+                Finding::new(Status::Problem, "no gateway responded to the controller")
+                    .with_hint("check that the controller is running and that it polls gateways"),
+            );
+        } else {
+            let ids: Vec<String> = self
+                .gateways
+                .iter()
+                .map(|gateway| u16::from(gateway.id).to_string())
+                .collect();
+            findings.push(Finding::new(
+                Status::Ok,
+                format!("gateways heard from: {}", ids.join(", ")),
+            ));
+        }
+
+        if self.enumeration_seen {
+            findings.push(Finding::new(Status::Ok, "gateway enumeration seen"));
+        } else {
+            findings.push(
+                Finding::new(Status::Info, "no gateway enumeration seen").with_hint(
+                    "this is normal: the controller enumerates gateways when it starts, and \
+                     restarting it lets hardware addresses be learned sooner",
+                ),
+            );
+        }
+
+        // Receive responses, and the packets they carry
+        if self.legacy_receive_responses > 0 {
+            findings.push(
+                Finding::new(
+                    Status::Problem,
+                    format!(
+                        "{} receive responses in the older polling format, which can't yet be \
+                         decoded",
+                        self.legacy_receive_responses
+                    ),
+                )
+                .with_hint(
+                    "your CCA firmware uses the older polling format: please open an issue, \
+                     attaching a file made with `taptap capture`",
+                ),
+            );
+        }
+        if self.receive_responses == 0 {
+            if !self.gateways.is_empty() && self.legacy_receive_responses == 0 {
+                findings.push(
+                    Finding::new(Status::Problem, "no receive responses")
+                        .with_hint("the controller may not be polling gateways for PV packets"),
+                );
+            }
+        } else if self.receive_packets == 0 {
+            findings.push(
+                Finding::new(
+                    Status::Warning,
+                    format!(
+                        "{} receive responses, but none carried packets",
+                        self.receive_responses
+                    ),
+                )
+                .with_hint(
+                    "frames seen but no RECEIVE_RESPONSE payloads: your CCA firmware may use the \
+                     older polling format",
+                ),
+            );
+        } else {
+            findings.push(Finding::new(
+                Status::Ok,
+                format!(
+                    "{} receive responses carried {} packets",
+                    self.receive_responses, self.receive_packets
+                ),
+            ));
+        }
+
+        // Power reports
+        let invalid = counters.application.invalid_power_reports;
+        if self.power_reports > 0 {
+            findings.push(Finding::new(
+                Status::Ok,
+                format!("{} power reports decoded", self.power_reports),
+            ));
+        } else if self.receive_packets > 0 {
+            findings.push(
+                Finding::new(Status::Warning, "packets received, but no power reports").with_hint(
+                    "nodes only report while their panels produce power, so try again in \
+                         daylight, or listen for longer",
+                ),
+            );
+        }
+        if invalid > 0 {
+            findings.push(
+                Finding::new(
+                    Status::Warning,
+                    format!("{} power reports could not be decoded", invalid),
+                )
+                .with_hint("please open an issue, attaching this report as JSON"),
+            );
+        }
+
+        findings
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Listened for {:.0}s", self.duration_secs)?;
+        writeln!(f, "  bytes received        {}", self.bytes)?;
+        writeln!(f, "  valid frames          {}", self.frames)?;
+        writeln!(f, "  checksum failures     {}", self.checksum_failures)?;
+        writeln!(f, "  noise events          {}", self.noise)?;
+        writeln!(f, "  frames to gateways    {}", self.frames_to_gateways)?;
+        writeln!(f, "  frames from gateways  {}", self.frames_from_gateways)?;
+        writeln!(f, "  receive responses     {}", self.receive_responses)?;
+        if self.legacy_receive_responses > 0 {
+            writeln!(
+                f,
+                "  legacy responses      {}",
+                self.legacy_receive_responses
+            )?;
+        }
+        writeln!(f, "  packets received      {}", self.receive_packets)?;
+        writeln!(f, "  power reports         {}", self.power_reports)?;
+        writeln!(
+            f,
+            "  enumeration seen      {}",
+            if self.enumeration_seen { "yes" } else { "no" }
+        )?;
+        for gateway in &self.gateways {
+            writeln!(
+                f,
+                "  gateway {:<5}         {} receive responses, {} packets, {} power reports",
+                u16::from(gateway.id),
+                gateway.activity.receive_responses,
+                gateway.activity.packets,
+                gateway.activity.power_reports
+            )?;
+        }
+
+        writeln!(f)?;
+        for finding in &self.findings {
+            let status = format!("[{}]", finding.status);
+            writeln!(f, "{:<9} {}", status, finding.message)?;
+            if let Some(hint) = &finding.hint {
+                writeln!(f, "          {}", hint)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(report: &Report) -> Vec<(Status, &str)> {
+        report
+            .findings
+            .iter()
+            .map(|finding| (finding.status, finding.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn healthy() {
+        use crate::pv::physical::RSSI;
+        use crate::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let node = Node {
+            id: NodeID::try_from(2).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]),
+        };
+        let scenario = Scenario {
+            gateways: vec![Gateway {
+                id: gateway_id,
+                address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+                version: "Mgate Version G8.59\r".into(),
+                nodes: vec![node],
+            }],
+            power_reports: (0..3)
+                .map(|i| PowerReport {
+                    gateway_id,
+                    node_id: node.id,
+                    slot_counter: SlotCounter::from(i * 4000),
+                    measurement: Measurement {
+                        voltage_in: 30.0,
+                        voltage_out: 29.5,
+                        current: 6.5,
+                        dc_dc_duty_cycle: 1.0,
+                        temperature: 25.0,
+                        rssi: RSSI(120),
+                    },
+                })
+                .collect(),
+            ..Scenario::new(std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200))
+        };
+        let bytes = scenario.encode().bytes;
+
+        let mut rx = crate::pipeline(Collector::new());
+        rx.extend_from_slice(&bytes);
+        let report = Report::new(&rx, bytes.len() as u64, Duration::from_secs(30));
+        assert!(report.passed(), "{}", report);
+        assert!(report.enumeration_seen);
+        assert_eq!(report.power_reports, 3);
+        assert_eq!(
+            report
+                .gateways
+                .iter()
+                .map(|gateway| gateway.id)
+                .collect::<Vec<_>>(),
+            vec![gateway_id]
+        );
+        assert!(report.frames_to_gateways > 0 && report.frames_from_gateways > 0);
+        assert!(statuses(&report)
+            .iter()
+            .all(|(status, _)| *status == Status::Ok));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["findings"][0]["status"], "ok");
+        assert_eq!(json["gateways"][0]["power_reports"], report.power_reports);
+    }
+
+    #[test]
+    fn no_data() {
+        let rx = crate::pipeline(Collector::new());
+        let report = Report::new(&rx, 0, Duration::from_secs(30));
+        assert!(!report.passed());
+        assert_eq!(
+            statuses(&report),
+            vec![(Status::Problem, "no data received")]
+        );
+    }
+
+    #[test]
+    fn garbage() {
+        let mut rx = crate::pipeline(Collector::new());
+        let garbage: Vec<u8> = (0..1000u32).map(|i| (i * 37 % 251) as u8 | 0x01).collect();
+        rx.extend_from_slice(&garbage);
+        let report = Report::new(&rx, garbage.len() as u64, Duration::from_secs(30));
+        assert!(!report.passed());
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(
+            report.findings[0].hint.as_deref(),
+            Some("data present but not parseable; check baud rate / wiring polarity")
+        );
+    }
+
+    #[test]
+    fn legacy_polling() {
+        use crate::gateway::link::{Address, Frame, Type};
+
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let mut bytes = Vec::new();
+        for _ in 0..3 {
+            bytes.extend(
+                Frame {
+                    address: Address::To(gateway_id),
+                    frame_type: Type::LEGACY_RECEIVE_REQUEST,
+                    payload: vec![0x00, 0x01],
+                }
+                .encode(),
+            );
+            bytes.extend(
+                Frame {
+                    address: Address::From(gateway_id),
+                    frame_type: Type::LEGACY_RECEIVE_RESPONSE,
+                    payload: vec![0x00, 0x01, 0x02, 0x03],
+                }
+                .encode(),
+            );
+        }
+
+        let mut rx = crate::pipeline(Collector::new());
+        rx.extend_from_slice(&bytes);
+        let report = Report::new(&rx, bytes.len() as u64, Duration::from_secs(30));
+        assert!(!report.passed());
+        assert_eq!(report.legacy_receive_responses, 3);
+        assert_eq!(report.gateways.len(), 1);
+        let legacy = report
+            .findings
+            .iter()
+            .find(|finding| finding.message.contains("older polling format"))
+            .expect("legacy finding");
+        assert_eq!(legacy.status, Status::Problem);
+        assert!(!statuses(&report)
+            .iter()
+            .any(|(_, message)| *message == "no receive responses"));
+    }
+}
@@ -12,3 +12,6 @@ pub mod link;
 pub use link::{Frame, GatewayID};
 
 pub mod transport;
+
+pub mod capabilities;
+pub use capabilities::GatewayCapabilities;
@@ -0,0 +1,83 @@
+//! Differences in behavior between gateway firmware versions.
+//!
+//! Decoding decisions which depend on a gateway's firmware are expressed as fields of
+//! [`GatewayCapabilities`], rather than as checks against version strings, so that each quirk is
+//! described once in [`KNOWN_FIRMWARE`] and consulted by name wherever it matters.
+
+/// How a gateway's traffic should be interpreted.
+///
+/// The default describes current firmware.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct GatewayCapabilities {
+    /// The slot counters the gateway reports can't be used to timestamp power reports, which are
+    /// instead timestamped when they are received.
+    pub unreliable_slot_counters: bool,
+}
+
+/// Firmware versions whose behavior is known, by the firmware token of their version string.
+///
+/// Firmware not listed here is assumed to behave like current firmware.
+pub const KNOWN_FIRMWARE: &[(&str, GatewayCapabilities)] = &[(
+    "G8.59",
+    GatewayCapabilities {
+        unreliable_slot_counters: false,
+    },
+)];
+
+impl GatewayCapabilities {
+    /// Determine a gateway's capabilities from its normalized version string, like
+    /// `"Mgate Version G8.59 / Jul  6 2020 / 16:51:51 / GW-H158.4.3S0.12"`.
+    pub fn from_version(version: &str) -> Self {
+        firmware(version)
+            .and_then(|firmware| {
+                KNOWN_FIRMWARE
+                    .iter()
+                    .find(|(known, _)| *known == firmware)
+                    .map(|(_, capabilities)| *capabilities)
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Extract the firmware token from a version string.
+fn firmware(version: &str) -> Option<&str> {
+    let (_, rest) = version.split_once("Version ")?;
+    rest.split_whitespace().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_version() {
+        assert_eq!(
+            firmware("Mgate Version G8.59 / Jul  6 2020 / 16:51:51 / GW-H158.4.3S0.12"),
+            Some("G8.59")
+        );
+        assert_eq!(firmware("garbage"), None);
+
+        for (firmware, capabilities) in KNOWN_FIRMWARE {
+            assert_eq!(
+                GatewayCapabilities::from_version(&format!(
+                    "Mgate Version {} / Jan  1 2020 / 00:00:00",
+                    firmware
+                )),
+                *capabilities
+            );
+        }
+
+        // Unknown firmware is assumed to be current
+        assert_eq!(
+            GatewayCapabilities::from_version("Mgate Version G9.99"),
+            GatewayCapabilities::default()
+        );
+        assert_eq!(
+            GatewayCapabilities::from_version(""),
+            GatewayCapabilities::default()
+        );
+    }
+}
@@ -2,14 +2,43 @@
 
 mod address;
 
-pub use address::{Address, GatewayID, InvalidGatewayID};
+#[cfg(feature = "serde")]
+pub use address::gateway_id_keys;
+pub use address::{Address, GatewayID, InvalidGatewayID, ParseGatewayIDError};
 
 mod crc;
 
 mod escaping;
+mod foreign;
 mod receive;
 pub use receive::{Counters, Receiver, Sink};
 
+#[cfg(feature = "active")]
+mod send;
+#[cfg(feature = "active")]
+pub use send::{SendError, Sender};
+
+mod throughput;
+pub use throughput::{Throughput, ThroughputTable, TypeThroughput, BUS_BYTES_PER_SECOND};
+
+mod wiring;
+pub use wiring::{WiringCheck, WiringProblem};
+
+/// The largest frame the receiver will accept, in bytes, counting the address, frame type, payload,
+/// and CRC before escaping.
+pub const MAX_FRAME_SIZE: usize = 256;
+
+/// The largest payload which fits in a frame of `MAX_FRAME_SIZE`.
+pub const MAX_PAYLOAD_SIZE: usize = MAX_FRAME_SIZE - 2 - 2 - 2;
+
+/// A frame's payload is too long to be transmitted.
+#[derive(thiserror::Error, Debug, Copy, Clone, Eq, PartialEq)]
+#[error("frame payload of {payload_len} bytes exceeds the maximum of {max_payload} bytes")]
+pub struct FrameTooLong {
+    pub payload_len: usize,
+    pub max_payload: usize,
+}
+
 /// A gateway link layer frame.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Frame {
@@ -19,6 +48,52 @@ pub struct Frame {
 }
 
 impl Frame {
+    /// Check that the frame's payload is no longer than `max_payload` bytes, which is usually
+    /// `MAX_PAYLOAD_SIZE`.
+    ///
+    /// The limit applies before escaping: the receiver at the other end counts unescaped bytes, so
+    /// a payload which needs escaping is no more likely to be rejected than one which does not.
+    pub fn validate(&self, max_payload: usize) -> Result<(), FrameTooLong> {
+        if self.payload.len() > max_payload {
+            Err(FrameTooLong {
+                payload_len: self.payload.len(),
+                max_payload,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Determine the number of bytes `encode()` will produce, including preamble and escaping.
+    pub fn encoded_len(&self) -> usize {
+        let start = match self.address {
+            Address::From(_) => 3,
+            Address::To(_) => 5,
+        };
+        let crc = self.crc();
+        start
+            + escaping::escaped_length(&<[u8; 2]>::from(self.address))
+            + escaping::escaped_length(&self.frame_type.0.to_be_bytes())
+            + escaping::escaped_length(&self.payload)
+            + escaping::escaped_length(&crc.to_le_bytes())
+            + 2
+    }
+
+    /// Encode the frame for transmission, refusing to produce a frame which the other end would
+    /// drop for being too long.
+    pub fn try_encode(&self) -> Result<Vec<u8>, FrameTooLong> {
+        self.validate(MAX_PAYLOAD_SIZE)?;
+        Ok(self.encode())
+    }
+
+    fn crc(&self) -> u16 {
+        let mut body = Vec::with_capacity(2 + 2 + self.payload.len());
+        body.extend_from_slice(&<[u8; 2]>::from(self.address));
+        body.extend_from_slice(&self.frame_type.0.to_be_bytes());
+        body.extend_from_slice(&self.payload);
+        crc::crc(&body)
+    }
+
     /// Encode the frame into `Bytes` ready for transmission by the physical layer, including a
     /// preamble.
     pub fn encode(&self) -> Vec<u8> {
@@ -71,7 +146,7 @@ impl Frame {
 }
 
 /// A link layer frame type.
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Type(pub u16);
 impl Type {
     pub const RECEIVE_REQUEST: Self = Type(0x0148);
@@ -92,6 +167,11 @@ impl Type {
     pub const VERSION_RESPONSE: Self = Type(0x000B);
     pub const ENUMERATION_END_REQUEST: Self = Type(0x0E02);
     pub const ENUMERATION_END_RESPONSE: Self = Type(0x0006);
+    /// Polls a gateway for received packets, as older gateway firmware does instead of
+    /// [`Type::RECEIVE_REQUEST`].
+    pub const LEGACY_RECEIVE_REQUEST: Self = Type(0x0042);
+    /// Answers a [`Type::LEGACY_RECEIVE_REQUEST`]. Its payload layout isn't yet understood.
+    pub const LEGACY_RECEIVE_RESPONSE: Self = Type(0x0043);
 }
 
 impl std::fmt::Debug for Type {
@@ -115,6 +195,8 @@ impl std::fmt::Debug for Type {
             Self::VERSION_RESPONSE => f.write_str("Type::VERSION_RESPONSE"),
             Self::ENUMERATION_END_REQUEST => f.write_str("Type::ENUMERATION_END_REQUEST"),
             Self::ENUMERATION_END_RESPONSE => f.write_str("Type::ENUMERATION_END_RESPONSE"),
+            Self::LEGACY_RECEIVE_REQUEST => f.write_str("Type::LEGACY_RECEIVE_REQUEST"),
+            Self::LEGACY_RECEIVE_RESPONSE => f.write_str("Type::LEGACY_RECEIVE_RESPONSE"),
             Self(value) => f
                 .debug_tuple("Type")
                 .field(&format_args!("{:#04x}", value))
@@ -148,6 +230,41 @@ mod tests {
         assert!(encoded.capacity() <= encoded.len() + 6);
     }
 
+    #[test]
+    fn frame_length_limits() {
+        for byte in [0x00, 0x7e, 0xa4] {
+            for (len, valid) in [(MAX_PAYLOAD_SIZE, true), (MAX_PAYLOAD_SIZE + 1, false)] {
+                let frame = Frame {
+                    address: Address::To(GatewayID::try_from(0x1201).unwrap()),
+                    frame_type: Type::COMMAND_REQUEST,
+                    payload: vec![byte; len],
+                };
+
+                let encoded = frame.encode();
+                assert_eq!(frame.encoded_len(), encoded.len());
+
+                // Validation agrees with what the receiver will accept, even when every payload
+                // byte is escaped
+                let mut rx = Receiver::new(Vec::new());
+                rx.extend_from_slice(&encoded);
+                if valid {
+                    assert_eq!(frame.validate(MAX_PAYLOAD_SIZE), Ok(()));
+                    assert_eq!(frame.try_encode(), Ok(encoded));
+                    assert_eq!(rx.sink(), &[frame]);
+                } else {
+                    let err = FrameTooLong {
+                        payload_len: len,
+                        max_payload: MAX_PAYLOAD_SIZE,
+                    };
+                    assert_eq!(frame.validate(MAX_PAYLOAD_SIZE), Err(err));
+                    assert_eq!(frame.try_encode(), Err(err));
+                    assert_eq!(rx.sink(), &[]);
+                    assert_eq!(rx.counters().giants, 1);
+                }
+            }
+        }
+    }
+
     #[test]
     fn type_debug() {
         assert_eq!(
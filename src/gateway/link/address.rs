@@ -1,6 +1,3 @@
-use schemars::JsonSchema;
-use serde::de::Error;
-use serde::{Deserialize, Deserializer, Serialize};
 use std::convert::TryFrom;
 
 const DIRECTION_BIT: u16 = 0x8000;
@@ -50,8 +47,10 @@ impl From<Address> for [u8; 2] {
 }
 
 /// A 15-bit gateway ID.
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, JsonSchema)]
-#[serde(transparent)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct GatewayID(u16);
 
 impl GatewayID {
@@ -89,19 +88,138 @@ impl TryFrom<u16> for GatewayID {
     }
 }
 
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum ParseGatewayIDError {
+    #[error("empty gateway ID")]
+    Empty,
+    #[error("invalid gateway ID {0:?}: expected decimal, 0x-prefixed hex, or #-prefixed hex")]
+    Invalid(String),
+    #[error("gateway ID {0:?} is out of range: gateway IDs are at most 0x7fff (32767)")]
+    OutOfRange(String),
+}
+
+impl std::str::FromStr for GatewayID {
+    type Err = ParseGatewayIDError;
+
+    /// Parse a gateway ID.
+    ///
+    /// Gateway IDs are conventionally written in hex, but people type them every which way, so
+    /// this accepts:
+    ///
+    /// * `0x`-prefixed hex, like `0x1201`
+    /// * `#`-prefixed hex, like `#1201`
+    /// * bare hex containing at least one hex letter, like `12ab`
+    /// * otherwise, decimal, like `4609`
+    ///
+    /// Note that this means `1201` is decimal, i.e. `0x04b1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParseGatewayIDError::Empty);
+        }
+
+        let (digits, radix) = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+            (hex, 16)
+        } else if let Some(hex) = s.strip_prefix('#') {
+            (hex, 16)
+        } else if s.bytes().any(|b| b.is_ascii_alphabetic()) {
+            (s, 16)
+        } else {
+            (s, 10)
+        };
+
+        // from_str_radix() would accept a sign, which we don't
+        if digits.is_empty()
+            || !digits
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (radix == 16 && b.is_ascii_hexdigit()))
+        {
+            return Err(ParseGatewayIDError::Invalid(s.into()));
+        }
+
+        u16::from_str_radix(digits, radix)
+            .ok()
+            .and_then(|value| GatewayID::try_from(value).ok())
+            .ok_or_else(|| ParseGatewayIDError::OutOfRange(s.into()))
+    }
+}
+
 impl From<GatewayID> for u16 {
     fn from(value: GatewayID) -> Self {
         value.0
     }
 }
 
-impl<'de> Deserialize<'de> for GatewayID {
+/// Gateway IDs serialize as numbers, but deserialize from either numbers or strings.
+///
+/// Strings are parsed by `FromStr`, which accepts the keys written by [`gateway_id_keys`] as
+/// well as the decimal keys `serde_json` writes for numeric map keys.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for GatewayID {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        struct Visitor;
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = GatewayID;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a gateway ID")
+            }
+
+            fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+                u16::try_from(v)
+                    .ok()
+                    .and_then(|v| GatewayID::try_from(v).ok())
+                    .ok_or_else(|| E::custom(format!("invalid gateway ID {}", v)))
+            }
+
+            fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map_err(|_| E::custom(format!("invalid gateway ID {}", v)))
+                    .and_then(|v| self.visit_u64(v))
+            }
+
+            fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Serialize maps keyed by [`GatewayID`] with string keys like `"0x1201"`.
+///
+/// Formats like TOML only permit string keys. Use with `#[serde(with = "gateway_id_keys")]`.
+#[cfg(feature = "serde")]
+pub mod gateway_id_keys {
+    use super::GatewayID;
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S, V>(map: &BTreeMap<GatewayID, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        let mut s = serializer.serialize_map(Some(map.len()))?;
+        for (id, value) in map {
+            s.serialize_entry(&id.to_string(), value)?;
+        }
+        s.end()
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<BTreeMap<GatewayID, V>, D::Error>
     where
         D: Deserializer<'de>,
+        V: Deserialize<'de>,
     {
-        let id = u16::deserialize(deserializer)?;
-        Self::try_from(id).map_err(D::Error::custom)
+        // GatewayID accepts string keys on its own
+        BTreeMap::deserialize(deserializer)
     }
 }
 
@@ -122,6 +240,105 @@ mod tests {
         assert_eq!(GatewayID(0x1201).to_string(), "0x1201");
     }
 
+    #[test]
+    fn gateway_id_from_str() {
+        let ok = |s: &str, id: u16| assert_eq!(s.parse::<GatewayID>(), Ok(GatewayID(id)), "{}", s);
+        let invalid = |s: &str| {
+            assert_eq!(
+                s.parse::<GatewayID>(),
+                Err(ParseGatewayIDError::Invalid(s.into())),
+                "{}",
+                s
+            )
+        };
+        let out_of_range = |s: &str| {
+            assert_eq!(
+                s.parse::<GatewayID>(),
+                Err(ParseGatewayIDError::OutOfRange(s.into())),
+                "{}",
+                s
+            )
+        };
+
+        // Decimal
+        ok("0", 0);
+        ok("4609", 0x1201);
+        ok("1201", 1201);
+        ok("01201", 1201);
+        ok("32767", 0x7fff);
+        out_of_range("32768");
+        out_of_range("65536");
+        out_of_range("99999999999999999999");
+
+        // Prefixed hex
+        ok("0x1201", 0x1201);
+        ok("0X1201", 0x1201);
+        ok("0x1", 1);
+        ok("0x7FFF", 0x7fff);
+        ok("#1201", 0x1201);
+        ok("#7fff", 0x7fff);
+        out_of_range("0x8000");
+        out_of_range("#ffff");
+        out_of_range("0x10000");
+
+        // Bare hex, distinguishable by its letters
+        ok("12ab", 0x12ab);
+        ok("12AB", 0x12ab);
+        ok("a", 0xa);
+        ok("0b10", 0x0b10); // not binary
+        out_of_range("abcd");
+
+        // Garbage
+        assert_eq!("".parse::<GatewayID>(), Err(ParseGatewayIDError::Empty));
+        invalid("0x");
+        invalid("#");
+        invalid("-1");
+        invalid("+1");
+        invalid(" 1201");
+        invalid("1201 ");
+        invalid("12g4");
+        invalid("0x12g4");
+        invalid("#0x1201");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn gateway_id_serde() {
+        use serde::{Deserialize, Serialize};
+
+        let id = GatewayID(0x1201);
+        assert_eq!(serde_json::to_string(&id).unwrap(), "4609");
+        for json in ["4609", "\"4609\"", "\"0x1201\"", "\"#1201\""] {
+            assert_eq!(
+                serde_json::from_str::<GatewayID>(json).unwrap(),
+                id,
+                "{}",
+                json
+            );
+        }
+        for json in ["-1", "32768", "\"0x8000\"", "\"\"", "1.5", "null"] {
+            assert!(serde_json::from_str::<GatewayID>(json).is_err(), "{}", json);
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Table {
+            #[serde(with = "gateway_id_keys")]
+            map: std::collections::BTreeMap<GatewayID, u8>,
+        }
+        let table = Table {
+            map: [(GatewayID(0x1201), 1), (GatewayID(2), 2)].into(),
+        };
+        let json = serde_json::to_string(&table).unwrap();
+        assert_eq!(json, r#"{"map":{"0x02":2,"0x1201":1}}"#);
+        assert_eq!(serde_json::from_str::<Table>(&json).unwrap(), table);
+
+        // Keys written by serde_json's own numeric key handling still work
+        assert_eq!(
+            serde_json::from_str::<Table>(r#"{"map":{"2":2,"4609":1}}"#).unwrap(),
+            table
+        );
+    }
+
     #[test]
     fn address() {
         assert_eq!(Address::from([0x12, 0x01]), Address::To(GatewayID(0x1201)));
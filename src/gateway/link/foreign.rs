@@ -0,0 +1,112 @@
+//! Recognition of traffic from other protocols sharing the bus.
+//!
+//! Some installations share the RS-485 bus with other equipment, most often speaking Modbus RTU.
+//! Their traffic is well-formed, just not addressed to us, so it should be distinguished from line
+//! noise.
+
+/// The shortest Modbus RTU frame: address, function, and CRC.
+const MIN_MODBUS_FRAME_LENGTH: usize = 4;
+
+/// The longest Modbus RTU frame.
+pub const MAX_MODBUS_FRAME_LENGTH: usize = 256;
+
+/// Calculate a Modbus RTU CRC.
+fn modbus_crc(buffer: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for byte in buffer {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Determine whether `buffer` is exactly one plausible Modbus RTU frame.
+fn is_modbus_frame(buffer: &[u8]) -> bool {
+    if buffer.len() < MIN_MODBUS_FRAME_LENGTH || buffer.len() > MAX_MODBUS_FRAME_LENGTH {
+        return false;
+    }
+
+    // Unicast addresses are 1-247, and function codes are 1-127, possibly with the exception bit
+    let (address, function) = (buffer[0], buffer[1] & 0x7f);
+    if !(1..=247).contains(&address) || function == 0 {
+        return false;
+    }
+
+    let (body, crc) = buffer.split_at(buffer.len() - 2);
+    modbus_crc(body) == u16::from_le_bytes([crc[0], crc[1]])
+}
+
+/// Find the foreign frames at the start of a run of bytes received between our frames.
+///
+/// Returns the number of frames found, their total length, and the number of bytes consumed
+/// including any preamble bytes surrounding them.
+pub fn classify(run: &[u8]) -> (u64, u64, usize) {
+    let mut frames = 0;
+    let mut frame_bytes = 0;
+    let mut consumed = 0;
+
+    loop {
+        // Skip our own preamble bytes, which are not valid Modbus addresses
+        let rest = &run[consumed..];
+        let preamble = rest
+            .iter()
+            .take_while(|b| **b == 0x00 || **b == 0xff)
+            .count();
+        let rest = &rest[preamble..];
+
+        // Look for the shortest frame at this position
+        let Some(length) = (MIN_MODBUS_FRAME_LENGTH..=rest.len().min(MAX_MODBUS_FRAME_LENGTH))
+            .find(|length| is_modbus_frame(&rest[..*length]))
+        else {
+            if frames > 0 {
+                // Trailing preamble belongs to this run
+                consumed += preamble;
+            }
+            return (frames, frame_bytes, consumed);
+        };
+
+        frames += 1;
+        frame_bytes += length as u64;
+        consumed += preamble + length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Read holding registers 0-9 from unit 1, and the response
+    const REQUEST: &[u8] = &[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD];
+    const RESPONSE: &[u8] = &[0x01, 0x03, 0x02, 0x12, 0x34, 0xB5, 0x33];
+
+    #[test]
+    fn crc() {
+        assert!(is_modbus_frame(REQUEST));
+        assert!(is_modbus_frame(RESPONSE));
+        assert!(!is_modbus_frame(&REQUEST[..7]));
+        assert!(!is_modbus_frame(&[0x00, 0x03, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn runs() {
+        let mut run = vec![0x00, 0xff];
+        run.extend_from_slice(REQUEST);
+        run.extend_from_slice(RESPONSE);
+        run.extend_from_slice(&[0x00, 0xff, 0xff]);
+        assert_eq!(classify(&run), (2, 15, run.len()));
+
+        // Garbage after the frames is left alone
+        run.extend_from_slice(&[0x12, 0x34]);
+        assert_eq!(classify(&run), (2, 15, run.len() - 2));
+
+        // Garbage alone is not a frame
+        assert_eq!(classify(&[0x12, 0x34, 0x56, 0x78, 0x9a]), (0, 0, 0));
+        assert_eq!(classify(&[0x00, 0xff, 0xff]), (0, 0, 0));
+    }
+}
@@ -1,8 +1,17 @@
 use super::*;
+use crate::log_limit::LogLimiter;
 
 /// An object which handles reception callbacks.
 pub trait Sink {
     fn frame(&mut self, frame: Frame);
+
+    /// A frame was discarded for having an incorrect checksum.
+    ///
+    /// `bytes` is the frame as it appeared on the bus, from its start through its end, so that a
+    /// `Receiver` given the same bytes discards it the same way.
+    fn checksum_failed(&mut self, bytes: &[u8]) {
+        let _ = bytes;
+    }
 }
 
 impl Sink for Vec<Frame> {
@@ -21,11 +30,13 @@ pub struct Receiver<S: Sink> {
     state: State,
     counters: Counters,
     buffer: Vec<u8>,
+    // Bytes received between frames, and the noise counted while receiving them
+    run: Vec<u8>,
+    run_noise: u64,
+    warnings: LogLimiter<()>,
 }
 
 impl<S: Sink> Receiver<S> {
-    const MAX_FRAME_SIZE: usize = 256;
-
     /// Instantiate a new receiver with a given `Sink`.
     pub fn new(sink: S) -> Self {
         Self {
@@ -33,6 +44,9 @@ impl<S: Sink> Receiver<S> {
             state: Default::default(),
             counters: Default::default(),
             buffer: Default::default(),
+            run: Default::default(),
+            run_noise: 0,
+            warnings: Default::default(),
         }
     }
 
@@ -59,6 +73,8 @@ impl<S: Sink> Receiver<S> {
     /// Reset the counters.
     pub fn reset_counters(&mut self) {
         self.counters = Counters::default();
+        // Noise counted before the reset is gone, so it can't be taken back
+        self.run_noise = 0;
     }
 
     /// Add a slice of bytes to the receiver.
@@ -68,10 +84,53 @@ impl<S: Sink> Receiver<S> {
         for byte in buffer {
             self.push_u8(*byte);
         }
+
+        // Reads tend to end at gaps in transmission, which also delimit foreign frames
+        if matches!(self.state, State::Idle | State::Noise) {
+            self.classify_run(false);
+        }
+    }
+
+    /// Recognize foreign frames among the bytes received between frames, so they are not counted
+    /// as noise.
+    fn classify_run(&mut self, ended: bool) {
+        let (frames, frame_bytes, consumed) = foreign::classify(&self.run);
+        self.counters.foreign_frames += frames;
+        self.counters.foreign_bytes += frame_bytes;
+
+        if frames > 0 && consumed == self.run.len() {
+            // The whole run is explained by foreign frames, so any noise which follows is new
+            self.counters.noise -= self.run_noise;
+            self.run_noise = 0;
+            if !ended {
+                self.state = State::Idle;
+            }
+        }
+
+        if ended {
+            self.run.clear();
+            self.run_noise = 0;
+        } else {
+            self.run.drain(..consumed);
+            if self.run.len() > 2 * foreign::MAX_MODBUS_FRAME_LENGTH {
+                self.run
+                    .drain(..self.run.len() - foreign::MAX_MODBUS_FRAME_LENGTH);
+            }
+        }
     }
 
     /// Add a single byte to the receiver.
     fn push_u8(&mut self, byte: u8) {
+        match (self.state, byte) {
+            (State::Idle | State::Noise, 0x7e) => {
+                // Possibly the start of a frame, decided by the next byte
+            }
+            (State::Idle | State::Noise, _) => self.run.push(byte),
+            (State::StartOfFrame, 0x07) => self.classify_run(true),
+            (State::StartOfFrame, _) => self.run.extend_from_slice(&[0x7e, byte]),
+            _ => {}
+        }
+
         let next_state = match self.state {
             State::Idle => {
                 match byte {
@@ -104,7 +163,7 @@ impl<S: Sink> Receiver<S> {
                     // Escape sequence
                     0x7e => State::FrameEscape,
                     // Normal data byte
-                    _ if self.buffer.len() < Self::MAX_FRAME_SIZE => {
+                    _ if self.buffer.len() < MAX_FRAME_SIZE => {
                         self.buffer.push(byte);
                         State::Frame
                     }
@@ -118,13 +177,22 @@ impl<S: Sink> Receiver<S> {
                     self.parse_frame_from_buffer();
                     self.buffer.truncate(0);
                     State::Idle
+                } else if byte == 0x07 {
+                    // Start of frame, so this frame's end was lost, but the frame may be whole
+                    if self.recover_frame_from_buffer() {
+                        self.counters.recovered_frames += 1;
+                    } else {
+                        self.counters.noise += 1;
+                    }
+                    self.buffer.truncate(0);
+                    State::Frame
                 } else if let Ok(byte) = escaping::unescaped_byte(byte) {
-                    if self.buffer.len() < Self::MAX_FRAME_SIZE {
+                    if self.buffer.len() < MAX_FRAME_SIZE {
                         self.buffer.push(byte);
                         State::Frame
                     } else {
-                        self.buffer.truncate(0);
-                        State::GiantEscape
+                        // Overlong frame, ending in an escaped byte
+                        State::Giant
                     }
                 } else {
                     self.buffer.truncate(0);
@@ -133,7 +201,10 @@ impl<S: Sink> Receiver<S> {
             }
             State::Giant => match byte {
                 0x7e => State::GiantEscape,
-                _ => State::Giant,
+                _ => {
+                    self.counters.bytes_discarded_in_giants += 1;
+                    State::Giant
+                }
             },
             State::GiantEscape => {
                 match byte {
@@ -141,8 +212,16 @@ impl<S: Sink> Receiver<S> {
                     0x07 => State::Frame,
                     // End of frame
                     0x08 => State::Idle,
-                    // Continue discarding
-                    _ => State::Giant,
+                    // The previous 0x7e was garbage, but this one may start a frame
+                    0x7e => {
+                        self.counters.bytes_discarded_in_giants += 1;
+                        State::GiantEscape
+                    }
+                    // An escaped byte, or garbage; continue discarding
+                    _ => {
+                        self.counters.bytes_discarded_in_giants += 1;
+                        State::Giant
+                    }
                 }
             }
         };
@@ -150,8 +229,11 @@ impl<S: Sink> Receiver<S> {
         match next_state {
             State::Noise if self.state != State::Noise => {
                 self.counters.noise += 1;
+                self.run_noise += 1;
             }
             State::Giant if self.state != State::Giant && self.state != State::GiantEscape => {
+                // Discard what was buffered, along with the byte which overflowed it
+                self.counters.bytes_discarded_in_giants += self.buffer.len() as u64 + 1;
                 self.buffer.truncate(0);
                 self.counters.giants += 1;
             }
@@ -161,26 +243,63 @@ impl<S: Sink> Receiver<S> {
         self.state = next_state;
     }
 
+    /// Parse a frame whose end was lost from the buffer, returning whether one was found.
+    ///
+    /// A 0x7E within a frame is always escaped, so a frame start within a frame can only mean
+    /// that the frame's end was lost. If the frame is otherwise whole, its CRC still checks out,
+    /// either over the whole buffer or once the next frame's preamble is set aside.
+    fn recover_frame_from_buffer(&mut self) -> bool {
+        let preamble = self
+            .buffer
+            .iter()
+            .rev()
+            .take(MAX_PREAMBLE_LEN)
+            .take_while(|byte| matches!(byte, 0x00 | 0xff))
+            .count();
+        let Some(len) = (0..=preamble)
+            .map(|preamble| self.buffer.len() - preamble)
+            .find(|&len| len >= MIN_FRAME_LEN && crc_valid(&self.buffer[..len]))
+        else {
+            return false;
+        };
+
+        self.buffer.truncate(len);
+        self.parse_frame_from_buffer();
+        true
+    }
+
     fn parse_frame_from_buffer(&mut self) {
         // Ensure we're a valid length
-        if self.buffer.len() < 6 {
+        if self.buffer.len() < MIN_FRAME_LEN {
             self.counters.runts += 1;
             return;
         }
 
         // Verify the CRC
-        let (body, expected_crc) = self.buffer.split_at(self.buffer.len() - 2);
-        let crc = crc::crc(body);
-        let expected_crc = u16::from_le_bytes([expected_crc[0], expected_crc[1]]);
-        if expected_crc != crc {
+        if !crc_valid(&self.buffer) {
             self.counters.checksums += 1;
+            if let Some(count) = self.warnings.count(()) {
+                log::warn!(
+                    "discarded a frame with an incorrect checksum ({} so far)",
+                    count
+                );
+            }
+            let mut bytes = vec![0x7e, 0x07];
+            escaping::escape(&self.buffer, &mut bytes);
+            bytes.extend_from_slice(&[0x7e, 0x08]);
+            self.sink.checksum_failed(&bytes);
             return;
         }
 
+        let body = &self.buffer[..self.buffer.len() - 2];
         let address = Address::from([body[0], body[1]]);
         let frame_type = Type(u16::from_be_bytes([body[2], body[3]]));
 
         self.counters.frames += 1;
+        match address {
+            Address::To(_) => self.counters.frames_to_gateways += 1,
+            Address::From(_) => self.counters.frames_from_gateways += 1,
+        }
         self.sink.frame(Frame {
             address,
             frame_type,
@@ -189,6 +308,18 @@ impl<S: Sink> Receiver<S> {
     }
 }
 
+/// The shortest frame, with an address, a frame type, and a CRC.
+const MIN_FRAME_LEN: usize = 6;
+
+/// The longest preamble which precedes a frame's start, as sent by the controller.
+const MAX_PREAMBLE_LEN: usize = 3;
+
+/// Whether a frame ends with the CRC of what precedes it.
+fn crc_valid(frame: &[u8]) -> bool {
+    let (body, expected_crc) = frame.split_at(frame.len() - 2);
+    crc::crc(body) == u16::from_le_bytes([expected_crc[0], expected_crc[1]])
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
 enum State {
     #[default]
@@ -203,9 +334,16 @@ enum State {
 
 /// Counters describing the internal state transitions of a `Receiver`.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Counters {
     /// The number of valid frames successfully received.
     pub frames: u64,
+    /// The number of valid frames sent by the controller to a gateway.
+    pub frames_to_gateways: u64,
+    /// The number of valid frames sent by a gateway to the controller.
+    pub frames_from_gateways: u64,
     /// The number of frames discarded for being too short.
     pub runts: u64,
     /// The number of frames discarded for being too long.
@@ -214,6 +352,16 @@ pub struct Counters {
     pub checksums: u64,
     /// The number of inter-frame periods where line noise was detected.
     pub noise: u64,
+    /// The number of frames from another protocol, such as Modbus RTU, which were recognized
+    /// between frames. These are not counted as noise.
+    pub foreign_frames: u64,
+    /// The number of bytes in foreign frames.
+    pub foreign_bytes: u64,
+    /// The number of unescaped bytes discarded as part of frames which were too long.
+    pub bytes_discarded_in_giants: u64,
+    /// The number of valid frames received without their ends, having been followed directly by
+    /// the start of the next frame. These are also counted as frames.
+    pub recovered_frames: u64,
 }
 
 #[cfg(test)]
@@ -236,10 +384,16 @@ mod tests {
             rx.counters,
             Counters {
                 frames: 4,
+                frames_to_gateways: 2,
+                frames_from_gateways: 2,
                 runts: 0,
                 giants: 0,
                 checksums: 0,
                 noise: 0,
+                foreign_frames: 0,
+                foreign_bytes: 0,
+                bytes_discarded_in_giants: 0,
+                recovered_frames: 0,
             }
         );
         assert_eq!(rx.buffer.len(), 0);
@@ -291,15 +445,84 @@ mod tests {
             rx.counters,
             Counters {
                 frames: 4,
+                frames_to_gateways: 2,
+                frames_from_gateways: 2,
                 runts: 0,
                 giants: 0,
                 checksums: 0,
                 noise: 3,
+                foreign_frames: 0,
+                foreign_bytes: 0,
+                bytes_discarded_in_giants: 0,
+                recovered_frames: 0,
             }
         );
         assert_eq!(rx.buffer.len(), 0);
     }
 
+    #[test]
+    fn foreign_frames() {
+        let frame = [
+            0x00, 0xFF, 0xFF, 0x7E, 0x07, 0x12, 0x01, 0x01, 0x48, 0x00, 0x01, 0x18, 0x83, 0x04,
+            0x17, 0x44, 0x7E, 0x08,
+        ];
+        // A Modbus RTU request and response
+        let request = [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD];
+        let response = [0x01, 0x03, 0x02, 0x12, 0x34, 0xB5, 0x33];
+
+        let mut rx = Receiver::new(Vec::new());
+
+        // Foreign frames between our frames
+        rx.extend_from_slice(&frame);
+        rx.extend_from_slice(&request);
+        rx.extend_from_slice(&response);
+        rx.extend_from_slice(&frame);
+
+        // Foreign frames delivered in pieces
+        rx.extend_from_slice(&request[..3]);
+        rx.extend_from_slice(&request[3..]);
+
+        // Foreign frames delivered along with our frames
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&request);
+        buffer.extend_from_slice(&frame);
+        buffer.extend_from_slice(&response);
+        rx.extend_from_slice(&buffer);
+
+        assert_eq!(
+            rx.counters,
+            Counters {
+                frames: 3,
+                frames_to_gateways: 3,
+                frames_from_gateways: 0,
+                runts: 0,
+                giants: 0,
+                checksums: 0,
+                noise: 0,
+                foreign_frames: 5,
+                foreign_bytes: 38,
+                bytes_discarded_in_giants: 0,
+                recovered_frames: 0,
+            }
+        );
+
+        // Actual noise is still noise
+        rx.extend_from_slice(&[0x12, 0x34, 0x56]);
+        rx.extend_from_slice(&frame);
+        assert_eq!(rx.counters.noise, 1);
+        assert_eq!(rx.counters.foreign_frames, 5);
+    }
+
+    #[test]
+    fn reset_counters_within_foreign_frame() {
+        let mut rx = Receiver::new(Vec::new());
+        rx.extend_from_slice(&[0x01, 0x03, 0x00]);
+        rx.reset_counters();
+        rx.extend_from_slice(&[0x00, 0x00, 0x0A, 0xC5, 0xCD]);
+        assert_eq!(rx.counters.noise, 0);
+        assert_eq!(rx.counters.foreign_frames, 1);
+    }
+
     #[test]
     fn checksum() {
         let mut rx = Receiver::new(Vec::new());
@@ -316,10 +539,16 @@ mod tests {
             rx.counters,
             Counters {
                 frames: 2,
+                frames_to_gateways: 1,
+                frames_from_gateways: 1,
                 runts: 0,
                 giants: 0,
                 checksums: 2,
                 noise: 0,
+                foreign_frames: 0,
+                foreign_bytes: 0,
+                bytes_discarded_in_giants: 0,
+                recovered_frames: 0,
             }
         );
         assert_eq!(rx.buffer.len(), 0);
@@ -341,10 +570,16 @@ mod tests {
             rx.counters,
             Counters {
                 frames: 1,
+                frames_to_gateways: 0,
+                frames_from_gateways: 1,
                 runts: 0,
                 giants: 0,
                 checksums: 0,
                 noise: 6,
+                foreign_frames: 0,
+                foreign_bytes: 0,
+                bytes_discarded_in_giants: 0,
+                recovered_frames: 0,
             }
         );
         assert_eq!(rx.buffer.len(), 0);
@@ -370,10 +605,16 @@ mod tests {
             rx.counters,
             Counters {
                 frames: 1,
+                frames_to_gateways: 1,
+                frames_from_gateways: 0,
                 runts: 5,
                 giants: 0,
                 checksums: 0,
                 noise: 0,
+                foreign_frames: 0,
+                foreign_bytes: 0,
+                bytes_discarded_in_giants: 0,
+                recovered_frames: 0,
             }
         );
         assert_eq!(rx.buffer.len(), 0);
@@ -393,12 +634,184 @@ mod tests {
             rx.counters,
             Counters {
                 frames: 0,
+                frames_to_gateways: 0,
+                frames_from_gateways: 0,
                 runts: 0,
                 giants: 1,
                 checksums: 0,
                 noise: 0,
+                foreign_frames: 0,
+                foreign_bytes: 0,
+                bytes_discarded_in_giants: 1002,
+                recovered_frames: 0,
             }
         );
         assert_eq!(rx.buffer.len(), 0);
     }
+
+    fn frame() -> Frame {
+        Frame {
+            address: Address::From(0x1201.try_into().unwrap()),
+            frame_type: Type::RECEIVE_RESPONSE,
+            payload: b"\x00\xFF\x7C\xDB\xC2".as_slice().into(),
+        }
+    }
+
+    /// A frame which starts, but goes on longer than any frame can.
+    fn giant_bytes() -> Vec<u8> {
+        let mut bytes = vec![0x7E, 0x07];
+        bytes.extend_from_slice(&[0x55; MAX_FRAME_SIZE + 10]);
+        bytes
+    }
+
+    #[test]
+    fn giant_followed_by_frame() {
+        // The next frame starts immediately, with no idle bytes
+        let mut rx = Receiver::new(Vec::new());
+        rx.extend_from_slice(&giant_bytes());
+        rx.extend_from_slice(&frame().encode()[1..]);
+        assert_eq!(rx.state, State::Idle);
+        assert_eq!(rx.sink, vec![frame()]);
+        assert_eq!(rx.counters.giants, 1);
+        assert_eq!(
+            rx.counters.bytes_discarded_in_giants,
+            MAX_FRAME_SIZE as u64 + 10
+        );
+
+        // The giant ends, and then the next frame starts
+        let mut rx = Receiver::new(Vec::new());
+        rx.extend_from_slice(&giant_bytes());
+        rx.extend_from_slice(&[0x7E, 0x08]);
+        assert_eq!(rx.state, State::Idle);
+        rx.extend_from_slice(&frame().encode());
+        assert_eq!(rx.sink, vec![frame()]);
+        assert_eq!(rx.counters.giants, 1);
+        assert_eq!(rx.counters.noise, 0);
+    }
+
+    #[test]
+    fn giant_escape_sequences() {
+        let mut rx = Receiver::new(Vec::new());
+        rx.extend_from_slice(&giant_bytes());
+
+        // Escaped bytes and invalid escape sequences are discarded as a byte each
+        for escaped in [0x00, 0x01, 0x06, 0x55] {
+            rx.extend_from_slice(&[0x7E]);
+            assert_eq!(rx.state, State::GiantEscape);
+            rx.extend_from_slice(&[escaped]);
+            assert_eq!(rx.state, State::Giant);
+        }
+        assert_eq!(
+            rx.counters.bytes_discarded_in_giants,
+            MAX_FRAME_SIZE as u64 + 14
+        );
+
+        // A stray 0x7E doesn't hide the start of the next frame
+        rx.extend_from_slice(&[0x7E]);
+        rx.extend_from_slice(&frame().encode()[1..]);
+        assert_eq!(rx.state, State::Idle);
+        assert_eq!(rx.sink, vec![frame()]);
+        assert_eq!(rx.counters.giants, 1);
+        assert_eq!(
+            rx.counters.bytes_discarded_in_giants,
+            MAX_FRAME_SIZE as u64 + 15
+        );
+    }
+
+    #[test]
+    fn giant_ending_in_escaped_byte() {
+        let mut rx = Receiver::new(Vec::new());
+        rx.extend_from_slice(&[0x7E, 0x07]);
+        rx.extend_from_slice(&[0x55; MAX_FRAME_SIZE]);
+        assert_eq!(rx.state, State::Frame);
+
+        // The escape sequence which overflows the frame is complete, so what follows is data
+        rx.extend_from_slice(&[0x7E, 0x00]);
+        assert_eq!(rx.state, State::Giant);
+        rx.extend_from_slice(&[0x08]);
+        assert_eq!(rx.state, State::Giant);
+        assert_eq!(rx.counters.giants, 1);
+        assert_eq!(
+            rx.counters.bytes_discarded_in_giants,
+            MAX_FRAME_SIZE as u64 + 2
+        );
+
+        rx.extend_from_slice(&frame().encode()[1..]);
+        assert_eq!(rx.sink, vec![frame()]);
+        assert_eq!(rx.counters.giants, 1);
+    }
+
+    #[test]
+    fn frame_end_lost() {
+        // A frame's end is lost, so the next frame's start arrives just after its CRC
+        let encoded = frame().encode();
+        let mut rx = Receiver::new(Vec::new());
+        rx.extend_from_slice(&encoded[..encoded.len() - 2]);
+        rx.extend_from_slice(&encoded[1..]);
+        assert_eq!(rx.state, State::Idle);
+        assert_eq!(rx.sink, vec![frame(), frame()]);
+        assert_eq!(rx.counters.frames, 2);
+        assert_eq!(rx.counters.recovered_frames, 1);
+        assert_eq!(rx.counters.noise, 0);
+        assert_eq!(rx.counters.giants, 0);
+
+        // A byte is lost from the middle of the frame too, so it can't be recovered
+        let mut damaged = encoded[..encoded.len() - 2].to_vec();
+        damaged.remove(8);
+        let mut rx = Receiver::new(Vec::new());
+        rx.extend_from_slice(&damaged);
+        rx.extend_from_slice(&encoded[1..]);
+        assert_eq!(rx.state, State::Idle);
+        assert_eq!(rx.sink, vec![frame()]);
+        assert_eq!(rx.counters.recovered_frames, 0);
+        assert_eq!(rx.counters.noise, 1);
+        assert_eq!(rx.counters.checksums, 0);
+    }
+
+    #[test]
+    fn frame_ends_lost_back_to_back() {
+        let frames = [
+            Frame {
+                address: Address::To(0x1201.try_into().unwrap()),
+                frame_type: Type::RECEIVE_REQUEST,
+                payload: b"\x00\x01\x18\x83\x04".as_slice().into(),
+            },
+            frame(),
+            // Escaped 0x7E bytes, even ones looking like frame starts and ends, are data
+            Frame {
+                address: Address::From(0x1201.try_into().unwrap()),
+                frame_type: Type::RECEIVE_RESPONSE,
+                payload: b"\x7E\x07\x7E\x08\x7E\xFF\xFF".as_slice().into(),
+            },
+            frame(),
+        ];
+
+        // Every frame but the last loses its end, and each is followed by the next's preamble
+        let mut bytes = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            let encoded = frame.encode();
+            let end = if i + 1 < frames.len() { 2 } else { 0 };
+            bytes.extend_from_slice(&encoded[..encoded.len() - end]);
+        }
+
+        // Byte by byte or all at once, every frame is recovered
+        for chunk_size in [1, bytes.len()] {
+            let mut rx = Receiver::new(Vec::new());
+            for chunk in bytes.chunks(chunk_size) {
+                rx.extend_from_slice(chunk);
+            }
+            assert_eq!(rx.state, State::Idle);
+            assert_eq!(rx.sink, frames);
+            assert_eq!(
+                rx.counters,
+                Counters {
+                    frames: 4,
+                    frames_to_gateways: 1,
+                    frames_from_gateways: 3,
+                    recovered_frames: 3,
+                    ..Default::default()
+                }
+            );
+        }
+    }
 }
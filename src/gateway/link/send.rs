@@ -0,0 +1,80 @@
+use super::*;
+use std::io::Write;
+
+/// An error sending a frame.
+#[derive(thiserror::Error, Debug)]
+pub enum SendError {
+    #[error(transparent)]
+    TooLong(#[from] FrameTooLong),
+    #[error("error writing frame: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A sender which writes `Frame`s to a connection, escaped and with a CRC.
+///
+/// The sender writes each frame in full and flushes it, so that a frame is never left half
+/// transmitted on a bus shared with other devices.
+#[derive(Debug)]
+pub struct Sender<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Sender<W> {
+    /// Instantiate a new sender writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Access the writer.
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// Mutably access the writer.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Destroy the `Sender` to obtain the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Send a frame, refusing to send one which the other end would drop for being too long.
+    pub fn send(&mut self, frame: &Frame) -> Result<(), SendError> {
+        let bytes = frame.try_encode()?;
+        self.writer.write_all(&bytes)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send() {
+        let frame = Frame {
+            address: Address::To(GatewayID::try_from(0x1201).unwrap()),
+            frame_type: Type::PING_REQUEST,
+            payload: vec![0x01],
+        };
+        let mut sender = Sender::new(Vec::new());
+        sender.send(&frame).unwrap();
+
+        // The ping request from the doc
+        assert_eq!(
+            sender.writer().as_slice(),
+            [0x00, 0xFF, 0xFF, 0x7E, 0x07, 0x12, 0x01, 0x0B, 0x00, 0x01, 0xFE, 0x83, 0x7E, 0x08]
+        );
+
+        // A frame which is too long isn't written at all
+        let frame = Frame {
+            payload: vec![0; MAX_PAYLOAD_SIZE + 1],
+            ..frame
+        };
+        assert!(matches!(sender.send(&frame), Err(SendError::TooLong(_))));
+        assert_eq!(sender.into_inner().len(), 14);
+    }
+}
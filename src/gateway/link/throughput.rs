@@ -0,0 +1,161 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The gateway bus runs at 38400 baud with 8N1 framing, i.e. ten bits per byte.
+pub const BUS_BYTES_PER_SECOND: f64 = 38400.0 / 10.0;
+
+/// A `Sink` which accounts for the frames passing through it, by frame type, before forwarding
+/// them to another `Sink`.
+///
+/// This can be layered between a `Receiver` and its usual sink to learn what the bus is spending
+/// its bandwidth on.
+#[derive(Debug)]
+pub struct Throughput<S: Sink> {
+    sink: S,
+    table: ThroughputTable,
+}
+
+impl<S: Sink> Throughput<S> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            table: Default::default(),
+        }
+    }
+
+    /// Access the `Sink`.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Mutably access the `Sink`.
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Destroy the `Throughput` to obtain the `Sink`.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+
+    /// The throughput accumulated so far.
+    pub fn table(&self) -> &ThroughputTable {
+        &self.table
+    }
+
+    /// Take the throughput accumulated so far, starting a new interval.
+    pub fn take_table(&mut self) -> ThroughputTable {
+        std::mem::take(&mut self.table)
+    }
+}
+
+impl<S: Sink> Sink for Throughput<S> {
+    fn frame(&mut self, frame: Frame) {
+        self.table.push(&frame);
+        self.sink.frame(frame);
+    }
+}
+
+/// Frame and byte counts for each frame type.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct ThroughputTable(pub BTreeMap<Type, TypeThroughput>);
+
+impl ThroughputTable {
+    fn push(&mut self, frame: &Frame) {
+        let entry = self.0.entry(frame.frame_type).or_default();
+        entry.frames += 1;
+        entry.payload_bytes += frame.payload.len() as u64;
+        entry.wire_bytes += frame.encode().len() as u64;
+    }
+
+    /// The sum of every frame type's throughput.
+    pub fn total(&self) -> TypeThroughput {
+        self.0
+            .values()
+            .fold(TypeThroughput::default(), |total, t| TypeThroughput {
+                frames: total.frames + t.frames,
+                payload_bytes: total.payload_bytes + t.payload_bytes,
+                wire_bytes: total.wire_bytes + t.wire_bytes,
+            })
+    }
+}
+
+/// Frame and byte counts for a single frame type.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct TypeThroughput {
+    /// The number of frames received.
+    pub frames: u64,
+    /// The number of payload bytes received, excluding framing and escaping.
+    pub payload_bytes: u64,
+    /// The number of bytes these frames occupied on the wire, including preamble, framing,
+    /// escaping, and CRC.
+    pub wire_bytes: u64,
+}
+
+impl TypeThroughput {
+    /// The fraction of the bus's capacity these frames occupied over an interval.
+    pub fn utilization(&self, interval: Duration) -> f64 {
+        self.wire_bytes as f64 / (BUS_BYTES_PER_SECOND * interval.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounting() {
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let frames = [
+            Frame {
+                address: Address::To(gateway_id),
+                frame_type: Type::RECEIVE_REQUEST,
+                payload: vec![0x00, 0x01, 0x01],
+            },
+            Frame {
+                address: Address::From(gateway_id),
+                frame_type: Type::RECEIVE_RESPONSE,
+                // Contains bytes which must be escaped
+                payload: vec![0x00, 0x7e, 0x7e],
+            },
+            Frame {
+                address: Address::To(gateway_id),
+                frame_type: Type::RECEIVE_REQUEST,
+                payload: vec![0x00, 0x01, 0x02],
+            },
+        ];
+
+        let mut rx = Receiver::new(Throughput::new(Vec::new()));
+        for frame in &frames {
+            rx.extend_from_slice(&frame.encode());
+        }
+
+        // Frames are forwarded unchanged
+        assert_eq!(rx.sink().sink(), &frames.to_vec());
+
+        let table = rx.sink_mut().take_table();
+        assert_eq!(
+            table.0.get(&Type::RECEIVE_REQUEST),
+            Some(&TypeThroughput {
+                frames: 2,
+                payload_bytes: 6,
+                wire_bytes: 2 * 16,
+            })
+        );
+        assert_eq!(
+            table.0.get(&Type::RECEIVE_RESPONSE),
+            Some(&TypeThroughput {
+                frames: 1,
+                payload_bytes: 3,
+                wire_bytes: 16,
+            })
+        );
+        assert_eq!(table.total().frames, 3);
+        assert_eq!(
+            table.total().utilization(Duration::from_secs(1)),
+            48.0 / 3840.0
+        );
+        assert!(rx.sink().table().0.is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+//! Recognizing a bus which is wired or tapped wrong.
+//!
+//! An adapter with RS-485 A and B swapped, or tapped where only one side of the bus is visible,
+//! can still receive frames which parse, but only ever in one direction. At the wrong baud rate or
+//! with the polarity reversed, data arrives but never forms a valid frame. Both look like a quiet
+//! system unless someone reads the counters, so a [`WiringCheck`] reads them instead.
+
+use super::Counters;
+use std::time::{Duration, Instant};
+
+/// The number of frames after which the bus is judged.
+pub const MIN_FRAMES: u64 = 100;
+
+/// How long after the check starts the bus is judged, however few frames were received.
+pub const MIN_DURATION: Duration = Duration::from_secs(30);
+
+/// A problem evident from the link layer's counters.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WiringProblem {
+    /// Frames were received from the controller, but none from gateways.
+    OnlyToGateways,
+    /// Frames were received from gateways, but none from the controller.
+    OnlyFromGateways,
+    /// Data was received, but no valid frames.
+    Unparseable,
+}
+
+impl std::fmt::Display for WiringProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WiringProblem::OnlyToGateways => {
+                "only controller\u{2192}gateway traffic observed; check wiring or adapter tap point"
+            }
+            WiringProblem::OnlyFromGateways => {
+                "only gateway\u{2192}controller traffic observed; check wiring or adapter tap point"
+            }
+            WiringProblem::Unparseable => {
+                "data present but not parseable; check baud rate / wiring polarity"
+            }
+        })
+    }
+}
+
+/// Judges a bus by its link layer counters, once enough has been received to tell.
+///
+/// The bus is judged once `MIN_FRAMES` valid frames were received, or after `MIN_DURATION` if
+/// anything was received at all, and only once: a problem is reported at most one time.
+#[derive(Debug, Clone)]
+pub struct WiringCheck {
+    started: Instant,
+    judged: bool,
+}
+
+impl WiringCheck {
+    /// Start checking at `now`, against counters which started from zero.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            judged: false,
+        }
+    }
+
+    /// Judge the bus by `counters` as of `now`, returning a problem if this is the moment one
+    /// became evident.
+    pub fn check(&mut self, counters: &Counters, now: Instant) -> Option<WiringProblem> {
+        if self.judged {
+            return None;
+        }
+
+        let waited = now.saturating_duration_since(self.started) >= MIN_DURATION;
+        let invalid = counters.noise + counters.checksums + counters.runts + counters.giants;
+        let problem = if counters.frames >= MIN_FRAMES || (waited && counters.frames > 0) {
+            if counters.frames_from_gateways == 0 {
+                Some(WiringProblem::OnlyToGateways)
+            } else if counters.frames_to_gateways == 0 {
+                Some(WiringProblem::OnlyFromGateways)
+            } else {
+                None
+            }
+        } else if waited && invalid > 0 {
+            Some(WiringProblem::Unparseable)
+        } else {
+            // Nothing to go on yet
+            return None;
+        };
+
+        self.judged = true;
+        problem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(to: u64, from: u64, noise: u64) -> Counters {
+        Counters {
+            frames: to + from,
+            frames_to_gateways: to,
+            frames_from_gateways: from,
+            noise,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn healthy() {
+        let t0 = Instant::now();
+        let mut check = WiringCheck::new(t0);
+        assert_eq!(check.check(&counters(40, 40, 2), t0), None);
+        assert_eq!(check.check(&counters(50, 50, 2), t0), None);
+
+        // A bus judged healthy stays that way
+        assert_eq!(check.check(&counters(1000, 50, 2), t0), None);
+    }
+
+    #[test]
+    fn one_direction() {
+        let t0 = Instant::now();
+        let mut check = WiringCheck::new(t0);
+        assert_eq!(check.check(&counters(99, 0, 0), t0), None);
+        assert_eq!(
+            check.check(&counters(100, 0, 0), t0),
+            Some(WiringProblem::OnlyToGateways)
+        );
+        assert_eq!(check.check(&counters(200, 0, 0), t0), None);
+
+        // A quiet bus is judged after a while instead
+        let mut check = WiringCheck::new(t0);
+        assert_eq!(check.check(&counters(0, 5, 0), t0 + MIN_DURATION / 2), None);
+        assert_eq!(
+            check.check(&counters(0, 10, 0), t0 + MIN_DURATION),
+            Some(WiringProblem::OnlyFromGateways)
+        );
+    }
+
+    #[test]
+    fn unparseable() {
+        let t0 = Instant::now();
+        let mut check = WiringCheck::new(t0);
+        let garbage = Counters {
+            noise: 300,
+            checksums: 20,
+            ..Default::default()
+        };
+        assert_eq!(check.check(&garbage, t0 + MIN_DURATION / 2), None);
+        assert_eq!(
+            check.check(&garbage, t0 + MIN_DURATION),
+            Some(WiringProblem::Unparseable)
+        );
+        assert_eq!(check.check(&garbage, t0 + MIN_DURATION * 2), None);
+
+        // Silence is not a wiring problem, at least not one this can diagnose
+        let mut check = WiringCheck::new(t0);
+        assert_eq!(
+            check.check(&Counters::default(), t0 + MIN_DURATION * 2),
+            None
+        );
+    }
+}
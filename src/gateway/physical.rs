@@ -6,11 +6,24 @@
 //! * `serialport`, when compiled with the `serialport` feature
 //! * [`tcp`]
 //! * `termios`, when compiled on UNIX-like systems
+//! * `tokio`, when compiled with the `tokio` feature, for async programs
+//!
+//! [`timing`] profiles how data is delivered by a connection, to diagnose adapters which buffer it.
 
 use std::fmt::Debug;
 
-pub trait Connection: std::io::Read + std::io::Write + Debug {}
+pub trait Connection: std::io::Read + std::io::Write + Debug + Send {}
+
+impl Connection for Box<dyn Connection> {}
+
+/// A connection for async programs, read and written through tokio.
+#[cfg(feature = "tokio")]
+pub trait AsyncConnection:
+    ::tokio::io::AsyncRead + ::tokio::io::AsyncWrite + Debug + Send + Unpin
+{
+}
 
+#[cfg(feature = "serialport")]
 pub mod serialport;
 
 #[cfg(unix)]
@@ -18,5 +31,10 @@ pub mod termios;
 
 pub mod tcp;
 
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+pub mod timing;
+
 //#[cfg(all(target_arch = "armv7l", target_os = "linux"))]
 //pub mod trace_meshdcd;
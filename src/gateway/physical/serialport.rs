@@ -29,6 +29,7 @@ impl PortInfo {
 #[derive(Debug)]
 pub struct Port {
     pub inner: Box<dyn SerialPort>,
+    return_timeouts: bool,
 }
 
 impl Port {
@@ -44,7 +45,17 @@ impl Port {
     }
 
     fn new(inner: Box<dyn SerialPort>) -> Self {
-        Port { inner }
+        Port {
+            inner,
+            return_timeouts: false,
+        }
+    }
+
+    /// Fail reads which receive nothing for a few milliseconds with
+    /// [`std::io::ErrorKind::TimedOut`], rather than waiting until data arrives, for callers which
+    /// need to do something else in the meantime.
+    pub fn set_return_timeouts(&mut self, return_timeouts: bool) {
+        self.return_timeouts = return_timeouts;
     }
 }
 
@@ -53,7 +64,7 @@ impl std::io::Read for Port {
         loop {
             match self.inner.read(buf) {
                 Ok(n) => return Ok(n),
-                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut && !self.return_timeouts => {
                     continue;
                 }
                 Err(e) => return Err(e),
@@ -1,18 +1,68 @@
+//! Serial-over-TCP connections, as offered by RS-485-to-Ethernet adapters.
+//!
+//! Every connection enables TCP keepalive, so that an adapter which disappears without closing the
+//! connection is noticed within about a minute rather than never. Adapters can also wedge while
+//! keeping the connection open, so a connection can be given an idle timeout, after which a read
+//! which receives nothing fails with [`std::io::ErrorKind::TimedOut`] wrapping an [`IdleTimeout`].
+//!
+//! A [`ReconnectingConnection`] never fails to read. Whenever its connection is closed, fails, or
+//! idles out, it logs why and reconnects, backing off exponentially while the adapter is
+//! unreachable. The bytes on either side of the gap needn't line up: the gateway link layer
+//! discards a partial frame as it would any other noise.
+
 use std::io::{Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long to wait before the first attempt to reconnect, doubling after each failure.
+pub const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest to wait between attempts to reconnect.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The error inside the [`TimedOut`](std::io::ErrorKind::TimedOut) error a [`Connection`] returns
+/// when it receives nothing for its idle timeout.
+///
+/// A serial port's read timeout only means that nothing has arrived yet, but an idle timeout means
+/// the connection is as good as lost.
+#[derive(thiserror::Error, Debug, Copy, Clone, Eq, PartialEq)]
+#[error("no data received for {0:?}")]
+pub struct IdleTimeout(pub Duration);
+
+impl IdleTimeout {
+    /// Whether `error` is a connection's idle timeout.
+    pub fn is(error: &std::io::Error) -> bool {
+        error.get_ref().is_some_and(|e| e.is::<IdleTimeout>())
+    }
+}
 
 /// A TCP serial connection.
 #[derive(Debug)]
 pub struct Connection {
     socket: TcpStream,
     readonly: bool,
+    idle_timeout: Option<Duration>,
 }
 
 impl Connection {
     pub fn connect<A: ToSocketAddrs>(addr: A, readonly: bool) -> Result<Self, std::io::Error> {
         let socket = TcpStream::connect(addr)?;
+        if let Err(e) = enable_keepalive(&socket) {
+            log::warn!("error enabling TCP keepalive: {}", e);
+        }
+
+        Ok(Self {
+            socket,
+            readonly,
+            idle_timeout: None,
+        })
+    }
 
-        Ok(Self { socket, readonly })
+    /// Fail reads which receive nothing for `idle_timeout`, or never if `None`.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) -> std::io::Result<()> {
+        self.socket.set_read_timeout(idle_timeout)?;
+        self.idle_timeout = idle_timeout;
+        Ok(())
     }
 }
 
@@ -20,7 +70,21 @@ impl super::Connection for Connection {}
 
 impl Read for Connection {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.socket.read(buf)
+        match self.socket.read(buf) {
+            // Platforms disagree on how a read timeout is reported
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    IdleTimeout(self.idle_timeout.unwrap_or_default()),
+                ))
+            }
+            result => result,
+        }
     }
 }
 
@@ -41,3 +105,181 @@ impl Write for Connection {
         }
     }
 }
+
+/// Probe an idle connection after 30 seconds, then every 10 seconds, giving up after 3 probes.
+#[cfg(unix)]
+pub(super) fn enable_keepalive(socket: &impl std::os::unix::io::AsRawFd) -> std::io::Result<()> {
+    let set = |level, name, value: libc::c_int| {
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    };
+
+    set(libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, 30)?;
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, 10)?;
+        set(libc::IPPROTO_TCP, libc::TCP_KEEPCNT, 3)?;
+    }
+    #[cfg(target_os = "macos")]
+    set(libc::IPPROTO_TCP, libc::TCP_KEEPALIVE, 30)?;
+    Ok(())
+}
+
+/// Keepalive isn't configured on other platforms, leaving the idle timeout to notice a lost peer.
+#[cfg(not(unix))]
+pub(super) fn enable_keepalive<S>(_socket: &S) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// A TCP serial connection which reconnects whenever it's lost.
+#[derive(Debug)]
+pub struct ReconnectingConnection {
+    host: String,
+    port: u16,
+    readonly: bool,
+    idle_timeout: Option<Duration>,
+    connection: Option<Connection>,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    reconnections: u64,
+}
+
+impl ReconnectingConnection {
+    /// Connect to `host:port`, reconnecting whenever the connection is lost or receives nothing
+    /// for `idle_timeout`.
+    ///
+    /// The first attempt to connect isn't retried, so that a misconfigured address fails fast.
+    pub fn connect(
+        host: &str,
+        port: u16,
+        readonly: bool,
+        idle_timeout: Option<Duration>,
+    ) -> std::io::Result<Self> {
+        let mut connection = Connection::connect((host, port), readonly)?;
+        connection.set_idle_timeout(idle_timeout)?;
+        Ok(Self {
+            host: host.into(),
+            port,
+            readonly,
+            idle_timeout,
+            connection: Some(connection),
+            min_backoff: MIN_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+            backoff: MIN_BACKOFF,
+            reconnections: 0,
+        })
+    }
+
+    /// Wait at least `min` and at most `max` between attempts to reconnect.
+    pub fn set_backoff(&mut self, min: Duration, max: Duration) {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self.backoff = min;
+    }
+
+    /// The number of times the connection was re-established.
+    pub fn reconnections(&self) -> u64 {
+        self.reconnections
+    }
+
+    /// Block until connected.
+    fn reconnect(&mut self) -> Connection {
+        loop {
+            std::thread::sleep(self.backoff);
+            let result = Connection::connect((self.host.as_str(), self.port), self.readonly)
+                .and_then(|mut connection| {
+                    connection.set_idle_timeout(self.idle_timeout)?;
+                    Ok(connection)
+                });
+            match result {
+                Ok(connection) => {
+                    log::info!("reconnected to {}:{}", self.host, self.port);
+                    self.reconnections += 1;
+                    return connection;
+                }
+                Err(e) => {
+                    self.backoff = (self.backoff * 2).min(self.max_backoff);
+                    log::warn!(
+                        "error reconnecting to {}:{}: {}; retrying in {:?}",
+                        self.host,
+                        self.port,
+                        e,
+                        self.backoff
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl super::Connection for ReconnectingConnection {}
+
+impl Read for ReconnectingConnection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let connection = match &mut self.connection {
+                Some(connection) => connection,
+                None => {
+                    let connection = self.reconnect();
+                    self.connection.insert(connection)
+                }
+            };
+
+            match connection.read(buf) {
+                Ok(0) if !buf.is_empty() => {
+                    log::warn!("{}:{} closed the connection", self.host, self.port);
+                }
+                Ok(n) => {
+                    // Only a connection which delivers data resets the backoff, so that an
+                    // adapter which accepts connections and immediately closes them isn't
+                    // hammered
+                    self.backoff = self.min_backoff;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::warn!("lost connection to {}:{}: {}", self.host, self.port, e);
+                }
+            }
+            self.connection = None;
+        }
+    }
+}
+
+impl Write for ReconnectingConnection {
+    /// Write to the current connection, failing if there isn't one.
+    ///
+    /// A failed write drops the connection, so that the next read reconnects.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let connection = self
+            .connection
+            .as_mut()
+            .ok_or(std::io::ErrorKind::NotConnected)?;
+        let result = connection.write(buf);
+        if matches!(&result, Err(e) if e.kind() != std::io::ErrorKind::Unsupported) {
+            self.connection = None;
+        }
+        result
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.connection {
+            Some(connection) => connection.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Timing of the bytes and frames read from a connection.
+//!
+//! A direct RS-485 connection delivers bytes as they arrive on the bus, so reads are small and
+//! closely spaced while a frame is being transmitted. Some adapters, particularly RS-485-to-TCP
+//! converters, instead buffer data and deliver it in batches. Batching delays every frame by up
+//! to the batching interval, which degrades the accuracy of anything timed by when frames
+//! arrive, such as the slot clock.
+//!
+//! A [`TimingProfile`] summarizes the gaps between bytes and between frames as histograms, and
+//! recognizes batching.
+
+use std::time::Duration;
+
+/// Reads which follow a gap of at least this long are considered to be delivered in a batch.
+pub const BATCHING_THRESHOLD: Duration = Duration::from_millis(20);
+
+/// The share of bytes which must be delivered in batches before a profile is considered
+/// [batched](TimingProfile::batching).
+pub const BATCHING_SHARE: f64 = 0.5;
+
+/// The upper bound of each histogram bucket but the last, in milliseconds.
+const BUCKET_BOUNDS_MS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/// A histogram of durations, in fixed buckets from under a millisecond to over five seconds.
+///
+/// Serializes as a sequence of [`Bucket`]s.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Histogram {
+    /// The number of durations in each bucket, aligned with [`Histogram::buckets`].
+    counts: [u64; BUCKET_BOUNDS_MS.len() + 1],
+}
+
+/// One bucket of a [`Histogram`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Bucket {
+    /// The inclusive lower bound of this bucket, in milliseconds.
+    pub from_ms: u64,
+    /// The exclusive upper bound of this bucket, in milliseconds, or `None` for the last bucket.
+    pub to_ms: Option<u64>,
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Count `n` occurrences of `duration`.
+    pub fn add(&mut self, duration: Duration, n: u64) {
+        let ms = duration.as_millis();
+        let index = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| ms < bound as u128)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.counts[index] += n;
+    }
+
+    /// The total number of durations counted.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Every bucket, in order of increasing duration.
+    pub fn buckets(&self) -> impl Iterator<Item = Bucket> + '_ {
+        self.counts.iter().enumerate().map(|(i, &count)| Bucket {
+            from_ms: if i == 0 { 0 } else { BUCKET_BOUNDS_MS[i - 1] },
+            to_ms: BUCKET_BOUNDS_MS.get(i).copied(),
+            count,
+        })
+    }
+
+    /// The non-empty bucket containing the most durations of at least `at_least`, if any.
+    fn mode_from(&self, at_least: Duration) -> Option<Bucket> {
+        let at_least = at_least.as_millis();
+        self.buckets()
+            .filter(|bucket| bucket.count > 0 && bucket.from_ms as u128 >= at_least)
+            .max_by_key(|bucket| bucket.count)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Histogram {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.buckets())
+    }
+}
+
+impl std::fmt::Display for Bucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.to_ms {
+            Some(to) => write!(f, "{}-{}ms", self.from_ms, to),
+            None => write!(f, "{}ms+", self.from_ms),
+        }
+    }
+}
+
+impl std::fmt::Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const WIDTH: u64 = 40;
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        for bucket in self.buckets() {
+            let bar = (bucket.count * WIDTH).div_ceil(max) as usize;
+            writeln!(
+                f,
+                "{:>12} {:>10} {}",
+                bucket.to_string(),
+                bucket.count,
+                "#".repeat(bar)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The distribution of gaps between bytes and between frames read from a connection.
+///
+/// Reads are timestamped rather than individual bytes, so the first byte of each read is counted
+/// as following the gap since the previous read, and the rest of its bytes as following no gap.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TimingProfile {
+    /// The number of non-empty reads.
+    pub reads: u64,
+    /// The number of bytes read.
+    pub bytes: u64,
+    /// The number of bytes delivered by reads following a gap of at least
+    /// [`BATCHING_THRESHOLD`].
+    pub batched_bytes: u64,
+    /// The gaps between consecutive bytes.
+    pub byte_gaps: Histogram,
+    /// The gaps between the reads completing consecutive frames.
+    pub frame_gaps: Histogram,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_read: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_frame: Option<Duration>,
+}
+
+impl TimingProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for a read of `bytes` bytes at `at`, measured from any fixed origin.
+    ///
+    /// Reads which appear to precede the previous read, as can happen when a capture's wall
+    /// clock steps backwards, are counted as following no gap.
+    pub fn push_read(&mut self, at: Duration, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        let bytes = bytes as u64;
+        self.reads += 1;
+        self.bytes += bytes;
+
+        if let Some(last) = self.last_read.replace(at) {
+            let gap = at.saturating_sub(last);
+            self.byte_gaps.add(gap, 1);
+            if gap >= BATCHING_THRESHOLD {
+                self.batched_bytes += bytes;
+            }
+        }
+        self.byte_gaps.add(Duration::ZERO, bytes - 1);
+    }
+
+    /// Account for `frames` frames completed by the read at `at`.
+    pub fn push_frames(&mut self, at: Duration, frames: u64) {
+        if frames == 0 {
+            return;
+        }
+        if let Some(last) = self.last_frame {
+            self.frame_gaps.add(at.saturating_sub(last), 1);
+        }
+        self.frame_gaps.add(Duration::ZERO, frames - 1);
+        self.last_frame = Some(at);
+    }
+
+    /// If most bytes were delivered in batches, the range of gaps most common between batches.
+    pub fn batching(&self) -> Option<Bucket> {
+        if self.bytes == 0 || (self.batched_bytes as f64) < self.bytes as f64 * BATCHING_SHARE {
+            return None;
+        }
+        self.byte_gaps.mode_from(BATCHING_THRESHOLD)
+    }
+}
+
+impl std::fmt::Display for TimingProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Gaps between bytes, over {} bytes in {} reads:",
+            self.bytes, self.reads
+        )?;
+        write!(f, "{}", self.byte_gaps)?;
+        writeln!(
+            f,
+            "Gaps between frames, over {} frames:",
+            self.frame_gaps.total()
+        )?;
+        write!(f, "{}", self.frame_gaps)?;
+        if let Some(batch) = self.batching() {
+            writeln!(
+                f,
+                "Most bytes arrived in batches {} apart, which suggests that the connection \
+                 buffers data rather than passing it through as it arrives. This delays frames \
+                 and makes the slot clock less accurate; if possible, reduce the adapter's \
+                 packing interval or connect directly.",
+                batch
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::default();
+        histogram.add(ms(0), 3);
+        histogram.add(ms(1), 1);
+        histogram.add(ms(49), 1);
+        histogram.add(ms(50), 1);
+        histogram.add(ms(60_000), 1);
+        assert_eq!(histogram.total(), 7);
+
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(buckets.len(), 13);
+        assert_eq!(
+            buckets[0],
+            Bucket {
+                from_ms: 0,
+                to_ms: Some(1),
+                count: 3
+            }
+        );
+        assert_eq!(buckets[1].count, 1);
+        assert_eq!(buckets[5].to_string(), "20-50ms");
+        assert_eq!(buckets[5].count, 1);
+        assert_eq!(buckets[6].count, 1);
+        assert_eq!(buckets[12].to_string(), "5000ms+");
+        assert_eq!(buckets[12].count, 1);
+    }
+
+    #[test]
+    fn direct() {
+        // Each frame trickles in a few bytes at a time, with long idle periods between frames
+        let mut profile = TimingProfile::new();
+        for frame in 0..100 {
+            let start = frame * 300;
+            for i in 0..10 {
+                profile.push_read(ms(start + i), 2);
+            }
+            profile.push_frames(ms(start + 9), 1);
+        }
+
+        assert_eq!(profile.reads, 1000);
+        assert_eq!(profile.bytes, 2000);
+        assert_eq!(profile.batched_bytes, 99 * 2);
+        assert_eq!(profile.byte_gaps.total(), 1999);
+        assert_eq!(profile.frame_gaps.total(), 99);
+        assert_eq!(profile.batching(), None);
+        assert!(!profile.to_string().contains("batches"));
+    }
+
+    #[test]
+    fn batched() {
+        // Several frames arrive at once, every 50 ms
+        let mut profile = TimingProfile::new();
+        for batch in 0..100 {
+            profile.push_read(ms(batch * 50), 60);
+            profile.push_frames(ms(batch * 50), 3);
+        }
+
+        assert_eq!(profile.batched_bytes, 99 * 60);
+        assert_eq!(profile.frame_gaps.total(), 300 - 1);
+        let batch = profile.batching().unwrap();
+        assert_eq!(batch.to_string(), "50-100ms");
+        assert!(profile.to_string().contains("batches 50-100ms apart"));
+    }
+
+    #[test]
+    fn clock_steps_backwards() {
+        let mut profile = TimingProfile::new();
+        profile.push_read(ms(1000), 1);
+        profile.push_read(ms(500), 1);
+        profile.push_read(ms(500), 0);
+        assert_eq!(profile.reads, 2);
+        assert_eq!(profile.byte_gaps.buckets().next().unwrap().count, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn json() {
+        let mut profile = TimingProfile::new();
+        profile.push_read(ms(0), 1);
+        profile.push_read(ms(3), 1);
+        let json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(json["reads"], 2);
+        assert_eq!(
+            json["byte_gaps"][2],
+            serde_json::json!({"from_ms": 2, "to_ms": 5, "count": 1})
+        );
+        assert_eq!(json["byte_gaps"][12]["to_ms"], serde_json::Value::Null);
+        assert!(json.get("last_read").is_none());
+    }
+}
@@ -0,0 +1,169 @@
+//! Connections for async programs using tokio.
+//!
+//! These mirror the blocking connections: [`TcpConnection`] enables TCP keepalive like
+//! [`tcp::Connection`](super::tcp::Connection), and `SerialPort`, with the `tokio-serial` feature,
+//! opens a port with the same settings as `serialport::Port`. [`drive()`] reads from any of them
+//! into a link layer [`Receiver`], whose state machines are the same for async and blocking
+//! programs.
+
+use super::AsyncConnection;
+use crate::gateway::link::{Receiver, Sink};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+impl AsyncConnection for tokio::io::DuplexStream {}
+
+/// A TCP serial connection, for async programs.
+#[derive(Debug)]
+pub struct TcpConnection {
+    socket: TcpStream,
+    readonly: bool,
+}
+
+impl TcpConnection {
+    pub async fn connect<A: ToSocketAddrs>(addr: A, readonly: bool) -> std::io::Result<Self> {
+        let socket = TcpStream::connect(addr).await?;
+        if let Err(e) = super::tcp::enable_keepalive(&socket) {
+            log::warn!("error enabling TCP keepalive: {}", e);
+        }
+
+        Ok(Self { socket, readonly })
+    }
+}
+
+impl AsyncConnection for TcpConnection {}
+
+impl AsyncRead for TcpConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.socket).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.readonly {
+            Poll::Ready(Err(std::io::ErrorKind::Unsupported.into()))
+        } else {
+            Pin::new(&mut self.socket).poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.readonly {
+            Poll::Ready(Ok(()))
+        } else {
+            Pin::new(&mut self.socket).poll_flush(cx)
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.socket).poll_shutdown(cx)
+    }
+}
+
+/// A serial port, for async programs.
+#[cfg(feature = "tokio-serial")]
+#[derive(Debug)]
+pub struct SerialPort(tokio_serial::SerialStream);
+
+#[cfg(feature = "tokio-serial")]
+impl SerialPort {
+    pub fn open(name: &str) -> tokio_serial::Result<Self> {
+        use tokio_serial::{DataBits, FlowControl, Parity, SerialPortBuilderExt, StopBits};
+
+        tokio_serial::new(name, 38400)
+            .data_bits(DataBits::Eight)
+            .parity(Parity::None)
+            .stop_bits(StopBits::One)
+            .flow_control(FlowControl::None)
+            .open_native_async()
+            .map(Self)
+    }
+}
+
+#[cfg(feature = "tokio-serial")]
+impl AsyncConnection for SerialPort {}
+
+#[cfg(feature = "tokio-serial")]
+impl AsyncRead for SerialPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "tokio-serial")]
+impl AsyncWrite for SerialPort {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Read from `source` into a link layer receiver until the source ends or fails.
+pub async fn drive<S: Sink>(
+    mut source: impl AsyncRead + Unpin,
+    receiver: &mut Receiver<S>,
+) -> std::io::Result<()> {
+    let mut buffer = [0u8; 1024];
+    loop {
+        match source.read(&mut buffer).await {
+            Ok(0) => return Ok(()),
+            Ok(n) => receiver.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::link::Frame;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn drive_enumeration_sequence() {
+        // Feed the test vector through a small pipe, so that it arrives in pieces
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let writer = tokio::spawn(async move {
+            tx.write_all(crate::test_data::ENUMERATION_SEQUENCE)
+                .await
+                .unwrap();
+        });
+        let mut receiver = Receiver::new(Vec::<Frame>::new());
+        drive(rx, &mut receiver).await.unwrap();
+        writer.await.unwrap();
+
+        // The frames are the same as when the bytes are received all at once
+        let mut expected = Receiver::new(Vec::<Frame>::new());
+        expected.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+        assert!(!expected.sink().is_empty());
+        assert_eq!(receiver.sink(), expected.sink());
+        assert_eq!(receiver.counters(), expected.counters());
+    }
+}
@@ -1,5 +1,3 @@
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
 // Use `zerocopy` to transmute `#[repr(C)]` structs to/from byte slices
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
 
@@ -7,11 +5,21 @@ use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
 use pv::application::PacketType;
 use zerocopy::byteorder::big_endian::U16;
 
+pub mod polling;
 mod receiver;
-use crate::gateway::link::{Address, GatewayID};
+use crate::gateway::link::{self, Address, Frame, GatewayID};
 use crate::pv;
 use crate::pv::link::SlotCounter;
-pub use receiver::{Counters, Receiver, Sink};
+pub use receiver::{Counters, InvalidFrameReason, Receiver, Sink};
+
+#[cfg(feature = "active")]
+mod controller;
+#[cfg(feature = "active")]
+pub use controller::{Controller, ControllerConfig, ControllerError};
+#[cfg(feature = "active")]
+mod requester;
+#[cfg(feature = "active")]
+pub use requester::{RequestConfig, RequestError, Requester, RequesterCounters};
 
 #[derive(
     Debug,
@@ -26,12 +34,11 @@ pub use receiver::{Counters, Receiver, Sink};
     Unaligned,
     KnownLayout,
     Immutable,
-    Serialize,
-    Deserialize,
-    JsonSchema,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(transparent)]
-#[serde(transparent)]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct CommandSequenceNumber(pub u8);
 
 /// A command request frame payload.
@@ -45,6 +52,30 @@ pub struct CommandRequest {
     pub sequence_number: CommandSequenceNumber,
 }
 
+impl CommandRequest {
+    /// A command request header, as controllers send it.
+    pub fn new(packet_type: PacketType, sequence_number: CommandSequenceNumber) -> Self {
+        Self {
+            unknown: [0x00, 0x01, 0x00],
+            packet_type,
+            sequence_number,
+        }
+    }
+}
+
+/// A command request to a gateway, with the command's payload following the header.
+impl From<(GatewayID, &CommandRequest, &[u8])> for Frame {
+    fn from((gateway_id, request, payload): (GatewayID, &CommandRequest, &[u8])) -> Self {
+        let mut request_payload = request.as_bytes().to_vec();
+        request_payload.extend_from_slice(payload);
+        Frame {
+            address: Address::To(gateway_id),
+            frame_type: link::Type::COMMAND_REQUEST,
+            payload: request_payload,
+        }
+    }
+}
+
 /// A command response frame payload.
 #[derive(
     Debug, Copy, Clone, Eq, PartialEq, FromBytes, IntoBytes, Unaligned, KnownLayout, Immutable,
@@ -69,13 +100,40 @@ pub struct ReceiveRequest {
     pub unknown_2: u8,
 }
 
+impl ReceiveRequest {
+    /// A receive request carrying the controller's latest packet number, as controllers send it.
+    pub fn new(packet_number: u16) -> Self {
+        Self {
+            unknown_1: [0x00, 0x01],
+            packet_number: packet_number.into(),
+            unknown_2: 0x04,
+        }
+    }
+}
+
+impl From<(GatewayID, &ReceiveRequest)> for Frame {
+    fn from((gateway_id, request): (GatewayID, &ReceiveRequest)) -> Self {
+        Frame {
+            address: Address::To(gateway_id),
+            frame_type: link::Type::RECEIVE_REQUEST,
+            payload: request.as_bytes().to_vec(),
+        }
+    }
+}
+
 /// A receive response frame payload, decoded into its most general form.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReceiveResponse {
     pub rx_buffers_used: Option<u8>,
     pub tx_buffers_free: Option<u8>,
     pub unknown_a: Option<[u8; 2]>,
     pub unknown_b: Option<[u8; 2]>,
+    /// The high half of the packet number, if the gateway disclosed it.
+    ///
+    /// When this is `None`, `packet_number` was inferred from the low half and the reference
+    /// packet number, and should be considered less authoritative.
+    pub packet_number_high: Option<u8>,
     pub packet_number: u16,
     pub slot_counter: SlotCounter,
 }
@@ -86,7 +144,7 @@ fn interpret_packet_number_lo(new_lo: u8, old: u16) -> u16 {
         old_hi
     } else {
         // wrap
-        old_hi + 1
+        old_hi.wrapping_add(1)
     };
     u16::from_be_bytes([new_hi, new_lo])
 }
@@ -105,7 +163,7 @@ impl ReceiveResponse {
     pub fn read_from_bytes(
         bytes: &[u8],
         packet_number: u16,
-    ) -> Result<(Self, pv::network::ReceivedPackets), InvalidReceiveResponse> {
+    ) -> Result<(Self, pv::network::ReceivedPackets<'_>), InvalidReceiveResponse> {
         // Ensure we have at least a minimal length
         if bytes.len() < 2 {
             return Err(InvalidReceiveResponse::TooShort(5));
@@ -169,14 +227,14 @@ impl ReceiveResponse {
         };
 
         // Grab packet number, expanding as needed
-        let packet_number = if status_type & 0x0010 == 0 {
+        let (packet_number_high, packet_number) = if status_type & 0x0010 == 0 {
             let (value, new_rest) = rest.split_at(2);
             rest = new_rest;
-            u16::from_be_bytes([value[0], value[1]])
+            (Some(value[0]), u16::from_be_bytes([value[0], value[1]]))
         } else {
             let (value, new_rest) = rest.split_at(1);
             rest = new_rest;
-            interpret_packet_number_lo(value[0], packet_number)
+            (None, interpret_packet_number_lo(value[0], packet_number))
         };
 
         // Grab slot counter
@@ -190,12 +248,52 @@ impl ReceiveResponse {
                 tx_buffers_free,
                 unknown_a,
                 unknown_b,
+                packet_number_high,
                 packet_number,
                 slot_counter,
             },
             pv::network::ReceivedPackets(rest),
         ))
     }
+
+    /// Encode this response as a frame payload, followed by `packets`.
+    ///
+    /// This is the inverse of `read_from_bytes()`. The full packet number is included if and only
+    /// if `packet_number_high` is present.
+    pub fn encode(&self, packets: &[u8]) -> Vec<u8> {
+        let mut status_type = 0x00e0u16;
+        let mut output = Vec::with_capacity(2 + 1 + 1 + 2 + 2 + 2 + 2 + packets.len());
+        output.extend_from_slice(&[0, 0]);
+
+        match self.rx_buffers_used {
+            Some(value) => output.push(value),
+            None => status_type |= 0x0001,
+        }
+        match self.tx_buffers_free {
+            Some(value) => output.push(value),
+            None => status_type |= 0x0002,
+        }
+        match self.unknown_a {
+            Some(value) => output.extend_from_slice(&value),
+            None => status_type |= 0x0004,
+        }
+        match self.unknown_b {
+            Some(value) => output.extend_from_slice(&value),
+            None => status_type |= 0x0008,
+        }
+        match self.packet_number_high {
+            Some(high) => output.extend_from_slice(&[high, self.packet_number as u8]),
+            None => {
+                status_type |= 0x0010;
+                output.push(self.packet_number as u8);
+            }
+        }
+        output.extend_from_slice(self.slot_counter.as_bytes());
+        output.extend_from_slice(packets);
+
+        output[0..2].copy_from_slice(&status_type.to_be_bytes());
+        output
+    }
 }
 
 /// An identify response frame payload.
@@ -217,6 +315,41 @@ impl IdentifyResponse {
     }
 }
 
+/// An assign gateway ID request frame payload.
+///
+/// The controller sends this to a gateway's current ID, which during enumeration is the
+/// enumeration ID, naming the gateway by its hardware address in case several share that ID. The
+/// gateway's assign gateway ID response has no payload.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, IntoBytes, Unaligned, KnownLayout, Immutable,
+)]
+#[repr(C)]
+pub struct AssignGatewayIdRequest {
+    pub unknown: [u8; 4],
+    pub pv_long_address: pv::LongAddress,
+    pub gateway_address: [u8; 2],
+}
+
+impl AssignGatewayIdRequest {
+    /// A request assigning `gateway_id` to the gateway with the hardware address
+    /// `pv_long_address`, as controllers send it.
+    pub fn new(pv_long_address: pv::LongAddress, gateway_id: GatewayID) -> Self {
+        Self {
+            unknown: ENUMERATION_UNKNOWN,
+            pv_long_address,
+            gateway_address: Address::To(gateway_id).into(),
+        }
+    }
+
+    /// The gateway ID being assigned.
+    pub fn gateway_id(&self) -> Option<GatewayID> {
+        match Address::from(self.gateway_address) {
+            Address::From(_) => None,
+            Address::To(id) => Some(id),
+        }
+    }
+}
+
 /// An enumeration start request frame payload.
 #[derive(
     Debug, Copy, Clone, Eq, PartialEq, FromBytes, IntoBytes, Unaligned, KnownLayout, Immutable,
@@ -227,7 +360,30 @@ pub struct EnumerationStartRequest {
     pub enumeration_address: [u8; 2],
 }
 
+/// The leading bytes of enumeration start and assign gateway ID requests, whose meaning isn't known.
+const ENUMERATION_UNKNOWN: [u8; 4] = [0x37, 0x7E, 0x92, 0x66];
+
+/// An assign gateway ID request, sent to the gateway's current ID.
+impl From<(GatewayID, &AssignGatewayIdRequest)> for Frame {
+    fn from((gateway_id, request): (GatewayID, &AssignGatewayIdRequest)) -> Self {
+        Frame {
+            address: Address::To(gateway_id),
+            frame_type: link::Type::ASSIGN_GATEWAY_ID_REQUEST,
+            payload: request.as_bytes().to_vec(),
+        }
+    }
+}
+
 impl EnumerationStartRequest {
+    /// A request for every gateway to move to `enumeration_gateway_id` for enumeration, as
+    /// controllers send it.
+    pub fn new(enumeration_gateway_id: GatewayID) -> Self {
+        Self {
+            unknown: ENUMERATION_UNKNOWN,
+            enumeration_address: Address::To(enumeration_gateway_id).into(),
+        }
+    }
+
     pub fn enumeration_gateway_id(&self) -> Option<GatewayID> {
         match Address::from(self.enumeration_address) {
             Address::From(_) => None,
@@ -236,11 +392,116 @@ impl EnumerationStartRequest {
     }
 }
 
+/// An enumeration start request, which is broadcast to gateway ID 0.
+impl From<&EnumerationStartRequest> for Frame {
+    fn from(request: &EnumerationStartRequest) -> Self {
+        Frame {
+            address: Address::To(GatewayID::ZERO),
+            frame_type: link::Type::ENUMERATION_START_REQUEST,
+            payload: request.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// A ping request to a gateway.
+pub fn ping_request(gateway_id: GatewayID) -> Frame {
+    request(gateway_id, link::Type::PING_REQUEST, &[0x01])
+}
+
+/// A request for a gateway's version string.
+pub fn version_request(gateway_id: GatewayID) -> Frame {
+    request(gateway_id, link::Type::VERSION_REQUEST, &[])
+}
+
+/// A request for a gateway to identify itself with its hardware address.
+pub fn identify_request(gateway_id: GatewayID) -> Frame {
+    request(gateway_id, link::Type::IDENTIFY_REQUEST, &[])
+}
+
+/// A request for a gateway at the enumeration ID to identify itself.
+pub fn enumeration_request(enumeration_gateway_id: GatewayID) -> Frame {
+    request(enumeration_gateway_id, link::Type::ENUMERATION_REQUEST, &[])
+}
+
+/// A request ending enumeration, sent to a gateway's assigned ID.
+pub fn enumeration_end_request(gateway_id: GatewayID) -> Frame {
+    request(gateway_id, link::Type::ENUMERATION_END_REQUEST, &[])
+}
+
+fn request(gateway_id: GatewayID, frame_type: link::Type, payload: &[u8]) -> Frame {
+    Frame {
+        address: Address::To(gateway_id),
+        frame_type,
+        payload: payload.to_vec(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::pv::network::ReceivedPackets;
 
+    #[test]
+    fn assign_gateway_id_request() {
+        // From the enumeration sequence, unescaped
+        let request = AssignGatewayIdRequest::ref_from_bytes(&[
+            0x37, 0x7E, 0x92, 0x66, 0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16, 0x12, 0x01,
+        ])
+        .unwrap();
+        assert_eq!(
+            request.pv_long_address,
+            pv::LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
+        );
+        assert_eq!(
+            request.gateway_id(),
+            Some(GatewayID::try_from(0x1201).unwrap())
+        );
+
+        let request = AssignGatewayIdRequest {
+            gateway_address: [0x92, 0x01],
+            ..*request
+        };
+        assert_eq!(request.gateway_id(), None);
+    }
+
+    #[test]
+    fn requests_as_controllers_send_them() {
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let long_address = pv::LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16]);
+
+        let frame = Frame::from((
+            gateway_id,
+            &AssignGatewayIdRequest::new(long_address, gateway_id),
+        ));
+        assert_eq!(frame.address, Address::To(gateway_id));
+        assert_eq!(
+            frame.payload,
+            [0x37, 0x7E, 0x92, 0x66, 0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16, 0x12, 0x01]
+        );
+
+        let frame = Frame::from((gateway_id, &ReceiveRequest::new(0x1883)));
+        assert_eq!(frame.frame_type, link::Type::RECEIVE_REQUEST);
+        assert_eq!(frame.payload, [0x00, 0x01, 0x18, 0x83, 0x04]);
+
+        let frame = Frame::from(&EnumerationStartRequest::new(
+            GatewayID::try_from(0x1235).unwrap(),
+        ));
+        assert_eq!(frame.address, Address::To(GatewayID::ZERO));
+        assert_eq!(frame.payload, [0x37, 0x7E, 0x92, 0x66, 0x12, 0x35]);
+
+        let request = CommandRequest::new(PacketType::STRING_REQUEST, CommandSequenceNumber(0x42));
+        let frame = Frame::from((gateway_id, &request, b"AT".as_slice()));
+        assert_eq!(frame.frame_type, link::Type::COMMAND_REQUEST);
+        assert_eq!(&frame.payload[..3], [0x00, 0x01, 0x00]);
+        assert_eq!(&frame.payload[4..], [0x42, b'A', b'T']);
+
+        assert_eq!(ping_request(gateway_id).payload, [0x01]);
+        assert_eq!(
+            version_request(gateway_id).frame_type,
+            link::Type::VERSION_REQUEST
+        );
+    }
+
     #[test]
     fn rx_request_from_bytes() {
         assert_eq!(
@@ -266,6 +527,7 @@ mod tests {
                     tx_buffers_free: Some(0x0E),
                     unknown_a: Some([0x00, 0x01]),
                     unknown_b: Some([0x02, 0x00]),
+                    packet_number_high: Some(0x40),
                     packet_number: 0x40FB,
                     slot_counter: 0x211B.into(),
                 },
@@ -281,6 +543,7 @@ mod tests {
                     tx_buffers_free: None,
                     unknown_a: None,
                     unknown_b: None,
+                    packet_number_high: None,
                     packet_number: 0x40FF,
                     slot_counter: 0x2122.into(),
                 },
@@ -296,6 +559,7 @@ mod tests {
                     tx_buffers_free: None,
                     unknown_a: None,
                     unknown_b: None,
+                    packet_number_high: Some(0x41),
                     packet_number: 0x4101,
                     slot_counter: 0x2127.into(),
                 },
@@ -332,6 +596,7 @@ mod tests {
                     tx_buffers_free: None,
                     unknown_a: None,
                     unknown_b: None,
+                    packet_number_high: None,
                     packet_number: 0x4103,
                     slot_counter: 0x2131.into(),
                 },
@@ -354,6 +619,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rx_response_encode() {
+        for (bytes, packet_number) in [
+            (
+                [
+                    0x00, 0xE0, 0x04, 0x0E, 0x00, 0x01, 0x02, 0x00, 0x40, 0xFB, 0x21, 0x1B, 1, 2, 3,
+                ]
+                .as_slice(),
+                0x40FB,
+            ),
+            (&[0x00, 0xFE, 0x02, 0xFF, 0x21, 0x22, 4], 0x40FB),
+            (&[0x00, 0xEE, 0x00, 0x41, 0x01, 0x21, 0x27], 0x40FB),
+            (&[0x00, 0xFF, 0x03, 0x21, 0x31], 0x40FB),
+        ] {
+            let (response, packets) =
+                ReceiveResponse::read_from_bytes(bytes, packet_number).unwrap();
+            assert_eq!(response.encode(packets.0), bytes);
+        }
+    }
+
+    #[test]
+    fn rx_response_packet_number_wrap() {
+        // The low half wraps, carrying into the high half
+        assert_eq!(
+            ReceiveResponse::read_from_bytes(&[0x00, 0xFF, 0x02, 0x21, 0x31], 0x12FE)
+                .map(|(status, _)| status.packet_number),
+            Ok(0x1302)
+        );
+
+        // The high half wraps too, without overflowing
+        assert_eq!(
+            ReceiveResponse::read_from_bytes(&[0x00, 0xFF, 0x02, 0x21, 0x31], 0xFFFE)
+                .map(|(status, _)| status.packet_number),
+            Ok(0x0002)
+        );
+
+        // A gateway which restarted its packet numbers is indistinguishable from a wrap using only
+        // the low half
+        assert_eq!(
+            ReceiveResponse::read_from_bytes(&[0x00, 0xFF, 0x00, 0x21, 0x31], 0x40FB)
+                .map(|(status, _)| (status.packet_number_high, status.packet_number)),
+            Ok((None, 0x4100))
+        );
+
+        // ...but the full packet number is taken as-is
+        assert_eq!(
+            ReceiveResponse::read_from_bytes(&[0x00, 0xEE, 0x00, 0x00, 0x00, 0x21, 0x31], 0x40FB)
+                .map(|(status, _)| (status.packet_number_high, status.packet_number)),
+            Ok((Some(0x00), 0x0000))
+        );
+    }
+
     #[test]
     fn identify_response_payload() {
         let expected = IdentifyResponse {
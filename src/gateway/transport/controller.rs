@@ -0,0 +1,216 @@
+use super::*;
+use crate::gateway::link::{Frame, Type};
+use crate::gateway::physical::Connection;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// How a `Controller` paces its requests.
+///
+/// The defaults are deliberately slower than a real controller's, since gateways buffer what
+/// they receive, and a bus which is asked too little of loses nothing but latency.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ControllerConfig {
+    /// How often to ask each gateway for the packets it has received.
+    pub poll_interval: Duration,
+    /// How often to ping each gateway, starting with the first poll.
+    pub ping_interval: Duration,
+    /// How long to listen for another controller before sending anything.
+    pub listen: Duration,
+    /// How long to wait for each response, and how often to retry.
+    pub request: RequestConfig,
+}
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            ping_interval: Duration::from_secs(60),
+            listen: Duration::from_secs(5),
+            request: RequestConfig::default(),
+        }
+    }
+}
+
+/// An error running in place of a controller.
+#[derive(thiserror::Error, Debug)]
+pub enum ControllerError {
+    #[error("another controller appears to be active: received {frame_type:?} frame {address:?}")]
+    OtherController { address: Address, frame_type: Type },
+    #[error(transparent)]
+    Request(#[from] RequestError),
+}
+
+/// What a `Controller` knows about each of its gateways.
+#[derive(Debug, Copy, Clone)]
+struct Gateway {
+    /// The packet number to acknowledge in the next receive request.
+    packet_number: u16,
+    next_poll: Option<Instant>,
+    next_ping: Option<Instant>,
+}
+
+/// Polls gateways for the packets they receive, in place of a controller.
+///
+/// A controller pings its gateways, enumerates them, and then asks each in turn for whatever it
+/// has received, acknowledging the packet number of the previous response. A `Controller` does
+/// the pinging and the polling for gateways which have already been enumerated. Enumeration is
+/// not yet supported.
+///
+/// Every request sent and every response received is passed to a [`link::Sink`], normally the
+/// transport [`Receiver`], so that what the gateways receive is observed exactly as if another
+/// controller had asked for it.
+///
+/// Two controllers on one bus would trample each other's requests, so
+/// [`check_bus_idle()`](Self::check_bus_idle) should be called before anything else.
+#[derive(Debug)]
+pub struct Controller<C: Connection> {
+    requester: Requester<C>,
+    config: ControllerConfig,
+    gateways: BTreeMap<GatewayID, Gateway>,
+}
+
+impl<C: Connection> Controller<C> {
+    pub fn new(
+        connection: C,
+        config: ControllerConfig,
+        gateways: impl IntoIterator<Item = GatewayID>,
+    ) -> Self {
+        let gateway = Gateway {
+            packet_number: 0,
+            next_poll: None,
+            next_ping: None,
+        };
+        Self {
+            requester: Requester::new(connection, config.request),
+            config,
+            gateways: gateways.into_iter().map(|id| (id, gateway)).collect(),
+        }
+    }
+
+    /// Access the requester, and through it the connection and its counters.
+    pub fn requester(&self) -> &Requester<C> {
+        &self.requester
+    }
+
+    /// Destroy the `Controller` to obtain the connection.
+    pub fn into_inner(self) -> C {
+        self.requester.into_inner()
+    }
+
+    /// The packet number which the next receive request to a gateway will acknowledge.
+    pub fn packet_number(&self, gateway_id: GatewayID) -> Option<u16> {
+        self.gateways
+            .get(&gateway_id)
+            .map(|gateway| gateway.packet_number)
+    }
+
+    /// Listen for the configured time without sending anything, failing as soon as anything is
+    /// heard.
+    ///
+    /// Gateways only speak when spoken to, so any frame at all means that something else is
+    /// controlling the bus.
+    pub fn check_bus_idle(&mut self) -> Result<(), ControllerError> {
+        match self.requester.listen(self.config.listen)? {
+            Some(frame) => Err(ControllerError::OtherController {
+                address: frame.address,
+                frame_type: frame.frame_type,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// When the next request is due, if there are any gateways to send it to.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.gateways
+            .values()
+            .flat_map(|gateway| [gateway.next_poll, gateway.next_ping])
+            .map(|due| due.unwrap_or_else(Instant::now))
+            .min()
+    }
+
+    /// Send every request which is due as of `now`, passing the requests and their responses to
+    /// `sink`.
+    ///
+    /// A gateway which doesn't respond is logged and asked again when its next request is due;
+    /// only a failure of the connection itself is returned.
+    pub fn poll(&mut self, now: Instant, sink: &mut impl link::Sink) -> Result<(), RequestError> {
+        let due: Vec<GatewayID> = self.gateways.keys().copied().collect();
+        for gateway_id in due {
+            let gateway = self.gateways[&gateway_id];
+            if gateway.next_ping.is_none_or(|at| at <= now) {
+                self.ping(gateway_id, sink)?;
+                self.gateways.get_mut(&gateway_id).unwrap().next_ping =
+                    Some(now + self.config.ping_interval);
+            }
+            if gateway.next_poll.is_none_or(|at| at <= now) {
+                self.receive(gateway_id, sink)?;
+                self.gateways.get_mut(&gateway_id).unwrap().next_poll =
+                    Some(now + self.config.poll_interval);
+            }
+        }
+        Ok(())
+    }
+
+    fn ping(
+        &mut self,
+        gateway_id: GatewayID,
+        sink: &mut impl link::Sink,
+    ) -> Result<(), RequestError> {
+        let request = ping_request(gateway_id);
+        let response = self.exchange(&request, sink, |frame| {
+            frame.address == Address::From(gateway_id) && frame.frame_type == Type::PING_RESPONSE
+        })?;
+        if response.is_none() {
+            log::warn!("gateway {} didn't answer a ping", gateway_id);
+        }
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        gateway_id: GatewayID,
+        sink: &mut impl link::Sink,
+    ) -> Result<(), RequestError> {
+        let packet_number = self.gateways[&gateway_id].packet_number;
+        let request = Frame::from((gateway_id, &ReceiveRequest::new(packet_number)));
+        let response = self.exchange(&request, sink, |frame| {
+            frame.address == Address::From(gateway_id) && frame.frame_type == Type::RECEIVE_RESPONSE
+        })?;
+        let Some(response) = response else {
+            log::warn!("gateway {} didn't answer a receive request", gateway_id);
+            return Ok(());
+        };
+
+        match ReceiveResponse::read_from_bytes(&response.payload, packet_number) {
+            Ok((status, _)) => {
+                self.gateways.get_mut(&gateway_id).unwrap().packet_number = status.packet_number;
+            }
+            Err(e) => log::warn!(
+                "invalid receive response from gateway {}: {}",
+                gateway_id,
+                e
+            ),
+        }
+        Ok(())
+    }
+
+    /// Make a request, passing it and any response to `sink`, and treating a timeout as no
+    /// response.
+    fn exchange(
+        &mut self,
+        request: &Frame,
+        sink: &mut impl link::Sink,
+        matches: impl FnMut(&Frame) -> bool,
+    ) -> Result<Option<Frame>, RequestError> {
+        let response = match self.requester.request(request, matches) {
+            Ok(response) => Some(response),
+            Err(RequestError::TimedOut { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        sink.frame(request.clone());
+        if let Some(response) = &response {
+            sink.frame(response.clone());
+        }
+        Ok(response)
+    }
+}
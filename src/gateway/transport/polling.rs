@@ -0,0 +1,317 @@
+//! How evenly the controller polls its gateways.
+//!
+//! On a bus with several gateways, the controller polls each in turn with receive requests.
+//! Controllers have been seen to start favoring one gateway, after a partial reset for example,
+//! leaving the nodes behind the other gateways reporting minutes late. A [`PollingMonitor`]
+//! measures each gateway's share of the receive requests over windows of consecutive requests,
+//! rather than of time, so that it needs no clock.
+
+use super::GatewayID;
+use crate::memory::btree_map_bytes;
+use std::collections::BTreeMap;
+
+/// How many consecutive windows a gateway can go without being polled before it's forgotten, on
+/// the assumption that its ID was reassigned.
+const FORGET_AFTER_WINDOWS: u32 = 10;
+
+/// Thresholds for deciding that the controller is polling its gateways unfairly.
+///
+/// A gateway's fair share is an equal share of the receive requests among every gateway polled
+/// recently.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PollingFairness {
+    /// The number of consecutive receive requests over which shares are measured.
+    pub window_requests: u32,
+    /// A gateway is polled unfairly when its share falls below this percentage of its fair share.
+    pub min_fair_share_pct: u8,
+    /// The number of consecutive windows for which a gateway must be polled unfairly before this
+    /// is reported.
+    pub sustained_windows: u32,
+}
+
+impl Default for PollingFairness {
+    fn default() -> Self {
+        // Controllers poll several times a second, so this is a few minutes
+        Self {
+            window_requests: 300,
+            min_fair_share_pct: 50,
+            sustained_windows: 3,
+        }
+    }
+}
+
+/// A gateway which has been polled unfairly for the sustained number of windows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnfairPolling {
+    /// The gateway's share of receive requests in the latest window, from 0 to 1.
+    pub share: f64,
+    /// The share of receive requests the gateway would have had if polled fairly.
+    pub fair_share: f64,
+    /// The most receive requests to other gateways between consecutive requests to this one.
+    pub max_gap: u64,
+}
+
+/// How often a gateway has been polled.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PollingShare {
+    pub gateway: GatewayID,
+    /// The number of receive requests to this gateway.
+    pub requests: u64,
+    /// The gateway's share of receive requests in the latest complete window, from 0 to 1.
+    pub share: Option<f64>,
+    /// The share of receive requests the gateway would have had in that window if polled fairly.
+    pub fair_share: Option<f64>,
+    /// The mean number of receive requests to other gateways between consecutive requests to this
+    /// one.
+    pub mean_gap: Option<f64>,
+    /// The most receive requests to other gateways between consecutive requests to this one.
+    pub max_gap: u64,
+}
+
+/// Every gateway's [`PollingShare`], which displays as a table.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct PollingReport(pub Vec<PollingShare>);
+
+impl std::fmt::Display for PollingReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn pct(share: Option<f64>) -> String {
+            share.map_or_else(|| "-".into(), |share| format!("{:.1}%", share * 100.0))
+        }
+
+        writeln!(
+            f,
+            "{:>8} {:>10} {:>8} {:>8} {:>9} {:>8}",
+            "gateway", "requests", "share", "fair", "mean gap", "max gap"
+        )?;
+        for share in &self.0 {
+            writeln!(
+                f,
+                "{:>8} {:>10} {:>8} {:>8} {:>9} {:>8}",
+                format!("{:?}", share.gateway),
+                share.requests,
+                pct(share.share),
+                pct(share.fair_share),
+                share
+                    .mean_gap
+                    .map_or_else(|| "-".into(), |gap| format!("{:.1}", gap)),
+                share.max_gap
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct GatewayPolling {
+    requests: u64,
+    window_requests: u64,
+    /// The index of the latest receive request to this gateway.
+    last_request: Option<u64>,
+    gaps: u64,
+    gap_sum: u64,
+    max_gap: u64,
+    share: Option<f64>,
+    fair_share: Option<f64>,
+    unfair_windows: u32,
+    idle_windows: u32,
+}
+
+/// Measures each gateway's share of receive requests.
+#[derive(Debug, Clone, Default)]
+pub struct PollingMonitor {
+    /// The number of receive requests seen.
+    requests: u64,
+    /// The number of receive requests seen in the current window.
+    window_requests: u64,
+    gateways: BTreeMap<GatewayID, GatewayPolling>,
+}
+
+impl PollingMonitor {
+    /// Note a receive request to a gateway, returning any gateways which have now been polled
+    /// unfairly for the sustained number of windows.
+    pub fn request(
+        &mut self,
+        gateway_id: GatewayID,
+        fairness: &PollingFairness,
+    ) -> Vec<(GatewayID, UnfairPolling)> {
+        let index = self.requests;
+        self.requests += 1;
+        self.window_requests += 1;
+
+        let gateway = self.gateways.entry(gateway_id).or_default();
+        gateway.requests += 1;
+        gateway.window_requests += 1;
+        if let Some(last) = gateway.last_request.replace(index) {
+            let gap = index - last - 1;
+            gateway.gaps += 1;
+            gateway.gap_sum += gap;
+            gateway.max_gap = gateway.max_gap.max(gap);
+        }
+
+        if self.window_requests < u64::from(fairness.window_requests.max(1)) {
+            return Vec::new();
+        }
+        self.end_window(fairness)
+    }
+
+    fn end_window(&mut self, fairness: &PollingFairness) -> Vec<(GatewayID, UnfairPolling)> {
+        let window = std::mem::take(&mut self.window_requests) as f64;
+        let fair_share = 1.0 / self.gateways.len() as f64;
+        let threshold = fair_share * f64::from(fairness.min_fair_share_pct) / 100.0;
+        // With only one gateway, every share is fair
+        let comparable = self.gateways.len() >= 2;
+
+        let mut unfair = Vec::new();
+        for (&gateway_id, gateway) in &mut self.gateways {
+            let share = std::mem::take(&mut gateway.window_requests) as f64 / window;
+            gateway.share = Some(share);
+            gateway.fair_share = Some(fair_share);
+            gateway.idle_windows = if share == 0.0 {
+                gateway.idle_windows + 1
+            } else {
+                0
+            };
+
+            if comparable && share < threshold {
+                gateway.unfair_windows += 1;
+                if gateway.unfair_windows == fairness.sustained_windows.max(1) {
+                    unfair.push((
+                        gateway_id,
+                        UnfairPolling {
+                            share,
+                            fair_share,
+                            max_gap: gateway.max_gap,
+                        },
+                    ));
+                }
+            } else {
+                gateway.unfair_windows = 0;
+            }
+        }
+
+        self.gateways
+            .retain(|_, gateway| gateway.idle_windows < FORGET_AFTER_WINDOWS);
+        unfair
+    }
+
+    /// Each gateway's share of receive requests.
+    pub fn report(&self) -> PollingReport {
+        PollingReport(
+            self.gateways
+                .iter()
+                .map(|(&gateway, polling)| PollingShare {
+                    gateway,
+                    requests: polling.requests,
+                    share: polling.share,
+                    fair_share: polling.fair_share,
+                    mean_gap: (polling.gaps > 0)
+                        .then(|| polling.gap_sum as f64 / polling.gaps as f64),
+                    max_gap: polling.max_gap,
+                })
+                .collect(),
+        )
+    }
+
+    /// Reset the request counts and gap statistics, keeping the current window.
+    pub fn reset_statistics(&mut self) {
+        for gateway in self.gateways.values_mut() {
+            gateway.requests = 0;
+            gateway.gaps = 0;
+            gateway.gap_sum = 0;
+            gateway.max_gap = 0;
+        }
+    }
+
+    /// The approximate number of bytes this monitor occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<GatewayID, GatewayPolling>(self.gateways.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway(id: u16) -> GatewayID {
+        GatewayID::try_from(id).unwrap()
+    }
+
+    /// Poll gateways in the given repeating pattern for `requests` requests.
+    fn poll(
+        monitor: &mut PollingMonitor,
+        pattern: &[u16],
+        requests: usize,
+    ) -> Vec<(GatewayID, UnfairPolling)> {
+        let fairness = PollingFairness {
+            window_requests: 100,
+            ..Default::default()
+        };
+        pattern
+            .iter()
+            .cycle()
+            .take(requests)
+            .flat_map(|id| monitor.request(gateway(*id), &fairness))
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut monitor = PollingMonitor::default();
+        assert_eq!(poll(&mut monitor, &[1, 2], 1000), vec![]);
+
+        let report = monitor.report();
+        assert_eq!(report.0.len(), 2);
+        for share in &report.0 {
+            assert_eq!(share.requests, 500);
+            assert_eq!(share.share, Some(0.5));
+            assert_eq!(share.fair_share, Some(0.5));
+            assert_eq!(share.mean_gap, Some(1.0));
+            assert_eq!(share.max_gap, 1);
+        }
+    }
+
+    #[test]
+    fn favoring_one_gateway() {
+        let mut monitor = PollingMonitor::default();
+        poll(&mut monitor, &[1, 2], 100);
+
+        // Gateway 2 gets one request in ten, which is a fifth of its fair share
+        let pattern = [1, 1, 1, 1, 1, 1, 1, 1, 1, 2];
+        assert_eq!(poll(&mut monitor, &pattern, 200), vec![]);
+        assert_eq!(
+            poll(&mut monitor, &pattern, 100),
+            vec![(
+                gateway(2),
+                UnfairPolling {
+                    share: 0.1,
+                    fair_share: 0.5,
+                    max_gap: 9,
+                }
+            )]
+        );
+
+        // Reported once, until it recovers
+        assert_eq!(poll(&mut monitor, &pattern, 1000), vec![]);
+        poll(&mut monitor, &[1, 2], 100);
+        assert_eq!(poll(&mut monitor, &pattern, 300).len(), 1);
+    }
+
+    #[test]
+    fn one_gateway() {
+        let mut monitor = PollingMonitor::default();
+        assert_eq!(poll(&mut monitor, &[1], 1000), vec![]);
+        assert_eq!(monitor.report().0[0].share, Some(1.0));
+
+        // A gateway which stops being polled is eventually forgotten
+        poll(&mut monitor, &[2], 100 * FORGET_AFTER_WINDOWS as usize);
+        let report = monitor.report();
+        assert_eq!(report.0.len(), 1);
+        assert_eq!(report.0[0].gateway, gateway(2));
+    }
+}
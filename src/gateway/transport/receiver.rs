@@ -1,11 +1,16 @@
 use super::super::link::{self, Frame, GatewayID};
+use super::polling::{PollingFairness, PollingMonitor, PollingReport, UnfairPolling};
 use super::*;
 use crate::gateway::link::Address;
+use crate::gateway::GatewayCapabilities;
+use crate::log_limit::LogLimiter;
+use crate::memory::{btree_map_bytes, MemoryReport};
 use crate::pv;
 use crate::pv::link::SlotCounter;
 use crate::pv::network::ReceivedPacketHeader;
+use crate::text::LossyStr;
 use std::collections::btree_map::Entry;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::mem::size_of;
 
 pub trait Sink {
@@ -19,7 +24,18 @@ pub trait Sink {
     fn gateway_identity_observed(&mut self, gateway_id: GatewayID, address: pv::LongAddress);
 
     /// A gateway's version was observed.
-    fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str);
+    ///
+    /// `version` is normalized to a single line of bounded length, while `raw` contains the bytes
+    /// exactly as they were received.
+    fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str, raw: &[u8]);
+
+    /// The controller assigned a gateway a new ID, and the gateway acknowledged it.
+    ///
+    /// The gateway with hardware address `address` answered at `old`, which during enumeration is
+    /// the enumeration ID, and now answers at `new`.
+    fn gateway_id_assigned(&mut self, old: GatewayID, new: GatewayID, address: pv::LongAddress) {
+        let _ = (old, new, address);
+    }
 
     /// Enumeration ended.
     fn enumeration_ended(&mut self, gateway_id: GatewayID);
@@ -51,19 +67,131 @@ pub trait Sink {
         request: (PacketType, &[u8]),
         response: (PacketType, &[u8]),
     );
+
+    /// A command was abandoned by the controller without a response from the gateway.
+    fn command_timed_out(
+        &mut self,
+        gateway_id: GatewayID,
+        packet_type: PacketType,
+        sequence_number: CommandSequenceNumber,
+    );
+
+    /// A gateway reported the number of transmit buffers it has free.
+    ///
+    /// A gateway with no free buffers can't accept more commands.
+    fn gateway_tx_buffers_free_observed(&mut self, gateway_id: GatewayID, tx_buffers_free: u8);
+
+    /// A gateway reported its status in a receive response.
+    ///
+    /// This is called for every receive response, before any of the packets it carries, with
+    /// every status field the gateway included. Fields with a meaning of their own, such as the
+    /// slot counter, are also reported through their own callbacks.
+    fn receive_status(&mut self, gateway_id: GatewayID, status: &ReceiveResponse) {
+        let _ = (gateway_id, status);
+    }
+
+    /// A frame could not be interpreted, and was discarded.
+    ///
+    /// This is called for each frame counted as invalid or of an unhandled type, so that it can be
+    /// kept for study.
+    fn invalid_frame(&mut self, frame: &Frame, reason: InvalidFrameReason) {
+        let _ = (frame, reason);
+    }
+
+    /// The link layer discarded a frame for having an incorrect checksum.
+    ///
+    /// `bytes` is the frame as it appeared on the bus, as passed to
+    /// [`link::Sink::checksum_failed()`].
+    fn checksum_failed(&mut self, bytes: &[u8]) {
+        let _ = bytes;
+    }
+
+    /// How evenly gateways must be polled to avoid a call to `polling_unfair()`.
+    fn polling_fairness(&self) -> PollingFairness {
+        PollingFairness::default()
+    }
+
+    /// A gateway's share of receive requests has stayed below its fair share for the sustained
+    /// number of windows.
+    ///
+    /// This is called once each time this begins.
+    fn polling_unfair(&mut self, gateway_id: GatewayID, polling: &UnfairPolling) {
+        let _ = (gateway_id, polling);
+    }
+
+    /// The capabilities of a gateway's firmware, which determine how its traffic is decoded.
+    fn gateway_capabilities(&self, gateway_id: GatewayID) -> GatewayCapabilities {
+        let _ = gateway_id;
+        GatewayCapabilities::default()
+    }
+}
+
+/// Why the transport layer could not interpret a frame.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum InvalidFrameReason {
+    /// The frame's type is not one the transport layer handles.
+    UnknownType,
+    /// The frame was addressed in the wrong direction, or to the wrong gateway, for its type.
+    WrongAddress,
+    /// The frame's payload was the wrong length for its type.
+    WrongLength,
+    /// The frame's payload could not be decoded.
+    Malformed,
+    /// The frame's type is known, but taptap can't yet decode its payload.
+    Undecoded,
+}
+
+impl InvalidFrameReason {
+    /// Every reason.
+    pub const ALL: [InvalidFrameReason; 5] = [
+        InvalidFrameReason::UnknownType,
+        InvalidFrameReason::WrongAddress,
+        InvalidFrameReason::WrongLength,
+        InvalidFrameReason::Malformed,
+        InvalidFrameReason::Undecoded,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvalidFrameReason::UnknownType => "unknown_type",
+            InvalidFrameReason::WrongAddress => "wrong_address",
+            InvalidFrameReason::WrongLength => "wrong_length",
+            InvalidFrameReason::Malformed => "malformed",
+            InvalidFrameReason::Undecoded => "undecoded",
+        }
+    }
+}
+
+impl std::fmt::Display for InvalidFrameReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Receiver<S: Sink> {
     sink: S,
-    rx_packet_numbers: BTreeMap<GatewayID, u16>,
+    rx_packet_numbers: BTreeMap<GatewayID, PacketNumbers>,
     command_sequence_numbers: BTreeMap<GatewayID, CommandSequenceNumber>,
     commands_awaiting_response: BTreeMap<(GatewayID, CommandSequenceNumber), (PacketType, Vec<u8>)>,
+    unanswered_commands: BTreeMap<GatewayID, u64>,
+    /// The new ID and hardware address in each assign gateway ID request awaiting a response, by
+    /// the ID to which it was sent.
+    pending_assignments: BTreeMap<GatewayID, (GatewayID, pv::LongAddress)>,
+    recent_packets: BTreeMap<GatewayID, RecentPackets>,
+    /// The number of receive responses handled, which ages `recent_packets`.
+    receive_response_clock: u64,
+    polling: PollingMonitor,
     counters: Counters,
+    warnings: LogLimiter<(link::Type, InvalidFrameReason)>,
 }
 
 impl<S: Sink> link::Sink for Receiver<S> {
     fn frame(&mut self, frame: Frame) {
+        self.counters.frames += 1;
         match frame.frame_type {
             link::Type::RECEIVE_REQUEST => {
                 self.receive_request(frame);
@@ -96,10 +224,10 @@ impl<S: Sink> link::Sink for Receiver<S> {
                 self.enumeration_response(frame);
             }
             link::Type::ASSIGN_GATEWAY_ID_REQUEST => {
-                self.counters.assign_gateway_id_requests += 1;
+                self.assign_gateway_id_request(frame);
             }
             link::Type::ASSIGN_GATEWAY_ID_RESPONSE => {
-                self.counters.assign_gateway_id_responses += 1;
+                self.assign_gateway_id_response(frame);
             }
             link::Type::IDENTIFY_REQUEST => {
                 self.counters.identify_requests += 1;
@@ -123,13 +251,27 @@ impl<S: Sink> link::Sink for Receiver<S> {
                 }
                 Address::To(_) => {
                     self.counters.invalid_enumeration_end_responses += 1;
+                    self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
                 }
             },
+            link::Type::LEGACY_RECEIVE_REQUEST => {
+                self.counters.legacy_receive_requests += 1;
+                self.invalid_frame(&frame, InvalidFrameReason::Undecoded);
+            }
+            link::Type::LEGACY_RECEIVE_RESPONSE => {
+                self.counters.legacy_receive_responses += 1;
+                self.invalid_frame(&frame, InvalidFrameReason::Undecoded);
+            }
             _ => {
-                self.counters.unhandled_frame_type += 1;
+                self.counters.unhandled_frame_types += 1;
+                self.invalid_frame(&frame, InvalidFrameReason::UnknownType);
             }
         }
     }
+
+    fn checksum_failed(&mut self, bytes: &[u8]) {
+        self.sink.checksum_failed(bytes);
+    }
 }
 
 impl<S: Sink> Receiver<S> {
@@ -140,7 +282,13 @@ impl<S: Sink> Receiver<S> {
             rx_packet_numbers: Default::default(),
             command_sequence_numbers: Default::default(),
             commands_awaiting_response: Default::default(),
+            unanswered_commands: Default::default(),
+            pending_assignments: Default::default(),
+            recent_packets: Default::default(),
+            receive_response_clock: 0,
+            polling: Default::default(),
             counters: Default::default(),
+            warnings: Default::default(),
         }
     }
 
@@ -167,16 +315,82 @@ impl<S: Sink> Receiver<S> {
     /// Reset the counters.
     pub fn reset_counters(&mut self) {
         self.counters = Default::default();
+        self.unanswered_commands.clear();
+        self.polling.reset_statistics();
+    }
+
+    /// The number of commands abandoned without a response, by gateway.
+    pub fn unanswered_commands(&self) -> &BTreeMap<GatewayID, u64> {
+        &self.unanswered_commands
+    }
+
+    /// Each gateway's share of receive requests.
+    pub fn polling_report(&self) -> PollingReport {
+        self.polling.report()
+    }
+
+    /// Approximate the memory held by the receiver's bookkeeping.
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::default();
+        report.add(
+            "transport.packet_numbers",
+            btree_map_bytes::<GatewayID, PacketNumbers>(self.rx_packet_numbers.len())
+                + btree_map_bytes::<GatewayID, CommandSequenceNumber>(
+                    self.command_sequence_numbers.len(),
+                )
+                + btree_map_bytes::<GatewayID, u64>(self.unanswered_commands.len())
+                + btree_map_bytes::<GatewayID, (GatewayID, pv::LongAddress)>(
+                    self.pending_assignments.len(),
+                ),
+        );
+        report.add(
+            "transport.commands_awaiting_response",
+            btree_map_bytes::<(GatewayID, CommandSequenceNumber), (PacketType, Vec<u8>)>(
+                self.commands_awaiting_response.len(),
+            ) + self
+                .commands_awaiting_response
+                .values()
+                .map(|(_, request)| request.capacity())
+                .sum::<usize>(),
+        );
+        report.add(
+            "transport.recent_packets",
+            btree_map_bytes::<GatewayID, RecentPackets>(self.recent_packets.len())
+                + self
+                    .recent_packets
+                    .values()
+                    .map(|recent| recent.packets.capacity() * size_of::<PacketKey>())
+                    .sum::<usize>(),
+        );
+        report.add("transport.polling", self.polling.approximate_bytes());
+        report
+    }
+
+    /// Pass an invalid frame to the sink, logging the first of each kind and then fewer.
+    fn invalid_frame(&mut self, frame: &Frame, reason: InvalidFrameReason) {
+        if let Some(count) = self.warnings.count((frame.frame_type, reason)) {
+            log::warn!(
+                "discarded {:?} frame {:?} ({} bytes): {} ({} so far)",
+                frame.frame_type,
+                frame.address,
+                frame.payload.len(),
+                reason,
+                count
+            );
+        }
+        self.sink.invalid_frame(frame, reason);
     }
 
     fn receive_request(&mut self, frame: Frame) {
         let Address::To(gateway_id) = frame.address else {
-            self.counters.invalid_receive_request += 1;
+            self.counters.invalid_receive_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
         let Ok(payload) = ReceiveRequest::ref_from_bytes(frame.payload.as_ref()) else {
-            self.counters.invalid_receive_request += 1;
+            self.counters.invalid_receive_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongLength);
             return;
         };
 
@@ -186,69 +400,133 @@ impl<S: Sink> Receiver<S> {
 
         self.counters.receive_requests += 1;
 
+        let fairness = self.sink.polling_fairness();
+        for (gateway_id, unfair) in self.polling.request(gateway_id, &fairness) {
+            self.sink.polling_unfair(gateway_id, &unfair);
+        }
+
         // Record the packet number for this gateway
         let n: u16 = payload.packet_number.into();
-        *self.rx_packet_numbers.entry(gateway_id).or_insert(n) = n;
+        if self
+            .rx_packet_numbers
+            .entry(gateway_id)
+            .or_insert_with(|| PacketNumbers::new(n))
+            .advertise(n)
+        {
+            self.counters.packet_number_resyncs += 1;
+        }
     }
 
     fn receive_response(&mut self, frame: Frame) {
         let Address::From(gateway_id) = frame.address else {
             self.counters.invalid_receive_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
         // Get the packet number for this gateway
-        let Some(n) = self.rx_packet_numbers.get_mut(&gateway_id) else {
-            self.counters.receive_response_from_unknown_gateway += 1;
+        let Some(packet_numbers) = self.rx_packet_numbers.get_mut(&gateway_id) else {
+            self.counters.receive_responses_from_unknown_gateways += 1;
             return;
         };
 
         // Interpret the response
-        let Ok((status, packets)) = ReceiveResponse::read_from_bytes(frame.payload.as_ref(), *n)
+        let Ok((status, packets)) =
+            ReceiveResponse::read_from_bytes(frame.payload.as_ref(), packet_numbers.reference)
         else {
             self.counters.invalid_receive_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::Malformed);
             return;
         };
 
         self.counters.receive_responses += 1;
 
-        // TODO: deduplicate gateway -> controller retransmissions
+        // Forget the packets of gateways which have gone quiet
+        self.receive_response_clock += 1;
+        let now = self.receive_response_clock;
+        self.recent_packets
+            .retain(|_, recent| now - recent.last_response < RecentPackets::FORGET_AFTER);
+        let recent_packets = self.recent_packets.entry(gateway_id).or_default();
+        recent_packets.last_response = now;
 
         // Update the packet number
-        *n = status.packet_number;
+        if packet_numbers.observe(&status) {
+            self.counters.packet_number_resyncs += 1;
+        }
 
-        // Observe the slot counter
-        self.sink
-            .gateway_slot_counter_observed(gateway_id, status.slot_counter);
+        self.sink.receive_status(gateway_id, &status);
+
+        // Observe the slot counter, if it means anything
+        if self
+            .sink
+            .gateway_capabilities(gateway_id)
+            .unreliable_slot_counters
+        {
+            self.counters.unreliable_slot_counters += 1;
+        } else {
+            self.sink
+                .gateway_slot_counter_observed(gateway_id, status.slot_counter);
+        }
+
+        if let Some(tx_buffers_free) = status.tx_buffers_free {
+            self.sink
+                .gateway_tx_buffers_free_observed(gateway_id, tx_buffers_free);
+        }
 
         for packet in packets {
             if let Ok((header, data)) = packet {
+                // Has this packet already been delivered in an earlier response?
+                if !recent_packets.insert((header.node_address.0.get(), header.dsn.0)) {
+                    self.counters.duplicate_receive_packets += 1;
+                    continue;
+                }
+
                 self.counters.receive_packets += 1;
 
                 // Observe the packet
                 self.sink.packet_received(gateway_id, header, data);
             } else {
-                self.counters.receive_packet_too_short += 1;
+                self.counters.receive_packets_too_short += 1;
             }
         }
     }
 
     fn command_request(&mut self, frame: Frame) {
         let Address::To(gateway_id) = frame.address else {
-            println!("bad tx request: {:?}", frame);
             self.counters.invalid_command_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
         if frame.payload.len() < size_of::<CommandRequest>() {
-            println!("bad tx request: {:?}", frame);
             self.counters.invalid_command_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongLength);
             return;
         }
 
         let (header, payload) = frame.payload.split_at(size_of::<CommandRequest>());
         let header = CommandRequest::ref_from_bytes(header).unwrap(); // infallible
 
+        // The controller issues one command at a time to each gateway, retransmitting it until it
+        // gets a response. Any other command still awaiting a response has been abandoned.
+        let abandoned: Vec<_> = self
+            .commands_awaiting_response
+            .range(
+                (gateway_id, CommandSequenceNumber(0))
+                    ..=(gateway_id, CommandSequenceNumber(u8::MAX)),
+            )
+            .map(|(key, (packet_type, _))| (key.1, *packet_type))
+            .filter(|(sequence_number, _)| *sequence_number != header.sequence_number)
+            .collect();
+        for (sequence_number, packet_type) in abandoned {
+            self.commands_awaiting_response
+                .remove(&(gateway_id, sequence_number));
+            self.counters.command_timeouts += 1;
+            *self.unanswered_commands.entry(gateway_id).or_default() += 1;
+            self.sink
+                .command_timed_out(gateway_id, packet_type, sequence_number);
+        }
+
         // The gateway may respond to this, so record it
         self.commands_awaiting_response.insert(
             (gateway_id, header.sequence_number),
@@ -278,20 +556,23 @@ impl<S: Sink> Receiver<S> {
 
     fn command_response(&mut self, frame: Frame) {
         let Address::From(gateway_id) = frame.address else {
-            println!("wrong addr: {:?}", frame);
             self.counters.invalid_command_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
         if frame.payload.len() < size_of::<CommandResponse>() {
-            println!("bad tx response: {:?}", frame);
             self.counters.invalid_command_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongLength);
             return;
         };
 
         let (header, payload) = frame.payload.split_at(size_of::<CommandResponse>());
         let header = CommandResponse::ref_from_bytes(header).unwrap(); // infallible
 
+        self.sink
+            .gateway_tx_buffers_free_observed(gateway_id, header.tx_buffers_free);
+
         // Deduplicate responses
         let Some((request_packet_type, request_payload)) = self
             .commands_awaiting_response
@@ -312,17 +593,20 @@ impl<S: Sink> Receiver<S> {
 
     fn enumeration_start_request(&mut self, frame: Frame) {
         let Address::To(GatewayID::ZERO) = frame.address else {
-            self.counters.invalid_enumeration_start_request += 1;
+            self.counters.invalid_enumeration_start_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
         let Ok(request) = EnumerationStartRequest::ref_from_bytes(frame.payload.as_ref()) else {
-            self.counters.invalid_enumeration_start_request += 1;
+            self.counters.invalid_enumeration_start_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongLength);
             return;
         };
 
         let Some(gateway_id) = request.enumeration_gateway_id() else {
-            self.counters.invalid_enumeration_start_request += 1;
+            self.counters.invalid_enumeration_start_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::Malformed);
             return;
         };
 
@@ -331,14 +615,56 @@ impl<S: Sink> Receiver<S> {
         self.sink.enumeration_started(gateway_id);
     }
 
+    fn assign_gateway_id_request(&mut self, frame: Frame) {
+        let Address::To(gateway_id) = frame.address else {
+            self.counters.invalid_assign_gateway_id_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
+            return;
+        };
+
+        let Ok(request) = AssignGatewayIdRequest::ref_from_bytes(frame.payload.as_ref()) else {
+            self.counters.invalid_assign_gateway_id_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongLength);
+            return;
+        };
+
+        let Some(new_gateway_id) = request.gateway_id() else {
+            self.counters.invalid_assign_gateway_id_requests += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::Malformed);
+            return;
+        };
+
+        self.counters.assign_gateway_id_requests += 1;
+        self.pending_assignments
+            .insert(gateway_id, (new_gateway_id, request.pv_long_address));
+    }
+
+    fn assign_gateway_id_response(&mut self, frame: Frame) {
+        let Address::From(gateway_id) = frame.address else {
+            self.counters.invalid_assign_gateway_id_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
+            return;
+        };
+
+        self.counters.assign_gateway_id_responses += 1;
+
+        // Only a response to a request we saw says which ID was assigned
+        if let Some((new_gateway_id, address)) = self.pending_assignments.remove(&gateway_id) {
+            self.sink
+                .gateway_id_assigned(gateway_id, new_gateway_id, address);
+        }
+    }
+
     fn identify_response(&mut self, frame: Frame) {
         let Address::From(gateway_id) = frame.address else {
             self.counters.invalid_identify_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
         let Ok(response) = IdentifyResponse::ref_from_bytes(frame.payload.as_ref()) else {
             self.counters.invalid_identify_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongLength);
             return;
         };
 
@@ -351,11 +677,13 @@ impl<S: Sink> Receiver<S> {
     fn enumeration_response(&mut self, frame: Frame) {
         let Address::From(gateway_id) = frame.address else {
             self.counters.invalid_enumeration_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
         let Ok(response) = IdentifyResponse::ref_from_bytes(frame.payload.as_ref()) else {
             self.counters.invalid_enumeration_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongLength);
             return;
         };
 
@@ -368,55 +696,256 @@ impl<S: Sink> Receiver<S> {
     pub fn version_response(&mut self, frame: Frame) {
         let Address::From(gateway_id) = frame.address else {
             self.counters.invalid_version_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::WrongAddress);
             return;
         };
 
-        let version = match std::str::from_utf8(frame.payload.as_ref()) {
-            Ok(str) if !str.is_empty() => str,
-            _ => {
-                self.counters.invalid_version_responses += 1;
-                return;
-            }
+        let raw = LossyStr(frame.payload.as_ref());
+        let version = if raw.is_mostly_binary() {
+            None
+        } else {
+            normalize_version(&raw.to_str_lossy())
+        };
+        let Some(version) = version else {
+            self.counters.invalid_version_responses += 1;
+            self.invalid_frame(&frame, InvalidFrameReason::Malformed);
+            return;
         };
 
         self.counters.version_responses += 1;
-        self.sink.gateway_version_observed(gateway_id, version);
+        if !raw.is_valid_utf8() {
+            self.counters.lossy_version_responses += 1;
+        }
+        self.sink
+            .gateway_version_observed(gateway_id, &version, frame.payload.as_ref());
+    }
+}
+
+/// The maximum length of a normalized version string, in bytes.
+const MAX_VERSION_LENGTH: usize = 128;
+
+/// Normalize a gateway version string.
+///
+/// Gateways report their version as several `\r`-terminated lines. These lines are trimmed and
+/// joined with `" / "`, other control characters are replaced with U+FFFD, and the result is
+/// truncated to `MAX_VERSION_LENGTH`. Returns `None` if the version is empty.
+fn normalize_version(raw: &str) -> Option<String> {
+    let mut version = raw
+        .split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.replace(|c: char| c.is_control(), "\u{FFFD}"))
+        .collect::<Vec<_>>()
+        .join(" / ");
+
+    if version.len() > MAX_VERSION_LENGTH {
+        let mut end = MAX_VERSION_LENGTH;
+        while !version.is_char_boundary(end) {
+            end -= 1;
+        }
+        version.truncate(end);
+    }
+
+    if version.is_empty() {
+        None
+    } else {
+        Some(version)
     }
 }
 
+/// A received packet's node address and DSN.
+type PacketKey = (u16, u8);
+
+/// The packets recently delivered from one gateway, to recognize those it delivers again.
+///
+/// A gateway which is polled again before its response reaches the controller may send the same
+/// packets in another response, at a later slot counter. Each packet is identified by its node
+/// address and DSN, which only repeat once the node's DSN wraps around.
+#[derive(Debug, Clone, Default)]
+struct RecentPackets {
+    /// The most recent packets, oldest first.
+    packets: VecDeque<PacketKey>,
+    /// The receive response clock as of this gateway's last response.
+    last_response: u64,
+}
+
+impl RecentPackets {
+    /// The number of packets remembered per gateway, which is far fewer than a node sends before
+    /// its DSN wraps around.
+    const CAPACITY: usize = 64;
+    /// The number of receive responses from any gateway after which a gateway which hasn't sent
+    /// one is forgotten.
+    const FORGET_AFTER: u64 = 4096;
+
+    /// Remember a packet, returning `false` if it was already delivered.
+    fn insert(&mut self, key: PacketKey) -> bool {
+        if self.packets.contains(&key) {
+            return false;
+        }
+        if self.packets.len() == Self::CAPACITY {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(key);
+        true
+    }
+}
+
+/// Packet number bookkeeping for a single gateway.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct PacketNumbers {
+    /// The packet number used to expand truncated packet numbers in receive responses.
+    reference: u16,
+    /// The most recent plausible packet number and slot counter in a receive response.
+    last_received: Option<(u16, SlotCounter)>,
+    /// Whether we're waiting for a full packet number to re-baseline.
+    resynchronizing: bool,
+}
+
+impl PacketNumbers {
+    /// The number of packets by which the packet number may advance regardless of elapsed time.
+    const MAX_ADVANCE: u16 = 32;
+    /// The number of slots per additional packet by which the packet number may advance.
+    const SLOTS_PER_ADVANCE: u16 = 20;
+    /// The number of packets by which the packet number may go backwards.
+    const MAX_REGRESSION: u16 = 8;
+
+    fn new(reference: u16) -> Self {
+        Self {
+            reference,
+            last_received: None,
+            resynchronizing: false,
+        }
+    }
+
+    /// Determine if a packet number is a plausible successor to the last received packet number.
+    fn is_plausible(&self, packet_number: u16, slot_counter: SlotCounter) -> bool {
+        let Some((last_packet_number, last_slot_counter)) = self.last_received else {
+            return true;
+        };
+
+        // How much time has passed?
+        let elapsed_slots = if slot_counter == last_slot_counter {
+            0
+        } else {
+            slot_counter.slots_since(&last_slot_counter).unwrap_or(0)
+        };
+
+        let advance = packet_number.wrapping_sub(last_packet_number);
+        let regression = last_packet_number.wrapping_sub(packet_number);
+        advance <= Self::MAX_ADVANCE + elapsed_slots / Self::SLOTS_PER_ADVANCE
+            || regression <= Self::MAX_REGRESSION
+    }
+
+    /// Note the packet number a controller advertised in a receive request, returning `true` if the
+    /// packet numbers were resynchronized.
+    ///
+    /// A controller only advances its packet numbers, so one which went backwards by more than a
+    /// retransmission could explain means the controller restarted. Its new packet number is
+    /// authoritative: the gateway's responses will follow from it.
+    fn advertise(&mut self, packet_number: u16) -> bool {
+        self.reference = packet_number;
+
+        let Some((last_packet_number, _)) = self.last_received else {
+            return false;
+        };
+        let regression = last_packet_number.wrapping_sub(packet_number);
+        if regression <= Self::MAX_REGRESSION || regression > u16::MAX / 2 {
+            return false;
+        }
+
+        self.last_received = None;
+        self.resynchronizing = false;
+        true
+    }
+
+    /// Observe a receive response, returning `true` if the packet numbers were resynchronized.
+    fn observe(&mut self, status: &ReceiveResponse) -> bool {
+        let plausible = self.is_plausible(status.packet_number, status.slot_counter);
+
+        let resync = if status.packet_number_high.is_some() {
+            // The full packet number is authoritative, so accept it unconditionally
+            let resync = self.resynchronizing || !plausible;
+            self.resynchronizing = false;
+            resync
+        } else if self.resynchronizing || !plausible {
+            // The truncated packet number can't be trusted
+            // Wait for a full packet number
+            self.resynchronizing = true;
+            return false;
+        } else {
+            false
+        };
+
+        self.reference = status.packet_number;
+        self.last_received = Some((status.packet_number, status.slot_counter));
+        resync
+    }
+}
+
+/// Counters describing the frames and commands handled by a `Receiver`.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Counters {
+    /// The number of frames received, of any type.
+    pub frames: u64,
     /// The number of received frames with an unknown frame type.
-    pub unhandled_frame_type: u64,
-    pub invalid_receive_request: u64,
+    #[cfg_attr(feature = "serde", serde(alias = "unhandled_frame_type"))]
+    pub unhandled_frame_types: u64,
+    #[cfg_attr(feature = "serde", serde(alias = "invalid_receive_request"))]
+    pub invalid_receive_requests: u64,
     pub receive_requests: u64,
     pub invalid_receive_responses: u64,
-    pub receive_response_from_unknown_gateway: u64,
+    #[cfg_attr(
+        feature = "serde",
+        serde(alias = "receive_response_from_unknown_gateway")
+    )]
+    pub receive_responses_from_unknown_gateways: u64,
     pub receive_responses: u64,
+    /// The number of receive requests in the older polling format, which isn't yet decoded.
+    pub legacy_receive_requests: u64,
+    /// The number of receive responses in the older polling format, which isn't yet decoded.
+    pub legacy_receive_responses: u64,
+    /// The number of times a gateway's packet number jumped implausibly and was re-baselined.
+    pub packet_number_resyncs: u64,
     pub receive_packets: u64,
-    pub receive_packet_too_short: u64,
+    /// The number of received packets dropped because the gateway had already delivered them.
+    pub duplicate_receive_packets: u64,
+    /// The number of slot counters ignored because the gateway's firmware reports them unreliably.
+    pub unreliable_slot_counters: u64,
+    #[cfg_attr(feature = "serde", serde(alias = "receive_packet_too_short"))]
+    pub receive_packets_too_short: u64,
     pub invalid_command_requests: u64,
     pub retransmitted_command_requests: u64,
     pub command_requests: u64,
     pub invalid_command_responses: u64,
     pub retransmitted_command_responses: u64,
     pub command_responses: u64,
+    /// The number of commands abandoned by the controller without a response.
+    pub command_timeouts: u64,
     pub ping_requests: u64,
     pub ping_responses: u64,
     pub enumeration_start_requests: u64,
-    pub invalid_enumeration_start_request: u64,
+    #[cfg_attr(feature = "serde", serde(alias = "invalid_enumeration_start_request"))]
+    pub invalid_enumeration_start_requests: u64,
     pub enumeration_start_responses: u64,
     pub enumeration_requests: u64,
     pub enumeration_responses: u64,
     pub invalid_enumeration_responses: u64,
     pub version_requests: u64,
     pub version_responses: u64,
+    /// The number of version responses which weren't valid UTF-8, but were mostly text and were
+    /// decoded with replacement characters.
+    pub lossy_version_responses: u64,
     pub invalid_version_responses: u64,
     pub enumeration_end_requests: u64,
     pub enumeration_end_responses: u64,
     pub invalid_enumeration_end_responses: u64,
     pub assign_gateway_id_requests: u64,
+    pub invalid_assign_gateway_id_requests: u64,
     pub assign_gateway_id_responses: u64,
+    pub invalid_assign_gateway_id_responses: u64,
     pub identify_requests: u64,
     pub identify_responses: u64,
     pub invalid_identify_responses: u64,
@@ -442,6 +971,11 @@ mod tests {
             gateway_id: GatewayID,
             version: String,
         },
+        GatewayIdAssigned {
+            old: GatewayID,
+            new: GatewayID,
+            address: LongAddress,
+        },
         EnumerationEnded {
             gateway_id: GatewayID,
         },
@@ -462,11 +996,28 @@ mod tests {
             request: (PacketType, Vec<u8>),
             response: (PacketType, Vec<u8>),
         },
+        CommandTimedOut {
+            gateway_id: GatewayID,
+            packet_type: PacketType,
+            sequence_number: CommandSequenceNumber,
+        },
+        GatewayTxBuffersFreeObserved {
+            gateway_id: GatewayID,
+            tx_buffers_free: u8,
+        },
+        ReceiveStatus {
+            gateway_id: GatewayID,
+            status: ReceiveResponse,
+        },
+        InvalidFrame {
+            frame: Frame,
+            reason: InvalidFrameReason,
+        },
     }
     use Event::*;
 
     #[derive(Debug, Default)]
-    struct TestSink(Vec<Event>);
+    struct TestSink(Vec<Event>, GatewayCapabilities);
     impl super::Sink for TestSink {
         fn enumeration_started(&mut self, enumeration_gateway_id: GatewayID) {
             self.0.push(EnumerationStarted {
@@ -481,13 +1032,17 @@ mod tests {
             })
         }
 
-        fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str) {
+        fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str, _raw: &[u8]) {
             self.0.push(GatewayVersionObserved {
                 gateway_id,
                 version: version.into(),
             })
         }
 
+        fn gateway_id_assigned(&mut self, old: GatewayID, new: GatewayID, address: LongAddress) {
+            self.0.push(GatewayIdAssigned { old, new, address });
+        }
+
         fn enumeration_ended(&mut self, gateway_id: GatewayID) {
             self.0.push(EnumerationEnded { gateway_id });
         }
@@ -515,7 +1070,7 @@ mod tests {
         ) {
             self.0.push(PacketReceived {
                 gateway_id,
-                header: header.clone(),
+                header: *header,
                 data: data.into(),
             })
         }
@@ -532,27 +1087,316 @@ mod tests {
                 response: (response.0, response.1.into()),
             })
         }
+
+        fn command_timed_out(
+            &mut self,
+            gateway_id: GatewayID,
+            packet_type: PacketType,
+            sequence_number: CommandSequenceNumber,
+        ) {
+            self.0.push(CommandTimedOut {
+                gateway_id,
+                packet_type,
+                sequence_number,
+            })
+        }
+
+        fn gateway_tx_buffers_free_observed(&mut self, gateway_id: GatewayID, tx_buffers_free: u8) {
+            self.0.push(GatewayTxBuffersFreeObserved {
+                gateway_id,
+                tx_buffers_free,
+            })
+        }
+
+        fn receive_status(&mut self, gateway_id: GatewayID, status: &ReceiveResponse) {
+            self.0.push(ReceiveStatus {
+                gateway_id,
+                status: *status,
+            })
+        }
+
+        fn invalid_frame(&mut self, frame: &Frame, reason: InvalidFrameReason) {
+            self.0.push(InvalidFrame {
+                frame: frame.clone(),
+                reason,
+            })
+        }
+
+        fn gateway_capabilities(&self, _gateway_id: GatewayID) -> GatewayCapabilities {
+            self.1
+        }
     }
 
     #[test]
     fn unhandled_frame_type() {
         let mut rx = Receiver::new(TestSink::default());
-        rx.frame(Frame {
+        let frame = Frame {
             address: 0x1201.into(),
             frame_type: Type(0xffff),
             payload: vec![],
-        });
+        };
+        rx.frame(frame.clone());
+
+        assert_eq!(
+            &rx.sink().0,
+            &[InvalidFrame {
+                frame,
+                reason: InvalidFrameReason::UnknownType
+            }]
+        );
+        assert_eq!(
+            rx.counters(),
+            &Counters {
+                frames: 1,
+                unhandled_frame_types: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn legacy_receive_exchange() {
+        let mut rx = Receiver::new(TestSink::default());
+        let request = Frame {
+            address: Address::To(0x1201.try_into().unwrap()),
+            frame_type: Type::LEGACY_RECEIVE_REQUEST,
+            payload: vec![0x00, 0x01],
+        };
+        let response = Frame {
+            address: Address::From(0x1201.try_into().unwrap()),
+            frame_type: Type::LEGACY_RECEIVE_RESPONSE,
+            payload: vec![0x00, 0x01, 0x02, 0x03],
+        };
+        rx.frame(request.clone());
+        rx.frame(response.clone());
 
-        assert_eq!(&rx.sink().0, &[]);
+        // The exchange is recognized, but passed on undecoded
+        assert_eq!(
+            &rx.sink().0,
+            &[
+                InvalidFrame {
+                    frame: request,
+                    reason: InvalidFrameReason::Undecoded
+                },
+                InvalidFrame {
+                    frame: response,
+                    reason: InvalidFrameReason::Undecoded
+                }
+            ]
+        );
         assert_eq!(
             rx.counters(),
             &Counters {
-                unhandled_frame_type: 1,
+                frames: 2,
+                legacy_receive_requests: 1,
+                legacy_receive_responses: 1,
                 ..Default::default()
             }
         );
     }
 
+    #[test]
+    fn encoded_requests() {
+        use gateway::transport::{
+            enumeration_end_request, enumeration_request, identify_request, ping_request,
+            version_request,
+        };
+
+        let enumeration_id = GatewayID::try_from(0x1235).unwrap();
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let address = LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16]);
+        let response = |gateway_id, frame_type, payload: &[u8]| Frame {
+            address: Address::From(gateway_id),
+            frame_type,
+            payload: payload.to_vec(),
+        };
+        let identity = IdentifyResponse {
+            pv_long_address: address,
+            gateway_address: Address::To(gateway_id).into(),
+        };
+        let command = CommandRequest::new(PacketType::STRING_REQUEST, CommandSequenceNumber(7));
+        let command_response = CommandResponse {
+            unknown_1: 0x00,
+            tx_buffers_free: 0x0E,
+            unknown_2: 0x00,
+            packet_type: PacketType::STRING_RESPONSE,
+            command_sequence_number: CommandSequenceNumber(7),
+        };
+
+        let frames = [
+            Frame::from(&EnumerationStartRequest::new(enumeration_id)),
+            response(GatewayID::ZERO, Type::ENUMERATION_START_RESPONSE, &[]),
+            enumeration_request(enumeration_id),
+            response(
+                enumeration_id,
+                Type::ENUMERATION_RESPONSE,
+                identity.as_bytes(),
+            ),
+            Frame::from((
+                enumeration_id,
+                &AssignGatewayIdRequest::new(address, gateway_id),
+            )),
+            response(enumeration_id, Type::ASSIGN_GATEWAY_ID_RESPONSE, &[]),
+            identify_request(gateway_id),
+            response(gateway_id, Type::IDENTIFY_RESPONSE, identity.as_bytes()),
+            version_request(gateway_id),
+            response(gateway_id, Type::VERSION_RESPONSE, b"Mgate Version G8.59"),
+            enumeration_end_request(gateway_id),
+            response(gateway_id, Type::ENUMERATION_END_RESPONSE, &[]),
+            ping_request(gateway_id),
+            response(gateway_id, Type::PING_RESPONSE, &[]),
+            Frame::from((gateway_id, &ReceiveRequest::new(0x0101))),
+            Frame::from((gateway_id, &command, b"AT".as_slice())),
+            response(
+                gateway_id,
+                Type::COMMAND_RESPONSE,
+                command_response.as_bytes(),
+            ),
+        ];
+
+        // Encode every frame, and receive them as the link layer does
+        let bytes: Vec<u8> = frames.iter().flat_map(Frame::encode).collect();
+        let mut rx = gateway::link::Receiver::new(Receiver::new(TestSink::default()));
+        rx.extend_from_slice(&bytes);
+
+        assert_eq!(rx.counters().frames, frames.len() as u64);
+        let rx = rx.sink();
+        assert_eq!(
+            &rx.sink().0,
+            &[
+                EnumerationStarted {
+                    enumeration_gateway_id: enumeration_id
+                },
+                GatewayIdentityObserved {
+                    gateway_id: enumeration_id,
+                    address
+                },
+                GatewayIdAssigned {
+                    old: enumeration_id,
+                    new: gateway_id,
+                    address
+                },
+                GatewayIdentityObserved {
+                    gateway_id,
+                    address
+                },
+                GatewayVersionObserved {
+                    gateway_id,
+                    version: "Mgate Version G8.59".into()
+                },
+                EnumerationEnded { gateway_id },
+                GatewaySlotCounterCaptured { gateway_id },
+                GatewayTxBuffersFreeObserved {
+                    gateway_id,
+                    tx_buffers_free: 0x0E
+                },
+                CommandExecuted {
+                    gateway_id,
+                    request: (PacketType::STRING_REQUEST, b"AT".to_vec()),
+                    response: (PacketType::STRING_RESPONSE, vec![]),
+                },
+            ]
+        );
+        assert_eq!(
+            rx.counters(),
+            &Counters {
+                frames: frames.len() as u64,
+                enumeration_start_requests: 1,
+                enumeration_start_responses: 1,
+                enumeration_requests: 1,
+                enumeration_responses: 1,
+                assign_gateway_id_requests: 1,
+                assign_gateway_id_responses: 1,
+                identify_requests: 1,
+                identify_responses: 1,
+                version_requests: 1,
+                version_responses: 1,
+                enumeration_end_requests: 1,
+                enumeration_end_responses: 1,
+                ping_requests: 1,
+                ping_responses: 1,
+                receive_requests: 1,
+                command_requests: 1,
+                command_responses: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let to = Address::To(0x1201.try_into().unwrap());
+        let from = Address::From(0x1201.try_into().unwrap());
+        let frame = |address, frame_type, payload: &[u8]| Frame {
+            address,
+            frame_type,
+            payload: payload.into(),
+        };
+        let cases = [
+            (
+                frame(from, Type::RECEIVE_REQUEST, &[0x00, 0x01, 0x12, 0x34]),
+                InvalidFrameReason::WrongAddress,
+            ),
+            (
+                frame(to, Type::RECEIVE_REQUEST, &[0x00, 0x01]),
+                InvalidFrameReason::WrongLength,
+            ),
+            (
+                frame(to, Type::COMMAND_REQUEST, &[0x00]),
+                InvalidFrameReason::WrongLength,
+            ),
+            (
+                frame(to, Type::COMMAND_RESPONSE, &[]),
+                InvalidFrameReason::WrongAddress,
+            ),
+            (
+                frame(to, Type::ENUMERATION_START_REQUEST, &[]),
+                InvalidFrameReason::WrongAddress,
+            ),
+            (
+                frame(from, Type::IDENTIFY_RESPONSE, &[0x04, 0xC0]),
+                InvalidFrameReason::WrongLength,
+            ),
+            (
+                frame(from, Type::VERSION_RESPONSE, &[0xFF, 0xFE]),
+                InvalidFrameReason::Malformed,
+            ),
+            (
+                frame(to, Type::ENUMERATION_END_RESPONSE, &[]),
+                InvalidFrameReason::WrongAddress,
+            ),
+        ];
+
+        let mut rx = Receiver::new(TestSink::default());
+        for (frame, _) in &cases {
+            rx.frame(frame.clone());
+        }
+        let expected: Vec<_> = cases
+            .into_iter()
+            .map(|(frame, reason)| InvalidFrame { frame, reason })
+            .collect();
+        assert_eq!(rx.sink().0, expected);
+
+        // A receive response which can't be decoded
+        let mut rx = Receiver::new(TestSink::default());
+        rx.frame(receive_request(0x1234));
+        let malformed = receive_response(&[0x00, 0xE0, 0x03]);
+        rx.frame(malformed.clone());
+        assert_eq!(
+            rx.sink().0,
+            vec![
+                GatewaySlotCounterCaptured {
+                    gateway_id: 0x1201.try_into().unwrap()
+                },
+                InvalidFrame {
+                    frame: malformed,
+                    reason: InvalidFrameReason::Malformed
+                }
+            ]
+        );
+        assert_eq!(rx.counters().invalid_receive_responses, 1);
+    }
+
     #[test]
     fn reset_counters() {
         let mut rx = Receiver::new(TestSink::default());
@@ -570,6 +1414,286 @@ mod tests {
         assert_eq!(rx.counters(), &Counters::default());
     }
 
+    fn receive_request(packet_number: u16) -> Frame {
+        let [hi, lo] = packet_number.to_be_bytes();
+        Frame {
+            address: Address::To(0x1201.try_into().unwrap()),
+            frame_type: Type::RECEIVE_REQUEST,
+            payload: vec![0x00, 0x01, hi, lo, 0x04],
+        }
+    }
+
+    fn receive_response(payload: &[u8]) -> Frame {
+        Frame {
+            address: Address::From(0x1201.try_into().unwrap()),
+            frame_type: Type::RECEIVE_RESPONSE,
+            payload: payload.into(),
+        }
+    }
+
+    #[test]
+    fn packet_number_wrap() {
+        let mut rx = Receiver::new(TestSink::default());
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+        rx.frame(receive_request(0x12FF));
+        rx.frame(receive_response(&[0x00, 0xFF, 0xFF, 0x21, 0x31]));
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x12FF);
+
+        rx.frame(receive_request(0x1300));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x00, 0x21, 0x35]));
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x1300);
+
+        // Wrap the whole counter
+        let mut rx = Receiver::new(TestSink::default());
+        rx.frame(receive_request(0xFFFE));
+        rx.frame(receive_response(&[0x00, 0xFF, 0xFE, 0x21, 0x31]));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x01, 0x21, 0x35]));
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x0001);
+
+        assert_eq!(rx.counters().packet_number_resyncs, 0);
+        assert_eq!(rx.counters().receive_responses, 2);
+    }
+
+    #[test]
+    fn unreliable_slot_counters() {
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let exchange = |capabilities| {
+            let mut rx = Receiver::new(TestSink(vec![], capabilities));
+            rx.frame(receive_request(0x12FF));
+            rx.frame(receive_response(&[0x00, 0xFF, 0xFF, 0x21, 0x31]));
+            rx
+        };
+
+        let status = ReceiveResponse {
+            rx_buffers_used: None,
+            tx_buffers_free: None,
+            unknown_a: None,
+            unknown_b: None,
+            packet_number_high: None,
+            packet_number: 0x12FF,
+            slot_counter: SlotCounter::from(0x2131),
+        };
+
+        let rx = exchange(GatewayCapabilities::default());
+        assert_eq!(
+            &rx.sink().0,
+            &[
+                GatewaySlotCounterCaptured { gateway_id },
+                ReceiveStatus { gateway_id, status },
+                GatewaySlotCounterObserved {
+                    gateway_id,
+                    slot_counter: SlotCounter::from(0x2131),
+                },
+            ]
+        );
+
+        // The status is still reported in full, even though the slot counter isn't observed
+        let rx = exchange(GatewayCapabilities {
+            unreliable_slot_counters: true,
+        });
+        assert_eq!(
+            &rx.sink().0,
+            &[
+                GatewaySlotCounterCaptured { gateway_id },
+                ReceiveStatus { gateway_id, status },
+            ]
+        );
+        assert_eq!(rx.counters().unreliable_slot_counters, 1);
+        assert_eq!(rx.counters().receive_responses, 1);
+    }
+
+    #[test]
+    fn receive_status() {
+        let mut rx = Receiver::new(TestSink::default());
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+        // Every status field is present, followed by a packet
+        rx.frame(receive_request(0x1233));
+        rx.frame(receive_response(&[
+            0x00, 0xE0, 0x03, 0x0E, 0xAA, 0xBB, 0xCC, 0xDD, 0x12, 0x34, 0x21, 0x31, 0x31, 0x00,
+            0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0xFF,
+        ]));
+
+        let events = &rx.sink().0;
+        assert_eq!(
+            events[1],
+            ReceiveStatus {
+                gateway_id,
+                status: ReceiveResponse {
+                    rx_buffers_used: Some(0x03),
+                    tx_buffers_free: Some(0x0E),
+                    unknown_a: Some([0xAA, 0xBB]),
+                    unknown_b: Some([0xCC, 0xDD]),
+                    packet_number_high: Some(0x12),
+                    packet_number: 0x1234,
+                    slot_counter: SlotCounter::from(0x2131),
+                },
+            }
+        );
+        // Before the packet it carried
+        assert!(matches!(events.last(), Some(PacketReceived { .. })));
+    }
+
+    #[test]
+    fn duplicate_receive_packets() {
+        let mut rx = Receiver::new(TestSink::default());
+        let response = receive_response(&[
+            0x00, 0xE0, 0x03, 0x0E, 0xAA, 0xBB, 0xCC, 0xDD, 0x12, 0x34, 0x21, 0x31, 0x31, 0x00,
+            0x02, 0x00, 0x02, 0x07, 0x01, 0xAA, 0x31, 0x00, 0x03, 0x00, 0x03, 0x07, 0x00,
+        ]);
+        let packets = |rx: &Receiver<TestSink>| {
+            rx.sink()
+                .0
+                .iter()
+                .filter(|event| matches!(event, PacketReceived { .. }))
+                .count()
+        };
+
+        // The gateway retransmits its response, carrying the same packets
+        rx.frame(receive_request(0x1233));
+        rx.frame(response.clone());
+        rx.frame(response.clone());
+        assert_eq!(packets(&rx), 2);
+        assert_eq!(rx.counters().receive_responses, 2);
+        assert_eq!(rx.counters().receive_packets, 2);
+        assert_eq!(rx.counters().duplicate_receive_packets, 2);
+
+        // A later response at a later slot counter may carry the same packets again, along with
+        // new ones
+        rx.frame(receive_response(&[
+            0x00, 0xE0, 0x03, 0x0E, 0xAA, 0xBB, 0xCC, 0xDD, 0x12, 0x35, 0x21, 0x45, 0x31, 0x00,
+            0x02, 0x00, 0x02, 0x07, 0x01, 0xAA, 0x31, 0x00, 0x02, 0x00, 0x02, 0x08, 0x00,
+        ]));
+        assert_eq!(packets(&rx), 3);
+        assert_eq!(rx.counters().receive_packets, 3);
+        assert_eq!(rx.counters().duplicate_receive_packets, 3);
+
+        // Windows are bounded, and those of quiet gateways are forgotten
+        let mut recent = RecentPackets::default();
+        for dsn in 0..=u8::MAX {
+            assert!(recent.insert((2, dsn)));
+        }
+        assert_eq!(recent.packets.len(), RecentPackets::CAPACITY);
+        assert!(recent.insert((2, 0)));
+        rx.receive_response_clock += RecentPackets::FORGET_AFTER;
+        let other = GatewayID::try_from(0x1202).unwrap();
+        rx.frame(Frame {
+            address: Address::To(other),
+            ..receive_request(0x0036)
+        });
+        rx.frame(Frame {
+            address: Address::From(other),
+            ..receive_response(&[0x00, 0xFF, 0x36, 0x21, 0x50])
+        });
+        assert_eq!(rx.recent_packets.keys().collect::<Vec<_>>(), vec![&other]);
+    }
+
+    #[test]
+    fn packet_number_reset() {
+        let mut rx = Receiver::new(TestSink::default());
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+        rx.frame(receive_request(0x1883));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x83, 0x21, 0x31]));
+        rx.frame(receive_request(0x1884));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x84, 0x21, 0x35]));
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x1884);
+
+        // The gateway restarts its packet numbers, which would naively be expanded to 0x1902
+        rx.frame(receive_request(0x1885));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x02, 0x21, 0x40]));
+        assert!(rx.rx_packet_numbers[&gateway_id].resynchronizing);
+        assert_eq!(rx.counters().packet_number_resyncs, 0);
+
+        // Further truncated packet numbers are not trusted
+        rx.frame(receive_response(&[0x00, 0xFF, 0x03, 0x21, 0x45]));
+        assert!(rx.rx_packet_numbers[&gateway_id].resynchronizing);
+
+        // The next full packet number re-baselines
+        rx.frame(receive_response(&[
+            0x00, 0xEE, 0x00, 0x00, 0x03, 0x21, 0x49,
+        ]));
+        assert!(!rx.rx_packet_numbers[&gateway_id].resynchronizing);
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x0003);
+        assert_eq!(rx.counters().packet_number_resyncs, 1);
+
+        // Resume normally
+        rx.frame(receive_request(0x0004));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x04, 0x21, 0x4D]));
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x0004);
+        assert_eq!(rx.counters().packet_number_resyncs, 1);
+        assert_eq!(rx.counters().receive_responses, 6);
+    }
+
+    #[test]
+    fn packet_number_controller_restart() {
+        let mut rx = Receiver::new(TestSink::default());
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let packets = |rx: &Receiver<TestSink>| {
+            rx.sink()
+                .0
+                .iter()
+                .filter(|event| matches!(event, PacketReceived { .. }))
+                .count()
+        };
+
+        rx.frame(receive_request(0x1883));
+        rx.frame(receive_response(&[
+            0x00, 0xFF, 0x83, 0x21, 0x31, 0x31, 0x00, 0x02, 0x00, 0x02, 0x07, 0x01, 0xAA,
+        ]));
+        rx.frame(receive_request(0x1884));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x84, 0x21, 0x35]));
+        assert_eq!(packets(&rx), 1);
+
+        // The controller restarts, advertising a much earlier packet number
+        rx.frame(receive_request(0x0001));
+        assert_eq!(rx.counters().packet_number_resyncs, 1);
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x0001);
+
+        // Truncated packet numbers follow from it, and packets are delivered without waiting for a
+        // full packet number
+        rx.frame(receive_response(&[
+            0x00, 0xFF, 0x01, 0x21, 0x39, 0x31, 0x00, 0x02, 0x00, 0x02, 0x08, 0x01, 0xAA,
+        ]));
+        assert!(!rx.rx_packet_numbers[&gateway_id].resynchronizing);
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x0001);
+        assert_eq!(packets(&rx), 2);
+
+        // Packet numbers which wrap, or which go back by a retransmission, aren't restarts
+        rx.frame(receive_request(0xFFFF));
+        rx.frame(receive_response(&[0x00, 0xFF, 0xFF, 0x21, 0x3D]));
+        rx.frame(receive_request(0x0000));
+        rx.frame(receive_response(&[0x00, 0xFF, 0x00, 0x21, 0x41]));
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x0000);
+        rx.frame(receive_request(0xFFFE));
+        assert_eq!(rx.counters().packet_number_resyncs, 1);
+    }
+
+    #[test]
+    fn packet_number_jump_with_full_packet_number() {
+        let mut rx = Receiver::new(TestSink::default());
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+        rx.frame(receive_request(0x4000));
+        rx.frame(receive_response(&[
+            0x00, 0xEE, 0x00, 0x40, 0x00, 0x21, 0x31,
+        ]));
+
+        // A full packet number jumping backwards is accepted immediately
+        rx.frame(receive_response(&[
+            0x00, 0xEE, 0x00, 0x10, 0x00, 0x21, 0x35,
+        ]));
+        assert_eq!(rx.rx_packet_numbers[&gateway_id].reference, 0x1000);
+        assert_eq!(rx.counters().packet_number_resyncs, 1);
+
+        // Retransmissions of the same packet number are fine
+        rx.frame(receive_response(&[
+            0x00, 0xEE, 0x00, 0x10, 0x00, 0x21, 0x39,
+        ]));
+        assert_eq!(rx.counters().packet_number_resyncs, 1);
+    }
+
     #[test]
     fn enumeration_sequence() {
         // Receive the exchange from the doc
@@ -598,21 +1722,48 @@ mod tests {
                     gateway_id: GatewayID::try_from(0x1235).unwrap(),
                     address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
                 },
+                GatewayIdAssigned {
+                    old: GatewayID::try_from(0x1235).unwrap(),
+                    new: GatewayID::try_from(0x1201).unwrap(),
+                    address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
+                },
                 GatewayIdentityObserved {
                     gateway_id: GatewayID::try_from(0x1201).unwrap(),
                     address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
                 },
+                GatewayIdAssigned {
+                    old: GatewayID::try_from(0x1201).unwrap(),
+                    new: GatewayID::try_from(0x1202).unwrap(),
+                    address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
+                },
                 GatewayIdentityObserved {
                     gateway_id: GatewayID::try_from(0x1202).unwrap(),
                     address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
                 },
+                // These frame types appear during enumeration, but their meaning is unknown
+                InvalidFrame {
+                    frame: Frame {
+                        address: Address::To(GatewayID::ZERO),
+                        frame_type: Type(0x0010),
+                        payload: vec![0x37, 0x24, 0x92, 0x66],
+                    },
+                    reason: InvalidFrameReason::UnknownType
+                },
+                InvalidFrame {
+                    frame: Frame {
+                        address: Address::From(GatewayID::ZERO),
+                        frame_type: Type(0x0011),
+                        payload: vec![],
+                    },
+                    reason: InvalidFrameReason::UnknownType
+                },
                 GatewayIdentityObserved {
                     gateway_id: GatewayID::try_from(0x1201).unwrap(),
                     address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
                 },
                 GatewayVersionObserved {
                     gateway_id: GatewayID::try_from(0x1201).unwrap(),
-                    version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r"
+                    version: "Mgate Version G8.59 / Jul  6 2020 / 16:51:51 / GW-H158.4.3S0.12"
                         .into()
                 },
                 EnumerationEnded {
@@ -623,7 +1774,8 @@ mod tests {
         assert_eq!(
             rx.sink().counters(),
             &Counters {
-                unhandled_frame_type: 2,
+                frames: 37,
+                unhandled_frame_types: 2,
                 ping_requests: 2,
                 ping_responses: 2,
                 enumeration_start_requests: 5,
@@ -642,4 +1794,135 @@ mod tests {
             }
         );
     }
+
+    fn command_request(sequence_number: u8) -> Frame {
+        Frame {
+            address: Address::To(0x1201.try_into().unwrap()),
+            frame_type: Type::COMMAND_REQUEST,
+            payload: vec![
+                0x00,
+                0x01,
+                0x00,
+                PacketType::STRING_REQUEST.0,
+                sequence_number,
+            ],
+        }
+    }
+
+    fn command_response(sequence_number: u8, tx_buffers_free: u8) -> Frame {
+        Frame {
+            address: Address::From(0x1201.try_into().unwrap()),
+            frame_type: Type::COMMAND_RESPONSE,
+            payload: vec![
+                0x00,
+                tx_buffers_free,
+                0x00,
+                PacketType::STRING_RESPONSE.0,
+                sequence_number,
+            ],
+        }
+    }
+
+    #[test]
+    fn command_timeout() {
+        let mut rx = Receiver::new(TestSink::default());
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+        // A command is answered
+        rx.frame(command_request(1));
+        rx.frame(command_response(1, 0x0E));
+
+        // A command is retransmitted, but never answered
+        rx.frame(command_request(2));
+        rx.frame(command_request(2));
+
+        // The controller moves on, and the gateway is out of buffers
+        rx.frame(command_request(3));
+        rx.frame(command_response(3, 0x00));
+
+        assert_eq!(
+            &rx.sink().0,
+            &[
+                GatewayTxBuffersFreeObserved {
+                    gateway_id,
+                    tx_buffers_free: 0x0E,
+                },
+                CommandExecuted {
+                    gateway_id,
+                    request: (PacketType::STRING_REQUEST, vec![]),
+                    response: (PacketType::STRING_RESPONSE, vec![]),
+                },
+                CommandTimedOut {
+                    gateway_id,
+                    packet_type: PacketType::STRING_REQUEST,
+                    sequence_number: CommandSequenceNumber(2),
+                },
+                GatewayTxBuffersFreeObserved {
+                    gateway_id,
+                    tx_buffers_free: 0x00,
+                },
+                CommandExecuted {
+                    gateway_id,
+                    request: (PacketType::STRING_REQUEST, vec![]),
+                    response: (PacketType::STRING_RESPONSE, vec![]),
+                },
+            ]
+        );
+        assert_eq!(rx.counters().command_timeouts, 1);
+        assert_eq!(rx.counters().retransmitted_command_requests, 1);
+        assert_eq!(rx.unanswered_commands(), &BTreeMap::from([(gateway_id, 1)]));
+        assert!(rx.commands_awaiting_response.is_empty());
+    }
+
+    #[test]
+    fn version_normalization() {
+        assert_eq!(
+            normalize_version("Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r"),
+            Some("Mgate Version G8.59 / Jul  6 2020 / 16:51:51 / GW-H158.4.3S0.12".into())
+        );
+        assert_eq!(normalize_version(" a \r\n b "), Some("a / b".into()));
+        assert_eq!(normalize_version("\r\r"), None);
+        assert_eq!(
+            normalize_version("Mgate\x1b[2J"),
+            Some("Mgate\u{FFFD}[2J".into())
+        );
+        assert_eq!(normalize_version("Mgate\0"), Some("Mgate\u{FFFD}".into()));
+
+        let long = "é".repeat(MAX_VERSION_LENGTH);
+        let normalized = normalize_version(&long).unwrap();
+        assert!(normalized.len() <= MAX_VERSION_LENGTH);
+        assert!(long.starts_with(&normalized));
+    }
+
+    #[test]
+    fn lossy_version() {
+        let mut rx = Receiver::new(TestSink::default());
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+        // A stray high byte in an otherwise readable version
+        rx.frame(Frame {
+            address: Address::From(gateway_id),
+            frame_type: Type::VERSION_RESPONSE,
+            payload: b"Mgate Version G8.59\xB0\rJul  6 2020\r".to_vec(),
+        });
+        assert_eq!(
+            rx.sink().0,
+            vec![GatewayVersionObserved {
+                gateway_id,
+                version: "Mgate Version G8.59\u{FFFD} / Jul  6 2020".into()
+            }]
+        );
+        assert_eq!(rx.counters().version_responses, 1);
+        assert_eq!(rx.counters().lossy_version_responses, 1);
+        assert_eq!(rx.counters().invalid_version_responses, 0);
+
+        // Mostly binary
+        rx.frame(Frame {
+            address: Address::From(gateway_id),
+            frame_type: Type::VERSION_RESPONSE,
+            payload: b"M\xB0\xB1\x00\x01".to_vec(),
+        });
+        assert_eq!(rx.counters().version_responses, 1);
+        assert_eq!(rx.counters().invalid_version_responses, 1);
+    }
 }
@@ -0,0 +1,239 @@
+use super::*;
+use crate::gateway::link::{self, Frame, SendError, Sender, Type};
+use crate::gateway::physical::Connection;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+/// How long a `Requester` waits for responses, and how often it tries again.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RequestConfig {
+    /// How long to wait for a response to each transmission.
+    pub timeout: Duration,
+    /// How many times to retransmit a request which received no response.
+    pub retries: u32,
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(500),
+            retries: 2,
+        }
+    }
+}
+
+/// An error making a request.
+#[derive(thiserror::Error, Debug)]
+pub enum RequestError {
+    #[error("the connection is read-only")]
+    ReadOnly,
+    #[error("no response after {attempts} attempts")]
+    TimedOut { attempts: u32 },
+    #[error("the connection closed")]
+    Closed,
+    #[error(transparent)]
+    Send(SendError),
+    #[error("error reading response: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<SendError> for RequestError {
+    fn from(value: SendError) -> Self {
+        match value {
+            SendError::Io(e) if e.kind() == ErrorKind::Unsupported => RequestError::ReadOnly,
+            e => RequestError::Send(e),
+        }
+    }
+}
+
+/// Counters describing a `Requester`'s activity.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequesterCounters {
+    pub requests: u64,
+    pub retransmissions: u64,
+    pub responses: u64,
+    pub timeouts: u64,
+    /// Frames received while awaiting a response which weren't that response.
+    pub unmatched_frames: u64,
+}
+
+/// Sends requests to gateways and awaits their responses, as a controller does.
+///
+/// Most users observe a controller talking to its gateways, and never transmit. A `Requester` is
+/// for running without a controller: it sends one request at a time on a read-write
+/// [`Connection`], retransmits it if no response arrives in time, and returns the matching
+/// response. A read-only connection fails with [`RequestError::ReadOnly`].
+///
+/// Timeouts are checked between reads, so the connection's reads must return periodically, as
+/// serial ports with a read timeout do, rather than blocking until data arrives.
+#[derive(Debug)]
+pub struct Requester<C: Connection> {
+    sender: Sender<C>,
+    receiver: link::Receiver<Vec<Frame>>,
+    sequence_numbers: BTreeMap<GatewayID, CommandSequenceNumber>,
+    config: RequestConfig,
+    counters: RequesterCounters,
+}
+
+impl<C: Connection> Requester<C> {
+    pub fn new(connection: C, config: RequestConfig) -> Self {
+        Self {
+            sender: Sender::new(connection),
+            receiver: link::Receiver::new(Vec::new()),
+            sequence_numbers: Default::default(),
+            config,
+            counters: Default::default(),
+        }
+    }
+
+    /// Access the connection.
+    pub fn connection(&self) -> &C {
+        self.sender.writer()
+    }
+
+    /// Destroy the `Requester` to obtain the connection.
+    pub fn into_inner(self) -> C {
+        self.sender.into_inner()
+    }
+
+    pub fn counters(&self) -> &RequesterCounters {
+        &self.counters
+    }
+
+    /// Ping a gateway.
+    pub fn ping(&mut self, gateway_id: GatewayID) -> Result<(), RequestError> {
+        self.request(&ping_request(gateway_id), |frame| {
+            frame.address == Address::From(gateway_id) && frame.frame_type == Type::PING_RESPONSE
+        })?;
+        Ok(())
+    }
+
+    /// Request a gateway's version string.
+    pub fn version(&mut self, gateway_id: GatewayID) -> Result<Vec<u8>, RequestError> {
+        let response = self.request(&version_request(gateway_id), |frame| {
+            frame.address == Address::From(gateway_id) && frame.frame_type == Type::VERSION_RESPONSE
+        })?;
+        Ok(response.payload)
+    }
+
+    /// Send a command to a gateway, returning the packet type and payload of its response.
+    ///
+    /// Each command to a gateway takes the next command sequence number, and only a response
+    /// bearing that sequence number is accepted. Retransmissions reuse the sequence number, so
+    /// that the gateway can tell them apart from a new command.
+    pub fn command(
+        &mut self,
+        gateway_id: GatewayID,
+        packet_type: PacketType,
+        payload: &[u8],
+    ) -> Result<(PacketType, Vec<u8>), RequestError> {
+        let sequence_number = self.next_sequence_number(gateway_id);
+        let header = CommandRequest::new(packet_type, sequence_number);
+        let request = Frame::from((gateway_id, &header, payload));
+
+        let response = self.request(&request, |frame| {
+            frame.address == Address::From(gateway_id)
+                && frame.frame_type == Type::COMMAND_RESPONSE
+                && CommandResponse::ref_from_prefix(&frame.payload)
+                    .is_ok_and(|(header, _)| header.command_sequence_number == sequence_number)
+        })?;
+
+        let (header, payload) = response.payload.split_at(size_of::<CommandResponse>());
+        let header = CommandResponse::ref_from_bytes(header).unwrap(); // matched above
+        Ok((header.packet_type, payload.to_vec()))
+    }
+
+    fn next_sequence_number(&mut self, gateway_id: GatewayID) -> CommandSequenceNumber {
+        let next = self
+            .sequence_numbers
+            .get(&gateway_id)
+            .map_or(1, |previous| previous.0.wrapping_add(1));
+        self.sequence_numbers
+            .insert(gateway_id, CommandSequenceNumber(next));
+        CommandSequenceNumber(next)
+    }
+
+    /// Send `request` until a frame satisfying `matches` arrives, or the retries are exhausted.
+    pub fn request(
+        &mut self,
+        request: &Frame,
+        mut matches: impl FnMut(&Frame) -> bool,
+    ) -> Result<Frame, RequestError> {
+        self.counters.requests += 1;
+
+        // Anything received before the request can't be its response
+        self.receiver.sink_mut().clear();
+
+        let attempts = 1 + self.config.retries;
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                self.counters.retransmissions += 1;
+            }
+            self.sender.send(request)?;
+
+            if let Some(response) = self.await_response(&mut matches)? {
+                self.counters.responses += 1;
+                return Ok(response);
+            }
+        }
+
+        self.counters.timeouts += 1;
+        Err(RequestError::TimedOut { attempts })
+    }
+
+    /// Wait up to `duration` for any frame to arrive, without sending anything.
+    pub fn listen(&mut self, duration: Duration) -> Result<Option<Frame>, RequestError> {
+        self.receiver.sink_mut().clear();
+        let deadline = Instant::now() + duration;
+        while self.receiver.sink().is_empty() && Instant::now() < deadline {
+            self.read()?;
+        }
+        Ok(std::mem::take(self.receiver.sink_mut()).into_iter().next())
+    }
+
+    fn await_response(
+        &mut self,
+        matches: &mut impl FnMut(&Frame) -> bool,
+    ) -> Result<Option<Frame>, RequestError> {
+        let deadline = Instant::now() + self.config.timeout;
+        loop {
+            // Check what we've received so far
+            for frame in std::mem::take(self.receiver.sink_mut()) {
+                if matches(&frame) {
+                    return Ok(Some(frame));
+                }
+                self.counters.unmatched_frames += 1;
+            }
+
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+
+            self.read()?;
+        }
+    }
+
+    /// Pass whatever the connection has received, if anything, to the receiver.
+    fn read(&mut self) -> Result<(), RequestError> {
+        let mut buffer = [0u8; 256];
+        match self.sender.writer_mut().read(&mut buffer) {
+            Ok(0) => Err(RequestError::Closed),
+            Ok(n) => {
+                self.receiver.extend_from_slice(&buffer[..n]);
+                Ok(())
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -1,13 +1,65 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "parsers")]
 pub mod barcode;
+#[cfg(feature = "parsers")]
 pub mod gateway;
+#[cfg(feature = "parsers")]
 pub mod pv;
+#[cfg(feature = "parsers")]
+pub mod text;
 
+#[cfg(feature = "capture")]
 pub mod capture;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "observer")]
+pub mod console;
 
+#[cfg(feature = "observer")]
+pub mod analyze;
+#[cfg(feature = "observer")]
+pub mod client;
+#[cfg(all(feature = "observer", feature = "tokio"))]
+pub use client::AsyncClient;
+#[cfg(feature = "observer")]
+pub use client::Client;
+#[cfg(feature = "observer")]
+pub mod compare;
+#[cfg(feature = "observer")]
 pub mod config;
+#[cfg(feature = "observer")]
+pub mod control;
+#[cfg(feature = "parsers")]
+mod counters;
+#[cfg(feature = "observer")]
+pub mod diagnostics;
+#[cfg(feature = "parsers")]
+pub use counters::Counters;
+#[cfg(feature = "parsers")]
+mod pipeline;
+#[cfg(feature = "parsers")]
+pub use pipeline::{pipeline, Pipeline};
+#[cfg(feature = "parsers")]
+mod log_limit;
+#[cfg(feature = "parsers")]
+pub mod memory;
+#[cfg(feature = "observer")]
 pub mod observer;
+#[cfg(any(feature = "observer", all(feature = "capture", feature = "parsers")))]
+pub mod output;
+#[cfg(feature = "parsers")]
+pub mod prelude;
+#[cfg(feature = "observer")]
+pub mod run;
+#[cfg(all(feature = "schema", feature = "observer"))]
+pub mod schema;
+#[cfg(feature = "observer")]
+pub mod soak;
+#[cfg(feature = "observer")]
+pub mod testing;
+#[cfg(feature = "observer")]
+pub mod write_behind;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "parsers"))]
 pub mod test_data;
@@ -0,0 +1,46 @@
+//! Logging recurring problems without flooding the log.
+
+use std::collections::BTreeMap;
+
+/// Decides which occurrences of a recurring problem to log.
+///
+/// A bus which produces one malformed frame tends to produce thousands, so the first occurrence of
+/// each kind of problem is logged, then the 10th, the 100th, and so on, each with its count.
+#[derive(Debug, Clone)]
+pub(crate) struct LogLimiter<K: Ord>(BTreeMap<K, u64>);
+
+impl<K: Ord> Default for LogLimiter<K> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<K: Ord> LogLimiter<K> {
+    /// Count an occurrence of `kind`, returning how many there have been if this one should be
+    /// logged.
+    pub fn count(&mut self, kind: K) -> Option<u64> {
+        let count = self.0.entry(kind).or_default();
+        *count += 1;
+        let mut power = 1;
+        while power < *count {
+            power *= 10;
+        }
+        (power == *count).then_some(*count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count() {
+        let mut limiter = LogLimiter::default();
+        let logged: Vec<u64> = (0..1000).filter_map(|_| limiter.count("a")).collect();
+        assert_eq!(logged, [1, 10, 100, 1000]);
+
+        // Each kind is counted separately
+        assert_eq!(limiter.count("b"), Some(1));
+        assert_eq!(limiter.count("a"), None);
+    }
+}
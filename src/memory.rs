@@ -0,0 +1,307 @@
+//! Bounds on, and accounting for, the memory held over long runs.
+//!
+//! An observer runs for months, so any per-node or per-gateway history it keeps must be bounded.
+//! [`BoundedHistory`] holds at most a fixed number of entries, optionally also discarding entries
+//! older than a maximum age. [`MemoryReport`] approximates how many bytes each subsystem holds, so
+//! that growth can be attributed when it happens.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::mem::size_of;
+use std::time::{Duration, SystemTime};
+
+/// A history of at most `capacity` entries, from which the oldest entries are evicted.
+///
+/// A history created [`with_max_age()`](Self::with_max_age) also evicts entries older than its
+/// maximum age as newer entries are pushed, or when [`expire()`](Self::expire) is called.
+#[derive(Debug, Clone)]
+pub struct BoundedHistory<T> {
+    capacity: usize,
+    max_age: Option<Duration>,
+    entries: VecDeque<T>,
+    // The time of each entry, kept only when entries can expire
+    times: VecDeque<SystemTime>,
+    evicted: u64,
+}
+
+impl<T> BoundedHistory<T> {
+    /// Create a history holding at most `capacity` entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a history must be able to hold something");
+        Self {
+            capacity,
+            max_age: None,
+            entries: VecDeque::with_capacity(capacity),
+            times: VecDeque::new(),
+            evicted: 0,
+        }
+    }
+
+    /// Create a history holding at most `capacity` entries, none older than `max_age`.
+    pub fn with_max_age(capacity: usize, max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            times: VecDeque::with_capacity(capacity),
+            ..Self::new(capacity)
+        }
+    }
+
+    /// Add an entry to a history without a maximum age, evicting the oldest entry if full.
+    ///
+    /// Histories with a maximum age need to know when each entry was added, so they take
+    /// [`push_at()`](Self::push_at) instead.
+    pub fn push(&mut self, value: T) {
+        debug_assert!(self.max_age.is_none(), "push() on a history with a max age");
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.evicted += 1;
+        }
+        self.entries.push_back(value);
+    }
+
+    /// Add an entry as of `now`, evicting the oldest entry if full and any entries which have
+    /// expired.
+    pub fn push_at(&mut self, now: SystemTime, value: T) {
+        if self.max_age.is_none() {
+            return self.push(value);
+        }
+        self.expire(now);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.times.pop_front();
+            self.evicted += 1;
+        }
+        self.entries.push_back(value);
+        self.times.push_back(now);
+    }
+
+    /// Evict entries which are older than the maximum age as of `now`.
+    pub fn expire(&mut self, now: SystemTime) {
+        let Some(max_age) = self.max_age else {
+            return;
+        };
+        while let Some(time) = self.times.front() {
+            // Entries from the future (i.e. the clock went backwards) are kept
+            match now.duration_since(*time) {
+                Ok(age) if age > max_age => {
+                    self.times.pop_front();
+                    self.entries.pop_front();
+                    self.evicted += 1;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Discard all entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.times.clear();
+    }
+
+    /// The entries, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    /// The most recent entry.
+    pub fn latest(&self) -> Option<&T> {
+        self.entries.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.len() == self.capacity
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// The number of entries evicted, whether for space or for age.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// The approximate number of bytes this history occupies, including its allocations.
+    ///
+    /// This counts the space reserved for entries rather than the space used, since a history
+    /// which has ever been full retains its allocation.
+    pub fn approximate_bytes(&self) -> usize {
+        size_of::<Self>()
+            + self.entries.capacity() * size_of::<T>()
+            + self.times.capacity() * size_of::<SystemTime>()
+    }
+}
+
+/// The approximate number of bytes occupied by a `BTreeMap<K, V>` with `len` entries, not counting
+/// any allocations owned by the keys or values.
+///
+/// B-tree nodes are kept between half and completely full, so this assumes they average
+/// two-thirds full.
+pub fn btree_map_bytes<K, V>(len: usize) -> usize {
+    size_of::<BTreeMap<K, V>>() + len * (size_of::<K>() + size_of::<V>()) * 3 / 2
+}
+
+/// The approximate memory held by each subsystem, in bytes.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MemoryReport(pub BTreeMap<&'static str, usize>);
+
+impl MemoryReport {
+    /// Capture the memory report of a complete receiver stack.
+    #[cfg(feature = "observer")]
+    pub fn snapshot(
+        rx: &crate::gateway::link::Receiver<
+            crate::gateway::transport::Receiver<
+                crate::pv::application::Receiver<crate::observer::Observer>,
+            >,
+        >,
+    ) -> Self {
+        let mut report = rx.sink().memory_report();
+        report.extend(rx.sink().sink().memory_report());
+        report.extend(rx.sink().sink().sink().memory_report());
+        report
+    }
+
+    /// Account for `bytes` held by `subsystem`, in addition to anything already counted for it.
+    pub fn add(&mut self, subsystem: &'static str, bytes: usize) {
+        *self.0.entry(subsystem).or_default() += bytes;
+    }
+
+    /// Add each subsystem of another report to this one.
+    pub fn extend(&mut self, other: MemoryReport) {
+        for (subsystem, bytes) in other.0 {
+            self.add(subsystem, bytes);
+        }
+    }
+
+    /// The total number of bytes held by all subsystems.
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .0
+            .keys()
+            .map(|name| name.len())
+            .fold("total".len(), usize::max);
+        for (subsystem, bytes) in &self.0 {
+            writeln!(f, "{:width$}  {:>10}", subsystem, bytes, width = width)?;
+        }
+        write!(f, "{:width$}  {:>10}", "total", self.total(), width = width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capacity() {
+        let mut history = BoundedHistory::new(4);
+        let initial_bytes = history.approximate_bytes();
+        for i in 0..10_000u32 {
+            history.push(i);
+            assert!(history.len() <= 4);
+        }
+        assert!(history.is_full());
+        assert_eq!(
+            history.iter().copied().collect::<Vec<_>>(),
+            [9996, 9997, 9998, 9999]
+        );
+        assert_eq!(history.latest(), Some(&9999));
+        assert_eq!(history.evicted(), 9996);
+
+        // Sustained insertion doesn't grow the allocation
+        assert_eq!(history.approximate_bytes(), initial_bytes);
+
+        history.clear();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn max_age() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+        let mut history = BoundedHistory::with_max_age(100, Duration::from_secs(60));
+        let initial_bytes = history.approximate_bytes();
+
+        // One entry per second keeps the last minute
+        for i in 0..10_000u64 {
+            history.push_at(t + Duration::from_secs(i), i);
+            assert!(history.len() <= 61);
+        }
+        assert_eq!(history.iter().next(), Some(&(9999 - 60)));
+        assert_eq!(history.approximate_bytes(), initial_bytes);
+
+        // Ten entries per second are limited by capacity instead
+        let t = t + Duration::from_secs(20_000);
+        for i in 0..10_000u64 {
+            history.push_at(t + Duration::from_millis(i * 100), i);
+            assert!(history.len() <= 100);
+        }
+        assert!(history.is_full());
+        assert_eq!(history.approximate_bytes(), initial_bytes);
+
+        // Expiring doesn't require a push
+        history.expire(t + Duration::from_secs(2000));
+        assert!(history.is_empty());
+
+        // A clock going backwards doesn't expire anything
+        history.push_at(t, 1);
+        history.expire(t - Duration::from_secs(3600));
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "observer")]
+    fn snapshot() {
+        let mut rx = crate::gateway::link::Receiver::new(crate::gateway::transport::Receiver::new(
+            crate::pv::application::Receiver::new(crate::observer::Observer::default()),
+        ));
+        let empty = MemoryReport::snapshot(&rx);
+        assert!(empty.0.contains_key("transport.commands_awaiting_response"));
+        assert!(empty.0.contains_key("application.packet_loss"));
+        assert!(empty.0.contains_key("observer.node_tables"));
+
+        // Replaying the same traffic doesn't accumulate anything
+        rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+        let once = MemoryReport::snapshot(&rx);
+        assert!(once.total() > empty.total());
+        for _ in 0..10 {
+            rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+        }
+        assert_eq!(MemoryReport::snapshot(&rx), once);
+    }
+
+    #[test]
+    fn report() {
+        let mut report = MemoryReport::default();
+        report.add("a", 10);
+        report.add("bb", 20);
+        report.extend(MemoryReport([("a", 5)].into_iter().collect()));
+        assert_eq!(report.0["a"], 15);
+        assert_eq!(report.total(), 35);
+        assert_eq!(
+            report.to_string(),
+            "a              15\nbb             20\ntotal          35"
+        );
+    }
+}
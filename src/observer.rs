@@ -14,35 +14,138 @@
 //! └───┘  └───┘
 //! ```
 
-use crate::gateway::link::GatewayID;
-use crate::pv::application::{NodeTableResponseEntry, TopologyReport};
+use crate::gateway::link::{gateway_id_keys, GatewayID};
+use crate::memory::{btree_map_bytes, MemoryReport};
+use crate::pv::application::{
+    strings, Broadcast, NetworkStatusResponse, NodeTableResponseEntry, PvConfiguration,
+    PvConfigurationResponse, RadioConfiguration, RadioConfigurationResponse, TopologyReport,
+};
 use crate::pv::link::SlotCounter;
 use crate::pv::network::{NodeAddress, ReceivedPacketHeader};
 use crate::pv::{LongAddress, NodeID, PacketType};
+use crate::text::LossyStr;
 use crate::{gateway, pv};
-use schemars::JsonSchema;
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use std::collections::btree_map::Entry;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 use std::time::SystemTime;
 
+pub mod alerts;
+use alerts::AlertTracker;
+
+mod array_sleep;
+use array_sleep::ArraySleepTracker;
+
+pub mod calibration;
+use calibration::Calibration;
+
+pub mod clock;
+use clock::{Clock, SystemClock};
+
+pub mod config;
+pub use config::Config;
+
+mod daily_summary;
+use daily_summary::DailySummaries;
+
+pub mod diagnostic;
+
+mod energy;
+use energy::EnergyAccumulators;
+
+mod gateway_status;
+use gateway_status::GatewayStatusTracker;
+
+mod string_queries;
+use string_queries::PendingQueries;
+
+mod early_reports;
+use early_reports::{EarlyReport, EarlyReports};
+
 pub mod event;
+pub mod health;
+pub mod invalid_dump;
+pub mod invalid_frame;
+pub mod journal;
+pub mod rate_limit;
+pub mod routing;
+pub mod shared;
+pub mod state_file;
+use event::{DiagnosticEvent, Event};
+use invalid_dump::InvalidDump;
+use invalid_frame::{InvalidFrameLog, InvalidFrameRecord};
+use rate_limit::{Admission, RateLimiter};
+
+mod home_gateway;
+use home_gateway::{Confirmation, HomeGateways};
+
+mod node_gaps;
+use node_gaps::LastReports;
+
+mod node_configurations;
+use node_configurations::NodeConfigurations;
+
+mod node_inventory;
+use node_inventory::NodeInventory;
+
+pub mod node_resolver;
 
 mod node_table;
 use node_table::{NodeTable, NodeTableBuilder};
 
-mod slot_clock;
-use slot_clock::SlotClock;
+mod node_validation;
+use node_validation::NodeValidation;
+
+pub mod provenance;
+use provenance::{Provenance, ProvenanceTable};
+
+pub mod snapshot;
+use snapshot::{NetworkStatus, SystemSnapshot, TopologyTable};
+
+pub mod slot_clock;
+use slot_clock::{SlotClock, SlotClockCalibrations};
 
 /// An observer, monitoring a controller interacting with one or more TAPs via an RS-485 interface.
 #[derive(Debug)]
 pub struct Observer {
+    config: Config,
+    clock: Box<dyn Clock>,
     persistent_state: PersistentState,
+    calibration: Calibration,
 
     enumeration_state: Option<EnumerationState>,
     captured_slot_counters: BTreeMap<GatewayID, SystemTime>,
     slot_clocks: BTreeMap<GatewayID, SlotClock>,
+    /// Gateways whose slot clocks were restored from the persistent state, and which have not yet
+    /// been checked against an observation.
+    restored_slot_clocks: BTreeSet<GatewayID>,
     node_table_builders: BTreeMap<GatewayID, NodeTableBuilder>,
+    unknown_identities_reported: BTreeSet<GatewayID>,
+    tx_buffers_exhausted: BTreeMap<GatewayID, u32>,
+    lossy_nodes: BTreeSet<(GatewayID, NodeID)>,
+    array_sleep: ArraySleepTracker,
+    alerts: AlertTracker,
+    topology: TopologyTable,
+    network_status: BTreeMap<GatewayID, NetworkStatus>,
+    gateway_status: GatewayStatusTracker,
+    pending_queries: PendingQueries,
+    early_reports: EarlyReports,
+    home_moves: BTreeMap<LongAddress, Vec<SystemTime>>,
+    flapping_nodes: BTreeSet<LongAddress>,
+    node_validation: NodeValidation,
+
+    event_sink: Option<Box<dyn EventSink>>,
+    diagnostics: diagnostic::Output,
+    invalid_frame_log: Option<InvalidFrameLog>,
+    invalid_dump: Option<InvalidDump>,
+    state_file: Option<state_file::StateFile>,
+    journal: Option<journal::Journal>,
+    rate_limiter: RateLimiter,
+    counters: Counters,
+    /// The source whose traffic this observer handles, when several share outputs; see [`shared`].
+    source: Option<Arc<str>>,
 }
 
 impl Default for Observer {
@@ -54,11 +157,37 @@ impl Default for Observer {
 impl Observer {
     pub fn from_persistent_state(persistent_state: PersistentState) -> Self {
         Observer {
+            config: Default::default(),
+            clock: Box::new(SystemClock),
             persistent_state,
+            calibration: Default::default(),
             enumeration_state: None,
             captured_slot_counters: Default::default(),
             slot_clocks: Default::default(),
+            restored_slot_clocks: Default::default(),
             node_table_builders: Default::default(),
+            unknown_identities_reported: Default::default(),
+            tx_buffers_exhausted: Default::default(),
+            lossy_nodes: Default::default(),
+            array_sleep: Default::default(),
+            alerts: Default::default(),
+            topology: Default::default(),
+            network_status: Default::default(),
+            gateway_status: Default::default(),
+            pending_queries: Default::default(),
+            early_reports: Default::default(),
+            home_moves: Default::default(),
+            flapping_nodes: Default::default(),
+            node_validation: Default::default(),
+            event_sink: None,
+            diagnostics: Default::default(),
+            invalid_frame_log: None,
+            invalid_dump: None,
+            state_file: None,
+            journal: None,
+            rate_limiter: Default::default(),
+            counters: Default::default(),
+            source: None,
         }
     }
 
@@ -66,9 +195,770 @@ impl Observer {
         &self.persistent_state
     }
 
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.counters = Default::default();
+    }
+
+    /// Approximate the memory held by each of the observer's subsystems.
+    pub fn memory_report(&self) -> MemoryReport {
+        let state = &self.persistent_state;
+        let mut report = MemoryReport::default();
+        report.add(
+            "observer.node_tables",
+            btree_map_bytes::<GatewayID, NodeTable>(state.gateway_node_tables.len())
+                + state
+                    .gateway_node_tables
+                    .values()
+                    .map(NodeTable::approximate_bytes)
+                    .sum::<usize>()
+                + btree_map_bytes::<GatewayID, NodeTableBuilder>(self.node_table_builders.len())
+                + self
+                    .node_table_builders
+                    .values()
+                    .map(NodeTableBuilder::approximate_bytes)
+                    .sum::<usize>(),
+        );
+        report.add(
+            "observer.gateways",
+            btree_map_bytes::<GatewayID, LongAddress>(state.gateway_identities.len())
+                + btree_map_bytes::<GatewayID, String>(state.gateway_versions.len())
+                + btree_map_bytes::<GatewayID, RadioConfiguration>(
+                    state.gateway_radio_configurations.len(),
+                )
+                + state
+                    .gateway_versions
+                    .values()
+                    .map(String::capacity)
+                    .sum::<usize>()
+                + btree_map_bytes::<GatewayID, SystemTime>(self.captured_slot_counters.len())
+                + btree_map_bytes::<GatewayID, SlotClock>(self.slot_clocks.len())
+                + btree_map_bytes::<GatewayID, NetworkStatus>(self.network_status.len())
+                + self.gateway_status.approximate_bytes()
+                + btree_map_bytes::<LongAddress, slot_clock::Calibration>(
+                    state.slot_clock_calibrations.0.len(),
+                )
+                + btree_map_bytes::<GatewayID, slot_clock::Anchor>(state.slot_clock_anchors.len())
+                + self.early_reports.approximate_bytes(),
+        );
+        report.add(
+            "observer.daily_summaries",
+            state.daily_summaries.approximate_bytes(),
+        );
+        report.add("observer.energy", state.energy.approximate_bytes());
+        report.add(
+            "observer.last_reports",
+            state.last_reports.approximate_bytes(),
+        );
+        report.add("observer.provenance", state.provenance.approximate_bytes());
+        report.add(
+            "observer.node_inventory",
+            state.node_inventory.approximate_bytes() + self.pending_queries.approximate_bytes(),
+        );
+        report.add(
+            "observer.node_configurations",
+            state.node_configurations.approximate_bytes(),
+        );
+        report.add(
+            "observer.diagnostics",
+            btree_map_bytes::<GatewayID, ()>(self.unknown_identities_reported.len())
+                + btree_map_bytes::<GatewayID, u32>(self.tx_buffers_exhausted.len())
+                + btree_map_bytes::<(GatewayID, NodeID), ()>(self.lossy_nodes.len())
+                + self.node_validation.approximate_bytes(),
+        );
+        report.add(
+            "observer.home_gateways",
+            state.home_gateways.approximate_bytes()
+                + btree_map_bytes::<LongAddress, Vec<SystemTime>>(self.home_moves.len())
+                + self
+                    .home_moves
+                    .values()
+                    .map(|moves| moves.capacity() * std::mem::size_of::<SystemTime>())
+                    .sum::<usize>()
+                + btree_map_bytes::<LongAddress, ()>(self.flapping_nodes.len()),
+        );
+        report.add("observer.array_sleep", self.array_sleep.approximate_bytes());
+        report.add("observer.alerts", self.alerts.approximate_bytes());
+        report.add("observer.topology", self.topology.approximate_bytes());
+        report.add(
+            "observer.rate_limiter",
+            self.rate_limiter.approximate_bytes(),
+        );
+        report
+    }
+
+    /// Describe every gateway and node the observer knows of, and the mesh between them.
+    pub fn snapshot(&self) -> SystemSnapshot {
+        let state = &self.persistent_state;
+        let gateways: BTreeSet<GatewayID> = state
+            .gateway_identities
+            .keys()
+            .chain(state.gateway_versions.keys())
+            .chain(state.gateway_node_tables.keys())
+            .chain(self.slot_clocks.keys())
+            .chain(self.network_status.keys())
+            .copied()
+            .chain(self.topology.keys().map(|(gateway_id, _)| gateway_id))
+            .collect();
+        let nodes: BTreeSet<(GatewayID, NodeID)> = state
+            .gateway_node_tables
+            .iter()
+            .flat_map(|(gateway_id, table)| table.0.keys().map(|node_id| (*gateway_id, *node_id)))
+            .chain(self.topology.keys())
+            .collect();
+
+        SystemSnapshot {
+            timestamp: self.clock.now().into(),
+            gateways: gateways
+                .into_iter()
+                .map(|id| snapshot::GatewaySnapshot {
+                    id,
+                    address: state.gateway_identities.get(&id).copied(),
+                    version: state.gateway_versions.get(&id).cloned(),
+                    network_status: self.network_status.get(&id).copied(),
+                    source: self.source.as_deref().map(Into::into),
+                })
+                .collect(),
+            nodes: nodes
+                .into_iter()
+                .map(|(gateway_id, node_id)| {
+                    snapshot::NodeSnapshot::new(
+                        gateway_id,
+                        node_id,
+                        state
+                            .gateway_node_tables
+                            .get(&gateway_id)
+                            .and_then(|table| table.0.get(&node_id))
+                            .copied(),
+                        self.topology.get(gateway_id, node_id).copied(),
+                        &state.node_inventory,
+                        self.source.as_deref(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The current model of a gateway's slot clock, if its slot counter has been observed.
+    pub fn slot_clock(&self, gateway_id: GatewayID) -> Option<slot_clock::Parameters> {
+        self.slot_clocks.get(&gateway_id).map(SlotClock::parameters)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Correct subsequent power reports using a given calibration, replacing any previous one.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Use a given clock in place of the system clock.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Shut down the observer, emitting anything which would otherwise be lost.
+    ///
+    /// Daily summaries for days in progress are emitted as partial summaries. Their accumulators
+    /// remain in the persistent state, so that a subsequent observer can complete them. The
+    /// persistent state is then saved to the state file and checkpointed to the journal, if there
+    /// are any.
+    pub fn shutdown(&mut self) {
+        self.emit_partial_summaries();
+        if let Some(state_file) = &mut self.state_file {
+            state_file.save(&self.persistent_state, std::time::Instant::now());
+        }
+        if let Some(journal) = &mut self.journal {
+            let timestamp = self.clock.now().into();
+            if let Err(e) =
+                journal.checkpoint(&self.persistent_state, timestamp, std::time::Instant::now())
+            {
+                log::error!("error writing journal {:?}: {}", journal.dir(), e);
+            }
+        }
+    }
+
+    /// Emit daily summaries for days in progress as partial summaries.
+    fn emit_partial_summaries(&mut self) {
+        if self.config.daily_summaries {
+            for summary in self.persistent_state.daily_summaries.partial() {
+                self.emit(Event::DailySummary(summary));
+            }
+        }
+    }
+
+    /// Create an observer with no persistent state, delivering events to a given sink.
+    pub fn with_event_sink(sink: impl EventSink + 'static) -> Self {
+        let mut observer = Self::default();
+        observer.set_event_sink(sink);
+        observer
+    }
+
+    /// Deliver events to a given sink.
+    ///
+    /// By default, events are written to standard output as JSON, as by [`StdoutJsonSink`]. Diagnostics are routed
+    /// separately, using `set_diagnostics_output()`.
+    pub fn set_event_sink(&mut self, sink: impl EventSink + 'static) {
+        self.event_sink = Some(Box::new(sink));
+    }
+
+    /// Route diagnostics to a given destination.
+    ///
+    /// By default, diagnostics are written to the `log` crate.
+    pub fn set_diagnostics_output(&mut self, output: diagnostic::Output) {
+        self.diagnostics = output;
+    }
+
+    /// Write each frame which the transport layer could not interpret to a given destination.
+    ///
+    /// Invalid frames are always counted by reason; by default, they are not otherwise kept.
+    pub fn set_invalid_frame_log(&mut self, log: InvalidFrameLog) {
+        self.invalid_frame_log = Some(log);
+    }
+
+    /// Write each frame or packet which could not be interpreted to a file of its own, along with
+    /// the raw bytes which preceded it.
+    ///
+    /// Raw bytes must be passed to [`bytes_received()`](Self::bytes_received) for the files to
+    /// have that context.
+    pub fn set_invalid_dump(&mut self, dump: InvalidDump) {
+        self.invalid_dump = Some(dump);
+    }
+
+    /// Note raw bytes received from the bus, just before passing them to the receiver stack.
+    ///
+    /// This is only needed for the context in an [`InvalidDump`].
+    pub fn bytes_received(&mut self, bytes: &[u8]) {
+        if let Some(dump) = self.invalid_dump.as_mut() {
+            dump.bytes_received(self.source.as_ref(), bytes);
+        }
+    }
+
+    /// Save the persistent state to a given file, as of [`save_state_if_due()`] and [`shutdown()`].
+    ///
+    /// The observer doesn't load the file; pass [`StateFile::load()`] to
+    /// [`from_persistent_state()`] for that.
+    ///
+    /// [`save_state_if_due()`]: Self::save_state_if_due
+    /// [`shutdown()`]: Self::shutdown
+    /// [`StateFile::load()`]: state_file::StateFile::load
+    /// [`from_persistent_state()`]: Self::from_persistent_state
+    pub fn set_state_file(&mut self, state_file: state_file::StateFile) {
+        self.state_file = Some(state_file);
+    }
+
+    /// Append every event to a given journal, checkpointing the persistent state to it as of
+    /// [`save_state_if_due()`] and [`shutdown()`].
+    ///
+    /// Like the state file, the observer doesn't load the journal's checkpoints; pass
+    /// [`journal::last_checkpoint()`] to [`from_persistent_state()`] for that.
+    ///
+    /// [`save_state_if_due()`]: Self::save_state_if_due
+    /// [`shutdown()`]: Self::shutdown
+    /// [`from_persistent_state()`]: Self::from_persistent_state
+    pub fn set_journal(&mut self, journal: journal::Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Save the persistent state to the state file if a node table has changed, or if it hasn't
+    /// been saved recently, and checkpoint it to the journal if a checkpoint is due. Call this
+    /// regularly.
+    pub fn save_state_if_due(&mut self, now: std::time::Instant) {
+        if let Some(state_file) = &mut self.state_file {
+            state_file.save_if_due(&self.persistent_state, now);
+        }
+        if let Some(journal) = &mut self.journal {
+            let timestamp = self.clock.now().into();
+            if let Err(e) = journal.checkpoint_if_due(&self.persistent_state, timestamp, now) {
+                log::error!("error writing journal {:?}: {}", journal.dir(), e);
+            }
+        }
+    }
+
+    /// Emit whatever is due with the passage of time, rather than prompted by traffic. Call this
+    /// regularly, such as every second.
+    ///
+    /// Time is measured by the observer's clock, so that a replay is judged as of the capture.
+    pub fn tick(&mut self) {
+        self.update_gateway_status();
+
+        let expired = self.pending_queries.expire(self.clock.now());
+        if expired > 0 {
+            log::debug!("{} string requests went unanswered", expired);
+        }
+
+        for (gateway_id, dropped) in self.early_reports.expire(self.clock.now()) {
+            self.early_reports_dropped(gateway_id, dropped);
+        }
+    }
+
+    /// Report that the timestamps of a capture being replayed stepped backwards.
+    ///
+    /// `smoothed` indicates whether the replay clock is absorbing the step, rather than passing
+    /// it through to event timestamps.
+    pub fn capture_clock_stepped(&mut self, step: &clock::ClockStep, smoothed: bool) {
+        let magnitude = step.magnitude();
+        self.diagnostic(
+            DiagnosticEvent::new(
+                diagnostic::Severity::Warning,
+                diagnostic::Code::CaptureClockStepped,
+                format!(
+                    "capture timestamps stepped backwards by {:.3}s, from {} to {}; {}",
+                    magnitude.as_secs_f64(),
+                    self.config.time_zone.datetime(step.from.into()),
+                    self.config.time_zone.datetime(step.to.into()),
+                    if smoothed {
+                        "smoothing event timestamps to keep them in order"
+                    } else {
+                        "event timestamps will step backwards too"
+                    }
+                ),
+            )
+            .with_context("step_seconds", magnitude.as_secs_f64())
+            .with_context("smoothed", smoothed),
+        );
+    }
+
+    /// Emit a power report, along with any daily summary it completes.
+    fn accept_power_report(&mut self, mut event: event::PowerReportEvent) {
+        if self.config.validate_node_tables {
+            self.validate_node(&mut event);
+        }
+        self.calibration.apply(&mut event);
+
+        if let Some(address) = event.node.address {
+            if !self.confirm_home_gateway(address, event.gateway.id) {
+                self.counters.duplicate_power_reports += 1;
+                return;
+            }
+            event.node.home_gateway = self.persistent_state.home_gateways.get(&address);
+        }
+
+        if self.config.daily_summaries {
+            self.roll_over_daily_summaries();
+            if let Some(summary) = self
+                .persistent_state
+                .daily_summaries
+                .push(&event, self.config.time_zone)
+            {
+                self.emit(Event::DailySummary(summary));
+            }
+        }
+
+        if self.config.energy {
+            event.energy_wh_today = self
+                .persistent_state
+                .energy
+                .push(&event, self.config.time_zone);
+        }
+
+        let since_previous = self.persistent_state.last_reports.push(&event);
+        event.seconds_since_previous_report =
+            since_previous.map(|elapsed| elapsed.num_milliseconds() as f64 / 1000.0);
+        if let Some(gap_seconds) = event.seconds_since_previous_report {
+            if gap_seconds > self.config.node_gaps.threshold_secs as f64 {
+                self.emit(Event::NodeGap(event::NodeGapEvent {
+                    gateway: event.gateway,
+                    node: event.node,
+                    timestamp: event.timestamp,
+                    gap_seconds,
+                    source: None,
+                }));
+            }
+        }
+
+        if self.config.array_sleep.is_some() {
+            self.array_sleep
+                .report(event.gateway.id, event.node.id, self.clock.now());
+            self.update_array_sleep();
+        }
+
+        if !self.config.alerts.is_empty() {
+            self.alerts.report(&event, self.clock.now());
+        }
+
+        self.emit(Event::PowerReport(event));
+        self.update_alerts();
+    }
+
+    /// Check a power report's node ID against its gateway's node table, if the table is known.
+    fn validate_node(&mut self, event: &mut event::PowerReportEvent) {
+        let gateway_id = event.gateway.id;
+        let Some(node_table) = self.persistent_state.gateway_node_tables.get(&gateway_id) else {
+            return;
+        };
+
+        let verified = node_table.0.contains_key(&event.node.id);
+        if !verified {
+            event.node_unverified = true;
+            *self
+                .counters
+                .unverified_power_reports
+                .entry(gateway_id)
+                .or_default() += 1;
+        }
+
+        if let Some(stale) = self.node_validation.report(gateway_id, verified) {
+            self.diagnostic(
+                DiagnosticEvent::new(
+                    diagnostic::Severity::Warning,
+                    diagnostic::Code::NodeTableStale,
+                    format!(
+                        "{} of {} power reports from gateway {:?} came from nodes missing from its \
+                         node table, which may be stale",
+                        stale.unverified, stale.reports, gateway_id
+                    ),
+                )
+                .with_gateway(event.gateway)
+                .with_context("unverified_reports", stale.unverified)
+                .with_context("reports", stale.reports),
+            );
+        }
+    }
+
+    /// Note that a gateway reported a node, returning whether its report should be accepted.
+    fn confirm_home_gateway(&mut self, address: LongAddress, gateway_id: GatewayID) -> bool {
+        let now = self.clock.now();
+        let confirmation = self.persistent_state.home_gateways.confirm(
+            address,
+            gateway_id,
+            now.into(),
+            self.config.duplicate_addresses,
+        );
+
+        // Forget moves which are no longer recent
+        let moves = self.home_moves.entry(address).or_default();
+        moves.retain(|time| now.duration_since(*time).unwrap_or_default() < HOME_FLAP_WINDOW);
+
+        let from = match confirmation {
+            Confirmation::Home => {
+                if moves.is_empty() {
+                    self.home_moves.remove(&address);
+                    self.flapping_nodes.remove(&address);
+                }
+                return true;
+            }
+            Confirmation::Away { .. } => return false,
+            Confirmation::Moved { from } => from,
+        };
+
+        moves.push(now);
+        if moves.len() < HOME_FLAP_THRESHOLD || !self.flapping_nodes.insert(address) {
+            return true;
+        }
+
+        self.diagnostic(
+            DiagnosticEvent::new(
+                diagnostic::Severity::Warning,
+                diagnostic::Code::NodeGatewayFlapping,
+                format!(
+                    "node {} has moved between gateways {} times in {} minutes, most recently from {:?} to {:?}",
+                    address,
+                    HOME_FLAP_THRESHOLD,
+                    HOME_FLAP_WINDOW.as_secs() / 60,
+                    from,
+                    gateway_id
+                ),
+            )
+            .with_gateway(self.gateway(gateway_id))
+            .with_context("previous_gateway", serde_json::to_value(from).unwrap()),
+        );
+        true
+    }
+
+    /// Emit an event if the array has fallen asleep or woken up.
+    fn update_array_sleep(&mut self) {
+        let Some(thresholds) = self.config.array_sleep else {
+            return;
+        };
+
+        let state = match self.persistent_state.asleep_since {
+            Some(_) => event::ArrayState::Asleep,
+            None => event::ArrayState::Awake,
+        };
+        let now = self.clock.now();
+        let Some(transition) = self.array_sleep.update(&thresholds, state, now) else {
+            return;
+        };
+
+        let event = event::ArrayStateEvent {
+            timestamp: now.into(),
+            state: transition.state,
+            reporting_nodes: transition.reporting_nodes,
+            known_nodes: transition.known_nodes,
+            source: None,
+        };
+        match transition.state {
+            event::ArrayState::Asleep => {
+                self.persistent_state.asleep_since = Some(event.timestamp);
+                self.emit(Event::ArrayAsleep(event));
+            }
+            event::ArrayState::Awake => {
+                self.persistent_state.asleep_since = None;
+                self.emit(Event::ArrayWake(event));
+            }
+        }
+    }
+
+    /// Emit each gateway's status, if it has changed or is due to be repeated.
+    fn update_gateway_status(&mut self) {
+        let Some(thresholds) = self.config.gateway_status else {
+            return;
+        };
+
+        let now = self.clock.now();
+        for gateway_id in self.persistent_state.gateway_identities.keys() {
+            self.gateway_status.known(*gateway_id, now);
+        }
+        for (gateway_id, status, activity) in self.gateway_status.update(&thresholds, now) {
+            let event = event::GatewayStatusEvent {
+                timestamp: now.into(),
+                gateway: self.gateway(gateway_id),
+                status,
+                last_response: activity.last_response.map(Into::into),
+                last_slot_counter: activity.last_slot_counter.map(Into::into),
+                receive_responses: activity.receive_responses,
+                source: None,
+            };
+            self.emit(Event::GatewayStatus(event));
+        }
+    }
+
+    /// Emit any alerts raised or cleared.
+    fn update_alerts(&mut self) {
+        if self.config.alerts.is_empty() {
+            return;
+        }
+
+        let now = self.clock.now();
+        for event in self.alerts.update(&self.config.alerts, now) {
+            self.emit(event);
+        }
+    }
+
+    fn emit(&mut self, mut event: Event) {
+        if !self.admit(&event) {
+            return;
+        }
+        if let Some(source) = &self.source {
+            event.set_source(source);
+        }
+
+        if !matches!(event, Event::Diagnostic(_)) {
+            self.persistent_state.last_event = Some(self.clock.now().into());
+        }
+
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.event(&event) {
+                log::error!("error writing journal {:?}: {}", journal.dir(), e);
+            }
+        }
+
+        match (event, self.event_sink.as_mut()) {
+            (Event::Diagnostic(diagnostic), _)
+                if !matches!(self.diagnostics, diagnostic::Output::Events) =>
+            {
+                self.diagnostics.write(&diagnostic);
+            }
+            (event, Some(sink)) => {
+                sink.event(event);
+            }
+            (event, None) => {
+                StdoutJsonSink.event(event);
+            }
+        }
+    }
+
+    /// Apply rate limits to an event, returning whether it should be emitted.
+    fn admit(&mut self, event: &Event) -> bool {
+        let node = match event {
+            // Alert rules limit their own frequency, and broadcasts are rare but may be shutdowns
+            Event::Diagnostic(_)
+            | Event::Alert(_)
+            | Event::AlertCleared(_)
+            | Event::Broadcast(_) => return true,
+            Event::PowerReport(event) => Some((event.gateway.id, event.node.id)),
+            Event::DailySummary(event) => Some((event.gateway.id, event.node.id)),
+            Event::NodeIdentity(event) => Some((event.gateway.id, event.node.id)),
+            Event::NodeConfiguration(event) => Some((event.gateway.id, event.node.id)),
+            Event::NodeDiagnostic(event) => Some((event.gateway.id, event.node.id)),
+            Event::NodeGap(event) => Some((event.gateway.id, event.node.id)),
+            Event::NodeTableProgress(_)
+            | Event::NodeTable(_)
+            | Event::NodeTableChanged(_)
+            | Event::CommandTimeout(_)
+            | Event::ArrayAsleep(_)
+            | Event::ArrayWake(_)
+            | Event::NetworkStatus(_)
+            | Event::SlotClockUpdated(_)
+            | Event::GatewayStatus(_) => None,
+        };
+
+        let now = self.clock.now();
+        let Admission::Dropped { scope, engaged } =
+            self.rate_limiter.admit(&self.config.rate_limits, node, now)
+        else {
+            return true;
+        };
+
+        let diagnostic = match scope {
+            rate_limit::Scope::Global => {
+                self.counters.events_dropped_by_global_limit += 1;
+                DiagnosticEvent::new(
+                    diagnostic::Severity::Warning,
+                    diagnostic::Code::EventsRateLimited,
+                    "dropping events due to the global rate limit",
+                )
+                .with_context("scope", "global")
+            }
+            rate_limit::Scope::Node(gateway_id, node_id) => {
+                self.counters.events_dropped_by_node_limit += 1;
+                DiagnosticEvent::new(
+                    diagnostic::Severity::Warning,
+                    diagnostic::Code::EventsRateLimited,
+                    format!(
+                        "dropping events from gateway {:?} node {:?} due to the per-node rate limit",
+                        gateway_id, node_id
+                    ),
+                )
+                .with_gateway(self.gateway(gateway_id))
+                .with_node(self.node(gateway_id, node_id))
+                .with_context("scope", "node")
+            }
+        };
+
+        if engaged {
+            self.diagnostic(diagnostic);
+        }
+        false
+    }
+
+    /// Emit summaries for every day which has ended.
+    fn roll_over_daily_summaries(&mut self) {
+        if !self.config.daily_summaries {
+            return;
+        }
+
+        let now = DateTime::<Local>::from(self.clock.now());
+        let today = self.config.time_zone.date(now);
+        for summary in self.persistent_state.daily_summaries.roll_over(today) {
+            self.emit(Event::DailySummary(summary));
+        }
+    }
+
+    fn diagnostic(&mut self, mut diagnostic: DiagnosticEvent) {
+        // Stamp diagnostics by the observer's clock, which is the capture's clock during a replay
+        diagnostic.timestamp = self.clock.now().into();
+        self.emit(Event::Diagnostic(diagnostic));
+    }
+
+    /// Start a gateway's slot clock from its latest observation before a restart, if it has no
+    /// slot clock yet and the observation is recent enough.
+    ///
+    /// The restored clock is checked against the next observation of the slot counter.
+    fn restore_slot_clock(&mut self, gateway_id: GatewayID) {
+        if self.config.slot_clock_restore.max_age_secs == 0
+            || self.slot_clocks.contains_key(&gateway_id)
+        {
+            return;
+        }
+        let Some(anchor) = self.persistent_state.slot_clock_anchor(gateway_id).copied() else {
+            return;
+        };
+        let max_age = std::time::Duration::from_secs(self.config.slot_clock_restore.max_age_secs);
+        match self.clock.now().duration_since(anchor.time.into()) {
+            Ok(age) if age <= max_age => {}
+            _ => return,
+        }
+
+        let calibration = self.persistent_state.slot_clock_calibration(gateway_id);
+        let Ok(clock) = SlotClock::with_calibration(
+            SlotCounter::from(anchor.slot_counter),
+            anchor.time.into(),
+            calibration,
+        ) else {
+            return;
+        };
+        log::info!(
+            "restored gateway {:?} slot clock from {:?}",
+            gateway_id,
+            anchor.time
+        );
+        self.slot_clocks.insert(gateway_id, clock);
+        self.restored_slot_clocks.insert(gateway_id);
+    }
+
+    /// Note that a gateway was seen in traffic, reporting if its identity is unknown.
+    fn gateway_seen(&mut self, gateway_id: GatewayID) {
+        if self.enumeration_state.is_some()
+            || self
+                .persistent_state
+                .gateway_identities
+                .contains_key(&gateway_id)
+            || !self.unknown_identities_reported.insert(gateway_id)
+        {
+            return;
+        }
+
+        let last_enumeration = self.persistent_state.last_enumeration;
+        self.diagnostic(
+            DiagnosticEvent::new(
+                diagnostic::Severity::Warning,
+                diagnostic::Code::GatewayIdentityUnknown,
+                match last_enumeration {
+                    Some(time) => format!(
+                        "gateway {:?} has an unknown identity; the last enumeration was observed at {}",
+                        gateway_id,
+                        self.config.time_zone.datetime(time)
+                    ),
+                    None => format!(
+                        "gateway {:?} has an unknown identity; no enumeration has been observed",
+                        gateway_id
+                    ),
+                },
+            )
+            .with_gateway(self.gateway(gateway_id))
+            .with_context(
+                "last_enumeration",
+                serde_json::to_value(last_enumeration).unwrap(),
+            ),
+        );
+    }
+
+    /// The capabilities of a gateway, as configured or as implied by its firmware version.
+    fn capabilities(&self, id: GatewayID) -> gateway::GatewayCapabilities {
+        if let Some(capabilities) = self.config.gateway_capabilities.get(&id) {
+            return *capabilities;
+        }
+        self.persistent_state
+            .gateway_versions
+            .get(&id)
+            .map(|version| gateway::GatewayCapabilities::from_version(version))
+            .unwrap_or_default()
+    }
+
     fn gateway(&self, id: GatewayID) -> event::Gateway {
         let address = self.persistent_state.gateway_identities.get(&id).copied();
-        event::Gateway { id, address }
+        let provenance = self
+            .persistent_state
+            .provenance
+            .gateway_identities
+            .get(&id)
+            .copied()
+            .filter(|_| self.config.provenance);
+        event::Gateway {
+            id,
+            address,
+            provenance,
+        }
     }
 
     fn node(&self, gateway_id: GatewayID, id: NodeID) -> event::Node {
@@ -78,8 +968,101 @@ impl Observer {
             .get(&gateway_id)
             .and_then(|node_table| node_table.0.get(&id))
             .copied();
+        let provenance = self
+            .persistent_state
+            .provenance
+            .nodes
+            .get(&gateway_id)
+            .and_then(|nodes| nodes.get(&id))
+            .copied()
+            .filter(|_| self.config.provenance);
+
+        let home_gateway =
+            address.and_then(|address| self.persistent_state.home_gateways.get(&address));
+
+        event::Node {
+            id,
+            address,
+            provenance,
+            home_gateway,
+        }
+    }
+}
+
+/// A destination for the events produced by an [`Observer`].
+pub trait EventSink: std::fmt::Debug + Send {
+    fn event(&mut self, event: Event);
+}
+
+/// Writes each event to standard output as a line of JSON, which is what an observer does with
+/// its events unless given another sink.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StdoutJsonSink;
+
+impl EventSink for StdoutJsonSink {
+    fn event(&mut self, event: Event) {
+        println!("{}", event.to_json());
+    }
+}
+
+/// Writes each event as a line of JSON which doesn't vary between runs or machines, for comparing
+/// output byte for byte.
+///
+/// See [`Event::to_deterministic_json()`].
+#[derive(Debug)]
+pub struct DeterministicJsonSink<W>(pub W);
+
+impl<W: std::io::Write + std::fmt::Debug + Send> EventSink for DeterministicJsonSink<W> {
+    fn event(&mut self, event: Event) {
+        if let Err(e) = writeln!(self.0, "{}", event.to_deterministic_json()) {
+            log::error!("error writing event: {}", e);
+        }
+    }
+}
+
+impl EventSink for Vec<Event> {
+    fn event(&mut self, event: Event) {
+        self.push(event);
+    }
+}
+
+impl EventSink for Box<dyn EventSink> {
+    fn event(&mut self, event: Event) {
+        (**self).event(event);
+    }
+}
+
+impl EventSink for std::sync::mpsc::Sender<Event> {
+    fn event(&mut self, event: Event) {
+        // The receiver hanging up means nobody is interested
+        self.send(event).ok();
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl EventSink for tokio::sync::mpsc::UnboundedSender<Event> {
+    fn event(&mut self, event: Event) {
+        self.send(event).ok();
+    }
+}
 
-        event::Node { id, address }
+/// Events written to a console are written as a line of JSON each, like the default output.
+impl EventSink for crate::console::Writer {
+    fn event(&mut self, event: Event) {
+        use std::io::Write;
+        if let Err(e) = writeln!(self, "{}", event.to_json()) {
+            log::error!("error writing event: {}", e);
+        }
+    }
+}
+
+/// Events written to a file are written as a line of JSON each, like the default output.
+impl EventSink for crate::write_behind::WriteBehind {
+    fn event(&mut self, event: Event) {
+        use std::io::Write;
+        if let Err(e) = writeln!(self, "{}", event.to_json()) {
+            log::error!("error writing event: {}", e);
+        }
     }
 }
 
@@ -99,13 +1082,17 @@ impl gateway::transport::Sink for Observer {
             enumeration_state.gateway_identity_observed(gateway_id, address);
         } else {
             // Accept the identity as-is
-            self.persistent_state
-                .gateway_identities
-                .insert(gateway_id, address);
+            let now = self.clock.now().into();
+            self.persistent_state.set_gateway_identity(
+                gateway_id,
+                address,
+                provenance::Source::GatewayResponse,
+                now,
+            );
         }
     }
 
-    fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str) {
+    fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str, _raw: &[u8]) {
         let version = version.to_owned();
 
         if let Some(enumeration_state) = self.enumeration_state.as_mut() {
@@ -113,42 +1100,216 @@ impl gateway::transport::Sink for Observer {
                 .gateway_versions
                 .insert(gateway_id, version);
         } else {
-            self.persistent_state
-                .gateway_versions
-                .insert(gateway_id, version);
+            let now = self.clock.now().into();
+            self.persistent_state.set_gateway_version(
+                gateway_id,
+                version,
+                provenance::Source::GatewayResponse,
+                now,
+            );
+        }
+    }
+
+    fn gateway_id_assigned(&mut self, old: GatewayID, new: GatewayID, address: LongAddress) {
+        if let Some(enumeration_state) = self.enumeration_state.as_mut() {
+            enumeration_state.gateway_id_assigned(old, new, address);
+            return;
+        }
+
+        // Re-key what we know about this gateway now, rather than waiting for another enumeration
+        let now = self.clock.now().into();
+        if self
+            .persistent_state
+            .reassign_gateway_id(old, new, address, now)
+        {
+            if let Some(slot_clock) = self.slot_clocks.remove(&old) {
+                self.slot_clocks.insert(new, slot_clock);
+            }
+            if self.restored_slot_clocks.remove(&old) {
+                self.restored_slot_clocks.insert(new);
+            }
+            self.early_reports.reassign(old, new);
+            if let Some(network_status) = self.network_status.remove(&old) {
+                self.network_status.insert(new, network_status);
+            }
+            self.gateway_status.reassign(old, new);
         }
+        self.unknown_identities_reported.remove(&new);
     }
 
     fn enumeration_ended(&mut self, _gateway_id: GatewayID) {
         // We're done enumerating
         // Did we catch the whole exchange?
         if let Some(enumeration_state) = self.enumeration_state.take() {
-            // Accept the gateway information learned during enumeration as a replacement for our
-            // existing state
-            self.persistent_state.gateway_identities = enumeration_state.gateway_identities;
-            self.persistent_state.gateway_versions = enumeration_state.gateway_versions;
+            // Accept the gateway information learned during enumeration
+            let now = self.clock.now().into();
+            if self.config.enumeration_merge == config::EnumerationMerge::Replace {
+                // Forget gateways which weren't enumerated
+                let identities = &enumeration_state.gateway_identities;
+                let versions = &enumeration_state.gateway_versions;
+                self.persistent_state
+                    .retain_gateway_identities(|gateway_id| identities.contains_key(gateway_id));
+                self.persistent_state
+                    .retain_gateway_versions(|gateway_id| versions.contains_key(gateway_id));
+            }
+
+            // Otherwise, we may have missed part of the exchange, so keep gateways we didn't hear
+            // about this time
+            for (gateway_id, address) in enumeration_state.gateway_identities {
+                self.persistent_state.set_gateway_identity(
+                    gateway_id,
+                    address,
+                    provenance::Source::Enumeration,
+                    now,
+                );
+            }
+            for (gateway_id, version) in enumeration_state.gateway_versions {
+                self.persistent_state.set_gateway_version(
+                    gateway_id,
+                    version,
+                    provenance::Source::Enumeration,
+                    now,
+                );
+            }
+            self.persistent_state.last_enumeration = Some(now);
         }
     }
 
     fn gateway_slot_counter_captured(&mut self, gateway_id: GatewayID) {
         self.captured_slot_counters
-            .insert(gateway_id, SystemTime::now());
+            .insert(gateway_id, self.clock.now());
     }
 
     fn gateway_slot_counter_observed(&mut self, gateway_id: GatewayID, slot_counter: SlotCounter) {
+        self.gateway_seen(gateway_id);
+        self.gateway_status
+            .slot_counter(gateway_id, self.clock.now());
+
         let Some(time) = self.captured_slot_counters.remove(&gateway_id) else {
             return;
         };
 
-        match self.slot_clocks.entry(gateway_id) {
+        // A clock restored from before a restart must fit the gateway's slot counter, which restarts
+        // whenever the controller does
+        if self.restored_slot_clocks.remove(&gateway_id) {
+            let anchor = self
+                .slot_clocks
+                .get(&gateway_id)
+                .filter(|clock| !clock.is_consistent(slot_counter, time))
+                .map(|clock| clock.anchor());
+            if let Some(anchor) = anchor {
+                self.slot_clocks.remove(&gateway_id);
+                self.diagnostic(
+                    DiagnosticEvent::new(
+                        diagnostic::Severity::Warning,
+                        diagnostic::Code::RestoredSlotClockDiscarded,
+                        format!(
+                            "gateway {:?} slot counter {:?} doesn't fit the slot clock restored from {}, so it must have restarted",
+                            gateway_id,
+                            slot_counter,
+                            self.config.time_zone.datetime(anchor.time).to_rfc3339(),
+                        ),
+                    )
+                    .with_gateway(self.gateway(gateway_id))
+                    .with_context("slot_counter", u16::from(slot_counter))
+                    .with_context("restored_slot_counter", anchor.slot_counter)
+                    .with_context("restored_from", anchor.time.to_rfc3339()),
+                );
+            }
+        }
+
+        let address = self
+            .persistent_state
+            .gateway_identities
+            .get(&gateway_id)
+            .copied();
+        let slot_clock = match self.slot_clocks.entry(gateway_id) {
             Entry::Vacant(e) => {
-                if let Ok(clock) = SlotClock::new(slot_counter, time) {
-                    e.insert(clock);
-                }
+                // Start from this gateway's previous calibration, if any
+                let calibration = self.persistent_state.slot_clock_calibration(gateway_id);
+                SlotClock::with_calibration(slot_counter, time, calibration)
+                    .ok()
+                    .map(|clock| (&*e.insert(clock), true))
             }
-            Entry::Occupied(mut e) => {
-                e.get_mut().set(slot_counter, time).ok();
+            Entry::Occupied(e) => {
+                let clock = e.into_mut();
+                let new_index = clock.set(slot_counter, time).unwrap_or(false);
+                Some((&*clock, new_index))
             }
+        };
+        let updated = slot_clock
+            .filter(|(_, new_index)| *new_index)
+            .map(|(clock, _)| clock.parameters());
+        let slot_clock = slot_clock.map(|(clock, _)| clock);
+
+        // Remember the latest observation and the calibration for next time
+        if let Some(clock) = slot_clock {
+            self.persistent_state
+                .slot_clock_anchors
+                .insert(gateway_id, clock.anchor());
+        }
+        if let (Some(address), Some(calibration)) = (
+            address,
+            slot_clock.and_then(|clock| clock.calibration().copied()),
+        ) {
+            self.persistent_state
+                .slot_clock_calibrations
+                .0
+                .insert(address, calibration);
+        }
+
+        if let Some(parameters) = updated.filter(|_| self.config.slot_clock_updates) {
+            self.emit(Event::SlotClockUpdated(event::SlotClockUpdatedEvent::new(
+                self.gateway(gateway_id),
+                &parameters,
+            )));
+        }
+
+        // Place the reports which arrived before the slot clock
+        if self.slot_clocks.contains_key(&gateway_id) {
+            let released = self.early_reports.release(gateway_id, self.clock.now());
+            if released.dropped > 0 {
+                self.early_reports_dropped(gateway_id, released.dropped);
+            }
+            for report in released.reports {
+                self.counters.late_power_reports += 1;
+                self.place_power_report(
+                    gateway_id,
+                    report.node_id,
+                    &report.power_report,
+                    report.received,
+                );
+            }
+        }
+
+        // Gateways are polled through the night, so this notices the array or its nodes falling silent
+        self.update_array_sleep();
+        self.update_alerts();
+        self.roll_over_daily_summaries();
+    }
+
+    fn invalid_frame(
+        &mut self,
+        frame: &gateway::link::Frame,
+        reason: gateway::transport::InvalidFrameReason,
+    ) {
+        *self.counters.invalid_frames.entry(reason).or_default() += 1;
+
+        if let Some(log) = self.invalid_frame_log.as_mut() {
+            log.write(&InvalidFrameRecord::new(
+                frame,
+                reason,
+                self.clock.now().into(),
+            ));
+        }
+        if let Some(dump) = self.invalid_dump.as_mut() {
+            dump.invalid_frame(self.source.as_ref(), frame, reason, self.clock.now().into());
+        }
+    }
+
+    fn checksum_failed(&mut self, bytes: &[u8]) {
+        if let Some(dump) = self.invalid_dump.as_mut() {
+            dump.checksum_failed(self.source.as_ref(), bytes, self.clock.now().into());
         }
     }
 
@@ -162,17 +1323,204 @@ impl gateway::transport::Sink for Observer {
 
     fn command_executed(
         &mut self,
-        _gateway_id: GatewayID,
+        gateway_id: GatewayID,
         _request: (PacketType, &[u8]),
         _response: (PacketType, &[u8]),
     ) {
+        self.gateway_status.response(gateway_id, self.clock.now());
+    }
+
+    fn receive_status(
+        &mut self,
+        gateway_id: GatewayID,
+        _status: &gateway::transport::ReceiveResponse,
+    ) {
+        self.gateway_status
+            .receive_response(gateway_id, self.clock.now());
+    }
+
+    fn command_timed_out(
+        &mut self,
+        gateway_id: GatewayID,
+        packet_type: PacketType,
+        sequence_number: gateway::transport::CommandSequenceNumber,
+    ) {
+        self.counters.command_timeouts += 1;
+
+        let event = event::CommandTimeoutEvent {
+            gateway: self.gateway(gateway_id),
+            timestamp: self.clock.now().into(),
+            packet_type: packet_type.0,
+            sequence_number,
+            source: None,
+        };
+        self.emit(Event::CommandTimeout(event));
+    }
+
+    fn gateway_tx_buffers_free_observed(&mut self, gateway_id: GatewayID, tx_buffers_free: u8) {
+        if tx_buffers_free != 0 {
+            self.tx_buffers_exhausted.remove(&gateway_id);
+            return;
+        }
+
+        let observations = self.tx_buffers_exhausted.entry(gateway_id).or_default();
+        *observations += 1;
+        if *observations != TX_BUFFERS_EXHAUSTED_THRESHOLD {
+            return;
+        }
+
+        self.diagnostic(
+            DiagnosticEvent::new(
+                diagnostic::Severity::Warning,
+                diagnostic::Code::GatewayTxBuffersExhausted,
+                format!(
+                    "gateway {:?} has reported no free transmit buffers {} times in a row",
+                    gateway_id, TX_BUFFERS_EXHAUSTED_THRESHOLD
+                ),
+            )
+            .with_gateway(self.gateway(gateway_id))
+            .with_context("consecutive_observations", TX_BUFFERS_EXHAUSTED_THRESHOLD),
+        );
+    }
+
+    fn polling_fairness(&self) -> gateway::transport::polling::PollingFairness {
+        self.config.polling_fairness
+    }
+
+    fn polling_unfair(
+        &mut self,
+        gateway_id: GatewayID,
+        polling: &gateway::transport::polling::UnfairPolling,
+    ) {
+        self.diagnostic(
+            DiagnosticEvent::new(
+                diagnostic::Severity::Warning,
+                diagnostic::Code::GatewayPollingUnfair,
+                format!(
+                    "gateway {:?} received {:.1}% of receive requests, against a fair share of \
+                     {:.1}%, so its nodes may report late",
+                    gateway_id,
+                    polling.share * 100.0,
+                    polling.fair_share * 100.0
+                ),
+            )
+            .with_gateway(self.gateway(gateway_id))
+            .with_context("share", polling.share)
+            .with_context("fair_share", polling.fair_share)
+            .with_context("max_gap", polling.max_gap),
+        );
+    }
+
+    fn gateway_capabilities(&self, gateway_id: GatewayID) -> gateway::GatewayCapabilities {
+        self.capabilities(gateway_id)
     }
 }
 
+/// The number of times a node's home gateway must change within `HOME_FLAP_WINDOW` before this is
+/// considered a problem.
+const HOME_FLAP_THRESHOLD: usize = 6;
+const HOME_FLAP_WINDOW: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// The number of consecutive times a gateway must report no free transmit buffers before this is
+/// considered a problem, rather than a momentary backlog.
+const TX_BUFFERS_EXHAUSTED_THRESHOLD: u32 = 10;
+
+/// The estimated packet loss, in percent, above which a node is considered to have a problem.
+///
+/// A node is considered recovered once its loss falls below half this.
+const SUSTAINED_PACKET_LOSS_PCT: f64 = 25.0;
+
 impl pv::application::Sink for Observer {
-    fn string_request(&mut self, _gateway_id: GatewayID, _pv_node_id: NodeID, _request: &str) {}
+    fn invalid_packet(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        packet_type: PacketType,
+        data: &[u8],
+    ) {
+        if let Some(dump) = self.invalid_dump.as_mut() {
+            dump.invalid_packet(
+                self.source.as_ref(),
+                gateway_id,
+                pv_node_id,
+                packet_type,
+                data,
+                self.clock.now().into(),
+            );
+        }
+    }
+
+    fn string_request(&mut self, gateway_id: GatewayID, pv_node_id: NodeID, request: LossyStr) {
+        let query = strings::Query::parse(&request.to_str_lossy());
+        self.pending_queries
+            .request(gateway_id, pv_node_id, query, self.clock.now());
+    }
+
+    fn string_response(&mut self, gateway_id: GatewayID, pv_node_id: NodeID, response: LossyStr) {
+        let node = self.node(gateway_id, pv_node_id);
+        let now = self.clock.now();
+        let timestamp = DateTime::<Local>::from(now);
+
+        let parsed = strings::Response::parse(&response.to_str_lossy());
+        if let Some((query, requested_at)) = self
+            .pending_queries
+            .response(gateway_id, pv_node_id, &parsed, now)
+        {
+            self.emit(Event::NodeDiagnostic(event::NodeDiagnosticEvent {
+                gateway: self.gateway(gateway_id),
+                node,
+                timestamp,
+                requested_at: requested_at.into(),
+                query,
+                response: parsed,
+                source: None,
+            }));
+        }
+
+        let Some(address) = node.address else {
+            return;
+        };
+        let Some(recorded) = self.persistent_state.node_inventory.record(
+            address,
+            &response.to_str_lossy(),
+            timestamp,
+        ) else {
+            return;
+        };
+        let strings = self.persistent_state.node_inventory.get(&address).unwrap();
+        let firmware = strings.firmware.clone();
+        let responses = strings.responses.clone();
+        let gateway = self.gateway(gateway_id);
+
+        if let Some(previous) = recorded.previous_firmware {
+            let current = firmware.clone().unwrap_or_default();
+            self.diagnostic(
+                DiagnosticEvent::new(
+                    diagnostic::Severity::Info,
+                    diagnostic::Code::NodeFirmwareChanged,
+                    format!(
+                        "node {} firmware changed from {:?} to {:?}",
+                        address, previous, current
+                    ),
+                )
+                .with_gateway(gateway)
+                .with_node(node)
+                .with_context("previous_firmware", previous)
+                .with_context("firmware", current),
+            );
+        }
 
-    fn string_response(&mut self, _gateway_id: GatewayID, _pv_node_id: NodeID, _response: &str) {}
+        if recorded.changed {
+            self.emit(Event::NodeIdentity(event::NodeIdentityEvent {
+                gateway,
+                node,
+                timestamp,
+                firmware,
+                responses,
+                source: None,
+            }));
+        }
+    }
 
     fn node_table_page(
         &mut self,
@@ -180,21 +1528,210 @@ impl pv::application::Sink for Observer {
         start_address: NodeAddress,
         nodes: &[NodeTableResponseEntry],
     ) {
+        let gateway = self.gateway(gateway_id);
+        let timestamp = DateTime::<Local>::from(self.clock.now());
         let builder = self.node_table_builders.entry(gateway_id).or_default();
 
-        if let Some(new_table) = builder.push(start_address, nodes) {
-            self.persistent_state
+        let abandoned = builder.len();
+        let restarted = !builder.is_empty() && !builder.continues(start_address);
+        let new_table = builder.push(start_address, nodes);
+        let entries_so_far = builder.len();
+
+        if restarted {
+            self.diagnostic(
+                DiagnosticEvent::new(
+                    diagnostic::Severity::Info,
+                    diagnostic::Code::NodeTableWalkRestarted,
+                    format!(
+                        "node table walk for gateway {:?} restarted after {} entries",
+                        gateway_id, abandoned
+                    ),
+                )
+                .with_gateway(gateway)
+                .with_context("abandoned_entries", abandoned),
+            );
+        }
+
+        if let Some(new_table) = new_table {
+            let home_gateways = &self.persistent_state.home_gateways;
+            let node = |id, address| event::Node {
+                id,
+                address: Some(address),
+                provenance: None,
+                home_gateway: home_gateways.get(&address),
+            };
+            let nodes = new_table
+                .0
+                .iter()
+                .map(|(&id, &address)| node(id, address))
+                .collect();
+
+            // Only a table which replaces another has changed
+            let changed = self
+                .persistent_state
                 .gateway_node_tables
-                .insert(gateway_id, new_table);
+                .get(&gateway_id)
+                .map(|previous| new_table.diff(previous))
+                .filter(|diff| !diff.is_empty())
+                .map(|diff| event::NodeTableChangedEvent {
+                    gateway,
+                    timestamp,
+                    added: (diff.added.into_iter())
+                        .map(|(id, address)| node(id, address))
+                        .collect(),
+                    removed: (diff.removed.into_iter())
+                        .map(|(id, address)| node(id, address))
+                        .collect(),
+                    readdressed: (diff.readdressed.into_iter())
+                        .map(|(previous_id, id, address)| event::ReaddressedNode {
+                            previous_id,
+                            node: node(id, address),
+                        })
+                        .collect(),
+                    source: None,
+                });
+
+            self.persistent_state
+                .set_node_table(gateway_id, new_table, timestamp);
+            self.node_validation.reset(gateway_id);
+            self.emit(Event::NodeTable(event::NodeTableEvent {
+                gateway,
+                timestamp,
+                nodes,
+                source: None,
+            }));
+            if let Some(changed) = changed {
+                self.emit(Event::NodeTableChanged(changed));
+            }
+        } else if entries_so_far > 0 {
+            // The walk is in progress
+            self.emit(Event::NodeTableProgress(event::NodeTableProgressEvent {
+                gateway,
+                timestamp,
+                entries_so_far,
+                last_start_address: start_address.0.get(),
+                source: None,
+            }));
         }
     }
 
     fn topology_report(
         &mut self,
-        _gateway_id: GatewayID,
-        _pv_node_id: NodeID,
-        _topology_report: &TopologyReport,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        topology_report: &TopologyReport,
     ) {
+        self.topology.insert(
+            gateway_id,
+            pv_node_id,
+            topology_report,
+            self.clock.now().into(),
+        );
+    }
+
+    fn broadcast(&mut self, gateway_id: GatewayID, payload: &Broadcast) {
+        self.emit(Event::Broadcast(event::BroadcastEvent {
+            gateway: self.gateway(gateway_id),
+            timestamp: self.clock.now().into(),
+            destination: payload.pv_node_id.0.get(),
+            data: payload
+                .data
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            pv_off: payload.pv_off(),
+            source: None,
+        }));
+    }
+
+    fn network_status(&mut self, gateway_id: GatewayID, status: &NetworkStatusResponse) {
+        let status = NetworkStatus::new(status, self.clock.now().into());
+        let changed = self
+            .network_status
+            .insert(gateway_id, status)
+            .is_none_or(|previous| !previous.same_as(&status));
+        if changed {
+            self.emit(Event::NetworkStatus(event::NetworkStatusEvent {
+                gateway: self.gateway(gateway_id),
+                timestamp: status.updated,
+                counter: status.counter,
+                node_counts: status.node_counts,
+                unknown: status.unknown,
+                source: None,
+            }));
+        }
+    }
+
+    fn gateway_radio_configuration(
+        &mut self,
+        gateway_id: GatewayID,
+        config: &RadioConfigurationResponse,
+    ) {
+        self.persistent_state
+            .gateway_radio_configurations
+            .insert(gateway_id, config.into());
+    }
+
+    fn pv_configuration(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        config: &PvConfigurationResponse,
+    ) {
+        let node = self.node(gateway_id, pv_node_id);
+        let Some(address) = node.address else {
+            return;
+        };
+        let configuration = PvConfiguration::from(config);
+        let Some(changed) = self
+            .persistent_state
+            .node_configurations
+            .record(address, configuration.clone())
+        else {
+            return;
+        };
+
+        self.emit(Event::NodeConfiguration(Box::new(
+            event::NodeConfigurationEvent {
+                gateway: self.gateway(gateway_id),
+                node,
+                timestamp: self.clock.now().into(),
+                configuration,
+                previous: changed.previous,
+                source: None,
+            },
+        )));
+    }
+
+    fn packet_loss_estimated(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        estimated_loss_pct: f64,
+    ) {
+        if estimated_loss_pct < SUSTAINED_PACKET_LOSS_PCT / 2.0 {
+            self.lossy_nodes.remove(&(gateway_id, pv_node_id));
+            return;
+        }
+        if estimated_loss_pct < SUSTAINED_PACKET_LOSS_PCT
+            || !self.lossy_nodes.insert((gateway_id, pv_node_id))
+        {
+            return;
+        }
+
+        self.diagnostic(
+            DiagnosticEvent::new(
+                diagnostic::Severity::Warning,
+                diagnostic::Code::SustainedPacketLoss,
+                format!(
+                    "node {:?} on gateway {:?} is losing an estimated {:.0}% of its packets",
+                    pv_node_id, gateway_id, estimated_loss_pct
+                ),
+            )
+            .with_gateway(self.gateway(gateway_id))
+            .with_node(self.node(gateway_id, pv_node_id))
+            .with_context("estimated_loss_pct", estimated_loss_pct),
+        );
     }
 
     fn power_report(
@@ -203,43 +1740,378 @@ impl pv::application::Sink for Observer {
         pv_node_id: NodeID,
         power_report: &pv::application::PowerReport,
     ) {
-        let Some(slot_clock) = self.slot_clocks.get(&gateway_id) else {
-            log::error!(
-                "discarding power report from gateway {:?} due to missing slot clock: {:?}",
-                gateway_id,
-                power_report
+        // Some firmware can't be trusted to say when a measurement was taken
+        if self.capabilities(gateway_id).unreliable_slot_counters {
+            let event = event::PowerReportEvent::received_at(
+                self.gateway(gateway_id),
+                self.node(gateway_id, pv_node_id),
+                power_report,
+                self.clock.now().into(),
             );
+            self.accept_power_report(event);
+            return;
+        }
+
+        self.restore_slot_clock(gateway_id);
+        let now = self.clock.now();
+        if !self.slot_clocks.contains_key(&gateway_id) {
+            // Hold the report until the gateway's slot counter is observed
+            let report = EarlyReport {
+                node_id: pv_node_id,
+                power_report: *power_report,
+                received: now,
+            };
+            let Some(engaged) = self.early_reports.push(gateway_id, report) else {
+                return;
+            };
+            self.counters.dropped_early_power_reports += 1;
+            // Say so once until the queue drains, rather than for every report discarded
+            if engaged {
+                self.diagnostic(
+                    DiagnosticEvent::new(
+                        diagnostic::Severity::Error,
+                        diagnostic::Code::PowerReportWithoutSlotClock,
+                        format!(
+                            "gateway {:?} has {} power reports waiting for its slot clock, so the oldest are being discarded",
+                            gateway_id,
+                            early_reports::MAX_QUEUED_PER_GATEWAY
+                        ),
+                    )
+                    .with_gateway(self.gateway(gateway_id)),
+                );
+            }
+            return;
+        }
+
+        self.place_power_report(gateway_id, pv_node_id, power_report, now);
+    }
+}
+
+impl Observer {
+    /// Convert a power report received at `received` to an event, using its gateway's slot clock.
+    fn place_power_report(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        power_report: &pv::application::PowerReport,
+        received: SystemTime,
+    ) {
+        let Some(slot_clock) = self.slot_clocks.get(&gateway_id) else {
             return;
         };
+        let gateway = self.gateway(gateway_id);
+        let node = self.node(gateway_id, pv_node_id);
 
-        let Ok(event) = event::PowerReportEvent::new(
-            self.gateway(gateway_id),
-            self.node(gateway_id, pv_node_id),
-            slot_clock,
-            power_report,
-        ) else {
-            log::error!(
-                "discarding power report from gateway {:?} due to invalid slot counter: {:?}",
-                gateway_id,
-                power_report
+        let Ok(mut event) =
+            event::PowerReportEvent::new(gateway, node, slot_clock, power_report, received)
+        else {
+            self.diagnostic(
+                DiagnosticEvent::new(
+                    diagnostic::Severity::Error,
+                    diagnostic::Code::PowerReportInvalidSlotCounter,
+                    format!(
+                        "discarding power report from gateway {:?} due to invalid slot counter: {:?}",
+                        gateway_id, power_report
+                    ),
+                )
+                .with_gateway(gateway)
+                .with_node(node)
+                .with_context("slot_counter", u16::from(power_report.slot_counter)),
             );
             return;
         };
 
-        println!("{}", serde_json::to_string(&event).unwrap());
+        if let Some(max_age) = self.config.max_report_age_secs {
+            let age = received
+                .duration_since(event.timestamp.into())
+                .unwrap_or_default();
+            event.timestamp_uncertain = age > std::time::Duration::from_secs(max_age);
+        }
+        // A clock restored from before a restart is unconfirmed until the slot counter is next
+        // observed, since the controller may have restarted too
+        if self.restored_slot_clocks.contains(&gateway_id) {
+            event.timestamp_uncertain = true;
+        }
+
+        self.accept_power_report(event);
     }
+
+    /// Report that power reports waiting for a gateway's slot clock were discarded.
+    fn early_reports_dropped(&mut self, gateway_id: GatewayID, dropped: u64) {
+        self.counters.dropped_early_power_reports += dropped;
+        self.diagnostic(
+            DiagnosticEvent::new(
+                diagnostic::Severity::Error,
+                diagnostic::Code::PowerReportWithoutSlotClock,
+                format!(
+                    "discarding {} power reports from gateway {:?} after waiting {:?} for its slot clock",
+                    dropped,
+                    gateway_id,
+                    early_reports::TIMEOUT
+                ),
+            )
+            .with_gateway(self.gateway(gateway_id))
+            .with_context("dropped", dropped),
+        );
+    }
+}
+
+/// Counters describing the observer's own behavior.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Counters {
+    /// The number of events dropped by the global rate limit.
+    pub events_dropped_by_global_limit: u64,
+    /// The number of events dropped by the per-node rate limit.
+    pub events_dropped_by_node_limit: u64,
+    /// The number of commands abandoned by the controller without a response.
+    pub command_timeouts: u64,
+    /// The number of power reports dropped for arriving through a gateway other than the node's
+    /// home gateway.
+    pub duplicate_power_reports: u64,
+    /// The number of power reports from each gateway whose node ID was missing from the
+    /// gateway's node table, when node table validation is enabled.
+    pub unverified_power_reports: BTreeMap<GatewayID, u64>,
+    /// The number of frames the transport layer could not interpret, by reason.
+    pub invalid_frames: BTreeMap<gateway::transport::InvalidFrameReason, u64>,
+    /// The number of power reports which arrived before their gateway's slot clock was
+    /// established, and were emitted once it was.
+    pub late_power_reports: u64,
+    /// The number of power reports which arrived before their gateway's slot clock was
+    /// established, and were discarded for waiting too long or to make room for later ones.
+    pub dropped_early_power_reports: u64,
 }
 
 /// Persistent state of an observed network.
 ///
 /// Information like hardware addresses and version numbers are exchanged infrequently. This data
 /// is captured and stored in `PersistentState`.
-#[derive(Debug, Clone, Eq, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PersistentState {
+    #[serde(with = "gateway_id_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, NodeTable>"))]
     gateway_node_tables: BTreeMap<GatewayID, NodeTable>,
 
+    #[serde(with = "gateway_id_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, LongAddress>"))]
     gateway_identities: BTreeMap<GatewayID, LongAddress>,
+    #[serde(with = "gateway_id_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, String>"))]
     gateway_versions: BTreeMap<GatewayID, String>,
+    /// Each gateway's latest response to a radio configuration request.
+    #[serde(default, with = "gateway_id_keys")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "BTreeMap<String, RadioConfiguration>")
+    )]
+    gateway_radio_configurations: BTreeMap<GatewayID, RadioConfiguration>,
+
+    /// The time at which an enumeration was last observed.
+    #[serde(default)]
+    last_enumeration: Option<DateTime<Local>>,
+
+    #[serde(default)]
+    daily_summaries: DailySummaries,
+
+    /// The energy each node has produced today.
+    #[serde(default)]
+    energy: EnergyAccumulators,
+
+    /// The time of each node's latest power report, by hardware address.
+    #[serde(default)]
+    last_reports: LastReports,
+
+    /// When and how each gateway identity, gateway version, and node table entry was learned.
+    #[serde(default)]
+    provenance: ProvenanceTable,
+
+    /// Each gateway's measured slot rate, by hardware address.
+    #[serde(default)]
+    slot_clock_calibrations: SlotClockCalibrations,
+
+    /// Each gateway's latest slot counter observation, from which its slot clock can be restored
+    /// after a restart.
+    #[serde(default, with = "gateway_id_keys")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "BTreeMap<String, slot_clock::Anchor>")
+    )]
+    slot_clock_anchors: BTreeMap<GatewayID, slot_clock::Anchor>,
+
+    /// The time at which the observer last emitted an event other than a diagnostic.
+    #[serde(default)]
+    last_event: Option<DateTime<Local>>,
+
+    /// The time at which the array was last observed falling asleep, if it hasn't since woken.
+    #[serde(default)]
+    asleep_since: Option<DateTime<Local>>,
+
+    /// The gateway to which each node belongs, by hardware address.
+    #[serde(default)]
+    home_gateways: HomeGateways,
+
+    /// What each node reported in response to string requests, by hardware address.
+    #[serde(default)]
+    node_inventory: NodeInventory,
+
+    /// The PV configuration each node last reported, by hardware address.
+    #[serde(default)]
+    node_configurations: NodeConfigurations,
+
+    /// Each source's own state, by label, when several share an observer; see [`shared`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sources: BTreeMap<String, PersistentState>,
+}
+
+impl PersistentState {
+    /// The time at which the observer last emitted an event other than a diagnostic, indicating
+    /// that data was flowing.
+    pub fn last_event(&self) -> Option<DateTime<Local>> {
+        self.last_event
+    }
+
+    /// The time at which the array fell asleep, if the observer last saw it asleep.
+    pub fn asleep_since(&self) -> Option<DateTime<Local>> {
+        self.asleep_since
+    }
+
+    /// A gateway's latest radio configuration, if it has been observed.
+    pub fn gateway_radio_configuration(
+        &self,
+        gateway_id: GatewayID,
+    ) -> Option<&RadioConfiguration> {
+        self.gateway_radio_configurations.get(&gateway_id)
+    }
+
+    /// The PV configuration a node last reported, if it has been observed.
+    pub fn node_configuration(&self, address: &LongAddress) -> Option<&PvConfiguration> {
+        self.node_configurations.get(address)
+    }
+
+    /// A gateway's latest slot counter observation, if it has been observed.
+    pub fn slot_clock_anchor(&self, gateway_id: GatewayID) -> Option<&slot_clock::Anchor> {
+        self.slot_clock_anchors.get(&gateway_id)
+    }
+
+    /// A gateway's measured slot rate, if its hardware address is known and it has been measured.
+    fn slot_clock_calibration(&self, gateway_id: GatewayID) -> Option<slot_clock::Calibration> {
+        let address = self.gateway_identities.get(&gateway_id)?;
+        self.slot_clock_calibrations.0.get(address).copied()
+    }
+
+    fn set_gateway_identity(
+        &mut self,
+        gateway_id: GatewayID,
+        address: LongAddress,
+        source: provenance::Source,
+        now: DateTime<Local>,
+    ) {
+        let previous = self.gateway_identities.insert(gateway_id, address);
+        let provenance = Provenance::learned(
+            self.provenance.gateway_identities.get(&gateway_id).copied(),
+            previous != Some(address),
+            source,
+            now,
+        );
+        self.provenance
+            .gateway_identities
+            .insert(gateway_id, provenance);
+    }
+
+    fn set_gateway_version(
+        &mut self,
+        gateway_id: GatewayID,
+        version: String,
+        source: provenance::Source,
+        now: DateTime<Local>,
+    ) {
+        let changed = self.gateway_versions.get(&gateway_id) != Some(&version);
+        self.gateway_versions.insert(gateway_id, version);
+        let provenance = Provenance::learned(
+            self.provenance.gateway_versions.get(&gateway_id).copied(),
+            changed,
+            source,
+            now,
+        );
+        self.provenance
+            .gateway_versions
+            .insert(gateway_id, provenance);
+    }
+
+    /// Record that the gateway with hardware address `address` moved from `old` to `new`.
+    ///
+    /// If `old` was known to be this gateway, its version and node table move with it. Returns
+    /// whether they did.
+    fn reassign_gateway_id(
+        &mut self,
+        old: GatewayID,
+        new: GatewayID,
+        address: LongAddress,
+        now: DateTime<Local>,
+    ) -> bool {
+        let moved = old != new && self.gateway_identities.get(&old) == Some(&address);
+        if moved {
+            self.gateway_identities.remove(&old);
+            self.provenance.gateway_identities.remove(&old);
+            if let Some(version) = self.gateway_versions.remove(&old) {
+                self.gateway_versions.insert(new, version);
+            }
+            if let Some(provenance) = self.provenance.gateway_versions.remove(&old) {
+                self.provenance.gateway_versions.insert(new, provenance);
+            }
+            if let Some(config) = self.gateway_radio_configurations.remove(&old) {
+                self.gateway_radio_configurations.insert(new, config);
+            }
+            if let Some(anchor) = self.slot_clock_anchors.remove(&old) {
+                self.slot_clock_anchors.insert(new, anchor);
+            }
+            if let Some(table) = self.gateway_node_tables.remove(&old) {
+                self.gateway_node_tables.insert(new, table);
+            }
+            if let Some(provenance) = self.provenance.nodes.remove(&old) {
+                self.provenance.nodes.insert(new, provenance);
+            }
+        }
+
+        self.set_gateway_identity(new, address, provenance::Source::Assignment, now);
+        moved
+    }
+
+    fn set_node_table(&mut self, gateway_id: GatewayID, table: NodeTable, now: DateTime<Local>) {
+        let previous_table = self.gateway_node_tables.get(&gateway_id);
+        let previous_provenance = self
+            .provenance
+            .nodes
+            .remove(&gateway_id)
+            .unwrap_or_default();
+        let provenance = table
+            .0
+            .iter()
+            .map(|(node_id, address)| {
+                let changed = previous_table.and_then(|t| t.0.get(node_id)) != Some(address);
+                let provenance = Provenance::learned(
+                    previous_provenance.get(node_id).copied(),
+                    changed,
+                    provenance::Source::NodeTable,
+                    now,
+                );
+                (*node_id, provenance)
+            })
+            .collect();
+
+        self.provenance.nodes.insert(gateway_id, provenance);
+        self.gateway_node_tables.insert(gateway_id, table);
+    }
+
+    fn retain_gateway_identities(&mut self, keep: impl Fn(&GatewayID) -> bool) {
+        self.gateway_identities.retain(|id, _| keep(id));
+        self.provenance.gateway_identities.retain(|id, _| keep(id));
+    }
+
+    fn retain_gateway_versions(&mut self, keep: impl Fn(&GatewayID) -> bool) {
+        self.gateway_versions.retain(|id, _| keep(id));
+        self.provenance.gateway_versions.retain(|id, _| keep(id));
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -261,6 +2133,19 @@ impl EnumerationState {
         // Store the identity
         self.gateway_identities.insert(gateway, address);
     }
+
+    fn gateway_id_assigned(&mut self, old: GatewayID, new: GatewayID, address: LongAddress) {
+        // Did this gateway already have a persistent ID?
+        if old != new && self.gateway_identities.get(&old) == Some(&address) {
+            // Yes, so it's no longer there
+            self.gateway_identities.remove(&old);
+            if let Some(version) = self.gateway_versions.remove(&old) {
+                self.gateway_versions.insert(new, version);
+            }
+        }
+
+        self.gateway_identity_observed(new, address);
+    }
 }
 
 #[cfg(test)]
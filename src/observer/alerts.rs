@@ -0,0 +1,607 @@
+//! Alerts raised when nodes' power reports cross configured thresholds.
+//!
+//! Installations without an external alerting stack can have the observer watch for conditions
+//! like "temperature above 85 °C for five minutes" itself. Each [`AlertRule`] is evaluated against
+//! every node's latest power report as reports arrive and as gateways are polled, so that a node
+//! which stops reporting can still raise an alert. An alert is emitted as `Event::Alert` once its
+//! condition has held for long enough, and as `Event::AlertCleared` once it no longer holds.
+//!
+//! Rules are often easiest to enable from a [`Template`], by name.
+
+use super::event::{AlertEvent, Event, PowerReportEvent};
+use crate::analyze::Field;
+use crate::barcode::Barcode;
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::pv::NodeID;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, SystemTime};
+
+/// How recently a node must have reported for its report to be evaluated, or for it to count as a
+/// sibling. Healthy nodes report every ~20 seconds.
+const RECENT: Duration = Duration::from_secs(120);
+
+/// How long a node can go without reporting before it's forgotten, along with its alerts.
+const FORGET_AFTER: Duration = Duration::from_secs(3 * 24 * 3600);
+
+/// An alert to watch for: either a built-in template, named like `"over-temperature"`, or a rule.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum AlertConfig {
+    Template(Template),
+    Rule(AlertRule),
+}
+
+impl AlertConfig {
+    pub fn rule(&self) -> Cow<'_, AlertRule> {
+        match self {
+            AlertConfig::Template(template) => Cow::Owned(template.rule()),
+            AlertConfig::Rule(rule) => Cow::Borrowed(rule),
+        }
+    }
+}
+
+impl From<Template> for AlertConfig {
+    fn from(template: Template) -> Self {
+        AlertConfig::Template(template)
+    }
+}
+
+/// A condition on a node's power reports, and how to alert on it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AlertRule {
+    /// The rule's name, which appears in its alerts.
+    pub name: String,
+    pub condition: Condition,
+    /// How long the condition must hold before an alert is raised, in seconds.
+    #[serde(default)]
+    pub for_secs: u64,
+    /// The least time between two alerts from this rule for the same node, in seconds, so that a
+    /// condition which comes and goes doesn't flood consumers with alerts.
+    #[serde(default)]
+    pub min_interval_secs: u64,
+    /// The nodes to which the rule applies, by barcode, or every node if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
+    pub nodes: Vec<Barcode>,
+}
+
+/// A condition on a node's power reports.
+///
+/// Conditions on a node's values only hold or stop holding when the node reports, so a node which
+/// falls silent keeps its alerts until it reports again. Once a condition holds, it stops holding
+/// only when the value retreats past its threshold by `hysteresis`, so that a value hovering at
+/// the threshold raises one alert rather than many.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// The field is above `threshold`.
+    Above {
+        field: Field,
+        threshold: f64,
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// The field is below `threshold`.
+    Below {
+        field: Field,
+        threshold: f64,
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// The field is below `threshold`, while its median across the other nodes reporting through
+    /// the same gateway is above `siblings_above`.
+    ///
+    /// This tells a failing module apart from the whole array being shaded.
+    BelowSiblings {
+        field: Field,
+        threshold: f64,
+        siblings_above: f64,
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// The node hasn't reported for `after_secs`, while other nodes reporting through the same
+    /// gateway have. The alert's value is the number of seconds since the node's last report.
+    ///
+    /// Requiring siblings to be reporting keeps the array going to sleep overnight from raising
+    /// an alert for every node.
+    Stale { after_secs: u64 },
+}
+
+/// A built-in alert rule, enabled by name.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Template {
+    /// A node above 85 °C for five minutes, clearing below 80 °C.
+    OverTemperature,
+    /// A node producing under 10 W for ten minutes, while its siblings produce over 100 W.
+    UnderperformingModule,
+    /// A node not reporting for fifteen minutes, while its siblings are reporting.
+    StaleNode,
+}
+
+impl Template {
+    pub const ALL: [Template; 3] = [
+        Template::OverTemperature,
+        Template::UnderperformingModule,
+        Template::StaleNode,
+    ];
+
+    /// The template's name, which is also the name of its rule.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Template::OverTemperature => "over-temperature",
+            Template::UnderperformingModule => "underperforming-module",
+            Template::StaleNode => "stale-node",
+        }
+    }
+
+    /// The template's rule, applying to every node.
+    pub fn rule(&self) -> AlertRule {
+        let (condition, for_secs) = match self {
+            Template::OverTemperature => (
+                Condition::Above {
+                    field: Field::Temperature,
+                    threshold: 85.0,
+                    hysteresis: 5.0,
+                },
+                300,
+            ),
+            Template::UnderperformingModule => (
+                Condition::BelowSiblings {
+                    field: Field::PowerOut,
+                    threshold: 10.0,
+                    siblings_above: 100.0,
+                    hysteresis: 5.0,
+                },
+                600,
+            ),
+            Template::StaleNode => (Condition::Stale { after_secs: 900 }, 0),
+        };
+        AlertRule {
+            name: self.as_str().into(),
+            condition,
+            for_secs,
+            min_interval_secs: 3600,
+            nodes: Vec::new(),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown alert template {0:?}")]
+pub struct UnknownTemplateError(String);
+
+impl std::str::FromStr for Template {
+    type Err = UnknownTemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::ALL
+            .into_iter()
+            .find(|template| template.as_str() == s)
+            .ok_or_else(|| UnknownTemplateError(s.into()))
+    }
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The progress of one rule for one node.
+#[derive(Debug, Copy, Clone, Default)]
+struct RuleState {
+    /// When the condition started holding, if it holds.
+    holding_since: Option<SystemTime>,
+    /// When the condition started holding, if an alert has been raised and not yet cleared.
+    raised_since: Option<SystemTime>,
+    /// When an alert was last raised.
+    last_raised: Option<SystemTime>,
+}
+
+/// Evaluates alert rules against each node's latest power report.
+#[derive(Debug, Clone, Default)]
+pub struct AlertTracker {
+    /// Each node's latest power report, and when it was received.
+    latest: BTreeMap<(GatewayID, NodeID), (PowerReportEvent, SystemTime)>,
+    /// The state of each rule for each node, by rule name.
+    states: BTreeMap<(String, GatewayID, NodeID), RuleState>,
+}
+
+impl AlertTracker {
+    /// Note a power report received at `now`.
+    pub fn report(&mut self, report: &PowerReportEvent, now: SystemTime) {
+        self.latest
+            .insert((report.gateway.id, report.node.id), (report.clone(), now));
+    }
+
+    /// Evaluate `rules` at `now`, returning alerts raised and cleared.
+    pub fn update<'a>(
+        &mut self,
+        rules: impl IntoIterator<Item = &'a AlertConfig>,
+        now: SystemTime,
+    ) -> Vec<Event> {
+        self.latest
+            .retain(|_, (_, received)| age(*received, now) <= FORGET_AFTER);
+
+        let mut events = Vec::new();
+        let mut names = BTreeSet::new();
+        for rule in rules {
+            let rule = rule.rule();
+            names.insert(rule.name.clone());
+            for (&(gateway_id, node_id), (report, received)) in &self.latest {
+                if !rule.nodes.is_empty()
+                    && !report
+                        .node
+                        .address
+                        .is_some_and(|address| rule.nodes.contains(&address.into()))
+                {
+                    continue;
+                }
+
+                let key = (rule.name.clone(), gateway_id, node_id);
+                let holding = self
+                    .states
+                    .get(&key)
+                    .is_some_and(|state| state.holding_since.is_some());
+                let Some((holds, value)) =
+                    self.evaluate(&rule.condition, report, *received, holding, now)
+                else {
+                    continue;
+                };
+
+                let event = AlertEvent {
+                    timestamp: now.into(),
+                    rule: rule.name.clone(),
+                    gateway: report.gateway,
+                    node: report.node,
+                    value,
+                    since: now.into(),
+                    source: None,
+                };
+                let state = self.states.entry(key).or_default();
+                if holds {
+                    let since = *state.holding_since.get_or_insert(now);
+                    let held_long_enough = age(since, now) >= Duration::from_secs(rule.for_secs);
+                    let min_interval = Duration::from_secs(rule.min_interval_secs);
+                    let guarded = state
+                        .last_raised
+                        .is_some_and(|last_raised| age(last_raised, now) < min_interval);
+                    if state.raised_since.is_none() && held_long_enough && !guarded {
+                        state.raised_since = Some(since);
+                        state.last_raised = Some(now);
+                        events.push(Event::Alert(AlertEvent {
+                            since: since.into(),
+                            ..event
+                        }));
+                    }
+                } else {
+                    state.holding_since = None;
+                    if let Some(since) = state.raised_since.take() {
+                        events.push(Event::AlertCleared(AlertEvent {
+                            since: since.into(),
+                            ..event
+                        }));
+                    }
+                }
+            }
+        }
+
+        // Forget rules which are no longer configured, and nodes which have been forgotten
+        let latest = &self.latest;
+        self.states.retain(|(name, gateway_id, node_id), _| {
+            names.contains(name) && latest.contains_key(&(*gateway_id, *node_id))
+        });
+        events
+    }
+
+    /// Evaluate a condition for a node, returning whether it holds and the value it's judged on,
+    /// or `None` if there's not enough information to tell.
+    fn evaluate(
+        &self,
+        condition: &Condition,
+        report: &PowerReportEvent,
+        received: SystemTime,
+        holding: bool,
+        now: SystemTime,
+    ) -> Option<(bool, f64)> {
+        // While the condition holds, it takes `hysteresis` beyond the threshold to stop
+        let relax = |hysteresis: f64| if holding { hysteresis } else { 0.0 };
+
+        if let Condition::Stale { after_secs } = *condition {
+            let silent = age(received, now);
+            let value = silent.as_secs_f64();
+            if silent <= Duration::from_secs(after_secs) {
+                return Some((false, value));
+            }
+            return self.siblings(report, now).next().map(|_| (true, value));
+        }
+
+        if age(received, now) > RECENT {
+            return None;
+        }
+        match *condition {
+            Condition::Above {
+                field,
+                threshold,
+                hysteresis,
+            } => {
+                let value = field.value(report);
+                Some((value > threshold - relax(hysteresis), value))
+            }
+            Condition::Below {
+                field,
+                threshold,
+                hysteresis,
+            } => {
+                let value = field.value(report);
+                Some((value < threshold + relax(hysteresis), value))
+            }
+            Condition::BelowSiblings {
+                field,
+                threshold,
+                siblings_above,
+                hysteresis,
+            } => {
+                let value = field.value(report);
+                let mut siblings: Vec<f64> = self
+                    .siblings(report, now)
+                    .map(|sibling| field.value(sibling))
+                    .collect();
+                if siblings.is_empty() {
+                    return None;
+                }
+                siblings.sort_by(f64::total_cmp);
+                let median = siblings[siblings.len() / 2];
+                Some((
+                    value < threshold + relax(hysteresis) && median > siblings_above,
+                    value,
+                ))
+            }
+            Condition::Stale { .. } => unreachable!(),
+        }
+    }
+
+    /// The latest reports of the other nodes on a report's gateway which have reported recently.
+    fn siblings<'a>(
+        &'a self,
+        report: &'a PowerReportEvent,
+        now: SystemTime,
+    ) -> impl Iterator<Item = &'a PowerReportEvent> + 'a {
+        let gateway_id = report.gateway.id;
+        self.latest
+            .range((gateway_id, NodeID::GATEWAY)..=(gateway_id, NodeID::MAX))
+            .filter(move |((_, node_id), (_, received))| {
+                *node_id != report.node.id && age(*received, now) <= RECENT
+            })
+            .map(|(_, (sibling, _))| sibling)
+    }
+
+    /// The approximate number of bytes this tracker occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<(GatewayID, NodeID), (PowerReportEvent, SystemTime)>(self.latest.len())
+            + btree_map_bytes::<(String, GatewayID, NodeID), RuleState>(self.states.len())
+            + self
+                .states
+                .keys()
+                .map(|(name, _, _)| name.capacity())
+                .sum::<usize>()
+    }
+}
+
+fn age(time: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(time).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::event::{Gateway, Node};
+    use crate::pv::physical::RSSI;
+
+    fn t0() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+    }
+
+    fn minutes(n: u64) -> SystemTime {
+        t0() + Duration::from_secs(n * 60)
+    }
+
+    fn report(node: u16, power: f64, temperature: f64) -> PowerReportEvent {
+        PowerReportEvent {
+            gateway: Gateway {
+                id: GatewayID::try_from(0x1201).unwrap(),
+                address: None,
+                provenance: None,
+            },
+            node: Node {
+                id: NodeID::try_from(node).unwrap(),
+                address: None,
+                provenance: None,
+                home_gateway: None,
+            },
+            timestamp: t0().into(),
+            voltage_in: 40.0,
+            voltage_out: 40.0,
+            current: power / 40.0,
+            power_w: power,
+            dc_dc_duty_cycle: 1.0,
+            temperature,
+            rssi: RSSI(100),
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        }
+    }
+
+    /// Alerts as `(raised, rule, node, since)`.
+    fn summarize(events: Vec<Event>) -> Vec<(bool, String, u16, SystemTime)> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                Event::Alert(alert) => (true, alert),
+                Event::AlertCleared(alert) => (false, alert),
+                other => panic!("unexpected event {:?}", other),
+            })
+            .map(|(raised, alert)| {
+                (
+                    raised,
+                    alert.rule,
+                    u16::from(alert.node.id),
+                    alert.since.into(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn over_temperature() {
+        let rules = [AlertConfig::from(Template::OverTemperature)];
+        let mut tracker = AlertTracker::default();
+        let at = |tracker: &mut AlertTracker, minute: u64, temperature: f64| {
+            tracker.report(&report(2, 200.0, temperature), minutes(minute));
+            summarize(tracker.update(&rules, minutes(minute)))
+        };
+
+        // Hot, but not for long enough
+        assert_eq!(at(&mut tracker, 0, 86.0), vec![]);
+        assert_eq!(at(&mut tracker, 4, 86.0), vec![]);
+        assert_eq!(at(&mut tracker, 5, 79.0), vec![]);
+
+        // Hot for five minutes, allowing for hysteresis
+        assert_eq!(at(&mut tracker, 7, 86.0), vec![]);
+        assert_eq!(at(&mut tracker, 10, 82.0), vec![]);
+        assert_eq!(
+            at(&mut tracker, 12, 86.0),
+            vec![(true, "over-temperature".into(), 2, minutes(7))]
+        );
+        assert_eq!(at(&mut tracker, 13, 90.0), vec![]);
+
+        // Cooling to just below the threshold isn't enough to clear
+        assert_eq!(at(&mut tracker, 14, 81.0), vec![]);
+        assert_eq!(
+            at(&mut tracker, 15, 79.0),
+            vec![(false, "over-temperature".into(), 2, minutes(7))]
+        );
+
+        // Another alert within the hour is held back until the hour has passed
+        assert_eq!(at(&mut tracker, 20, 86.0), vec![]);
+        assert_eq!(at(&mut tracker, 25, 86.0), vec![]);
+        assert_eq!(
+            at(&mut tracker, 72, 86.0),
+            vec![(true, "over-temperature".into(), 2, minutes(20))]
+        );
+    }
+
+    #[test]
+    fn underperforming_module() {
+        let rules = [AlertConfig::from(Template::UnderperformingModule)];
+        let mut tracker = AlertTracker::default();
+        let at = |tracker: &mut AlertTracker, minute: u64, power: f64, siblings: f64| {
+            for node in 3..=5 {
+                tracker.report(&report(node, siblings, 40.0), minutes(minute));
+            }
+            tracker.report(&report(2, power, 40.0), minutes(minute));
+            summarize(tracker.update(&rules, minutes(minute)))
+        };
+
+        // The whole array is shaded, which isn't the module's fault
+        for minute in 0..20 {
+            assert_eq!(at(&mut tracker, minute, 5.0, 20.0), vec![]);
+        }
+
+        // The sun comes out, and the module doesn't produce
+        assert_eq!(at(&mut tracker, 20, 5.0, 200.0), vec![]);
+        assert_eq!(
+            at(&mut tracker, 30, 5.0, 200.0),
+            vec![(true, "underperforming-module".into(), 2, minutes(20))]
+        );
+
+        // Producing a little doesn't clear the alert, but producing enough does
+        assert_eq!(at(&mut tracker, 31, 12.0, 200.0), vec![]);
+        assert_eq!(
+            at(&mut tracker, 32, 150.0, 200.0),
+            vec![(false, "underperforming-module".into(), 2, minutes(20))]
+        );
+    }
+
+    #[test]
+    fn stale_node() {
+        let rules = [AlertConfig::from(Template::StaleNode)];
+        let mut tracker = AlertTracker::default();
+        let at = |tracker: &mut AlertTracker, minute: u64, nodes: &[u16]| {
+            for &node in nodes {
+                tracker.report(&report(node, 200.0, 40.0), minutes(minute));
+            }
+            summarize(tracker.update(&rules, minutes(minute)))
+        };
+
+        for minute in 0..10 {
+            assert_eq!(at(&mut tracker, minute, &[2, 3, 4]), vec![]);
+        }
+
+        // Node 2 falls silent while the others keep going
+        for minute in 10..25 {
+            assert_eq!(at(&mut tracker, minute, &[3, 4]), vec![]);
+        }
+        assert_eq!(
+            at(&mut tracker, 25, &[3, 4]),
+            vec![(true, "stale-node".into(), 2, minutes(25))]
+        );
+        assert_eq!(at(&mut tracker, 26, &[3, 4]), vec![]);
+
+        // The alert clears when it reports again
+        assert_eq!(
+            at(&mut tracker, 30, &[2, 3, 4]),
+            vec![(false, "stale-node".into(), 2, minutes(25))]
+        );
+
+        // Overnight, the whole array falling silent doesn't raise alerts
+        for minute in 100..1000 {
+            assert_eq!(at(&mut tracker, minute, &[]), vec![]);
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let config: Vec<AlertConfig> = serde_json::from_str(
+            r#"[
+                "stale-node",
+                {
+                    "name": "hot-module",
+                    "condition": { "type": "above", "field": "temperature", "threshold": 70 },
+                    "for_secs": 60,
+                    "nodes": ["4-9A57A2L"]
+                }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(config[0], AlertConfig::Template(Template::StaleNode));
+        assert_eq!(config[1].rule().for_secs, 60);
+        assert_eq!("stale-node".parse(), Ok(Template::StaleNode));
+
+        // The rule only applies to the node it names
+        let mut tracker = AlertTracker::default();
+        let mut named = report(2, 200.0, 75.0);
+        named.node.address = Some("4-9A57A2L".parse::<Barcode>().unwrap().into());
+        let mut events = Vec::new();
+        for minute in 0..=1 {
+            tracker.report(&named, minutes(minute));
+            tracker.report(&report(3, 200.0, 75.0), minutes(minute));
+            events.extend(tracker.update(&config[1..], minutes(minute)));
+        }
+        assert_eq!(
+            summarize(events),
+            vec![(true, "hot-module".into(), 2, minutes(0))]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use super::config::ArraySleep;
+use super::event::ArrayState;
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::pv::NodeID;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+/// How long a node can go without reporting before it's considered gone, rather than asleep.
+const FORGET_AFTER: Duration = Duration::from_secs(3 * 24 * 3600);
+
+/// Whether the array as a whole is asleep, judged by how many of its nodes are reporting.
+#[derive(Debug, Clone, Default)]
+pub struct ArraySleepTracker {
+    last_reports: BTreeMap<(GatewayID, NodeID), SystemTime>,
+}
+
+/// A change in the array's state.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Transition {
+    pub state: ArrayState,
+    pub reporting_nodes: usize,
+    pub known_nodes: usize,
+}
+
+impl ArraySleepTracker {
+    /// Note that a node reported at `now`.
+    pub fn report(&mut self, gateway_id: GatewayID, node_id: NodeID, now: SystemTime) {
+        self.last_reports.insert((gateway_id, node_id), now);
+    }
+
+    /// Judge the array's state at `now`, returning a transition if it differs from `state`.
+    pub fn update(
+        &mut self,
+        thresholds: &ArraySleep,
+        state: ArrayState,
+        now: SystemTime,
+    ) -> Option<Transition> {
+        self.last_reports
+            .retain(|_, last_report| age(*last_report, now) <= FORGET_AFTER);
+
+        let known_nodes = self.last_reports.len();
+        if known_nodes == 0 {
+            return None;
+        }
+
+        let window = Duration::from_secs(u64::from(thresholds.window_minutes) * 60);
+        let reporting_nodes = self
+            .last_reports
+            .values()
+            .filter(|last_report| age(**last_report, now) <= window)
+            .count();
+        let pct = reporting_nodes * 100 / known_nodes;
+
+        let new_state = match state {
+            ArrayState::Awake if pct < usize::from(thresholds.asleep_below_pct) => {
+                ArrayState::Asleep
+            }
+            ArrayState::Asleep if pct >= usize::from(thresholds.awake_at_pct) => ArrayState::Awake,
+            _ => return None,
+        };
+
+        Some(Transition {
+            state: new_state,
+            reporting_nodes,
+            known_nodes,
+        })
+    }
+
+    /// The approximate number of bytes this tracker occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<(GatewayID, NodeID), SystemTime>(self.last_reports.len())
+    }
+}
+
+fn age(time: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(time).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_array() {
+        let thresholds = ArraySleep::default();
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let minutes = |n: u64| t0 + Duration::from_secs(n * 60);
+
+        let mut tracker = ArraySleepTracker::default();
+        for node in 2..=21 {
+            tracker.report(gateway_id, NodeID::try_from(node).unwrap(), t0);
+        }
+        assert_eq!(tracker.update(&thresholds, ArrayState::Awake, t0), None);
+
+        // Half the array is shaded early, and the other half keeps going
+        for node in 12..=21 {
+            tracker.report(gateway_id, NodeID::try_from(node).unwrap(), minutes(30));
+        }
+        assert_eq!(
+            tracker.update(&thresholds, ArrayState::Awake, minutes(30)),
+            None
+        );
+
+        // Down to one node out of twenty, which is still 5% of the array
+        tracker.report(gateway_id, NodeID::try_from(21).unwrap(), minutes(60));
+        assert_eq!(
+            tracker.update(&thresholds, ArrayState::Awake, minutes(60)),
+            Some(Transition {
+                state: ArrayState::Asleep,
+                reporting_nodes: 1,
+                known_nodes: 20,
+            })
+        );
+
+        // A few early risers aren't enough to wake the array
+        for node in 2..=5 {
+            tracker.report(gateway_id, NodeID::try_from(node).unwrap(), minutes(600));
+        }
+        assert_eq!(
+            tracker.update(&thresholds, ArrayState::Asleep, minutes(600)),
+            None
+        );
+        tracker.report(gateway_id, NodeID::try_from(6).unwrap(), minutes(601));
+        assert_eq!(
+            tracker.update(&thresholds, ArrayState::Asleep, minutes(601)),
+            Some(Transition {
+                state: ArrayState::Awake,
+                reporting_nodes: 5,
+                known_nodes: 20,
+            })
+        );
+    }
+
+    #[test]
+    fn forgotten_nodes() {
+        let thresholds = ArraySleep::default();
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+
+        // A node which stopped reporting long ago doesn't hold the array awake or asleep
+        let mut tracker = ArraySleepTracker::default();
+        tracker.report(gateway_id, NodeID::try_from(2).unwrap(), t0);
+        let later = t0 + FORGET_AFTER + Duration::from_secs(1);
+        assert_eq!(tracker.update(&thresholds, ArrayState::Awake, later), None);
+        assert!(tracker.last_reports.is_empty());
+    }
+}
@@ -0,0 +1,493 @@
+//! Correcting the measurements in individual nodes' power reports.
+//!
+//! Power reports carry raw readings which are scaled by fixed constants, like 1/200 A per count of
+//! current. Some installations find that particular nodes, or particular models of node, read
+//! consistently high or low. A [`Calibration`] maps nodes, by barcode or by node ID, to a scale and
+//! an offset for each measurement, applied as `value * scale + offset`, after which power is
+//! recomputed from the corrected voltage and current. Nodes it doesn't mention are left alone.
+//!
+//! A calibration is written as JSON:
+//!
+//! ```json
+//! {
+//!   "nodes": [
+//!     { "barcode": "4-C3F25DB", "current": { "scale": 1.02 } },
+//!     { "gateway": 4609, "node": 12, "temperature": { "offset": -1.5 } }
+//!   ]
+//! }
+//! ```
+//!
+//! A node matched by barcode takes that entry over any matching its node ID, and a node ID given
+//! with a gateway takes precedence over one without.
+//!
+//! A [`CalibrationFile`] reloads the calibration when its file changes, or when asked to, keeping
+//! the previous calibration if the new one is invalid.
+
+use super::event::PowerReportEvent;
+use crate::barcode::Barcode;
+use crate::gateway::link::GatewayID;
+use crate::pv::{LongAddress, NodeID};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a [`CalibrationFile`] checks whether its file has changed.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Corrections to the measurements of particular nodes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Calibration {
+    by_barcode: BTreeMap<LongAddress, Adjustments>,
+    by_node: BTreeMap<(Option<GatewayID>, NodeID), Adjustments>,
+}
+
+/// A scale and offset for each measurement in a power report.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Adjustments {
+    pub voltage_in: Adjustment,
+    pub voltage_out: Adjustment,
+    pub current: Adjustment,
+    pub temperature: Adjustment,
+}
+
+/// A correction to one measurement, applied as `value * scale + offset`.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Adjustment {
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl Default for Adjustment {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl Adjustment {
+    pub fn apply(&self, value: f64) -> f64 {
+        value * self.scale + self.offset
+    }
+
+    fn is_finite(&self) -> bool {
+        self.scale.is_finite() && self.offset.is_finite()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    nodes: Vec<Entry>,
+}
+
+/// One node's corrections, as written in a calibration file.
+#[derive(Deserialize)]
+#[serde(try_from = "RawEntry")]
+struct Entry {
+    selector: Selector,
+    adjustments: Adjustments,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Selector {
+    Barcode(Barcode),
+    Node(Option<GatewayID>, NodeID),
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Barcode(barcode) => write!(f, "barcode {}", barcode),
+            Selector::Node(Some(gateway), node) => {
+                write!(
+                    f,
+                    "gateway {} node {}",
+                    u16::from(*gateway),
+                    u16::from(*node)
+                )
+            }
+            Selector::Node(None, node) => write!(f, "node {}", u16::from(*node)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawEntry {
+    barcode: Option<Barcode>,
+    gateway: Option<GatewayID>,
+    node: Option<NodeID>,
+    #[serde(default)]
+    voltage_in: Adjustment,
+    #[serde(default)]
+    voltage_out: Adjustment,
+    #[serde(default)]
+    current: Adjustment,
+    #[serde(default)]
+    temperature: Adjustment,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+enum InvalidEntryError {
+    #[error("an entry needs either a barcode or a node ID")]
+    Unselected,
+    #[error("an entry can't have both a barcode and a node ID")]
+    Overselected,
+    #[error("a gateway ID needs a node ID to go with it")]
+    GatewayWithoutNode,
+    #[error("scales and offsets must be finite")]
+    NotFinite,
+}
+
+impl TryFrom<RawEntry> for Entry {
+    type Error = InvalidEntryError;
+
+    fn try_from(raw: RawEntry) -> Result<Self, Self::Error> {
+        let selector = match (raw.barcode, raw.gateway, raw.node) {
+            (Some(_), _, Some(_)) => return Err(InvalidEntryError::Overselected),
+            (_, Some(_), None) => return Err(InvalidEntryError::GatewayWithoutNode),
+            (Some(barcode), None, None) => Selector::Barcode(barcode),
+            (None, gateway, Some(node)) => Selector::Node(gateway, node),
+            (None, None, None) => return Err(InvalidEntryError::Unselected),
+        };
+        let adjustments = Adjustments {
+            voltage_in: raw.voltage_in,
+            voltage_out: raw.voltage_out,
+            current: raw.current,
+            temperature: raw.temperature,
+        };
+        if ![
+            adjustments.voltage_in,
+            adjustments.voltage_out,
+            adjustments.current,
+            adjustments.temperature,
+        ]
+        .iter()
+        .all(Adjustment::is_finite)
+        {
+            return Err(InvalidEntryError::NotFinite);
+        }
+        Ok(Self {
+            selector,
+            adjustments,
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CalibrationError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The file isn't a valid calibration, with the offending line and a caret under the column.
+    #[error("line {line}, column {column}: {message}\n{context}")]
+    Invalid {
+        line: usize,
+        column: usize,
+        message: String,
+        context: String,
+    },
+    #[error("{0} is calibrated more than once")]
+    Duplicate(String),
+}
+
+impl Calibration {
+    /// Parse a calibration from JSON.
+    pub fn parse(json: &str) -> Result<Self, CalibrationError> {
+        let file: File = serde_json::from_str(json).map_err(|e| {
+            let (line, column) = (e.line(), e.column());
+            let context = match json.lines().nth(line.saturating_sub(1)) {
+                Some(text) if line > 0 => {
+                    format!("  {}\n  {}^", text, " ".repeat(column.saturating_sub(1)))
+                }
+                _ => String::new(),
+            };
+            // serde_json appends the position to its message, which is given separately here
+            let message = e.to_string();
+            let message = match message.rsplit_once(" at line ") {
+                Some((message, _)) => message.to_string(),
+                None => message,
+            };
+            CalibrationError::Invalid {
+                line,
+                column,
+                message,
+                context,
+            }
+        })?;
+
+        let mut calibration = Self::default();
+        for entry in file.nodes {
+            let duplicate = match entry.selector {
+                Selector::Barcode(barcode) => calibration
+                    .by_barcode
+                    .insert(barcode.0, entry.adjustments)
+                    .is_some(),
+                Selector::Node(gateway, node) => calibration
+                    .by_node
+                    .insert((gateway, node), entry.adjustments)
+                    .is_some(),
+            };
+            if duplicate {
+                return Err(CalibrationError::Duplicate(entry.selector.to_string()));
+            }
+        }
+        Ok(calibration)
+    }
+
+    /// Read a calibration from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, CalibrationError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The number of nodes calibrated.
+    pub fn len(&self) -> usize {
+        self.by_barcode.len() + self.by_node.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The corrections for a node, if it has any.
+    pub fn get(
+        &self,
+        address: Option<LongAddress>,
+        gateway: GatewayID,
+        node: NodeID,
+    ) -> Option<&Adjustments> {
+        address
+            .and_then(|address| self.by_barcode.get(&address))
+            .or_else(|| self.by_node.get(&(Some(gateway), node)))
+            .or_else(|| self.by_node.get(&(None, node)))
+    }
+
+    /// Correct a power report's measurements, returning whether its node was calibrated.
+    pub fn apply(&self, report: &mut PowerReportEvent) -> bool {
+        let Some(adjustments) = self.get(report.node.address, report.gateway.id, report.node.id)
+        else {
+            return false;
+        };
+        report.voltage_in = adjustments.voltage_in.apply(report.voltage_in);
+        report.voltage_out = adjustments.voltage_out.apply(report.voltage_out);
+        report.current = adjustments.current.apply(report.current);
+        report.temperature = adjustments.temperature.apply(report.temperature);
+        report.power_w = report.voltage_in * report.current;
+        true
+    }
+}
+
+/// A file holding a [`Calibration`], reloaded when it changes.
+#[derive(Debug)]
+pub struct CalibrationFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_checked: Option<Instant>,
+}
+
+impl CalibrationFile {
+    /// Load the calibration in `path`, which must be valid.
+    pub fn open(path: impl Into<PathBuf>) -> Result<(Self, Calibration), CalibrationError> {
+        let path = path.into();
+        let modified = modified(&path);
+        let calibration = Calibration::load(&path)?;
+        let file = Self {
+            path,
+            modified,
+            last_checked: None,
+        };
+        Ok((file, calibration))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reload the calibration if the file has been modified since it was last loaded, checking no
+    /// more often than [`CHECK_INTERVAL`] as of `now`.
+    ///
+    /// Returns `None` if the file hasn't changed, or if it is now invalid, which is logged.
+    pub fn reload_if_changed(&mut self, now: Instant) -> Option<Calibration> {
+        let due = self.last_checked.is_none_or(|last_checked| {
+            now.saturating_duration_since(last_checked) >= CHECK_INTERVAL
+        });
+        if !due {
+            return None;
+        }
+        self.last_checked = Some(now);
+        if modified(&self.path) == self.modified {
+            return None;
+        }
+        self.reload()
+    }
+
+    /// Reload the calibration, returning `None` if it is now invalid, which is logged.
+    pub fn reload(&mut self) -> Option<Calibration> {
+        self.modified = modified(&self.path);
+        match Calibration::load(&self.path) {
+            Ok(calibration) => {
+                log::info!(
+                    "loaded calibration {:?} for {} nodes",
+                    self.path,
+                    calibration.len()
+                );
+                Some(calibration)
+            }
+            Err(e) => {
+                log::warn!(
+                    "keeping the previous calibration, since {:?} is invalid: {}",
+                    self.path,
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// When a file was last modified, if that can be determined.
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::event::{Gateway, Node};
+    use crate::pv::physical::RSSI;
+    use chrono::{Local, TimeZone as _};
+
+    const ADDRESS: LongAddress = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]);
+
+    fn report(gateway: u16, node: u16, address: Option<LongAddress>) -> PowerReportEvent {
+        PowerReportEvent {
+            gateway: Gateway {
+                id: GatewayID::try_from(gateway).unwrap(),
+                address: None,
+                provenance: None,
+            },
+            node: Node {
+                id: NodeID::try_from(node).unwrap(),
+                address,
+                provenance: None,
+                home_gateway: None,
+            },
+            timestamp: Local.with_ymd_and_hms(2024, 8, 24, 9, 0, 0).unwrap(),
+            voltage_in: 30.0,
+            voltage_out: 30.0,
+            current: 6.0,
+            power_w: 180.0,
+            dc_dc_duty_cycle: 1.0,
+            temperature: 25.0,
+            rssi: RSSI(120),
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn apply() {
+        let json = format!(
+            r#"{{
+              "nodes": [
+                {{ "barcode": "{}", "current": {{ "scale": 1.5 }} }},
+                {{ "gateway": 4609, "node": 3, "voltage_in": {{ "offset": -1 }} }},
+                {{ "node": 3, "temperature": {{ "scale": 2, "offset": 1 }} }}
+              ]
+            }}"#,
+            Barcode(ADDRESS)
+        );
+        let calibration = Calibration::parse(&json).unwrap();
+        assert_eq!(calibration.len(), 3);
+
+        // By barcode, even though node 2 is also given by node ID
+        let mut r = report(4609, 2, Some(ADDRESS));
+        assert!(calibration.apply(&mut r));
+        assert_eq!((r.current, r.power_w), (9.0, 270.0));
+
+        // By gateway and node ID, over node ID alone
+        let mut r = report(4609, 3, None);
+        assert!(calibration.apply(&mut r));
+        assert_eq!(
+            (r.voltage_in, r.power_w, r.temperature),
+            (29.0, 174.0, 25.0)
+        );
+
+        // By node ID alone, on any other gateway
+        let mut r = report(4610, 3, None);
+        assert!(calibration.apply(&mut r));
+        assert_eq!((r.voltage_in, r.temperature), (30.0, 51.0));
+
+        // Anything else is left alone
+        let mut r = report(4609, 4, None);
+        assert!(!calibration.apply(&mut r));
+        assert_eq!(r, report(4609, 4, None));
+
+        assert!(Calibration::parse("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |json: &str| Calibration::parse(json).unwrap_err().to_string();
+
+        assert_eq!(
+            error("{\"nodes\": [\n  {\"node\": 3, \"current\": {\"scael\": 2}}\n]}"),
+            [
+                "line 2, column 33: unknown field `scael`, expected `scale` or `offset`",
+                "    {\"node\": 3, \"current\": {\"scael\": 2}}",
+                "                                  ^",
+            ]
+            .join("\n")
+        );
+        assert!(error("{\"nodes\": [{\"current\": {}}]}")
+            .contains("an entry needs either a barcode or a node ID\n"));
+        assert!(
+            error("{\"nodes\": [{\"gateway\": 4609}]}").contains("a gateway ID needs a node ID")
+        );
+        assert!(error("{\"nodes\": [{\"barcode\": \"nope\"}]}").contains("invalid barcode"));
+        assert!(error("{\"nodes\": [\n").starts_with("line 2, column 0: EOF"));
+        assert_eq!(
+            error("{\"nodes\": [{\"node\": 3}, {\"node\": 3}]}"),
+            "node 3 is calibrated more than once"
+        );
+    }
+
+    #[test]
+    fn reload() {
+        let dir = std::env::temp_dir().join(format!("taptap-calibration-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("calibration.json");
+        std::fs::write(&path, r#"{"nodes": [{"node": 3}]}"#).unwrap();
+
+        let (mut file, calibration) = CalibrationFile::open(&path).unwrap();
+        assert_eq!(calibration.len(), 1);
+        let now = Instant::now();
+        assert_eq!(file.reload_if_changed(now), None);
+
+        // An invalid file keeps the previous calibration
+        std::fs::write(&path, "{").unwrap();
+        assert_eq!(file.reload(), None);
+
+        std::fs::write(&path, r#"{"nodes": [{"node": 3}, {"node": 4}]}"#).unwrap();
+        assert_eq!(file.reload().map(|c| c.len()), Some(2));
+
+        // Changes are noticed once the check interval has passed
+        file.modified = None;
+        assert_eq!(file.reload_if_changed(now + Duration::from_secs(1)), None);
+        assert_eq!(
+            file.reload_if_changed(now + CHECK_INTERVAL)
+                .map(|c| c.len()),
+            Some(2)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time.
+///
+/// Observers consult a `Clock` whenever they need to know the current time, which allows them to
+/// operate on recorded data or in tests.
+pub trait Clock: std::fmt::Debug + Send {
+    fn now(&self) -> SystemTime;
+}
+
+impl<C: Clock + ?Sized> Clock for Box<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+/// A `Clock` which uses the system's real time clock.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A `Clock` which is set manually.
+///
+/// Clones of a `ManualClock` share the same time, so one clone can be given to an observer while
+/// another is used to adjust the time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    pub fn new(time: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(time)))
+    }
+
+    /// Set the current time.
+    pub fn set(&self, time: SystemTime) {
+        *self.0.lock().unwrap() = time;
+    }
+
+    /// Advance the current time.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// How replay treats capture timestamps which step backwards.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ReplayClockMode {
+    /// Use each record's timestamp as captured, even when it steps backwards.
+    #[default]
+    Raw,
+    /// Keep time monotonic by holding the clock when timestamps step backwards, then running it
+    /// slow until it has caught up with the capture's timestamps.
+    Smooth,
+}
+
+impl ReplayClockMode {
+    pub const ALL: &'static [ReplayClockMode] = &[ReplayClockMode::Raw, ReplayClockMode::Smooth];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReplayClockMode::Raw => "raw",
+            ReplayClockMode::Smooth => "smooth",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown replay clock mode {0:?}")]
+pub struct UnknownReplayClockModeError(String);
+
+impl std::str::FromStr for ReplayClockMode {
+    type Err = UnknownReplayClockModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| UnknownReplayClockModeError(s.into()))
+    }
+}
+
+impl std::fmt::Display for ReplayClockMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// While catching up after a step, the fraction of each interval between records by which a
+/// [`ReplayClock`] runs slow. A step is therefore spread across twice its own length.
+const SLEW: f64 = 0.5;
+
+/// A step backwards in a capture's timestamps, as when NTP corrects the capturing machine's clock.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ClockStep {
+    /// The timestamp of the last record before the step.
+    pub from: SystemTime,
+    /// The timestamp of the first record after the step.
+    pub to: SystemTime,
+}
+
+impl ClockStep {
+    /// How far the timestamps stepped backwards.
+    pub fn magnitude(&self) -> Duration {
+        self.from.duration_since(self.to).unwrap_or_default()
+    }
+}
+
+/// A `Clock` driven by the timestamps of records replayed from a capture.
+///
+/// The capturing machine's clock may have stepped while it was capturing. Steps backwards are
+/// detected, and depending on the [`ReplayClockMode`], either passed through or smoothed away.
+/// Steps forwards can't be told apart from a pause in the data, so they are always passed through.
+#[derive(Debug, Clone)]
+pub struct ReplayClock {
+    mode: ReplayClockMode,
+    clock: ManualClock,
+    last_timestamp: Option<SystemTime>,
+    // How far the clock is ahead of the capture's timestamps, while catching up after a step
+    ahead: Duration,
+}
+
+impl ReplayClock {
+    pub fn new(mode: ReplayClockMode) -> Self {
+        Self {
+            mode,
+            clock: ManualClock::new(UNIX_EPOCH),
+            last_timestamp: None,
+            ahead: Duration::ZERO,
+        }
+    }
+
+    pub fn mode(&self) -> ReplayClockMode {
+        self.mode
+    }
+
+    /// The clock to give to an observer, which is set as records are replayed.
+    pub fn clock(&self) -> ManualClock {
+        self.clock.clone()
+    }
+
+    /// Set the clock for a record captured at `timestamp`, returning the step if the capture's
+    /// timestamps stepped backwards.
+    pub fn set(&mut self, timestamp: SystemTime) -> Option<ClockStep> {
+        let last = self.last_timestamp.replace(timestamp);
+        let step = last.filter(|&last| timestamp < last).map(|from| ClockStep {
+            from,
+            to: timestamp,
+        });
+
+        let time = match self.mode {
+            ReplayClockMode::Raw => timestamp,
+            ReplayClockMode::Smooth => {
+                if let Some(step) = step {
+                    // Hold the clock where it was
+                    self.ahead += step.magnitude();
+                } else if let Some(last) = last {
+                    // Run slow until caught up
+                    let elapsed = timestamp.duration_since(last).unwrap_or_default();
+                    self.ahead = self.ahead.saturating_sub(elapsed.mul_f64(SLEW));
+                }
+                timestamp + self.ahead
+            }
+        };
+        self.clock.set(time);
+
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1724497200 + seconds)
+    }
+
+    #[test]
+    fn replay_clock_mode() {
+        for mode in ReplayClockMode::ALL {
+            assert_eq!(mode.name().parse::<ReplayClockMode>(), Ok(*mode));
+            assert_eq!(mode.to_string(), mode.name());
+        }
+        assert!("fast".parse::<ReplayClockMode>().is_err());
+    }
+
+    #[test]
+    fn raw() {
+        let mut replay = ReplayClock::new(ReplayClockMode::Raw);
+        let clock = replay.clock();
+        assert_eq!(replay.set(at(100)), None);
+        assert_eq!(replay.set(at(110)), None);
+        assert_eq!(clock.now(), at(110));
+
+        let step = replay.set(at(80)).unwrap();
+        assert_eq!(
+            step,
+            ClockStep {
+                from: at(110),
+                to: at(80)
+            }
+        );
+        assert_eq!(step.magnitude(), Duration::from_secs(30));
+        assert_eq!(clock.now(), at(80));
+
+        // Forward jumps pass through
+        assert_eq!(replay.set(at(1000)), None);
+        assert_eq!(clock.now(), at(1000));
+    }
+
+    #[test]
+    fn smooth() {
+        let mut replay = ReplayClock::new(ReplayClockMode::Smooth);
+        let clock = replay.clock();
+        replay.set(at(100));
+        replay.set(at(110));
+
+        // The clock holds still across the step
+        assert!(replay.set(at(80)).is_some());
+        assert_eq!(clock.now(), at(110));
+
+        // Then runs at half speed until it catches up, 60 seconds later
+        let mut last = clock.now();
+        for t in (81..=140).step_by(1) {
+            assert_eq!(replay.set(at(t)), None);
+            assert!(clock.now() >= last);
+            last = clock.now();
+        }
+        assert_eq!(clock.now(), at(140));
+        replay.set(at(150));
+        assert_eq!(clock.now(), at(150));
+    }
+}
@@ -0,0 +1,247 @@
+use super::alerts::AlertConfig;
+use super::rate_limit::RateLimits;
+use crate::gateway::link::{gateway_id_keys, GatewayID};
+use crate::gateway::transport::polling::PollingFairness;
+use crate::gateway::GatewayCapabilities;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Configuration for an [`Observer`](super::Observer).
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct Config {
+    /// The time zone used to determine calendar days.
+    pub time_zone: TimeZone,
+
+    /// Whether to accumulate daily extremes for each node, emitting `Event::DailySummary` after
+    /// each day ends.
+    pub daily_summaries: bool,
+
+    /// Whether to integrate each node's power over time, adding the energy it has produced so far
+    /// today to its power reports as `energy_wh_today`.
+    pub energy: bool,
+
+    /// Limits on the rate at which events are emitted, protecting downstream consumers from
+    /// misbehaving gateways.
+    pub rate_limits: RateLimits,
+
+    /// How gateway information learned during an enumeration is combined with existing state.
+    pub enumeration_merge: EnumerationMerge,
+
+    /// Whether to describe when and how gateway and node hardware addresses were learned in the
+    /// gateways and nodes attached to events.
+    pub provenance: bool,
+
+    /// Capabilities to assume for particular gateways, in place of those implied by their firmware
+    /// versions.
+    #[serde(with = "gateway_id_keys")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "BTreeMap<String, GatewayCapabilities>")
+    )]
+    pub gateway_capabilities: BTreeMap<GatewayID, GatewayCapabilities>,
+
+    /// How to detect the whole array going to sleep overnight, emitting `Event::ArrayAsleep` and
+    /// `Event::ArrayWake`, or `None` to not track this.
+    pub array_sleep: Option<ArraySleep>,
+
+    /// How to handle a node whose hardware address appears under more than one gateway.
+    pub duplicate_addresses: DuplicateAddresses,
+
+    /// Whether to check each power report's node ID against its gateway's node table, marking
+    /// reports from nodes missing from the table as `node_unverified` and diagnosing node tables
+    /// which appear stale.
+    pub validate_node_tables: bool,
+
+    /// How evenly the controller must poll its gateways to avoid a `gateway_polling_unfair`
+    /// diagnostic.
+    pub polling_fairness: PollingFairness,
+
+    /// Whether to emit `Event::SlotClockUpdated` each time a gateway's slot clock moves on to a
+    /// new thousand slots, about every five seconds while the gateway is polled.
+    pub slot_clock_updates: bool,
+
+    /// The age, in seconds, beyond which a power report's timestamp is marked
+    /// `timestamp_uncertain`, or `None` to never mark them. Slot counters wrap about every four
+    /// minutes, so a report which a gateway held for longer than that can't be placed exactly, and
+    /// one which appears nearly that old may have been held for longer still.
+    pub max_report_age_secs: Option<u64>,
+
+    /// When to consider each gateway stale or offline, emitting `Event::GatewayStatus` as its
+    /// state changes and periodically in between, or `None` to not track this. Statuses are only
+    /// emitted by [`Observer::tick()`](super::Observer::tick).
+    pub gateway_status: Option<GatewayStatus>,
+
+    /// When to consider a node to have gone quiet, emitting `Event::NodeGap` when it reports again.
+    pub node_gaps: NodeGaps,
+
+    /// How recently a gateway's slot counter must have been observed before a restart for its
+    /// slot clock to be restored from the persistent state.
+    pub slot_clock_restore: SlotClockRestore,
+
+    /// Alert rules to evaluate against each node's power reports, emitting `Event::Alert` and
+    /// `Event::AlertCleared`.
+    pub alerts: Vec<AlertConfig>,
+}
+
+/// A policy for nodes whose hardware address appears under more than one gateway.
+///
+/// Where gateways' radio coverage overlaps, a node can be in the node tables of several gateways,
+/// and its reports can arrive through any of them. Each node has a home gateway, which appears in
+/// events as `home_gateway`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAddresses {
+    /// A node's home is whichever gateway most recently reported it, and reports are emitted
+    /// whichever gateway they arrive through.
+    #[default]
+    FollowReports,
+    /// A node's home stays with one gateway for as long as that gateway keeps reporting it, and
+    /// reports arriving through any other gateway are dropped as duplicates.
+    HomeOnly,
+}
+
+/// Thresholds for deciding whether the array as a whole is asleep.
+///
+/// The array is judged by the fraction of its nodes which reported within the last
+/// `window_minutes`. It falls asleep when this drops below `asleep_below_pct`, and wakes when it
+/// reaches `awake_at_pct`. Parts of an array are often shaded well before sunset, so the
+/// thresholds should be low enough that the array is only considered asleep once nearly every
+/// node has stopped.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ArraySleep {
+    pub window_minutes: u32,
+    pub asleep_below_pct: u8,
+    pub awake_at_pct: u8,
+}
+
+impl Default for ArraySleep {
+    fn default() -> Self {
+        // Healthy nodes report every ~20 seconds
+        Self {
+            window_minutes: 10,
+            asleep_below_pct: 10,
+            awake_at_pct: 25,
+        }
+    }
+}
+
+/// Thresholds for judging whether each gateway is responding to the controller.
+///
+/// A gateway is online while it has responded within the last `stale_after_secs`, stale until it
+/// has gone `offline_after_secs` without responding, and offline after that. Its status is
+/// emitted whenever this changes, and every `interval_secs` otherwise.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct GatewayStatus {
+    pub stale_after_secs: u64,
+    pub offline_after_secs: u64,
+    pub interval_secs: u64,
+}
+
+impl Default for GatewayStatus {
+    fn default() -> Self {
+        // A gateway which is being polled responds many times a minute
+        Self {
+            stale_after_secs: 60,
+            offline_after_secs: 300,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// The threshold for a pause in a node's power reports to count as a gap.
+///
+/// Healthy nodes report about every 20 seconds, so a longer pause means that reports were lost,
+/// or that the node stopped sending them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct NodeGaps {
+    pub threshold_secs: u64,
+}
+
+impl Default for NodeGaps {
+    fn default() -> Self {
+        Self {
+            threshold_secs: 120,
+        }
+    }
+}
+
+/// When to restore a gateway's slot clock from the persistent state after a restart.
+///
+/// Power reports can't be placed in time until their gateway's slot counter is observed, which on
+/// a quiet bus can take a minute. A recent observation saved before the restart stands in until
+/// then, unless the gateway's slot counter turns out to have restarted too.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SlotClockRestore {
+    /// The greatest age of a saved observation which is used, or 0 to never restore slot clocks.
+    pub max_age_secs: u64,
+}
+
+impl Default for SlotClockRestore {
+    fn default() -> Self {
+        Self { max_age_secs: 600 }
+    }
+}
+
+/// A policy for combining gateway information learned during an enumeration with existing state.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum EnumerationMerge {
+    /// Replace all existing gateway information with the information learned during the
+    /// enumeration, forgetting gateways which were not enumerated.
+    Replace,
+    /// Update existing gateway information with the information learned during the enumeration,
+    /// preferring the new information, and retaining gateways which were not enumerated.
+    #[default]
+    Merge,
+}
+
+/// The time zone in which calendar days are reckoned.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TimeZone {
+    /// The system's local time zone.
+    #[default]
+    Local,
+    /// Coordinated Universal Time.
+    Utc,
+}
+
+impl TimeZone {
+    /// The calendar date of a timestamp in this time zone.
+    pub fn date(&self, timestamp: DateTime<Local>) -> NaiveDate {
+        match self {
+            TimeZone::Local => timestamp.date_naive(),
+            TimeZone::Utc => timestamp.naive_utc().date(),
+        }
+    }
+
+    /// The time of day of a timestamp in this time zone.
+    pub fn time(&self, timestamp: DateTime<Local>) -> NaiveTime {
+        match self {
+            TimeZone::Local => timestamp.time(),
+            TimeZone::Utc => timestamp.naive_utc().time(),
+        }
+    }
+
+    /// A timestamp with this time zone's offset, as it should be written in messages.
+    pub fn datetime(&self, timestamp: DateTime<Local>) -> DateTime<FixedOffset> {
+        match self {
+            TimeZone::Local => timestamp.fixed_offset(),
+            TimeZone::Utc => timestamp.to_utc().fixed_offset(),
+        }
+    }
+}
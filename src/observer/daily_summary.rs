@@ -0,0 +1,209 @@
+use super::config::TimeZone;
+use super::event::{DailySummaryEvent, Gateway, Node, PowerReportEvent};
+use crate::memory::btree_map_bytes;
+use crate::pv::LongAddress;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Daily extremes for each node, keyed by the node's hardware address.
+///
+/// Each node accumulates data for a single day at a time. Accumulators are removed and
+/// summarized once their day has ended.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DailySummaries(BTreeMap<LongAddress, DailyAccumulator>);
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for DailySummaries {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "DailySummaries".into()
+    }
+
+    fn schema_id() -> std::borrow::Cow<'static, str> {
+        concat!(module_path!(), "::DailySummaries").into()
+    }
+
+    fn json_schema(gen: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        <Vec<DailyAccumulator>>::json_schema(gen)
+    }
+}
+
+impl DailySummaries {
+    /// Accumulate a power report.
+    ///
+    /// If this report belongs to a later day than the node's current accumulator, the current
+    /// accumulator is removed and its summary is returned. Reports belonging to an earlier day
+    /// are ignored.
+    ///
+    /// Reports from nodes with unknown hardware addresses are ignored.
+    pub fn push(
+        &mut self,
+        report: &PowerReportEvent,
+        time_zone: TimeZone,
+    ) -> Option<DailySummaryEvent> {
+        let long_address = report.node.address?;
+        let date = time_zone.date(report.timestamp);
+
+        let Some(accumulator) = self.0.get_mut(&long_address) else {
+            self.0.insert(
+                long_address,
+                DailyAccumulator::new(long_address, date, report, time_zone),
+            );
+            return None;
+        };
+
+        if date == accumulator.date {
+            accumulator.push(report, time_zone);
+            None
+        } else if date > accumulator.date {
+            let previous = std::mem::replace(
+                accumulator,
+                DailyAccumulator::new(long_address, date, report, time_zone),
+            );
+            Some(previous.summary(false))
+        } else {
+            None
+        }
+    }
+
+    /// Remove and summarize every accumulator for a day before `today`.
+    pub fn roll_over(&mut self, today: NaiveDate) -> Vec<DailySummaryEvent> {
+        let ended: Vec<LongAddress> = self
+            .0
+            .iter()
+            .filter(|(_, accumulator)| accumulator.date < today)
+            .map(|(long_address, _)| *long_address)
+            .collect();
+
+        ended
+            .into_iter()
+            .filter_map(|long_address| self.0.remove(&long_address))
+            .map(|accumulator| accumulator.summary(false))
+            .collect()
+    }
+
+    /// Summarize every accumulator as it stands, without removing any of them.
+    pub fn partial(&self) -> Vec<DailySummaryEvent> {
+        self.0
+            .values()
+            .map(|accumulator| accumulator.summary(true))
+            .collect()
+    }
+
+    /// The approximate number of bytes these accumulators occupy.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<LongAddress, DailyAccumulator>(self.0.len())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct DailyAccumulator {
+    long_address: LongAddress,
+    date: NaiveDate,
+    gateway: Gateway,
+    node: Node,
+    reports: u64,
+    first_report: DateTime<Local>,
+    last_report: DateTime<Local>,
+    max_power: f64,
+    max_voltage_in: f64,
+    min_morning_voltage_in: Option<f64>,
+    min_temperature: f64,
+    max_temperature: f64,
+}
+
+impl DailyAccumulator {
+    fn new(
+        long_address: LongAddress,
+        date: NaiveDate,
+        report: &PowerReportEvent,
+        time_zone: TimeZone,
+    ) -> Self {
+        Self {
+            long_address,
+            date,
+            gateway: report.gateway,
+            node: report.node,
+            reports: 1,
+            first_report: report.timestamp,
+            last_report: report.timestamp,
+            max_power: power(report),
+            max_voltage_in: report.voltage_in,
+            min_morning_voltage_in: is_morning(report, time_zone).then_some(report.voltage_in),
+            min_temperature: report.temperature,
+            max_temperature: report.temperature,
+        }
+    }
+
+    fn push(&mut self, report: &PowerReportEvent, time_zone: TimeZone) {
+        self.gateway = report.gateway;
+        self.node = report.node;
+        self.reports += 1;
+        self.first_report = self.first_report.min(report.timestamp);
+        self.last_report = self.last_report.max(report.timestamp);
+        self.max_power = self.max_power.max(power(report));
+        self.max_voltage_in = self.max_voltage_in.max(report.voltage_in);
+        if is_morning(report, time_zone) {
+            self.min_morning_voltage_in = Some(match self.min_morning_voltage_in {
+                Some(v) => v.min(report.voltage_in),
+                None => report.voltage_in,
+            });
+        }
+        self.min_temperature = self.min_temperature.min(report.temperature);
+        self.max_temperature = self.max_temperature.max(report.temperature);
+    }
+
+    fn summary(&self, partial: bool) -> DailySummaryEvent {
+        DailySummaryEvent {
+            gateway: self.gateway,
+            node: self.node,
+            date: self.date,
+            partial,
+            reports: self.reports,
+            first_report: self.first_report,
+            last_report: self.last_report,
+            max_power: self.max_power,
+            max_voltage_in: self.max_voltage_in,
+            min_morning_voltage_in: self.min_morning_voltage_in,
+            min_temperature: self.min_temperature,
+            max_temperature: self.max_temperature,
+            source: None,
+        }
+    }
+}
+
+fn power(report: &PowerReportEvent) -> f64 {
+    report.voltage_out * report.current
+}
+
+fn is_morning(report: &PowerReportEvent, time_zone: TimeZone) -> bool {
+    time_zone.time(report.timestamp) < NaiveTime::from_hms_opt(12, 0, 0).unwrap()
+}
+
+// Serialize as Vec<DailyAccumulator>
+impl Serialize for DailySummaries {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let entries: Vec<&DailyAccumulator> = self.0.values().collect();
+        entries.serialize(serializer)
+    }
+}
+
+// Deserialize from Vec<DailyAccumulator>
+impl<'de> Deserialize<'de> for DailySummaries {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let entries = <Vec<DailyAccumulator>>::deserialize(deserializer)?;
+        Ok(Self(
+            entries
+                .into_iter()
+                .map(|accumulator| (accumulator.long_address, accumulator))
+                .collect(),
+        ))
+    }
+}
@@ -0,0 +1,229 @@
+//! Diagnostics produced by an observer.
+//!
+//! Diagnostics describe the health of the observed system and of the observer itself. They are
+//! kept separate from measurements, so that consumers storing measurements are not burdened by
+//! them, and so that they can be routed to a different destination.
+//!
+//! Each diagnostic carries a stable [`Code`], which consumers may match against.
+
+use super::event::DiagnosticEvent;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// The severity of a diagnostic.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Something noteworthy happened, but no data was lost.
+    Info,
+    /// Something unexpected happened, possibly resulting in lost or degraded data.
+    Warning,
+    /// Something went wrong, resulting in lost data.
+    Error,
+}
+
+impl From<Severity> for log::Level {
+    fn from(value: Severity) -> Self {
+        match value {
+            Severity::Info => log::Level::Info,
+            Severity::Warning => log::Level::Warn,
+            Severity::Error => log::Level::Error,
+        }
+    }
+}
+
+/// A stable identifier for a kind of diagnostic.
+///
+/// The serialized form of each code is its `snake_case` name, as returned by [`Code::as_str()`].
+/// These strings are stable: codes may be added, but existing codes will not be renamed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Code {
+    /// A power report was received from a gateway whose slot counter has not yet been observed,
+    /// so its timestamp could not be determined. The report was discarded.
+    PowerReportWithoutSlotClock,
+
+    /// A power report contained an invalid slot counter, so its timestamp could not be
+    /// determined. The report was discarded.
+    PowerReportInvalidSlotCounter,
+
+    /// Events are being produced faster than a configured rate limit allows, and are being
+    /// dropped. Emitted once each time limiting engages.
+    EventsRateLimited,
+
+    /// A gateway was seen in traffic, but its hardware address is unknown, so events from it
+    /// carry only its link layer ID. Gateway identities are learned when the controller
+    /// enumerates, which may be days away. Emitted once per gateway.
+    GatewayIdentityUnknown,
+
+    /// A node table walk was abandoned partway through, and a new walk began. Repeated restarts
+    /// delay the resolution of node hardware addresses.
+    NodeTableWalkRestarted,
+
+    /// A gateway has repeatedly reported that it has no free transmit buffers, meaning it can't
+    /// accept more commands from the controller. Emitted once each time this begins.
+    GatewayTxBuffersExhausted,
+
+    /// A node's packets have been going missing at a high rate, as judged by gaps in their
+    /// sequence numbers. Emitted once each time this begins.
+    SustainedPacketLoss,
+
+    /// A node's home gateway has changed repeatedly in a short time, meaning its reports are
+    /// arriving through more than one gateway. Emitted once each time this begins.
+    NodeGatewayFlapping,
+
+    /// The timestamps of a capture being replayed stepped backwards, typically because the
+    /// capturing machine's clock was corrected. Unless replay is smoothing its clock, events
+    /// timestamped after the step may be earlier than those before it.
+    CaptureClockStepped,
+
+    /// Many power reports from a gateway have come from node IDs missing from its node table,
+    /// suggesting that the gateway has renumbered its nodes since the table was last walked. Only
+    /// emitted when node table validation is enabled, and once per node table.
+    NodeTableStale,
+
+    /// The controller has been polling a gateway much less often than its fair share, so the
+    /// gateway's nodes report late. Emitted once each time this begins.
+    GatewayPollingUnfair,
+
+    /// A node reported a firmware version different from the one it reported before, meaning
+    /// that it was updated, or that a different module now has its hardware address.
+    NodeFirmwareChanged,
+
+    /// A gateway's slot counter didn't fit the slot clock restored for it from before the observer
+    /// restarted, meaning that the controller restarted too. Reports already placed with the
+    /// restored clock were marked `timestamp_uncertain`, and the clock starts over.
+    RestoredSlotClockDiscarded,
+}
+
+impl Code {
+    /// Every known code.
+    pub const ALL: &'static [Code] = &[
+        Code::PowerReportWithoutSlotClock,
+        Code::PowerReportInvalidSlotCounter,
+        Code::EventsRateLimited,
+        Code::GatewayIdentityUnknown,
+        Code::NodeTableWalkRestarted,
+        Code::GatewayTxBuffersExhausted,
+        Code::SustainedPacketLoss,
+        Code::NodeGatewayFlapping,
+        Code::CaptureClockStepped,
+        Code::NodeTableStale,
+        Code::GatewayPollingUnfair,
+        Code::NodeFirmwareChanged,
+        Code::RestoredSlotClockDiscarded,
+    ];
+
+    /// The stable string representation of this code.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::PowerReportWithoutSlotClock => "power_report_without_slot_clock",
+            Code::PowerReportInvalidSlotCounter => "power_report_invalid_slot_counter",
+            Code::EventsRateLimited => "events_rate_limited",
+            Code::GatewayIdentityUnknown => "gateway_identity_unknown",
+            Code::NodeTableWalkRestarted => "node_table_walk_restarted",
+            Code::GatewayTxBuffersExhausted => "gateway_tx_buffers_exhausted",
+            Code::SustainedPacketLoss => "sustained_packet_loss",
+            Code::NodeGatewayFlapping => "node_gateway_flapping",
+            Code::CaptureClockStepped => "capture_clock_stepped",
+            Code::NodeTableStale => "node_table_stale",
+            Code::GatewayPollingUnfair => "gateway_polling_unfair",
+            Code::NodeFirmwareChanged => "node_firmware_changed",
+            Code::RestoredSlotClockDiscarded => "restored_slot_clock_discarded",
+        }
+    }
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A destination for diagnostics.
+#[derive(Default)]
+pub enum Output {
+    /// Write diagnostics to the `log` crate at a level corresponding to their severity.
+    #[default]
+    Log,
+    /// Write diagnostics to standard output as JSON, one per line.
+    Stdout,
+    /// Write diagnostics to standard error as JSON, one per line.
+    Stderr,
+    /// Write diagnostics to an arbitrary destination as JSON, one per line.
+    Writer(Box<dyn Write + Send>),
+    /// Deliver diagnostics to the observer's event sink as `Event::Diagnostic`, along with every
+    /// other event, so that the sink can route them.
+    Events,
+    /// Discard diagnostics.
+    Discard,
+}
+
+impl std::fmt::Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Output::Log => f.write_str("Output::Log"),
+            Output::Stdout => f.write_str("Output::Stdout"),
+            Output::Stderr => f.write_str("Output::Stderr"),
+            Output::Writer(_) => f.write_str("Output::Writer(..)"),
+            Output::Events => f.write_str("Output::Events"),
+            Output::Discard => f.write_str("Output::Discard"),
+        }
+    }
+}
+
+impl Output {
+    /// Write a diagnostic to this destination.
+    pub fn write(&mut self, diagnostic: &DiagnosticEvent) {
+        let result = match self {
+            Output::Log => {
+                log::log!(
+                    diagnostic.severity.into(),
+                    "{}: {}",
+                    diagnostic.code,
+                    diagnostic.message
+                );
+                Ok(())
+            }
+            Output::Stdout => write_json(&mut std::io::stdout().lock(), diagnostic),
+            Output::Stderr => write_json(&mut std::io::stderr().lock(), diagnostic),
+            Output::Writer(writer) => write_json(writer, diagnostic),
+            // The observer delivers these itself
+            Output::Events | Output::Discard => Ok(()),
+        };
+
+        if let Err(e) = result {
+            log::error!("error writing diagnostic {}: {}", diagnostic.code, e);
+        }
+    }
+}
+
+fn write_json<W: Write + ?Sized>(
+    writer: &mut W,
+    diagnostic: &DiagnosticEvent,
+) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, diagnostic)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        for code in Code::ALL {
+            assert_eq!(
+                serde_json::to_string(code).unwrap(),
+                format!("\"{}\"", code.as_str())
+            );
+            assert_eq!(
+                serde_json::from_str::<Code>(&format!("\"{}\"", code.as_str())).unwrap(),
+                *code
+            );
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::pv::application::PowerReport;
+use crate::pv::NodeID;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// How long power reports wait for their gateway's slot clock.
+pub const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The most power reports waiting for any one gateway's slot clock.
+pub const MAX_QUEUED_PER_GATEWAY: usize = 256;
+
+/// A power report received before its gateway's slot clock was established.
+#[derive(Debug, Clone)]
+pub struct EarlyReport {
+    pub node_id: NodeID,
+    pub power_report: PowerReport,
+    /// The time at which the report was received, which places its slot counter in time.
+    pub received: SystemTime,
+}
+
+/// Power reports waiting for their gateway's slot clock, by gateway, in the order they arrived.
+///
+/// A gateway's slot counter isn't observed until the controller next polls it, so reports
+/// received just after a restart would otherwise be lost. Reports are held until the slot clock is
+/// established, or for at most [`TIMEOUT`].
+#[derive(Debug, Clone, Default)]
+pub struct EarlyReports(BTreeMap<GatewayID, Queue>);
+
+#[derive(Debug, Clone, Default)]
+struct Queue {
+    reports: VecDeque<EarlyReport>,
+    /// Whether reports have been discarded to make room since the queue was last released or
+    /// expired.
+    overflowing: bool,
+}
+
+/// What became of the reports waiting for a gateway's slot clock.
+#[derive(Debug, Clone, Default)]
+pub struct Released {
+    /// The reports which can now be placed in time, in the order they arrived.
+    pub reports: Vec<EarlyReport>,
+    /// The number of reports which waited too long, and were discarded.
+    pub dropped: u64,
+}
+
+impl EarlyReports {
+    /// Hold a power report until its gateway's slot clock is established.
+    ///
+    /// If an older report was discarded to make room, returns `Some(engaged)`, where `engaged` is
+    /// true if this is the first report discarded since the queue was last released or expired.
+    pub fn push(&mut self, gateway_id: GatewayID, report: EarlyReport) -> Option<bool> {
+        let queue = self.0.entry(gateway_id).or_default();
+        let overflowed = if queue.reports.len() == MAX_QUEUED_PER_GATEWAY {
+            queue.reports.pop_front();
+            Some(!std::mem::replace(&mut queue.overflowing, true))
+        } else {
+            None
+        };
+        queue.reports.push_back(report);
+        overflowed
+    }
+
+    /// Release the reports waiting for a gateway's slot clock, which is now established.
+    ///
+    /// Reports which waited longer than [`TIMEOUT`] as of `now` are counted as dropped instead.
+    pub fn release(&mut self, gateway_id: GatewayID, now: SystemTime) -> Released {
+        let Some(queue) = self.0.remove(&gateway_id) else {
+            return Released::default();
+        };
+        let mut released = Released {
+            reports: Vec::with_capacity(queue.reports.len()),
+            dropped: 0,
+        };
+        for report in queue.reports {
+            if expired(report.received, now) {
+                released.dropped += 1;
+            } else {
+                released.reports.push(report);
+            }
+        }
+        released
+    }
+
+    /// Give up on reports which have waited too long, returning how many were dropped from each
+    /// gateway.
+    pub fn expire(&mut self, now: SystemTime) -> BTreeMap<GatewayID, u64> {
+        let mut dropped = BTreeMap::new();
+        self.0.retain(|gateway_id, queue| {
+            let before = queue.reports.len();
+            queue
+                .reports
+                .retain(|report| !expired(report.received, now));
+            let expired = before - queue.reports.len();
+            if expired > 0 {
+                dropped.insert(*gateway_id, expired as u64);
+                queue.overflowing = false;
+            }
+            !queue.reports.is_empty()
+        });
+        dropped
+    }
+
+    /// Move a gateway's waiting reports to a new gateway ID.
+    pub fn reassign(&mut self, old: GatewayID, new: GatewayID) {
+        if let Some(queue) = self.0.remove(&old) {
+            self.0.insert(new, queue);
+        }
+    }
+
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<GatewayID, Queue>(self.0.len())
+            + self
+                .0
+                .values()
+                .map(|queue| queue.reports.capacity() * std::mem::size_of::<EarlyReport>())
+                .sum::<usize>()
+    }
+}
+
+fn expired(received: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(received)
+        .is_ok_and(|waited| waited > TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pv::application::U12Pair;
+    use crate::pv::physical::RSSI;
+    use crate::pv::SlotCounter;
+
+    fn report(node: u16, received: SystemTime) -> EarlyReport {
+        EarlyReport {
+            node_id: NodeID::try_from(node).unwrap(),
+            power_report: PowerReport {
+                voltage_in_and_voltage_out: U12Pair::try_from((500, 250)).unwrap(),
+                dc_dc_duty_cycle: 255,
+                current_and_temperature: U12Pair::try_from((200, 250)).unwrap(),
+                unknown: [0, 0, 0],
+                slot_counter: SlotCounter::ZERO,
+                rssi: RSSI(100),
+            },
+            received,
+        }
+    }
+
+    fn nodes(released: &Released) -> Vec<u16> {
+        released
+            .reports
+            .iter()
+            .map(|report| u16::from(report.node_id))
+            .collect()
+    }
+
+    #[test]
+    fn release() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let other = GatewayID::try_from(0x1202).unwrap();
+        let mut reports = EarlyReports::default();
+
+        // Reports are released in arrival order, without those of other gateways
+        assert_eq!(reports.push(gateway_id, report(3, t)), None);
+        assert_eq!(reports.push(other, report(4, t)), None);
+        assert_eq!(
+            reports.push(gateway_id, report(2, t + Duration::from_secs(1))),
+            None
+        );
+        let released = reports.release(gateway_id, t + Duration::from_secs(2));
+        assert_eq!(nodes(&released), [3, 2]);
+        assert_eq!(released.dropped, 0);
+        assert!(reports.release(gateway_id, t).reports.is_empty());
+
+        // Those which waited too long are dropped
+        let released = reports.release(other, t + TIMEOUT + Duration::from_secs(1));
+        assert_eq!(nodes(&released), [] as [u16; 0]);
+        assert_eq!(released.dropped, 1);
+    }
+
+    #[test]
+    fn overflow() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let mut reports = EarlyReports::default();
+
+        for i in 0..MAX_QUEUED_PER_GATEWAY as u16 {
+            assert_eq!(reports.push(gateway_id, report(2 + i, t)), None);
+        }
+        // Only the first report to overflow the queue engages
+        assert_eq!(reports.push(gateway_id, report(1000, t)), Some(true));
+        assert_eq!(reports.push(gateway_id, report(1001, t)), Some(false));
+
+        // The oldest reports made room for the newest
+        let released = reports.release(gateway_id, t);
+        assert_eq!(released.reports.len(), MAX_QUEUED_PER_GATEWAY);
+        assert_eq!(nodes(&released)[0], 4);
+        assert_eq!(nodes(&released).last(), Some(&1001));
+        assert_eq!(released.dropped, 0);
+
+        // Releasing the queue disengages it
+        for i in 0..MAX_QUEUED_PER_GATEWAY as u16 {
+            reports.push(gateway_id, report(2 + i, t));
+        }
+        assert_eq!(reports.push(gateway_id, report(1000, t)), Some(true));
+
+        // So does expiring reports from it
+        let later = t + Duration::from_secs(1);
+        reports.push(gateway_id, report(1001, later));
+        reports.expire(later + TIMEOUT);
+        for i in 0..MAX_QUEUED_PER_GATEWAY as u16 - 1 {
+            assert_eq!(reports.push(gateway_id, report(2 + i, later)), None);
+        }
+        assert_eq!(reports.push(gateway_id, report(1000, later)), Some(true));
+    }
+
+    #[test]
+    fn expire() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let mut reports = EarlyReports::default();
+        reports.push(gateway_id, report(2, t));
+        reports.push(gateway_id, report(3, t + Duration::from_secs(60)));
+
+        assert_eq!(reports.expire(t + TIMEOUT), [].into());
+        assert_eq!(
+            reports.expire(t + TIMEOUT + Duration::from_secs(1)),
+            [(gateway_id, 1)].into()
+        );
+        assert_eq!(
+            reports.expire(t + TIMEOUT + Duration::from_secs(61)),
+            [(gateway_id, 1)].into()
+        );
+        assert!(reports.release(gateway_id, t).reports.is_empty());
+    }
+}
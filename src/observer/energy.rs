@@ -0,0 +1,162 @@
+use super::config::TimeZone;
+use super::event::PowerReportEvent;
+use crate::memory::btree_map_bytes;
+use crate::pv::link::long_address_keys;
+use crate::pv::LongAddress;
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The longest gap between reports over which power is integrated.
+///
+/// A node which stops reporting for longer than this, as every node does overnight, is assumed to
+/// have produced power at its last reported rate for no more than this long.
+const MAX_GAP: TimeDelta = TimeDelta::minutes(15);
+
+/// The energy each node has produced today, keyed by the node's hardware address.
+///
+/// Energy is integrated from each node's power reports using the trapezoidal rule, and starts
+/// again from zero with the first report of each calendar day.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct EnergyAccumulators(
+    #[serde(with = "long_address_keys")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "BTreeMap<String, EnergyAccumulator>")
+    )]
+    BTreeMap<LongAddress, EnergyAccumulator>,
+);
+
+/// One node's energy so far today.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct EnergyAccumulator {
+    date: NaiveDate,
+    energy_wh: f64,
+    last_report: DateTime<Local>,
+    last_power_w: f64,
+}
+
+impl EnergyAccumulators {
+    /// Accumulate a power report, returning the node's energy so far today in watt-hours.
+    ///
+    /// Reports from nodes with unknown hardware addresses are ignored. A report timestamped
+    /// before the node's latest report adds nothing and leaves the latest report in place, so that
+    /// the interval since the latest report isn't integrated twice.
+    pub fn push(&mut self, report: &PowerReportEvent, time_zone: TimeZone) -> Option<f64> {
+        let long_address = report.node.address?;
+        let date = time_zone.date(report.timestamp);
+        let fresh = EnergyAccumulator {
+            date,
+            energy_wh: 0.0,
+            last_report: report.timestamp,
+            last_power_w: report.power_w,
+        };
+
+        let accumulator = self.0.entry(long_address).or_insert(fresh);
+        if report.timestamp < accumulator.last_report {
+            return Some(accumulator.energy_wh);
+        }
+        if accumulator.date != date {
+            *accumulator = fresh;
+        }
+
+        let elapsed = (report.timestamp - accumulator.last_report).min(MAX_GAP);
+        if elapsed > TimeDelta::zero() {
+            let hours = elapsed.num_milliseconds() as f64 / 3_600_000.0;
+            accumulator.energy_wh += (accumulator.last_power_w + report.power_w) / 2.0 * hours;
+        }
+        accumulator.last_report = report.timestamp;
+        accumulator.last_power_w = report.power_w;
+
+        Some(accumulator.energy_wh)
+    }
+
+    /// The approximate number of bytes these accumulators occupy.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<LongAddress, EnergyAccumulator>(self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::link::GatewayID;
+    use crate::observer::event::{Gateway, Node};
+    use crate::pv::physical::RSSI;
+    use crate::pv::NodeID;
+    use chrono::TimeZone as _;
+
+    const ADDRESS: LongAddress = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]);
+
+    fn report(timestamp: DateTime<Local>, power_w: f64) -> PowerReportEvent {
+        PowerReportEvent {
+            gateway: Gateway {
+                id: GatewayID::try_from(0x1201).unwrap(),
+                address: None,
+                provenance: None,
+            },
+            node: Node {
+                id: NodeID::try_from(2).unwrap(),
+                address: Some(ADDRESS),
+                provenance: None,
+                home_gateway: None,
+            },
+            timestamp,
+            voltage_in: 30.0,
+            voltage_out: 30.0,
+            current: power_w / 30.0,
+            power_w,
+            dc_dc_duty_cycle: 1.0,
+            temperature: 25.0,
+            rssi: RSSI(120),
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn integrate() {
+        let t0 = Local.with_ymd_and_hms(2024, 8, 24, 9, 0, 0).unwrap();
+        let minutes = |m: i64| t0 + TimeDelta::minutes(m);
+        let mut energy = EnergyAccumulators::default();
+        let mut push = |t, power_w| energy.push(&report(t, power_w), TimeZone::Local).unwrap();
+
+        // The first report only starts the day
+        assert_eq!(push(t0, 100.0), 0.0);
+
+        // Fifteen minutes ramping from 100 W to 200 W is 37.5 Wh
+        assert_eq!(push(minutes(15), 200.0), 37.5);
+
+        // A report from the past adds nothing, and integration continues from the latest report,
+        // so ten minutes at 200 W adds 33.3 Wh
+        assert_eq!(push(minutes(10), 100.0), 37.5);
+        assert!((push(minutes(25), 200.0) - 70.833).abs() < 0.001);
+
+        // An hour without reports counts for no more than 15 minutes
+        assert!((push(minutes(85), 200.0) - 120.833).abs() < 0.001);
+
+        // Reports without a hardware address are ignored
+        let mut anonymous = report(minutes(100), 200.0);
+        anonymous.node.address = None;
+        assert_eq!(energy.push(&anonymous, TimeZone::Local), None);
+
+        // The next day starts again from zero
+        let tomorrow = t0 + TimeDelta::days(1);
+        assert_eq!(
+            energy.push(&report(tomorrow, 50.0), TimeZone::Local),
+            Some(0.0)
+        );
+
+        let json = serde_json::to_string(&energy).unwrap();
+        assert_eq!(
+            serde_json::from_str::<EnergyAccumulators>(&json).unwrap(),
+            energy
+        );
+    }
+}
@@ -1,17 +1,297 @@
+use super::diagnostic::{Code, Severity};
 use super::*;
 use crate::pv;
 use crate::pv::link::InvalidSlotNumber;
 use crate::pv::physical::RSSI;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate};
 
 /// An event produced by an observer.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename = "snake_case")]
 pub enum Event {
     PowerReport(PowerReportEvent),
+    Diagnostic(DiagnosticEvent),
+    DailySummary(DailySummaryEvent),
+    NodeTableProgress(NodeTableProgressEvent),
+    NodeTable(NodeTableEvent),
+    NodeTableChanged(NodeTableChangedEvent),
+    CommandTimeout(CommandTimeoutEvent),
+    ArrayAsleep(ArrayStateEvent),
+    ArrayWake(ArrayStateEvent),
+    Alert(AlertEvent),
+    AlertCleared(AlertEvent),
+    NetworkStatus(NetworkStatusEvent),
+    Broadcast(BroadcastEvent),
+    NodeIdentity(NodeIdentityEvent),
+    NodeConfiguration(Box<NodeConfigurationEvent>),
+    NodeDiagnostic(NodeDiagnosticEvent),
+    NodeGap(NodeGapEvent),
+    SlotClockUpdated(SlotClockUpdatedEvent),
+    GatewayStatus(GatewayStatusEvent),
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+impl Event {
+    /// The kind of this event.
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::PowerReport(_) => EventKind::PowerReport,
+            Event::Diagnostic(_) => EventKind::Diagnostic,
+            Event::DailySummary(_) => EventKind::DailySummary,
+            Event::NodeTableProgress(_) => EventKind::NodeTableProgress,
+            Event::NodeTable(_) => EventKind::NodeTable,
+            Event::NodeTableChanged(_) => EventKind::NodeTableChanged,
+            Event::CommandTimeout(_) => EventKind::CommandTimeout,
+            Event::ArrayAsleep(_) => EventKind::ArrayAsleep,
+            Event::ArrayWake(_) => EventKind::ArrayWake,
+            Event::Alert(_) => EventKind::Alert,
+            Event::AlertCleared(_) => EventKind::AlertCleared,
+            Event::NetworkStatus(_) => EventKind::NetworkStatus,
+            Event::Broadcast(_) => EventKind::Broadcast,
+            Event::NodeIdentity(_) => EventKind::NodeIdentity,
+            Event::NodeConfiguration(_) => EventKind::NodeConfiguration,
+            Event::NodeDiagnostic(_) => EventKind::NodeDiagnostic,
+            Event::NodeGap(_) => EventKind::NodeGap,
+            Event::SlotClockUpdated(_) => EventKind::SlotClockUpdated,
+            Event::GatewayStatus(_) => EventKind::GatewayStatus,
+        }
+    }
+
+    /// When the event happened. A daily summary happened at its day's last report.
+    pub fn timestamp(&self) -> DateTime<Local> {
+        match self {
+            Event::PowerReport(event) => event.timestamp,
+            Event::Diagnostic(event) => event.timestamp,
+            Event::DailySummary(event) => event.last_report,
+            Event::NodeTableProgress(event) => event.timestamp,
+            Event::NodeTable(event) => event.timestamp,
+            Event::NodeTableChanged(event) => event.timestamp,
+            Event::CommandTimeout(event) => event.timestamp,
+            Event::ArrayAsleep(event) | Event::ArrayWake(event) => event.timestamp,
+            Event::Alert(event) | Event::AlertCleared(event) => event.timestamp,
+            Event::NetworkStatus(event) => event.timestamp,
+            Event::Broadcast(event) => event.timestamp,
+            Event::NodeIdentity(event) => event.timestamp,
+            Event::NodeConfiguration(event) => event.timestamp,
+            Event::NodeDiagnostic(event) => event.timestamp,
+            Event::NodeGap(event) => event.timestamp,
+            Event::SlotClockUpdated(event) => event.system_time,
+            Event::GatewayStatus(event) => event.timestamp,
+        }
+    }
+
+    /// The nodes to which the event pertains.
+    pub fn nodes(&self) -> Vec<&Node> {
+        match self {
+            Event::PowerReport(event) => vec![&event.node],
+            Event::Diagnostic(event) => event.node.iter().collect(),
+            Event::DailySummary(event) => vec![&event.node],
+            Event::NodeTable(event) => event.nodes.iter().collect(),
+            Event::NodeTableChanged(event) => (event.added.iter())
+                .chain(&event.removed)
+                .chain(
+                    event
+                        .readdressed
+                        .iter()
+                        .map(|readdressed| &readdressed.node),
+                )
+                .collect(),
+            Event::Alert(event) | Event::AlertCleared(event) => vec![&event.node],
+            Event::NodeIdentity(event) => vec![&event.node],
+            Event::NodeConfiguration(event) => vec![&event.node],
+            Event::NodeDiagnostic(event) => vec![&event.node],
+            Event::NodeGap(event) => vec![&event.node],
+            Event::NodeTableProgress(_)
+            | Event::CommandTimeout(_)
+            | Event::ArrayAsleep(_)
+            | Event::ArrayWake(_)
+            | Event::NetworkStatus(_)
+            | Event::Broadcast(_)
+            | Event::SlotClockUpdated(_)
+            | Event::GatewayStatus(_) => Vec::new(),
+        }
+    }
+
+    /// Label the event with the source through which its traffic was received.
+    pub fn set_source(&mut self, source: &str) {
+        *self.source_mut() = Some(source.into());
+    }
+
+    fn source_mut(&mut self) -> &mut Option<String> {
+        match self {
+            Event::PowerReport(event) => &mut event.source,
+            Event::Diagnostic(event) => &mut event.source,
+            Event::DailySummary(event) => &mut event.source,
+            Event::NodeTableProgress(event) => &mut event.source,
+            Event::NodeTable(event) => &mut event.source,
+            Event::NodeTableChanged(event) => &mut event.source,
+            Event::CommandTimeout(event) => &mut event.source,
+            Event::ArrayAsleep(event) | Event::ArrayWake(event) => &mut event.source,
+            Event::Alert(event) | Event::AlertCleared(event) => &mut event.source,
+            Event::NetworkStatus(event) => &mut event.source,
+            Event::Broadcast(event) => &mut event.source,
+            Event::NodeIdentity(event) => &mut event.source,
+            Event::NodeConfiguration(event) => &mut event.source,
+            Event::NodeDiagnostic(event) => &mut event.source,
+            Event::NodeGap(event) => &mut event.source,
+            Event::SlotClockUpdated(event) => &mut event.source,
+            Event::GatewayStatus(event) => &mut event.source,
+        }
+    }
+
+    /// Serialize the event's payload as a single line of JSON.
+    ///
+    /// This is the observer's output format, which omits the variant name.
+    pub fn to_json(&self) -> String {
+        let result = match self {
+            Event::PowerReport(event) => serde_json::to_string(event),
+            Event::Diagnostic(event) => serde_json::to_string(event),
+            Event::DailySummary(event) => serde_json::to_string(event),
+            Event::NodeTableProgress(event) => serde_json::to_string(event),
+            Event::NodeTable(event) => serde_json::to_string(event),
+            Event::NodeTableChanged(event) => serde_json::to_string(event),
+            Event::CommandTimeout(event) => serde_json::to_string(event),
+            Event::ArrayAsleep(event) | Event::ArrayWake(event) => serde_json::to_string(event),
+            Event::Alert(event) | Event::AlertCleared(event) => serde_json::to_string(event),
+            Event::NetworkStatus(event) => serde_json::to_string(event),
+            Event::Broadcast(event) => serde_json::to_string(event),
+            Event::NodeIdentity(event) => serde_json::to_string(event),
+            Event::NodeConfiguration(event) => serde_json::to_string(event),
+            Event::NodeDiagnostic(event) => serde_json::to_string(event),
+            Event::NodeGap(event) => serde_json::to_string(event),
+            Event::SlotClockUpdated(event) => serde_json::to_string(event),
+            Event::GatewayStatus(event) => serde_json::to_string(event),
+        };
+        result.unwrap()
+    }
+
+    /// Serialize the event's payload as a single line of JSON which doesn't vary between runs or
+    /// machines.
+    ///
+    /// This is [`to_json()`](Self::to_json) with object keys sorted and every timestamp written in
+    /// UTC to the microsecond, for comparing output byte for byte.
+    pub fn to_deterministic_json(&self) -> String {
+        let mut value: serde_json::Value = serde_json::from_str(&self.to_json()).unwrap();
+        normalize_timestamps(&mut value);
+        // `Value` keeps object keys in sorted order
+        value.to_string()
+    }
+}
+
+fn normalize_timestamps(value: &mut serde_json::Value) {
+    use serde_json::Value;
+    match value {
+        Value::String(string) => {
+            if let Ok(timestamp) = DateTime::parse_from_rfc3339(string) {
+                *string = timestamp
+                    .with_timezone(&chrono::Utc)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(normalize_timestamps),
+        Value::Object(map) => map.values_mut().for_each(normalize_timestamps),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// The kind of an [`Event`], without its payload.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    PowerReport,
+    Diagnostic,
+    DailySummary,
+    NodeTableProgress,
+    NodeTable,
+    NodeTableChanged,
+    CommandTimeout,
+    ArrayAsleep,
+    ArrayWake,
+    Alert,
+    AlertCleared,
+    NetworkStatus,
+    Broadcast,
+    NodeIdentity,
+    NodeConfiguration,
+    NodeDiagnostic,
+    NodeGap,
+    SlotClockUpdated,
+    GatewayStatus,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 19] = [
+        EventKind::PowerReport,
+        EventKind::Diagnostic,
+        EventKind::DailySummary,
+        EventKind::NodeTableProgress,
+        EventKind::NodeTable,
+        EventKind::NodeTableChanged,
+        EventKind::CommandTimeout,
+        EventKind::ArrayAsleep,
+        EventKind::ArrayWake,
+        EventKind::Alert,
+        EventKind::AlertCleared,
+        EventKind::NetworkStatus,
+        EventKind::Broadcast,
+        EventKind::NodeIdentity,
+        EventKind::NodeConfiguration,
+        EventKind::NodeDiagnostic,
+        EventKind::NodeGap,
+        EventKind::SlotClockUpdated,
+        EventKind::GatewayStatus,
+    ];
+
+    /// The kind's name, as it appears in configuration.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::PowerReport => "power_report",
+            EventKind::Diagnostic => "diagnostic",
+            EventKind::DailySummary => "daily_summary",
+            EventKind::NodeTableProgress => "node_table_progress",
+            EventKind::NodeTable => "node_table",
+            EventKind::NodeTableChanged => "node_table_changed",
+            EventKind::CommandTimeout => "command_timeout",
+            EventKind::ArrayAsleep => "array_asleep",
+            EventKind::ArrayWake => "array_wake",
+            EventKind::Alert => "alert",
+            EventKind::AlertCleared => "alert_cleared",
+            EventKind::NetworkStatus => "network_status",
+            EventKind::Broadcast => "broadcast",
+            EventKind::NodeIdentity => "node_identity",
+            EventKind::NodeConfiguration => "node_configuration",
+            EventKind::NodeDiagnostic => "node_diagnostic",
+            EventKind::NodeGap => "node_gap",
+            EventKind::SlotClockUpdated => "slot_clock_updated",
+            EventKind::GatewayStatus => "gateway_status",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown event kind {0:?}")]
+pub struct UnknownEventKindError(String);
+
+impl std::str::FromStr for EventKind {
+    type Err = UnknownEventKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| UnknownEventKindError(s.into()))
+    }
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Gateway {
     /// The gateway's link layer ID.
     ///
@@ -24,9 +304,14 @@ pub struct Gateway {
     /// This value is permanent and globally unique, but it is not always known.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub address: Option<pv::LongAddress>,
+
+    /// When and how the hardware address was learned, if the observer is configured to say.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<super::provenance::Provenance>,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Node {
     /// The node's ID.
     ///
@@ -39,9 +324,19 @@ pub struct Node {
     /// This value is permanent and globally unique, but it is not always known.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub address: Option<pv::LongAddress>,
+
+    /// When and how the hardware address was learned, if the observer is configured to say.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<super::provenance::Provenance>,
+
+    /// The gateway to which the node belongs, which matters when its hardware address appears
+    /// under more than one gateway. Only known once the hardware address is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_gateway: Option<gateway::link::GatewayID>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PowerReportEvent {
     /// The gateway through which the power report was received.
     pub gateway: Gateway,
@@ -52,42 +347,468 @@ pub struct PowerReportEvent {
     pub voltage_in: f64,
     pub voltage_out: f64,
     pub current: f64,
+    /// The power drawn from the panel in watts, i.e. `voltage_in * current`.
+    ///
+    /// Nodes measure current on the panel side of their DC-DC converter, so `voltage_out * current`
+    /// understates power whenever the converter steps the voltage down. The difference between
+    /// this and the node's output is the converter's loss, which is small.
+    #[serde(default)]
+    pub power_w: f64,
     pub dc_dc_duty_cycle: f64,
     pub temperature: f64,
     pub rssi: RSSI,
+    /// Whether the node ID is missing from the gateway's node table, meaning that the report may
+    /// be misattributed. Only checked when node table validation is enabled.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub node_unverified: bool,
+    /// Whether the report appeared older than the observer's configured maximum age, meaning that
+    /// its gateway held it for so long that `timestamp` may be a whole number of slot counter wraps
+    /// (about four minutes each) too late. Also set for reports placed with a slot clock restored
+    /// from before a restart, which may be wrong if the controller restarted too.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timestamp_uncertain: bool,
+    /// The energy the node has produced so far today, in watt-hours, when the observer is
+    /// configured to accumulate it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy_wh_today: Option<f64>,
+    /// The time since the node's previous power report, in seconds, if its hardware address is
+    /// known and it has reported before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds_since_previous_report: Option<f64>,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 
 impl PowerReportEvent {
+    /// Interpret a power report received at `receive_time`.
     pub fn new(
         gateway: Gateway,
         node: Node,
         slot_clock: &SlotClock,
         report: &pv::application::PowerReport,
+        receive_time: SystemTime,
     ) -> Result<Self, InvalidSlotNumber> {
-        let timestamp = slot_clock.get(report.slot_counter)?;
-
-        let (voltage_in, voltage_out) = report.voltage_in_and_voltage_out.into();
-        let (current, temperature) = report.current_and_temperature.into();
-
-        // XXX: is it correct to sign-extend temperature?
-        // How are below-freezing temperatures reported? (This assumes two's complement.)
-        let temperature = if temperature & 0x800 == 0 {
-            temperature
-        } else {
-            temperature | 0xF000
-        } as i16;
+        let timestamp = slot_clock.get_near(report.slot_counter, receive_time)?;
+        Ok(Self::received_at(gateway, node, report, timestamp.into()))
+    }
 
-        Ok(Self {
+    /// Interpret a power report, taking its timestamp as given rather than from its slot counter.
+    pub fn received_at(
+        gateway: Gateway,
+        node: Node,
+        report: &pv::application::PowerReport,
+        timestamp: DateTime<Local>,
+    ) -> Self {
+        Self {
             gateway,
             node,
-            timestamp: timestamp.into(),
-            voltage_in: voltage_in as f64 / 20.0,   //* 0.05,
-            voltage_out: voltage_out as f64 / 10.0, // * 0.10,
+            timestamp,
+            voltage_in: report.voltage_in(),
+            voltage_out: report.voltage_out(),
             dc_dc_duty_cycle: report.dc_dc_duty_cycle as f64 / 255.0,
-            current: current as f64 / 200.0,        // * 0.005,
-            temperature: temperature as f64 / 10.0, // * 0.01,
+            current: report.current(),
+            power_w: report.voltage_in() * report.current(),
+            temperature: report.temperature(),
             rssi: report.rssi,
-        })
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        }
+    }
+}
+
+/// Extremes observed for a single node over a single calendar day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DailySummaryEvent {
+    /// The gateway through which the node's most recent power report was received.
+    pub gateway: Gateway,
+    /// The node being summarized.
+    pub node: Node,
+    /// The calendar day being summarized, in the observer's configured time zone.
+    pub date: NaiveDate,
+    /// Whether the day had not yet ended when this summary was produced.
+    pub partial: bool,
+    /// The number of power reports received during this day.
+    pub reports: u64,
+    pub first_report: DateTime<Local>,
+    pub last_report: DateTime<Local>,
+    /// The greatest output power, in watts.
+    pub max_power: f64,
+    pub max_voltage_in: f64,
+    /// The lowest input voltage reported before noon, if any reports were received before noon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_morning_voltage_in: Option<f64>,
+    pub min_temperature: f64,
+    pub max_temperature: f64,
+    /// The source whose traffic the event summarizes, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// Progress of a node table walk, emitted as each page of the table arrives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeTableProgressEvent {
+    /// The gateway whose node table is being walked.
+    pub gateway: Gateway,
+    /// The time at which this page arrived.
+    pub timestamp: DateTime<Local>,
+    /// The number of entries received so far during this walk.
+    pub entries_so_far: usize,
+    /// The node address at which the most recent page started.
+    pub last_start_address: u16,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A complete node table, emitted when a node table walk finishes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeTableEvent {
+    /// The gateway whose node table was walked.
+    pub gateway: Gateway,
+    /// The time at which the walk finished.
+    pub timestamp: DateTime<Local>,
+    /// Every node in the table.
+    pub nodes: Vec<Node>,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// The changes to a gateway's node table, emitted when a node table walk finishes with a table
+/// which differs from the one before it.
+///
+/// Nodes are identified by their addresses, so a node which was given a new ID appears as
+/// readdressed, rather than as removed and added.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeTableChangedEvent {
+    /// The gateway whose node table was walked.
+    pub gateway: Gateway,
+    /// The time at which the walk finished.
+    pub timestamp: DateTime<Local>,
+    /// Nodes which weren't in the previous table.
+    pub added: Vec<Node>,
+    /// Nodes which aren't in the table any more, with the IDs they had.
+    pub removed: Vec<Node>,
+    /// Nodes which are still in the table under new IDs.
+    pub readdressed: Vec<ReaddressedNode>,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A node which kept its address under a new ID.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ReaddressedNode {
+    /// The node's ID in the previous table.
+    pub previous_id: NodeID,
+    /// The node, under its new ID.
+    pub node: Node,
+}
+
+/// A command which the controller abandoned without receiving a response from the gateway.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CommandTimeoutEvent {
+    /// The gateway to which the command was sent.
+    pub gateway: Gateway,
+    /// The time at which the controller moved on to another command.
+    pub timestamp: DateTime<Local>,
+    /// The PV packet type of the command.
+    pub packet_type: u8,
+    /// The command's sequence number.
+    pub sequence_number: gateway::transport::CommandSequenceNumber,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A gateway's network status changed.
+///
+/// Gateways answer network status requests every few seconds, so this is only emitted when the
+/// response differs from the previous one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NetworkStatusEvent {
+    pub gateway: Gateway,
+    pub timestamp: DateTime<Local>,
+    /// A value which long network status requests appear to set.
+    pub counter: u16,
+    /// The number of nodes in the mesh, reported three ways whose differences aren't known yet.
+    pub node_counts: [u16; 3],
+    /// A field whose meaning isn't known yet.
+    pub unknown: u8,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// Data broadcast by the controller through a gateway.
+///
+/// Broadcasts are how the controller shuts every module down, so a broadcast asserting PV off
+/// means that rapid shutdown was commanded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BroadcastEvent {
+    /// The gateway which acknowledged the broadcast.
+    pub gateway: Gateway,
+    pub timestamp: DateTime<Local>,
+    /// The PV node ID to which the data was sent, which is 0 for every node.
+    pub destination: u16,
+    /// The data, in hex.
+    pub data: String,
+    /// Whether the broadcast asserts PV off, if it says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pv_off: Option<bool>,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// What a node said about itself in response to string requests, emitted whenever it changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeIdentityEvent {
+    pub gateway: Gateway,
+    pub node: Node,
+    pub timestamp: DateTime<Local>,
+    /// The node's firmware version, as it answered a version request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<String>,
+    /// The node's latest response to each other string request, by the name of the request.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub responses: BTreeMap<String, String>,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A node's PV configuration, emitted whenever it differs from what the node reported before.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeConfigurationEvent {
+    pub gateway: Gateway,
+    pub node: Node,
+    pub timestamp: DateTime<Local>,
+    pub configuration: pv::application::PvConfiguration,
+    /// The configuration the node reported before, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<pv::application::PvConfiguration>,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A node's response to a string request, with the request it answers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeDiagnosticEvent {
+    pub gateway: Gateway,
+    pub node: Node,
+    /// The time at which the response was received.
+    pub timestamp: DateTime<Local>,
+    /// The time at which the request was sent.
+    pub requested_at: DateTime<Local>,
+    pub query: pv::application::strings::Query,
+    pub response: pv::application::strings::Response,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A node reporting again after going quiet for longer than the observer's configured threshold,
+/// emitted just before the power report which ends the gap.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeGapEvent {
+    pub gateway: Gateway,
+    pub node: Node,
+    /// The time of the power report which ended the gap.
+    pub timestamp: DateTime<Local>,
+    /// The time since the node's previous power report, in seconds.
+    pub gap_seconds: f64,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A gateway's slot clock moving on to a new thousand slots.
+///
+/// Power reports are timestamped by slot counter, which the observer converts to time using a
+/// model of each gateway's slot clock. This describes the model, for consumers doing their own
+/// timestamp math or investigating odd timestamps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SlotClockUpdatedEvent {
+    pub gateway: Gateway,
+    /// The most recently observed slot counter.
+    #[cfg_attr(feature = "schema", schemars(with = "u16"))]
+    pub slot_counter: SlotCounter,
+    /// The time at which `slot_counter` was observed.
+    pub system_time: DateTime<Local>,
+    /// The estimated duration of each slot in microseconds, which is nominally 5000.
+    pub estimated_slot_duration_us: f64,
+    /// Whether the slot duration was measured rather than assumed to be nominal.
+    pub calibrated: bool,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl SlotClockUpdatedEvent {
+    /// Describe a gateway's slot clock.
+    pub fn new(gateway: Gateway, parameters: &slot_clock::Parameters) -> Self {
+        Self {
+            gateway,
+            slot_counter: parameters.slot_counter,
+            system_time: parameters.system_time.into(),
+            estimated_slot_duration_us: parameters.slot_duration.as_secs_f64() * 1e6,
+            calibrated: parameters.calibrated,
+            source: None,
+        }
+    }
+}
+
+/// Whether a gateway is responding to the controller.
+///
+/// With several gateways on one bus, a gateway which stops responding only shows up as fewer
+/// power reports. These events make it explicit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GatewayStatusEvent {
+    /// The time at which the status was judged.
+    pub timestamp: DateTime<Local>,
+    pub gateway: Gateway,
+    pub status: GatewayState,
+    /// When the gateway last responded to the controller, if it has since the observer started.
+    pub last_response: Option<DateTime<Local>>,
+    /// When the gateway's slot counter was last observed.
+    pub last_slot_counter: Option<DateTime<Local>>,
+    /// The number of receive responses from the gateway since the observer started.
+    pub receive_responses: u64,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum GatewayState {
+    Online,
+    Stale,
+    Offline,
+}
+
+/// The array as a whole going to sleep or waking up.
+///
+/// Nodes only report while their panels produce power, so overnight the whole array falls silent.
+/// Consumers can use these events to tell that silence apart from nodes which have failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ArrayStateEvent {
+    /// The time at which the change was detected.
+    pub timestamp: DateTime<Local>,
+    /// The array's new state.
+    pub state: ArrayState,
+    /// The number of nodes which reported recently.
+    pub reporting_nodes: usize,
+    /// The number of nodes which are expected to report while the array is awake.
+    pub known_nodes: usize,
+    /// The source whose traffic the event summarizes, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ArrayState {
+    Asleep,
+    Awake,
+}
+
+/// An alert rule's condition holding for a node, or no longer holding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AlertEvent {
+    /// The time at which the alert was raised or cleared.
+    pub timestamp: DateTime<Local>,
+    /// The name of the rule.
+    pub rule: String,
+    /// The gateway through which the node's latest power report was received.
+    pub gateway: Gateway,
+    /// The node to which the alert pertains.
+    pub node: Node,
+    /// The value on which the rule was judged, like a temperature or a number of seconds.
+    pub value: f64,
+    /// When the rule's condition started holding.
+    pub since: DateTime<Local>,
+    /// The source whose traffic the event summarizes, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A diagnostic describing the health of the observed system or of the observer itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DiagnosticEvent {
+    /// The time at which this diagnostic was produced.
+    pub timestamp: DateTime<Local>,
+    pub severity: Severity,
+    /// A stable code identifying the kind of diagnostic.
+    pub code: Code,
+    /// A human-readable description, which may change between versions.
+    pub message: String,
+    /// The gateway to which this diagnostic pertains, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<Gateway>,
+    /// The node to which this diagnostic pertains, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<Node>,
+    /// Additional code-specific information.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub context: BTreeMap<String, serde_json::Value>,
+    /// The source through which the event's traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl DiagnosticEvent {
+    pub fn new(severity: Severity, code: Code, message: impl Into<String>) -> Self {
+        Self {
+            timestamp: Local::now(),
+            severity,
+            code,
+            message: message.into(),
+            gateway: None,
+            node: None,
+            context: Default::default(),
+            source: None,
+        }
+    }
+
+    pub fn with_gateway(mut self, gateway: Gateway) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    pub fn with_node(mut self, node: Node) -> Self {
+        self.node = Some(node);
+        self
+    }
+
+    pub fn with_context(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.context.insert(key.into(), value.into());
+        self
     }
 }
 
@@ -101,10 +822,13 @@ mod tests {
         let gateway = Gateway {
             id: 1.try_into().unwrap(),
             address: None,
+            provenance: None,
         };
         let node = Node {
             id: 1.try_into().unwrap(),
             address: None,
+            provenance: None,
+            home_gateway: None,
         };
 
         let rssi = RSSI(100);
@@ -122,7 +846,7 @@ mod tests {
         };
 
         let power_report_event =
-            PowerReportEvent::new(gateway, node, &slot_clock, &power_report).unwrap();
+            PowerReportEvent::new(gateway, node, &slot_clock, &power_report, timestamp).unwrap();
 
         let actual = serde_json::to_string(&power_report_event).unwrap();
         let expected = serde_json::to_string(&PowerReportEvent {
@@ -132,11 +856,49 @@ mod tests {
             voltage_in: 25.0,
             voltage_out: 25.0,
             current: 1.00,
+            power_w: 25.0,
             dc_dc_duty_cycle: 1.0,
             temperature: -0.1,
             rssi,
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
         })
         .unwrap();
         assert_eq!(actual, expected); // floats :|
+        assert!(!actual.contains("node_unverified"));
+        assert!(!actual.contains("timestamp_uncertain"));
+    }
+
+    #[test]
+    fn deterministic_json() {
+        let timestamp = DateTime::parse_from_rfc3339("2024-08-24T07:00:00.25-04:00").unwrap();
+        let event = Event::ArrayAsleep(ArrayStateEvent {
+            timestamp: timestamp.with_timezone(&Local),
+            state: ArrayState::Asleep,
+            reporting_nodes: 0,
+            known_nodes: 4,
+            source: None,
+        });
+
+        // Keys are sorted, and the timestamp is in UTC regardless of the local time zone
+        assert_eq!(
+            event.to_deterministic_json(),
+            r#"{"known_nodes":4,"reporting_nodes":0,"state":"asleep","timestamp":"2024-08-24T11:00:00.250000Z"}"#
+        );
+    }
+
+    #[test]
+    fn kind_names() {
+        for kind in EventKind::ALL {
+            assert_eq!(
+                serde_json::to_string(&kind).unwrap(),
+                format!("\"{}\"", kind)
+            );
+            assert_eq!(kind.as_str().parse(), Ok(kind));
+        }
+        assert!("diagnostics".parse::<EventKind>().is_err());
     }
 }
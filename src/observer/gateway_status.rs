@@ -0,0 +1,177 @@
+use super::config::GatewayStatus;
+use super::event::GatewayState;
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+/// Whether each gateway is responding to the controller.
+#[derive(Debug, Clone, Default)]
+pub struct GatewayStatusTracker {
+    gateways: BTreeMap<GatewayID, Activity>,
+}
+
+/// What's been heard from a gateway.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Activity {
+    /// When the gateway was first tracked.
+    pub since: SystemTime,
+    pub last_response: Option<SystemTime>,
+    pub last_slot_counter: Option<SystemTime>,
+    pub receive_responses: u64,
+    /// The status last reported, and when.
+    reported: Option<(GatewayState, SystemTime)>,
+}
+
+impl GatewayStatusTracker {
+    /// Note that a gateway is known to exist, so that it's reported even if it never responds.
+    pub fn known(&mut self, gateway_id: GatewayID, now: SystemTime) {
+        self.activity(gateway_id, now);
+    }
+
+    /// Note that a gateway responded at `now`.
+    pub fn response(&mut self, gateway_id: GatewayID, now: SystemTime) {
+        self.activity(gateway_id, now).last_response = Some(now);
+    }
+
+    /// Note that a gateway sent a receive response at `now`.
+    pub fn receive_response(&mut self, gateway_id: GatewayID, now: SystemTime) {
+        let activity = self.activity(gateway_id, now);
+        activity.last_response = Some(now);
+        activity.receive_responses += 1;
+    }
+
+    /// Note that a gateway's slot counter was observed at `now`.
+    pub fn slot_counter(&mut self, gateway_id: GatewayID, now: SystemTime) {
+        self.activity(gateway_id, now).last_slot_counter = Some(now);
+    }
+
+    /// Move a gateway's activity to its new ID.
+    pub fn reassign(&mut self, old: GatewayID, new: GatewayID) {
+        if let Some(activity) = self.gateways.remove(&old) {
+            self.gateways.insert(new, activity);
+        }
+    }
+
+    /// Judge each gateway's status at `now`, returning those which changed or are due to be
+    /// reported again.
+    ///
+    /// A gateway which has never responded isn't reported until it would be judged stale, so that
+    /// gateways known from before the observer started aren't reported online without evidence.
+    pub fn update(
+        &mut self,
+        thresholds: &GatewayStatus,
+        now: SystemTime,
+    ) -> Vec<(GatewayID, GatewayState, Activity)> {
+        let stale_after = Duration::from_secs(thresholds.stale_after_secs);
+        let offline_after = Duration::from_secs(thresholds.offline_after_secs);
+        let interval = Duration::from_secs(thresholds.interval_secs);
+
+        let mut due = Vec::new();
+        for (gateway_id, activity) in &mut self.gateways {
+            let silence = age(activity.last_response.unwrap_or(activity.since), now);
+            let state = if silence >= offline_after {
+                GatewayState::Offline
+            } else if silence >= stale_after {
+                GatewayState::Stale
+            } else if activity.last_response.is_some() {
+                GatewayState::Online
+            } else {
+                continue;
+            };
+
+            let report = match activity.reported {
+                None => true,
+                Some((reported, at)) => reported != state || age(at, now) >= interval,
+            };
+            if report {
+                activity.reported = Some((state, now));
+                due.push((*gateway_id, state, *activity));
+            }
+        }
+        due
+    }
+
+    /// The approximate number of bytes this tracker occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<GatewayID, Activity>(self.gateways.len())
+    }
+
+    fn activity(&mut self, gateway_id: GatewayID, now: SystemTime) -> &mut Activity {
+        self.gateways.entry(gateway_id).or_insert(Activity {
+            since: now,
+            last_response: None,
+            last_slot_counter: None,
+            receive_responses: 0,
+            reported: None,
+        })
+    }
+}
+
+fn age(time: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(time).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let at = |s: u64| t0 + Duration::from_secs(s);
+        let thresholds = GatewayStatus::default();
+        let active = GatewayID::try_from(0x1201).unwrap();
+        let silent = GatewayID::try_from(0x1202).unwrap();
+        let mut tracker = GatewayStatusTracker::default();
+        let update = |tracker: &mut GatewayStatusTracker, s: u64| {
+            tracker
+                .update(&thresholds, at(s))
+                .into_iter()
+                .map(|(gateway_id, state, _)| (gateway_id, state))
+                .collect::<Vec<_>>()
+        };
+
+        // A known gateway which never responds is only reported once it's stale
+        tracker.known(silent, at(0));
+        tracker.receive_response(active, at(0));
+        assert_eq!(update(&mut tracker, 0), [(active, GatewayState::Online)]);
+        tracker.receive_response(active, at(30));
+        assert_eq!(update(&mut tracker, 30), []);
+
+        // Statuses are repeated each interval
+        tracker.receive_response(active, at(60));
+        assert_eq!(
+            update(&mut tracker, 60),
+            [
+                (active, GatewayState::Online),
+                (silent, GatewayState::Stale)
+            ]
+        );
+
+        // A gateway which goes quiet goes stale, then offline
+        assert_eq!(update(&mut tracker, 119), []);
+        assert_eq!(
+            update(&mut tracker, 120),
+            [(active, GatewayState::Stale), (silent, GatewayState::Stale)]
+        );
+        assert_eq!(
+            update(&mut tracker, 300),
+            [
+                (active, GatewayState::Stale),
+                (silent, GatewayState::Offline)
+            ]
+        );
+        assert_eq!(
+            update(&mut tracker, 360),
+            [
+                (active, GatewayState::Offline),
+                (silent, GatewayState::Offline)
+            ]
+        );
+
+        // A response brings it back immediately
+        tracker.response(active, at(361));
+        assert_eq!(update(&mut tracker, 361), [(active, GatewayState::Online)]);
+    }
+}
@@ -0,0 +1,151 @@
+//! Whether an observer's data is flowing, for use as a container health check.
+
+use super::PersistentState;
+use chrono::{DateTime, Local};
+use std::time::Duration;
+
+/// The longest the array can sleep before its silence is treated as staleness after all.
+const MAX_NIGHT: Duration = Duration::from_secs(20 * 3600);
+
+/// The health of an observer, as judged from its persistent state.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Health {
+    /// An event was emitted within the allowed age.
+    Healthy { age: Duration },
+    /// The most recent event is older than the allowed age.
+    Stale { age: Duration, max_age: Duration },
+    /// The most recent event is older than the allowed age, but the array is asleep, so no events
+    /// are expected.
+    Asleep { age: Duration, asleep_for: Duration },
+    /// No event has ever been emitted.
+    NoEvents,
+}
+
+impl Health {
+    /// Judge whether an event has been emitted within `max_age` of `now`.
+    ///
+    /// Silence is expected while the array is asleep, unless it's been asleep for longer than any
+    /// night lasts.
+    pub fn check(state: &PersistentState, now: DateTime<Local>, max_age: Duration) -> Self {
+        let Some(last_event) = state.last_event() else {
+            return Health::NoEvents;
+        };
+
+        // An event from the future is as fresh as it gets
+        let age = (now - last_event).to_std().unwrap_or_default();
+        if age <= max_age {
+            return Health::Healthy { age };
+        }
+
+        match state
+            .asleep_since()
+            .map(|since| (now - since).to_std().unwrap_or_default())
+        {
+            Some(asleep_for) if asleep_for <= MAX_NIGHT => Health::Asleep { age, asleep_for },
+            _ => Health::Stale { age, max_age },
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, Health::Healthy { .. } | Health::Asleep { .. })
+    }
+}
+
+impl std::fmt::Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Health::Healthy { age } => write!(f, "healthy: last event {}s ago", age.as_secs()),
+            Health::Stale { age, max_age } => write!(
+                f,
+                "stale: last event {}s ago, exceeding {}s",
+                age.as_secs(),
+                max_age.as_secs()
+            ),
+            Health::Asleep { age, asleep_for } => write!(
+                f,
+                "asleep: last event {}s ago, array asleep for {}s",
+                age.as_secs(),
+                asleep_for.as_secs()
+            ),
+            Health::NoEvents => f.write_str("no data: no events have been emitted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn check() {
+        let now = Local.timestamp_opt(1724497200, 0).unwrap();
+        let max_age = Duration::from_secs(300);
+        let state_at = |seconds_ago: i64| -> PersistentState {
+            serde_json::from_value(serde_json::json!({
+                "gateway_node_tables": {},
+                "gateway_identities": {},
+                "gateway_versions": {},
+                "last_event": now - chrono::Duration::seconds(seconds_ago),
+            }))
+            .unwrap()
+        };
+
+        let health = Health::check(&state_at(20), now, max_age);
+        assert_eq!(
+            health,
+            Health::Healthy {
+                age: Duration::from_secs(20)
+            }
+        );
+        assert!(health.is_healthy());
+        assert_eq!(health.to_string(), "healthy: last event 20s ago");
+
+        let health = Health::check(&state_at(301), now, max_age);
+        assert_eq!(
+            health,
+            Health::Stale {
+                age: Duration::from_secs(301),
+                max_age
+            }
+        );
+        assert!(!health.is_healthy());
+        assert_eq!(
+            health.to_string(),
+            "stale: last event 301s ago, exceeding 300s"
+        );
+
+        // Clock skew doesn't make a fresh event look stale
+        assert!(Health::check(&state_at(-5), now, max_age).is_healthy());
+
+        // Silence is fine while the array sleeps, but not forever
+        let asleep_at = |seconds_ago: i64, asleep_seconds_ago: i64| -> PersistentState {
+            serde_json::from_value(serde_json::json!({
+                "gateway_node_tables": {},
+                "gateway_identities": {},
+                "gateway_versions": {},
+                "last_event": now - chrono::Duration::seconds(seconds_ago),
+                "asleep_since": now - chrono::Duration::seconds(asleep_seconds_ago),
+            }))
+            .unwrap()
+        };
+        let health = Health::check(&asleep_at(3600, 3600), now, max_age);
+        assert_eq!(
+            health,
+            Health::Asleep {
+                age: Duration::from_secs(3600),
+                asleep_for: Duration::from_secs(3600)
+            }
+        );
+        assert!(health.is_healthy());
+        assert_eq!(
+            health.to_string(),
+            "asleep: last event 3600s ago, array asleep for 3600s"
+        );
+        assert!(!Health::check(&asleep_at(86400, 86400), now, max_age).is_healthy());
+
+        let health = Health::check(&PersistentState::default(), now, max_age);
+        assert_eq!(health, Health::NoEvents);
+        assert!(!health.is_healthy());
+    }
+}
@@ -0,0 +1,143 @@
+use super::config::DuplicateAddresses;
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::pv::link::long_address_keys;
+use crate::pv::LongAddress;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How long a node's home gateway can go without reporting it before another gateway takes over,
+/// under [`DuplicateAddresses::HomeOnly`].
+const HOME_TIMEOUT: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+
+/// The gateway through which each node most recently reported, by the node's hardware address.
+///
+/// A node normally belongs to a single gateway, but where gateways' radio coverage overlaps, a
+/// node can appear in the node tables of more than one, and its reports can arrive through any of
+/// them.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct HomeGateways(
+    #[serde(with = "long_address_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, Home>"))]
+    BTreeMap<LongAddress, Home>,
+);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Home {
+    gateway_id: GatewayID,
+    confirmed: DateTime<Local>,
+}
+
+/// The outcome of a gateway reporting a node.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Confirmation {
+    /// The gateway is the node's home.
+    Home,
+    /// The gateway became the node's home, replacing another.
+    Moved { from: GatewayID },
+    /// The gateway isn't the node's home, and the node stays where it is.
+    Away { home: GatewayID },
+}
+
+impl HomeGateways {
+    /// The home gateway of a node.
+    pub fn get(&self, address: &LongAddress) -> Option<GatewayID> {
+        self.0.get(address).map(|home| home.gateway_id)
+    }
+
+    /// Note that a gateway reported a node at `now`, moving its home according to `policy`.
+    pub fn confirm(
+        &mut self,
+        address: LongAddress,
+        gateway_id: GatewayID,
+        now: DateTime<Local>,
+        policy: DuplicateAddresses,
+    ) -> Confirmation {
+        let home = self.0.entry(address).or_insert(Home {
+            gateway_id,
+            confirmed: now,
+        });
+        if home.gateway_id == gateway_id {
+            home.confirmed = now;
+            return Confirmation::Home;
+        }
+
+        match policy {
+            DuplicateAddresses::HomeOnly if now - home.confirmed <= HOME_TIMEOUT => {
+                Confirmation::Away {
+                    home: home.gateway_id,
+                }
+            }
+            DuplicateAddresses::FollowReports | DuplicateAddresses::HomeOnly => {
+                let from = std::mem::replace(
+                    home,
+                    Home {
+                        gateway_id,
+                        confirmed: now,
+                    },
+                )
+                .gateway_id;
+                Confirmation::Moved { from }
+            }
+        }
+    }
+
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<LongAddress, Home>(self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn policies() {
+        let a = GatewayID::try_from(0x1201).unwrap();
+        let b = GatewayID::try_from(0x1202).unwrap();
+        let node = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x56]);
+        let t = Local.timestamp_opt(1724497200, 0).unwrap();
+        let minutes = |n| t + chrono::TimeDelta::minutes(n);
+
+        for (policy, expected) in [
+            (
+                DuplicateAddresses::FollowReports,
+                [
+                    Confirmation::Home,
+                    Confirmation::Moved { from: a },
+                    Confirmation::Moved { from: b },
+                    Confirmation::Moved { from: a },
+                ],
+            ),
+            (
+                DuplicateAddresses::HomeOnly,
+                [
+                    Confirmation::Home,
+                    Confirmation::Away { home: a },
+                    Confirmation::Home,
+                    Confirmation::Moved { from: a },
+                ],
+            ),
+        ] {
+            let mut homes = HomeGateways::default();
+            let actual = [
+                homes.confirm(node, a, minutes(0), policy),
+                homes.confirm(node, b, minutes(1), policy),
+                homes.confirm(node, a, minutes(2), policy),
+                // Gateway A hasn't reported the node for a while
+                homes.confirm(node, b, minutes(20), policy),
+            ];
+            assert_eq!(actual, expected, "{:?}", policy);
+            assert_eq!(homes.get(&node), Some(b));
+
+            let json = serde_json::to_string(&homes).unwrap();
+            assert_eq!(serde_json::from_str::<HomeGateways>(&json).unwrap(), homes);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! A directory of files, one for each frame or packet which couldn't be interpreted, with the bus
+//! traffic which led up to it.
+//!
+//! Counters say how often traffic is malformed, but not how. Each file in an [`InvalidDump`] holds
+//! the offending bytes along with the raw bytes most recently received from the same source, so
+//! that the problem can be reproduced by feeding those bytes back into a receiver stack.
+
+use crate::gateway::link::{Frame, GatewayID};
+use crate::gateway::transport::InvalidFrameReason;
+use crate::pv::{NodeID, PacketType};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The number of raw bytes kept from each source, to accompany whatever goes wrong next.
+///
+/// This is enough for a receive request and a large receive response.
+pub const CONTEXT_LEN: usize = 1024;
+
+/// What couldn't be interpreted.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Invalid {
+    /// A frame which the link layer discarded for having an incorrect checksum.
+    Checksum {
+        /// The frame as it appeared on the bus, in hexadecimal.
+        frame: String,
+    },
+    /// A frame which the transport layer couldn't interpret.
+    Frame {
+        reason: InvalidFrameReason,
+        /// The frame's raw link layer address, including the direction bit.
+        address: u16,
+        frame_type: u16,
+        /// The frame's payload, in hexadecimal.
+        payload: String,
+    },
+    /// A packet received from a node which the application layer couldn't interpret.
+    Packet {
+        gateway: GatewayID,
+        node: NodeID,
+        packet_type: u8,
+        /// The packet's data, in hexadecimal.
+        data: String,
+    },
+}
+
+impl Invalid {
+    /// The kind of problem, as used in file names.
+    fn kind(&self) -> &'static str {
+        match self {
+            Invalid::Checksum { .. } => "checksum",
+            Invalid::Frame { .. } => "frame",
+            Invalid::Packet { .. } => "packet",
+        }
+    }
+
+    /// The frame which the transport layer couldn't interpret, if that's what this is.
+    pub fn frame(&self) -> Option<Frame> {
+        match self {
+            Invalid::Frame {
+                address,
+                frame_type,
+                payload,
+                ..
+            } => Some(Frame {
+                address: (*address).into(),
+                frame_type: crate::gateway::link::Type(*frame_type),
+                payload: from_hex(payload)?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The offending bytes: a whole frame as it appeared on the bus, a frame's payload, or a
+    /// packet's data.
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Invalid::Checksum { frame } => from_hex(frame),
+            Invalid::Frame { payload, .. } => from_hex(payload),
+            Invalid::Packet { data, .. } => from_hex(data),
+        }
+    }
+}
+
+/// One file in an [`InvalidDump`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InvalidDumpRecord {
+    pub timestamp: DateTime<Local>,
+    /// The source through which the traffic was received, when observing more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(flatten)]
+    pub invalid: Invalid,
+    /// The last raw bytes received from the source, through the end of the read in which the
+    /// problem was found, in hexadecimal.
+    pub context: String,
+}
+
+impl InvalidDumpRecord {
+    /// The raw bytes received leading up to the problem.
+    pub fn context_bytes(&self) -> Option<Vec<u8>> {
+        from_hex(&self.context)
+    }
+}
+
+/// Writes each frame or packet which couldn't be interpreted to a file of its own, in a directory.
+///
+/// Files are named for the time, a sequence number, and the kind of problem, as in
+/// `20240824T090000.123-0000-checksum.json`. Once `max_files` have been written, the rest are
+/// dropped, so that a bus which is mostly noise can't fill the disk.
+pub struct InvalidDump {
+    dir: PathBuf,
+    max_files: usize,
+    written: usize,
+    contexts: BTreeMap<Option<Arc<str>>, VecDeque<u8>>,
+}
+
+impl std::fmt::Debug for InvalidDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("InvalidDump")
+            .field("dir", &self.dir)
+            .field("max_files", &self.max_files)
+            .field("written", &self.written)
+            .finish_non_exhaustive()
+    }
+}
+
+impl InvalidDump {
+    /// Dump into `dir`, creating it if necessary.
+    pub fn new(dir: impl Into<PathBuf>, max_files: usize) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            max_files,
+            written: 0,
+            contexts: BTreeMap::new(),
+        })
+    }
+
+    /// The directory being written.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The number of files written so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Note raw bytes received from `source`, before passing them to its receiver stack.
+    pub fn bytes_received(&mut self, source: Option<&Arc<str>>, bytes: &[u8]) {
+        let context = self.contexts.entry(source.cloned()).or_default();
+        context.extend(bytes);
+        let excess = context.len().saturating_sub(CONTEXT_LEN);
+        context.drain(..excess);
+    }
+
+    /// Write a file describing a problem with traffic from `source`.
+    pub fn write(
+        &mut self,
+        source: Option<&Arc<str>>,
+        invalid: Invalid,
+        timestamp: DateTime<Local>,
+    ) {
+        if self.written >= self.max_files {
+            return;
+        }
+
+        let record = InvalidDumpRecord {
+            timestamp,
+            source: source.map(|source| source.to_string()),
+            context: self
+                .contexts
+                .get(&source.cloned())
+                .map(|context| hex(context.iter().copied()))
+                .unwrap_or_default(),
+            invalid,
+        };
+        let path = self.dir.join(format!(
+            "{}-{:04}-{}.json",
+            timestamp.format("%Y%m%dT%H%M%S%.3f"),
+            self.written,
+            record.invalid.kind()
+        ));
+        let mut json = serde_json::to_vec(&record).unwrap();
+        json.push(b'\n');
+        if let Err(e) = std::fs::write(&path, json) {
+            log::error!("error writing {:?}: {}", path, e);
+            return;
+        }
+
+        self.written += 1;
+        if self.written == self.max_files {
+            log::warn!(
+                "wrote {} files of invalid traffic to {:?}, and won't write any more",
+                self.written,
+                self.dir
+            );
+        }
+    }
+
+    /// Write a file describing a frame with an incorrect checksum.
+    pub fn checksum_failed(
+        &mut self,
+        source: Option<&Arc<str>>,
+        bytes: &[u8],
+        timestamp: DateTime<Local>,
+    ) {
+        let invalid = Invalid::Checksum {
+            frame: hex(bytes.iter().copied()),
+        };
+        self.write(source, invalid, timestamp);
+    }
+
+    /// Write a file describing a frame which the transport layer couldn't interpret.
+    pub fn invalid_frame(
+        &mut self,
+        source: Option<&Arc<str>>,
+        frame: &Frame,
+        reason: InvalidFrameReason,
+        timestamp: DateTime<Local>,
+    ) {
+        let invalid = Invalid::Frame {
+            reason,
+            address: u16::from(frame.address),
+            frame_type: frame.frame_type.0,
+            payload: hex(frame.payload.iter().copied()),
+        };
+        self.write(source, invalid, timestamp);
+    }
+
+    /// Write a file describing a packet which the application layer couldn't interpret.
+    pub fn invalid_packet(
+        &mut self,
+        source: Option<&Arc<str>>,
+        gateway: GatewayID,
+        node: NodeID,
+        packet_type: PacketType,
+        data: &[u8],
+        timestamp: DateTime<Local>,
+    ) {
+        let invalid = Invalid::Packet {
+            gateway,
+            node,
+            packet_type: packet_type.0,
+            data: hex(data.iter().copied()),
+        };
+        self.write(source, invalid, timestamp);
+    }
+}
+
+fn hex(bytes: impl Iterator<Item = u8>) -> String {
+    bytes.map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "taptap-invalid-dump-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn write() {
+        let dir = dir("write");
+        let mut dump = InvalidDump::new(&dir, 2).unwrap();
+        let t0 = Local.with_ymd_and_hms(2024, 8, 24, 9, 0, 0).unwrap();
+        let source: Arc<str> = "east".into();
+
+        // Context is kept for each source, and only the latest is kept
+        dump.bytes_received(None, &[0xAA; CONTEXT_LEN]);
+        dump.bytes_received(None, &[0x01, 0x02]);
+        dump.bytes_received(Some(&source), &[0x03]);
+        dump.checksum_failed(None, &[0x7e, 0x07, 0x12, 0x7e, 0x08], t0);
+        dump.checksum_failed(Some(&source), &[0x7e, 0x07, 0x34, 0x7e, 0x08], t0);
+        dump.checksum_failed(None, &[0x7e, 0x07, 0x56, 0x7e, 0x08], t0);
+        assert_eq!(dump.written(), 2);
+
+        let files = files(&dir);
+        assert_eq!(
+            files
+                .iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap())
+                .collect::<Vec<_>>(),
+            [
+                "20240824T090000.000-0000-checksum.json",
+                "20240824T090000.000-0001-checksum.json"
+            ]
+        );
+        let records: Vec<InvalidDumpRecord> = files
+            .iter()
+            .map(|path| serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap())
+            .collect();
+
+        let context = records[0].context_bytes().unwrap();
+        assert_eq!(context.len(), CONTEXT_LEN);
+        assert!(context.ends_with(&[0xAA, 0x01, 0x02]));
+        assert_eq!(records[0].source, None);
+        assert_eq!(
+            records[0].invalid.bytes(),
+            Some(vec![0x7e, 0x07, 0x12, 0x7e, 0x08])
+        );
+        assert_eq!(records[1].source.as_deref(), Some("east"));
+        assert_eq!(records[1].context, "03");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn from_hex() {
+        assert_eq!(super::from_hex("00ff7E"), Some(vec![0x00, 0xff, 0x7e]));
+        assert_eq!(super::from_hex(""), Some(vec![]));
+        assert_eq!(super::from_hex("0"), None);
+        assert_eq!(super::from_hex("zz"), None);
+    }
+}
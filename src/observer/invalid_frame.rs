@@ -0,0 +1,65 @@
+//! A side-file of the frames which the transport layer could not interpret.
+//!
+//! Invalid frames are otherwise only counted, which makes it hard to tell a gateway speaking an
+//! unfamiliar firmware dialect from a noisy bus. Each record carries the whole frame, so that it
+//! can be studied or fed back into a decoder later.
+
+use crate::gateway::link::Frame;
+use crate::gateway::transport::InvalidFrameReason;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::io::Write;
+
+/// One frame which the transport layer could not interpret, as written to an [`InvalidFrameLog`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct InvalidFrameRecord {
+    pub timestamp: DateTime<Local>,
+    pub reason: InvalidFrameReason,
+    /// The frame's raw link layer address, including the direction bit.
+    pub address: u16,
+    pub frame_type: u16,
+    /// The frame's payload, in hexadecimal.
+    pub payload: String,
+}
+
+impl InvalidFrameRecord {
+    pub fn new(frame: &Frame, reason: InvalidFrameReason, timestamp: DateTime<Local>) -> Self {
+        Self {
+            timestamp,
+            reason,
+            address: u16::from(frame.address),
+            frame_type: frame.frame_type.0,
+            payload: frame
+                .payload
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }
+    }
+}
+
+/// A destination for invalid frames, written as JSON, one per line.
+pub struct InvalidFrameLog(Box<dyn Write + Send>);
+
+impl std::fmt::Debug for InvalidFrameLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("InvalidFrameLog(..)")
+    }
+}
+
+impl InvalidFrameLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self(Box::new(writer))
+    }
+
+    /// Write a record to this log.
+    pub fn write(&mut self, record: &InvalidFrameRecord) {
+        let result = serde_json::to_writer(&mut self.0, record)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.0.write_all(b"\n"))
+            .and_then(|_| self.0.flush());
+        if let Err(e) = result {
+            log::error!("error writing invalid frame: {}", e);
+        }
+    }
+}
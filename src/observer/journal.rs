@@ -0,0 +1,565 @@
+//! An append-only journal of an observer's events, with periodic checkpoints of its state.
+//!
+//! A journal lets events be queried later without keeping, and re-decoding, the raw capture they
+//! came from. It is a directory of numbered segment files, each holding a sequence of
+//! [`Record`]s. Every record is a big-endian `u32` length followed by that many bytes of JSON.
+//!
+//! An observer writes a [`Checkpoint`] of its [`PersistentState`] when it starts each segment and
+//! then periodically, so that a restarted observer can warm-start from [`last_checkpoint()`], and
+//! so that retention can delete whole segments from the front of the journal without losing the
+//! state needed to interpret the rest.
+//!
+//! Records are never rewritten. A process which dies mid-write leaves a torn record at the end of
+//! its last segment; readers stop reading that segment there, and the next process to open the
+//! journal starts a new segment rather than appending to it.
+
+use super::event::{Event, Node};
+use super::routing::Route;
+use super::PersistentState;
+use crate::barcode::Barcode;
+use crate::pv::{LongAddress, NodeID};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often an observer checkpoints its state to its journal.
+pub const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Records longer than this are taken to be corrupt.
+const MAX_RECORD_LENGTH: u32 = 16 << 20;
+
+const SEGMENT_EXTENSION: &str = "journal";
+
+/// How much of its history a journal keeps.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Retention {
+    /// Start a new segment at the next checkpoint once the current segment reaches this size.
+    pub segment_bytes: u64,
+    /// Delete the oldest segments once the journal exceeds this size.
+    pub max_bytes: u64,
+    /// Delete segments last written longer ago than this.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 16 << 20,
+            max_bytes: 256 << 20,
+            max_age: None,
+        }
+    }
+}
+
+/// One entry in a journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Record {
+    Event(Event),
+    Checkpoint(Checkpoint),
+}
+
+/// An observer's persistent state as of a given time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub timestamp: DateTime<Local>,
+    pub state: PersistentState,
+}
+
+/// A [`Record`], borrowed for writing.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RecordRef<'a> {
+    Event(&'a Event),
+    Checkpoint {
+        timestamp: DateTime<Local>,
+        state: &'a PersistentState,
+    },
+}
+
+/// A journal open for writing.
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    retention: Retention,
+    checkpoint_interval: Duration,
+    segment: File,
+    segment_number: u64,
+    segment_bytes: u64,
+    last_checkpoint: Option<Instant>,
+}
+
+impl Journal {
+    /// Open a journal in a directory, creating the directory if needed, and start a new segment.
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        retention: Retention,
+        checkpoint_interval: Duration,
+    ) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let segment_number = segments(&dir)?.last().map_or(1, |(number, _)| number + 1);
+        let segment = File::create_new(segment_path(&dir, segment_number))?;
+        Ok(Self {
+            dir,
+            retention,
+            checkpoint_interval,
+            segment,
+            segment_number,
+            segment_bytes: 0,
+            last_checkpoint: None,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Append an event.
+    pub fn event(&mut self, event: &Event) -> std::io::Result<()> {
+        self.append(&RecordRef::Event(event))
+    }
+
+    /// Checkpoint the state if the checkpoint interval has elapsed as of `now`, or if nothing has
+    /// been checkpointed yet.
+    pub fn checkpoint_if_due(
+        &mut self,
+        state: &PersistentState,
+        timestamp: DateTime<Local>,
+        now: Instant,
+    ) -> std::io::Result<()> {
+        let due = self
+            .last_checkpoint
+            .is_none_or(|last| now.saturating_duration_since(last) >= self.checkpoint_interval);
+        if due {
+            self.checkpoint(state, timestamp, now)?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoint the state, first moving to a new segment if the current one is full.
+    pub fn checkpoint(
+        &mut self,
+        state: &PersistentState,
+        timestamp: DateTime<Local>,
+        now: Instant,
+    ) -> std::io::Result<()> {
+        // Whether or not this works, wait for the interval before trying again
+        self.last_checkpoint = Some(now);
+        if self.segment_bytes >= self.retention.segment_bytes {
+            self.rotate()?;
+        }
+        self.append(&RecordRef::Checkpoint { timestamp, state })
+    }
+
+    fn append(&mut self, record: &RecordRef) -> std::io::Result<()> {
+        let json = serde_json::to_vec(record)?;
+        let mut buffer = Vec::with_capacity(4 + json.len());
+        buffer.extend_from_slice(&(json.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(&json);
+        // One write per record, so that records from a dying process are torn at most once
+        self.segment.write_all(&buffer)?;
+        self.segment_bytes += buffer.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.segment.sync_all()?;
+        self.segment_number += 1;
+        self.segment = File::create_new(segment_path(&self.dir, self.segment_number))?;
+        self.segment_bytes = 0;
+        self.enforce_retention()
+    }
+
+    /// Delete old segments, never including the current one.
+    fn enforce_retention(&self) -> std::io::Result<()> {
+        let mut old: Vec<(PathBuf, u64, SystemTime)> = Vec::new();
+        for (number, path) in segments(&self.dir)? {
+            if number == self.segment_number {
+                continue;
+            }
+            let metadata = std::fs::metadata(&path)?;
+            old.push((path, metadata.len(), metadata.modified()?));
+        }
+
+        let mut total = self.segment_bytes + old.iter().map(|(_, len, _)| len).sum::<u64>();
+        let now = SystemTime::now();
+        for (path, len, modified) in old {
+            let expired = self
+                .retention
+                .max_age
+                .is_some_and(|max_age| now.duration_since(modified).is_ok_and(|age| age > max_age));
+            if total <= self.retention.max_bytes && !expired {
+                continue;
+            }
+            log::info!("deleting journal segment {:?}", path);
+            std::fs::remove_file(&path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// The segments in a journal directory, in order.
+fn segments(dir: &Path) -> std::io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(number) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        {
+            segments.push((number, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:016}.{}", number, SEGMENT_EXTENSION))
+}
+
+/// Reads the valid records of one segment.
+struct Segment(BufReader<File>);
+
+impl Segment {
+    /// Open a segment, or return `None` if retention has since deleted it.
+    fn open(path: &Path) -> std::io::Result<Option<Self>> {
+        match File::open(path) {
+            Ok(file) => Ok(Some(Self(BufReader::new(file)))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the next record, returning `Ok(None)` at the end of the segment, or an
+    /// [`InvalidData`](ErrorKind::InvalidData) error at a torn or corrupt record.
+    fn next(&mut self) -> Result<Option<Record>, std::io::Error> {
+        let mut length = [0u8; 4];
+        let mut read = 0;
+        while read < length.len() {
+            match self.0.read(&mut length[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(torn()),
+                n => read += n,
+            }
+        }
+        let length = u32::from_be_bytes(length);
+        if length > MAX_RECORD_LENGTH {
+            return Err(torn());
+        }
+
+        let mut json = vec![0u8; length as usize];
+        self.0.read_exact(&mut json).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => torn(),
+            _ => e,
+        })?;
+        serde_json::from_slice(&json).map_err(|_| torn()).map(Some)
+    }
+}
+
+fn torn() -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, "torn journal record")
+}
+
+fn is_torn(e: &std::io::Error) -> bool {
+    e.kind() == ErrorKind::InvalidData
+}
+
+/// Reads every valid record in a journal, in order.
+///
+/// A segment ending in a torn or corrupt record is read up to that record, and counted by
+/// [`truncated_segments()`](Self::truncated_segments).
+pub struct Reader {
+    segments: std::vec::IntoIter<(u64, PathBuf)>,
+    current: Option<Segment>,
+    truncated_segments: u64,
+}
+
+impl Reader {
+    pub fn open(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            segments: segments(dir.as_ref())?.into_iter(),
+            current: None,
+            truncated_segments: 0,
+        })
+    }
+
+    /// The number of segments so far which ended in a torn or corrupt record.
+    pub fn truncated_segments(&self) -> u64 {
+        self.truncated_segments
+    }
+}
+
+impl Iterator for Reader {
+    type Item = std::io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(segment) = &mut self.current {
+                match segment.next() {
+                    Ok(Some(record)) => return Some(Ok(record)),
+                    Ok(None) => {}
+                    Err(e) if is_torn(&e) => self.truncated_segments += 1,
+                    Err(e) => return Some(Err(e)),
+                }
+                self.current = None;
+            }
+
+            let (_, path) = self.segments.next()?;
+            match Segment::open(&path) {
+                Ok(segment) => self.current = segment,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Find the most recent checkpoint in a journal, if it has any.
+pub fn last_checkpoint(dir: impl AsRef<Path>) -> std::io::Result<Option<Checkpoint>> {
+    for (_, path) in segments(dir.as_ref())?.into_iter().rev() {
+        let Some(mut segment) = Segment::open(&path)? else {
+            continue;
+        };
+        let mut checkpoint = None;
+        loop {
+            match segment.next() {
+                Ok(Some(Record::Checkpoint(c))) => checkpoint = Some(c),
+                Ok(Some(Record::Event(_))) => {}
+                Ok(None) => break,
+                Err(e) if is_torn(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if checkpoint.is_some() {
+            return Ok(checkpoint);
+        }
+    }
+    Ok(None)
+}
+
+/// A node to look for in a journal, by node ID or by barcode.
+///
+/// Node IDs are only unique within a gateway, so an ID matches that node under every gateway.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NodeFilter {
+    Id(NodeID),
+    Address(LongAddress),
+}
+
+impl NodeFilter {
+    pub fn matches(&self, node: &Node) -> bool {
+        match self {
+            NodeFilter::Id(id) => node.id == *id,
+            NodeFilter::Address(address) => node.address == Some(*address),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("expected a node ID or a barcode, not {0:?}")]
+pub struct InvalidNodeFilterError(String);
+
+impl std::str::FromStr for NodeFilter {
+    type Err = InvalidNodeFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(barcode) = s.parse::<Barcode>() {
+            return Ok(NodeFilter::Address(barcode.into()));
+        }
+        s.parse::<u16>()
+            .ok()
+            .and_then(|id| NodeID::try_from(id).ok())
+            .map(NodeFilter::Id)
+            .ok_or_else(|| InvalidNodeFilterError(s.into()))
+    }
+}
+
+/// Criteria for selecting events from a journal.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Events at or after this time.
+    pub since: Option<DateTime<Local>>,
+    /// Events before this time.
+    pub until: Option<DateTime<Local>>,
+    /// Events pertaining to this node.
+    pub node: Option<NodeFilter>,
+    /// Events of these kinds, or of every kind if empty.
+    pub kinds: Route,
+}
+
+impl Query {
+    pub fn matches(&self, event: &Event) -> bool {
+        let timestamp = event.timestamp();
+        self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp < until)
+            && self
+                .node
+                .is_none_or(|filter| event.nodes().iter().any(|node| filter.matches(node)))
+            && self.kinds.matches(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::event::{ArrayState, ArrayStateEvent};
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("taptap-journal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn event(seconds: i64) -> Event {
+        Event::ArrayWake(ArrayStateEvent {
+            timestamp: DateTime::from_timestamp(1723500000 + seconds, 0)
+                .unwrap()
+                .into(),
+            state: ArrayState::Awake,
+            reporting_nodes: 1,
+            known_nodes: 2,
+            source: None,
+        })
+    }
+
+    fn events(dir: &Path) -> Vec<Event> {
+        Reader::open(dir)
+            .unwrap()
+            .filter_map(|record| match record.unwrap() {
+                Record::Event(event) => Some(event),
+                Record::Checkpoint(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = dir("round-trip");
+        let start = Instant::now();
+        let state = PersistentState::default();
+        let timestamp = event(0).timestamp();
+
+        let mut journal =
+            Journal::open(&dir, Retention::default(), Duration::from_secs(60)).unwrap();
+        journal.checkpoint_if_due(&state, timestamp, start).unwrap();
+        journal.event(&event(1)).unwrap();
+        journal.event(&event(2)).unwrap();
+        drop(journal);
+
+        // Reopening starts a new segment
+        let mut journal =
+            Journal::open(&dir, Retention::default(), Duration::from_secs(60)).unwrap();
+        journal.event(&event(3)).unwrap();
+        drop(journal);
+        assert_eq!(segments(&dir).unwrap().len(), 2);
+
+        assert_eq!(events(&dir), [event(1), event(2), event(3)]);
+        assert_eq!(
+            last_checkpoint(&dir).unwrap(),
+            Some(Checkpoint { timestamp, state })
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncated() {
+        let dir = dir("truncated");
+        let mut journal =
+            Journal::open(&dir, Retention::default(), Duration::from_secs(60)).unwrap();
+        journal.event(&event(1)).unwrap();
+        journal.event(&event(2)).unwrap();
+        drop(journal);
+
+        // Tear the last record, as if the process died mid-write
+        let (_, path) = segments(&dir).unwrap().pop().unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+        drop(file);
+
+        let mut journal =
+            Journal::open(&dir, Retention::default(), Duration::from_secs(60)).unwrap();
+        journal.event(&event(3)).unwrap();
+        drop(journal);
+
+        let mut reader = Reader::open(&dir).unwrap();
+        assert_eq!(reader.by_ref().count(), 2);
+        assert_eq!(reader.truncated_segments(), 1);
+        assert_eq!(events(&dir), [event(1), event(3)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention() {
+        let dir = dir("retention");
+        let retention = Retention {
+            segment_bytes: 1,
+            max_bytes: 1000,
+            max_age: None,
+        };
+        let start = Instant::now();
+        let state = PersistentState::default();
+        let mut journal = Journal::open(&dir, retention, Duration::from_secs(60)).unwrap();
+        for i in 0..20 {
+            journal
+                .checkpoint(&state, event(i).timestamp(), start)
+                .unwrap();
+            journal.event(&event(i)).unwrap();
+        }
+        drop(journal);
+
+        // Only the most recent segments remain, each starting with a checkpoint. The current
+        // segment may grow past the limit until it is rotated.
+        let segments = segments(&dir).unwrap();
+        assert!(segments.len() < 20);
+        let old: u64 = segments[..segments.len() - 1]
+            .iter()
+            .map(|(_, path)| std::fs::metadata(path).unwrap().len())
+            .sum();
+        assert!(old <= 1000);
+        assert_eq!(events(&dir).last(), Some(&event(19)));
+        assert!(matches!(
+            Reader::open(&dir).unwrap().next(),
+            Some(Ok(Record::Checkpoint(_)))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query() {
+        let query = Query {
+            since: Some(event(10).timestamp()),
+            until: Some(event(20).timestamp()),
+            ..Default::default()
+        };
+        assert!(!query.matches(&event(9)));
+        assert!(query.matches(&event(10)));
+        assert!(!query.matches(&event(20)));
+
+        // Array events pertain to no node in particular
+        let query = Query {
+            node: Some("2".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(!query.matches(&event(10)));
+
+        assert_eq!(
+            "4-9A57A2L".parse(),
+            Ok(NodeFilter::Address(LongAddress([
+                0x04, 0xC0, 0x5B, 0x40, 0x00, 0x9A, 0x57, 0xA2
+            ])))
+        );
+        assert!("0".parse::<NodeFilter>().is_err());
+        assert!("x".parse::<NodeFilter>().is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use crate::memory::btree_map_bytes;
+use crate::pv::application::PvConfiguration;
+use crate::pv::link::long_address_keys;
+use crate::pv::LongAddress;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The PV configuration each node last reported, by hardware address.
+///
+/// Controllers ask nodes for their configuration periodically, and nodes almost always answer
+/// with the same thing, so what matters is when an answer differs from the one before.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct NodeConfigurations(
+    #[serde(with = "long_address_keys")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "BTreeMap<String, PvConfiguration>")
+    )]
+    BTreeMap<LongAddress, PvConfiguration>,
+);
+
+/// A configuration which differed from the one stored before it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Changed {
+    /// The configuration it replaced, if the node had reported one before.
+    pub previous: Option<PvConfiguration>,
+}
+
+impl NodeConfigurations {
+    /// The configuration a node last reported.
+    pub fn get(&self, address: &LongAddress) -> Option<&PvConfiguration> {
+        self.0.get(address)
+    }
+
+    /// Record a node's configuration, returning `None` if it's the same as before.
+    pub fn record(
+        &mut self,
+        address: LongAddress,
+        configuration: PvConfiguration,
+    ) -> Option<Changed> {
+        if self.0.get(&address) == Some(&configuration) {
+            return None;
+        }
+        Some(Changed {
+            previous: self.0.insert(address, configuration),
+        })
+    }
+
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<LongAddress, PvConfiguration>(self.0.len())
+            + self
+                .0
+                .values()
+                .map(|configuration| {
+                    configuration.unknown.capacity()
+                        + (configuration.radio.iter())
+                            .map(|radio| radio.unknown.capacity())
+                            .sum::<usize>()
+                        + (configuration.reporting.iter())
+                            .flat_map(|reporting| &reporting.unknown)
+                            .map(String::capacity)
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+}
@@ -0,0 +1,111 @@
+use super::event::PowerReportEvent;
+use crate::memory::btree_map_bytes;
+use crate::pv::link::long_address_keys;
+use crate::pv::LongAddress;
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The time of each node's latest power report, keyed by the node's hardware address.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct LastReports(
+    #[serde(with = "long_address_keys")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "BTreeMap<String, DateTime<Local>>")
+    )]
+    BTreeMap<LongAddress, DateTime<Local>>,
+);
+
+impl LastReports {
+    /// Record a power report, returning the time since the node's previous report.
+    ///
+    /// Reports from nodes with unknown hardware addresses are ignored. A report timestamped before
+    /// the node's latest report follows it by zero, and leaves the latest report in place.
+    pub fn push(&mut self, report: &PowerReportEvent) -> Option<TimeDelta> {
+        let long_address = report.node.address?;
+        match self.0.get_mut(&long_address) {
+            Some(last_report) => {
+                let elapsed = (report.timestamp - *last_report).max(TimeDelta::zero());
+                *last_report = report.timestamp.max(*last_report);
+                Some(elapsed)
+            }
+            None => {
+                self.0.insert(long_address, report.timestamp);
+                None
+            }
+        }
+    }
+
+    /// The approximate number of bytes these records occupy.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<LongAddress, DateTime<Local>>(self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::link::GatewayID;
+    use crate::observer::event::{Gateway, Node};
+    use crate::pv::physical::RSSI;
+    use crate::pv::NodeID;
+    use chrono::TimeZone as _;
+
+    const ADDRESS: LongAddress = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]);
+
+    fn report(timestamp: DateTime<Local>, address: Option<LongAddress>) -> PowerReportEvent {
+        PowerReportEvent {
+            gateway: Gateway {
+                id: GatewayID::try_from(0x1201).unwrap(),
+                address: None,
+                provenance: None,
+            },
+            node: Node {
+                id: NodeID::try_from(2).unwrap(),
+                address,
+                provenance: None,
+                home_gateway: None,
+            },
+            timestamp,
+            voltage_in: 30.0,
+            voltage_out: 30.0,
+            current: 6.0,
+            power_w: 180.0,
+            dc_dc_duty_cycle: 1.0,
+            temperature: 25.0,
+            rssi: RSSI(120),
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn push() {
+        let t0 = Local.with_ymd_and_hms(2024, 8, 24, 9, 0, 0).unwrap();
+        let seconds = |s: i64| t0 + TimeDelta::seconds(s);
+        let mut last_reports = LastReports::default();
+        let mut push = |s| last_reports.push(&report(seconds(s), Some(ADDRESS)));
+
+        assert_eq!(push(0), None);
+        assert_eq!(push(20), Some(TimeDelta::seconds(20)));
+
+        // A report from the past follows by zero, and doesn't move the latest report back
+        assert_eq!(push(15), Some(TimeDelta::zero()));
+        assert_eq!(push(30), Some(TimeDelta::seconds(10)));
+
+        // Reports without a hardware address are ignored
+        assert_eq!(last_reports.push(&report(seconds(40), None)), None);
+
+        let json = serde_json::to_string(&last_reports).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LastReports>(&json).unwrap(),
+            last_reports
+        );
+    }
+}
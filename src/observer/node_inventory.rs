@@ -0,0 +1,191 @@
+use super::provenance::{Provenance, Source};
+use crate::memory::btree_map_bytes;
+use crate::pv::application::strings::Response;
+use crate::pv::link::long_address_keys;
+use crate::pv::LongAddress;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What each node has said about itself in response to string requests, by hardware address.
+///
+/// Nodes answer a `Version` request with their firmware version, as in
+/// `"Mnode Version K8.0120 (2D)"`, and other requests such as `Info` with responses naming the
+/// request, as in `"!Info 0000 15 …"`. The latest of each is kept.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct NodeInventory(
+    #[serde(with = "long_address_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, NodeStrings>"))]
+    BTreeMap<LongAddress, NodeStrings>,
+);
+
+/// The strings a node reported.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NodeStrings {
+    /// The node's firmware version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<String>,
+    /// The node's latest response to each other request, by the name of the request.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub responses: BTreeMap<String, String>,
+    /// When and how the firmware version was learned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+/// The outcome of recording a string response.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Recorded {
+    /// Whether the response told us anything new.
+    pub changed: bool,
+    /// The firmware version which the response replaced, if the node had reported a different
+    /// one before.
+    pub previous_firmware: Option<String>,
+}
+
+impl NodeInventory {
+    /// The strings reported by a node.
+    pub fn get(&self, address: &LongAddress) -> Option<&NodeStrings> {
+        self.0.get(address)
+    }
+
+    /// Record a node's response to a string request, received at `now`.
+    ///
+    /// Returns `None` if the response doesn't look like one which identifies the node.
+    pub fn record(
+        &mut self,
+        address: LongAddress,
+        response: &str,
+        now: DateTime<Local>,
+    ) -> Option<Recorded> {
+        match Response::parse(response) {
+            Response::Version(response) => {
+                let strings = self.0.entry(address).or_insert_with(NodeStrings::new);
+                let changed = strings.firmware.as_ref() != Some(&response);
+                let previous_firmware = strings.firmware.replace(response);
+                strings.provenance = Some(Provenance::learned(
+                    strings.provenance,
+                    changed,
+                    Source::StringResponse,
+                    now,
+                ));
+                Some(Recorded {
+                    changed,
+                    previous_firmware: previous_firmware.filter(|_| changed),
+                })
+            }
+            Response::Parameter { parameter, .. } => {
+                let response = response.trim();
+                let strings = self.0.entry(address).or_insert_with(NodeStrings::new);
+                let previous = strings.responses.insert(parameter, response.to_owned());
+                Some(Recorded {
+                    changed: previous.as_deref() != Some(response),
+                    previous_firmware: None,
+                })
+            }
+            Response::Unknown(_) => None,
+        }
+    }
+
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<LongAddress, NodeStrings>(self.0.len())
+            + self
+                .0
+                .values()
+                .map(|strings| {
+                    strings.firmware.as_ref().map_or(0, String::capacity)
+                        + btree_map_bytes::<String, String>(strings.responses.len())
+                        + strings
+                            .responses
+                            .iter()
+                            .map(|(name, response)| name.capacity() + response.capacity())
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+}
+
+impl NodeStrings {
+    fn new() -> Self {
+        Self {
+            firmware: None,
+            responses: BTreeMap::new(),
+            provenance: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn record() {
+        let address = LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16]);
+        let t0 = Local.with_ymd_and_hms(2024, 8, 24, 9, 0, 0).unwrap();
+        let t1 = t0 + chrono::TimeDelta::hours(1);
+        let mut inventory = NodeInventory::default();
+
+        assert_eq!(inventory.record(address, "Mchip", t0), None);
+        assert_eq!(inventory.get(&address), None);
+
+        let recorded = inventory
+            .record(address, "Mnode Version K8.0120 (2D)\r", t0)
+            .unwrap();
+        assert!(recorded.changed);
+        assert_eq!(recorded.previous_firmware, None);
+
+        let recorded = inventory
+            .record(address, "!Info 0000 15 0000 0981", t0)
+            .unwrap();
+        assert!(recorded.changed);
+
+        // Hearing the same again changes nothing, but confirms the firmware
+        let recorded = inventory
+            .record(address, "Mnode Version K8.0120 (2D)", t1)
+            .unwrap();
+        assert!(!recorded.changed);
+        let strings = inventory.get(&address).unwrap();
+        assert_eq!(
+            strings.firmware.as_deref(),
+            Some("Mnode Version K8.0120 (2D)")
+        );
+        assert_eq!(
+            strings.responses.get("Info").map(String::as_str),
+            Some("!Info 0000 15 0000 0981")
+        );
+        let provenance = strings.provenance.unwrap();
+        assert_eq!(provenance.source, Source::StringResponse);
+        assert_eq!((provenance.first_seen, provenance.last_confirmed), (t0, t1));
+
+        // A different version replaces the old one
+        let recorded = inventory
+            .record(address, "Mnode Version K8.0130 (2D)", t1)
+            .unwrap();
+        assert!(recorded.changed);
+        assert_eq!(
+            recorded.previous_firmware.as_deref(),
+            Some("Mnode Version K8.0120 (2D)")
+        );
+        assert_eq!(
+            inventory
+                .get(&address)
+                .unwrap()
+                .provenance
+                .unwrap()
+                .first_seen,
+            t1
+        );
+
+        let json = serde_json::to_string(&inventory).unwrap();
+        assert_eq!(
+            serde_json::from_str::<NodeInventory>(&json).unwrap(),
+            inventory
+        );
+    }
+}
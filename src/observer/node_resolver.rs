@@ -0,0 +1,111 @@
+//! Telling which node is which from the node table walks going by.
+
+use super::node_table::{NodeTable, NodeTableBuilder};
+use crate::barcode::Barcode;
+use crate::gateway::link::GatewayID;
+use crate::pv::application::NodeTableResponseEntry;
+use crate::pv::network::NodeAddress;
+use crate::pv::{LongAddress, NodeID};
+use std::collections::BTreeMap;
+
+/// Resolves node IDs to hardware addresses by following each gateway's node table walks.
+///
+/// Node IDs are only meaningful within a gateway, and only until the next walk reassigns them, so
+/// a finished walk replaces everything known about its gateway. Until a gateway's first walk
+/// finishes, the entries of the walk in progress are used instead, since the first walk after
+/// starting can take hours.
+#[derive(Debug, Clone, Default)]
+pub struct NodeResolver {
+    builders: BTreeMap<GatewayID, NodeTableBuilder>,
+    tables: BTreeMap<GatewayID, NodeTable>,
+}
+
+impl NodeResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow a page of a gateway's node table, returning whether it finished a walk.
+    pub fn node_table_page(
+        &mut self,
+        gateway_id: GatewayID,
+        start_address: NodeAddress,
+        nodes: &[NodeTableResponseEntry],
+    ) -> bool {
+        let builder = self.builders.entry(gateway_id).or_default();
+        match builder.push(start_address, nodes) {
+            Some(table) => {
+                self.tables.insert(gateway_id, table);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The hardware address of a gateway's node, if known.
+    pub fn address(&self, gateway_id: GatewayID, node_id: NodeID) -> Option<LongAddress> {
+        match self.tables.get(&gateway_id) {
+            Some(table) => table.0.get(&node_id).copied(),
+            None => self.builders.get(&gateway_id)?.partial(node_id),
+        }
+    }
+
+    /// The barcode of a gateway's node, if known.
+    pub fn barcode(&self, gateway_id: GatewayID, node_id: NodeID) -> Option<Barcode> {
+        self.address(gateway_id, node_id).map(Barcode::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway(id: u16) -> GatewayID {
+        GatewayID::try_from(id).unwrap()
+    }
+
+    fn id(id: u16) -> NodeID {
+        NodeID::try_from(id).unwrap()
+    }
+
+    fn address(serial: u8) -> LongAddress {
+        LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x9A, 0x57, serial])
+    }
+
+    fn page(entries: &[(u16, u8)]) -> Vec<NodeTableResponseEntry> {
+        entries
+            .iter()
+            .map(|&(node_id, serial)| NodeTableResponseEntry {
+                long_address: address(serial),
+                node_id: id(node_id).into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolve() {
+        let mut resolver = NodeResolver::new();
+        let (g1, g2) = (gateway(0x1201), gateway(0x1202));
+        assert_eq!(resolver.address(g1, id(2)), None);
+
+        // The first walk's entries are used as they arrive
+        assert!(!resolver.node_table_page(g1, NodeAddress::ZERO, &page(&[(2, 0xA1), (3, 0xA2)])));
+        assert_eq!(resolver.address(g1, id(3)), Some(address(0xA2)));
+        assert_eq!(resolver.address(g2, id(3)), None);
+        assert!(!resolver.node_table_page(g1, id(3).successor().into(), &page(&[(8, 0xA8)])));
+        assert!(resolver.node_table_page(g1, id(8).successor().into(), &[]));
+        assert_eq!(resolver.address(g1, id(8)), Some(address(0xA8)));
+        assert_eq!(resolver.address(g1, id(4)), None);
+        assert_eq!(
+            resolver.barcode(g1, id(2)),
+            Some(Barcode::from(address(0xA1)))
+        );
+
+        // Later walks replace the table only once they finish
+        assert!(!resolver.node_table_page(g1, NodeAddress::ZERO, &page(&[(2, 0xB1)])));
+        assert_eq!(resolver.address(g1, id(2)), Some(address(0xA1)));
+        assert!(resolver.node_table_page(g1, id(2).successor().into(), &[]));
+        assert_eq!(resolver.address(g1, id(2)), Some(address(0xB1)));
+        assert_eq!(resolver.address(g1, id(3)), None);
+    }
+}
@@ -1,25 +1,25 @@
+use crate::memory::btree_map_bytes;
 use crate::pv::application::NodeTableResponseEntry;
 use crate::pv::network::NodeAddress;
 use crate::pv::{LongAddress, NodeID};
-use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::borrow::Cow;
 use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct NodeTable(pub(crate) BTreeMap<NodeID, LongAddress>);
 
-impl JsonSchema for NodeTable {
-    fn schema_name() -> Cow<'static, str> {
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for NodeTable {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
         "NodeTable".into()
     }
 
-    fn schema_id() -> Cow<'static, str> {
+    fn schema_id() -> std::borrow::Cow<'static, str> {
         concat!(module_path!(), "::NodeTable").into()
     }
 
-    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+    fn json_schema(gen: &mut schemars::SchemaGenerator) -> schemars::Schema {
         schemars::json_schema!({
             "type": "array",
             "uniqueItems": true,
@@ -28,7 +28,8 @@ impl JsonSchema for NodeTable {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, JsonSchema, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 struct NodeTableEntry {
     pub node_id: NodeID,
     pub long_address: LongAddress,
@@ -98,14 +99,103 @@ pub struct NodeTableBuilder {
     table: NodeTable,
 }
 
+impl NodeTable {
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<NodeID, LongAddress>(self.0.len())
+    }
+
+    /// Compare this table with the one which came before it.
+    ///
+    /// Nodes are identified by their long addresses, since node IDs are reassigned: a node which
+    /// keeps its address under a new ID was readdressed, not removed and added.
+    pub fn diff(&self, previous: &NodeTable) -> NodeTableDiff {
+        // Entries which appear in both tables are unchanged; group the rest by address
+        let mut gone: BTreeMap<LongAddress, Vec<NodeID>> = BTreeMap::new();
+        for (id, address) in &previous.0 {
+            if self.0.get(id) != Some(address) {
+                gone.entry(*address).or_default().push(*id);
+            }
+        }
+        let mut new: BTreeMap<LongAddress, Vec<NodeID>> = BTreeMap::new();
+        for (id, address) in &self.0 {
+            if previous.0.get(id) != Some(address) {
+                new.entry(*address).or_default().push(*id);
+            }
+        }
+
+        let mut diff = NodeTableDiff::default();
+        for (address, previous_ids) in gone {
+            let mut ids = new.remove(&address).unwrap_or_default().into_iter();
+            for previous_id in previous_ids {
+                match ids.next() {
+                    Some(id) => diff.readdressed.push((previous_id, id, address)),
+                    None => diff.removed.push((previous_id, address)),
+                }
+            }
+            diff.added.extend(ids.map(|id| (id, address)));
+        }
+        for (address, ids) in new {
+            diff.added.extend(ids.into_iter().map(|id| (id, address)));
+        }
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.readdressed.sort_by_key(|&(_, id, _)| id);
+        diff
+    }
+}
+
+/// The changes from one node table to the next, each list in order of node ID.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct NodeTableDiff {
+    /// Nodes whose addresses weren't in the previous table.
+    pub added: Vec<(NodeID, LongAddress)>,
+    /// Nodes whose addresses aren't in the new table, with their former IDs.
+    pub removed: Vec<(NodeID, LongAddress)>,
+    /// Nodes which kept their addresses under new IDs, as `(previous ID, new ID, address)`.
+    pub readdressed: Vec<(NodeID, NodeID, LongAddress)>,
+}
+
+impl NodeTableDiff {
+    /// Whether the tables hold the same nodes under the same IDs.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.readdressed.is_empty()
+    }
+}
+
 impl NodeTableBuilder {
+    /// The approximate number of bytes this builder occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        self.table.approximate_bytes()
+    }
+
+    /// The number of entries accumulated so far by a walk in progress.
+    pub fn len(&self) -> usize {
+        self.table.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.0.is_empty()
+    }
+
+    /// The address of a node among the entries accumulated so far by a walk in progress.
+    pub fn partial(&self, node_id: NodeID) -> Option<LongAddress> {
+        self.table.0.get(&node_id).copied()
+    }
+
+    /// Whether a page starting at `start_address` would continue the walk in progress.
+    pub fn continues(&self, start_address: NodeAddress) -> bool {
+        NodeAddress::from(self.expected_next) == start_address
+    }
+
     pub fn push(
         &mut self,
         start_address: NodeAddress,
         entries: &[NodeTableResponseEntry],
     ) -> Option<NodeTable> {
         // Are we continuing an existing table?
-        if NodeAddress::from(self.expected_next) != start_address {
+        if !self.continues(start_address) {
             // Reset
             self.expected_next = Default::default();
             self.table = Default::default();
@@ -153,3 +243,90 @@ impl NodeTableBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(serial: u8) -> LongAddress {
+        LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x9A, 0x57, serial])
+    }
+
+    fn id(id: u16) -> NodeID {
+        NodeID::try_from(id).unwrap()
+    }
+
+    /// Walk a table of `(node ID, address serial)` entries, two to a page.
+    fn walk(entries: &[(u16, u8)]) -> NodeTable {
+        let mut entries: Vec<(NodeID, LongAddress)> = entries
+            .iter()
+            .map(|&(node_id, serial)| (id(node_id), address(serial)))
+            .collect();
+        entries.sort();
+
+        let mut builder = NodeTableBuilder::default();
+        let mut start_address = NodeAddress::ZERO;
+        for page in entries.chunks(2) {
+            let responses: Vec<NodeTableResponseEntry> = page
+                .iter()
+                .map(|&(node_id, long_address)| NodeTableResponseEntry {
+                    long_address,
+                    node_id: node_id.into(),
+                })
+                .collect();
+            assert_eq!(builder.push(start_address, &responses), None);
+            start_address = page.last().unwrap().0.successor().into();
+        }
+        builder.push(start_address, &[]).unwrap()
+    }
+
+    #[test]
+    fn unchanged() {
+        let table = walk(&[(2, 0xA1), (3, 0xA2), (4, 0xA3)]);
+        assert!(table.diff(&table).is_empty());
+        assert!(NodeTable::default().diff(&NodeTable::default()).is_empty());
+    }
+
+    #[test]
+    fn shuffled() {
+        let previous = walk(&[(2, 0xA1), (3, 0xA2), (4, 0xA3), (5, 0xA4)]);
+
+        // 0xA1 keeps its ID, 0xA2 and 0xA3 swap, and 0xA4 moves to a free ID
+        let table = walk(&[(2, 0xA1), (3, 0xA3), (4, 0xA2), (9, 0xA4)]);
+        let diff = table.diff(&previous);
+        assert_eq!(
+            diff,
+            NodeTableDiff {
+                added: vec![],
+                removed: vec![],
+                readdressed: vec![
+                    (id(4), id(3), address(0xA3)),
+                    (id(3), id(4), address(0xA2)),
+                    (id(5), id(9), address(0xA4)),
+                ],
+            }
+        );
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn replaced() {
+        let previous = walk(&[(2, 0xA1), (3, 0xA2), (4, 0xA3)]);
+
+        // 0xA2 is replaced by 0xB2 under its ID, 0xA3 leaves, and 0xA1 takes the ID 0xA3 had
+        let table = walk(&[(3, 0xB2), (4, 0xA1), (6, 0xB6)]);
+        assert_eq!(
+            table.diff(&previous),
+            NodeTableDiff {
+                added: vec![(id(3), address(0xB2)), (id(6), address(0xB6))],
+                removed: vec![(id(3), address(0xA2)), (id(4), address(0xA3))],
+                readdressed: vec![(id(2), id(4), address(0xA1))],
+            }
+        );
+
+        // From nothing, everything was added
+        let diff = table.diff(&NodeTable::default());
+        assert_eq!(diff.added.len(), 3);
+        assert!(diff.removed.is_empty() && diff.readdressed.is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use std::collections::BTreeMap;
+
+/// The fewest unverified power reports from a gateway before its node table is considered stale.
+const STALE_MIN_UNVERIFIED: u64 = 10;
+
+/// The share of a gateway's power reports which must be unverified before its node table is
+/// considered stale.
+const STALE_SHARE: f64 = 0.1;
+
+/// How many of each gateway's power reports came from nodes missing from its node table.
+///
+/// An occasional unverified report is likely a corrupted header, but a steady stream of them means
+/// that the gateway has renumbered its nodes since its node table was last walked. Tallies start
+/// over whenever a gateway's node table is replaced.
+#[derive(Debug, Clone, Default)]
+pub struct NodeValidation(BTreeMap<GatewayID, Tally>);
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Tally {
+    reports: u64,
+    unverified: u64,
+    stale: bool,
+}
+
+/// The evidence that a gateway's node table is stale.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Stale {
+    /// The number of power reports received since the node table was replaced.
+    pub reports: u64,
+    /// The number of those reports from nodes missing from the node table.
+    pub unverified: u64,
+}
+
+impl NodeValidation {
+    /// Count a power report received through a gateway, returning `Some` the first time its node
+    /// table appears stale.
+    pub fn report(&mut self, gateway_id: GatewayID, verified: bool) -> Option<Stale> {
+        let tally = self.0.entry(gateway_id).or_default();
+        tally.reports += 1;
+        if verified {
+            return None;
+        }
+        tally.unverified += 1;
+
+        if tally.stale
+            || tally.unverified < STALE_MIN_UNVERIFIED
+            || (tally.unverified as f64) < tally.reports as f64 * STALE_SHARE
+        {
+            return None;
+        }
+        tally.stale = true;
+        Some(Stale {
+            reports: tally.reports,
+            unverified: tally.unverified,
+        })
+    }
+
+    /// Start over for a gateway whose node table was replaced.
+    pub fn reset(&mut self, gateway_id: GatewayID) {
+        self.0.remove(&gateway_id);
+    }
+
+    /// The approximate number of bytes this tracker occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<GatewayID, Tally>(self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale() {
+        let a = GatewayID::try_from(0x1201).unwrap();
+        let b = GatewayID::try_from(0x1202).unwrap();
+        let mut validation = NodeValidation::default();
+
+        // A trickle of unverified reports among many verified ones isn't enough
+        for _ in 0..200 {
+            assert_eq!(validation.report(a, true), None);
+        }
+        for _ in 0..15 {
+            assert_eq!(validation.report(a, false), None);
+        }
+
+        // Nor is a high share of only a few reports
+        for _ in 0..(STALE_MIN_UNVERIFIED - 1) {
+            assert_eq!(validation.report(b, false), None);
+        }
+        assert_eq!(
+            validation.report(b, false),
+            Some(Stale {
+                reports: STALE_MIN_UNVERIFIED,
+                unverified: STALE_MIN_UNVERIFIED
+            })
+        );
+
+        // Staleness is reported once per node table
+        assert_eq!(validation.report(b, false), None);
+        validation.reset(b);
+        for _ in 0..(STALE_MIN_UNVERIFIED - 1) {
+            assert_eq!(validation.report(b, false), None);
+        }
+        assert!(validation.report(b, false).is_some());
+    }
+}
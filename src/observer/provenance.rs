@@ -0,0 +1,106 @@
+//! When and how an observer learned the facts in its persistent state.
+
+use crate::gateway::link::{gateway_id_keys, GatewayID};
+use crate::memory::btree_map_bytes;
+use crate::pv::NodeID;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The means by which a fact was learned.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// Observed while the controller enumerated the gateways.
+    Enumeration,
+    /// Observed in a gateway's response outside of an enumeration.
+    GatewayResponse,
+    /// Observed as the controller assigned a gateway a new ID outside of an enumeration.
+    Assignment,
+    /// Observed in a walk of a gateway's node table.
+    NodeTable,
+    /// Observed in a node's topology report.
+    TopologyReport,
+    /// Observed in a node's response to a string request.
+    StringResponse,
+    /// Imported from outside the observed network.
+    ManualImport,
+}
+
+/// When and how a fact was learned.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Provenance {
+    /// How this fact was most recently learned.
+    pub source: Source,
+    /// The time at which this fact was first learned.
+    pub first_seen: DateTime<Local>,
+    /// The time at which this fact was most recently learned again.
+    pub last_confirmed: DateTime<Local>,
+}
+
+impl Provenance {
+    pub fn new(source: Source, now: DateTime<Local>) -> Self {
+        Self {
+            source,
+            first_seen: now,
+            last_confirmed: now,
+        }
+    }
+
+    /// The provenance of a fact which was just learned, given the provenance of the fact it
+    /// replaces, if any.
+    ///
+    /// If the fact is unchanged, it retains its original `first_seen` time.
+    pub fn learned(
+        previous: Option<Provenance>,
+        changed: bool,
+        source: Source,
+        now: DateTime<Local>,
+    ) -> Self {
+        match previous {
+            Some(previous) if !changed => Self {
+                source,
+                first_seen: previous.first_seen,
+                last_confirmed: now,
+            },
+            _ => Self::new(source, now),
+        }
+    }
+}
+
+/// The provenance of each fact in a `PersistentState`.
+///
+/// Facts learned before provenance was recorded have no entry here.
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ProvenanceTable {
+    #[serde(with = "gateway_id_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, Provenance>"))]
+    pub gateway_identities: BTreeMap<GatewayID, Provenance>,
+    #[serde(with = "gateway_id_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, Provenance>"))]
+    pub gateway_versions: BTreeMap<GatewayID, Provenance>,
+    #[serde(with = "gateway_id_keys")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "BTreeMap<String, BTreeMap<NodeID, Provenance>>")
+    )]
+    pub nodes: BTreeMap<GatewayID, BTreeMap<NodeID, Provenance>>,
+}
+
+impl ProvenanceTable {
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<GatewayID, Provenance>(self.gateway_identities.len())
+            + btree_map_bytes::<GatewayID, Provenance>(self.gateway_versions.len())
+            + btree_map_bytes::<GatewayID, BTreeMap<NodeID, Provenance>>(self.nodes.len())
+            + self
+                .nodes
+                .values()
+                .map(|nodes| btree_map_bytes::<NodeID, Provenance>(nodes.len()))
+                .sum::<usize>()
+    }
+}
@@ -0,0 +1,270 @@
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::pv::NodeID;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+/// Limits on the rate at which an observer emits events.
+///
+/// Diagnostics are never rate limited.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct RateLimits {
+    /// A limit on all events, or `None` for no limit.
+    pub global: Option<RateLimit>,
+    /// A limit on events pertaining to any single node, or `None` for no limit.
+    pub per_node: Option<RateLimit>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        // Healthy nodes report every ~20 seconds, so these limits are never reached in practice
+        Self {
+            global: Some(RateLimit {
+                events_per_minute: 12000,
+                burst: 12000,
+            }),
+            per_node: Some(RateLimit {
+                events_per_minute: 60,
+                burst: 60,
+            }),
+        }
+    }
+}
+
+/// A token bucket rate limit.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RateLimit {
+    /// The sustained rate at which events are permitted.
+    pub events_per_minute: u32,
+    /// The number of events permitted in a burst.
+    pub burst: u32,
+}
+
+/// The scope of a rate limit.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Scope {
+    Global,
+    Node(GatewayID, NodeID),
+}
+
+/// The outcome of offering an event to a `RateLimiter`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Admission {
+    Admitted,
+    /// The event was dropped. `engaged` indicates that this is the first event dropped since the
+    /// limit was last within bounds.
+    Dropped {
+        scope: Scope,
+        engaged: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    global: Option<TokenBucket>,
+    per_node: BTreeMap<(GatewayID, NodeID), TokenBucket>,
+}
+
+impl RateLimiter {
+    /// The approximate number of bytes this limiter occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + btree_map_bytes::<(GatewayID, NodeID), TokenBucket>(self.per_node.len())
+    }
+
+    /// Offer an event to the rate limiter, consuming a token from each applicable bucket if the
+    /// event is admitted.
+    pub fn admit(
+        &mut self,
+        limits: &RateLimits,
+        node: Option<(GatewayID, NodeID)>,
+        now: SystemTime,
+    ) -> Admission {
+        let node_bucket = match (limits.per_node, node) {
+            (Some(limit), Some(key)) => Some((
+                Scope::Node(key.0, key.1),
+                self.per_node
+                    .entry(key)
+                    .or_insert_with(|| TokenBucket::new(limit, now)),
+            )),
+            _ => None,
+        };
+
+        if let Some((scope, bucket)) = node_bucket {
+            bucket.refill(now);
+            if let Some(engaged) = bucket.exhausted() {
+                return Admission::Dropped { scope, engaged };
+            }
+        }
+
+        let global_bucket = match limits.global {
+            Some(limit) => Some(
+                self.global
+                    .get_or_insert_with(|| TokenBucket::new(limit, now)),
+            ),
+            None => None,
+        };
+        if let Some(bucket) = global_bucket {
+            bucket.refill(now);
+            if let Some(engaged) = bucket.exhausted() {
+                return Admission::Dropped {
+                    scope: Scope::Global,
+                    engaged,
+                };
+            }
+            bucket.take();
+        }
+
+        if let Some(key) = node {
+            if let Some(bucket) = self.per_node.get_mut(&key) {
+                bucket.take();
+            }
+        }
+
+        Admission::Admitted
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: SystemTime,
+    limiting: bool,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: SystemTime) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+            limiting: false,
+        }
+    }
+
+    fn refill(&mut self, now: SystemTime) {
+        // Tolerate the clock going backwards by not refilling
+        if let Ok(elapsed) = now.duration_since(self.last_refill) {
+            self.tokens = (self.tokens
+                + elapsed.as_secs_f64() * self.limit.events_per_minute as f64 / 60.0)
+                .min(self.limit.burst as f64);
+        }
+        self.last_refill = now;
+    }
+
+    /// If the bucket is empty, returns `Some(engaged)`, where `engaged` is true if the bucket was
+    /// not already limiting.
+    fn exhausted(&mut self) -> Option<bool> {
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            let engaged = !self.limiting;
+            self.limiting = true;
+            Some(engaged)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+        self.limiting = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn per_node() {
+        let limits = RateLimits {
+            global: None,
+            per_node: Some(RateLimit {
+                events_per_minute: 60,
+                burst: 2,
+            }),
+        };
+        let node = (
+            GatewayID::try_from(1).unwrap(),
+            NodeID::try_from(2).unwrap(),
+        );
+        let other_node = (
+            GatewayID::try_from(1).unwrap(),
+            NodeID::try_from(3).unwrap(),
+        );
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+
+        let mut limiter = RateLimiter::default();
+        assert_eq!(limiter.admit(&limits, Some(node), t), Admission::Admitted);
+        assert_eq!(limiter.admit(&limits, Some(node), t), Admission::Admitted);
+        assert_eq!(
+            limiter.admit(&limits, Some(node), t),
+            Admission::Dropped {
+                scope: Scope::Node(node.0, node.1),
+                engaged: true
+            }
+        );
+        assert_eq!(
+            limiter.admit(&limits, Some(node), t),
+            Admission::Dropped {
+                scope: Scope::Node(node.0, node.1),
+                engaged: false
+            }
+        );
+
+        // Other nodes and node-less events are unaffected
+        assert_eq!(
+            limiter.admit(&limits, Some(other_node), t),
+            Admission::Admitted
+        );
+        assert_eq!(limiter.admit(&limits, None, t), Admission::Admitted);
+
+        // One event per second refills
+        let t = t + Duration::from_secs(1);
+        assert_eq!(limiter.admit(&limits, Some(node), t), Admission::Admitted);
+        assert_eq!(
+            limiter.admit(&limits, Some(node), t),
+            Admission::Dropped {
+                scope: Scope::Node(node.0, node.1),
+                engaged: true
+            }
+        );
+    }
+
+    #[test]
+    fn global() {
+        let limits = RateLimits {
+            global: Some(RateLimit {
+                events_per_minute: 60,
+                burst: 1,
+            }),
+            per_node: None,
+        };
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+
+        let mut limiter = RateLimiter::default();
+        assert_eq!(limiter.admit(&limits, None, t), Admission::Admitted);
+        assert_eq!(
+            limiter.admit(&limits, None, t),
+            Admission::Dropped {
+                scope: Scope::Global,
+                engaged: true
+            }
+        );
+
+        // A clock going backwards doesn't refill
+        let t = t - Duration::from_secs(10);
+        assert_eq!(
+            limiter.admit(&limits, None, t),
+            Admission::Dropped {
+                scope: Scope::Global,
+                engaged: false
+            }
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Delivering events to several outputs according to their kinds.
+//!
+//! A [`FanOut`] is an [`EventSink`] holding any number of outputs, each with a [`Route`] naming
+//! the kinds of event it wants. Every event is delivered to each output whose route matches it,
+//! and events which no route matches are delivered to a default sink, so that nothing is lost by
+//! routing only some kinds elsewhere.
+//!
+//! Diagnostics normally bypass the event sink. To route them, set the observer's diagnostics
+//! output to [`diagnostic::Output::Events`](super::diagnostic::Output::Events).
+
+use super::event::{Event, EventKind};
+use super::EventSink;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// The kinds of event delivered to an output.
+///
+/// An empty route matches every event, so that an output which doesn't specify a route receives
+/// everything.
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct Route(pub BTreeSet<EventKind>);
+
+impl Route {
+    /// Whether an event should be delivered along this route.
+    pub fn matches(&self, event: &Event) -> bool {
+        self.0.is_empty() || self.0.contains(&event.kind())
+    }
+}
+
+impl FromIterator<EventKind> for Route {
+    fn from_iter<T: IntoIterator<Item = EventKind>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// An event sink delivering each event to every output whose route matches it, or to a default
+/// sink if none do.
+#[derive(Debug)]
+pub struct FanOut {
+    outputs: Vec<(Route, Box<dyn EventSink>)>,
+    default: Box<dyn EventSink>,
+}
+
+impl FanOut {
+    /// Deliver events to `default` until outputs are added.
+    pub fn new(default: impl EventSink + 'static) -> Self {
+        Self {
+            outputs: Vec::new(),
+            default: Box::new(default),
+        }
+    }
+
+    /// Also deliver events matching `route` to `sink`, instead of to the default sink.
+    pub fn with_output(mut self, route: Route, sink: impl EventSink + 'static) -> Self {
+        self.outputs.push((route, Box::new(sink)));
+        self
+    }
+}
+
+impl EventSink for FanOut {
+    fn event(&mut self, event: Event) {
+        let mut routed = false;
+        for (route, sink) in &mut self.outputs {
+            if route.matches(&event) {
+                sink.event(event.clone());
+                routed = true;
+            }
+        }
+
+        if !routed {
+            self.default.event(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::diagnostic::{Code, Severity};
+    use crate::observer::event::{CommandTimeoutEvent, DiagnosticEvent, Gateway};
+    use std::sync::mpsc;
+
+    fn events() -> [Event; 2] {
+        let gateway = Gateway {
+            id: 0x1201.try_into().unwrap(),
+            address: None,
+            provenance: None,
+        };
+        [
+            Event::Diagnostic(DiagnosticEvent::new(
+                Severity::Warning,
+                Code::EventsRateLimited,
+                "slow down",
+            )),
+            Event::CommandTimeout(CommandTimeoutEvent {
+                gateway,
+                timestamp: chrono::Local::now(),
+                packet_type: 0x2f,
+                sequence_number: crate::gateway::transport::CommandSequenceNumber(1),
+                source: None,
+            }),
+        ]
+    }
+
+    #[test]
+    fn routes() {
+        let (default_tx, default) = mpsc::channel();
+        let (diagnostics_tx, diagnostics) = mpsc::channel();
+        let (everything_tx, everything) = mpsc::channel();
+        let (power_tx, power) = mpsc::channel();
+        let mut fan_out = FanOut::new(default_tx)
+            .with_output(
+                [EventKind::Diagnostic].into_iter().collect(),
+                diagnostics_tx,
+            )
+            .with_output(Route::default(), everything_tx)
+            .with_output([EventKind::PowerReport].into_iter().collect(), power_tx);
+
+        let [diagnostic, timeout] = events();
+        fan_out.event(diagnostic.clone());
+        fan_out.event(timeout.clone());
+
+        assert_eq!(
+            everything.try_iter().collect::<Vec<_>>(),
+            [diagnostic.clone(), timeout]
+        );
+        assert_eq!(diagnostics.try_iter().collect::<Vec<_>>(), [diagnostic]);
+        assert_eq!(power.try_iter().count(), 0);
+
+        // Everything was routed somewhere
+        assert_eq!(default.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn unrouted() {
+        let (default_tx, default) = mpsc::channel();
+        let (diagnostics_tx, diagnostics) = mpsc::channel();
+        let mut fan_out = FanOut::new(default_tx).with_output(
+            [EventKind::Diagnostic].into_iter().collect(),
+            diagnostics_tx,
+        );
+
+        let [diagnostic, timeout] = events();
+        fan_out.event(diagnostic.clone());
+        fan_out.event(timeout.clone());
+
+        assert_eq!(diagnostics.try_iter().collect::<Vec<_>>(), [diagnostic]);
+        assert_eq!(default.try_iter().collect::<Vec<_>>(), [timeout]);
+    }
+
+    #[test]
+    fn route_serialization() {
+        let route: Route = serde_json::from_str(r#"["power_report", "diagnostic"]"#).unwrap();
+        assert_eq!(
+            route,
+            [EventKind::Diagnostic, EventKind::PowerReport]
+                .into_iter()
+                .collect()
+        );
+        assert!(serde_json::from_str::<Route>(r#"["diagnostics"]"#).is_err());
+    }
+}
@@ -0,0 +1,526 @@
+//! One observer fed by several buses.
+//!
+//! A site with more than one controller has a bus for each, and so a receiver stack for each. A
+//! [`SharedObserver`] lets those stacks deliver to one observer, so that their events form a single
+//! stream: each stack gets a [`SourceSink`] of its own, which labels the events arising from its
+//! traffic with the name of its source.
+//!
+//! Each bus's controller assigns its own gateway IDs, so the buses' gateways often share them. The
+//! observer keys everything it learns by gateway ID, so each source is observed by an observer of
+//! its own, and those observers take turns with the configuration, clock, and outputs of the one
+//! passed to [`SharedObserver::new()`]. Their persistent states are saved together, by source, to
+//! its state file and journal. Likewise, an enumeration on one bus only affects that bus's
+//! gateways.
+
+use super::snapshot::SystemSnapshot;
+use super::Observer;
+use crate::gateway::link::{Frame, GatewayID};
+use crate::gateway::transport::polling::{PollingFairness, UnfairPolling};
+use crate::gateway::transport::{CommandSequenceNumber, InvalidFrameReason, ReceiveResponse};
+use crate::gateway::GatewayCapabilities;
+use crate::memory::MemoryReport;
+use crate::observer::calibration::Calibration;
+use crate::pv::application::{
+    Broadcast, NetworkStatusResponse, NodeTableResponseEntry, PowerReport, PvConfigurationResponse,
+    RadioConfigurationResponse, TopologyReport,
+};
+use crate::pv::network::{NodeAddress, ReceivedPacketHeader};
+use crate::pv::{LongAddress, NodeID, PacketType, SlotCounter};
+use crate::text::LossyStr;
+use crate::{gateway, pv};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// An observer shared between receiver stacks.
+///
+/// Clones share the same observer.
+#[derive(Debug, Clone)]
+pub struct SharedObserver(Arc<Mutex<Shared>>);
+
+#[derive(Debug)]
+struct Shared {
+    /// The observer given to [`SharedObserver::new()`], whose outputs the sources' observers
+    /// borrow.
+    site: Observer,
+    /// Each source's observer, by label.
+    sources: BTreeMap<Arc<str>, Observer>,
+}
+
+impl SharedObserver {
+    pub fn new(observer: Observer) -> Self {
+        Self(Arc::new(Mutex::new(Shared {
+            site: observer,
+            sources: BTreeMap::new(),
+        })))
+    }
+
+    /// A sink for the receiver stack reading from `source`, labeling the events arising from its
+    /// traffic with `source`.
+    ///
+    /// The source's observer starts from whatever state was saved for `source`.
+    pub fn source(&self, source: impl Into<String>) -> SourceSink {
+        let source: Arc<str> = source.into().into();
+        let mut shared = self.lock();
+        let Shared { site, sources } = &mut *shared;
+        sources.entry(source.clone()).or_insert_with(|| {
+            let state = site.persistent_state.sources.remove(&*source);
+            let mut observer = Observer::from_persistent_state(state.unwrap_or_default());
+            observer.source = Some(source.clone());
+            observer
+        });
+        SourceSink {
+            observer: self.clone(),
+            source,
+        }
+    }
+
+    /// Emit whatever is due with the passage of time on each source; see [`Observer::tick()`].
+    pub fn tick(&self) {
+        let mut shared = self.lock();
+        let sources: Vec<Arc<str>> = shared.sources.keys().cloned().collect();
+        for source in sources {
+            shared.with(&source, Observer::tick);
+        }
+    }
+
+    /// Save every source's persistent state if due; see [`Observer::save_state_if_due()`].
+    pub fn save_state_if_due(&self, now: std::time::Instant) {
+        self.lock().with_states(|site| site.save_state_if_due(now))
+    }
+
+    /// Shut down each source's observer, and then save their persistent states; see
+    /// [`Observer::shutdown()`].
+    pub fn shutdown(&self) {
+        let mut shared = self.lock();
+        let sources: Vec<Arc<str>> = shared.sources.keys().cloned().collect();
+        for source in sources {
+            shared.with(&source, Observer::emit_partial_summaries);
+        }
+        shared.with_states(Observer::shutdown)
+    }
+
+    /// Correct subsequent power reports using a given calibration; see
+    /// [`Observer::set_calibration()`].
+    pub fn set_calibration(&self, calibration: Calibration) {
+        self.lock().site.set_calibration(calibration)
+    }
+
+    /// Describe every gateway and node known on any source, labeled with their source.
+    pub fn snapshot(&self) -> SystemSnapshot {
+        let shared = self.lock();
+        let mut snapshot = shared.site.snapshot();
+        for observer in shared.sources.values() {
+            let source = observer.snapshot();
+            snapshot.gateways.extend(source.gateways);
+            snapshot.nodes.extend(source.nodes);
+        }
+        snapshot
+    }
+
+    /// Approximate the memory held by every source's observer.
+    pub fn memory_report(&self) -> MemoryReport {
+        let shared = self.lock();
+        let mut report = shared.site.memory_report();
+        for observer in shared.sources.values() {
+            report.extend(observer.memory_report());
+        }
+        report
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.0.lock().unwrap()
+    }
+}
+
+impl Shared {
+    /// Call `f` on a source's observer, lending it the site's configuration, clock, and outputs.
+    fn with<R>(&mut self, source: &str, f: impl FnOnce(&mut Observer) -> R) -> R {
+        let observer = self.sources.get_mut(source).unwrap();
+        lend(&mut self.site, observer);
+        let result = f(observer);
+        lend(&mut self.site, observer);
+        result
+    }
+
+    /// Call `f` on the site's observer with every source's persistent state lent to its own, so
+    /// that saving it saves theirs too.
+    fn with_states<R>(&mut self, f: impl FnOnce(&mut Observer) -> R) -> R {
+        for (source, observer) in &mut self.sources {
+            let state = std::mem::take(&mut observer.persistent_state);
+            self.site
+                .persistent_state
+                .sources
+                .insert(source.to_string(), state);
+        }
+        let result = f(&mut self.site);
+        for (source, observer) in &mut self.sources {
+            let state = self.site.persistent_state.sources.remove(&**source);
+            observer.persistent_state = state.unwrap_or_default();
+        }
+        result
+    }
+}
+
+/// Exchange the configuration, clock, calibration, and outputs of two observers, as to lend them
+/// from one to the other and back.
+///
+/// Each keeps its own persistent state and its knowledge of its gateways. The state file stays
+/// with the site, so that only it saves.
+fn lend(a: &mut Observer, b: &mut Observer) {
+    std::mem::swap(&mut a.config, &mut b.config);
+    std::mem::swap(&mut a.clock, &mut b.clock);
+    std::mem::swap(&mut a.calibration, &mut b.calibration);
+    std::mem::swap(&mut a.event_sink, &mut b.event_sink);
+    std::mem::swap(&mut a.diagnostics, &mut b.diagnostics);
+    std::mem::swap(&mut a.invalid_frame_log, &mut b.invalid_frame_log);
+    std::mem::swap(&mut a.invalid_dump, &mut b.invalid_dump);
+    std::mem::swap(&mut a.journal, &mut b.journal);
+    std::mem::swap(&mut a.rate_limiter, &mut b.rate_limiter);
+    std::mem::swap(&mut a.counters, &mut b.counters);
+}
+
+/// Delivers one receiver stack's traffic to a [`SharedObserver`].
+#[derive(Debug, Clone)]
+pub struct SourceSink {
+    observer: SharedObserver,
+    source: Arc<str>,
+}
+
+impl SourceSink {
+    /// The label given to this source's events.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn observer(&self) -> &SharedObserver {
+        &self.observer
+    }
+
+    /// Call `f` on this source's observer.
+    fn with<R>(&self, f: impl FnOnce(&mut Observer) -> R) -> R {
+        self.observer.lock().with(&self.source, f)
+    }
+
+    /// Note raw bytes received from this source; see [`Observer::bytes_received()`].
+    pub fn bytes_received(&self, bytes: &[u8]) {
+        self.with(|o| o.bytes_received(bytes))
+    }
+}
+
+impl gateway::transport::Sink for SourceSink {
+    fn enumeration_started(&mut self, enumeration_gateway_id: GatewayID) {
+        self.with(|o| o.enumeration_started(enumeration_gateway_id))
+    }
+
+    fn gateway_identity_observed(&mut self, gateway_id: GatewayID, address: LongAddress) {
+        self.with(|o| o.gateway_identity_observed(gateway_id, address))
+    }
+
+    fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str, raw: &[u8]) {
+        self.with(|o| o.gateway_version_observed(gateway_id, version, raw))
+    }
+
+    fn gateway_id_assigned(&mut self, old: GatewayID, new: GatewayID, address: LongAddress) {
+        self.with(|o| o.gateway_id_assigned(old, new, address))
+    }
+
+    fn enumeration_ended(&mut self, gateway_id: GatewayID) {
+        self.with(|o| o.enumeration_ended(gateway_id))
+    }
+
+    fn gateway_slot_counter_captured(&mut self, gateway_id: GatewayID) {
+        self.with(|o| o.gateway_slot_counter_captured(gateway_id))
+    }
+
+    fn gateway_slot_counter_observed(&mut self, gateway_id: GatewayID, slot_counter: SlotCounter) {
+        self.with(|o| o.gateway_slot_counter_observed(gateway_id, slot_counter))
+    }
+
+    fn packet_received(
+        &mut self,
+        gateway_id: GatewayID,
+        header: &ReceivedPacketHeader,
+        data: &[u8],
+    ) {
+        self.with(|o| o.packet_received(gateway_id, header, data))
+    }
+
+    fn command_executed(
+        &mut self,
+        gateway_id: GatewayID,
+        request: (PacketType, &[u8]),
+        response: (PacketType, &[u8]),
+    ) {
+        self.with(|o| o.command_executed(gateway_id, request, response))
+    }
+
+    fn command_timed_out(
+        &mut self,
+        gateway_id: GatewayID,
+        packet_type: PacketType,
+        sequence_number: CommandSequenceNumber,
+    ) {
+        self.with(|o| o.command_timed_out(gateway_id, packet_type, sequence_number))
+    }
+
+    fn gateway_tx_buffers_free_observed(&mut self, gateway_id: GatewayID, tx_buffers_free: u8) {
+        self.with(|o| o.gateway_tx_buffers_free_observed(gateway_id, tx_buffers_free))
+    }
+
+    fn receive_status(&mut self, gateway_id: GatewayID, status: &ReceiveResponse) {
+        self.with(|o| o.receive_status(gateway_id, status))
+    }
+
+    fn invalid_frame(&mut self, frame: &Frame, reason: InvalidFrameReason) {
+        self.with(|o| gateway::transport::Sink::invalid_frame(o, frame, reason))
+    }
+
+    fn checksum_failed(&mut self, bytes: &[u8]) {
+        self.with(|o| o.checksum_failed(bytes))
+    }
+
+    fn polling_fairness(&self) -> PollingFairness {
+        self.with(|o| o.polling_fairness())
+    }
+
+    fn polling_unfair(&mut self, gateway_id: GatewayID, polling: &UnfairPolling) {
+        self.with(|o| o.polling_unfair(gateway_id, polling))
+    }
+
+    fn gateway_capabilities(&self, gateway_id: GatewayID) -> GatewayCapabilities {
+        self.with(|o| o.gateway_capabilities(gateway_id))
+    }
+}
+
+impl pv::application::Sink for SourceSink {
+    fn invalid_packet(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        packet_type: PacketType,
+        data: &[u8],
+    ) {
+        self.with(|o| o.invalid_packet(gateway_id, pv_node_id, packet_type, data))
+    }
+
+    fn string_request(&mut self, gateway_id: GatewayID, pv_node_id: NodeID, request: LossyStr) {
+        self.with(|o| o.string_request(gateway_id, pv_node_id, request))
+    }
+
+    fn string_response(&mut self, gateway_id: GatewayID, pv_node_id: NodeID, response: LossyStr) {
+        self.with(|o| o.string_response(gateway_id, pv_node_id, response))
+    }
+
+    fn node_table_page(
+        &mut self,
+        gateway_id: GatewayID,
+        start_address: NodeAddress,
+        nodes: &[NodeTableResponseEntry],
+    ) {
+        self.with(|o| o.node_table_page(gateway_id, start_address, nodes))
+    }
+
+    fn topology_report(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        topology_report: &TopologyReport,
+    ) {
+        self.with(|o| o.topology_report(gateway_id, pv_node_id, topology_report))
+    }
+
+    fn power_report(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        power_report: &PowerReport,
+    ) {
+        self.with(|o| o.power_report(gateway_id, pv_node_id, power_report))
+    }
+
+    fn broadcast(&mut self, gateway_id: GatewayID, payload: &Broadcast) {
+        self.with(|o| o.broadcast(gateway_id, payload))
+    }
+
+    fn network_status(&mut self, gateway_id: GatewayID, status: &NetworkStatusResponse) {
+        self.with(|o| o.network_status(gateway_id, status))
+    }
+
+    fn gateway_radio_configuration(
+        &mut self,
+        gateway_id: GatewayID,
+        config: &RadioConfigurationResponse,
+    ) {
+        self.with(|o| o.gateway_radio_configuration(gateway_id, config))
+    }
+
+    fn pv_configuration(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        config: &PvConfigurationResponse,
+    ) {
+        self.with(|o| o.pv_configuration(gateway_id, pv_node_id, config))
+    }
+
+    fn packet_loss_estimated(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: NodeID,
+        estimated_loss_pct: f64,
+    ) {
+        self.with(|o| o.packet_loss_estimated(gateway_id, pv_node_id, estimated_loss_pct))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::clock::ManualClock;
+    use crate::observer::event::Event;
+    use crate::pv::physical::RSSI;
+    use crate::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario, Stream};
+    use std::time::{Duration, SystemTime};
+
+    fn start() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+    }
+
+    /// A gateway on a given bus with two nodes, each reporting once.
+    fn scenario(gateway_id: u16, bus: u8) -> Scenario {
+        let gateway_id = GatewayID::try_from(gateway_id).unwrap();
+        let [a, b] = u16::from(gateway_id).to_be_bytes();
+        let nodes: Vec<Node> = (2..4)
+            .map(|id| Node {
+                id: NodeID::try_from(id).unwrap(),
+                address: LongAddress([0x04, 0xC0, 0x5B, 0x40, a, b, bus, id as u8]),
+            })
+            .collect();
+        let power_reports = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| PowerReport {
+                gateway_id,
+                node_id: node.id,
+                slot_counter: SlotCounter::from(i as u16 * 500),
+                measurement: Measurement {
+                    voltage_in: 30.0,
+                    voltage_out: 29.0,
+                    current: 6.5,
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                },
+            })
+            .collect();
+        Scenario {
+            gateways: vec![Gateway {
+                id: gateway_id,
+                address: LongAddress([0x04, 0xC0, 0x5B, 0x30, bus, 0x02, a, b]),
+                version: "Mgate Version G8.59\r".into(),
+                nodes,
+            }],
+            power_reports,
+            ..Scenario::new(start())
+        }
+    }
+
+    /// Deliver a stream, setting the clock to the time at which each part appeared.
+    fn feed(rx: &mut crate::Pipeline<SourceSink>, stream: &Stream, clock: &ManualClock) {
+        for (i, &(offset, time)) in stream.times.iter().enumerate() {
+            let end = stream
+                .times
+                .get(i + 1)
+                .map_or(stream.bytes.len(), |(end, _)| *end);
+            clock.set(time);
+            rx.extend_from_slice(&stream.bytes[offset..end]);
+        }
+    }
+
+    /// Observe each scenario on a source of its own, asserting that each source's events are those
+    /// of its scenario, labeled with the source.
+    fn observe(scenarios: [(&str, Scenario); 2]) -> SharedObserver {
+        let clock = ManualClock::new(start());
+        let (tx, events) = std::sync::mpsc::channel();
+        let mut observer = Observer::with_event_sink(tx);
+        observer.set_clock(clock.clone());
+        let observer = SharedObserver::new(observer);
+
+        let mut expected = Vec::new();
+        for (source, scenario) in scenarios {
+            let mut rx = crate::pipeline(observer.source(source));
+            assert_eq!(rx.sink().sink().sink().source(), source);
+            feed(&mut rx, &scenario.encode(), &clock);
+            expected.extend(scenario.expected_events().into_iter().map(|mut event| {
+                event.set_source(source);
+                event
+            }));
+        }
+        let actual: Vec<Event> = events.try_iter().collect();
+        assert_eq!(actual, expected);
+        assert!(actual[0].to_json().contains(r#""source":"tcp:cca1:7160""#));
+        observer
+    }
+
+    #[test]
+    fn labels() {
+        let observer = observe([
+            ("tcp:cca1:7160", scenario(0x1201, 0)),
+            ("tcp:cca2:7160", scenario(0x1202, 0)),
+        ]);
+
+        // Both gateways appear in the snapshot, labeled with their sources
+        let snapshot = observer.snapshot();
+        let gateways: Vec<_> = snapshot
+            .gateways
+            .iter()
+            .map(|gateway| (gateway.source.as_deref(), u16::from(gateway.id)))
+            .collect();
+        assert_eq!(
+            gateways,
+            [
+                (Some("tcp:cca1:7160"), 0x1201),
+                (Some("tcp:cca2:7160"), 0x1202)
+            ]
+        );
+    }
+
+    #[test]
+    fn colliding_gateway_ids() {
+        // Each controller numbers its own gateways, so both buses have a gateway 0x1201. Each
+        // source's gateway keeps its own identity and nodes, with no diagnostics about the other's.
+        let (a, b) = (scenario(0x1201, 1), scenario(0x1201, 2));
+        let addresses = [a.gateways[0].address, b.gateways[0].address];
+        let observer = observe([("tcp:cca1:7160", a), ("tcp:cca2:7160", b)]);
+
+        let snapshot = observer.snapshot();
+        let gateways: Vec<_> = snapshot
+            .gateways
+            .iter()
+            .map(|gateway| (gateway.source.as_deref(), gateway.address))
+            .collect();
+        assert_eq!(
+            gateways,
+            [
+                (Some("tcp:cca1:7160"), Some(addresses[0])),
+                (Some("tcp:cca2:7160"), Some(addresses[1])),
+            ]
+        );
+        assert_eq!(snapshot.nodes.len(), 4);
+
+        // Their persistent states are saved by source, and restored to the same sources
+        let state = observer
+            .lock()
+            .with_states(|site| site.persistent_state().clone());
+        assert_eq!(state.sources.len(), 2);
+        assert!(state.gateway_identities.is_empty());
+        let restored = SharedObserver::new(Observer::from_persistent_state(state));
+        let _ = restored.source("tcp:cca2:7160");
+        let restored = restored.lock();
+        let identities = &restored.sources["tcp:cca2:7160"]
+            .persistent_state()
+            .gateway_identities;
+        assert_eq!(identities.values().collect::<Vec<_>>(), [&addresses[1]]);
+        assert_eq!(restored.site.persistent_state().sources.len(), 1);
+    }
+}
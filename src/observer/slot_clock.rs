@@ -1,5 +1,8 @@
-use crate::pv::link::InvalidSlotNumber;
-use crate::pv::SlotCounter;
+use crate::pv::link::{long_address_keys, InvalidSlotNumber};
+use crate::pv::{LongAddress, SlotCounter};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::time::{Duration, SystemTime};
 
 /// A data structure collating absolute timestamps to slot counters.
@@ -8,22 +11,125 @@ pub struct SlotClock {
     // SystemTime timestamp per thousand ticks, i.e. per ±5s, wrapping with the counter
     times: [SystemTime; 48],
     last_index: usize,
+    last_slot_counter: SlotCounter,
     last_time: SystemTime,
+    // The slot rate used to convert between slots and time
+    slots_per_second: f64,
+    // The ongoing measurement of the slot rate
+    calibration: Option<Calibration>,
 }
 
+/// A measurement of a gateway's actual slot rate, which differs from nominal by the error of its
+/// crystal.
+///
+/// The error is stable over time, so a measurement can be carried across restarts. The anchor
+/// allows a later observation to extend the measurement, provided it arrives before the error
+/// accumulates to a quarter of a wrap, which takes days.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Calibration {
+    /// The measured number of slots per second.
+    pub slots_per_second: f64,
+    /// The number of seconds over which the rate was measured.
+    pub baseline: f64,
+    /// The most recently observed slot, counting from the start of a wrap.
+    pub anchor_slot: u32,
+    /// The time at which `anchor_slot` was observed.
+    pub anchor_time: DateTime<Local>,
+}
+
+/// A slot clock's model of a gateway's slot counter: the latest observation, and the rate at which
+/// the counter advances.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Parameters {
+    /// The most recently observed slot counter.
+    pub slot_counter: SlotCounter,
+    /// The time at which `slot_counter` was observed.
+    pub system_time: SystemTime,
+    /// The duration of each slot, which is nominally 5 ms.
+    pub slot_duration: Duration,
+    /// Whether `slot_duration` was measured rather than assumed to be nominal.
+    pub calibrated: bool,
+}
+
+/// A gateway's latest slot counter observation, kept across restarts.
+///
+/// A restarted observer can't place power reports in time until it observes the gateway's slot
+/// counter again, which on a quiet bus can take a minute. A recent anchor can stand in until then.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Anchor {
+    /// The observed slot counter, as its raw value.
+    pub slot_counter: u16,
+    /// The time at which `slot_counter` was observed.
+    pub time: DateTime<Local>,
+}
+
+/// Slot clock calibrations for each gateway, by hardware address.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct SlotClockCalibrations(
+    #[serde(with = "long_address_keys")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<String, Calibration>"))]
+    pub BTreeMap<LongAddress, Calibration>,
+);
+
 const NOMINAL_DURATION_PER_SLOT: Duration = Duration::from_millis(5);
 const NOMINAL_DURATION_PER_INDEX: Duration = Duration::from_millis(5 * 1000);
+const NOMINAL_DURATION_PER_WRAP: Duration = Duration::from_millis(5 * 1000 * 48);
+const NOMINAL_SLOTS_PER_SECOND: f64 = 200.0;
+const SLOTS_PER_WRAP: u32 = 48000;
+
+/// The shortest measurement which is trusted in place of the nominal slot rate.
+const MIN_CALIBRATION_BASELINE: f64 = 600.0;
+
+/// The longest period over which a measurement is averaged, so that it can follow slow drift.
+const MAX_CALIBRATION_BASELINE: f64 = 86400.0;
+
+/// The greatest plausible deviation of the slot rate from nominal.
+const MAX_CALIBRATION_ERROR: f64 = 0.01;
+
+/// How far after its receipt a slot counter may plausibly appear to have been sampled, accounting
+/// for jitter between the gateway's clock and ours.
+const RECEIVE_TOLERANCE: Duration = NOMINAL_DURATION_PER_INDEX;
+
+/// How far after the latest observation a slot counter may appear to have been sampled, accounting
+/// for jitter between observations within the same thousand slots.
+const OBSERVATION_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// How far an observation may stray from a clock's prediction and still be considered consistent
+/// with it, in slots, in addition to the greatest plausible error in the slot rate.
+const CONSISTENCY_TOLERANCE: f64 = 1000.0;
 
 impl SlotClock {
     pub fn new(slot_counter: SlotCounter, time: SystemTime) -> Result<Self, InvalidSlotNumber> {
+        Self::with_calibration(slot_counter, time, None)
+    }
+
+    /// Create a slot clock, using a previous calibration if available.
+    pub fn with_calibration(
+        slot_counter: SlotCounter,
+        time: SystemTime,
+        calibration: Option<Calibration>,
+    ) -> Result<Self, InvalidSlotNumber> {
         let (index, offset) = Self::index_and_offset(slot_counter)?;
-        let index_time = time - offset;
 
         let mut table = Self {
-            times: [index_time; 48],
+            times: [time; 48],
             last_index: index,
+            last_slot_counter: slot_counter,
             last_time: time,
+            slots_per_second: NOMINAL_SLOTS_PER_SECOND,
+            calibration,
         };
+        if let Some(calibration) = calibration {
+            table.adopt(&calibration);
+        }
+        table.calibrate(Self::absolute_slot(slot_counter)?, time);
+
+        let index_time = time - table.scale(offset);
+        table.times = [index_time; 48];
 
         // Walk backwards, assuming nominal time for each
         let mut index_time = index_time;
@@ -37,7 +143,7 @@ impl SlotClock {
             }
 
             // Subtract one duration
-            index_time -= NOMINAL_DURATION_PER_INDEX;
+            index_time -= table.scale(NOMINAL_DURATION_PER_INDEX);
             // Assign
             table.times[i] = index_time;
         }
@@ -45,32 +151,157 @@ impl SlotClock {
         Ok(table)
     }
 
+    fn absolute_slot(slot_counter: SlotCounter) -> Result<u32, InvalidSlotNumber> {
+        slot_counter
+            .slot_number()
+            .map(|n| (slot_counter.epoch() as u8 as u32) * 12000 + u16::from(n) as u32)
+    }
+
     fn index_and_offset(slot_counter: SlotCounter) -> Result<(usize, Duration), InvalidSlotNumber> {
-        slot_counter.slot_number().map(|n| {
-            let absolute_slot =
-                (slot_counter.epoch() as u8 as usize) * 12000 + (u16::from(n)) as usize;
-            let index = absolute_slot / 1000;
-            let offset = NOMINAL_DURATION_PER_SLOT * (absolute_slot % 1000) as u32;
+        Self::absolute_slot(slot_counter).map(|absolute_slot| {
+            let index = absolute_slot as usize / 1000;
+            let offset = NOMINAL_DURATION_PER_SLOT * (absolute_slot % 1000);
             (index, offset)
         })
     }
 
+    /// Convert a nominal duration to this gateway's actual duration.
+    fn scale(&self, nominal: Duration) -> Duration {
+        if self.slots_per_second == NOMINAL_SLOTS_PER_SECOND {
+            nominal
+        } else {
+            nominal.mul_f64(NOMINAL_SLOTS_PER_SECOND / self.slots_per_second)
+        }
+    }
+
+    /// The current measurement of this gateway's slot rate, if any.
+    pub fn calibration(&self) -> Option<&Calibration> {
+        self.calibration.as_ref()
+    }
+
+    /// The clock's current model of the slot counter.
+    pub fn parameters(&self) -> Parameters {
+        Parameters {
+            slot_counter: self.last_slot_counter,
+            system_time: self.last_time,
+            slot_duration: self.scale(NOMINAL_DURATION_PER_SLOT),
+            calibrated: self.slots_per_second != NOMINAL_SLOTS_PER_SECOND,
+        }
+    }
+
+    /// The clock's latest observation, to be kept across restarts.
+    pub fn anchor(&self) -> Anchor {
+        Anchor {
+            slot_counter: self.last_slot_counter.into(),
+            time: self.last_time.into(),
+        }
+    }
+
+    /// Whether an observation agrees with the slot counter this clock predicts for its time.
+    ///
+    /// A gateway's slot counter restarts when its controller does, so an observation which
+    /// disagrees means this clock describes a counter which no longer exists. A restarted counter
+    /// agrees by coincidence about one time in twenty.
+    pub fn is_consistent(&self, slot_counter: SlotCounter, time: SystemTime) -> bool {
+        let (Ok(absolute_slot), Ok(last_slot)) = (
+            Self::absolute_slot(slot_counter),
+            Self::absolute_slot(self.last_slot_counter),
+        ) else {
+            return false;
+        };
+        let Ok(elapsed) = time.duration_since(self.last_time) else {
+            return false;
+        };
+        let elapsed = elapsed.as_secs_f64() * self.slots_per_second;
+
+        let predicted = (last_slot as f64 + elapsed) % SLOTS_PER_WRAP as f64;
+        let difference = (absolute_slot as f64 - predicted).rem_euclid(SLOTS_PER_WRAP as f64);
+        let difference = difference.min(SLOTS_PER_WRAP as f64 - difference);
+        difference <= CONSISTENCY_TOLERANCE + elapsed * MAX_CALIBRATION_ERROR
+    }
+
+    /// Use a calibration's rate, if it's trustworthy.
+    fn adopt(&mut self, calibration: &Calibration) {
+        let error = calibration.slots_per_second / NOMINAL_SLOTS_PER_SECOND - 1.0;
+        if calibration.baseline >= MIN_CALIBRATION_BASELINE && error.abs() <= MAX_CALIBRATION_ERROR
+        {
+            self.slots_per_second = calibration.slots_per_second;
+        }
+    }
+
+    /// Extend the measurement of the slot rate with an observation.
+    fn calibrate(&mut self, absolute_slot: u32, time: SystemTime) {
+        let restart = Calibration {
+            slots_per_second: self.slots_per_second,
+            baseline: 0.0,
+            anchor_slot: absolute_slot,
+            anchor_time: time.into(),
+        };
+
+        let Some(calibration) = self.calibration.as_mut() else {
+            self.calibration = Some(restart);
+            return;
+        };
+
+        let Ok(elapsed) = time.duration_since(calibration.anchor_time.into()) else {
+            // Time went backwards
+            *calibration = restart;
+            return;
+        };
+        let elapsed = elapsed.as_secs_f64();
+
+        // Count the slots which elapsed, choosing the number of wraps which best fits the rate
+        let predicted = elapsed * self.slots_per_second;
+        let partial =
+            ((absolute_slot + SLOTS_PER_WRAP - calibration.anchor_slot) % SLOTS_PER_WRAP) as f64;
+        let wraps = ((predicted - partial) / SLOTS_PER_WRAP as f64)
+            .round()
+            .max(0.0);
+        let measured = partial + wraps * SLOTS_PER_WRAP as f64;
+        if (measured - predicted).abs() > (SLOTS_PER_WRAP / 4) as f64 {
+            // Too far off to be sure how many wraps elapsed, or the counter was reset
+            *calibration = restart;
+            return;
+        }
+
+        let baseline = calibration.baseline + elapsed;
+        if baseline > 0.0 {
+            let slots = calibration.slots_per_second * calibration.baseline + measured;
+            calibration.slots_per_second = slots / baseline;
+        }
+        calibration.baseline = baseline.min(MAX_CALIBRATION_BASELINE);
+        calibration.anchor_slot = absolute_slot;
+        calibration.anchor_time = time.into();
+
+        let calibration = *calibration;
+        self.adopt(&calibration);
+    }
+
+    /// Record that a gateway's slot counter was `slot_counter` at `time`.
+    ///
+    /// Returns whether the observation moved the clock on to a new thousand slots, which is when
+    /// it reassigns times.
     pub fn set(
         &mut self,
         slot_counter: SlotCounter,
         time: SystemTime,
-    ) -> Result<(), InvalidSlotNumber> {
+    ) -> Result<bool, InvalidSlotNumber> {
         let (index, offset) = Self::index_and_offset(slot_counter)?;
 
         if self.last_time > time {
             // Clock went backwards
-            // Replace the table entirely
+            // Replace the table entirely, keeping the calibration
             log::warn!("time went backwards: {:?} => {:?}", self.last_time, time);
-            *self = Self::new(slot_counter, time)?;
-            return Ok(());
-        } else if self.last_index != index {
+            *self = Self::with_calibration(slot_counter, time, self.calibration)?;
+            return Ok(true);
+        }
+
+        self.calibrate(Self::absolute_slot(slot_counter)?, time);
+
+        let new_index = self.last_index != index;
+        if new_index {
             // Assign this index
-            let index_time = time - offset;
+            let index_time = time - self.scale(offset);
 
             // Set the entry
             self.times[index] = index_time;
@@ -87,7 +318,7 @@ impl SlotClock {
                 }
 
                 // Subtract one duration
-                index_time -= NOMINAL_DURATION_PER_INDEX;
+                index_time -= self.scale(NOMINAL_DURATION_PER_INDEX);
                 // Assign
                 self.times[i] = index_time;
             }
@@ -97,15 +328,52 @@ impl SlotClock {
 
         // Record this assignment
         self.last_index = index;
+        self.last_slot_counter = slot_counter;
         self.last_time = time;
 
-        Ok(())
+        Ok(new_index)
     }
 
     pub fn get(&self, slot_counter: SlotCounter) -> Result<SystemTime, InvalidSlotNumber> {
         // TODO: interpolate for accuracy? Or don't, because measurements come in at thousands.
         let (index, offset) = Self::index_and_offset(slot_counter)?;
-        Ok(self.times[index] + offset)
+        Ok(self.times[index] + self.scale(offset))
+    }
+
+    /// Determine the time of a slot counter received at `receive_time`.
+    ///
+    /// The table only describes the most recent wrap of the slot counter as of its last update. If
+    /// the table hasn't been updated for more than a wrap, `get()` would place a recent slot
+    /// counter in a stale wrap. This method instead adjusts the result by whole wraps so that it
+    /// falls within the wrap preceding `receive_time`.
+    ///
+    /// If the table was updated as of `receive_time`, the slot counter can't be newer than the
+    /// latest observation, so one which appears to be is placed a wrap earlier. A slot counter
+    /// received more than a wrap after it was sampled is still placed whole wraps too late: the
+    /// counter alone can't tell those wraps apart.
+    pub fn get_near(
+        &self,
+        slot_counter: SlotCounter,
+        receive_time: SystemTime,
+    ) -> Result<SystemTime, InvalidSlotNumber> {
+        let mut time = self.get(slot_counter)?;
+        let mut latest = receive_time + RECEIVE_TOLERANCE;
+        if self.last_time + RECEIVE_TOLERANCE >= receive_time {
+            latest = latest.min(self.last_time + OBSERVATION_TOLERANCE);
+        }
+        let wrap = self.scale(NOMINAL_DURATION_PER_WRAP);
+
+        // Move forwards into the most recent plausible wrap
+        while time + wrap <= latest {
+            time += wrap;
+        }
+
+        // Move backwards if the slot counter appears to be from the future
+        while time > latest {
+            time -= wrap;
+        }
+
+        Ok(time)
     }
 }
 
@@ -142,9 +410,27 @@ mod tests {
             Ok(x - Duration::from_secs(180 + 55))
         );
 
-        // Advance to 0xc000 + 1000 at 5 seconds later
+        // Advance to 0xc000 + 1000 at 5 seconds later, which starts a new index
         let later = x + Duration::from_secs(5);
-        clock.set(SlotCounter::from(0xc000 + 1000), later).unwrap();
+        assert_eq!(clock.set(SlotCounter::from(0xc000 + 1000), later), Ok(true));
+        assert_eq!(
+            clock.parameters(),
+            Parameters {
+                slot_counter: SlotCounter::from(0xc000 + 1000),
+                system_time: later,
+                slot_duration: Duration::from_millis(5),
+                calibrated: false,
+            }
+        );
+
+        // The next slot is in the same index
+        assert_eq!(
+            clock.set(
+                SlotCounter::from(0xc000 + 1001),
+                later + Duration::from_millis(5)
+            ),
+            Ok(false)
+        );
 
         // 0x8000 was one minute before x
         assert_eq!(
@@ -168,6 +454,151 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_near_after_long_gap() {
+        let x = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+        let clock = SlotClock::new(SlotCounter::from(0x0000), x).unwrap();
+
+        // Received promptly, a slot counter is where get() says it is
+        assert_eq!(
+            clock.get_near(SlotCounter::from(0xc000), x),
+            Ok(x - Duration::from_secs(60))
+        );
+        assert_eq!(
+            clock.get_near(SlotCounter::from(0x0000), x + Duration::from_secs(2)),
+            Ok(x)
+        );
+
+        // Five minutes pass without any updates to the clock, and then we receive the slot counter
+        // from that time. get() places it in the stale wrap, two wraps too early.
+        let slot_counter = SlotCounter::from(0x4000);
+        let receive_time = x + Duration::from_secs(300 + 2);
+        assert_eq!(clock.get(slot_counter), Ok(x - Duration::from_secs(180)));
+        assert_eq!(
+            clock.get_near(slot_counter, receive_time),
+            Ok(x + Duration::from_secs(300))
+        );
+
+        // A receive time earlier than the table moves backwards instead
+        assert_eq!(
+            clock.get_near(SlotCounter::from(0x0000), x - Duration::from_secs(200)),
+            Ok(x - Duration::from_secs(240))
+        );
+    }
+
+    #[test]
+    fn get_near_delayed() {
+        let x = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+        let at = |s: u64| x + Duration::from_secs(s);
+        let slot_counter_at = |s: u64| {
+            let slot = (s * 200 % 48000) as u16;
+            SlotCounter::from(((slot / 12000) << 14) | (slot % 12000))
+        };
+
+        // The gateway is polled every second for five minutes
+        let mut clock = SlotClock::new(slot_counter_at(0), x).unwrap();
+        for s in 1..=300 {
+            clock.set(slot_counter_at(s), at(s)).unwrap();
+        }
+        let now = at(300);
+
+        // A report sampled just now is placed just now
+        assert_eq!(clock.get_near(slot_counter_at(300), now), Ok(now));
+        assert_eq!(clock.get_near(slot_counter_at(290), now), Ok(at(290)));
+
+        // One sampled just after the latest observation must be from the previous wrap, even though
+        // get() puts it in the future
+        assert_eq!(clock.get(slot_counter_at(303)), Ok(at(303)));
+        assert_eq!(clock.get_near(slot_counter_at(303), now), Ok(at(63)));
+
+        // Reports delayed by 5 or 9 minutes are indistinguishable from one delayed by 1 minute, so
+        // they're placed a whole number of wraps too late
+        assert_eq!(clock.get_near(slot_counter_at(240), now), Ok(at(240)));
+        assert_eq!(clock.get_near(slot_counter_at(0), now), Ok(at(240)));
+        let nine_minutes_ago = slot_counter_at(300 + 4 * 240 - 540);
+        assert_eq!(clock.get_near(nine_minutes_ago, now), Ok(at(240)));
+    }
+
+    #[test]
+    fn calibration() {
+        let x = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+
+        // A gateway whose crystal runs 0.2% fast, observed once per second
+        let slot_counter_at = |seconds: u32| {
+            let slot = (seconds as f64 * 200.4) as u32 % 48000;
+            SlotCounter::from((((slot / 12000) << 14) | (slot % 12000)) as u16)
+        };
+        let mut clock = SlotClock::new(slot_counter_at(0), x).unwrap();
+        for s in 1..=3600 {
+            clock
+                .set(slot_counter_at(s), x + Duration::from_secs(s as u64))
+                .unwrap();
+
+            // The nominal rate is used until the measurement is long enough to trust
+            if s < MIN_CALIBRATION_BASELINE as u32 {
+                assert_eq!(clock.slots_per_second, NOMINAL_SLOTS_PER_SECOND);
+            }
+        }
+
+        let calibration = *clock.calibration().unwrap();
+        assert!((calibration.slots_per_second - 200.4).abs() < 0.001);
+        let parameters = clock.parameters();
+        assert!(parameters.calibrated);
+        assert_eq!(parameters.slot_counter, slot_counter_at(3600));
+        assert_eq!(parameters.slot_duration.as_nanos() / 1000, 4990);
+        assert_eq!(calibration.baseline, 3600.0);
+        assert_eq!(clock.slots_per_second, calibration.slots_per_second);
+
+        // A clock started from this calibration a day later converts slots to time at the measured
+        // rate, where the nominal rate would be 120ms off
+        let later = x + Duration::from_secs(86400);
+        let clock =
+            SlotClock::with_calibration(slot_counter_at(86400), later, Some(calibration)).unwrap();
+        let expected = later - Duration::from_secs(60);
+        let actual = clock.get(slot_counter_at(86400 - 60)).unwrap();
+        let error = match actual.duration_since(expected) {
+            Ok(d) => d,
+            Err(e) => e.duration(),
+        };
+        assert!(error < Duration::from_millis(10), "{:?}", error);
+
+        // The measurement was extended across the gap
+        assert_eq!(
+            clock.calibration().unwrap().baseline,
+            MAX_CALIBRATION_BASELINE
+        );
+    }
+
+    #[test]
+    fn is_consistent() {
+        let x = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+        let clock = SlotClock::new(SlotCounter::from(0x4000 + 500), x).unwrap();
+        assert_eq!(
+            clock.anchor(),
+            Anchor {
+                slot_counter: 0x4000 + 500,
+                time: x.into(),
+            }
+        );
+
+        // Ten minutes is 120000 slots, two and a half wraps, ending 24000 slots further on
+        let later = x + Duration::from_secs(600);
+        assert!(clock.is_consistent(SlotCounter::from(0xc000 + 500), later));
+        assert!(clock.is_consistent(SlotCounter::from(0xc000 + 1500), later));
+        assert!(clock.is_consistent(SlotCounter::from(0x8000 + 11000), later));
+
+        // A counter which started over from zero at the restart doesn't fit
+        assert!(!clock.is_consistent(SlotCounter::from(3000), later));
+        assert!(!clock.is_consistent(SlotCounter::from(0xc000 + 4000), later));
+
+        // Nor does one from before the clock's latest observation, or an invalid one
+        assert!(!clock.is_consistent(
+            SlotCounter::from(0x4000 + 500),
+            x - Duration::from_secs(240)
+        ));
+        assert!(!clock.is_consistent(SlotCounter::from(12000), later));
+    }
+
     #[test]
     fn index_and_offset() {
         assert_eq!(
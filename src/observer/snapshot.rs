@@ -0,0 +1,208 @@
+//! What an observer believes the observed system looks like.
+//!
+//! Gateways, nodes, and the mesh between them are learned piecemeal from enumerations, node table
+//! walks, and topology reports. A [`SystemSnapshot`] gathers all of it in one place.
+
+use super::node_inventory::NodeInventory;
+use crate::barcode::Barcode;
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::pv::application::{NetworkStatusResponse, TopologyReport};
+use crate::pv::physical::RSSI;
+use crate::pv::{LongAddress, NodeID};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The latest topology report from each node.
+#[derive(Debug, Clone, Default)]
+pub(super) struct TopologyTable(BTreeMap<(GatewayID, NodeID), Topology>);
+
+impl TopologyTable {
+    pub fn insert(
+        &mut self,
+        gateway_id: GatewayID,
+        node_id: NodeID,
+        report: &TopologyReport,
+        now: DateTime<Local>,
+    ) {
+        self.0.insert(
+            (gateway_id, node_id),
+            Topology {
+                next_hop: NodeID::try_from(u16::from(report.next_hop.0)).ok(),
+                address: report.long_address,
+                rssi: report.rssi,
+                updated: now,
+            },
+        );
+    }
+
+    pub fn get(&self, gateway_id: GatewayID, node_id: NodeID) -> Option<&Topology> {
+        self.0.get(&(gateway_id, node_id))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (GatewayID, NodeID)> + '_ {
+        self.0.keys().copied()
+    }
+
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<(GatewayID, NodeID), Topology>(self.0.len())
+    }
+}
+
+/// A node's place in the mesh, according to its latest topology report.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub struct Topology {
+    /// The node through which this node reaches its gateway, where node 1 is the gateway itself.
+    pub next_hop: Option<NodeID>,
+    /// The hardware address the node reported for itself.
+    pub address: LongAddress,
+    /// The signal strength the node reported.
+    pub rssi: RSSI,
+    /// When the report was received.
+    pub updated: DateTime<Local>,
+}
+
+/// A gateway's latest response to a network status request.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub struct NetworkStatus {
+    pub counter: u16,
+    /// The number of nodes in the mesh, reported three ways whose differences aren't known yet.
+    pub node_counts: [u16; 3],
+    pub unknown: u8,
+    /// When the response was received.
+    pub updated: DateTime<Local>,
+}
+
+impl NetworkStatus {
+    pub fn new(response: &NetworkStatusResponse, now: DateTime<Local>) -> Self {
+        Self {
+            counter: response.counter.get(),
+            node_counts: response.node_counts(),
+            unknown: response.unknown[0],
+            updated: now,
+        }
+    }
+
+    /// Whether this status says the same as another, regardless of when either was received.
+    pub fn same_as(&self, other: &Self) -> bool {
+        (self.counter, self.node_counts, self.unknown)
+            == (other.counter, other.node_counts, other.unknown)
+    }
+}
+
+/// Everything an observer knows about the observed system's gateways and nodes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SystemSnapshot {
+    pub timestamp: DateTime<Local>,
+    pub gateways: Vec<GatewaySnapshot>,
+    pub nodes: Vec<NodeSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GatewaySnapshot {
+    pub id: GatewayID,
+    pub address: Option<LongAddress>,
+    pub version: Option<String>,
+    pub network_status: Option<NetworkStatus>,
+    /// The source through which the gateway was observed, when observing more than one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeSnapshot {
+    pub gateway: GatewayID,
+    pub id: NodeID,
+    /// The node's hardware address, from its gateway's node table, or failing that from its
+    /// topology report.
+    pub address: Option<LongAddress>,
+    pub barcode: Option<Barcode>,
+    pub topology: Option<Topology>,
+    /// The node's firmware version, as it last answered a version request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<String>,
+    /// The node's latest response to each other string request, by the name of the request.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub responses: BTreeMap<String, String>,
+    /// The source through which the node was observed, when observing more than one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl std::fmt::Display for SystemSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for gateway in &self.gateways {
+            write!(f, "gateway {}", gateway.id)?;
+            if let Some(source) = &gateway.source {
+                write!(f, " on {}", source)?;
+            }
+            if let Some(address) = gateway.address {
+                write!(f, " {}", address)?;
+            }
+            if let Some(version) = &gateway.version {
+                write!(f, " {:?}", version)?;
+            }
+            if let Some(status) = &gateway.network_status {
+                write!(f, " node counts {:?}", status.node_counts)?;
+            }
+            writeln!(f)?;
+
+            for node in self
+                .nodes
+                .iter()
+                .filter(|node| (node.gateway, &node.source) == (gateway.id, &gateway.source))
+            {
+                write!(f, "  node {}", node.id)?;
+                if let Some(barcode) = node.barcode {
+                    write!(f, " {}", barcode)?;
+                }
+                if let Some(firmware) = &node.firmware {
+                    write!(f, " {:?}", firmware)?;
+                }
+                if let Some(topology) = &node.topology {
+                    match topology.next_hop {
+                        Some(NodeID::GATEWAY) => write!(f, " via gateway")?,
+                        Some(next_hop) => write!(f, " via node {}", next_hop)?,
+                        None => {}
+                    }
+                    write!(
+                        f,
+                        " rssi {} at {}",
+                        topology.rssi.0,
+                        topology.updated.format("%Y-%m-%d %H:%M:%S")
+                    )?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NodeSnapshot {
+    pub(super) fn new(
+        gateway: GatewayID,
+        id: NodeID,
+        table_address: Option<LongAddress>,
+        topology: Option<Topology>,
+        inventory: &NodeInventory,
+        source: Option<&str>,
+    ) -> Self {
+        let address = table_address.or(topology.map(|topology| topology.address));
+        let strings = address.and_then(|address| inventory.get(&address));
+        Self {
+            gateway,
+            id,
+            address,
+            barcode: address.map(Barcode::from),
+            topology,
+            firmware: strings.and_then(|strings| strings.firmware.clone()),
+            responses: strings
+                .map(|strings| strings.responses.clone())
+                .unwrap_or_default(),
+            source: source.map(Into::into),
+        }
+    }
+}
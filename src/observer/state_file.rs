@@ -0,0 +1,188 @@
+//! Keeping an observer's [`PersistentState`] in a file across restarts.
+//!
+//! Node tables in particular are exchanged rarely: a restarted observer which forgot them might
+//! wait hours for the next node table walk before it can tell which node sent a power report. A
+//! [`StateFile`] saves the state as JSON whenever a node table changes, and otherwise
+//! periodically, so that the next observer can start where this one left off.
+//!
+//! Saves write a temporary file alongside the state file and rename it into place, so a crash
+//! mid-save leaves the previous state intact.
+
+use super::{NodeTable, PersistentState};
+use crate::gateway::GatewayID;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often a [`StateFile`] saves the state even if no node table has changed.
+///
+/// `taptap health` reads when the last event was emitted from the state file, so this is kept well
+/// under its default `--max-age`.
+pub const DEFAULT_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A file holding an observer's [`PersistentState`].
+#[derive(Debug)]
+pub struct StateFile {
+    path: PathBuf,
+    interval: Duration,
+    last_saved: Option<Instant>,
+    saved_node_tables: BTreeMap<GatewayID, NodeTable>,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+            last_saved: None,
+            saved_node_tables: Default::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load the state from this file.
+    ///
+    /// A missing file is normal on first start, and is logged as such. An unreadable or unparsable
+    /// file is logged as a warning. Either way, the observer starts from an empty state rather than
+    /// not starting at all.
+    pub fn load(&mut self) -> PersistentState {
+        let state = match std::fs::read_to_string(&self.path) {
+            Ok(json) => match serde_json::from_str::<PersistentState>(&json) {
+                Ok(state) => state,
+                Err(e) => {
+                    log::warn!("ignoring unparsable state file {:?}: {}", self.path, e);
+                    PersistentState::default()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("state file {:?} does not exist yet", self.path);
+                PersistentState::default()
+            }
+            Err(e) => {
+                log::warn!("ignoring unreadable state file {:?}: {}", self.path, e);
+                PersistentState::default()
+            }
+        };
+        self.saved_node_tables = state.gateway_node_tables.clone();
+        state
+    }
+
+    /// Save the state if a node table has changed since the last save, or if the save interval
+    /// has elapsed as of `now`.
+    pub fn save_if_due(&mut self, state: &PersistentState, now: Instant) {
+        let due = self
+            .last_saved
+            .is_none_or(|last_saved| now.saturating_duration_since(last_saved) >= self.interval);
+        if due || state.gateway_node_tables != self.saved_node_tables {
+            self.save(state, now);
+        }
+    }
+
+    /// Save the state, logging any error.
+    pub fn save(&mut self, state: &PersistentState, now: Instant) {
+        // Whether or not this works, wait for the interval before trying again
+        self.last_saved = Some(now);
+        self.saved_node_tables = state.gateway_node_tables.clone();
+        if let Err(e) = self.write(state) {
+            log::error!("error saving state file {:?}: {}", self.path, e);
+        }
+    }
+
+    fn write(&self, state: &PersistentState) -> std::io::Result<()> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let mut file = std::fs::File::create(&temporary)?;
+        serde_json::to_writer(&mut file, state)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&temporary, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pv::{LongAddress, NodeID};
+
+    fn state_file(name: &str) -> StateFile {
+        let path = std::env::temp_dir().join(format!(
+            "taptap-state-file-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        StateFile::new(path, Duration::from_secs(60))
+    }
+
+    fn state() -> PersistentState {
+        let mut state = PersistentState::default();
+        state.set_node_table(
+            GatewayID::try_from(0x1201).unwrap(),
+            NodeTable(
+                [(
+                    NodeID::try_from(2).unwrap(),
+                    LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]),
+                )]
+                .into(),
+            ),
+            chrono::Local::now(),
+        );
+        state
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut file = state_file("round-trip");
+        assert_eq!(file.load(), PersistentState::default());
+
+        let state = state();
+        let start = Instant::now();
+        file.save_if_due(&state, start);
+        assert_eq!(StateFile::new(file.path(), file.interval).load(), state);
+
+        std::fs::remove_file(file.path()).unwrap();
+    }
+
+    #[test]
+    fn save_if_due() {
+        let mut file = state_file("save-if-due");
+        let start = Instant::now();
+        let empty = PersistentState::default();
+        file.save_if_due(&empty, start);
+        assert!(file.path().exists());
+
+        // Not due yet
+        std::fs::remove_file(file.path()).unwrap();
+        file.save_if_due(&empty, start + Duration::from_secs(59));
+        assert!(!file.path().exists());
+
+        // A new node table is saved straight away
+        let state = state();
+        file.save_if_due(&state, start + Duration::from_secs(59));
+        assert!(file.path().exists());
+
+        // Otherwise, the interval applies
+        std::fs::remove_file(file.path()).unwrap();
+        file.save_if_due(&state, start + Duration::from_secs(100));
+        assert!(!file.path().exists());
+        file.save_if_due(&state, start + Duration::from_secs(119));
+        assert!(file.path().exists());
+
+        std::fs::remove_file(file.path()).unwrap();
+    }
+
+    #[test]
+    fn unparsable() {
+        let mut file = state_file("unparsable");
+        std::fs::write(file.path(), "{ not json").unwrap();
+        assert_eq!(file.load(), PersistentState::default());
+        std::fs::remove_file(file.path()).unwrap();
+    }
+}
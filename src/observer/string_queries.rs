@@ -0,0 +1,151 @@
+use crate::gateway::link::GatewayID;
+use crate::memory::btree_map_bytes;
+use crate::pv::application::strings::{Query, Response};
+use crate::pv::NodeID;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// How long a node has to answer a string request.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most requests awaiting a response from any one node.
+const MAX_PENDING_PER_NODE: usize = 8;
+
+/// String requests awaiting a response, by the node they were sent to.
+///
+/// Nodes answer in the order they were asked, so each response is matched with the oldest request
+/// to its node which it could answer. Requests which go unanswered for longer than [`TIMEOUT`] are
+/// given up on.
+#[derive(Debug, Clone, Default)]
+pub struct PendingQueries(BTreeMap<(GatewayID, NodeID), VecDeque<(Query, SystemTime)>>);
+
+impl PendingQueries {
+    /// Note that `query` was sent to a node at `now`.
+    pub fn request(
+        &mut self,
+        gateway_id: GatewayID,
+        node_id: NodeID,
+        query: Query,
+        now: SystemTime,
+    ) {
+        let pending = self.0.entry((gateway_id, node_id)).or_default();
+        if pending.len() == MAX_PENDING_PER_NODE {
+            pending.pop_front();
+        }
+        pending.push_back((query, now));
+    }
+
+    /// Match a node's response received at `now` with the request it answers, returning the
+    /// request and when it was sent.
+    ///
+    /// Requests which timed out, or which were skipped over by this response, are discarded.
+    pub fn response(
+        &mut self,
+        gateway_id: GatewayID,
+        node_id: NodeID,
+        response: &Response,
+        now: SystemTime,
+    ) -> Option<(Query, SystemTime)> {
+        let key = (gateway_id, node_id);
+        let pending = self.0.get_mut(&key)?;
+        let mut answered = None;
+        while let Some((query, sent)) = pending.pop_front() {
+            if expired(sent, now) {
+                continue;
+            }
+            if query.answered_by(response) {
+                answered = Some((query, sent));
+                break;
+            }
+        }
+        if pending.is_empty() {
+            self.0.remove(&key);
+        }
+        answered
+    }
+
+    /// Give up on requests which have gone unanswered for too long, returning how many.
+    pub fn expire(&mut self, now: SystemTime) -> usize {
+        let mut expired_count = 0;
+        self.0.retain(|_, pending| {
+            let before = pending.len();
+            pending.retain(|(_, sent)| !expired(*sent, now));
+            expired_count += before - pending.len();
+            !pending.is_empty()
+        });
+        expired_count
+    }
+
+    /// The approximate number of bytes this table occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        btree_map_bytes::<(GatewayID, NodeID), VecDeque<(Query, SystemTime)>>(self.0.len())
+            + self
+                .0
+                .values()
+                .map(|pending| pending.capacity() * std::mem::size_of::<(Query, SystemTime)>())
+                .sum::<usize>()
+    }
+}
+
+fn expired(sent: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(sent).unwrap_or_default() > TIMEOUT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlation() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+        let at = |s: u64| t0 + Duration::from_secs(s);
+        let gateway_id = GatewayID::try_from(0x1201).unwrap();
+        let a = NodeID::try_from(2).unwrap();
+        let b = NodeID::try_from(3).unwrap();
+        let mut pending = PendingQueries::default();
+
+        // Requests to different nodes are outstanding at once
+        pending.request(gateway_id, a, Query::Version, at(0));
+        pending.request(gateway_id, b, Query::parse("Info"), at(1));
+        pending.request(gateway_id, a, Query::parse("Info"), at(2));
+        assert_eq!(
+            pending.response(gateway_id, b, &Response::parse("!Info 0000"), at(3)),
+            Some((Query::parse("Info"), at(1)))
+        );
+        assert_eq!(
+            pending.response(gateway_id, a, &Response::parse("Mnode Version K8"), at(3)),
+            Some((Query::Version, at(0)))
+        );
+        assert_eq!(
+            pending.response(gateway_id, a, &Response::parse("!Info 0000"), at(4)),
+            Some((Query::parse("Info"), at(2)))
+        );
+        assert_eq!(
+            pending.response(gateway_id, a, &Response::parse("!Info 0000"), at(5)),
+            None
+        );
+
+        // A response skips over a request it can't answer
+        pending.request(gateway_id, a, Query::Version, at(10));
+        pending.request(gateway_id, a, Query::parse("Info"), at(11));
+        assert_eq!(
+            pending.response(gateway_id, a, &Response::parse("!Info 0000"), at(12)),
+            Some((Query::parse("Info"), at(11)))
+        );
+        assert_eq!(pending.0.len(), 0);
+
+        // Requests time out
+        pending.request(gateway_id, a, Query::Version, at(20));
+        pending.request(gateway_id, b, Query::Version, at(40));
+        assert_eq!(pending.expire(at(55)), 1);
+        assert_eq!(
+            pending.response(gateway_id, a, &Response::parse("Mnode Version K8"), at(55)),
+            None
+        );
+        assert_eq!(
+            pending.response(gateway_id, b, &Response::parse("Mnode Version K8"), at(80)),
+            None
+        );
+        assert_eq!(pending.0.len(), 0);
+    }
+}
@@ -1,4 +1,75 @@
 use super::*;
+use std::sync::mpsc;
+
+/// A power report of unremarkable measurements, sampled at `slot_counter`.
+fn power_report(slot_counter: SlotCounter) -> pv::application::PowerReport {
+    use crate::pv::application::U12Pair;
+    pv::application::PowerReport {
+        voltage_in_and_voltage_out: U12Pair::try_from((500, 250)).unwrap(),
+        dc_dc_duty_cycle: 255,
+        current_and_temperature: U12Pair::try_from((200, 250)).unwrap(),
+        unknown: [0, 0, 0],
+        slot_counter,
+        rssi: pv::physical::RSSI(100),
+    }
+}
+
+/// A gateway's slot counter, `elapsed` after it started counting at the nominal 200 slots per
+/// second.
+fn slot_counter_at(elapsed: std::time::Duration) -> SlotCounter {
+    slot_counter_at_rate(elapsed, 200.0)
+}
+
+/// A gateway's slot counter, `elapsed` after it started counting at `slots_per_second`.
+fn slot_counter_at_rate(elapsed: std::time::Duration, slots_per_second: f64) -> SlotCounter {
+    let slot = (elapsed.as_secs_f64() * slots_per_second) as u32 % 48000;
+    SlotCounter::from((((slot / 12000) << 14) | (slot % 12000)) as u16)
+}
+
+/// Deliver every event an observer emits, diagnostics included, to the returned receiver.
+fn collect_events(observer: &mut Observer) -> mpsc::Receiver<Event> {
+    let (tx, events) = mpsc::channel();
+    observer.set_event_sink(tx);
+    observer.set_diagnostics_output(diagnostic::Output::Events);
+    events
+}
+
+/// Observe a gateway's slot counter, as when the controller polls it.
+fn observe_slot_counter(observer: &mut Observer, gateway_id: GatewayID, slot_counter: SlotCounter) {
+    use gateway::transport::Sink as _;
+    observer.gateway_slot_counter_captured(gateway_id);
+    observer.gateway_slot_counter_observed(gateway_id, slot_counter);
+}
+
+/// An observer starting at `now` from `state`, as after a restart, with a clock to drive it and
+/// its events.
+fn restarted_observer(
+    state: PersistentState,
+    now: SystemTime,
+) -> (Observer, clock::ManualClock, mpsc::Receiver<Event>) {
+    let clock = clock::ManualClock::new(now);
+    let mut observer = Observer::from_persistent_state(state);
+    observer.set_clock(clock.clone());
+    let events = collect_events(&mut observer);
+    (observer, clock, events)
+}
+
+/// Receive a power report, returning its event if it was placed in time straight away.
+fn receive_power_report(
+    observer: &mut Observer,
+    events: &mpsc::Receiver<Event>,
+    gateway_id: GatewayID,
+    node_id: NodeID,
+    slot_counter: SlotCounter,
+) -> Option<event::PowerReportEvent> {
+    use pv::application::Sink as _;
+    observer.power_report(gateway_id, node_id, &power_report(slot_counter));
+    match events.try_iter().last() {
+        Some(Event::PowerReport(event)) => Some(event),
+        None => None,
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
 
 #[test]
 fn enumeration_sequence() {
@@ -59,7 +130,2116 @@ fn enumeration_sequence() {
             .collect::<Vec<_>>(),
         vec![(
             &GatewayID::try_from(0x1201).unwrap(),
-            &String::from("Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r")
+            &String::from("Mgate Version G8.59 / Jul  6 2020 / 16:51:51 / GW-H158.4.3S0.12")
         ),]
     );
 }
+
+fn enumeration_sequence_with_existing_state(
+    enumeration_merge: config::EnumerationMerge,
+) -> PersistentState {
+    // A stale identity for a gateway which will be enumerated, and a gateway which won't be
+    let mut persistent_state = PersistentState::default();
+    for (id, address, version) in [
+        (0x1201, [0xFF; 8], "stale"),
+        (0x1203, [0x03; 8], "not enumerated"),
+    ] {
+        let id = GatewayID::try_from(id).unwrap();
+        persistent_state
+            .gateway_identities
+            .insert(id, LongAddress(address));
+        persistent_state.gateway_versions.insert(id, version.into());
+    }
+
+    let mut observer = Observer::from_persistent_state(persistent_state);
+    observer.set_config(Config {
+        enumeration_merge,
+        ..Default::default()
+    });
+    let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+        pv::application::Receiver::new(observer),
+    ));
+    rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+    rx.into_inner().into_inner().into_inner().persistent_state
+}
+
+#[test]
+fn enumeration_sequence_merge() {
+    let state = enumeration_sequence_with_existing_state(config::EnumerationMerge::Merge);
+    assert_eq!(
+        state.gateway_identities.into_iter().collect::<Vec<_>>(),
+        vec![
+            (
+                GatewayID::try_from(0x1201).unwrap(),
+                LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
+            ),
+            (
+                GatewayID::try_from(0x1202).unwrap(),
+                LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16])
+            ),
+            (GatewayID::try_from(0x1203).unwrap(), LongAddress([0x03; 8])),
+        ]
+    );
+    assert_eq!(
+        state.gateway_versions.into_iter().collect::<Vec<_>>(),
+        vec![
+            (
+                GatewayID::try_from(0x1201).unwrap(),
+                String::from("Mgate Version G8.59 / Jul  6 2020 / 16:51:51 / GW-H158.4.3S0.12")
+            ),
+            (
+                GatewayID::try_from(0x1203).unwrap(),
+                String::from("not enumerated")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn enumeration_sequence_replace() {
+    let state = enumeration_sequence_with_existing_state(config::EnumerationMerge::Replace);
+    assert_eq!(
+        state.gateway_identities.into_keys().collect::<Vec<_>>(),
+        vec![
+            GatewayID::try_from(0x1201).unwrap(),
+            GatewayID::try_from(0x1202).unwrap(),
+        ]
+    );
+    assert_eq!(
+        state.gateway_versions.into_keys().collect::<Vec<_>>(),
+        vec![GatewayID::try_from(0x1201).unwrap()]
+    );
+}
+
+#[test]
+fn gateway_id_assigned() {
+    use gateway::transport::Sink as _;
+
+    let mut observer = Observer::default();
+    observer.set_config(Config {
+        provenance: true,
+        ..Default::default()
+    });
+    let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+        pv::application::Receiver::new(observer),
+    ));
+    rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+    let mut observer = rx.into_inner().into_inner().into_inner();
+
+    // Move the enumerated gateway outside of an enumeration
+    let address = LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16]);
+    let old = GatewayID::try_from(0x1201).unwrap();
+    let new = GatewayID::try_from(0x1204).unwrap();
+    observer.gateway_id_assigned(old, new, address);
+
+    let state = &observer.persistent_state;
+    assert_eq!(state.gateway_identities.get(&old), None);
+    assert_eq!(state.gateway_identities.get(&new), Some(&address));
+    assert_eq!(state.gateway_versions.get(&old), None);
+    assert_eq!(
+        state.gateway_versions.get(&new).map(String::as_str),
+        Some("Mgate Version G8.59 / Jul  6 2020 / 16:51:51 / GW-H158.4.3S0.12")
+    );
+    assert_eq!(
+        observer.gateway(new).provenance.map(|p| p.source),
+        Some(provenance::Source::Assignment)
+    );
+
+    // A gateway which isn't the one we knew at the old ID leaves that ID alone
+    let other = LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x17]);
+    let newer = GatewayID::try_from(0x1205).unwrap();
+    observer.gateway_id_assigned(GatewayID::try_from(0x1202).unwrap(), newer, other);
+    let state = &observer.persistent_state;
+    assert_eq!(
+        state
+            .gateway_identities
+            .get(&GatewayID::try_from(0x1202).unwrap()),
+        Some(&address)
+    );
+    assert_eq!(state.gateway_identities.get(&newer), Some(&other));
+}
+
+#[derive(Debug, Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn power_report_without_slot_clock_diagnostic() {
+    use pv::application::Sink;
+    use std::time::Duration;
+
+    let buffer = SharedBuffer::default();
+    let clock = clock::ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200));
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_diagnostics_output(diagnostic::Output::Writer(Box::new(buffer.clone())));
+
+    observer.power_report(
+        GatewayID::try_from(0x1201).unwrap(),
+        NodeID::try_from(2).unwrap(),
+        &power_report(SlotCounter::ZERO),
+    );
+
+    // The report waits for the slot clock, and is discarded once it has waited too long
+    observer.tick();
+    assert!(buffer.0.lock().unwrap().is_empty());
+    clock.advance(early_reports::TIMEOUT + Duration::from_secs(1));
+    observer.tick();
+    assert_eq!(observer.counters().dropped_early_power_reports, 1);
+    assert_eq!(observer.counters().late_power_reports, 0);
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 1);
+
+    let diagnostic: DiagnosticEvent = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(diagnostic.severity, diagnostic::Severity::Error);
+    assert_eq!(
+        diagnostic.code,
+        diagnostic::Code::PowerReportWithoutSlotClock
+    );
+    assert_eq!(
+        diagnostic.gateway.map(|gateway| gateway.id),
+        Some(GatewayID::try_from(0x1201).unwrap())
+    );
+    assert_eq!(diagnostic.context["dropped"], 1);
+}
+
+#[test]
+fn early_power_reports() {
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(start);
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    let events = collect_events(&mut observer);
+
+    let report = |node: u16, s: u64| {
+        (
+            NodeID::try_from(node).unwrap(),
+            power_report(slot_counter_at(Duration::from_secs(s))),
+        )
+    };
+
+    // Reports sampled over the last minute arrive over the next, before the slot counter is seen
+    for (node, sampled, received) in [(3, 5, 10), (2, 20, 25), (4, 0, 40), (5, 50, 55)] {
+        clock.set(start + Duration::from_secs(received));
+        let (node_id, power_report) = report(node, sampled);
+        observer.power_report(gateway_id, node_id, &power_report);
+    }
+    assert!(events.try_iter().next().is_none());
+
+    // Once it is, they're emitted in the order they arrived, placed in time as usual
+    clock.set(start + Duration::from_secs(60));
+    observe_slot_counter(
+        &mut observer,
+        gateway_id,
+        slot_counter_at(Duration::from_secs(60)),
+    );
+    let reports: Vec<(u16, SystemTime)> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            Event::PowerReport(event) => Some((u16::from(event.node.id), event.timestamp.into())),
+            _ => None,
+        })
+        .collect();
+    let at = |s: u64| start + Duration::from_secs(s);
+    assert_eq!(reports, [(3, at(5)), (2, at(20)), (4, at(0)), (5, at(50))]);
+    assert_eq!(observer.counters().late_power_reports, 4);
+    assert_eq!(observer.counters().dropped_early_power_reports, 0);
+
+    // Later reports are emitted straight away
+    clock.set(start + Duration::from_secs(65));
+    let (node_id, power_report) = report(2, 62);
+    observer.power_report(gateway_id, node_id, &power_report);
+    assert!(matches!(
+        events.try_iter().collect::<Vec<_>>().as_slice(),
+        [Event::PowerReport(_)]
+    ));
+    assert_eq!(observer.counters().late_power_reports, 4);
+}
+
+#[test]
+fn early_power_report_overflow() {
+    use pv::application::Sink as _;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+
+    // A gateway whose slot counter is never seen keeps reporting
+    let power_report = power_report(SlotCounter::ZERO);
+    for _ in 0..300 {
+        observer.power_report(gateway_id, NodeID::try_from(2).unwrap(), &power_report);
+    }
+
+    // Overflowing the queue is reported once, and counted every time
+    let diagnostics: Vec<_> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            Event::Diagnostic(diagnostic) => Some(diagnostic.code),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(diagnostics, [diagnostic::Code::PowerReportWithoutSlotClock]);
+    assert_eq!(
+        observer.counters().dropped_early_power_reports,
+        300 - early_reports::MAX_QUEUED_PER_GATEWAY as u64
+    );
+}
+
+#[test]
+fn daily_summaries() {
+    use crate::pv::application::{PowerReport, U12Pair};
+    use chrono::NaiveDate;
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let node_id = NodeID::try_from(2).unwrap();
+    let long_address = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x56]);
+
+    // 2024-08-24T11:00:00Z
+    let clock = clock::ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200));
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    observer.set_clock(clock.clone());
+    observer.set_config(Config {
+        time_zone: config::TimeZone::Utc,
+        daily_summaries: true,
+        ..Default::default()
+    });
+    observer
+        .persistent_state
+        .gateway_node_tables
+        .insert(gateway_id, NodeTable([(node_id, long_address)].into()));
+
+    let report = |observer: &mut Observer, slot_counter: u16, voltage_in: u16| {
+        let slot_counter = SlotCounter::from(slot_counter);
+        observe_slot_counter(observer, gateway_id, slot_counter);
+        observer.power_report(
+            gateway_id,
+            node_id,
+            &PowerReport {
+                voltage_in_and_voltage_out: U12Pair::try_from((voltage_in, 250)).unwrap(),
+                ..power_report(slot_counter)
+            },
+        );
+    };
+    let summaries = || {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::DailySummary(summary) => Some(summary),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // A report in the morning, and another just before midnight
+    report(&mut observer, 0x0000, 600);
+    clock.advance(Duration::from_secs(12 * 3600 + 59 * 60));
+    report(&mut observer, 0x4000, 800);
+    assert_eq!(summaries(), vec![]);
+
+    // Midnight passes, and the next slot counter observation rolls the day over
+    clock.advance(Duration::from_secs(120));
+    observe_slot_counter(&mut observer, gateway_id, SlotCounter::from(0x8000));
+    let summary = summaries();
+    assert_eq!(summary.len(), 1);
+    let summary = &summary[0];
+    assert_eq!(summary.date, NaiveDate::from_ymd_opt(2024, 8, 24).unwrap());
+    assert!(!summary.partial);
+    assert_eq!(summary.node.address, Some(long_address));
+    assert_eq!(summary.reports, 2);
+    assert_eq!(summary.max_voltage_in, 40.0);
+    assert_eq!(summary.min_morning_voltage_in, Some(30.0));
+    assert_eq!(summary.max_power, 25.0);
+
+    // A report on the next day is summarized as partial at shutdown
+    report(&mut observer, 0xc000, 700);
+    observer.shutdown();
+    let summary = summaries();
+    assert_eq!(summary.len(), 1);
+    assert_eq!(
+        summary[0].date,
+        NaiveDate::from_ymd_opt(2024, 8, 25).unwrap()
+    );
+    assert!(summary[0].partial);
+    assert_eq!(summary[0].reports, 1);
+
+    // The partial day survives a round trip through persistent state
+    let state: PersistentState =
+        serde_json::from_str(&serde_json::to_string(observer.persistent_state()).unwrap()).unwrap();
+    assert_eq!(&state, observer.persistent_state());
+}
+
+#[test]
+fn energy() {
+    use crate::pv::application::U12Pair;
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let node_id = NodeID::try_from(2).unwrap();
+    let long_address = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x56]);
+    let clock = clock::ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200));
+    let new_observer = |state: PersistentState| {
+        let mut observer = Observer::from_persistent_state(state);
+        observer.set_clock(clock.clone());
+        observer.set_config(Config {
+            energy: true,
+            ..Default::default()
+        });
+        let events = collect_events(&mut observer);
+        (observer, events)
+    };
+
+    // Each report is 30 V at 1 A, a minute after the last
+    let report = |observer: &mut Observer, events: &mpsc::Receiver<Event>, slot_counter: u16| {
+        let slot_counter = SlotCounter::from(slot_counter);
+        observe_slot_counter(observer, gateway_id, slot_counter);
+        observer.power_report(
+            gateway_id,
+            node_id,
+            &pv::application::PowerReport {
+                voltage_in_and_voltage_out: U12Pair::try_from((600, 250)).unwrap(),
+                ..power_report(slot_counter)
+            },
+        );
+        clock.advance(Duration::from_secs(60));
+        match events.try_iter().last() {
+            Some(Event::PowerReport(event)) => {
+                assert_eq!(event.power_w, 30.0);
+                event.energy_wh_today
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+    };
+
+    let (mut observer, events) = new_observer(PersistentState::default());
+    observer
+        .persistent_state
+        .gateway_node_tables
+        .insert(gateway_id, NodeTable([(node_id, long_address)].into()));
+    assert_eq!(report(&mut observer, &events, 0x0000), Some(0.0));
+    assert_eq!(report(&mut observer, &events, 0x4000), Some(0.5));
+
+    // The accumulator survives a restart
+    let state = serde_json::to_string(observer.persistent_state()).unwrap();
+    let (mut observer, events) = new_observer(serde_json::from_str(&state).unwrap());
+    assert_eq!(report(&mut observer, &events, 0x8000), Some(1.0));
+
+    // Nothing is accumulated unless configured
+    observer.set_config(Config::default());
+    assert_eq!(report(&mut observer, &events, 0xc000), None);
+}
+
+#[test]
+fn node_gaps() {
+    use crate::pv::physical::RSSI;
+    use chrono::TimeDelta;
+
+    let t = DateTime::<Local>::from(SystemTime::UNIX_EPOCH) + TimeDelta::seconds(1724497200);
+    let report = |seconds: i64| event::PowerReportEvent {
+        gateway: event::Gateway {
+            id: GatewayID::try_from(0x1201).unwrap(),
+            address: None,
+            provenance: None,
+        },
+        node: event::Node {
+            id: NodeID::try_from(2).unwrap(),
+            address: Some(LongAddress([
+                0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x56,
+            ])),
+            provenance: None,
+            home_gateway: None,
+        },
+        timestamp: t + TimeDelta::seconds(seconds),
+        voltage_in: 30.0,
+        voltage_out: 30.0,
+        current: 1.0,
+        power_w: 30.0,
+        dc_dc_duty_cycle: 1.0,
+        temperature: 25.0,
+        rssi: RSSI(100),
+        node_unverified: false,
+        timestamp_uncertain: false,
+        energy_wh_today: None,
+        seconds_since_previous_report: None,
+        source: None,
+    };
+    let since_previous = |events: &[Event]| match events.last() {
+        Some(Event::PowerReport(event)) => event.seconds_since_previous_report,
+        event => panic!("unexpected event: {:?}", event),
+    };
+
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    observer.accept_power_report(report(0));
+    observer.accept_power_report(report(10));
+    assert_eq!(
+        since_previous(&events.try_iter().collect::<Vec<_>>()),
+        Some(10.0)
+    );
+
+    // The time of the last report survives a restart
+    let state = serde_json::to_string(observer.persistent_state()).unwrap();
+    let mut observer = Observer::from_persistent_state(serde_json::from_str(&state).unwrap());
+    let events = collect_events(&mut observer);
+    observer.accept_power_report(report(400));
+    let emitted: Vec<_> = events.try_iter().collect();
+    assert_eq!(since_previous(&emitted), Some(390.0));
+
+    let gaps: Vec<_> = (emitted.iter())
+        .filter_map(|event| match event {
+            Event::NodeGap(event) => Some(event),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(gaps.len(), 1);
+    assert_eq!(gaps[0].gap_seconds, 390.0);
+    assert_eq!(gaps[0].timestamp, t + TimeDelta::seconds(400));
+
+    // A longer threshold keeps quiet
+    observer.set_config(Config {
+        node_gaps: config::NodeGaps {
+            threshold_secs: 600,
+        },
+        ..Default::default()
+    });
+    observer.accept_power_report(report(800));
+    let emitted: Vec<_> = events.try_iter().collect();
+    assert_eq!(emitted.len(), 1);
+    assert_eq!(since_previous(&emitted), Some(400.0));
+}
+
+#[test]
+fn rate_limiting() {
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let clock = clock::ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200));
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    let events = collect_events(&mut observer);
+    observer.set_config(Config {
+        rate_limits: rate_limit::RateLimits {
+            global: None,
+            per_node: Some(rate_limit::RateLimit {
+                events_per_minute: 60,
+                burst: 5,
+            }),
+        },
+        ..Default::default()
+    });
+    observe_slot_counter(&mut observer, gateway_id, SlotCounter::ZERO);
+
+    // A flood of duplicate reports from node 2, and a single report from node 3
+    for node_id in [2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3] {
+        observer.power_report(
+            gateway_id,
+            NodeID::try_from(node_id).unwrap(),
+            &power_report(SlotCounter::ZERO),
+        );
+    }
+
+    let emitted = events.try_iter().collect::<Vec<_>>();
+    let power_reports: Vec<_> = emitted
+        .iter()
+        .filter_map(|event| match event {
+            Event::PowerReport(event) => Some(event.node.id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        power_reports,
+        [2, 2, 2, 2, 2, 3]
+            .map(|id| NodeID::try_from(id).unwrap())
+            .to_vec()
+    );
+
+    // Limiting engaged once, and the diagnostic itself was not limited
+    let diagnostics: Vec<_> = emitted
+        .iter()
+        .filter_map(|event| match event {
+            Event::Diagnostic(event) => Some(event.code),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        diagnostics,
+        vec![
+            diagnostic::Code::GatewayIdentityUnknown,
+            diagnostic::Code::EventsRateLimited
+        ]
+    );
+    assert_eq!(
+        observer.counters(),
+        &Counters {
+            events_dropped_by_node_limit: 5,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn gateway_identity_unknown() {
+    use gateway::transport::Sink as _;
+
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    let unknown = GatewayID::try_from(0x1201).unwrap();
+    let known = GatewayID::try_from(0x1202).unwrap();
+    observer.gateway_identity_observed(known, LongAddress([0; 8]));
+
+    for gateway_id in [unknown, known, unknown, known] {
+        observe_slot_counter(&mut observer, gateway_id, SlotCounter::ZERO);
+    }
+
+    // Reported once, for the unknown gateway only
+    let diagnostics: Vec<_> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            Event::Diagnostic(diagnostic) => Some(diagnostic),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].code,
+        diagnostic::Code::GatewayIdentityUnknown
+    );
+    assert_eq!(diagnostics[0].gateway.map(|g| g.id), Some(unknown));
+    assert_eq!(
+        diagnostics[0].context.get("last_enumeration"),
+        Some(&serde_json::Value::Null)
+    );
+
+    // Observing an enumeration records when it happened
+    let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+        pv::application::Receiver::new(observer),
+    ));
+    rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+    assert!(rx
+        .sink()
+        .sink()
+        .sink()
+        .persistent_state
+        .last_enumeration
+        .is_some());
+}
+
+#[test]
+fn message_time_zone() {
+    use std::time::Duration;
+
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let step = clock::ClockStep {
+        from: t + Duration::from_secs(30),
+        to: t,
+    };
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    observer.set_config(Config {
+        time_zone: config::TimeZone::Utc,
+        ..Default::default()
+    });
+
+    // Times in messages are written in the configured time zone, not the system's
+    observer.capture_clock_stepped(&step, false);
+    let Some(Event::Diagnostic(diagnostic)) = events.try_iter().last() else {
+        panic!("expected a diagnostic");
+    };
+    assert!(
+        diagnostic
+            .message
+            .contains("from 2024-08-24 11:00:30 +00:00 to 2024-08-24 11:00:00 +00:00"),
+        "{}",
+        diagnostic.message
+    );
+}
+
+#[test]
+fn node_table_progress() {
+    use pv::application::Sink as _;
+
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let entry = |node_id: u16| NodeTableResponseEntry {
+        long_address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x00, 0x00, node_id as u8]),
+        node_id: NodeAddress::from(NodeID::try_from(node_id).ok()),
+    };
+
+    let address = |node_id: u16| NodeAddress::from(NodeID::try_from(node_id).ok());
+
+    // Start a walk, skip ahead (restarting it), and finish
+    observer.node_table_page(gateway_id, NodeAddress::ZERO, &[entry(2), entry(3)]);
+    observer.node_table_page(gateway_id, address(7), &[entry(7)]);
+    observer.node_table_page(gateway_id, address(8), &[entry(8), entry(9)]);
+    observer.node_table_page(gateway_id, address(10), &[]);
+
+    let summary: Vec<_> = events
+        .try_iter()
+        .map(|event| match event {
+            Event::NodeTableProgress(event) => {
+                format!(
+                    "progress {} {}",
+                    event.entries_so_far, event.last_start_address
+                )
+            }
+            Event::NodeTable(event) => format!("table {}", event.nodes.len()),
+            Event::Diagnostic(event) => format!(
+                "{} {}",
+                event.code,
+                event.context.get("abandoned_entries").unwrap()
+            ),
+            event => panic!("unexpected event: {:?}", event),
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            "progress 2 0",
+            "node_table_walk_restarted 2",
+            "progress 1 7",
+            "progress 3 8",
+            "table 3",
+        ]
+    );
+    assert_eq!(
+        observer
+            .node(gateway_id, NodeID::try_from(8).unwrap())
+            .address,
+        Some(entry(8).long_address)
+    );
+}
+
+#[test]
+fn node_table_changed() {
+    use pv::application::Sink as _;
+
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let address = |serial: u8| LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x00, 0x00, serial]);
+    let mut walk = |entries: &[(u16, u8)]| {
+        let entries: Vec<NodeTableResponseEntry> = entries
+            .iter()
+            .map(|&(node_id, serial)| NodeTableResponseEntry {
+                long_address: address(serial),
+                node_id: NodeAddress::from(NodeID::try_from(node_id).ok()),
+            })
+            .collect();
+        let end = NodeAddress::from(entries.last().unwrap().node_id.0.get() + 1);
+        observer.node_table_page(gateway_id, NodeAddress::ZERO, &entries);
+        observer.node_table_page(gateway_id, end, &[]);
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::NodeTableChanged(event) => Some(event),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The first table, and the same table again, change nothing
+    assert_eq!(walk(&[(2, 0xA2), (3, 0xA3), (4, 0xA4)]), vec![]);
+    assert_eq!(walk(&[(2, 0xA2), (3, 0xA3), (4, 0xA4)]), vec![]);
+
+    // 0xA2 and 0xA3 swap IDs, 0xA4 leaves, and 0xA5 joins
+    let changed = walk(&[(2, 0xA3), (3, 0xA2), (5, 0xA5)]);
+    assert_eq!(changed.len(), 1);
+    let ids = |nodes: &[event::Node]| -> Vec<(u16, Option<LongAddress>)> {
+        nodes
+            .iter()
+            .map(|node| (node.id.into(), node.address))
+            .collect()
+    };
+    assert_eq!(ids(&changed[0].added), vec![(5, Some(address(0xA5)))]);
+    assert_eq!(ids(&changed[0].removed), vec![(4, Some(address(0xA4)))]);
+    let readdressed: Vec<(u16, u16, Option<LongAddress>)> = changed[0]
+        .readdressed
+        .iter()
+        .map(|r| (r.previous_id.into(), r.node.id.into(), r.node.address))
+        .collect();
+    assert_eq!(
+        readdressed,
+        vec![(3, 2, Some(address(0xA3))), (2, 3, Some(address(0xA2)))]
+    );
+}
+
+#[test]
+fn provenance() {
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(t);
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    observer.set_config(Config {
+        provenance: true,
+        ..Default::default()
+    });
+
+    // Learn gateway identities by enumerating twice
+    let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+        pv::application::Receiver::new(observer),
+    ));
+    rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+    clock.advance(Duration::from_secs(3600));
+    rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+    let mut observer = rx.into_inner().into_inner().into_inner();
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let expected = Provenance {
+        source: provenance::Source::Enumeration,
+        first_seen: t.into(),
+        last_confirmed: (t + Duration::from_secs(3600)).into(),
+    };
+    assert_eq!(observer.gateway(gateway_id).provenance, Some(expected));
+    assert_eq!(
+        observer
+            .persistent_state
+            .provenance
+            .gateway_versions
+            .get(&gateway_id),
+        Some(&expected)
+    );
+
+    // Learn a node table twice, with one node changing
+    let entry = |node_id: u16, suffix: u8| NodeTableResponseEntry {
+        long_address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x00, 0x00, suffix]),
+        node_id: NodeAddress::from(NodeID::try_from(node_id).ok()),
+    };
+    let end = NodeAddress::from(NodeID::try_from(4).ok());
+    observer.node_table_page(gateway_id, NodeAddress::ZERO, &[entry(2, 2), entry(3, 3)]);
+    observer.node_table_page(gateway_id, end, &[]);
+    clock.advance(Duration::from_secs(60));
+    observer.node_table_page(gateway_id, NodeAddress::ZERO, &[entry(2, 2), entry(3, 9)]);
+    observer.node_table_page(gateway_id, end, &[]);
+
+    let later = t + Duration::from_secs(3600 + 60);
+    let node = observer.node(gateway_id, NodeID::try_from(2).unwrap());
+    assert_eq!(
+        node.provenance,
+        Some(Provenance {
+            source: provenance::Source::NodeTable,
+            first_seen: (t + Duration::from_secs(3600)).into(),
+            last_confirmed: later.into(),
+        })
+    );
+    let node = observer.node(gateway_id, NodeID::try_from(3).unwrap());
+    assert_eq!(
+        node.provenance,
+        Some(Provenance::new(provenance::Source::NodeTable, later.into()))
+    );
+
+    // Provenance is omitted from events unless configured
+    observer.set_config(Default::default());
+    assert_eq!(observer.gateway(gateway_id).provenance, None);
+
+    // State from before provenance was recorded can still be loaded
+    let mut legacy = serde_json::to_value(observer.persistent_state()).unwrap();
+    legacy.as_object_mut().unwrap().remove("provenance");
+    let state: PersistentState = serde_json::from_value(legacy).unwrap();
+    assert_eq!(state.provenance, ProvenanceTable::default());
+    assert_eq!(
+        state.gateway_identities,
+        observer.persistent_state.gateway_identities
+    );
+
+    // Provenance survives a round trip through persistent state
+    let state: PersistentState =
+        serde_json::from_str(&serde_json::to_string(observer.persistent_state()).unwrap()).unwrap();
+    assert_eq!(&state, observer.persistent_state());
+}
+
+#[test]
+fn command_trouble() {
+    use gateway::transport::{CommandSequenceNumber, Sink as _};
+
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+    observer.command_timed_out(
+        gateway_id,
+        PacketType::STRING_REQUEST,
+        CommandSequenceNumber(2),
+    );
+
+    // A momentary backlog is fine, but a persistent one is reported once
+    for _ in 0..5 {
+        observer.gateway_tx_buffers_free_observed(gateway_id, 0);
+    }
+    observer.gateway_tx_buffers_free_observed(gateway_id, 1);
+    for _ in 0..(TX_BUFFERS_EXHAUSTED_THRESHOLD * 2) {
+        observer.gateway_tx_buffers_free_observed(gateway_id, 0);
+    }
+
+    let emitted = events.try_iter().collect::<Vec<_>>();
+    assert_eq!(emitted.len(), 2);
+    let Event::CommandTimeout(timeout) = &emitted[0] else {
+        panic!("unexpected event: {:?}", emitted[0]);
+    };
+    assert_eq!(timeout.gateway.id, gateway_id);
+    assert_eq!(timeout.packet_type, PacketType::STRING_REQUEST.0);
+    assert_eq!(timeout.sequence_number, CommandSequenceNumber(2));
+    let Event::Diagnostic(diagnostic) = &emitted[1] else {
+        panic!("unexpected event: {:?}", emitted[1]);
+    };
+    assert_eq!(diagnostic.code, diagnostic::Code::GatewayTxBuffersExhausted);
+    assert_eq!(observer.counters().command_timeouts, 1);
+}
+
+#[test]
+fn persisted_slot_clock_calibration() {
+    use gateway::transport::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let node_id = NodeID::try_from(2).unwrap();
+    let long_address = LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x01, 0x23, 0x45]);
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+
+    // This gateway's crystal runs 0.2% fast
+    let slot_counter_at = |t: Duration| slot_counter_at_rate(t, 200.4);
+
+    let new_observer = |state: PersistentState| {
+        let (mut observer, clock, events) = restarted_observer(state, start);
+        observer.gateway_identity_observed(gateway_id, long_address);
+        (observer, clock, events)
+    };
+    let observe = |observer: &mut Observer, clock: &clock::ManualClock, t: Duration| {
+        clock.set(start + t);
+        observe_slot_counter(observer, gateway_id, slot_counter_at(t));
+    };
+    let report = |observer: &mut Observer, events: &mpsc::Receiver<Event>, t: Duration| {
+        receive_power_report(observer, events, gateway_id, node_id, slot_counter_at(t))
+            .unwrap()
+            .timestamp
+            .into()
+    };
+    let error = |actual: SystemTime, expected: SystemTime| match actual.duration_since(expected) {
+        Ok(d) => d,
+        Err(e) => e.duration(),
+    };
+
+    // Watch the gateway for an hour
+    let (mut original, clock, original_events) = new_observer(PersistentState::default());
+    for s in 0..3600 {
+        observe(&mut original, &clock, Duration::from_secs(s));
+    }
+
+    // Restart, with and without the persisted state
+    let state = serde_json::to_string(original.persistent_state()).unwrap();
+    let (mut restarted, restarted_clock, restarted_events) =
+        new_observer(serde_json::from_str(&state).unwrap());
+    let (mut forgetful, forgetful_clock, forgetful_events) =
+        new_observer(PersistentState::default());
+
+    // A day later, each observer sees the slot counter once, and then a report from three minutes
+    // earlier
+    let now = Duration::from_secs(86400);
+    let then = now - Duration::from_secs(180);
+    observe(&mut original, &clock, now);
+    observe(&mut restarted, &restarted_clock, now);
+    observe(&mut forgetful, &forgetful_clock, now);
+
+    let original: SystemTime = report(&mut original, &original_events, then);
+    let restarted: SystemTime = report(&mut restarted, &restarted_events, then);
+    let forgetful: SystemTime = report(&mut forgetful, &forgetful_events, then);
+
+    // The calibrated observers agree with each other and with reality, while the nominal slot rate
+    // is 360ms off
+    assert!(error(original, start + then) < Duration::from_millis(20));
+    assert!(error(restarted, original) < Duration::from_millis(20));
+    assert!(error(forgetful, start + then) > Duration::from_millis(300));
+}
+
+#[test]
+fn restored_slot_clock() {
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let node_id = NodeID::try_from(2).unwrap();
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+
+    // Each report's timestamp, and whether it's uncertain
+    let report = |observer: &mut Observer, events: &mpsc::Receiver<Event>, slot_counter| {
+        receive_power_report(observer, events, gateway_id, node_id, slot_counter)
+            .map(|event| (event.timestamp.into(), event.timestamp_uncertain))
+    };
+
+    // Watch the gateway for a minute, and save the state
+    let (mut original, clock, _) = restarted_observer(PersistentState::default(), start);
+    for s in 0..=60 {
+        clock.set(start + Duration::from_secs(s));
+        observe_slot_counter(
+            &mut original,
+            gateway_id,
+            slot_counter_at(Duration::from_secs(s)),
+        );
+    }
+    let anchor = *original
+        .persistent_state()
+        .slot_clock_anchor(gateway_id)
+        .unwrap();
+    assert_eq!(
+        anchor,
+        slot_clock::Anchor {
+            slot_counter: slot_counter_at(Duration::from_secs(60)).into(),
+            time: (start + Duration::from_secs(60)).into(),
+        }
+    );
+    let state: PersistentState =
+        serde_json::from_str(&serde_json::to_string(original.persistent_state()).unwrap()).unwrap();
+
+    // Restarting five minutes later, a report arriving before the slot counter is placed in time,
+    // but marked uncertain until the restored clock is confirmed
+    let now = Duration::from_secs(360);
+    let then = now - Duration::from_secs(20);
+    let (mut restarted, _, events) = restarted_observer(state.clone(), start + now);
+    assert_eq!(
+        report(&mut restarted, &events, slot_counter_at(then)),
+        Some((start + then, true))
+    );
+
+    // The slot counter fits, so the restored clock carries on
+    observe_slot_counter(&mut restarted, gateway_id, slot_counter_at(now));
+    assert!(restarted.restored_slot_clocks.is_empty());
+    assert_eq!(
+        report(&mut restarted, &events, slot_counter_at(then)),
+        Some((start + then, false))
+    );
+
+    // Restarting after the controller restarted too, the first reports are placed using the old
+    // slot counter and marked uncertain, but the clock starts over when the new slot counter
+    // doesn't fit
+    let restart = Duration::from_secs(200);
+    let (mut restarted, _, events) = restarted_observer(state.clone(), start + now);
+    assert!(matches!(
+        report(&mut restarted, &events, slot_counter_at(then - restart)),
+        Some((_, true))
+    ));
+    observe_slot_counter(&mut restarted, gateway_id, slot_counter_at(now - restart));
+    assert!(events.try_iter().any(|event| matches!(
+        event,
+        Event::Diagnostic(DiagnosticEvent {
+            code: diagnostic::Code::RestoredSlotClockDiscarded,
+            ..
+        })
+    )));
+    assert_eq!(
+        restarted.slot_clock(gateway_id).unwrap().slot_counter,
+        slot_counter_at(now - restart)
+    );
+    assert_eq!(
+        report(&mut restarted, &events, slot_counter_at(then - restart)),
+        Some((start + then, false))
+    );
+
+    // Restarting after a long gap, reports wait for the slot counter as before
+    let now = Duration::from_secs(3600);
+    let then = now - Duration::from_secs(20);
+    let (mut restarted, _, events) = restarted_observer(state.clone(), start + now);
+    assert_eq!(report(&mut restarted, &events, slot_counter_at(then)), None);
+    observe_slot_counter(&mut restarted, gateway_id, slot_counter_at(now));
+    assert_eq!(restarted.counters().late_power_reports, 1);
+    assert_eq!(
+        report(&mut restarted, &events, slot_counter_at(then)),
+        Some((start + then, false))
+    );
+
+    // The window can be configured
+    let (mut restarted, _, events) = restarted_observer(state.clone(), start + now);
+    restarted.set_config(Config {
+        slot_clock_restore: config::SlotClockRestore { max_age_secs: 3600 },
+        ..Default::default()
+    });
+    assert_eq!(
+        report(&mut restarted, &events, slot_counter_at(then)),
+        Some((start + then, true))
+    );
+
+    // Or set to zero, which never restores a slot clock, even one saved this very moment
+    let saved = Duration::from_secs(60);
+    let (mut restarted, _, events) = restarted_observer(state, start + saved);
+    restarted.set_config(Config {
+        slot_clock_restore: config::SlotClockRestore { max_age_secs: 0 },
+        ..Default::default()
+    });
+    assert_eq!(
+        report(&mut restarted, &events, slot_counter_at(saved)),
+        None
+    );
+}
+
+#[test]
+fn last_event() {
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(t);
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_event_sink(Vec::new());
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+
+    // Diagnostics don't indicate that data is flowing
+    observer.diagnostic(DiagnosticEvent::new(
+        diagnostic::Severity::Info,
+        diagnostic::Code::NodeTableWalkRestarted,
+        "test",
+    ));
+    assert_eq!(observer.persistent_state().last_event(), None);
+
+    clock.advance(Duration::from_secs(10));
+    observer.node_table_page(gateway_id, NodeAddress::ZERO, &[]);
+    assert_eq!(
+        observer.persistent_state().last_event(),
+        Some((t + Duration::from_secs(10)).into())
+    );
+}
+
+#[test]
+fn persistent_state_keys() {
+    let state = enumeration_sequence_with_existing_state(config::EnumerationMerge::Merge);
+
+    // Gateway IDs are written as strings, so that formats requiring string keys can hold them
+    let json = serde_json::to_value(&state).unwrap();
+    let keys: Vec<&String> = json["gateway_identities"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .collect();
+    assert_eq!(keys, ["0x1201", "0x1202", "0x1203"]);
+    assert_eq!(
+        serde_json::from_value::<PersistentState>(json.clone()).unwrap(),
+        state
+    );
+
+    // State written with decimal keys can still be loaded
+    let mut legacy = json;
+    for field in ["gateway_identities", "gateway_versions"] {
+        let map = legacy[field].as_object_mut().unwrap();
+        *map = std::mem::take(map)
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    u16::from(key.parse::<GatewayID>().unwrap()).to_string(),
+                    value,
+                )
+            })
+            .collect();
+    }
+    assert!(legacy["gateway_identities"].get("4609").is_some());
+    assert_eq!(
+        serde_json::from_value::<PersistentState>(legacy).unwrap(),
+        state
+    );
+
+    // Configuration round trips too
+    let config = Config {
+        daily_summaries: true,
+        time_zone: config::TimeZone::Utc,
+        enumeration_merge: config::EnumerationMerge::Replace,
+        provenance: true,
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+}
+
+#[test]
+fn persistent_state_formats() {
+    use crate::gateway::GatewayCapabilities;
+    use std::time::Duration;
+
+    // State with slot clock anchors as well as enumerated gateways
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let (mut observer, clock, _events) = restarted_observer(
+        enumeration_sequence_with_existing_state(config::EnumerationMerge::Merge),
+        start,
+    );
+    for s in 0..600 {
+        let elapsed = Duration::from_secs(s);
+        clock.set(start + elapsed);
+        observe_slot_counter(&mut observer, gateway_id, slot_counter_at(elapsed));
+    }
+    let state = observer.persistent_state().clone();
+    assert!(!state.slot_clock_anchors.is_empty());
+
+    // Configuration away from its defaults
+    let config = Config {
+        time_zone: config::TimeZone::Utc,
+        daily_summaries: true,
+        energy: true,
+        enumeration_merge: config::EnumerationMerge::Replace,
+        provenance: true,
+        gateway_capabilities: [(
+            gateway_id,
+            GatewayCapabilities {
+                unreliable_slot_counters: true,
+            },
+        )]
+        .into(),
+        array_sleep: Some(config::ArraySleep::default()),
+        duplicate_addresses: config::DuplicateAddresses::HomeOnly,
+        validate_node_tables: true,
+        slot_clock_updates: true,
+        max_report_age_secs: Some(3600),
+        gateway_status: Some(config::GatewayStatus::default()),
+        slot_clock_restore: config::SlotClockRestore { max_age_secs: 0 },
+        alerts: alerts::Template::ALL.into_iter().map(Into::into).collect(),
+        ..Default::default()
+    };
+
+    // Both round trip through TOML and YAML, which are friendlier to edit by hand than JSON
+    let toml = toml::to_string(&state).unwrap();
+    assert_eq!(toml::from_str::<PersistentState>(&toml).unwrap(), state);
+    let toml = toml::to_string(&config).unwrap();
+    assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+
+    let yaml = serde_yaml::to_string(&state).unwrap();
+    assert_eq!(
+        serde_yaml::from_str::<PersistentState>(&yaml).unwrap(),
+        state
+    );
+    let yaml = serde_yaml::to_string(&config).unwrap();
+    assert_eq!(serde_yaml::from_str::<Config>(&yaml).unwrap(), config);
+}
+
+#[test]
+fn gateway_capabilities() {
+    use crate::gateway::GatewayCapabilities;
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(t);
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let (tx, events) = mpsc::channel();
+    observer.set_event_sink(tx);
+
+    // Capabilities follow the firmware version
+    assert_eq!(
+        observer.capabilities(gateway_id),
+        GatewayCapabilities::default()
+    );
+    let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+        pv::application::Receiver::new(observer),
+    ));
+    rx.extend_from_slice(crate::test_data::ENUMERATION_SEQUENCE);
+    let mut observer = rx.into_inner().into_inner().into_inner();
+    assert_eq!(
+        observer.capabilities(gateway_id),
+        GatewayCapabilities::from_version(&observer.persistent_state.gateway_versions[&gateway_id])
+    );
+
+    // Without a slot clock, a power report normally waits for one
+    let power_report = power_report(SlotCounter::from(0));
+    let node_id = NodeID::try_from(2).unwrap();
+    events.try_iter().for_each(drop);
+    observer.power_report(gateway_id, node_id, &power_report);
+    assert_eq!(events.try_iter().count(), 0);
+
+    // Configuring unreliable slot counters timestamps it on receipt instead
+    let capabilities = GatewayCapabilities {
+        unreliable_slot_counters: true,
+    };
+    observer.set_config(Config {
+        gateway_capabilities: [(gateway_id, capabilities)].into(),
+        ..Default::default()
+    });
+    assert_eq!(observer.capabilities(gateway_id), capabilities);
+    clock.advance(Duration::from_secs(60));
+    observer.power_report(gateway_id, node_id, &power_report);
+    let events: Vec<_> = events.try_iter().collect();
+    let [Event::PowerReport(event)] = events.as_slice() else {
+        panic!("expected a power report: {:?}", events);
+    };
+    assert_eq!(
+        event.timestamp,
+        DateTime::<Local>::from(t + Duration::from_secs(60))
+    );
+
+    // The override survives a round trip through configuration
+    let config: Config =
+        serde_json::from_str(&serde_json::to_string(&observer.config).unwrap()).unwrap();
+    assert_eq!(config, observer.config);
+}
+
+#[test]
+fn sustained_packet_loss() {
+    use pv::application::Sink as _;
+
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let node_id = NodeID::try_from(2).unwrap();
+    let reported = || {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::Diagnostic(diagnostic)
+                    if diagnostic.code == diagnostic::Code::SustainedPacketLoss =>
+                {
+                    Some(diagnostic)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Modest loss is tolerated
+    observer.packet_loss_estimated(gateway_id, node_id, 5.0);
+    observer.packet_loss_estimated(gateway_id, node_id, 20.0);
+    assert!(reported().is_empty());
+
+    // Heavy loss is reported once, even as it fluctuates
+    observer.packet_loss_estimated(gateway_id, node_id, 30.0);
+    observer.packet_loss_estimated(gateway_id, node_id, 20.0);
+    observer.packet_loss_estimated(gateway_id, node_id, 40.0);
+    let diagnostics = reported();
+    let [diagnostic] = diagnostics.as_slice() else {
+        panic!("expected one diagnostic: {:?}", diagnostics);
+    };
+    assert_eq!(diagnostic.node.map(|node| node.id), Some(node_id));
+    assert_eq!(
+        diagnostic.context.get("estimated_loss_pct"),
+        Some(&serde_json::Value::from(30.0))
+    );
+
+    // Recovering re-arms the diagnostic
+    observer.packet_loss_estimated(gateway_id, node_id, 10.0);
+    observer.packet_loss_estimated(gateway_id, node_id, 50.0);
+    assert_eq!(reported().len(), 1);
+}
+
+#[test]
+fn array_sleep() {
+    use crate::gateway::GatewayCapabilities;
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(t);
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let events = collect_events(&mut observer);
+    observer.set_config(Config {
+        array_sleep: Some(config::ArraySleep::default()),
+        gateway_capabilities: [(
+            gateway_id,
+            GatewayCapabilities {
+                unreliable_slot_counters: true,
+            },
+        )]
+        .into(),
+        ..Default::default()
+    });
+
+    let power_report = power_report(SlotCounter::from(0));
+
+    // Simulate a minute, in which the gateway is polled and the given nodes report
+    let minute = |observer: &mut Observer, nodes: std::ops::Range<u16>| {
+        clock.advance(Duration::from_secs(60));
+        observe_slot_counter(observer, gateway_id, SlotCounter::from(0));
+        for node in nodes {
+            let node_id = NodeID::try_from(node).unwrap();
+            observer.power_report(gateway_id, node_id, &power_report);
+        }
+    };
+    let array_states = || {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::ArrayAsleep(event) | Event::ArrayWake(event) => {
+                    Some((event.state, event.reporting_nodes, event.known_nodes))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Ten nodes report through the afternoon, until half of them are shaded
+    for _ in 0..30 {
+        minute(&mut observer, 2..12);
+    }
+    for _ in 0..30 {
+        minute(&mut observer, 7..12);
+    }
+    assert_eq!(array_states(), vec![]);
+
+    // The sun sets, and once the last reports age out, the array is asleep
+    for _ in 0..60 {
+        minute(&mut observer, 2..2);
+    }
+    assert_eq!(array_states(), vec![(event::ArrayState::Asleep, 0, 10)]);
+    assert!(observer.persistent_state().asleep_since().is_some());
+
+    // Overnight, nothing more happens
+    for _ in 0..600 {
+        minute(&mut observer, 2..2);
+    }
+    assert_eq!(array_states(), vec![]);
+
+    // At sunrise, nodes wake one at a time, and the array wakes with them
+    for end in 3..=12 {
+        minute(&mut observer, 2..end);
+    }
+    assert_eq!(array_states(), vec![(event::ArrayState::Awake, 3, 10)]);
+    assert_eq!(observer.persistent_state().asleep_since(), None);
+}
+
+#[test]
+fn node_table_validation() {
+    use crate::gateway::GatewayCapabilities;
+    use pv::application::Sink as _;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let mut observer = Observer::default();
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let events = collect_events(&mut observer);
+    let config = Config {
+        validate_node_tables: true,
+        gateway_capabilities: [(
+            gateway_id,
+            GatewayCapabilities {
+                unreliable_slot_counters: true,
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+    observer.set_config(config.clone());
+
+    let power_report = power_report(SlotCounter::from(0));
+    let entry = |node_id: u16| NodeTableResponseEntry {
+        long_address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x00, 0x00, node_id as u8]),
+        node_id: NodeAddress::from(NodeID::try_from(node_id).ok()),
+    };
+    let end = |node_id: u16| NodeAddress::from(NodeID::try_from(node_id + 1).ok());
+    let report = |observer: &mut Observer, node: u16| {
+        observer.power_report(gateway_id, NodeID::try_from(node).unwrap(), &power_report);
+    };
+    let unverified = |emitted: &[Event]| {
+        (emitted.iter())
+            .filter_map(|event| match event {
+                Event::PowerReport(event) => Some((event.node.id.into(), event.node_unverified)),
+                _ => None,
+            })
+            .collect::<Vec<(u16, bool)>>()
+    };
+    let stale = |emitted: &[Event]| {
+        (emitted.iter())
+            .filter_map(|event| match event {
+                Event::Diagnostic(diagnostic)
+                    if diagnostic.code == diagnostic::Code::NodeTableStale =>
+                {
+                    Some(diagnostic.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Without a node table, nothing can be verified
+    report(&mut observer, 7);
+    assert_eq!(
+        unverified(&events.try_iter().collect::<Vec<_>>()),
+        vec![(7, false)]
+    );
+
+    // Node 7 is missing from the table, but its reports are still emitted
+    observer.node_table_page(gateway_id, NodeAddress::ZERO, &[entry(2), entry(3)]);
+    observer.node_table_page(gateway_id, end(3), &[]);
+    report(&mut observer, 2);
+    report(&mut observer, 7);
+    let emitted = events.try_iter().collect::<Vec<_>>();
+    assert_eq!(unverified(&emitted), vec![(2, false), (7, true)]);
+    assert_eq!(
+        observer.counters().unverified_power_reports,
+        [(gateway_id, 1)].into()
+    );
+    assert!(stale(&emitted).is_empty());
+
+    // A steady stream of them means the table is stale
+    for _ in 0..20 {
+        report(&mut observer, 7);
+    }
+    assert_eq!(
+        observer.counters().unverified_power_reports,
+        [(gateway_id, 21)].into()
+    );
+    let diagnostics = stale(&events.try_iter().collect::<Vec<_>>());
+    let [diagnostic] = diagnostics.as_slice() else {
+        panic!("expected one diagnostic: {:?}", diagnostics);
+    };
+    assert_eq!(
+        diagnostic.context.get("unverified_reports"),
+        Some(&serde_json::Value::from(10))
+    );
+    assert_eq!(
+        diagnostic.context.get("reports"),
+        Some(&serde_json::Value::from(11))
+    );
+
+    // Walking the table again verifies the node
+    observer.node_table_page(gateway_id, NodeAddress::ZERO, &[entry(2), entry(7)]);
+    observer.node_table_page(gateway_id, end(7), &[]);
+    report(&mut observer, 7);
+    assert_eq!(
+        unverified(&events.try_iter().collect::<Vec<_>>()),
+        vec![(7, false)]
+    );
+
+    // Without validation, reports aren't checked
+    observer.set_config(Config {
+        validate_node_tables: false,
+        ..config
+    });
+    report(&mut observer, 3);
+    assert_eq!(
+        unverified(&events.try_iter().collect::<Vec<_>>()),
+        vec![(3, false)]
+    );
+    assert_eq!(
+        observer.counters().unverified_power_reports,
+        [(gateway_id, 21)].into()
+    );
+}
+
+#[test]
+fn invalid_frames() {
+    use crate::gateway::link::{Address, Frame, Sink, Type};
+    use crate::gateway::transport::InvalidFrameReason;
+
+    let buffer = SharedBuffer::default();
+    let mut rx =
+        gateway::transport::Receiver::new(pv::application::Receiver::new(Observer::default()));
+    rx.sink_mut()
+        .sink_mut()
+        .set_invalid_frame_log(invalid_frame::InvalidFrameLog::new(buffer.clone()));
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    for frame in [
+        Frame {
+            address: Address::From(gateway_id),
+            frame_type: Type::RECEIVE_REQUEST,
+            payload: vec![0x00, 0x01, 0x12, 0x34],
+        },
+        Frame {
+            address: Address::To(gateway_id),
+            frame_type: Type::RECEIVE_REQUEST,
+            payload: vec![0x00, 0x01],
+        },
+        Frame {
+            address: Address::To(gateway_id),
+            frame_type: Type::COMMAND_REQUEST,
+            payload: vec![0x00],
+        },
+    ] {
+        rx.frame(frame);
+    }
+
+    let observer = rx.sink().sink();
+    assert_eq!(
+        observer.counters().invalid_frames,
+        [
+            (InvalidFrameReason::WrongAddress, 1),
+            (InvalidFrameReason::WrongLength, 2)
+        ]
+        .into()
+    );
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["reason"], "wrong_address");
+    assert_eq!(lines[0]["address"], 0x9201);
+    assert_eq!(lines[0]["frame_type"], 0x0148);
+    assert_eq!(lines[0]["payload"], "00011234");
+    assert_eq!(lines[2]["reason"], "wrong_length");
+    assert_eq!(lines[2]["payload"], "00");
+    assert!(lines[2]["timestamp"].is_string());
+}
+
+#[test]
+fn snapshot() {
+    use pv::application::Sink as _;
+    use pv::network::NodeAddress;
+    use pv::ShortAddress;
+    use zerocopy::byteorder::big_endian::U16;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let address = |n: u8| LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x9A, 0x57, n]);
+    let mut state = PersistentState::default();
+    state.set_gateway_identity(
+        gateway_id,
+        LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16]),
+        provenance::Source::Enumeration,
+        Local::now(),
+    );
+    state.set_node_table(
+        gateway_id,
+        NodeTable([(NodeID::try_from(2).unwrap(), address(0xA2))].into()),
+        Local::now(),
+    );
+    let mut observer = Observer::from_persistent_state(state);
+
+    // Node 3 isn't in the node table, but its topology report says where it is
+    observer.topology_report(
+        gateway_id,
+        NodeID::try_from(3).unwrap(),
+        &TopologyReport {
+            short_address: ShortAddress(U16::new(0x0003)),
+            pv_node_id: NodeAddress(U16::new(3)),
+            next_hop: NodeAddress(U16::new(2)),
+            unknown_1: [0; 2],
+            long_address: address(0xA3),
+            rssi: pv::physical::RSSI(120),
+            unknown_2: [0; 5],
+        },
+    );
+
+    let snapshot = observer.snapshot();
+    assert_eq!(snapshot.gateways.len(), 1);
+    assert_eq!(snapshot.gateways[0].id, gateway_id);
+    assert!(snapshot.gateways[0].address.is_some());
+
+    let nodes: Vec<_> = snapshot
+        .nodes
+        .iter()
+        .map(|node| {
+            (
+                u16::from(node.id),
+                node.barcode.map(|barcode| barcode.to_string()),
+                node.topology
+                    .map(|topology| (topology.next_hop.map(u16::from), topology.rssi.0)),
+            )
+        })
+        .collect();
+    assert_eq!(
+        nodes,
+        vec![
+            (2, Some("4-9A57A2L".into()), None),
+            (
+                3,
+                Some(crate::barcode::Barcode::from(address(0xA3)).to_string()),
+                Some((Some(2), 120))
+            ),
+        ]
+    );
+
+    let text = snapshot.to_string();
+    assert!(text.contains("  node 0x0003"));
+    assert!(text.contains("via node 0x0002 rssi 120"));
+    serde_json::to_string(&snapshot).unwrap();
+}
+
+#[test]
+fn slot_clock_updates() {
+    use gateway::transport::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(start);
+    let observe = |observer: &mut Observer, s: u64| {
+        clock.set(start + Duration::from_secs(s));
+        observe_slot_counter(observer, gateway_id, SlotCounter::from(s as u16 * 200));
+    };
+
+    // Nothing is emitted unless configured
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    observer.set_clock(clock.clone());
+    observer.gateway_identity_observed(
+        gateway_id,
+        LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x01, 0x23, 0x45]),
+    );
+    for s in 0..12 {
+        observe(&mut observer, s);
+    }
+    assert_eq!(events.try_iter().count(), 0);
+
+    // Once configured, updates are emitted as the clock moves on to each new thousand slots
+    observer.set_config(Config {
+        slot_clock_updates: true,
+        ..Default::default()
+    });
+    for s in 12..24 {
+        observe(&mut observer, s);
+    }
+    let updates: Vec<_> = events
+        .try_iter()
+        .map(|event| match event {
+            Event::SlotClockUpdated(event) => {
+                assert_eq!(event.gateway.id, gateway_id);
+                assert_eq!(event.estimated_slot_duration_us, 5000.0);
+                assert!(!event.calibrated);
+                (u16::from(event.slot_counter), event.system_time)
+            }
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect();
+    assert_eq!(
+        updates,
+        [15, 20]
+            .into_iter()
+            .map(|s| (s * 200, (start + Duration::from_secs(s as u64)).into()))
+            .collect::<Vec<_>>()
+    );
+
+    // The latest observation is available on request
+    let parameters = observer.slot_clock(gateway_id).unwrap();
+    assert_eq!(parameters.slot_counter, SlotCounter::from(23 * 200));
+    assert_eq!(parameters.system_time, start + Duration::from_secs(23));
+    assert_eq!(observer.slot_clock(GatewayID::try_from(2).unwrap()), None);
+}
+
+#[test]
+fn network_status() {
+    use pv::application::{NetworkStatusResponse, Sink as _};
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    let status = |bytes: &[u8]| *NetworkStatusResponse::parse(bytes).unwrap();
+
+    // Only changes are emitted
+    observer.network_status(gateway_id, &status(b"\x01\x03\x18\x00\x87\x00\x87\x00\x87"));
+    observer.network_status(gateway_id, &status(b"\x01\x03\x18\x00\x87\x00\x87\x00\x87"));
+    observer.network_status(gateway_id, &status(b"\x01\x03\x18\x00\x87\x00\x86\x00\x87"));
+    let node_counts: Vec<_> = events
+        .try_iter()
+        .map(|event| match event {
+            Event::NetworkStatus(event) => {
+                assert_eq!(event.gateway.id, gateway_id);
+                assert_eq!(event.counter, 0x0318);
+                event.node_counts
+            }
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect();
+    assert_eq!(node_counts, vec![[135, 135, 135], [135, 134, 135]]);
+
+    // The latest status appears in the snapshot
+    let snapshot = observer.snapshot();
+    assert_eq!(
+        snapshot.gateways[0].network_status.map(|s| s.node_counts),
+        Some([135, 134, 135])
+    );
+    assert!(snapshot.to_string().contains("node counts [135, 134, 135]"));
+}
+
+#[test]
+fn radio_configuration() {
+    use gateway::transport::Sink as _;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let mut response = [0u8; 37];
+    response[1..4].copy_from_slice(b"\x15\x24\xF6");
+    let mut rx = pv::application::Receiver::new(Observer::default());
+    rx.command_executed(
+        gateway_id,
+        (PacketType::GATEWAY_RADIO_CONFIGURATION_REQUEST, b"\x00\x01"),
+        (PacketType::GATEWAY_RADIO_CONFIGURATION_RESPONSE, &response),
+    );
+    rx.command_executed(
+        gateway_id,
+        (PacketType::GATEWAY_RADIO_CONFIGURATION_REQUEST, b"\x00\x01"),
+        (
+            PacketType::GATEWAY_RADIO_CONFIGURATION_RESPONSE,
+            &response[..36],
+        ),
+    );
+    assert_eq!(rx.counters().radio_configuration_responses, 1);
+    assert_eq!(rx.counters().invalid_radio_configuration_responses, 1);
+
+    // The latest configuration is kept in the persistent state
+    let mut observer = rx.into_inner();
+    let expected = pv::application::RadioConfiguration::from(
+        pv::application::RadioConfigurationResponse::parse(&response).unwrap(),
+    );
+    let state = observer.persistent_state();
+    assert_eq!(
+        state.gateway_radio_configuration(gateway_id),
+        Some(&expected)
+    );
+    let json = serde_json::to_string(state).unwrap();
+    assert!(
+        json.contains(r#""gateway_radio_configurations":{"0x1201":{"channel":21,"pan_id":9462,"#)
+    );
+    let state: PersistentState = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        state.gateway_radio_configuration(gateway_id),
+        Some(&expected)
+    );
+
+    // ...which follows the gateway to a new ID
+    let address = LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0xBE, 0x16]);
+    let new = GatewayID::try_from(0x1204).unwrap();
+    observer.gateway_identity_observed(gateway_id, address);
+    observer.gateway_id_assigned(gateway_id, new, address);
+    let state = observer.persistent_state();
+    assert_eq!(state.gateway_radio_configuration(gateway_id), None);
+    assert_eq!(state.gateway_radio_configuration(new), Some(&expected));
+}
+
+#[test]
+fn node_configuration() {
+    use gateway::transport::Sink as _;
+    use pv::application::Sink as _;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let node_id = NodeID::try_from(0x39).unwrap();
+    let address = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x00, 0x00, 0x39]);
+    let request = b"\x00\x39\x03\x00\x31\x02\x0F\xA0\x09\x7D\
+        \x00\x09\x02\x00\x00\x00\x00\x00\x30\x02\x00\x00\x00\x00";
+    let response = |phase: u8| {
+        let reporting = [
+            &b"\x03\x00\x30\x00\x00\x00\x00\x00\x31\x0F\xA0\x09"[..],
+            &[phase],
+            &b"\x00\x09\x00\x00\x00\x00"[..],
+        ]
+        .concat();
+        [
+            &b"\x0F\x24\xF6\x15\x6C\x00\x24\xF6\x15\x6C\x00"[..],
+            &reporting,
+            &reporting,
+        ]
+        .concat()
+    };
+
+    let mut observer = Observer::default();
+    let emitted = collect_events(&mut observer);
+    let mut rx = pv::application::Receiver::new(observer);
+    let entries = [NodeTableResponseEntry {
+        long_address: address,
+        node_id: node_id.into(),
+    }];
+    rx.sink_mut()
+        .node_table_page(gateway_id, NodeAddress::ZERO, &entries);
+    rx.sink_mut()
+        .node_table_page(gateway_id, node_id.successor().into(), &[]);
+    let mut execute = |request: &[u8], response: &[u8]| {
+        rx.command_executed(
+            gateway_id,
+            (PacketType::PV_CONFIGURATION_REQUEST, request),
+            (PacketType::PV_CONFIGURATION_RESPONSE, response),
+        );
+        emitted
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::NodeConfiguration(event) => Some(event),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The first configuration is new, reading it back again changes nothing, and a new phase
+    // changes it
+    let events = execute(request, &response(0x7D));
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].node.id, node_id);
+    assert_eq!(events[0].node.address, Some(address));
+    assert_eq!(events[0].configuration.reporting[0].phase, 0x097D);
+    assert_eq!(events[0].previous, None);
+    assert_eq!(execute(request, &response(0x7D)), vec![]);
+    let events = execute(request, &response(0x7E));
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].configuration.reporting[0].phase, 0x097E);
+    assert_eq!(
+        events[0].previous.as_ref().unwrap().reporting[0].phase,
+        0x097D
+    );
+
+    // Malformed requests and responses are counted
+    assert_eq!(execute(&request[..23], &response(0x7E)), vec![]);
+    assert_eq!(execute(request, &response(0x7E)[..48]), vec![]);
+    assert_eq!(rx.counters().pv_configuration_responses, 3);
+    assert_eq!(rx.counters().invalid_pv_configuration_requests, 1);
+    assert_eq!(rx.counters().invalid_pv_configuration_responses, 1);
+
+    // The latest configuration is kept in the persistent state, so a restart doesn't repeat it
+    let state = rx.sink().persistent_state();
+    assert_eq!(
+        state.node_configuration(&address).unwrap().reporting[1].phase,
+        0x097E
+    );
+    let json = serde_json::to_string(state).unwrap();
+    assert!(json.contains(r#""node_configurations":{"04:C0:5B:40:00:00:00:39":{"#));
+    let mut observer = Observer::from_persistent_state(serde_json::from_str(&json).unwrap());
+    let emitted = collect_events(&mut observer);
+    let mut configure = |phase: u8| {
+        observer.pv_configuration(
+            gateway_id,
+            node_id,
+            pv::application::PvConfigurationResponse::parse(&response(phase)).unwrap(),
+        );
+        emitted
+            .try_iter()
+            .filter(|event| matches!(event, Event::NodeConfiguration(_)))
+            .count()
+    };
+    assert_eq!(configure(0x7E), 0);
+    assert_eq!(configure(0x7F), 1);
+}
+
+#[test]
+fn broadcast() {
+    use pv::application::{Broadcast, Sink as _};
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    observer.broadcast(gateway_id, Broadcast::parse(b"\x00\x00\x00\x01").unwrap());
+    observer.broadcast(gateway_id, Broadcast::parse(b"\x00\x01").unwrap());
+
+    let emitted = events.try_iter().collect::<Vec<_>>();
+    let [Event::Broadcast(shutdown), Event::Broadcast(empty)] = &emitted[..] else {
+        panic!("unexpected events {:?}", emitted);
+    };
+    assert_eq!(shutdown.gateway.id, gateway_id);
+    assert_eq!(shutdown.destination, 0);
+    assert_eq!(shutdown.data, "0001");
+    assert_eq!(shutdown.pv_off, Some(true));
+    assert_eq!(empty.destination, 1);
+    assert_eq!(empty.pv_off, None);
+    assert!(!Event::Broadcast(empty.clone()).to_json().contains("pv_off"));
+}
+
+#[test]
+fn gateway_status() {
+    use gateway::transport::{ReceiveResponse, Sink as _};
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let clock = clock::ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200));
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let events = collect_events(&mut observer);
+    observer.set_config(Config {
+        gateway_status: Some(config::GatewayStatus::default()),
+        ..Default::default()
+    });
+
+    let status = ReceiveResponse {
+        rx_buffers_used: Some(0),
+        tx_buffers_free: Some(14),
+        unknown_a: None,
+        unknown_b: None,
+        packet_number_high: None,
+        packet_number: 0,
+        slot_counter: SlotCounter::from(0),
+    };
+    let statuses = || {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::GatewayStatus(event) => {
+                    Some((event.gateway.id, event.status, event.receive_responses))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Nothing is reported before the gateway is heard from
+    observer.tick();
+    assert_eq!(statuses(), vec![]);
+
+    // The gateway responds every few seconds, and is reported online once
+    for _ in 0..10 {
+        observer.receive_status(gateway_id, &status);
+        observer.tick();
+        clock.advance(Duration::from_secs(5));
+    }
+    assert_eq!(
+        statuses(),
+        vec![(gateway_id, event::GatewayState::Online, 1)]
+    );
+
+    // It stops responding, and is reported stale, then offline, each repeated every interval
+    for _ in 0..400 {
+        clock.advance(Duration::from_secs(1));
+        observer.tick();
+    }
+    let mut states = statuses()
+        .into_iter()
+        .map(|(_, state, responses)| (state, responses))
+        .collect::<Vec<_>>();
+    assert_eq!(states.len(), 7);
+    states.dedup();
+    assert_eq!(
+        states,
+        vec![
+            (event::GatewayState::Online, 10),
+            (event::GatewayState::Stale, 10),
+            (event::GatewayState::Offline, 10),
+        ]
+    );
+
+    // Recovering is reported immediately
+    observer.receive_status(gateway_id, &status);
+    observer.tick();
+    assert_eq!(
+        statuses(),
+        vec![(gateway_id, event::GatewayState::Online, 11)]
+    );
+}
+
+#[test]
+fn node_diagnostic() {
+    use pv::application::strings::{Query, Response};
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let a = NodeID::try_from(2).unwrap();
+    let b = NodeID::try_from(3).unwrap();
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(t);
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let events = collect_events(&mut observer);
+
+    let diagnostics = || {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::NodeDiagnostic(event) => Some((
+                    event.node.id,
+                    event.query,
+                    event.response,
+                    event.timestamp - event.requested_at,
+                )),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Requests to two nodes are answered out of order
+    observer.string_request(gateway_id, a, LossyStr(b"Version"));
+    observer.string_request(gateway_id, b, LossyStr(b"Info"));
+    clock.advance(Duration::from_secs(2));
+    observer.string_response(gateway_id, b, LossyStr(b"!Info 0000 15"));
+    observer.string_response(gateway_id, a, LossyStr(b"Mnode Version K8.0120 (2D)\r"));
+    assert_eq!(
+        diagnostics(),
+        vec![
+            (
+                b,
+                Query::Read {
+                    parameter: "Info".into()
+                },
+                Response::Parameter {
+                    parameter: "Info".into(),
+                    value: "0000 15".into()
+                },
+                chrono::TimeDelta::seconds(2)
+            ),
+            (
+                a,
+                Query::Version,
+                Response::Version("Mnode Version K8.0120 (2D)".into()),
+                chrono::TimeDelta::seconds(2)
+            ),
+        ]
+    );
+
+    // A response after the request timed out, or without one, isn't correlated
+    observer.string_request(gateway_id, a, LossyStr(b"Version"));
+    clock.advance(Duration::from_secs(60));
+    observer.tick();
+    observer.string_response(gateway_id, a, LossyStr(b"Mnode Version K8.0120 (2D)"));
+    observer.string_response(gateway_id, b, LossyStr(b"!Info 0000 15"));
+    assert_eq!(diagnostics(), vec![]);
+}
+
+#[test]
+fn timestamp_uncertain() {
+    use pv::application::Sink as _;
+    use std::time::Duration;
+
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let node_id = NodeID::try_from(2).unwrap();
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let clock = clock::ManualClock::new(start);
+    let mut observer = Observer::default();
+    let events = collect_events(&mut observer);
+    observer.set_clock(clock.clone());
+    observer.set_config(Config {
+        max_report_age_secs: Some(30),
+        ..Default::default()
+    });
+
+    // The gateway is polled every second for ten minutes
+    for s in 0..=600 {
+        clock.set(start + Duration::from_secs(s));
+        observe_slot_counter(
+            &mut observer,
+            gateway_id,
+            slot_counter_at(Duration::from_secs(s)),
+        );
+    }
+
+    // Reports sampled 10 seconds, 5 minutes, and 9 minutes ago
+    let reports: Vec<_> = [590, 300, 60]
+        .into_iter()
+        .map(|s| {
+            observer.power_report(
+                gateway_id,
+                node_id,
+                &power_report(slot_counter_at(Duration::from_secs(s))),
+            );
+            match events.try_iter().last() {
+                Some(Event::PowerReport(event)) => {
+                    (SystemTime::from(event.timestamp), event.timestamp_uncertain)
+                }
+                event => panic!("unexpected event: {:?}", event),
+            }
+        })
+        .collect();
+
+    // The delayed reports are each placed a minute ago, a whole number of wraps too late, and are
+    // marked as such
+    let at = |s: u64| start + Duration::from_secs(s);
+    assert_eq!(
+        reports,
+        [(at(590), false), (at(540), true), (at(540), true)]
+    );
+}
@@ -0,0 +1,59 @@
+//! Outputs which deliver events, or the frames behind them, to other systems.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "observer")]
+pub mod csv;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(all(feature = "capture", feature = "parsers"))]
+pub mod pcap;
+#[cfg(feature = "web")]
+pub mod web;
+
+/// How events are written to a stream or file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Every event as a line of JSON.
+    #[default]
+    JsonLines,
+    /// Power reports as rows of CSV, after a header. Other events are dropped.
+    Csv,
+}
+
+impl OutputFormat {
+    pub const ALL: &'static [OutputFormat] = &[OutputFormat::JsonLines, OutputFormat::Csv];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::JsonLines => "json_lines",
+            OutputFormat::Csv => "csv",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown output format {0:?}")]
+pub struct UnknownOutputFormatError(String);
+
+impl std::str::FromStr for OutputFormat {
+    type Err = UnknownOutputFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| UnknownOutputFormatError(s.into()))
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,237 @@
+//! Writing power reports as CSV, for spreadsheets and data loggers.
+//!
+//! Each power report becomes a row under the columns in [`HEADER`]. A node whose barcode isn't
+//! known yet has an empty `barcode` cell. Numbers are written the way Rust formats them, with a `.`
+//! decimal separator and no digit grouping whatever the system's locale, so that files read the
+//! same everywhere. No cell ever needs quoting.
+
+use crate::barcode::Barcode;
+use crate::observer::event::{Event, PowerReportEvent};
+use crate::observer::EventSink;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// The first row of every CSV output.
+pub const HEADER: &str = "timestamp,gateway_id,node_id,barcode,voltage_in,voltage_out,current,\
+                          power,duty_cycle,temperature,rssi";
+
+/// Format a power report as a row, including its line ending.
+pub fn row(report: &PowerReportEvent) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{}\n",
+        report.timestamp.to_rfc3339(),
+        u16::from(report.gateway.id),
+        u16::from(report.node.id),
+        report
+            .node
+            .address
+            .map(|address| Barcode(address).to_string())
+            .unwrap_or_default(),
+        report.voltage_in,
+        report.voltage_out,
+        report.current,
+        report.power_w,
+        report.dc_dc_duty_cycle,
+        report.temperature,
+        report.rssi.0,
+    )
+}
+
+/// Writes each power report as a row, after a header, and drops every other event.
+#[derive(Debug)]
+pub struct CsvSink<W> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Write to `writer`, starting with a header before the first row.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write(&mut self, report: &PowerReportEvent) -> std::io::Result<()> {
+        if !self.header_written {
+            writeln!(self.writer, "{}", HEADER)?;
+            self.header_written = true;
+        }
+        self.writer.write_all(row(report).as_bytes())
+    }
+}
+
+impl<W: Write + std::fmt::Debug + Send> EventSink for CsvSink<W> {
+    fn event(&mut self, event: Event) {
+        if let Event::PowerReport(report) = event {
+            if let Err(e) = self.write(&report) {
+                log::error!("error writing event: {}", e);
+            }
+        }
+    }
+}
+
+/// Appends power reports to CSV files named for the day of each report, and drops every other
+/// event.
+///
+/// The file's name is a pattern which may contain `strftime`-style fields, like
+/// `out-%Y-%m-%d.csv`. Each report goes to the file named by formatting the pattern with the
+/// report's timestamp, so a new file starts each day. A pattern without fields names a single file.
+/// Files which already exist are appended to, and a header is written only to those which are
+/// empty.
+#[derive(Debug)]
+pub struct CsvFile {
+    pattern: String,
+    current: Option<(PathBuf, File)>,
+}
+
+impl CsvFile {
+    /// Append to the files named by `pattern`, opening each as the first report for it arrives.
+    pub fn new(pattern: impl Into<String>) -> std::io::Result<Self> {
+        let pattern = pattern.into();
+        if chrono::format::StrftimeItems::new(&pattern)
+            .any(|item| item == chrono::format::Item::Error)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid date fields in {:?}", pattern),
+            ));
+        }
+        Ok(Self {
+            pattern,
+            current: None,
+        })
+    }
+
+    /// Append a report to the file for its timestamp.
+    pub fn write(&mut self, report: &PowerReportEvent) -> std::io::Result<()> {
+        let path = PathBuf::from(report.timestamp.format(&self.pattern).to_string());
+        let file = match &mut self.current {
+            Some((current, file)) if *current == path => file,
+            current => {
+                let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+                if file.metadata()?.len() == 0 {
+                    writeln!(file, "{}", HEADER)?;
+                }
+                &mut current.insert((path, file)).1
+            }
+        };
+
+        // One write per row, so that rows are never torn by another writer
+        file.write_all(row(report).as_bytes())
+    }
+}
+
+impl EventSink for CsvFile {
+    fn event(&mut self, event: Event) {
+        if let Event::PowerReport(report) = event {
+            if let Err(e) = self.write(&report) {
+                log::error!("error writing event: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::link::GatewayID;
+    use crate::observer::event::{Gateway, Node};
+    use crate::pv::physical::RSSI;
+    use crate::pv::{LongAddress, NodeID};
+    use chrono::{DateTime, Local, TimeZone};
+
+    fn report(timestamp: DateTime<Local>, address: Option<LongAddress>) -> PowerReportEvent {
+        PowerReportEvent {
+            gateway: Gateway {
+                id: GatewayID::try_from(0x1201).unwrap(),
+                address: None,
+                provenance: None,
+            },
+            node: Node {
+                id: NodeID::try_from(116).unwrap(),
+                address,
+                provenance: None,
+                home_gateway: None,
+            },
+            timestamp,
+            voltage_in: 30.6,
+            voltage_out: 30.2,
+            current: 6.94,
+            power_w: 30.6 * 6.94,
+            dc_dc_duty_cycle: 1.0,
+            temperature: -2.5,
+            rssi: RSSI(132),
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        }
+    }
+
+    fn t(day: u32, hour: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 8, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn sink() {
+        let address = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0x9A, 0x57, 0xA7]);
+        let mut sink = CsvSink::new(Vec::new());
+        sink.event(Event::PowerReport(report(t(24, 9), Some(address))));
+        sink.event(Event::PowerReport(report(t(24, 10), None)));
+        let csv = String::from_utf8(sink.into_inner()).unwrap();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], HEADER);
+        assert_eq!(
+            lines[1],
+            format!(
+                "{},4609,116,{},30.6,30.2,6.94,{},1,-2.5,132",
+                t(24, 9).to_rfc3339(),
+                Barcode(address),
+                30.6 * 6.94
+            )
+        );
+
+        // A node without a barcode has an empty cell, and every row has every column
+        assert!(lines[2].contains(",4609,116,,30.6,"));
+        for line in lines {
+            assert_eq!(line.split(',').count(), 11);
+        }
+    }
+
+    #[test]
+    fn daily_files() {
+        let dir = std::env::temp_dir().join(format!("taptap-csv-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let pattern = dir.join("out-%Y-%m-%d.csv").to_string_lossy().into_owned();
+        let read = |day: &str| std::fs::read_to_string(dir.join(format!("out-{}.csv", day)));
+
+        let mut file = CsvFile::new(&pattern).unwrap();
+        file.event(Event::PowerReport(report(t(24, 9), None)));
+        file.event(Event::PowerReport(report(t(24, 10), None)));
+        file.event(Event::PowerReport(report(t(25, 9), None)));
+        assert_eq!(read("2024-08-24").unwrap().lines().count(), 3);
+        assert_eq!(read("2024-08-25").unwrap().lines().count(), 2);
+
+        // Reopening appends without repeating the header
+        let mut file = CsvFile::new(&pattern).unwrap();
+        file.event(Event::PowerReport(report(t(25, 10), None)));
+        let csv = read("2024-08-25").unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert_eq!(csv.matches("timestamp,").count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(CsvFile::new("out-%Q.csv").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Exposing the latest power reports and the receiver's counters to Prometheus.
+//!
+//! A [`Registry`] holds the latest power report from each node and the latest snapshot of every
+//! layer's [`Counters`]. A [`MetricsSink`] records power reports into it on their way to another
+//! sink, the owner of the receiver stack updates its counters, and a [`Server`] answers
+//! `GET /metrics` with the registry's contents in the Prometheus text exposition format.
+//!
+//! Each node's measurements are gauges labeled by `gateway`, `node`, and `barcode`, where the
+//! barcode is empty until the node's hardware address is known. Each counter is named
+//! `taptap_<layer>_<counter>_total`, like `taptap_link_frames_total`.
+
+use crate::barcode::Barcode;
+use crate::gateway::link::GatewayID;
+use crate::observer::event::{Event, PowerReportEvent};
+use crate::observer::EventSink;
+use crate::pv::NodeID;
+use crate::Counters;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The address on which a [`Server`] listens by default.
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:9815";
+
+/// How long the server waits for a client.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The per-node gauges: name, help, and how to read the value from a power report.
+type Gauge = (&'static str, &'static str, fn(&PowerReportEvent) -> f64);
+
+const GAUGES: [Gauge; 6] = [
+    (
+        "taptap_node_voltage_in_volts",
+        "Input voltage in the node's latest power report.",
+        |report| report.voltage_in,
+    ),
+    (
+        "taptap_node_voltage_out_volts",
+        "Output voltage in the node's latest power report.",
+        |report| report.voltage_out,
+    ),
+    (
+        "taptap_node_current_amperes",
+        "Current in the node's latest power report.",
+        |report| report.current,
+    ),
+    (
+        "taptap_node_temperature_celsius",
+        "Temperature in the node's latest power report.",
+        |report| report.temperature,
+    ),
+    (
+        "taptap_node_rssi",
+        "Received signal strength in the node's latest power report.",
+        |report| report.rssi.0 as f64,
+    ),
+    (
+        "taptap_node_dc_dc_duty_cycle",
+        "DC-DC converter duty cycle in the node's latest power report, from 0 to 1.",
+        |report| report.dc_dc_duty_cycle,
+    ),
+];
+
+/// The latest values of everything exposed as metrics, shared between whoever updates them and
+/// the [`Server`].
+#[derive(Debug, Clone, Default)]
+pub struct Registry(Arc<Mutex<Metrics>>);
+
+#[derive(Debug, Default)]
+struct Metrics {
+    counters: Counters,
+    reports: BTreeMap<(GatewayID, NodeID), PowerReportEvent>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the counters with a newer snapshot.
+    pub fn set_counters(&self, counters: Counters) {
+        self.0.lock().unwrap().counters = counters;
+    }
+
+    /// Record a node's latest power report.
+    pub fn power_report(&self, report: &PowerReportEvent) {
+        self.0
+            .lock()
+            .unwrap()
+            .reports
+            .insert((report.gateway.id, report.node.id), report.clone());
+    }
+
+    /// Write every metric in the Prometheus text exposition format, with report ages as of `now`.
+    pub fn render(&self, now: DateTime<Local>) -> String {
+        let metrics = self.0.lock().unwrap();
+        let mut out = String::new();
+
+        let labels: Vec<String> = metrics
+            .reports
+            .values()
+            .map(|report| {
+                format!(
+                    "gateway=\"{}\",node=\"{}\",barcode=\"{}\"",
+                    u16::from(report.gateway.id),
+                    u16::from(report.node.id),
+                    report
+                        .node
+                        .address
+                        .map(|address| Barcode::from(address).to_string())
+                        .unwrap_or_default()
+                )
+            })
+            .collect();
+
+        for (name, help, value) in GAUGES {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            for (report, labels) in metrics.reports.values().zip(&labels) {
+                writeln!(out, "{}{{{}}} {}", name, labels, value(report)).unwrap();
+            }
+        }
+
+        let name = "taptap_node_last_report_age_seconds";
+        writeln!(
+            out,
+            "# HELP {} Time since the node's latest power report.",
+            name
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        for (report, labels) in metrics.reports.values().zip(&labels) {
+            let age = (now - report.timestamp).num_milliseconds().max(0) as f64 / 1000.0;
+            writeln!(out, "{}{{{}}} {}", name, labels, age).unwrap();
+        }
+
+        // Counters are named after their serialized fields, so that new ones appear automatically
+        let counters = serde_json::to_value(metrics.counters).unwrap();
+        for (layer, counters) in counters.as_object().into_iter().flatten() {
+            for (counter, value) in counters.as_object().into_iter().flatten() {
+                let name = format!("taptap_{}_{}_total", layer, counter);
+                writeln!(out, "# TYPE {} counter", name).unwrap();
+                writeln!(out, "{} {}", name, value).unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+/// An event sink which records power reports into a [`Registry`], and passes every event on to
+/// another sink.
+#[derive(Debug)]
+pub struct MetricsSink<S> {
+    registry: Registry,
+    inner: S,
+}
+
+impl<S: EventSink> MetricsSink<S> {
+    pub fn new(registry: Registry, inner: S) -> Self {
+        Self { registry, inner }
+    }
+}
+
+impl<S: EventSink> EventSink for MetricsSink<S> {
+    fn event(&mut self, event: Event) {
+        if let Event::PowerReport(report) = &event {
+            self.registry.power_report(report);
+        }
+        self.inner.event(event);
+    }
+}
+
+/// A minimal HTTP server answering `GET /metrics` from a [`Registry`].
+///
+/// Requests are answered one at a time on a background thread, which runs until the process
+/// exits.
+#[derive(Debug)]
+pub struct Server {
+    local_addr: SocketAddr,
+}
+
+impl Server {
+    /// Serve the registry's metrics on `addr`.
+    pub fn bind(addr: impl ToSocketAddrs, registry: Registry) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        std::thread::Builder::new()
+            .name("taptap-metrics".into())
+            .spawn(move || serve(listener, registry))?;
+        Ok(Self { local_addr })
+    }
+
+    /// The address on which the server is listening.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+fn serve(listener: TcpListener, registry: Registry) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| respond(stream, &registry));
+        if let Err(e) = result {
+            log::warn!("error serving metrics: {}", e);
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, registry: &Registry) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    // Read the request line, then skip the headers, reading no further than a request could be
+    let mut reader = BufReader::new((&mut stream).take(16 << 10));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            registry.render(Local::now()),
+        ),
+        ("GET", _) => ("404 Not Found", "text/plain", "not found\n".into()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".into(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,247 @@
+//! Writing the frames seen on the gateway bus as [pcapng](crate::capture::pcapng) files, for study
+//! in Wireshark.
+//!
+//! Each frame is written as one enhanced packet block holding the frame as it appears on the bus,
+//! preamble and escaping included, stamped with the time it was captured. Packets use the
+//! `LINKTYPE_USER0` link type, which Wireshark can hand to a custom dissector, and which `taptap`
+//! reads back as bus bytes, so these files can be replayed too.
+//!
+//! Long captures can be split across several files: once a file reaches a given size, the next
+//! frame starts a new one, numbered after the first. `frames.pcapng` is followed by
+//! `frames.1.pcapng`, `frames.2.pcapng`, and so on. Existing files are never overwritten.
+
+use crate::capture::pcapng::Writer;
+use crate::gateway::link::Frame;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Writes frames to a pcapng file, starting a new file whenever the current one grows too large.
+#[derive(Debug)]
+pub struct FrameWriter {
+    base: PathBuf,
+    max_bytes: Option<u64>,
+    number: u32,
+    path: PathBuf,
+    writer: Writer<Counting<BufWriter<File>>>,
+    frames: u64,
+}
+
+impl FrameWriter {
+    /// Create a file at `path`, to be followed by another once it reaches `max_bytes`, if given.
+    pub fn create(path: impl Into<PathBuf>, max_bytes: Option<u64>) -> std::io::Result<Self> {
+        let base = path.into();
+        let path = base.clone();
+        Ok(Self {
+            writer: create(&path)?,
+            base,
+            max_bytes,
+            number: 0,
+            path,
+            frames: 0,
+        })
+    }
+
+    /// The file currently being written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a frame captured at `timestamp`, first moving to a new file if the current one is
+    /// full.
+    ///
+    /// Every file holds at least one frame, so a file can exceed the limit by up to one frame.
+    pub fn write(&mut self, frame: &Frame, timestamp: SystemTime) -> std::io::Result<()> {
+        let full = self
+            .max_bytes
+            .is_some_and(|max_bytes| self.writer.get_ref().bytes >= max_bytes);
+        if full && self.frames > 0 {
+            self.rotate()?;
+        }
+        self.writer.write(&frame.encode(), timestamp)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Flush buffered frames to the current file, so that it's valid as it stands.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush and close the current file.
+    pub fn finish(self) -> std::io::Result<()> {
+        self.writer.finish()?.inner.into_inner()?.sync_all()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let path = rotated_path(&self.base, self.number + 1);
+        let writer = std::mem::replace(&mut self.writer, create(&path)?);
+        writer.finish()?.inner.into_inner()?.sync_all()?;
+        self.number += 1;
+        self.path = path;
+        self.frames = 0;
+        Ok(())
+    }
+}
+
+/// The path of the file numbered `number` after the one at `path`, inserting the number before
+/// the extension: `frames.pcapng` is followed by `frames.1.pcapng`.
+pub fn rotated_path(path: &Path, number: u32) -> PathBuf {
+    if number == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+fn create(path: &Path) -> std::io::Result<Writer<Counting<BufWriter<File>>>> {
+    Writer::new(Counting {
+        inner: BufWriter::new(File::create_new(path)?),
+        bytes: 0,
+    })
+}
+
+/// Counts the bytes written through it, buffered or not.
+#[derive(Debug)]
+struct Counting<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::pcapng::{Reader, LINKTYPE_USER0};
+    use crate::gateway::link::{Address, GatewayID, Receiver, Type};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "taptap-pcap-{}-{}.pcapng",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn t() -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(1723500000123456)
+    }
+
+    fn frames() -> [Frame; 2] {
+        [
+            Frame {
+                address: Address::To(GatewayID::try_from(0x1201).unwrap()),
+                frame_type: Type::RECEIVE_REQUEST,
+                payload: vec![0x00, 0x01, 0x7e, 0x02],
+            },
+            Frame {
+                address: Address::From(GatewayID::try_from(0x1201).unwrap()),
+                frame_type: Type::RECEIVE_RESPONSE,
+                payload: vec![0x00, 0xe1, 0x00, 0x01],
+            },
+        ]
+    }
+
+    #[test]
+    fn two_frames() {
+        let path = path("two-frames");
+        let _ = std::fs::remove_file(&path);
+        let frames = frames();
+        let mut writer = FrameWriter::create(&path, None).unwrap();
+        writer.write(&frames[0], t()).unwrap();
+        writer
+            .write(&frames[1], t() + Duration::from_millis(20))
+            .unwrap();
+        writer.finish().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Walk the blocks: a section header, an interface description, and a packet per frame
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let (block_type, length) = (u32_at(offset), u32_at(offset + 4) as usize);
+            assert_eq!(length % 4, 0);
+            assert_eq!(u32_at(offset + length - 4) as usize, length);
+            blocks.push((block_type, &bytes[offset + 8..offset + length - 4]));
+            offset += length;
+        }
+        assert_eq!(offset, bytes.len());
+        let types: Vec<u32> = blocks.iter().map(|(block_type, _)| *block_type).collect();
+        assert_eq!(types, [0x0A0D0D0A, 0x00000001, 0x00000006, 0x00000006]);
+        assert_eq!(
+            u16::from_le_bytes(blocks[1].1[0..2].try_into().unwrap()),
+            LINKTYPE_USER0
+        );
+        for ((_, body), frame) in blocks[2..].iter().zip(&frames) {
+            let encoded = frame.encode();
+            let captured = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+            assert_eq!(captured, encoded.len());
+            assert_eq!(&body[20..20 + captured], encoded.as_slice());
+        }
+
+        // Reading the packets back as bus bytes yields the same frames at the same times
+        let records: Vec<_> = Reader::new(bytes.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records[0].1, t());
+        assert_eq!(records[1].1, t() + Duration::from_millis(20));
+        let mut rx = Receiver::new(Vec::<Frame>::new());
+        for (data, _) in &records {
+            rx.extend_from_slice(data);
+        }
+        assert_eq!(rx.sink().as_slice(), frames.as_slice());
+    }
+
+    #[test]
+    fn rotation() {
+        let paths: Vec<PathBuf> = (0..3).map(|n| rotated_path(&path("rotation"), n)).collect();
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        assert!(paths[1].to_string_lossy().ends_with(".1.pcapng"));
+
+        // Headers alone fill each file, so every frame gets one of its own
+        let frames = frames();
+        let mut writer = FrameWriter::create(&paths[0], Some(1)).unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            writer.write(frame, t()).unwrap();
+            assert_eq!(writer.path(), paths[i]);
+        }
+        writer.finish().unwrap();
+
+        for (path, frame) in paths.iter().zip(&frames) {
+            let file = File::open(path).unwrap();
+            let records: Vec<_> = Reader::new(file).collect::<Result<_, _>>().unwrap();
+            assert_eq!(records, vec![(frame.encode(), t())]);
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(!paths[2].exists());
+
+        // Existing files are left alone
+        let existing = path("existing");
+        std::fs::write(&existing, b"keep").unwrap();
+        assert!(FrameWriter::create(&existing, None).is_err());
+        assert_eq!(std::fs::read(&existing).unwrap(), b"keep");
+        std::fs::remove_file(&existing).unwrap();
+    }
+}
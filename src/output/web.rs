@@ -0,0 +1,280 @@
+//! Serving a live dashboard of the observed system over HTTP.
+//!
+//! A [`Hub`] keeps the most recent events and the latest [`SystemSnapshot`], and forwards each
+//! event to every subscriber. A [`WebSink`] publishes events into it on their way to another sink,
+//! the owner of the observer refreshes its snapshot, and a [`Server`] answers:
+//!
+//! * `GET /` with a single-page dashboard showing each node's latest power report
+//! * `GET /events` with a stream of [Server-Sent Events], each carrying an event as written by
+//!   [`Event::to_json()`], starting with the most recent events the hub has kept
+//! * `GET /state` with the latest snapshot as JSON
+//!
+//! [Server-Sent Events]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+use crate::observer::event::Event;
+use crate::observer::snapshot::SystemSnapshot;
+use crate::observer::EventSink;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The address on which a [`Server`] listens by default.
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:8090";
+
+/// How many recent events a [`Hub`] keeps for new subscribers by default.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// How often a [`Hub`]'s snapshot is refreshed.
+pub const STATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many events may wait for a subscriber before it is considered too slow and dropped.
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+/// How long the server waits for a client to send its request.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an event stream may go without events before a comment is sent to keep it open.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+const DASHBOARD: &str = include_str!("web/dashboard.html");
+
+/// Recent events, subscribers to new ones, and the latest snapshot, shared between whoever
+/// updates them and the [`Server`].
+#[derive(Debug, Clone)]
+pub struct Hub(Arc<Mutex<Shared>>);
+
+#[derive(Debug)]
+struct Shared {
+    capacity: usize,
+    recent: VecDeque<Arc<str>>,
+    subscribers: Vec<SyncSender<Arc<str>>>,
+    state: Option<String>,
+    state_refreshed_at: Option<Instant>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A hub keeping the last `capacity` events for new subscribers.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Shared {
+            capacity,
+            recent: VecDeque::with_capacity(capacity),
+            subscribers: Vec::new(),
+            state: None,
+            state_refreshed_at: None,
+        })))
+    }
+
+    /// Keep an event, and send it to every subscriber.
+    ///
+    /// Subscribers which have gone away, or which have fallen too far behind, are dropped.
+    pub fn publish(&self, event: &Event) {
+        let json: Arc<str> = event.to_json().into();
+        let mut shared = self.0.lock().unwrap();
+        if shared.capacity > 0 {
+            if shared.recent.len() == shared.capacity {
+                shared.recent.pop_front();
+            }
+            shared.recent.push_back(json.clone());
+        }
+        shared
+            .subscribers
+            .retain(|subscriber| match subscriber.try_send(json.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("dropping a slow event stream subscriber");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    /// Subscribe to events, returning the recent events followed by a receiver for new ones.
+    pub fn subscribe(&self) -> (Vec<Arc<str>>, Receiver<Arc<str>>) {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        let mut shared = self.0.lock().unwrap();
+        shared.subscribers.push(tx);
+        (shared.recent.iter().cloned().collect(), rx)
+    }
+
+    /// The number of events kept for new subscribers.
+    pub fn recent_len(&self) -> usize {
+        self.0.lock().unwrap().recent.len()
+    }
+
+    /// Replace the snapshot.
+    pub fn set_state(&self, snapshot: &SystemSnapshot) {
+        let json = serde_json::to_string(snapshot).unwrap();
+        let mut shared = self.0.lock().unwrap();
+        shared.state = Some(json);
+        shared.state_refreshed_at = Some(Instant::now());
+    }
+
+    /// Replace the snapshot with `snapshot()` if it is at least [`STATE_INTERVAL`] old as of
+    /// `now`, so that callers can refresh it as often as they like.
+    pub fn refresh_state(&self, now: Instant, snapshot: impl FnOnce() -> SystemSnapshot) {
+        let due = match self.0.lock().unwrap().state_refreshed_at {
+            Some(at) => now.saturating_duration_since(at) >= STATE_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.set_state(&snapshot());
+        }
+    }
+
+    /// The latest snapshot as JSON, if there is one.
+    pub fn state(&self) -> Option<String> {
+        self.0.lock().unwrap().state.clone()
+    }
+}
+
+/// An event sink which publishes every event to a [`Hub`], and passes it on to another sink.
+#[derive(Debug)]
+pub struct WebSink<S> {
+    hub: Hub,
+    inner: S,
+}
+
+impl<S: EventSink> WebSink<S> {
+    pub fn new(hub: Hub, inner: S) -> Self {
+        Self { hub, inner }
+    }
+}
+
+impl<S: EventSink> EventSink for WebSink<S> {
+    fn event(&mut self, event: Event) {
+        self.hub.publish(&event);
+        self.inner.event(event);
+    }
+}
+
+/// A minimal HTTP server answering requests for the dashboard from a [`Hub`].
+///
+/// Each connection is answered on a thread of its own, since event streams stay open for as long
+/// as the client wants them. The server runs until the process exits.
+#[derive(Debug)]
+pub struct Server {
+    local_addr: SocketAddr,
+}
+
+impl Server {
+    /// Serve the hub's dashboard on `addr`.
+    pub fn bind(addr: impl ToSocketAddrs, hub: Hub) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        std::thread::Builder::new()
+            .name("taptap-web".into())
+            .spawn(move || serve(listener, hub))?;
+        Ok(Self { local_addr })
+    }
+
+    /// The address on which the server is listening.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+fn serve(listener: TcpListener, hub: Hub) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("error accepting web connection: {}", e);
+                continue;
+            }
+        };
+        let hub = hub.clone();
+        let result = std::thread::Builder::new()
+            .name("taptap-web-client".into())
+            .spawn(move || {
+                if let Err(e) = respond(stream, &hub) {
+                    log::debug!("error serving web client: {}", e);
+                }
+            });
+        if let Err(e) = result {
+            log::warn!("error starting web client thread: {}", e);
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, hub: &Hub) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    // Read the request line, then skip the headers, reading no further than a request could be
+    let mut reader = BufReader::new((&mut stream).take(16 << 10));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/events") => return stream_events(stream, hub),
+        ("GET", "/") => ("200 OK", "text/html; charset=utf-8", DASHBOARD.into()),
+        ("GET", "/state") => match hub.state() {
+            Some(state) => ("200 OK", "application/json", state),
+            None => (
+                "503 Service Unavailable",
+                "text/plain",
+                "no snapshot yet\n".into(),
+            ),
+        },
+        ("GET", _) => ("404 Not Found", "text/plain", "not found\n".into()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".into(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Write the hub's recent events and then each new one until the client goes away.
+fn stream_events(mut stream: TcpStream, hub: &Hub) -> std::io::Result<()> {
+    let (recent, events) = hub.subscribe();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Connection: close\r\n\r\n"
+    )?;
+    for json in recent {
+        write!(stream, "data: {}\n\n", json)?;
+    }
+    stream.flush()?;
+
+    loop {
+        match events.recv_timeout(KEEPALIVE) {
+            Ok(json) => write!(stream, "data: {}\n\n", json)?,
+            Err(RecvTimeoutError::Timeout) => stream.write_all(b": keepalive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
@@ -0,0 +1,25 @@
+#[cfg(feature = "observer")]
+use crate::observer::Observer;
+use crate::{gateway, pv};
+
+/// A complete receiver stack, turning bytes from the gateway bus into calls on a sink which
+/// implements both [`gateway::transport::Sink`] and [`pv::application::Sink`].
+#[cfg(feature = "observer")]
+pub type Pipeline<S = Observer> =
+    gateway::link::Receiver<gateway::transport::Receiver<pv::application::Receiver<S>>>;
+
+/// A complete receiver stack, turning bytes from the gateway bus into calls on a sink which
+/// implements both [`gateway::transport::Sink`] and [`pv::application::Sink`].
+#[cfg(not(feature = "observer"))]
+pub type Pipeline<S> =
+    gateway::link::Receiver<gateway::transport::Receiver<pv::application::Receiver<S>>>;
+
+/// Assemble a [`Pipeline`] delivering to `sink`.
+pub fn pipeline<S>(sink: S) -> Pipeline<S>
+where
+    S: gateway::transport::Sink + pv::application::Sink,
+{
+    gateway::link::Receiver::new(gateway::transport::Receiver::new(
+        pv::application::Receiver::new(sink),
+    ))
+}
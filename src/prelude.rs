@@ -0,0 +1,39 @@
+//! The types and traits most programs embedding `taptap` need, without the module paths.
+//!
+//! ```
+//! # #[cfg(feature = "observer")] {
+//! use taptap::prelude::*;
+//!
+//! let mut pipeline = pipeline(Observer::default());
+//! pipeline.extend_from_slice(b"\x00\xff\x7e\x07\x12\x01");
+//! # }
+//! ```
+//!
+//! Each layer has a `Receiver` and a `Sink`, so those are re-exported here under names saying
+//! which layer they belong to. The observer and its events are only included with the `observer`
+//! feature.
+
+pub use crate::barcode::Barcode;
+pub use crate::gateway::link::{
+    Address, Frame, GatewayID, Receiver as LinkReceiver, Sink as LinkSink, Type,
+};
+pub use crate::gateway::physical::Connection;
+pub use crate::gateway::transport::{
+    CommandSequenceNumber, Receiver as TransportReceiver, Sink as TransportSink,
+};
+#[cfg(feature = "observer")]
+pub use crate::observer::event::{
+    ArrayState, ArrayStateEvent, CommandTimeoutEvent, DailySummaryEvent, DiagnosticEvent, Event,
+    EventKind, Gateway, Node, NodeTableEvent, NodeTableProgressEvent, PowerReportEvent,
+};
+#[cfg(feature = "observer")]
+pub use crate::observer::{EventSink, Observer, StdoutJsonSink};
+pub use crate::pv::application::{
+    Broadcast, NetworkStatusResponse, NodeTableResponseEntry, PowerReport, PvConfigurationResponse,
+    RadioConfigurationResponse, Receiver as ApplicationReceiver, Sink as ApplicationSink,
+    TopologyReport,
+};
+pub use crate::pv::network::{NodeAddress, ReceivedPacketHeader};
+pub use crate::pv::{LongAddress, NodeID, PacketType, SlotCounter};
+pub use crate::text::LossyStr;
+pub use crate::{pipeline, Counters, Pipeline};
@@ -7,8 +7,28 @@ mod packet_type;
 pub use packet_type::PacketType;
 
 mod node_table;
-pub use node_table::{NodeTableRequest, NodeTableResponse, NodeTableResponseEntry};
+pub use node_table::{
+    InvalidNodeTableResponse, NodeTableRequest, NodeTableResponse, NodeTableResponseEntry,
+};
+mod broadcast;
+pub use broadcast::Broadcast;
+mod network_status;
+pub use network_status::NetworkStatusResponse;
+mod radio_configuration;
+pub use radio_configuration::{
+    RadioConfiguration, RadioConfigurationRequest, RadioConfigurationResponse,
+};
+mod pv_configuration;
+pub use pv_configuration::{
+    PvConfiguration, PvConfigurationRequest, PvConfigurationResponse, PvRadioConfiguration,
+    PvRadioParameters, PvReportingConfiguration, PvReportingParameters,
+};
 mod power_report;
 pub use power_report::{PowerReport, U12Pair};
 mod topology_report;
 pub use topology_report::TopologyReport;
+mod packet_loss;
+pub use packet_loss::{DsnObservation, PacketLoss, MAX_PLAUSIBLE_DSN_GAP, PACKET_LOSS_WINDOW};
+mod decode;
+pub use decode::{decode, DecodeError, DecodedPacket};
+pub mod strings;
@@ -0,0 +1,58 @@
+use super::*;
+use crate::pv::network::NodeAddress;
+
+/// Data broadcast by the controller, usually to every node.
+///
+/// The controller asserts "PV off", shutting down every module, by broadcasting a two byte
+/// payload whose lowest bit is set. It also occasionally broadcasts empty payloads, both to the
+/// broadcast address and to the gateway.
+#[derive(Debug, Eq, PartialEq, FromBytes, Immutable, KnownLayout, Unaligned)]
+#[repr(C)]
+pub struct Broadcast {
+    /// The PV node ID to which the data is sent, which is `00 00` to broadcast to every node.
+    pub pv_node_id: NodeAddress,
+    pub data: [u8],
+}
+
+impl Broadcast {
+    /// Interpret bytes as a broadcast.
+    pub fn parse(bytes: &[u8]) -> Option<&Self> {
+        Self::ref_from_bytes(bytes).ok()
+    }
+
+    /// Whether this broadcast is to every node, rather than to a single node.
+    pub fn is_to_every_node(&self) -> bool {
+        self.pv_node_id.0.get() == 0
+    }
+
+    /// Whether the broadcast asserts "PV off", or `None` if it doesn't say.
+    pub fn pv_off(&self) -> Option<bool> {
+        match self.data {
+            [_, flags] => Some(flags & 0x01 != 0),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let broadcast = Broadcast::parse(b"\x00\x00\x00\x01").unwrap();
+        assert!(broadcast.is_to_every_node());
+        assert_eq!(&broadcast.data, b"\x00\x01");
+        assert_eq!(broadcast.pv_off(), Some(true));
+
+        let broadcast = Broadcast::parse(b"\x00\x00\x00\x00").unwrap();
+        assert_eq!(broadcast.pv_off(), Some(false));
+
+        // Empty broadcasts say nothing about PV off
+        let broadcast = Broadcast::parse(b"\x00\x01").unwrap();
+        assert!(!broadcast.is_to_every_node());
+        assert_eq!(broadcast.pv_off(), None);
+
+        assert_eq!(Broadcast::parse(b"\x00"), None);
+    }
+}
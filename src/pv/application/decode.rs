@@ -0,0 +1,457 @@
+use super::*;
+use crate::pv::network::NodeAddress;
+use crate::pv::NodeID;
+#[cfg(feature = "serde")]
+use crate::pv::{physical::RSSI, LongAddress, SlotCounter};
+use crate::text::LossyStr;
+
+/// A single application layer payload, decoded according to its packet type.
+///
+/// Decoded packets borrow from the payload. They serialize as a JSON object whose `type` names the
+/// variant, with measurements converted to physical units and unknown fields shown as hex. Strings
+/// which aren't valid UTF-8 serialize with their raw bytes in hex, as [`LossyStr`] does.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DecodedPacket<'a> {
+    StringRequest {
+        pv_node_id: NodeID,
+        request: LossyStr<'a>,
+    },
+    StringResponse(LossyStr<'a>),
+    TopologyReport(&'a TopologyReport),
+    NodeTableRequest(&'a NodeTableRequest),
+    NodeTableResponse(&'a [NodeTableResponseEntry]),
+    Broadcast(&'a Broadcast),
+    NetworkStatusResponse(&'a NetworkStatusResponse),
+    RadioConfigurationResponse(&'a RadioConfigurationResponse),
+    PvConfigurationRequest(&'a PvConfigurationRequest),
+    PvConfigurationResponse(&'a PvConfigurationResponse),
+    PowerReport(&'a PowerReport),
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DecodeError {
+    #[error("don't know how to decode {0:?}")]
+    UnsupportedPacketType(PacketType),
+    #[error("{packet_type:?} payload can't be {length} bytes long")]
+    InvalidLength {
+        packet_type: PacketType,
+        length: usize,
+    },
+    #[error("invalid node ID")]
+    InvalidNodeID,
+    #[error("string is mostly binary")]
+    BinaryString,
+    #[error("node table response claims {claimed} entries but contains {actual}")]
+    EntryCountMismatch { claimed: u16, actual: usize },
+}
+
+/// Decode a single application layer payload of the given packet type.
+///
+/// This is the same interpretation the [`Receiver`] applies, without requiring a receiver stack,
+/// for payloads taken from logs or bug reports.
+pub fn decode(packet_type: PacketType, data: &[u8]) -> Result<DecodedPacket<'_>, DecodeError> {
+    let invalid_length = DecodeError::InvalidLength {
+        packet_type,
+        length: data.len(),
+    };
+
+    match packet_type {
+        PacketType::STRING_REQUEST => {
+            let (node, request) = NodeAddress::ref_from_prefix(data).map_err(|_| invalid_length)?;
+            let pv_node_id = NodeID::try_from(*node).map_err(|_| DecodeError::InvalidNodeID)?;
+            let request = LossyStr(request);
+            if request.is_mostly_binary() {
+                return Err(DecodeError::BinaryString);
+            }
+            Ok(DecodedPacket::StringRequest {
+                pv_node_id,
+                request,
+            })
+        }
+        PacketType::STRING_RESPONSE => match LossyStr(data) {
+            response if response.is_mostly_binary() => Err(DecodeError::BinaryString),
+            response => Ok(DecodedPacket::StringResponse(response)),
+        },
+        PacketType::TOPOLOGY_REPORT => TopologyReport::ref_from_bytes(data)
+            .map(DecodedPacket::TopologyReport)
+            .map_err(|_| invalid_length),
+        PacketType::NODE_TABLE_REQUEST => NodeTableRequest::ref_from_bytes(data)
+            .map(DecodedPacket::NodeTableRequest)
+            .map_err(|_| invalid_length),
+        PacketType::NODE_TABLE_RESPONSE => match NodeTableResponse::parse(data) {
+            Ok(response) => Ok(DecodedPacket::NodeTableResponse(&response.entries)),
+            Err(InvalidNodeTableResponse::InvalidLength(_)) => Err(invalid_length),
+            Err(InvalidNodeTableResponse::EntryCountMismatch { claimed, actual }) => {
+                Err(DecodeError::EntryCountMismatch { claimed, actual })
+            }
+        },
+        PacketType::BROADCAST => Broadcast::parse(data)
+            .map(DecodedPacket::Broadcast)
+            .ok_or(invalid_length),
+        PacketType::NETWORK_STATUS_RESPONSE => NetworkStatusResponse::parse(data)
+            .map(DecodedPacket::NetworkStatusResponse)
+            .ok_or(invalid_length),
+        PacketType::GATEWAY_RADIO_CONFIGURATION_RESPONSE => RadioConfigurationResponse::parse(data)
+            .map(DecodedPacket::RadioConfigurationResponse)
+            .ok_or(invalid_length),
+        PacketType::PV_CONFIGURATION_REQUEST => {
+            let request = PvConfigurationRequest::parse(data).ok_or(invalid_length)?;
+            NodeID::try_from(request.node_address).map_err(|_| DecodeError::InvalidNodeID)?;
+            Ok(DecodedPacket::PvConfigurationRequest(request))
+        }
+        PacketType::PV_CONFIGURATION_RESPONSE => PvConfigurationResponse::parse(data)
+            .map(DecodedPacket::PvConfigurationResponse)
+            .ok_or(invalid_length),
+        PacketType::POWER_REPORT => PowerReport::ref_from_bytes(data)
+            .map(DecodedPacket::PowerReport)
+            .map_err(|_| invalid_length),
+        _ => Err(DecodeError::UnsupportedPacketType(packet_type)),
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for DecodedPacket<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let json = match *self {
+            DecodedPacket::StringRequest {
+                pv_node_id,
+                request,
+            } => Json::StringRequest {
+                node_id: pv_node_id,
+                request,
+            },
+            DecodedPacket::StringResponse(response) => Json::StringResponse { response },
+            DecodedPacket::TopologyReport(report) => Json::TopologyReport {
+                short_address: report.short_address.0.get(),
+                node_id: report.pv_node_id.0.get(),
+                next_hop: report.next_hop.0.get(),
+                long_address: report.long_address,
+                rssi: report.rssi,
+                unknown_1: hex(&report.unknown_1),
+                unknown_2: hex(&report.unknown_2),
+            },
+            DecodedPacket::NodeTableRequest(request) => Json::NodeTableRequest {
+                start_at: request.start_at.0.get(),
+            },
+            DecodedPacket::NodeTableResponse(entries) => Json::NodeTableResponse {
+                entries: entries
+                    .iter()
+                    .map(|entry| JsonNodeTableEntry {
+                        node_id: entry.node_id.0.get(),
+                        long_address: entry.long_address,
+                    })
+                    .collect(),
+            },
+            DecodedPacket::Broadcast(broadcast) => Json::Broadcast {
+                node_id: broadcast.pv_node_id.0.get(),
+                data: hex(&broadcast.data),
+                pv_off: broadcast.pv_off(),
+            },
+            DecodedPacket::NetworkStatusResponse(status) => Json::NetworkStatusResponse {
+                counter: status.counter.get(),
+                node_counts: status.node_counts(),
+                unknown: hex(&status.unknown),
+            },
+            DecodedPacket::RadioConfigurationResponse(config) => {
+                Json::RadioConfigurationResponse(RadioConfiguration::from(config))
+            }
+            DecodedPacket::PvConfigurationRequest(request) => Json::PvConfigurationRequest {
+                node_id: request.node_address.0.get(),
+                packet_type: request.packet_type.0,
+                period: request.period.get(),
+                phase: request.phase.get(),
+                unknown: [
+                    hex(&request.unknown_1),
+                    hex(&[request.unknown_2]),
+                    hex(&request.unknown_3),
+                ],
+            },
+            DecodedPacket::PvConfigurationResponse(config) => {
+                Json::PvConfigurationResponse(PvConfiguration::from(config))
+            }
+            DecodedPacket::PowerReport(report) => Json::PowerReport {
+                voltage_in: report.voltage_in(),
+                voltage_out: report.voltage_out(),
+                current: report.current(),
+                dc_dc_duty_cycle: report.dc_dc_duty_cycle as f64 / 255.0,
+                temperature: report.temperature(),
+                slot_counter: report.slot_counter,
+                rssi: report.rssi,
+                unknown: hex(&report.unknown),
+            },
+        };
+        serde::Serialize::serialize(&json, serializer)
+    }
+}
+
+/// The serialized form of a [`DecodedPacket`].
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Json<'a> {
+    StringRequest {
+        node_id: NodeID,
+        request: LossyStr<'a>,
+    },
+    StringResponse {
+        response: LossyStr<'a>,
+    },
+    TopologyReport {
+        short_address: u16,
+        node_id: u16,
+        next_hop: u16,
+        long_address: LongAddress,
+        rssi: RSSI,
+        unknown_1: String,
+        unknown_2: String,
+    },
+    NodeTableRequest {
+        start_at: u16,
+    },
+    NodeTableResponse {
+        entries: Vec<JsonNodeTableEntry>,
+    },
+    Broadcast {
+        node_id: u16,
+        data: String,
+        pv_off: Option<bool>,
+    },
+    NetworkStatusResponse {
+        counter: u16,
+        node_counts: [u16; 3],
+        unknown: String,
+    },
+    RadioConfigurationResponse(RadioConfiguration),
+    PvConfigurationRequest {
+        node_id: u16,
+        packet_type: u8,
+        period: u16,
+        phase: u16,
+        unknown: [String; 3],
+    },
+    PvConfigurationResponse(PvConfiguration),
+    PowerReport {
+        voltage_in: f64,
+        voltage_out: f64,
+        current: f64,
+        dc_dc_duty_cycle: f64,
+        temperature: f64,
+        slot_counter: SlotCounter,
+        rssi: RSSI,
+        unknown: String,
+    },
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct JsonNodeTableEntry {
+    node_id: u16,
+    long_address: LongAddress,
+}
+
+#[cfg(feature = "serde")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_report() {
+        let report = PowerReport {
+            voltage_in_and_voltage_out: U12Pair::try_from((500, 250)).unwrap(),
+            dc_dc_duty_cycle: 255,
+            current_and_temperature: U12Pair::try_from((200, 250)).unwrap(),
+            unknown: [1, 2, 3],
+            slot_counter: SlotCounter::ZERO,
+            rssi: RSSI(100),
+        };
+        let decoded = decode(PacketType::POWER_REPORT, report.as_bytes()).unwrap();
+        assert_eq!(decoded, DecodedPacket::PowerReport(&report));
+
+        let json = serde_json::to_value(decoded).unwrap();
+        assert_eq!(json["type"], "power_report");
+        assert_eq!(json["voltage_in"], 25.0);
+        assert_eq!(json["dc_dc_duty_cycle"], 1.0);
+        assert_eq!(json["unknown"], "010203");
+
+        assert_eq!(
+            decode(PacketType::POWER_REPORT, &report.as_bytes()[1..]),
+            Err(DecodeError::InvalidLength {
+                packet_type: PacketType::POWER_REPORT,
+                length: 12
+            })
+        );
+    }
+
+    #[test]
+    fn node_table() {
+        let decoded = decode(PacketType::NODE_TABLE_REQUEST, b"\x00\x02").unwrap();
+        let json = serde_json::to_value(decoded).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"type": "node_table_request", "start_at": 2})
+        );
+
+        let entries = [NodeTableResponseEntry {
+            long_address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x6F]),
+            node_id: 0x0002.into(),
+        }];
+        let encoded = NodeTableResponse::encode(&entries);
+        assert_eq!(
+            decode(PacketType::NODE_TABLE_RESPONSE, &encoded),
+            Ok(DecodedPacket::NodeTableResponse(&entries))
+        );
+        assert_eq!(
+            decode(PacketType::NODE_TABLE_RESPONSE, b"\x00\x01"),
+            Err(DecodeError::EntryCountMismatch {
+                claimed: 1,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            decode(PacketType::STRING_REQUEST, b"\x00\x02Version"),
+            Ok(DecodedPacket::StringRequest {
+                pv_node_id: NodeID::try_from(2).unwrap(),
+                request: LossyStr(b"Version")
+            })
+        );
+        assert_eq!(
+            decode(PacketType::STRING_REQUEST, b"\x00\x00Version"),
+            Err(DecodeError::InvalidNodeID)
+        );
+        assert_eq!(
+            decode(PacketType::STRING_RESPONSE, b"Mchip"),
+            Ok(DecodedPacket::StringResponse(LossyStr(b"Mchip")))
+        );
+        assert_eq!(
+            decode(PacketType::STRING_RESPONSE, b"\xFF"),
+            Err(DecodeError::BinaryString)
+        );
+
+        // Mixed content is decoded, keeping the raw bytes
+        let decoded = decode(PacketType::STRING_RESPONSE, b"Mchip\xFF").unwrap();
+        assert_eq!(
+            serde_json::to_value(decoded).unwrap(),
+            serde_json::json!({
+                "type": "string_response",
+                "response": {"text": "Mchip\u{FFFD}", "raw": "4d63686970ff"}
+            })
+        );
+    }
+
+    #[test]
+    fn network_status() {
+        let decoded = decode(
+            PacketType::NETWORK_STATUS_RESPONSE,
+            b"\x01\x03\x84\x00\x87\x00\x87\x00\x87",
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(decoded).unwrap(),
+            serde_json::json!({
+                "type": "network_status_response",
+                "counter": 900,
+                "node_counts": [135, 135, 135],
+                "unknown": "01",
+            })
+        );
+    }
+
+    #[test]
+    fn radio_configuration() {
+        let mut payload = [0u8; 37];
+        payload[1..4].copy_from_slice(b"\x15\x24\xF6");
+        let decoded = decode(PacketType::GATEWAY_RADIO_CONFIGURATION_RESPONSE, &payload).unwrap();
+        let json = serde_json::to_value(decoded).unwrap();
+        assert_eq!(json["type"], "radio_configuration_response");
+        assert_eq!(json["channel"], 21);
+        assert_eq!(json["pan_id"], 0x24F6);
+        assert_eq!(json["encryption_key"], "00".repeat(16));
+    }
+
+    #[test]
+    fn pv_configuration() {
+        let request = b"\x00\x39\x03\x00\x31\x02\x0F\xA0\x09\x7D\
+            \x00\x09\x02\x00\x00\x00\x00\x00\x30\x02\x00\x00\x00\x00";
+        let decoded = decode(PacketType::PV_CONFIGURATION_REQUEST, request).unwrap();
+        assert_eq!(
+            serde_json::to_value(decoded).unwrap(),
+            serde_json::json!({
+                "type": "pv_configuration_request",
+                "node_id": 0x39,
+                "packet_type": 0x31,
+                "period": 4000,
+                "phase": 0x097D,
+                "unknown": ["0300", "02", "0009020000000000300200000000"],
+            })
+        );
+
+        let mut response = [0u8; 49];
+        response[1..4].copy_from_slice(b"\x24\xF6\x15");
+        let decoded = decode(PacketType::PV_CONFIGURATION_RESPONSE, &response).unwrap();
+        let json = serde_json::to_value(decoded).unwrap();
+        assert_eq!(json["type"], "pv_configuration_response");
+        assert_eq!(json["radio"][0]["pan_id"], 0x24F6);
+        assert_eq!(json["radio"][0]["channel"], 21);
+        assert_eq!(
+            decode(PacketType::PV_CONFIGURATION_RESPONSE, &response[..48]),
+            Err(DecodeError::InvalidLength {
+                packet_type: PacketType::PV_CONFIGURATION_RESPONSE,
+                length: 48,
+            })
+        );
+    }
+
+    #[test]
+    fn broadcast() {
+        let decoded = decode(PacketType::BROADCAST, b"\x00\x00\x00\x01").unwrap();
+        assert_eq!(
+            serde_json::to_value(decoded).unwrap(),
+            serde_json::json!({
+                "type": "broadcast",
+                "node_id": 0,
+                "data": "0001",
+                "pv_off": true,
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        assert_eq!(
+            decode(PacketType::BROADCAST_ACK, b""),
+            Err(DecodeError::UnsupportedPacketType(
+                PacketType::BROADCAST_ACK
+            ))
+        );
+    }
+
+    #[test]
+    fn arbitrary_payloads() {
+        // Decoding arbitrary bytes as any packet type returns an error rather than panicking, and
+        // anything decoded can be serialized
+        let mut state = 12345u64;
+        let mut random = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 33) as u8
+        };
+        for _ in 0..10_000 {
+            let packet_type = PacketType(random() % 0x40);
+            let length = random() as usize % 64;
+            let data: Vec<u8> = (0..length).map(|_| random()).collect();
+            if let Ok(decoded) = decode(packet_type, &data) {
+                serde_json::to_string(&decoded).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use super::*;
+use crate::pv::network::NodeAddress;
+use zerocopy::big_endian;
+
+/// A gateway's response to a network status request or a long network status request.
+///
+/// The response describes the number of nodes in the mesh three times over. These counts likely
+/// diverge when the gateway can't hear every node directly, but which is which isn't known yet.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
+)]
+#[repr(C)]
+pub struct NetworkStatusResponse {
+    pub unknown: [u8; 1],
+    /// A value which a long network status request appears to set.
+    pub counter: big_endian::U16,
+    pub node_counts: [big_endian::U16; 3],
+}
+
+impl NetworkStatusResponse {
+    /// Interpret bytes as a network status response.
+    ///
+    /// Some responses are prefixed with the PV node ID of the gateway, which is skipped.
+    pub fn parse(bytes: &[u8]) -> Option<&Self> {
+        if let Ok(response) = Self::ref_from_bytes(bytes) {
+            return Some(response);
+        }
+        let (_, response) = NodeAddress::ref_from_prefix(bytes).ok()?;
+        Self::ref_from_bytes(response).ok()
+    }
+
+    /// The node counts, in the order they appear.
+    pub fn node_counts(&self) -> [u16; 3] {
+        self.node_counts.map(|count| count.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let response =
+            NetworkStatusResponse::parse(b"\x01\x03\x84\x00\x87\x00\x87\x00\x87").unwrap();
+        assert_eq!(response.unknown, [0x01]);
+        assert_eq!(response.counter.get(), 0x0384);
+        assert_eq!(response.node_counts(), [135, 135, 135]);
+
+        // With the gateway's node ID
+        let response =
+            NetworkStatusResponse::parse(b"\x00\x01\x01\x03\x18\x00\x87\x00\x86\x00\x85").unwrap();
+        assert_eq!(response.counter.get(), 0x0318);
+        assert_eq!(response.node_counts(), [135, 134, 133]);
+
+        for bytes in [
+            &b""[..],
+            b"\x01\x03\x84\x00\x87",
+            b"\x00\x01\x01\x03\x84\x00\x87\x00",
+        ] {
+            assert_eq!(NetworkStatusResponse::parse(bytes), None);
+        }
+    }
+}
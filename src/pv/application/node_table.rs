@@ -18,6 +18,42 @@ pub struct NodeTableResponse {
     pub entries: [NodeTableResponseEntry],
 }
 
+/// Why bytes can't be interpreted as a [`NodeTableResponse`].
+#[derive(thiserror::Error, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InvalidNodeTableResponse {
+    #[error("node table response can't be {0} bytes long")]
+    InvalidLength(usize),
+    #[error("node table response claims {claimed} entries but contains {actual}")]
+    EntryCountMismatch { claimed: u16, actual: usize },
+}
+
+impl NodeTableResponse {
+    /// Interpret bytes as a node table response, verifying that `entries_count` matches the
+    /// number of entries actually present.
+    ///
+    /// Prefer this to [`ref_from_bytes()`](FromBytes::ref_from_bytes), which accepts any
+    /// `entries_count`.
+    pub fn parse(bytes: &[u8]) -> Result<&Self, InvalidNodeTableResponse> {
+        let response = Self::ref_from_bytes(bytes)
+            .map_err(|_| InvalidNodeTableResponse::InvalidLength(bytes.len()))?;
+        if response.entries.len() != response.entries_count.get() as usize {
+            return Err(InvalidNodeTableResponse::EntryCountMismatch {
+                claimed: response.entries_count.get(),
+                actual: response.entries.len(),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Encode a node table response containing `entries`.
+    pub fn encode(entries: &[NodeTableResponseEntry]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(2 + entries.as_bytes().len());
+        output.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        output.extend_from_slice(entries.as_bytes());
+        output
+    }
+}
+
 #[derive(
     Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
 )]
@@ -61,4 +97,61 @@ mod tests {
         );
         assert_eq!(response.entries[1].node_id, 0x0003.into());
     }
+
+    #[test]
+    fn response_parse() {
+        let response = NodeTableResponse::parse(b"\x00\x00").unwrap();
+        assert_eq!(response.entries.len(), 0);
+
+        // A count which disagrees with the entries present is rejected either way
+        assert_eq!(
+            NodeTableResponse::parse(
+                b"\x00\x0C\x04\xC0\x5B\x40\x00\xA2\x34\x6F\x00\x02\x04\xC0\x5B\x40\x00\xA2\x34\x71\x00\x03"
+            )
+            .map(|_| ()),
+            Err(InvalidNodeTableResponse::EntryCountMismatch {
+                claimed: 12,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            NodeTableResponse::parse(b"\xff\xff").map(|_| ()),
+            Err(InvalidNodeTableResponse::EntryCountMismatch {
+                claimed: 0xffff,
+                actual: 0
+            })
+        );
+
+        // Partial entries and missing counts are invalid lengths
+        for bytes in [
+            &b""[..],
+            b"\x00",
+            b"\x00\x01\x04\xC0\x5B\x40\x00\xA2\x34\x6F\x00",
+        ] {
+            assert_eq!(
+                NodeTableResponse::parse(bytes).map(|_| ()),
+                Err(InvalidNodeTableResponse::InvalidLength(bytes.len()))
+            );
+        }
+    }
+
+    #[test]
+    fn response_encode() {
+        let entries = [
+            NodeTableResponseEntry {
+                long_address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x6F]),
+                node_id: 0x0002.into(),
+            },
+            NodeTableResponseEntry {
+                long_address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x71]),
+                node_id: 0x0003.into(),
+            },
+        ];
+        let encoded = NodeTableResponse::encode(&entries);
+        let response = NodeTableResponse::ref_from_bytes(&encoded).unwrap();
+        assert_eq!(response.entries_count.get(), 2);
+        assert_eq!(&response.entries, &entries);
+
+        assert_eq!(NodeTableResponse::encode(&[]), b"\x00\x00");
+    }
 }
@@ -0,0 +1,209 @@
+use crate::memory::BoundedHistory;
+use crate::pv::link::DSN;
+
+/// The number of packets over which loss is estimated.
+pub const PACKET_LOSS_WINDOW: usize = 64;
+
+/// The largest DSN gap attributed to lost packets.
+///
+/// A larger jump is more likely a node restarting its sequence, or an outage long enough that
+/// counting it packet by packet would be meaningless, so the sequence is re-baselined instead.
+pub const MAX_PLAUSIBLE_DSN_GAP: u8 = 32;
+
+/// Estimates how many of a node's packets went missing, from gaps in their data sequence numbers.
+///
+/// Each node numbers its packets sequentially, so a gap between consecutive DSNs means packets
+/// were lost before they reached us, whether over the air or inside the gateway.
+#[derive(Debug, Clone)]
+pub struct PacketLoss {
+    last_dsn: Option<DSN>,
+    /// The DSN gap preceding each of the most recent packets, where 1 means nothing was lost.
+    gaps: BoundedHistory<u8>,
+    received: u64,
+    lost: u64,
+    resets: u64,
+}
+
+/// How a packet's DSN relates to the node's sequence.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DsnObservation {
+    /// The first packet seen from this node.
+    First,
+    /// The next packet in sequence, after `lost` missing packets.
+    InSequence { lost: u8 },
+    /// A repeat of the previous packet.
+    Duplicate,
+    /// An implausible jump, after which the sequence was re-baselined.
+    Reset,
+}
+
+impl Default for PacketLoss {
+    fn default() -> Self {
+        Self {
+            last_dsn: None,
+            gaps: BoundedHistory::new(PACKET_LOSS_WINDOW),
+            received: 0,
+            lost: 0,
+            resets: 0,
+        }
+    }
+}
+
+impl PacketLoss {
+    /// Account for a packet with the given DSN.
+    pub fn observe(&mut self, dsn: DSN) -> DsnObservation {
+        let Some(last) = self.last_dsn.replace(dsn) else {
+            self.received += 1;
+            return DsnObservation::First;
+        };
+
+        // DSNs are 8 bits, so wrapping subtraction handles wrap
+        match dsn.0.wrapping_sub(last.0) {
+            0 => DsnObservation::Duplicate,
+            gap if gap > MAX_PLAUSIBLE_DSN_GAP => {
+                self.received += 1;
+                self.resets += 1;
+                self.gaps.clear();
+                DsnObservation::Reset
+            }
+            gap => {
+                self.received += 1;
+                self.lost += u64::from(gap - 1);
+                self.gaps.push(gap);
+                DsnObservation::InSequence { lost: gap - 1 }
+            }
+        }
+    }
+
+    /// The percentage of packets lost over the most recent window, once a full window has been
+    /// observed since the sequence was last re-baselined.
+    pub fn estimated_loss_pct(&self) -> Option<f64> {
+        if !self.gaps.is_full() {
+            return None;
+        }
+        let expected: u32 = self.gaps.iter().map(|gap| u32::from(*gap)).sum();
+        let lost = expected - self.gaps.len() as u32;
+        Some(100.0 * f64::from(lost) / f64::from(expected))
+    }
+
+    /// The number of distinct packets received.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The number of packets inferred to be lost.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// The number of times the sequence was re-baselined.
+    pub fn resets(&self) -> u64 {
+        self.resets
+    }
+
+    /// The approximate number of bytes this estimator occupies.
+    pub fn approximate_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() - std::mem::size_of::<BoundedHistory<u8>>()
+            + self.gaps.approximate_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe_all(loss: &mut PacketLoss, dsns: impl IntoIterator<Item = u8>) {
+        for dsn in dsns {
+            loss.observe(DSN(dsn));
+        }
+    }
+
+    #[test]
+    fn in_sequence() {
+        let mut loss = PacketLoss::default();
+        assert_eq!(loss.observe(DSN(10)), DsnObservation::First);
+        assert_eq!(
+            loss.observe(DSN(11)),
+            DsnObservation::InSequence { lost: 0 }
+        );
+        assert_eq!(loss.observe(DSN(11)), DsnObservation::Duplicate);
+        assert_eq!(
+            loss.observe(DSN(14)),
+            DsnObservation::InSequence { lost: 2 }
+        );
+        assert_eq!(loss.received(), 3);
+        assert_eq!(loss.lost(), 2);
+
+        // No estimate until the window fills
+        assert_eq!(loss.estimated_loss_pct(), None);
+        observe_all(&mut loss, 15..15 + PACKET_LOSS_WINDOW as u8);
+        assert_eq!(loss.estimated_loss_pct(), Some(0.0));
+    }
+
+    #[test]
+    fn wrap() {
+        let mut loss = PacketLoss::default();
+
+        // Wrapping from 0xFF to 0x00 is an ordinary step, as is skipping over the wrap
+        observe_all(&mut loss, 0xF0..=0xFF);
+        assert_eq!(
+            loss.observe(DSN(0x00)),
+            DsnObservation::InSequence { lost: 0 }
+        );
+        assert_eq!(
+            loss.observe(DSN(0x02)),
+            DsnObservation::InSequence { lost: 1 }
+        );
+        observe_all(&mut loss, (0xFE..=0xFF).chain(0x00..0x10));
+        assert_eq!(loss.resets(), 1); // 0x02 -> 0xFE went backwards
+        assert_eq!(loss.lost(), 1);
+
+        // Every other packet lost, across several wraps
+        let mut loss = PacketLoss::default();
+        observe_all(&mut loss, (0..600u32).map(|i| (i * 2) as u8));
+        assert_eq!(loss.resets(), 0);
+        assert_eq!(loss.lost(), 599);
+        assert_eq!(loss.estimated_loss_pct(), Some(50.0));
+    }
+
+    #[test]
+    fn reset() {
+        let mut loss = PacketLoss::default();
+        observe_all(&mut loss, 100..=200);
+        assert_eq!(loss.estimated_loss_pct(), Some(0.0));
+
+        // A node restarting its sequence isn't counted as ~150 lost packets
+        assert_eq!(loss.observe(DSN(0)), DsnObservation::Reset);
+        assert_eq!(loss.lost(), 0);
+        assert_eq!(loss.resets(), 1);
+
+        // The estimate starts over
+        assert_eq!(loss.estimated_loss_pct(), None);
+        observe_all(
+            &mut loss,
+            (1..=PACKET_LOSS_WINDOW as u32).map(|i| (i * 4) as u8),
+        );
+        assert_eq!(loss.resets(), 1);
+        assert_eq!(loss.estimated_loss_pct(), Some(75.0));
+
+        // The largest plausible gap counts as loss, one more is a reset
+        let mut loss = PacketLoss::default();
+        observe_all(&mut loss, [0, MAX_PLAUSIBLE_DSN_GAP]);
+        assert_eq!(loss.lost(), u64::from(MAX_PLAUSIBLE_DSN_GAP) - 1);
+        assert_eq!(
+            loss.observe(DSN(MAX_PLAUSIBLE_DSN_GAP * 2 + 1)),
+            DsnObservation::Reset
+        );
+    }
+
+    #[test]
+    fn bounded() {
+        let mut loss = PacketLoss::default();
+        observe_all(&mut loss, 0..=PACKET_LOSS_WINDOW as u8);
+        let bytes = loss.approximate_bytes();
+
+        // Months of packets occupy no more space than one window
+        observe_all(&mut loss, (0..1_000_000u32).map(|i| (i * 3) as u8));
+        assert_eq!(loss.approximate_bytes(), bytes);
+    }
+}
@@ -1,6 +1,18 @@
 use super::*;
 
-#[derive(Copy, Clone, Eq, PartialEq, FromBytes, IntoBytes, Unaligned, KnownLayout, Immutable)]
+#[derive(
+    Copy,
+    Clone,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    FromBytes,
+    IntoBytes,
+    Unaligned,
+    KnownLayout,
+    Immutable,
+)]
 #[repr(transparent)]
 pub struct PacketType(pub u8);
 impl PacketType {
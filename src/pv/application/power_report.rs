@@ -15,6 +15,41 @@ pub struct PowerReport {
     pub rssi: RSSI,
 }
 
+impl PowerReport {
+    /// The input voltage, in volts.
+    pub fn voltage_in(&self) -> f64 {
+        let (voltage_in, _) = self.voltage_in_and_voltage_out.into();
+        voltage_in as f64 / 20.0
+    }
+
+    /// The output voltage, in volts.
+    pub fn voltage_out(&self) -> f64 {
+        let (_, voltage_out) = self.voltage_in_and_voltage_out.into();
+        voltage_out as f64 / 10.0
+    }
+
+    /// The current, in amps.
+    pub fn current(&self) -> f64 {
+        let (current, _) = self.current_and_temperature.into();
+        current as f64 / 200.0
+    }
+
+    /// The temperature, in degrees Celsius.
+    pub fn temperature(&self) -> f64 {
+        let (_, temperature) = self.current_and_temperature.into();
+
+        // XXX: is it correct to sign-extend temperature?
+        // How are below-freezing temperatures reported? (This assumes two's complement.)
+        let temperature = if temperature & 0x800 == 0 {
+            temperature
+        } else {
+            temperature | 0xF000
+        } as i16;
+
+        temperature as f64 / 10.0
+    }
+}
+
 /// A pair of 12-bit unsigned integers packed into a single `[u8; 3]`.
 #[derive(Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned)]
 #[repr(C)]
@@ -0,0 +1,203 @@
+use super::*;
+use crate::pv::network::NodeAddress;
+use zerocopy::big_endian;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A controller's request assigning a node's reporting parameters.
+///
+/// The period and phase are measured in slots of the gateway's slot counter. A period of 4000
+/// slots reports every 20 seconds, and each node is given a different phase to spread the load.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
+)]
+#[repr(C)]
+pub struct PvConfigurationRequest {
+    pub node_address: NodeAddress,
+    pub unknown_1: [u8; 2],
+    /// The type of packet to report, which is a power report.
+    pub packet_type: PacketType,
+    pub unknown_2: u8,
+    pub period: big_endian::U16,
+    pub phase: big_endian::U16,
+    pub unknown_3: [u8; 14],
+}
+
+impl PvConfigurationRequest {
+    /// Interpret bytes as a PV configuration request.
+    pub fn parse(bytes: &[u8]) -> Option<&Self> {
+        Self::ref_from_bytes(bytes).ok()
+    }
+}
+
+/// The radio parameters a node reports in its PV configuration response.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
+)]
+#[repr(C)]
+pub struct PvRadioParameters {
+    pub pan_id: big_endian::U16,
+    pub channel: u8,
+    pub unknown: [u8; 2],
+}
+
+/// The reporting parameters a node reports in its PV configuration response, echoing a request.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
+)]
+#[repr(C)]
+pub struct PvReportingParameters {
+    pub unknown_1: [u8; 8],
+    pub packet_type: PacketType,
+    pub period: big_endian::U16,
+    pub phase: big_endian::U16,
+    pub unknown_2: [u8; 6],
+}
+
+/// A node's response to a PV configuration request.
+///
+/// The radio parameters and the reporting parameters each appear twice, possibly describing an
+/// alternate or backup configuration.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
+)]
+#[repr(C)]
+pub struct PvConfigurationResponse {
+    pub unknown: u8,
+    pub radio: [PvRadioParameters; 2],
+    pub reporting: [PvReportingParameters; 2],
+}
+
+impl PvConfigurationResponse {
+    /// Interpret bytes as a PV configuration response.
+    pub fn parse(bytes: &[u8]) -> Option<&Self> {
+        Self::ref_from_bytes(bytes).ok()
+    }
+}
+
+/// A node's PV configuration, as kept after its response.
+///
+/// Fields whose meanings are unknown are kept in hex, in the order they appear.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PvConfiguration {
+    /// The radio parameters, followed by what might be an alternate.
+    pub radio: [PvRadioConfiguration; 2],
+    /// The reporting parameters, followed by what might be an alternate.
+    pub reporting: [PvReportingConfiguration; 2],
+    pub unknown: String,
+}
+
+/// The radio parameters of a [`PvConfiguration`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PvRadioConfiguration {
+    pub pan_id: u16,
+    pub channel: u8,
+    pub unknown: String,
+}
+
+/// The reporting parameters of a [`PvConfiguration`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PvReportingConfiguration {
+    /// The type of packet reported.
+    pub packet_type: u8,
+    /// The reporting period, in slots.
+    pub period: u16,
+    /// The reporting phase, in slots.
+    pub phase: u16,
+    pub unknown: [String; 2],
+}
+
+impl From<&PvRadioParameters> for PvRadioConfiguration {
+    fn from(radio: &PvRadioParameters) -> Self {
+        Self {
+            pan_id: radio.pan_id.get(),
+            channel: radio.channel,
+            unknown: hex(&radio.unknown),
+        }
+    }
+}
+
+impl From<&PvReportingParameters> for PvReportingConfiguration {
+    fn from(reporting: &PvReportingParameters) -> Self {
+        Self {
+            packet_type: reporting.packet_type.0,
+            period: reporting.period.get(),
+            phase: reporting.phase.get(),
+            unknown: [hex(&reporting.unknown_1), hex(&reporting.unknown_2)],
+        }
+    }
+}
+
+impl From<&PvConfigurationResponse> for PvConfiguration {
+    fn from(response: &PvConfigurationResponse) -> Self {
+        Self {
+            radio: response.radio.each_ref().map(PvRadioConfiguration::from),
+            reporting: response
+                .reporting
+                .each_ref()
+                .map(PvReportingConfiguration::from),
+            unknown: hex(&[response.unknown]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pv::NodeID;
+
+    /// The request in the protocol notes.
+    const REQUEST: &[u8] = b"\x00\x39\x03\x00\x31\x02\x0F\xA0\x09\x7D\
+        \x00\x09\x02\x00\x00\x00\x00\x00\x30\x02\x00\x00\x00\x00";
+
+    /// The response in the protocol notes, with each group repeated.
+    const RESPONSE: &[u8] = b"\x0F\x24\xF6\x15\x6C\x00\x24\xF6\x15\x6C\x00\
+        \x03\x00\x30\x00\x00\x00\x00\x00\x31\x0F\xA0\x09\x7D\x00\x09\x00\x00\x00\x00\
+        \x03\x00\x30\x00\x00\x00\x00\x00\x31\x0F\xA0\x09\x7D\x00\x09\x00\x00\x00\x00";
+
+    #[test]
+    fn parse() {
+        let request = PvConfigurationRequest::parse(REQUEST).unwrap();
+        assert_eq!(
+            NodeID::try_from(request.node_address),
+            Ok(NodeID::try_from(0x39).unwrap())
+        );
+        assert_eq!(request.packet_type, PacketType::POWER_REPORT);
+        assert_eq!(request.period.get(), 4000);
+        assert_eq!(request.phase.get(), 0x097D);
+
+        let response = PvConfigurationResponse::parse(RESPONSE).unwrap();
+        let radio = PvRadioConfiguration {
+            pan_id: 0x24F6,
+            channel: 21,
+            unknown: "6c00".into(),
+        };
+        let reporting = PvReportingConfiguration {
+            packet_type: 0x31,
+            period: 4000,
+            phase: 0x097D,
+            unknown: ["0300300000000000".into(), "000900000000".into()],
+        };
+        assert_eq!(
+            PvConfiguration::from(response),
+            PvConfiguration {
+                radio: [radio.clone(), radio],
+                reporting: [reporting.clone(), reporting],
+                unknown: "0f".into(),
+            }
+        );
+
+        for bytes in [&b""[..], &RESPONSE[..48], &[RESPONSE, b"\x00"].concat()] {
+            assert_eq!(PvConfigurationResponse::parse(bytes), None);
+        }
+        assert_eq!(PvConfigurationRequest::parse(&REQUEST[..23]), None);
+    }
+}
@@ -0,0 +1,123 @@
+use super::*;
+use zerocopy::big_endian;
+
+/// A controller's request for a gateway's radio configuration, sent after enumeration.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
+)]
+#[repr(C)]
+pub struct RadioConfigurationRequest {
+    pub unknown: [u8; 2],
+}
+
+/// A gateway's response to a radio configuration request.
+///
+/// The response describes the 802.15.4 network which the gateway runs for its nodes. Nodes only
+/// hear gateways on their own PAN ID and channel, so these explain which gateway a node joins.
+/// The four bytes following the PAN ID might describe the structure of the gateway's superframe,
+/// but this hasn't been confirmed.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned,
+)]
+#[repr(C)]
+pub struct RadioConfigurationResponse {
+    pub unknown_1: u8,
+    pub channel: u8,
+    pub pan_id: big_endian::U16,
+    /// Possibly the length of the contention access period.
+    pub cap: u8,
+    /// Possibly the length of the contention free period.
+    pub cfp: u8,
+    /// Possibly the length of the beacon-only period.
+    pub bop: u8,
+    /// Possibly the length of the inactive period.
+    pub iap: u8,
+    pub unknown_2: [u8; 7],
+    /// A key of the length AES-128 expects, though how it is used isn't known.
+    pub encryption_key: [u8; 16],
+    pub unknown_3: [u8; 6],
+}
+
+impl RadioConfigurationResponse {
+    /// Interpret bytes as a radio configuration response.
+    pub fn parse(bytes: &[u8]) -> Option<&Self> {
+        Self::ref_from_bytes(bytes).ok()
+    }
+}
+
+/// A gateway's radio configuration, as kept after its response.
+///
+/// Fields whose meanings are unknown are kept in hex, in the order they appear.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RadioConfiguration {
+    pub channel: u8,
+    pub pan_id: u16,
+    pub cap: u8,
+    pub cfp: u8,
+    pub bop: u8,
+    pub iap: u8,
+    pub encryption_key: String,
+    pub unknown: [String; 3],
+}
+
+impl From<&RadioConfigurationResponse> for RadioConfiguration {
+    fn from(response: &RadioConfigurationResponse) -> Self {
+        let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Self {
+            channel: response.channel,
+            pan_id: response.pan_id.get(),
+            cap: response.cap,
+            cfp: response.cfp,
+            bop: response.bop,
+            iap: response.iap,
+            encryption_key: hex(&response.encryption_key),
+            unknown: [
+                hex(&[response.unknown_1]),
+                hex(&response.unknown_2),
+                hex(&response.unknown_3),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The response in the protocol notes, with a key filled in.
+    const RESPONSE: &[u8] = b"\x00\x15\x24\xF6\x18\x04\x02\x01\x00\x00\x00\x00\x1C\x01\x5A\
+        \x00\x11\x22\x33\x44\x55\x66\x77\x88\x99\xAA\xBB\xCC\xDD\xEE\xFF\x00\x00\x02\x00\x3C\x00";
+
+    #[test]
+    fn parse() {
+        let response = RadioConfigurationResponse::parse(RESPONSE).unwrap();
+        assert_eq!(response.channel, 0x15);
+        assert_eq!(response.pan_id.get(), 0x24F6);
+        assert_eq!(
+            [response.cap, response.cfp, response.bop, response.iap],
+            [0x18, 0x04, 0x02, 0x01]
+        );
+        assert_eq!(
+            RadioConfiguration::from(response),
+            RadioConfiguration {
+                channel: 21,
+                pan_id: 0x24F6,
+                cap: 24,
+                cfp: 4,
+                bop: 2,
+                iap: 1,
+                encryption_key: "00112233445566778899aabbccddeeff".into(),
+                unknown: ["00".into(), "000000001c015a".into(), "000002003c00".into()],
+            }
+        );
+
+        for bytes in [&b""[..], &RESPONSE[..36], &[RESPONSE, b"\x00"].concat()] {
+            assert_eq!(RadioConfigurationResponse::parse(bytes), None);
+        }
+
+        let request = RadioConfigurationRequest::ref_from_bytes(b"\x00\x01").unwrap();
+        assert_eq!(request.unknown, [0x00, 0x01]);
+    }
+}
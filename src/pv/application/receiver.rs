@@ -1,12 +1,26 @@
 use super::*;
 use crate::gateway::GatewayID;
+use crate::log_limit::LogLimiter;
+use crate::memory::{btree_map_bytes, MemoryReport};
 use crate::pv::network::{NodeAddress, ReceivedPacketHeader};
 use crate::pv::{LongAddress, NodeID, PacketType, SlotCounter};
+use crate::text::LossyStr;
 use crate::{gateway, pv};
+use std::collections::BTreeMap;
 
 pub trait Sink {
-    fn string_request(&mut self, gateway_id: GatewayID, pv_node_id: pv::NodeID, request: &str);
-    fn string_response(&mut self, gateway_id: GatewayID, pv_node_id: pv::NodeID, response: &str);
+    /// A string command was sent to a node.
+    ///
+    /// Strings which aren't valid UTF-8 but are mostly text are passed along, and display with
+    /// replacement characters.
+    fn string_request(&mut self, gateway_id: GatewayID, pv_node_id: pv::NodeID, request: LossyStr);
+    /// A node responded to a string command.
+    fn string_response(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: pv::NodeID,
+        response: LossyStr,
+    );
     fn node_table_page(
         &mut self,
         gateway_id: GatewayID,
@@ -26,34 +40,106 @@ pub trait Sink {
         pv_node_id: pv::NodeID,
         power_report: &PowerReport,
     );
+
+    /// The controller broadcast data, and the gateway acknowledged it.
+    fn broadcast(&mut self, gateway_id: GatewayID, payload: &Broadcast);
+
+    /// A gateway responded to a network status request.
+    fn network_status(&mut self, gateway_id: GatewayID, status: &NetworkStatusResponse);
+
+    /// A gateway responded to a radio configuration request.
+    fn gateway_radio_configuration(
+        &mut self,
+        gateway_id: GatewayID,
+        config: &RadioConfigurationResponse,
+    );
+
+    /// A node reported its PV configuration, in response to a PV configuration request.
+    fn pv_configuration(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: pv::NodeID,
+        config: &PvConfigurationResponse,
+    );
+
+    /// A node's recent packet loss was estimated from gaps in its packets' sequence numbers.
+    ///
+    /// This is called after each packet once enough packets have been received to tell.
+    fn packet_loss_estimated(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: pv::NodeID,
+        estimated_loss_pct: f64,
+    );
+
+    /// A node sent a packet which could not be interpreted, and was discarded.
+    ///
+    /// This is called for each received packet counted as invalid, so that it can be kept for
+    /// study.
+    fn invalid_packet(
+        &mut self,
+        gateway_id: GatewayID,
+        pv_node_id: pv::NodeID,
+        packet_type: PacketType,
+        data: &[u8],
+    ) {
+        let _ = (gateway_id, pv_node_id, packet_type, data);
+    }
 }
 
+/// Counters describing the packets and commands handled by a `Receiver`.
+///
+/// [`crate::Counters::snapshot()`] collects these along with the counters of the layers below.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Counters {
-    invalid_received_packet_node_ids: u64,
-    invalid_power_reports: u64,
-    power_reports: u64,
-    invalid_topology_reports: u64,
-    topology_reports: u64,
-    invalid_node_table_requests: u64,
-    invalid_node_table_responses: u64,
-    invalid_string_commands: u64,
-    string_commands: u64,
-    invalid_string_responses: u64,
-    string_responses: u64,
+    pub invalid_received_packet_node_ids: u64,
+    pub invalid_power_reports: u64,
+    pub power_reports: u64,
+    pub invalid_topology_reports: u64,
+    pub topology_reports: u64,
+    pub invalid_node_table_requests: u64,
+    pub invalid_node_table_responses: u64,
+    pub invalid_string_commands: u64,
+    pub string_commands: u64,
+    /// The number of string commands which weren't valid UTF-8, but were mostly text.
+    pub lossy_string_commands: u64,
+    pub invalid_string_responses: u64,
+    pub string_responses: u64,
+    /// The number of string responses which weren't valid UTF-8, but were mostly text.
+    pub lossy_string_responses: u64,
+    pub invalid_broadcasts: u64,
+    pub broadcasts: u64,
+    pub invalid_network_status_responses: u64,
+    pub network_status_responses: u64,
+    pub invalid_radio_configuration_responses: u64,
+    pub radio_configuration_responses: u64,
+    pub invalid_pv_configuration_requests: u64,
+    pub invalid_pv_configuration_responses: u64,
+    pub pv_configuration_responses: u64,
+    /// The number of packets inferred to be lost from gaps in nodes' sequence numbers.
+    pub lost_packets: u64,
+    /// The number of times a node's sequence numbers jumped implausibly and were re-baselined.
+    pub dsn_resets: u64,
 }
 
 #[derive(Debug)]
 pub struct Receiver<S: gateway::transport::Sink + Sink> {
     sink: S,
+    packet_loss: BTreeMap<(GatewayID, NodeID), PacketLoss>,
     counters: Counters,
+    warnings: LogLimiter<PacketType>,
 }
 
 impl<S: gateway::transport::Sink + Sink> Receiver<S> {
     pub fn new(sink: S) -> Self {
         Self {
             sink,
+            packet_loss: Default::default(),
             counters: Default::default(),
+            warnings: Default::default(),
         }
     }
 
@@ -73,18 +159,41 @@ impl<S: gateway::transport::Sink + Sink> Receiver<S> {
         &self.counters
     }
 
+    /// Reset the counters.
+    ///
+    /// Each node's sequence number tracking is kept, so that the next packet from a node isn't
+    /// mistaken for a gap.
+    pub fn reset_counters(&mut self) {
+        self.counters = Counters::default();
+    }
+
+    /// Packet loss statistics for each node.
+    pub fn packet_loss(&self) -> &BTreeMap<(GatewayID, NodeID), PacketLoss> {
+        &self.packet_loss
+    }
+
+    /// Approximate the memory held by the receiver's per-node state.
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::default();
+        report.add(
+            "application.packet_loss",
+            btree_map_bytes::<(GatewayID, NodeID), ()>(self.packet_loss.len())
+                + self
+                    .packet_loss
+                    .values()
+                    .map(PacketLoss::approximate_bytes)
+                    .sum::<usize>(),
+        );
+        report
+    }
+
     fn node_table_command(&mut self, gateway_id: GatewayID, request: &[u8], response: &[u8]) {
         let Ok(request) = NodeTableRequest::ref_from_bytes(request) else {
             self.counters.invalid_node_table_requests += 1;
             return;
         };
 
-        let Ok(response) = NodeTableResponse::ref_from_bytes(response) else {
-            self.counters.invalid_node_table_responses += 1;
-            return;
-        };
-
-        if response.entries.len() != response.entries_count.get() as usize {
+        let Ok(response) = NodeTableResponse::parse(response) else {
             self.counters.invalid_node_table_responses += 1;
             return;
         };
@@ -103,10 +212,11 @@ impl<S: gateway::transport::Sink + Sink> Receiver<S> {
             return;
         };
 
-        let Ok(request) = std::str::from_utf8(request) else {
+        let request = LossyStr(request);
+        if request.is_mostly_binary() {
             self.counters.invalid_string_commands += 1;
             return;
-        };
+        }
 
         if !response.is_empty() {
             self.counters.invalid_string_commands += 1;
@@ -114,9 +224,51 @@ impl<S: gateway::transport::Sink + Sink> Receiver<S> {
         }
 
         self.counters.string_commands += 1;
+        if !request.is_valid_utf8() {
+            self.counters.lossy_string_commands += 1;
+        }
 
         self.sink.string_request(gateway_id, node, request);
     }
+
+    /// Returns whether the response was valid.
+    fn pv_configuration_response(
+        &mut self,
+        gateway_id: GatewayID,
+        node: NodeID,
+        response: &[u8],
+    ) -> bool {
+        if let Some(config) = PvConfigurationResponse::parse(response) {
+            self.counters.pv_configuration_responses += 1;
+            self.sink.pv_configuration(gateway_id, node, config);
+            true
+        } else {
+            self.counters.invalid_pv_configuration_responses += 1;
+            false
+        }
+    }
+
+    /// Pass an invalid packet to the sink, logging the first of each type and then fewer.
+    fn invalid_packet(
+        &mut self,
+        gateway_id: GatewayID,
+        node: NodeID,
+        packet_type: PacketType,
+        data: &[u8],
+    ) {
+        if let Some(count) = self.warnings.count(packet_type) {
+            log::warn!(
+                "discarded {} from {:?} node {:?} ({} bytes) ({} so far)",
+                packet_type,
+                gateway_id,
+                node,
+                data.len(),
+                count
+            );
+        }
+        self.sink
+            .invalid_packet(gateway_id, node, packet_type, data);
+    }
 }
 
 impl<S: gateway::transport::Sink + Sink> gateway::transport::Sink for Receiver<S> {
@@ -128,8 +280,12 @@ impl<S: gateway::transport::Sink + Sink> gateway::transport::Sink for Receiver<S
         self.sink.gateway_identity_observed(gateway_id, address)
     }
 
-    fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str) {
-        self.sink.gateway_version_observed(gateway_id, version)
+    fn gateway_version_observed(&mut self, gateway_id: GatewayID, version: &str, raw: &[u8]) {
+        self.sink.gateway_version_observed(gateway_id, version, raw)
+    }
+
+    fn gateway_id_assigned(&mut self, old: GatewayID, new: GatewayID, address: LongAddress) {
+        self.sink.gateway_id_assigned(old, new, address)
     }
 
     fn enumeration_ended(&mut self, gateway_id: GatewayID) {
@@ -145,6 +301,26 @@ impl<S: gateway::transport::Sink + Sink> gateway::transport::Sink for Receiver<S
             .gateway_slot_counter_observed(gateway_id, slot_counter)
     }
 
+    fn receive_status(
+        &mut self,
+        gateway_id: GatewayID,
+        status: &gateway::transport::ReceiveResponse,
+    ) {
+        self.sink.receive_status(gateway_id, status)
+    }
+
+    fn invalid_frame(
+        &mut self,
+        frame: &gateway::link::Frame,
+        reason: gateway::transport::InvalidFrameReason,
+    ) {
+        self.sink.invalid_frame(frame, reason)
+    }
+
+    fn checksum_failed(&mut self, bytes: &[u8]) {
+        self.sink.checksum_failed(bytes)
+    }
+
     fn packet_received(
         &mut self,
         gateway_id: GatewayID,
@@ -158,13 +334,34 @@ impl<S: gateway::transport::Sink + Sink> gateway::transport::Sink for Receiver<S
             return;
         };
 
-        match header.packet_type {
+        let packet_loss = self.packet_loss.entry((gateway_id, node_id)).or_default();
+        match packet_loss.observe(header.dsn) {
+            DsnObservation::InSequence { lost } => {
+                self.counters.lost_packets += u64::from(lost);
+                if let Some(estimated_loss_pct) = packet_loss.estimated_loss_pct() {
+                    self.sink
+                        .packet_loss_estimated(gateway_id, node_id, estimated_loss_pct);
+                }
+            }
+            DsnObservation::Reset => {
+                self.counters.dsn_resets += 1;
+            }
+            DsnObservation::First | DsnObservation::Duplicate => {}
+        }
+
+        let valid = match header.packet_type {
             PacketType::STRING_RESPONSE => {
-                if let Ok(response) = std::str::from_utf8(data) {
+                let response = LossyStr(data);
+                if response.is_mostly_binary() {
+                    self.counters.invalid_string_responses += 1;
+                    false
+                } else {
                     self.counters.string_responses += 1;
+                    if !response.is_valid_utf8() {
+                        self.counters.lossy_string_responses += 1;
+                    }
                     self.sink.string_response(gateway_id, node_id, response);
-                } else {
-                    self.counters.invalid_string_responses += 1;
+                    true
                 }
             }
             PacketType::TOPOLOGY_REPORT => {
@@ -172,19 +369,29 @@ impl<S: gateway::transport::Sink + Sink> gateway::transport::Sink for Receiver<S
                     self.counters.topology_reports += 1;
                     self.sink
                         .topology_report(gateway_id, node_id, topology_report);
+                    true
                 } else {
                     self.counters.invalid_topology_reports += 1;
+                    false
                 }
             }
             PacketType::POWER_REPORT => {
                 if let Ok(power_report) = PowerReport::ref_from_bytes(data) {
                     self.counters.power_reports += 1;
                     self.sink.power_report(gateway_id, node_id, power_report);
+                    true
                 } else {
                     self.counters.invalid_power_reports += 1;
+                    false
                 }
             }
-            _ => {}
+            PacketType::PV_CONFIGURATION_RESPONSE => {
+                self.pv_configuration_response(gateway_id, node_id, data)
+            }
+            _ => true,
+        };
+        if !valid {
+            self.invalid_packet(gateway_id, node_id, header.packet_type, data);
         }
     }
 
@@ -204,12 +411,46 @@ impl<S: gateway::transport::Sink + Sink> gateway::transport::Sink for Receiver<S
             (PacketType::STRING_REQUEST, PacketType::STRING_RESPONSE) => {
                 self.string_command(gateway_id, request.1, response.1);
             }
-            //(PacketType::BROADCAST, PacketType::BROADCAST_ACK) => {}
+            (PacketType::BROADCAST, PacketType::BROADCAST_ACK) => {
+                if let Some(broadcast) = Broadcast::parse(request.1) {
+                    self.counters.broadcasts += 1;
+                    self.sink.broadcast(gateway_id, broadcast);
+                } else {
+                    self.counters.invalid_broadcasts += 1;
+                }
+            }
             (
                 PacketType::NETWORK_STATUS_REQUEST | PacketType::LONG_NETWORK_STATUS_REQUEST,
                 PacketType::NETWORK_STATUS_RESPONSE,
             ) => {
-                // TODO
+                if let Some(status) = NetworkStatusResponse::parse(response.1) {
+                    self.counters.network_status_responses += 1;
+                    self.sink.network_status(gateway_id, status);
+                } else {
+                    self.counters.invalid_network_status_responses += 1;
+                }
+            }
+            (
+                PacketType::GATEWAY_RADIO_CONFIGURATION_REQUEST,
+                PacketType::GATEWAY_RADIO_CONFIGURATION_RESPONSE,
+            ) => {
+                if let Some(config) = RadioConfigurationResponse::parse(response.1) {
+                    self.counters.radio_configuration_responses += 1;
+                    self.sink.gateway_radio_configuration(gateway_id, config);
+                } else {
+                    self.counters.invalid_radio_configuration_responses += 1;
+                }
+            }
+            (PacketType::PV_CONFIGURATION_REQUEST, PacketType::PV_CONFIGURATION_RESPONSE) => {
+                // The node is named by the request, since the response doesn't say
+                let node = PvConfigurationRequest::parse(request.1)
+                    .and_then(|request| NodeID::try_from(request.node_address).ok());
+                match node {
+                    Some(node) => {
+                        self.pv_configuration_response(gateway_id, node, response.1);
+                    }
+                    None => self.counters.invalid_pv_configuration_requests += 1,
+                }
             }
             _ => {
                 /*
@@ -224,4 +465,117 @@ impl<S: gateway::transport::Sink + Sink> gateway::transport::Sink for Receiver<S
             }
         }
     }
+
+    fn command_timed_out(
+        &mut self,
+        gateway_id: GatewayID,
+        packet_type: PacketType,
+        sequence_number: gateway::transport::CommandSequenceNumber,
+    ) {
+        self.sink
+            .command_timed_out(gateway_id, packet_type, sequence_number)
+    }
+
+    fn gateway_tx_buffers_free_observed(&mut self, gateway_id: GatewayID, tx_buffers_free: u8) {
+        self.sink
+            .gateway_tx_buffers_free_observed(gateway_id, tx_buffers_free)
+    }
+
+    fn polling_fairness(&self) -> gateway::transport::polling::PollingFairness {
+        self.sink.polling_fairness()
+    }
+
+    fn polling_unfair(
+        &mut self,
+        gateway_id: GatewayID,
+        polling: &gateway::transport::polling::UnfairPolling,
+    ) {
+        self.sink.polling_unfair(gateway_id, polling)
+    }
+
+    fn gateway_capabilities(&self, gateway_id: GatewayID) -> gateway::GatewayCapabilities {
+        self.sink.gateway_capabilities(gateway_id)
+    }
+}
+
+#[cfg(all(test, feature = "observer"))]
+mod tests {
+    use super::*;
+    use crate::gateway::transport::Sink as _;
+    use crate::observer::Observer;
+    use crate::pv::link::DSN;
+    use crate::pv::ShortAddress;
+
+    fn receive(rx: &mut Receiver<Observer>, dsn: u8, packet_type: PacketType, data: &[u8]) {
+        let header = ReceivedPacketHeader {
+            packet_type,
+            node_address: NodeID::try_from(2).unwrap().into(),
+            short_address: ShortAddress(0x0000.into()),
+            dsn: DSN(dsn),
+            data_length: data.len() as u8,
+        };
+        rx.packet_received(GatewayID::try_from(0x1201).unwrap(), &header, data);
+    }
+
+    #[test]
+    fn counters() {
+        let mut rx = Receiver::new(Observer::default());
+
+        // Power reports are counted as invalid unless they're exactly the right length
+        receive(&mut rx, 1, PacketType::POWER_REPORT, &[0; 5]);
+        assert_eq!(rx.counters().invalid_power_reports, 1);
+        receive(
+            &mut rx,
+            2,
+            PacketType::POWER_REPORT,
+            &[0; size_of::<PowerReport>()],
+        );
+        assert_eq!(rx.counters().invalid_power_reports, 1);
+        assert_eq!(rx.counters().power_reports, 1);
+
+        // String responses are counted as invalid if they're mostly binary
+        receive(
+            &mut rx,
+            3,
+            PacketType::STRING_RESPONSE,
+            &[0x00, 0xff, 0x01, 0xfe],
+        );
+        assert_eq!(rx.counters().invalid_string_responses, 1);
+        assert_eq!(rx.counters().string_responses, 0);
+        receive(
+            &mut rx,
+            4,
+            PacketType::STRING_RESPONSE,
+            b"Mnode version 1.2\r",
+        );
+        assert_eq!(rx.counters().string_responses, 1);
+        receive(
+            &mut rx,
+            5,
+            PacketType::STRING_RESPONSE,
+            b"Mnode version 1.\xff\r",
+        );
+        assert_eq!(rx.counters().string_responses, 2);
+        assert_eq!(rx.counters().lossy_string_responses, 1);
+        assert_eq!(rx.counters().invalid_string_responses, 1);
+        assert_eq!(rx.counters().lost_packets, 0);
+
+        rx.reset_counters();
+        assert_eq!(rx.counters(), &Counters::default());
+
+        // The next packet in sequence isn't a gap
+        receive(
+            &mut rx,
+            6,
+            PacketType::POWER_REPORT,
+            &[0; size_of::<PowerReport>()],
+        );
+        assert_eq!(
+            rx.counters(),
+            &Counters {
+                power_reports: 1,
+                ..Default::default()
+            }
+        );
+    }
 }
@@ -0,0 +1,175 @@
+//! The text protocol carried by string requests and responses.
+//!
+//! The controller configures and interrogates nodes by sending them short text commands, to which
+//! they answer in text. Only the shapes of the common exchanges are understood: a node answers
+//! `Version` with its firmware version, as in `"Mnode Version K8.0120 (2D)"`, and answers a
+//! request naming a parameter, as in `Info`, with a response naming it again, as in
+//! `"!Info 0000 15 …"`. A parameter name followed by a value sets that parameter. Anything else is
+//! kept verbatim.
+
+/// A string request sent to a node.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Query {
+    /// A request for the node's firmware version.
+    Version,
+    /// A request for a parameter's value.
+    Read { parameter: String },
+    /// A request setting a parameter.
+    Write { parameter: String, value: String },
+    /// A request of no recognized shape, as it was sent.
+    Unknown(String),
+}
+
+impl Query {
+    pub fn parse(request: &str) -> Self {
+        let request = request.trim();
+        let (name, value) = match request.split_once(char::is_whitespace) {
+            Some((name, value)) => (name, value.trim_start()),
+            None => (request, ""),
+        };
+
+        if !is_parameter_name(name) {
+            Query::Unknown(request.into())
+        } else if value.is_empty() && name == "Version" {
+            Query::Version
+        } else if value.is_empty() {
+            Query::Read {
+                parameter: name.into(),
+            }
+        } else {
+            Query::Write {
+                parameter: name.into(),
+                value: value.into(),
+            }
+        }
+    }
+
+    /// Whether `response` could be an answer to this request.
+    ///
+    /// A response of an unrecognized shape could answer anything.
+    pub fn answered_by(&self, response: &Response) -> bool {
+        match (self, response) {
+            (_, Response::Unknown(_)) | (Query::Unknown(_), _) => true,
+            (Query::Version, Response::Version(_)) => true,
+            (Query::Read { parameter }, Response::Parameter { parameter: p, .. })
+            | (Query::Write { parameter, .. }, Response::Parameter { parameter: p, .. }) => {
+                parameter == p
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A node's response to a string request.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Response {
+    /// The node's firmware version, in full.
+    Version(String),
+    /// A parameter's value, following its name.
+    Parameter { parameter: String, value: String },
+    /// A response of no recognized shape, as it was received.
+    Unknown(String),
+}
+
+impl Response {
+    pub fn parse(response: &str) -> Self {
+        let response = response.trim();
+        if response.contains(" Version ") {
+            return Response::Version(response.into());
+        }
+
+        let named =
+            response
+                .strip_prefix('!')
+                .map(|named| match named.split_once(char::is_whitespace) {
+                    Some((name, value)) => (name, value.trim_start()),
+                    None => (named, ""),
+                });
+        match named {
+            Some((name, value)) if is_parameter_name(name) => Response::Parameter {
+                parameter: name.into(),
+                value: value.into(),
+            },
+            _ => Response::Unknown(response.into()),
+        }
+    }
+}
+
+fn is_parameter_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query() {
+        assert_eq!(Query::parse("Version"), Query::Version);
+        assert_eq!(Query::parse("Version\r"), Query::Version);
+        assert_eq!(
+            Query::parse("Info"),
+            Query::Read {
+                parameter: "Info".into()
+            }
+        );
+        assert_eq!(
+            Query::parse("Radio  3 1"),
+            Query::Write {
+                parameter: "Radio".into(),
+                value: "3 1".into()
+            }
+        );
+        assert_eq!(
+            Query::parse("Version 2"),
+            Query::Write {
+                parameter: "Version".into(),
+                value: "2".into()
+            }
+        );
+        assert_eq!(Query::parse("?"), Query::Unknown("?".into()));
+        assert_eq!(Query::parse(""), Query::Unknown("".into()));
+    }
+
+    #[test]
+    fn response() {
+        assert_eq!(
+            Response::parse("Mnode Version K8.0120 (2D)\r"),
+            Response::Version("Mnode Version K8.0120 (2D)".into())
+        );
+        assert_eq!(
+            Response::parse("!Info 0000 15 a2"),
+            Response::Parameter {
+                parameter: "Info".into(),
+                value: "0000 15 a2".into()
+            }
+        );
+        assert_eq!(
+            Response::parse("!Radio"),
+            Response::Parameter {
+                parameter: "Radio".into(),
+                value: "".into()
+            }
+        );
+        assert_eq!(Response::parse("!"), Response::Unknown("!".into()));
+        assert_eq!(Response::parse("OK"), Response::Unknown("OK".into()));
+    }
+
+    #[test]
+    fn answered_by() {
+        let info = Query::parse("Info");
+        assert!(info.answered_by(&Response::parse("!Info 0000")));
+        assert!(!info.answered_by(&Response::parse("!Radio 3")));
+        assert!(!info.answered_by(&Response::parse("Mnode Version K8.0120")));
+        assert!(info.answered_by(&Response::parse("OK")));
+        assert!(Query::Version.answered_by(&Response::parse("Mnode Version K8.0120")));
+        assert!(Query::parse("?").answered_by(&Response::parse("!Info 0000")));
+    }
+}
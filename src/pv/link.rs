@@ -1,5 +1,3 @@
-use schemars::JsonSchema;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use zerocopy::{big_endian, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned, U16};
 
 mod slot_counter;
@@ -44,15 +42,14 @@ impl std::fmt::Display for ShortAddress {
     PartialEq,
     Ord,
     PartialOrd,
-    Serialize,
-    Deserialize,
-    JsonSchema,
     FromBytes,
     IntoBytes,
     Unaligned,
     KnownLayout,
     Immutable,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(transparent)]
 pub struct LongAddress(pub [u8; 8]);
 
@@ -90,6 +87,67 @@ impl std::fmt::Display for LongAddress {
     }
 }
 
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("invalid long address {0:?}: expected eight colon-separated hex bytes")]
+pub struct ParseLongAddressError(String);
+
+impl std::str::FromStr for LongAddress {
+    type Err = ParseLongAddressError;
+
+    /// Parse a long address as written by `Display`, like `04:C0:5B:40:00:A2:34:56`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut address = [0u8; 8];
+        let mut parts = s.split(':');
+        for byte in &mut address {
+            *byte = parts
+                .next()
+                .filter(|part| part.len() == 2 && part.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|part| u8::from_str_radix(part, 16).ok())
+                .ok_or_else(|| ParseLongAddressError(s.into()))?;
+        }
+        match parts.next() {
+            None => Ok(LongAddress(address)),
+            Some(_) => Err(ParseLongAddressError(s.into())),
+        }
+    }
+}
+
+/// Serialize maps keyed by [`LongAddress`] with string keys like `"04:C0:5B:40:00:A2:34:56"`.
+///
+/// Long addresses serialize as arrays, which formats like JSON and TOML don't permit as keys. Use
+/// with `#[serde(with = "long_address_keys")]`.
+#[cfg(feature = "serde")]
+pub mod long_address_keys {
+    use super::LongAddress;
+    use serde::de::Error;
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S, V>(map: &BTreeMap<LongAddress, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        let mut s = serializer.serialize_map(Some(map.len()))?;
+        for (address, value) in map {
+            s.serialize_entry(&address.to_string(), value)?;
+        }
+        s.end()
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<BTreeMap<LongAddress, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        BTreeMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| Ok((key.parse().map_err(D::Error::custom)?, value)))
+            .collect()
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, FromBytes, IntoBytes, Unaligned, KnownLayout, Immutable)]
 #[repr(transparent)]
 pub struct DSN(pub u8);
@@ -138,6 +196,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn long_address_from_str() {
+        let address = LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x34, 0x56]);
+        assert_eq!(address.to_string().parse(), Ok(address));
+        assert_eq!("04:c0:5b:40:00:a2:34:56".parse(), Ok(address));
+        for s in [
+            "",
+            "04:C0:5B:40:00:A2:34",
+            "04:C0:5B:40:00:A2:34:56:78",
+            "04:C0:5B:40:00:A2:34:5",
+            "04:C0:5B:40:00:A2:34:+5",
+            "04C05B4000A23456",
+        ] {
+            assert_eq!(
+                s.parse::<LongAddress>(),
+                Err(ParseLongAddressError(s.into())),
+                "{}",
+                s
+            );
+        }
+    }
+
     #[test]
     fn short_address_fmt() {
         assert_eq!(
@@ -91,21 +91,25 @@ impl std::fmt::Debug for SlotCounter {
     }
 }
 
-impl Serialize for SlotCounter {
+#[cfg(feature = "serde")]
+impl serde::Serialize for SlotCounter {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
-        S: Serializer,
+        S: serde::Serializer,
     {
         serializer.serialize_u16(self.0.get())
     }
 }
 
-impl<'de> Deserialize<'de> for SlotCounter {
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SlotCounter {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
-        D: Deserializer<'de>,
+        D: serde::Deserializer<'de>,
     {
-        u16::deserialize(deserializer).map(U16::from).map(Self)
+        <u16 as serde::Deserialize>::deserialize(deserializer)
+            .map(U16::from)
+            .map(Self)
     }
 }
 
@@ -1,12 +1,12 @@
 use super::*;
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
 use std::mem::size_of;
 use std::num::{NonZeroU16, TryFromIntError};
 use zerocopy::{big_endian, FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
 
 /// A 16-bit PV network layer node ID.
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, JsonSchema)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(transparent)]
 pub struct NodeID(NonZeroU16);
 impl NodeID {
@@ -38,6 +38,11 @@ impl TryFrom<u16> for NodeID {
         NonZeroU16::try_from(value).map(Self)
     }
 }
+impl From<NodeID> for u16 {
+    fn from(value: NodeID) -> Self {
+        value.0.get()
+    }
+}
 
 /// A 16-bit PV network layer node address, which could be either a `NodeID` or the broadcast
 /// address.
@@ -0,0 +1,144 @@
+//! Running an observer over a source of bytes, for programs embedding `taptap`.
+//!
+//! [`run_observe()`] reads from a source until it ends, fails, or is cancelled through a
+//! [`CancellationToken`], and then shuts the observer down cleanly. [`spawn_observe()`] does the
+//! same on a thread of its own, so that another thread can stop it without stopping the process.
+//! With the `tokio` feature, [`run_observe_async()`] does the same for async programs.
+
+use crate::gateway::physical::tcp::IdleTimeout;
+use crate::observer::Observer;
+use crate::Pipeline;
+use std::io::{ErrorKind, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// A request to stop running, shared between the thread running an observer and any other.
+///
+/// Clones share the same request, so cancelling any clone cancels them all.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request that whatever holds this token stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether stopping has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Why [`run_observe()`] stopped reading.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Stopped {
+    /// The cancellation token was cancelled.
+    Cancelled,
+    /// The source reached EOF.
+    EndOfInput,
+}
+
+/// The result of running an observer to completion.
+#[derive(Debug)]
+pub struct Finished {
+    pub stopped: Stopped,
+    /// The receiver stack, whose observer has been shut down. Its counters and state remain
+    /// available, and dropping it closes the observer's outputs.
+    pub pipeline: Pipeline,
+}
+
+/// Feed bytes from `source` through `observer` until the source ends, fails, or `cancel` is
+/// cancelled.
+///
+/// The token is checked between reads, so a source which blocks indefinitely delays cancellation
+/// until it returns. Sources which time out, such as serial ports, are read again after checking
+/// the token, but a TCP connection's [idle timeout](IdleTimeout) means the connection is lost, so
+/// it stops reading with that error. However reading stops, the observer is then [shut down](Observer::shutdown), saving
+/// its state and emitting anything which would otherwise be lost.
+pub fn run_observe(
+    mut source: impl Read,
+    observer: Observer,
+    cancel: &CancellationToken,
+) -> std::io::Result<Finished> {
+    let mut pipeline = crate::pipeline(observer);
+    let mut buffer = [0u8; 1024];
+
+    let result = loop {
+        if cancel.is_cancelled() {
+            break Ok(Stopped::Cancelled);
+        }
+        match source.read(&mut buffer) {
+            Ok(0) => break Ok(Stopped::EndOfInput),
+            Ok(n) => pipeline.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == ErrorKind::TimedOut && !IdleTimeout::is(&e) => {}
+            Err(e) => break Err(e),
+        }
+        pipeline
+            .sink_mut()
+            .sink_mut()
+            .sink_mut()
+            .save_state_if_due(Instant::now());
+    };
+
+    pipeline.sink_mut().sink_mut().sink_mut().shutdown();
+    result.map(|stopped| Finished { stopped, pipeline })
+}
+
+/// How often [`run_observe_async()`] checks for cancellation while waiting to read.
+#[cfg(feature = "tokio")]
+const CANCEL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Feed bytes from an async `source` through `observer`, like [`run_observe()`].
+///
+/// Waiting to read is interrupted periodically to check the token, so cancellation doesn't depend
+/// on the source. This requires the tokio runtime's time driver.
+#[cfg(feature = "tokio")]
+pub async fn run_observe_async(
+    mut source: impl tokio::io::AsyncRead + Unpin,
+    observer: Observer,
+    cancel: &CancellationToken,
+) -> std::io::Result<Finished> {
+    use tokio::io::AsyncReadExt;
+
+    let mut pipeline = crate::pipeline(observer);
+    let mut buffer = [0u8; 1024];
+
+    let result = loop {
+        if cancel.is_cancelled() {
+            break Ok(Stopped::Cancelled);
+        }
+        match tokio::time::timeout(CANCEL_INTERVAL, source.read(&mut buffer)).await {
+            Err(_elapsed) => {}
+            Ok(Ok(0)) => break Ok(Stopped::EndOfInput),
+            Ok(Ok(n)) => pipeline.extend_from_slice(&buffer[..n]),
+            Ok(Err(e)) if e.kind() == ErrorKind::Interrupted => {}
+            Ok(Err(e)) if e.kind() == ErrorKind::TimedOut && !IdleTimeout::is(&e) => {}
+            Ok(Err(e)) => break Err(e),
+        }
+        pipeline
+            .sink_mut()
+            .sink_mut()
+            .sink_mut()
+            .save_state_if_due(Instant::now());
+    };
+
+    pipeline.sink_mut().sink_mut().sink_mut().shutdown();
+    result.map(|stopped| Finished { stopped, pipeline })
+}
+
+/// Run [`run_observe()`] on a new thread.
+pub fn spawn_observe(
+    source: impl Read + Send + 'static,
+    observer: Observer,
+    cancel: CancellationToken,
+) -> JoinHandle<std::io::Result<Finished>> {
+    std::thread::spawn(move || run_observe(source, observer, &cancel))
+}
@@ -0,0 +1,181 @@
+//! JSON schemas for the types `taptap` writes, so that other programs can generate bindings for
+//! them and validate what they read.
+//!
+//! Each schema is self-contained, with the types it refers to, like [`Gateway`](event::Gateway)
+//! and [`Node`](event::Node), under `$defs`. A node's barcode is carried by its hardware address,
+//! a [`LongAddress`](crate::pv::LongAddress).
+//!
+//! Events are written without a tag naming their kind, as by [`Event::to_json()`](event::Event),
+//! so the event schema is any one of the event types.
+
+use crate::config::SourceConfig;
+use crate::observer::{self, event, PersistentState};
+
+/// An event as written by `taptap observe`, which is any one of the event types.
+#[derive(schemars::JsonSchema)]
+#[schemars(untagged, rename = "Event")]
+#[allow(dead_code)]
+enum Event {
+    PowerReport(event::PowerReportEvent),
+    Diagnostic(event::DiagnosticEvent),
+    DailySummary(event::DailySummaryEvent),
+    NodeTableProgress(event::NodeTableProgressEvent),
+    NodeTable(event::NodeTableEvent),
+    NodeTableChanged(event::NodeTableChangedEvent),
+    CommandTimeout(event::CommandTimeoutEvent),
+    ArrayState(event::ArrayStateEvent),
+    Alert(event::AlertEvent),
+    NetworkStatus(event::NetworkStatusEvent),
+    Broadcast(event::BroadcastEvent),
+    NodeIdentity(event::NodeIdentityEvent),
+    NodeConfiguration(Box<event::NodeConfigurationEvent>),
+    NodeDiagnostic(event::NodeDiagnosticEvent),
+    NodeGap(event::NodeGapEvent),
+    SlotClockUpdated(event::SlotClockUpdatedEvent),
+    GatewayStatus(event::GatewayStatusEvent),
+}
+
+/// A type with a published schema.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SchemaType {
+    /// Any event, as written by `taptap observe`.
+    #[default]
+    Event,
+    /// A power report event, without the enclosing event.
+    PowerReport,
+    /// An observer's state, as kept in a state file.
+    PersistentState,
+    /// A source of bus bytes.
+    SourceConfig,
+    /// An observer's configuration file.
+    ObserverConfig,
+}
+
+impl SchemaType {
+    pub const ALL: &'static [SchemaType] = &[
+        SchemaType::Event,
+        SchemaType::PowerReport,
+        SchemaType::PersistentState,
+        SchemaType::SourceConfig,
+        SchemaType::ObserverConfig,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchemaType::Event => "event",
+            SchemaType::PowerReport => "power-report",
+            SchemaType::PersistentState => "persistent-state",
+            SchemaType::SourceConfig => "source-config",
+            SchemaType::ObserverConfig => "observer-config",
+        }
+    }
+
+    /// Generate the type's schema.
+    pub fn schema(&self) -> schemars::Schema {
+        match self {
+            SchemaType::Event => schemars::schema_for!(Event),
+            SchemaType::PowerReport => schemars::schema_for!(event::PowerReportEvent),
+            SchemaType::PersistentState => schemars::schema_for!(PersistentState),
+            SchemaType::SourceConfig => schemars::schema_for!(SourceConfig),
+            SchemaType::ObserverConfig => schemars::schema_for!(observer::Config),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+#[error("unknown schema type {0:?}")]
+pub struct UnknownSchemaTypeError(String);
+
+impl std::str::FromStr for SchemaType {
+    type Err = UnknownSchemaTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|schema_type| schema_type.name() == s)
+            .ok_or_else(|| UnknownSchemaTypeError(s.into()))
+    }
+}
+
+impl std::fmt::Display for SchemaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// The names of the properties of the definition `name`, or of the root if `None`.
+    fn properties(schema: &Value, name: Option<&str>) -> Vec<String> {
+        let definition = match name {
+            Some(name) => &schema["$defs"][name],
+            None => schema,
+        };
+        let mut properties: Vec<String> = definition["properties"]
+            .as_object()
+            .unwrap_or_else(|| panic!("{:?} has no properties", name))
+            .keys()
+            .cloned()
+            .collect();
+        properties.sort();
+        properties
+    }
+
+    /// Changing any of these changes the wire format, breaking downstream consumers.
+    #[test]
+    fn wire_format() {
+        let schema = SchemaType::Event.schema().to_value();
+        assert_eq!(
+            properties(&schema, Some("Gateway")),
+            ["address", "id", "provenance"]
+        );
+        assert_eq!(
+            properties(&schema, Some("Node")),
+            ["address", "home_gateway", "id", "provenance"]
+        );
+        assert!(schema["$defs"]["LongAddress"].is_object());
+        let power_report = [
+            "current",
+            "dc_dc_duty_cycle",
+            "energy_wh_today",
+            "gateway",
+            "node",
+            "node_unverified",
+            "power_w",
+            "rssi",
+            "seconds_since_previous_report",
+            "source",
+            "temperature",
+            "timestamp",
+            "timestamp_uncertain",
+            "voltage_in",
+            "voltage_out",
+        ];
+        assert_eq!(properties(&schema, Some("PowerReportEvent")), power_report);
+
+        // Events are written untagged, so each is one of the alternatives as it stands
+        assert_eq!(schema["anyOf"].as_array().unwrap().len(), 17);
+        assert!(properties(&schema, Some("DailySummaryEvent")).contains(&"node".to_string()));
+
+        // The power report on its own is the same type
+        let schema = SchemaType::PowerReport.schema().to_value();
+        assert_eq!(properties(&schema, None), power_report);
+    }
+
+    #[test]
+    fn every_type() {
+        for schema_type in SchemaType::ALL {
+            assert_eq!(schema_type.name().parse(), Ok(*schema_type));
+            let schema = schema_type.schema().to_value();
+            assert!(schema["$schema"].is_string(), "{}", schema_type);
+        }
+
+        let schema = SchemaType::PersistentState.schema().to_value();
+        assert!(properties(&schema, None).contains(&"gateway_node_tables".to_string()));
+        assert!(schema["$defs"]["NodeTable"].is_object());
+    }
+}
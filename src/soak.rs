@@ -0,0 +1,481 @@
+//! Invariant checking over long runs of the full pipeline.
+//!
+//! A [`Soak`] watches a [`Pipeline`] as data flows through it, checking properties which should
+//! hold no matter what arrives on the bus:
+//!
+//! * each node's power reports are timestamped in order, give or take a tolerance
+//! * no subsystem's memory keeps growing, as judged by sampling its [`MemoryReport`]
+//! * each layer's counters are consistent with those of the layer below
+//! * nothing panics
+//!
+//! Violations are collected into a [`Report`] rather than stopping the run, so that a single run
+//! over a long capture finds as many problems as possible.
+
+use crate::gateway::link::GatewayID;
+use crate::memory::MemoryReport;
+use crate::observer::event::Event;
+use crate::pv::NodeID;
+use crate::{Counters, Pipeline};
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+/// How far a node's power report timestamps may go backwards before it counts as a violation.
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(10);
+
+/// How often memory and counters are sampled, in capture time.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The fewest samples from which memory growth is judged. Subsystems fill up as gateways and
+/// nodes are learned, so growth early in a run is expected.
+const MIN_MEMORY_SAMPLES: u64 = 16;
+
+/// The factor by which a subsystem must grow over the second half of a run to count as growing
+/// without bound.
+const MEMORY_GROWTH_FACTOR: f64 = 1.5;
+
+/// The number of bytes by which a subsystem must also grow, so that small subsystems can settle.
+const MEMORY_GROWTH_SLACK: usize = 64 * 1024;
+
+/// The number of violations described in full. Further violations are only counted.
+const MAX_VIOLATIONS: usize = 100;
+
+/// A property checked by a [`Soak`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Invariant {
+    /// A node's power report was timestamped earlier than its previous report, by more than the
+    /// tolerance.
+    EventOrder,
+    /// A subsystem's memory grew substantially over the second half of the run.
+    MemoryGrowth,
+    /// A layer counted more than the layer below it handed over.
+    Counters,
+    /// The pipeline panicked.
+    Panic,
+}
+
+impl Invariant {
+    /// Every invariant.
+    pub const ALL: [Invariant; 4] = [
+        Invariant::EventOrder,
+        Invariant::MemoryGrowth,
+        Invariant::Counters,
+        Invariant::Panic,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Invariant::EventOrder => "event_order",
+            Invariant::MemoryGrowth => "memory_growth",
+            Invariant::Counters => "counters",
+            Invariant::Panic => "panic",
+        }
+    }
+}
+
+impl std::fmt::Display for Invariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A violation of an [`Invariant`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Violation {
+    pub invariant: Invariant,
+    /// The capture time at which the violation was detected.
+    pub at: SystemTime,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            DateTime::<Local>::from(self.at),
+            self.invariant,
+            self.message
+        )
+    }
+}
+
+/// An invariant checker for a pipeline ending in an [`Observer`](crate::observer::Observer).
+///
+/// Feed it every event the observer emits with [`event()`](Self::event), and call
+/// [`sample()`](Self::sample) after handing each chunk of data to the pipeline.
+#[derive(Debug, Clone)]
+pub struct Soak {
+    tolerance: Duration,
+    last_timestamps: BTreeMap<(GatewayID, NodeID), DateTime<Local>>,
+    power_report_events: u64,
+    events: u64,
+    last_sample: Option<SystemTime>,
+    samples: u64,
+    // The memory reports taken at the two most recent power-of-two samples
+    checkpoints: [Option<MemoryReport>; 2],
+    latest: Option<MemoryReport>,
+    violations: Vec<Violation>,
+    counts: BTreeMap<Invariant, u64>,
+}
+
+impl Default for Soak {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOLERANCE)
+    }
+}
+
+impl Soak {
+    /// Create a checker which tolerates power report timestamps going backwards by `tolerance`.
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            tolerance,
+            last_timestamps: Default::default(),
+            power_report_events: 0,
+            events: 0,
+            last_sample: None,
+            samples: 0,
+            checkpoints: Default::default(),
+            latest: None,
+            violations: Vec::new(),
+            counts: Default::default(),
+        }
+    }
+
+    /// Check an event emitted by the observer at capture time `at`.
+    pub fn event(&mut self, event: &Event, at: SystemTime) {
+        self.events += 1;
+        let Event::PowerReport(report) = event else {
+            return;
+        };
+        self.power_report_events += 1;
+
+        let key = (report.gateway.id, report.node.id);
+        let Some(last) = self.last_timestamps.insert(key, report.timestamp) else {
+            return;
+        };
+        let Ok(backwards) = (last - report.timestamp).to_std() else {
+            return;
+        };
+        if backwards > self.tolerance {
+            self.violation(
+                Invariant::EventOrder,
+                at,
+                format!(
+                    "node {:?} on gateway {:?} reported at {}, {:.3}s before its previous report",
+                    report.node.id,
+                    report.gateway.id,
+                    report.timestamp,
+                    backwards.as_secs_f64()
+                ),
+            );
+        }
+    }
+
+    /// Sample the pipeline's memory and counters at capture time `at`, if a sample is due.
+    pub fn sample(&mut self, rx: &Pipeline, at: SystemTime) {
+        // Sample again after the capture clock steps backwards, rather than waiting it out
+        if let Some(last) = self.last_sample {
+            if at >= last && at < last + SAMPLE_INTERVAL {
+                return;
+            }
+        }
+        self.sample_now(rx, at);
+    }
+
+    /// Sample the pipeline's memory and counters at capture time `at`.
+    pub fn sample_now(&mut self, rx: &Pipeline, at: SystemTime) {
+        self.last_sample = Some(at);
+        self.samples += 1;
+
+        self.check_counters(&Counters::snapshot(rx), at);
+
+        let memory = MemoryReport::snapshot(rx);
+        if self.samples.is_power_of_two() {
+            self.checkpoints.swap(0, 1);
+            self.checkpoints[1] = Some(memory.clone());
+        }
+        self.latest = Some(memory);
+    }
+
+    /// Note that the pipeline panicked at capture time `at`.
+    pub fn panicked(&mut self, payload: &(dyn std::any::Any + Send), at: SystemTime) {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "(non-string panic payload)".into());
+        self.violation(Invariant::Panic, at, message);
+    }
+
+    fn check_counters(&mut self, counters: &Counters, at: SystemTime) {
+        let Counters {
+            link,
+            transport,
+            application,
+        } = counters;
+
+        if transport.frames > link.frames {
+            self.violation(
+                Invariant::Counters,
+                at,
+                format!(
+                    "transport layer handled {} frames, but link layer only received {}",
+                    transport.frames, link.frames
+                ),
+            );
+        }
+
+        let packets = application.invalid_received_packet_node_ids
+            + application.power_reports
+            + application.invalid_power_reports
+            + application.topology_reports
+            + application.invalid_topology_reports
+            + application.string_responses
+            + application.invalid_string_responses;
+        if packets > transport.receive_packets {
+            self.violation(
+                Invariant::Counters,
+                at,
+                format!(
+                    "application layer handled {} packets, but transport layer only received {}",
+                    packets, transport.receive_packets
+                ),
+            );
+        }
+
+        if self.power_report_events > application.power_reports {
+            self.violation(
+                Invariant::Counters,
+                at,
+                format!(
+                    "observer emitted {} power reports, but application layer only decoded {}",
+                    self.power_report_events, application.power_reports
+                ),
+            );
+        }
+    }
+
+    fn check_memory_growth(
+        &mut self,
+        baseline: &MemoryReport,
+        latest: &MemoryReport,
+        at: SystemTime,
+    ) {
+        for (subsystem, &bytes) in &latest.0 {
+            let before = baseline.0.get(subsystem).copied().unwrap_or_default();
+            if bytes as f64 > before as f64 * MEMORY_GROWTH_FACTOR
+                && bytes - before > MEMORY_GROWTH_SLACK
+            {
+                self.violation(
+                    Invariant::MemoryGrowth,
+                    at,
+                    format!(
+                        "{} grew from {} to {} bytes over the second half of the run",
+                        subsystem, before, bytes
+                    ),
+                );
+            }
+        }
+    }
+
+    fn violation(&mut self, invariant: Invariant, at: SystemTime, message: String) {
+        *self.counts.entry(invariant).or_default() += 1;
+        if self.violations.len() < MAX_VIOLATIONS {
+            self.violations.push(Violation {
+                invariant,
+                at,
+                message,
+            });
+        }
+    }
+
+    /// Finish the run, taking a final sample at capture time `at` and judging memory growth.
+    pub fn finish(mut self, rx: &Pipeline, at: SystemTime) -> Report {
+        self.sample_now(rx, at);
+
+        // Compare the final sample to one from no later than halfway through the run
+        if let (true, Some(baseline), Some(latest)) = (
+            self.samples >= MIN_MEMORY_SAMPLES,
+            self.checkpoints[0].clone(),
+            self.latest.clone(),
+        ) {
+            self.check_memory_growth(&baseline, &latest, at);
+        }
+
+        Report {
+            events: self.events,
+            samples: self.samples,
+            memory: self.latest.unwrap_or_default(),
+            violations: self.violations,
+            counts: self.counts,
+        }
+    }
+}
+
+/// The outcome of a [`Soak`].
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The number of events checked.
+    pub events: u64,
+    /// The number of times memory and counters were sampled.
+    pub samples: u64,
+    /// The memory held by each subsystem at the end of the run.
+    pub memory: MemoryReport,
+    /// The first violations found, in the order they were found.
+    pub violations: Vec<Violation>,
+    /// The number of violations of each invariant, including any not described in `violations`.
+    pub counts: BTreeMap<Invariant, u64>,
+}
+
+impl Report {
+    /// Whether every invariant held.
+    pub fn passed(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Checked {} events over {} samples",
+            self.events, self.samples
+        )?;
+        for violation in &self.violations {
+            writeln!(f, "{}", violation)?;
+        }
+        let total: u64 = self.counts.values().sum();
+        if total > self.violations.len() as u64 {
+            writeln!(
+                f,
+                "... and {} more violations",
+                total - self.violations.len() as u64
+            )?;
+        }
+        for invariant in Invariant::ALL {
+            match self.counts.get(&invariant) {
+                Some(count) => writeln!(
+                    f,
+                    "{:<14} FAILED ({} violations)",
+                    invariant.as_str(),
+                    count
+                )?,
+                None => writeln!(f, "{:<14} ok", invariant.as_str())?,
+            }
+        }
+        write!(
+            f,
+            "Approximate memory use at the end, in bytes:\n{}",
+            self.memory
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::event::{Gateway, Node, PowerReportEvent};
+    use crate::observer::Observer;
+    use crate::pv::physical::RSSI;
+
+    fn t(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200 + seconds)
+    }
+
+    fn power_report(node: u16, timestamp: SystemTime) -> Event {
+        Event::PowerReport(PowerReportEvent {
+            gateway: Gateway {
+                id: GatewayID::try_from(0x1201).unwrap(),
+                address: None,
+                provenance: None,
+            },
+            node: Node {
+                id: NodeID::try_from(node).unwrap(),
+                address: None,
+                provenance: None,
+                home_gateway: None,
+            },
+            timestamp: timestamp.into(),
+            voltage_in: 30.0,
+            voltage_out: 30.0,
+            current: 6.0,
+            power_w: 180.0,
+            dc_dc_duty_cycle: 1.0,
+            temperature: 25.0,
+            rssi: RSSI(120),
+            node_unverified: false,
+            timestamp_uncertain: false,
+            energy_wh_today: None,
+            seconds_since_previous_report: None,
+            source: None,
+        })
+    }
+
+    #[test]
+    fn event_order() {
+        let mut soak = Soak::default();
+        soak.event(&power_report(2, t(100)), t(100));
+        // Another node's clock is independent
+        soak.event(&power_report(3, t(50)), t(100));
+        // Small steps backwards are tolerated
+        soak.event(&power_report(2, t(95)), t(101));
+        soak.event(&power_report(2, t(80)), t(102));
+        assert_eq!(soak.counts.get(&Invariant::EventOrder), Some(&1));
+        assert_eq!(soak.violations[0].at, t(102));
+        assert!(soak.violations[0].message.contains("15.000s"));
+    }
+
+    #[test]
+    fn counters() {
+        let mut soak = Soak::default();
+        let mut counters = Counters::default();
+        counters.link.frames = 10;
+        counters.transport.frames = 10;
+        counters.transport.receive_packets = 3;
+        counters.application.power_reports = 3;
+        soak.check_counters(&counters, t(0));
+        assert!(soak.violations.is_empty());
+
+        counters.transport.frames = 11;
+        counters.application.invalid_received_packet_node_ids = 1;
+        soak.check_counters(&counters, t(0));
+        assert_eq!(soak.counts.get(&Invariant::Counters), Some(&2));
+    }
+
+    #[test]
+    fn memory_growth() {
+        let mut soak = Soak::default();
+        let report =
+            |entries: &[(&'static str, usize)]| MemoryReport(entries.iter().copied().collect());
+        let baseline = report(&[("settled", 1000), ("small", 100), ("leaking", 100_000)]);
+        let latest = report(&[("settled", 1100), ("small", 1000), ("leaking", 300_000)]);
+        soak.check_memory_growth(&baseline, &latest, t(0));
+        assert_eq!(soak.counts.get(&Invariant::MemoryGrowth), Some(&1));
+        assert!(soak.violations[0].message.starts_with("leaking grew"));
+    }
+
+    #[test]
+    fn panic() {
+        let mut soak = Soak::default();
+        let payload = std::panic::catch_unwind(|| panic!("oh no {}", 42)).unwrap_err();
+        soak.panicked(payload.as_ref(), t(0));
+        assert_eq!(soak.violations[0].invariant, Invariant::Panic);
+        assert_eq!(soak.violations[0].message, "oh no 42");
+    }
+
+    #[test]
+    fn report() {
+        let rx = crate::pipeline(Observer::default());
+        let mut soak = Soak::default();
+        for minute in 0..100 {
+            soak.sample(&rx, t(minute * 60));
+            // Samples are taken at most once a minute
+            soak.sample(&rx, t(minute * 60 + 1));
+        }
+        let report = soak.finish(&rx, t(6000));
+        assert_eq!(report.samples, 101);
+        assert!(report.passed());
+        assert!(report.to_string().contains("memory_growth  ok"));
+    }
+}
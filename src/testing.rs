@@ -0,0 +1,6 @@
+//! Utilities for testing code which uses this crate, and for testing this crate itself.
+
+mod mock;
+pub use mock::{MockConnection, MockGateway, WriteLog};
+
+pub mod roundtrip;
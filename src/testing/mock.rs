@@ -0,0 +1,292 @@
+//! Scriptable [`physical::Connection`]s for exercising code which reads from or writes to a gateway
+//! bus.
+
+use crate::gateway::link::{self, Frame};
+use crate::gateway::physical;
+use crate::observer::clock::ManualClock;
+use crate::testing::roundtrip::Stream;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// A `Connection` which returns scripted data and records what is written to it.
+///
+/// Each call to `read()` consumes the script until it produces data or an error, returning at
+/// most one scripted chunk at a time. Delays in the script advance a [`ManualClock`], so code
+/// under test observes the passage of time without actually waiting. Once the script is
+/// exhausted, `read()` indicates EOF.
+#[derive(Debug, Default)]
+pub struct MockConnection {
+    script: VecDeque<Step>,
+    clock: Option<ManualClock>,
+    writes: WriteLog,
+    readonly: bool,
+}
+
+#[derive(Debug)]
+enum Step {
+    Data(Vec<u8>),
+    Delay(Duration),
+    SetTime(SystemTime),
+    Error(ErrorKind),
+}
+
+impl MockConnection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Script a connection which delivers a `Stream`, setting `clock` to the time at which each
+    /// part of the stream appeared on the bus.
+    pub fn from_stream(stream: &Stream, clock: ManualClock) -> Self {
+        let mut conn = Self::new().with_clock(clock);
+        let mut times = stream.times.iter().peekable();
+        while let Some((start, time)) = times.next() {
+            let end = times
+                .peek()
+                .map(|(start, _)| *start)
+                .unwrap_or(stream.bytes.len());
+            conn.script.push_back(Step::SetTime(*time));
+            conn.script
+                .push_back(Step::Data(stream.bytes[*start..end].to_vec()));
+        }
+        conn
+    }
+
+    /// Advance `clock` whenever the script calls for a delay.
+    pub fn with_clock(mut self, clock: ManualClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Reject writes, as a read-only connection does.
+    pub fn readonly(mut self) -> Self {
+        self.readonly = true;
+        self
+    }
+
+    /// Script a chunk of data to be read.
+    pub fn then_read(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.script.push_back(Step::Data(data.into()));
+        self
+    }
+
+    /// Script a delay before the next chunk.
+    pub fn then_delay(mut self, duration: Duration) -> Self {
+        self.script.push_back(Step::Delay(duration));
+        self
+    }
+
+    /// Script a read error.
+    pub fn then_error(mut self, kind: ErrorKind) -> Self {
+        self.script.push_back(Step::Error(kind));
+        self
+    }
+
+    /// Obtain a handle to the bytes written to this connection.
+    ///
+    /// The handle remains usable after the connection is boxed or moved.
+    pub fn write_log(&self) -> WriteLog {
+        self.writes.clone()
+    }
+}
+
+impl physical::Connection for MockConnection {}
+
+impl Read for MockConnection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some(step) = self.script.pop_front() {
+            match step {
+                Step::Data(mut data) => {
+                    let n = data.len().min(buf.len());
+                    buf[..n].copy_from_slice(&data[..n]);
+                    if n < data.len() {
+                        self.script.push_front(Step::Data(data.split_off(n)));
+                    }
+                    if n > 0 || buf.is_empty() {
+                        return Ok(n);
+                    }
+                }
+                Step::Delay(duration) => {
+                    if let Some(clock) = &self.clock {
+                        clock.advance(duration);
+                    }
+                }
+                Step::SetTime(time) => {
+                    if let Some(clock) = &self.clock {
+                        clock.set(time);
+                    }
+                }
+                Step::Error(kind) => return Err(kind.into()),
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+impl Write for MockConnection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.readonly {
+            Err(ErrorKind::Unsupported.into())
+        } else {
+            self.writes.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A function answering a frame written to a [`MockGateway`].
+type Answer = dyn FnMut(&Frame) -> Option<Frame> + Send;
+
+/// A `Connection` which answers the frames written to it, as a gateway would.
+///
+/// Each frame written is passed to a function, and whatever frame it returns is queued to be read.
+/// When nothing is queued, `read()` waits briefly and then times out, as a serial port does.
+pub struct MockGateway {
+    receiver: link::Receiver<Vec<Frame>>,
+    answer: Box<Answer>,
+    pending: VecDeque<u8>,
+    writes: WriteLog,
+}
+
+impl MockGateway {
+    pub fn new(answer: impl FnMut(&Frame) -> Option<Frame> + Send + 'static) -> Self {
+        Self {
+            receiver: link::Receiver::new(Vec::new()),
+            answer: Box::new(answer),
+            pending: Default::default(),
+            writes: Default::default(),
+        }
+    }
+
+    /// Obtain a handle to the bytes written to this connection.
+    pub fn write_log(&self) -> WriteLog {
+        self.writes.clone()
+    }
+}
+
+impl std::fmt::Debug for MockGateway {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockGateway")
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl physical::Connection for MockGateway {}
+
+impl Read for MockGateway {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+            return Err(ErrorKind::TimedOut.into());
+        }
+        let n = self.pending.len().min(buf.len());
+        for (byte, pending) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *byte = pending;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockGateway {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes.write_all(buf)?;
+        self.receiver.extend_from_slice(buf);
+        for frame in std::mem::take(self.receiver.sink_mut()) {
+            if let Some(response) = (self.answer)(&frame) {
+                self.pending.extend(response.encode());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A shared record of the bytes written to a [`MockConnection`], or to itself.
+#[derive(Debug, Clone, Default)]
+pub struct WriteLog(Arc<Mutex<Vec<u8>>>);
+
+impl Write for WriteLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteLog {
+    /// The bytes written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::clock::Clock;
+
+    #[test]
+    fn scripted_reads() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1723500000);
+        let clock = ManualClock::new(t);
+        let mut conn = MockConnection::new()
+            .with_clock(clock.clone())
+            .then_read([1, 2, 3, 4, 5])
+            .then_delay(Duration::from_secs(5))
+            .then_read([6])
+            .then_error(ErrorKind::ConnectionReset)
+            .then_read([7]);
+
+        // Chunks are split across small buffers
+        let mut buf = [0u8; 3];
+        assert_eq!(conn.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf, [1, 2, 3]);
+        assert_eq!(conn.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..2], [4, 5]);
+        assert_eq!(clock.now(), t);
+
+        // Delays advance the clock
+        assert_eq!(conn.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 6);
+        assert_eq!(clock.now(), t + Duration::from_secs(5));
+
+        // Errors are returned once, after which reading continues
+        assert_eq!(
+            conn.read(&mut buf).unwrap_err().kind(),
+            ErrorKind::ConnectionReset
+        );
+        assert_eq!(conn.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 7);
+
+        // The end of the script is EOF
+        assert_eq!(conn.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn writes() {
+        let mut conn = MockConnection::new();
+        let log = conn.write_log();
+        conn.write_all(&[1, 2]).unwrap();
+        conn.write_all(&[3]).unwrap();
+        conn.flush().unwrap();
+        assert_eq!(log.contents(), vec![1, 2, 3]);
+
+        let mut conn: Box<dyn physical::Connection> = Box::new(MockConnection::new().readonly());
+        assert_eq!(conn.write(&[1]).unwrap_err().kind(), ErrorKind::Unsupported);
+        conn.flush().unwrap();
+    }
+}
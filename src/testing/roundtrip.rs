@@ -0,0 +1,878 @@
+//! A round-trip harness for the receiver stack.
+//!
+//! A [`Scenario`] describes activity on a controller <-> gateway network: an enumeration, a walk
+//! of each gateway's node table, a series of power reports, and string responses from nodes. The
+//! harness encodes the scenario
+//! into the frames which would appear on the bus, interleaves them into a byte stream, runs it
+//! through the full `link` → `transport` → `application` → `Observer` stack, and compares the
+//! emitted events to those implied by the scenario.
+//!
+//! ```text
+//! Scenario ──▶ frames ──▶ bytes ──▶ link ──▶ transport ──▶ application ──▶ Observer
+//!     │                                                                       │
+//!     ▼                                                                       ▼
+//! expected_events() ◀─────────────────── assert_eq! ─────────────────────▶ events
+//! ```
+
+use crate::gateway::link::{self, Address, Frame, GatewayID};
+use crate::gateway::transport::{
+    self, CommandRequest, CommandResponse, CommandSequenceNumber, EnumerationStartRequest,
+    IdentifyResponse, ReceiveRequest, ReceiveResponse,
+};
+use crate::observer::clock::ManualClock;
+use crate::observer::config::DuplicateAddresses;
+use crate::observer::event::{self, DiagnosticEvent, Event, PowerReportEvent};
+use crate::observer::rate_limit::RateLimits;
+use crate::observer::snapshot::SystemSnapshot;
+use crate::observer::{diagnostic, Config, EventSink, Observer};
+use crate::pv::application::{
+    NodeTableRequest, NodeTableResponse, NodeTableResponseEntry, PacketType, U12Pair,
+};
+use crate::pv::link::DSN;
+use crate::pv::network::{NodeAddress, ReceivedPacketHeader};
+use crate::pv::physical::RSSI;
+use crate::pv::{self, LongAddress, NodeID, ShortAddress, SlotCounter};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use zerocopy::IntoBytes;
+
+/// The gateway ID used while enumerating.
+const ENUMERATION_GATEWAY_ID: u16 = 0x1235;
+
+/// The number of entries in each node table page.
+const NODE_TABLE_PAGE_SIZE: usize = 12;
+
+/// Bytes appearing on the bus between frames, used in turn.
+const GAPS: &[&[u8]] = &[&[], &[0x00], &[0xFF, 0xFF], &[0x00, 0x00, 0x00]];
+
+/// The sizes of the chunks in which bytes are delivered to the receiver, used in turn.
+const CHUNK_SIZES: &[usize] = &[1, 3, 17, 64, 255, 1024];
+
+const NOMINAL_DURATION_PER_SLOT: Duration = Duration::from_millis(5);
+
+/// A description of activity on a controller <-> gateway network.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    /// The time at which the scenario begins.
+    pub start: SystemTime,
+    /// Whether the controller enumerates the gateways before anything else happens.
+    pub enumerate: bool,
+    /// Whether the controller walks each gateway's node table before any power reports arrive.
+    pub walk_node_tables: bool,
+    pub gateways: Vec<Gateway>,
+    /// Power reports, in the order in which they are received.
+    ///
+    /// Each power report is received in its own exchange, which occurs at the time indicated by
+    /// its slot counter. Slot counters must therefore advance, and successive reports must be
+    /// less than four minutes apart.
+    pub power_reports: Vec<PowerReport>,
+    /// String responses from nodes, in the order in which they are received, after every power
+    /// report.
+    ///
+    /// Like power reports, each is received in its own exchange at the time indicated by its slot
+    /// counter.
+    pub string_responses: Vec<StringResponse>,
+    /// How the observer handles nodes appearing under more than one gateway.
+    ///
+    /// [`expected_events()`](Self::expected_events) assumes the default, under which every report
+    /// is emitted.
+    pub duplicate_addresses: DuplicateAddresses,
+}
+
+#[derive(Debug, Clone)]
+pub struct Gateway {
+    pub id: GatewayID,
+    pub address: LongAddress,
+    pub version: String,
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Node {
+    pub id: NodeID,
+    pub address: LongAddress,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PowerReport {
+    pub gateway_id: GatewayID,
+    pub node_id: NodeID,
+    pub slot_counter: SlotCounter,
+    pub measurement: Measurement,
+}
+
+/// A node's response to a string request, such as `"Mnode Version K8.0120 (2D)\r"`.
+#[derive(Debug, Clone)]
+pub struct StringResponse {
+    pub gateway_id: GatewayID,
+    pub node_id: NodeID,
+    pub slot_counter: SlotCounter,
+    pub response: String,
+}
+
+/// What a node has said about itself, following one of its string responses.
+struct NodeIdentity {
+    /// The index of the string response.
+    index: usize,
+    time: SystemTime,
+    address: LongAddress,
+    firmware: Option<String>,
+    responses: BTreeMap<String, String>,
+    /// Whether the response replaced a different firmware version.
+    firmware_changed: bool,
+}
+
+/// Measurements in engineering units.
+///
+/// Measurements are quantized to the resolution of the wire format when encoded, so expected
+/// events reflect the quantized values.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Measurement {
+    pub voltage_in: f64,
+    pub voltage_out: f64,
+    pub current: f64,
+    pub dc_dc_duty_cycle: f64,
+    pub temperature: f64,
+    pub rssi: RSSI,
+}
+
+/// A byte stream, annotated with the times at which its contents appeared on the bus.
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    pub bytes: Vec<u8>,
+    /// `(offset, time)` pairs, indicating that the bytes starting at `offset` appeared at `time`.
+    pub times: Vec<(usize, SystemTime)>,
+}
+
+/// The result of running a scenario.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub events: Vec<Event>,
+    pub diagnostics: Vec<DiagnosticEvent>,
+    pub counters: crate::Counters,
+    /// What the observer believed about the system once the scenario ended.
+    pub snapshot: SystemSnapshot,
+}
+
+impl Scenario {
+    /// Create an empty scenario which enumerates and walks node tables.
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            start,
+            enumerate: true,
+            walk_node_tables: true,
+            gateways: Vec::new(),
+            power_reports: Vec::new(),
+            string_responses: Vec::new(),
+            duplicate_addresses: Default::default(),
+        }
+    }
+
+    /// The times at which each power report is received.
+    fn power_report_times(&self) -> Vec<SystemTime> {
+        let mut times = self.receive_times();
+        times.truncate(self.power_reports.len());
+        times
+    }
+
+    /// The times at which each string response is received.
+    fn string_response_times(&self) -> Vec<SystemTime> {
+        self.receive_times().split_off(self.power_reports.len())
+    }
+
+    /// The times at which each power report and then each string response is received.
+    fn receive_times(&self) -> Vec<SystemTime> {
+        let mut time = self.start;
+        let mut last_slot_counter = None;
+        self.power_reports
+            .iter()
+            .map(|report| report.slot_counter)
+            .chain(
+                self.string_responses
+                    .iter()
+                    .map(|response| response.slot_counter),
+            )
+            .map(|slot_counter| {
+                if let Some(last) = last_slot_counter.replace(slot_counter) {
+                    if slot_counter != last {
+                        let slots = slot_counter.slots_since(&last).expect("valid slot counter");
+                        time += NOMINAL_DURATION_PER_SLOT * slots as u32;
+                    }
+                }
+                time
+            })
+            .collect()
+    }
+
+    /// The frames exchanged during this scenario, along with the time at which each appears.
+    pub fn frames(&self) -> Vec<(SystemTime, Frame)> {
+        let mut frames = Vec::new();
+
+        if self.enumerate {
+            self.enumeration_frames(&mut frames);
+        }
+
+        if self.walk_node_tables {
+            self.node_table_frames(&mut frames);
+        }
+
+        self.receive_frames(&mut frames);
+
+        frames
+    }
+
+    fn enumeration_frames(&self, frames: &mut Vec<(SystemTime, Frame)>) {
+        let Some(first) = self.gateways.first() else {
+            return;
+        };
+
+        let enumeration_gateway_id = GatewayID::try_from(ENUMERATION_GATEWAY_ID).unwrap();
+        let mut push = |address, frame_type, payload: &[u8]| {
+            frames.push((
+                self.start,
+                Frame {
+                    address,
+                    frame_type,
+                    payload: payload.to_vec(),
+                },
+            ));
+        };
+
+        push(
+            Address::To(GatewayID::ZERO),
+            link::Type::ENUMERATION_START_REQUEST,
+            EnumerationStartRequest::new(enumeration_gateway_id).as_bytes(),
+        );
+        push(
+            Address::From(GatewayID::ZERO),
+            link::Type::ENUMERATION_START_RESPONSE,
+            &[],
+        );
+
+        for gateway in &self.gateways {
+            // Discover the gateway at the enumeration address, and assign its ID
+            push(
+                Address::To(enumeration_gateway_id),
+                link::Type::ENUMERATION_REQUEST,
+                &[],
+            );
+            push(
+                Address::From(enumeration_gateway_id),
+                link::Type::ENUMERATION_RESPONSE,
+                IdentifyResponse {
+                    pv_long_address: gateway.address,
+                    gateway_address: Address::To(enumeration_gateway_id).into(),
+                }
+                .as_bytes(),
+            );
+
+            let mut assign = gateway.address.as_bytes().to_vec();
+            assign.extend_from_slice(&<[u8; 2]>::from(Address::To(gateway.id)));
+            push(
+                Address::To(enumeration_gateway_id),
+                link::Type::ASSIGN_GATEWAY_ID_REQUEST,
+                &assign,
+            );
+            push(
+                Address::From(enumeration_gateway_id),
+                link::Type::ASSIGN_GATEWAY_ID_RESPONSE,
+                &[],
+            );
+        }
+
+        for gateway in &self.gateways {
+            // Confirm the gateway's identity at its assigned ID, and ask its version
+            push(Address::To(gateway.id), link::Type::IDENTIFY_REQUEST, &[]);
+            push(
+                Address::From(gateway.id),
+                link::Type::IDENTIFY_RESPONSE,
+                IdentifyResponse {
+                    pv_long_address: gateway.address,
+                    gateway_address: Address::To(gateway.id).into(),
+                }
+                .as_bytes(),
+            );
+            push(Address::To(gateway.id), link::Type::VERSION_REQUEST, &[]);
+            push(
+                Address::From(gateway.id),
+                link::Type::VERSION_RESPONSE,
+                gateway.version.as_bytes(),
+            );
+        }
+
+        push(
+            Address::To(first.id),
+            link::Type::ENUMERATION_END_REQUEST,
+            &[],
+        );
+        push(
+            Address::From(first.id),
+            link::Type::ENUMERATION_END_RESPONSE,
+            &[],
+        );
+    }
+
+    fn node_table_frames(&self, frames: &mut Vec<(SystemTime, Frame)>) {
+        for gateway in &self.gateways {
+            let mut nodes = gateway.nodes.clone();
+            nodes.sort_by_key(|node| node.id);
+            let entries: Vec<NodeTableResponseEntry> = nodes
+                .iter()
+                .map(|node| NodeTableResponseEntry {
+                    long_address: node.address,
+                    node_id: node.id.into(),
+                })
+                .collect();
+
+            // Walk the table a page at a time, ending with an empty page
+            let mut pages: Vec<&[NodeTableResponseEntry]> =
+                entries.chunks(NODE_TABLE_PAGE_SIZE).collect();
+            pages.push(&[]);
+
+            let mut start_at = NodeAddress::ZERO;
+            for (sequence_number, page) in pages.into_iter().enumerate() {
+                let sequence_number = CommandSequenceNumber(sequence_number as u8 + 1);
+
+                let mut request = CommandRequest {
+                    unknown: [0x00, 0x01, 0x00],
+                    packet_type: PacketType::NODE_TABLE_REQUEST,
+                    sequence_number,
+                }
+                .as_bytes()
+                .to_vec();
+                request.extend_from_slice(NodeTableRequest { start_at }.as_bytes());
+
+                let mut response = CommandResponse {
+                    unknown_1: 0x00,
+                    tx_buffers_free: 0x0E,
+                    unknown_2: 0x00,
+                    packet_type: PacketType::NODE_TABLE_RESPONSE,
+                    command_sequence_number: sequence_number,
+                }
+                .as_bytes()
+                .to_vec();
+                response.extend_from_slice(&NodeTableResponse::encode(page));
+
+                frames.push((
+                    self.start,
+                    Frame {
+                        address: Address::To(gateway.id),
+                        frame_type: link::Type::COMMAND_REQUEST,
+                        payload: request,
+                    },
+                ));
+                frames.push((
+                    self.start,
+                    Frame {
+                        address: Address::From(gateway.id),
+                        frame_type: link::Type::COMMAND_RESPONSE,
+                        payload: response,
+                    },
+                ));
+
+                if let Some(last) = page.last() {
+                    start_at = NodeID::try_from(last.node_id)
+                        .ok()
+                        .and_then(|id| id.successor())
+                        .into();
+                }
+            }
+        }
+    }
+
+    fn receive_frames(&self, frames: &mut Vec<(SystemTime, Frame)>) {
+        let mut packet_numbers = std::collections::BTreeMap::new();
+        let mut dsns = std::collections::BTreeMap::new();
+
+        let power_reports = self.power_reports.iter().map(|report| {
+            (
+                report.gateway_id,
+                report.node_id,
+                report.slot_counter,
+                PacketType::POWER_REPORT,
+                report
+                    .measurement
+                    .encode(report.slot_counter)
+                    .as_bytes()
+                    .to_vec(),
+            )
+        });
+        let string_responses = self.string_responses.iter().map(|response| {
+            (
+                response.gateway_id,
+                response.node_id,
+                response.slot_counter,
+                PacketType::STRING_RESPONSE,
+                response.response.as_bytes().to_vec(),
+            )
+        });
+
+        for (i, ((gateway_id, node_id, slot_counter, packet_type, data), time)) in power_reports
+            .chain(string_responses)
+            .zip(self.receive_times())
+            .enumerate()
+        {
+            let packet_number: &mut u16 = packet_numbers.entry(gateway_id).or_insert(0x0100);
+            *packet_number = packet_number.wrapping_add(1);
+            let [high, _] = packet_number.to_be_bytes();
+
+            let request = ReceiveRequest::new(*packet_number);
+
+            // Each node numbers its own packets
+            let dsn: &mut DSN = dsns.entry((gateway_id, node_id)).or_insert(DSN(0));
+            *dsn = *dsn + 1;
+
+            let mut packet = ReceivedPacketHeader {
+                packet_type,
+                node_address: node_id.into(),
+                short_address: ShortAddress(0x0000.into()),
+                dsn: *dsn,
+                data_length: data.len() as u8,
+            }
+            .as_bytes()
+            .to_vec();
+            packet.extend_from_slice(&data);
+
+            // Alternate between full and abbreviated packet numbers
+            let response = ReceiveResponse {
+                rx_buffers_used: Some(0x00),
+                tx_buffers_free: Some(0x0E),
+                unknown_a: None,
+                unknown_b: None,
+                packet_number_high: (i % 2 == 0).then_some(high),
+                packet_number: *packet_number,
+                slot_counter,
+            };
+
+            frames.push((
+                time,
+                Frame {
+                    address: Address::To(gateway_id),
+                    frame_type: link::Type::RECEIVE_REQUEST,
+                    payload: request.as_bytes().to_vec(),
+                },
+            ));
+            frames.push((
+                time,
+                Frame {
+                    address: Address::From(gateway_id),
+                    frame_type: link::Type::RECEIVE_RESPONSE,
+                    payload: response.encode(&packet),
+                },
+            ));
+        }
+    }
+
+    /// Encode this scenario into a byte stream, with gaps between frames.
+    pub fn encode(&self) -> Stream {
+        let mut stream = Stream::default();
+        let mut gaps = GAPS.iter().cycle();
+
+        for (time, frame) in self.frames() {
+            if stream.times.last().map(|(_, t)| *t) != Some(time) {
+                stream.times.push((stream.bytes.len(), time));
+            }
+            stream.bytes.extend_from_slice(gaps.next().unwrap());
+            stream.bytes.extend_from_slice(&frame.encode());
+        }
+
+        stream
+    }
+
+    /// The events which an observer should emit in response to this scenario.
+    pub fn expected_events(&self) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        if self.walk_node_tables {
+            for gateway in &self.gateways {
+                let event_gateway = event::Gateway {
+                    id: gateway.id,
+                    address: Some(gateway.address).filter(|_| self.enumerate),
+                    provenance: None,
+                };
+
+                let mut nodes = gateway.nodes.clone();
+                nodes.sort_by_key(|node| node.id);
+
+                let mut entries_so_far = 0;
+                let mut start_at = NodeAddress::ZERO;
+                for page in nodes.chunks(NODE_TABLE_PAGE_SIZE) {
+                    entries_so_far += page.len();
+                    events.push(Event::NodeTableProgress(event::NodeTableProgressEvent {
+                        gateway: event_gateway,
+                        timestamp: self.start.into(),
+                        entries_so_far,
+                        last_start_address: start_at.0.get(),
+                        source: None,
+                    }));
+                    start_at = page.last().and_then(|node| node.id.successor()).into();
+                }
+
+                events.push(Event::NodeTable(event::NodeTableEvent {
+                    gateway: event_gateway,
+                    timestamp: self.start.into(),
+                    nodes: nodes
+                        .iter()
+                        .map(|node| event::Node {
+                            id: node.id,
+                            address: Some(node.address),
+                            provenance: None,
+                            home_gateway: None,
+                        })
+                        .collect(),
+                    source: None,
+                }));
+            }
+        }
+
+        // Reports from nodes whose addresses are known follow the node's previous report
+        let mut last_reports: BTreeMap<LongAddress, SystemTime> = BTreeMap::new();
+        let gap_threshold = Duration::from_secs(Config::default().node_gaps.threshold_secs);
+        for (report, time) in self.power_reports.iter().zip(self.power_report_times()) {
+            let gateway = self
+                .gateways
+                .iter()
+                .find(|gateway| gateway.id == report.gateway_id);
+            let node_address = gateway
+                .and_then(|gateway| gateway.nodes.iter().find(|node| node.id == report.node_id))
+                .map(|node| node.address);
+
+            let measurement = report.measurement.quantized();
+            let event_gateway = event::Gateway {
+                id: report.gateway_id,
+                address: gateway
+                    .filter(|_| self.enumerate)
+                    .map(|gateway| gateway.address),
+                provenance: None,
+            };
+            let node = event::Node {
+                id: report.node_id,
+                address: node_address.filter(|_| self.walk_node_tables),
+                provenance: None,
+                // Each report moves its node to the gateway it arrived through
+                home_gateway: Some(report.gateway_id)
+                    .filter(|_| node_address.is_some() && self.walk_node_tables),
+            };
+            let since_previous = node.address.and_then(|address| {
+                let previous = last_reports.insert(address, time)?;
+                Some(time.duration_since(previous).unwrap_or_default())
+            });
+            if let Some(gap) = since_previous.filter(|gap| *gap > gap_threshold) {
+                events.push(Event::NodeGap(event::NodeGapEvent {
+                    gateway: event_gateway,
+                    node,
+                    timestamp: time.into(),
+                    gap_seconds: gap.as_millis() as f64 / 1000.0,
+                    source: None,
+                }));
+            }
+            events.push(Event::PowerReport(PowerReportEvent {
+                gateway: event_gateway,
+                node,
+                timestamp: time.into(),
+                voltage_in: measurement.voltage_in,
+                voltage_out: measurement.voltage_out,
+                current: measurement.current,
+                power_w: measurement.voltage_in * measurement.current,
+                dc_dc_duty_cycle: measurement.dc_dc_duty_cycle,
+                temperature: measurement.temperature,
+                rssi: measurement.rssi,
+                node_unverified: false,
+                timestamp_uncertain: false,
+                energy_wh_today: None,
+                seconds_since_previous_report: since_previous
+                    .map(|elapsed| elapsed.as_millis() as f64 / 1000.0),
+                source: None,
+            }));
+        }
+
+        // Each string response which says something new about a node is reported with everything
+        // the node has said so far
+        let homes: BTreeMap<LongAddress, GatewayID> = self
+            .power_reports
+            .iter()
+            .filter_map(|report| {
+                let address = self.node_address(report.gateway_id, report.node_id)?;
+                Some((address, report.gateway_id))
+            })
+            .collect();
+        for identity in self.node_identities() {
+            let response = &self.string_responses[identity.index];
+            events.push(Event::NodeIdentity(event::NodeIdentityEvent {
+                gateway: event::Gateway {
+                    id: response.gateway_id,
+                    address: self
+                        .gateways
+                        .iter()
+                        .find(|gateway| gateway.id == response.gateway_id)
+                        .filter(|_| self.enumerate)
+                        .map(|gateway| gateway.address),
+                    provenance: None,
+                },
+                node: event::Node {
+                    id: response.node_id,
+                    address: Some(identity.address),
+                    provenance: None,
+                    home_gateway: homes.get(&identity.address).copied(),
+                },
+                timestamp: identity.time.into(),
+                firmware: identity.firmware,
+                responses: identity.responses,
+                source: None,
+            }));
+        }
+
+        events
+    }
+
+    /// The codes of the diagnostics which an observer should emit in response to this scenario.
+    pub fn expected_diagnostics(&self) -> Vec<diagnostic::Code> {
+        let mut codes = Vec::new();
+
+        // Gateways of unknown identity are reported the first time each is seen
+        let mut seen = std::collections::BTreeSet::new();
+        let mut unknown_gateway = |gateway_id| !self.enumerate && seen.insert(gateway_id);
+        for report in &self.power_reports {
+            if unknown_gateway(report.gateway_id) {
+                codes.push(diagnostic::Code::GatewayIdentityUnknown);
+            }
+        }
+
+        let identities = self.node_identities();
+        for (index, response) in self.string_responses.iter().enumerate() {
+            if unknown_gateway(response.gateway_id) {
+                codes.push(diagnostic::Code::GatewayIdentityUnknown);
+            }
+            if identities
+                .iter()
+                .any(|identity| identity.index == index && identity.firmware_changed)
+            {
+                codes.push(diagnostic::Code::NodeFirmwareChanged);
+            }
+        }
+
+        codes
+    }
+
+    /// The hardware address of a node, if the observer learns it from a node table walk.
+    fn node_address(&self, gateway_id: GatewayID, node_id: NodeID) -> Option<LongAddress> {
+        self.gateways
+            .iter()
+            .find(|gateway| gateway.id == gateway_id)?
+            .nodes
+            .iter()
+            .find(|node| node.id == node_id)
+            .map(|node| node.address)
+            .filter(|_| self.walk_node_tables)
+    }
+
+    /// What each string response which tells the observer something new says about its node.
+    fn node_identities(&self) -> Vec<NodeIdentity> {
+        let mut inventory: BTreeMap<LongAddress, (Option<String>, BTreeMap<String, String>)> =
+            BTreeMap::new();
+        let mut identities = Vec::new();
+
+        for (index, (response, time)) in self
+            .string_responses
+            .iter()
+            .zip(self.string_response_times())
+            .enumerate()
+        {
+            let Some(address) = self.node_address(response.gateway_id, response.node_id) else {
+                continue;
+            };
+            let text = response.response.trim();
+            let (firmware, responses) = inventory.entry(address).or_default();
+
+            let (changed, firmware_changed) = if text.contains(" Version ") {
+                let previous = firmware.replace(text.to_owned());
+                let changed = previous.as_deref() != Some(text);
+                (changed, changed && previous.is_some())
+            } else if let Some(name) = text
+                .strip_prefix('!')
+                .and_then(|named| named.split_whitespace().next())
+            {
+                let previous = responses.insert(name.to_owned(), text.to_owned());
+                (previous.as_deref() != Some(text), false)
+            } else {
+                continue;
+            };
+
+            if changed {
+                identities.push(NodeIdentity {
+                    index,
+                    time,
+                    address,
+                    firmware: firmware.clone(),
+                    responses: responses.clone(),
+                    firmware_changed,
+                });
+            }
+        }
+
+        identities
+    }
+
+    /// Run this scenario through the full receiver stack.
+    pub fn run(&self) -> Outcome {
+        let clock = ManualClock::new(self.start);
+        let events = Recorder::default();
+        let diagnostics = Recorder::default();
+
+        let mut observer = Observer::default();
+        observer.set_clock(clock.clone());
+        observer.set_config(Config {
+            rate_limits: RateLimits {
+                global: None,
+                per_node: None,
+            },
+            duplicate_addresses: self.duplicate_addresses,
+            ..Default::default()
+        });
+        observer.set_event_sink(events.clone());
+        observer.set_diagnostics_output(diagnostic::Output::Writer(Box::new(diagnostics.clone())));
+
+        let mut rx = link::Receiver::new(transport::Receiver::new(pv::application::Receiver::new(
+            observer,
+        )));
+
+        // Deliver the stream in chunks of varying sizes, setting the clock as we go
+        let stream = self.encode();
+        let mut chunk_sizes = CHUNK_SIZES.iter().copied().cycle();
+        let mut times = stream.times.iter().peekable();
+        let mut offset = 0;
+        while offset < stream.bytes.len() {
+            while let Some((_, time)) = times.next_if(|(start, _)| *start <= offset) {
+                clock.set(*time);
+            }
+
+            let end = times
+                .peek()
+                .map(|(start, _)| *start)
+                .unwrap_or(stream.bytes.len())
+                .min(offset + chunk_sizes.next().unwrap());
+            rx.extend_from_slice(&stream.bytes[offset..end]);
+            offset = end;
+        }
+
+        let counters = crate::Counters::snapshot(&rx);
+        let snapshot = rx.sink().sink().sink().snapshot();
+
+        let diagnostics = String::from_utf8(diagnostics.0.lock().unwrap().clone()).unwrap();
+        let diagnostics = diagnostics
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let events = std::mem::take(&mut *events.0.lock().unwrap());
+
+        Outcome {
+            events,
+            diagnostics,
+            counters,
+            snapshot,
+        }
+    }
+
+    /// Run this scenario, asserting that it produces exactly the expected events and
+    /// diagnostics.
+    pub fn assert_roundtrip(&self) -> Outcome {
+        let outcome = self.run();
+        assert_eq!(
+            outcome
+                .diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.code)
+                .collect::<Vec<_>>(),
+            self.expected_diagnostics()
+        );
+        assert_eq!(outcome.counters.link.checksums, 0);
+        assert_eq!(outcome.counters.application.lost_packets, 0);
+        assert_eq!(outcome.events, self.expected_events());
+        outcome
+    }
+}
+
+impl Measurement {
+    fn raw(&self) -> (u16, u16, u16, u8, u16) {
+        fn scale(value: f64, per_unit: f64, name: &str) -> u16 {
+            let raw = (value * per_unit).round();
+            assert!(
+                (0.0..=4095.0).contains(&raw),
+                "{} {} is out of range",
+                name,
+                value
+            );
+            raw as u16
+        }
+
+        let temperature = (self.temperature * 10.0).round();
+        assert!(
+            (-2048.0..=2047.0).contains(&temperature),
+            "temperature {} is out of range",
+            self.temperature
+        );
+
+        (
+            scale(self.voltage_in, 20.0, "voltage_in"),
+            scale(self.voltage_out, 10.0, "voltage_out"),
+            scale(self.current, 200.0, "current"),
+            (self.dc_dc_duty_cycle * 255.0).round().clamp(0.0, 255.0) as u8,
+            temperature as i16 as u16 & 0x0fff,
+        )
+    }
+
+    /// This measurement, quantized to the resolution of the wire format.
+    pub fn quantized(&self) -> Self {
+        let (voltage_in, voltage_out, current, dc_dc_duty_cycle, _) = self.raw();
+        Self {
+            voltage_in: voltage_in as f64 / 20.0,
+            voltage_out: voltage_out as f64 / 10.0,
+            current: current as f64 / 200.0,
+            dc_dc_duty_cycle: dc_dc_duty_cycle as f64 / 255.0,
+            temperature: (self.temperature * 10.0).round() / 10.0,
+            rssi: self.rssi,
+        }
+    }
+
+    /// Encode this measurement as a power report.
+    pub fn encode(&self, slot_counter: SlotCounter) -> pv::application::PowerReport {
+        let (voltage_in, voltage_out, current, dc_dc_duty_cycle, temperature) = self.raw();
+        pv::application::PowerReport {
+            voltage_in_and_voltage_out: U12Pair::try_from((voltage_in, voltage_out)).unwrap(),
+            dc_dc_duty_cycle,
+            current_and_temperature: U12Pair::try_from((current, temperature)).unwrap(),
+            unknown: [0, 0, 0],
+            slot_counter,
+            rssi: self.rssi,
+        }
+    }
+}
+
+/// A shared recording of events or bytes, which can be given to an observer while retaining
+/// access to its contents.
+#[derive(Debug, Clone)]
+struct Recorder<T>(Arc<Mutex<Vec<T>>>);
+
+impl<T> Default for Recorder<T> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl EventSink for Recorder<Event> {
+    fn event(&mut self, event: Event) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+impl std::io::Write for Recorder<u8> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
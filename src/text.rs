@@ -0,0 +1,144 @@
+//! Text payloads which aren't always valid UTF-8.
+//!
+//! Gateway versions and string commands are nominally text, but real gateways occasionally emit
+//! stray high bytes in otherwise readable strings. A [`LossyStr`] decodes such payloads with
+//! replacement characters instead of discarding them, while keeping the raw bytes for anyone who
+//! needs to see exactly what was sent. Only payloads which are mostly binary are treated as
+//! invalid.
+
+use std::borrow::Cow;
+
+/// A payload which is nominally text.
+///
+/// A `LossyStr` displays as its text, with invalid UTF-8 replaced by U+FFFD. It serializes as a
+/// string when it's valid UTF-8, and otherwise as an object with the lossy `text` and the `raw`
+/// bytes in hex.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct LossyStr<'a>(pub &'a [u8]);
+
+impl<'a> LossyStr<'a> {
+    /// The raw bytes of the payload.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The payload as text, with invalid UTF-8 replaced by U+FFFD.
+    pub fn to_str_lossy(&self) -> Cow<'a, str> {
+        String::from_utf8_lossy(self.0)
+    }
+
+    /// Whether the payload is entirely valid UTF-8.
+    pub fn is_valid_utf8(&self) -> bool {
+        std::str::from_utf8(self.0).is_ok()
+    }
+
+    /// Whether the payload is mostly binary rather than text.
+    ///
+    /// Bytes which aren't part of valid UTF-8, and control characters other than whitespace,
+    /// count as binary. A payload is mostly binary when more than half of its bytes are.
+    pub fn is_mostly_binary(&self) -> bool {
+        let mut binary = 0;
+        for chunk in self.0.utf8_chunks() {
+            binary += chunk.invalid().len();
+            binary += chunk
+                .valid()
+                .chars()
+                .filter(|c| c.is_control() && !c.is_ascii_whitespace())
+                .map(char::len_utf8)
+                .sum::<usize>();
+        }
+        binary * 2 > self.0.len()
+    }
+
+    /// The raw bytes of the payload in hex.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+impl std::fmt::Debug for LossyStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_valid_utf8() {
+            write!(f, "{:?}", self.to_str_lossy())
+        } else {
+            write!(f, "{:?} (raw: {})", self.to_str_lossy(), self.to_hex())
+        }
+    }
+}
+
+impl std::fmt::Display for LossyStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_str_lossy())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for LossyStr<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        #[derive(serde::Serialize)]
+        struct Lossy<'a> {
+            text: &'a str,
+            raw: String,
+        }
+
+        match std::str::from_utf8(self.0) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => Lossy {
+                text: &self.to_str_lossy(),
+                raw: self.to_hex(),
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        let text = LossyStr(b"Mgate Version G8.59\r\n");
+        assert!(text.is_valid_utf8());
+        assert!(!text.is_mostly_binary());
+        assert_eq!(text.to_str_lossy(), "Mgate Version G8.59\r\n");
+        assert!(!LossyStr(b"").is_mostly_binary());
+    }
+
+    #[test]
+    fn mixed_content() {
+        let text = LossyStr(b"Mchip\xFF v1.2\x00");
+        assert!(!text.is_valid_utf8());
+        assert!(!text.is_mostly_binary());
+        assert_eq!(text.to_string(), "Mchip\u{FFFD} v1.2\0");
+        assert_eq!(text.to_hex(), "4d63686970ff2076312e3200");
+        assert_eq!(
+            format!("{:?}", text),
+            "\"Mchip\u{FFFD} v1.2\\0\" (raw: 4d63686970ff2076312e3200)"
+        );
+    }
+
+    #[test]
+    fn binary() {
+        assert!(LossyStr(b"\xFF").is_mostly_binary());
+        assert!(LossyStr(b"\x01\x02\x03ab").is_mostly_binary());
+        assert!(!LossyStr(b"\x01\x02abc").is_mostly_binary());
+        assert!(LossyStr(&[0x80; 16]).is_mostly_binary());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        assert_eq!(
+            serde_json::to_value(LossyStr(b"Mchip")).unwrap(),
+            serde_json::json!("Mchip")
+        );
+        assert_eq!(
+            serde_json::to_value(LossyStr(b"Mchip\xFF")).unwrap(),
+            serde_json::json!({"text": "Mchip\u{FFFD}", "raw": "4d63686970ff"})
+        );
+    }
+}
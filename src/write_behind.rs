@@ -0,0 +1,458 @@
+//! Write-behind buffering for files on slow storage.
+//!
+//! Collectors often run from SD cards, where `fsync()` can occasionally take seconds. A
+//! [`WriteBehind`] accepts writes into memory and hands them to a background thread, which writes
+//! and syncs them in batches, so that a latency spike stalls the flusher instead of the writer.
+//!
+//! Buffered data is lost if the process dies before it is flushed. Each file has a marker file
+//! alongside it while it is open, so the next process to open the file can tell that this
+//! happened and how much data could have been lost.
+
+use crate::analyze::P2Quantile;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Limits on how much data a [`WriteBehind`] holds in memory.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Config {
+    /// Flush once this many bytes are buffered.
+    pub max_bytes: usize,
+    /// Flush once the oldest buffered byte is this old.
+    pub max_age: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 << 10,
+            max_age: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Config {
+    /// Writers block once this many bytes are buffered, which happens only when storage can't
+    /// keep up.
+    fn hard_limit(&self) -> usize {
+        self.max_bytes.saturating_mul(8)
+    }
+}
+
+/// Evidence that a previous process exited without flushing a file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UncleanShutdown {
+    /// The most data the previous process could have lost.
+    pub max_lost_bytes: usize,
+    /// The longest period of data the previous process could have lost.
+    pub max_lost_age: Duration,
+}
+
+impl std::fmt::Display for UncleanShutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "the previous writer exited without flushing, losing up to {} bytes or {:?} of data",
+            self.max_lost_bytes, self.max_lost_age
+        )
+    }
+}
+
+/// Statistics about a [`WriteBehind`]'s flushes.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct FlushStats {
+    /// The number of flushes completed.
+    pub flushes: u64,
+    /// The number of bytes flushed.
+    pub bytes: u64,
+    /// The number of flushes which failed.
+    pub errors: u64,
+    /// Estimated median flush latency.
+    pub latency_p50: Option<Duration>,
+    /// Estimated 90th percentile flush latency.
+    pub latency_p90: Option<Duration>,
+    /// Estimated 99th percentile flush latency.
+    pub latency_p99: Option<Duration>,
+    /// The greatest flush latency.
+    pub latency_max: Option<Duration>,
+}
+
+/// A file which is written by a background thread.
+///
+/// `Write::flush()` does not wait for storage, since callers which flush after every record would
+/// otherwise defeat the buffering. Data reaches storage within [`Config::max_age`]; call
+/// [`sync()`](Self::sync) to wait for it.
+pub struct WriteBehind {
+    shared: Arc<Shared>,
+    flusher: Option<JoinHandle<()>>,
+    marker: PathBuf,
+    unclean_shutdown: Option<UncleanShutdown>,
+}
+
+struct Shared {
+    config: Config,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    buffer: Vec<u8>,
+    oldest: Option<Instant>,
+    flushing: bool,
+    sync_requested: bool,
+    closing: bool,
+    error: Option<std::io::Error>,
+    stats: Stats,
+}
+
+struct Stats {
+    flushes: u64,
+    bytes: u64,
+    errors: u64,
+    latency: [P2Quantile; 3],
+    latency_max: Option<Duration>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            flushes: 0,
+            bytes: 0,
+            errors: 0,
+            latency: [0.5, 0.9, 0.99].map(P2Quantile::new),
+            latency_max: None,
+        }
+    }
+}
+
+impl WriteBehind {
+    /// Open a file for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>, config: Config) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::options().create(true).append(true).open(path)?;
+
+        // A marker left behind means the last writer didn't close the file
+        let mut marker = path.as_os_str().to_owned();
+        marker.push(".dirty");
+        let marker = PathBuf::from(marker);
+        let unclean_shutdown = match std::fs::read_to_string(&marker) {
+            Ok(contents) => Some(parse_marker(&contents).unwrap_or(UncleanShutdown {
+                max_lost_bytes: config.hard_limit(),
+                max_lost_age: config.max_age,
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        std::fs::write(
+            &marker,
+            format!("{} {}\n", config.hard_limit(), config.max_age.as_millis()),
+        )?;
+
+        let shared = Arc::new(Shared {
+            config,
+            state: Default::default(),
+            changed: Condvar::new(),
+        });
+        let flusher = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name(format!("flush {}", path.display()))
+                .spawn(move || shared.flush_loop(file))?
+        };
+
+        Ok(Self {
+            shared,
+            flusher: Some(flusher),
+            marker,
+            unclean_shutdown,
+        })
+    }
+
+    /// Whether the previous writer of this file exited without flushing it.
+    pub fn unclean_shutdown(&self) -> Option<UncleanShutdown> {
+        self.unclean_shutdown
+    }
+
+    /// Statistics about flushes so far.
+    pub fn stats(&self) -> FlushStats {
+        let state = self.shared.state.lock().unwrap();
+        let stats = &state.stats;
+        let [p50, p90, p99] = stats
+            .latency
+            .each_ref()
+            .map(|q| q.estimate().map(|s| Duration::from_secs_f64(s.max(0.0))));
+        FlushStats {
+            flushes: stats.flushes,
+            bytes: stats.bytes,
+            errors: stats.errors,
+            latency_p50: p50,
+            latency_p90: p90,
+            latency_p99: p99,
+            latency_max: stats.latency_max,
+        }
+    }
+
+    /// Wait until everything written so far has been written to storage and synced.
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.sync_requested = true;
+        self.shared.changed.notify_all();
+        while !state.buffer.is_empty() || state.flushing {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        state.sync_requested = false;
+        state.error.take().map_or(Ok(()), Err)
+    }
+
+    /// Flush and close the file, removing its marker.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        let Some(flusher) = self.flusher.take() else {
+            return Ok(());
+        };
+
+        self.shared.state.lock().unwrap().closing = true;
+        self.shared.changed.notify_all();
+        flusher.join().ok();
+
+        let stats = self.stats();
+        if stats.flushes > 0 {
+            log::debug!("{:?}: {:?}", self.marker, stats);
+        }
+
+        match self.shared.state.lock().unwrap().error.take() {
+            // Leave the marker, since data was lost
+            Some(e) => Err(e),
+            None => std::fs::remove_file(&self.marker),
+        }
+    }
+}
+
+fn parse_marker(contents: &str) -> Option<UncleanShutdown> {
+    let (bytes, millis) = contents.trim().split_once(' ')?;
+    Some(UncleanShutdown {
+        max_lost_bytes: bytes.parse().ok()?,
+        max_lost_age: Duration::from_millis(millis.parse().ok()?),
+    })
+}
+
+impl Shared {
+    fn flush_loop(&self, mut file: File) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            state = self.wait_for_work(state);
+            if state.buffer.is_empty() {
+                // Closing, and nothing left to flush
+                return;
+            }
+
+            let data = std::mem::take(&mut state.buffer);
+            state.oldest = None;
+            state.flushing = true;
+            drop(state);
+            // Writers blocked on the hard limit can proceed
+            self.changed.notify_all();
+
+            let start = Instant::now();
+            let result = file.write_all(&data).and_then(|_| file.sync_data());
+            let latency = start.elapsed();
+
+            state = self.state.lock().unwrap();
+            state.flushing = false;
+            let stats = &mut state.stats;
+            match result {
+                Ok(()) => {
+                    stats.flushes += 1;
+                    stats.bytes += data.len() as u64;
+                    for q in &mut stats.latency {
+                        q.push(latency.as_secs_f64());
+                    }
+                    stats.latency_max = stats.latency_max.max(Some(latency));
+                }
+                Err(e) => {
+                    log::error!("error flushing {} bytes: {}", data.len(), e);
+                    stats.errors += 1;
+                    state.error.get_or_insert(e);
+                }
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    /// Wait until the buffer should be flushed, or until it's empty and the writer is closing.
+    fn wait_for_work<'a>(&self, mut state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        loop {
+            if state.buffer.is_empty() {
+                if state.closing {
+                    return state;
+                }
+                state = self.changed.wait(state).unwrap();
+                continue;
+            }
+
+            let age = state.oldest.map(|t| t.elapsed()).unwrap_or_default();
+            if state.closing
+                || state.sync_requested
+                || state.buffer.len() >= self.config.max_bytes
+                || age >= self.config.max_age
+            {
+                return state;
+            }
+
+            state = self
+                .changed
+                .wait_timeout(state, self.config.max_age - age)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl Write for WriteBehind {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let config = &self.shared.config;
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+
+        // Storage isn't keeping up, so wait instead of growing without bound
+        while state.buffer.len() >= config.hard_limit() {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+
+        state.buffer.extend_from_slice(buf);
+        state.oldest.get_or_insert_with(Instant::now);
+        if state.buffer.len() >= config.max_bytes {
+            self.shared.changed.notify_all();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("error closing {:?}: {}", self.marker, e);
+        }
+    }
+}
+
+impl std::fmt::Debug for WriteBehind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WriteBehind")
+            .field("marker", &self.marker)
+            .field("config", &self.shared.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "taptap-{}-{}-{}",
+            name,
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ))
+    }
+
+    #[test]
+    fn write_behind() {
+        let path = temp_path("write-behind");
+        let config = Config {
+            max_bytes: 16,
+            max_age: Duration::from_secs(3600),
+        };
+
+        let mut writer = WriteBehind::open(&path, config).unwrap();
+        assert_eq!(writer.unclean_shutdown(), None);
+
+        // Small writes wait for the age limit, even when flushed
+        writer.write_all(b"hello\n").unwrap();
+        writer.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+
+        // Syncing writes them out
+        writer.sync().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello\n");
+
+        // Exceeding the size limit writes them out
+        writer.write_all(b"a much longer line\n").unwrap();
+        let start = Instant::now();
+        while std::fs::read(&path).unwrap().len() < 25 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // Wait for the flusher to finish syncing before looking at its statistics
+        writer.sync().unwrap();
+        let stats = writer.stats();
+        assert_eq!(stats.flushes, 2);
+        assert_eq!(stats.bytes, 25);
+        assert!(stats.latency_max.is_some());
+
+        // Finishing flushes and removes the marker
+        writer.write_all(b"bye\n").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"hello\na much longer line\nbye\n"
+        );
+        let reopened = WriteBehind::open(&path, config).unwrap();
+        assert_eq!(reopened.unclean_shutdown(), None);
+        drop(reopened);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unclean_shutdown() {
+        let path = temp_path("unclean-shutdown");
+        let config = Config {
+            max_bytes: 1000,
+            max_age: Duration::from_secs(3600),
+        };
+
+        // Dying without closing leaves unflushed data behind
+        let mut writer = WriteBehind::open(&path, config).unwrap();
+        writer.write_all(b"lost\n").unwrap();
+        std::mem::forget(writer);
+
+        let reopened = WriteBehind::open(&path, Config::default()).unwrap();
+        assert_eq!(
+            reopened.unclean_shutdown(),
+            Some(UncleanShutdown {
+                max_lost_bytes: 8000,
+                max_lost_age: Duration::from_secs(3600),
+            })
+        );
+        reopened.finish().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+        assert_eq!(
+            WriteBehind::open(&path, config).unwrap().unclean_shutdown(),
+            None
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+//! Merging overlapping captures of one bus made by machines whose clocks disagree.
+
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+use taptap::capture::{self, merge};
+use taptap::gateway::GatewayID;
+use taptap::observer::clock::{ReplayClock, ReplayClockMode};
+use taptap::observer::event::Event;
+use taptap::observer::rate_limit::RateLimits;
+use taptap::observer::{diagnostic, Config, Observer};
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+fn slot_counter(absolute_slot: u32) -> SlotCounter {
+    let absolute_slot = absolute_slot % 48000;
+    let epoch = (absolute_slot / 12000) as u16;
+    let slot_number = (absolute_slot % 12000) as u16;
+    SlotCounter::from(epoch << 14 | slot_number)
+}
+
+/// Two nodes reporting every 20 seconds, after an enumeration and a node table walk.
+fn scenario() -> Scenario {
+    let nodes: Vec<Node> = (2..4u16)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let gateway = Gateway {
+        id: GatewayID::try_from(0x1201).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+        version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r".into(),
+        nodes: nodes.clone(),
+    };
+    let power_reports = (0..8u16)
+        .flat_map(|round| {
+            nodes.iter().enumerate().map(move |(i, node)| PowerReport {
+                gateway_id: GatewayID::try_from(0x1201).unwrap(),
+                node_id: node.id,
+                // 4000 slots is 20 seconds
+                slot_counter: slot_counter(u32::from(round) * 4000 + i as u32 * 2000),
+                measurement: Measurement {
+                    voltage_in: 30.0,
+                    voltage_out: 29.0,
+                    current: 6.5 + f64::from(round),
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                },
+            })
+        })
+        .collect();
+    Scenario {
+        gateways: vec![gateway],
+        power_reports,
+        ..Scenario::new(start())
+    }
+}
+
+/// Each run of bytes in the scenario's stream, along with when it appeared on the bus.
+fn records(scenario: &Scenario) -> Vec<(Vec<u8>, SystemTime)> {
+    let stream = scenario.encode();
+    stream
+        .times
+        .iter()
+        .enumerate()
+        .map(|(i, &(offset, time))| {
+            let end = stream
+                .times
+                .get(i + 1)
+                .map_or(stream.bytes.len(), |(end, _)| *end);
+            (stream.bytes[offset..end].to_vec(), time)
+        })
+        .collect()
+}
+
+/// Write records to a capture file, shifting their timestamps by `skew`.
+fn write_capture(records: &[(Vec<u8>, SystemTime)], skew: Duration) -> Vec<u8> {
+    let mut writer = capture::Writer::new(Vec::new()).unwrap();
+    for (data, time) in records {
+        writer.write(data, *time + skew).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+/// Replay records through the full pipeline, returning the events emitted.
+fn replay(records: impl Iterator<Item = std::io::Result<(Vec<u8>, SystemTime)>>) -> Vec<Event> {
+    let mut clock = ReplayClock::new(ReplayClockMode::Raw);
+    let (tx, events) = mpsc::channel();
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clock());
+    observer.set_config(Config {
+        rate_limits: RateLimits {
+            global: None,
+            per_node: None,
+        },
+        ..Default::default()
+    });
+    observer.set_event_sink(tx);
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let mut rx = taptap::pipeline(observer);
+
+    for record in records {
+        let (data, timestamp) = record.unwrap();
+        assert!(clock.set(timestamp).is_none());
+        rx.extend_from_slice(&data);
+    }
+    drop(rx);
+    events.into_iter().collect()
+}
+
+#[test]
+fn known_offset() {
+    let scenario = scenario();
+    let records = records(&scenario);
+
+    // The first capture sees the first two thirds of the bus, and the second capture sees the
+    // last two thirds from a machine whose clock runs 3.5 seconds fast
+    let skew = Duration::from_millis(3500);
+    let third = records.len() / 3;
+    let first = write_capture(&records[..third * 2], Duration::ZERO);
+    let second = write_capture(&records[third..], skew);
+
+    let first = merge::frames(capture::Reader::new(first.as_slice()).unwrap()).unwrap();
+    let mut second = merge::frames(capture::Reader::new(second.as_slice()).unwrap()).unwrap();
+
+    let offset = merge::estimate_offset(&first, &second).unwrap();
+    assert_eq!(
+        offset,
+        merge::Offset {
+            duration: skew,
+            negative: true
+        }
+    );
+    for frame in &mut second {
+        frame.time = offset.apply(frame.time);
+    }
+
+    let (frames, summary) = merge::merge(vec![first, second], Some(Duration::from_millis(500)));
+    // Every frame on the bus was kept exactly once, and the overlap was dropped
+    let whole = merge::frames(records.iter().cloned().map(Ok)).unwrap();
+    assert_eq!(frames, whole);
+    assert_eq!(summary.frames.iter().sum::<u64>(), whole.len() as u64);
+    assert!(summary.duplicates > 0);
+
+    // The merged capture replays just like the whole bus
+    let mut writer = capture::Writer::new(Vec::new()).unwrap();
+    for frame in &frames {
+        writer.write(&frame.bytes, frame.time).unwrap();
+    }
+    let merged = writer.finish().unwrap();
+    assert_eq!(
+        replay(capture::Reader::new(merged.as_slice()).unwrap()),
+        scenario.expected_events()
+    );
+}
+
+#[test]
+fn overlapping_captures() {
+    let scenario = scenario();
+    let records = records(&scenario);
+
+    // Two capture processes on one machine, whose captures overlap by a third of the bus
+    let third = records.len() / 3;
+    let first = write_capture(&records[..third * 2], Duration::ZERO);
+    let second = write_capture(&records[third..], Duration::ZERO);
+
+    let inputs = [&first, &second]
+        .map(|capture| merge::Frames::new(capture::Reader::new(capture.as_slice()).unwrap()));
+    let mut merged = merge::Merge::new(inputs.into(), Some(Duration::ZERO));
+    let frames: Vec<_> = merged.by_ref().map(Result::unwrap).collect();
+
+    // Identical frames at identical times were kept once, leaving every frame on the bus
+    let whole = merge::frames(records.iter().cloned().map(Ok)).unwrap();
+    assert_eq!(frames, whole);
+    assert!(merged.summary().duplicates > 0);
+    let read: Vec<_> = merged
+        .inputs()
+        .iter()
+        .map(|input| input.summary().records)
+        .collect();
+    assert_eq!(read, [third as u64 * 2, (records.len() - third) as u64]);
+    assert!(merged
+        .inputs()
+        .iter()
+        .all(|input| input.summary().steps_back == 0));
+}
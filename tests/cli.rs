@@ -0,0 +1,412 @@
+//! The `taptap` executable's exit codes and outputs, run against mock sources.
+
+use std::io::Write;
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+use taptap::cli::ExitCode;
+use taptap::gateway::GatewayID;
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+fn taptap(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_taptap"));
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
+/// Run a command, killing it if it takes too long, and return its exit code.
+fn run(mut command: Command) -> i32 {
+    let mut child = command.spawn().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.code().expect("exited normally");
+        }
+        if Instant::now() > deadline {
+            child.kill().ok();
+            panic!("taptap didn't exit");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// A port on which nothing is listening.
+fn closed_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// A mock source which accepts one connection, sends `data`, and then either closes the
+/// connection or holds it open until the test ends.
+fn source(data: &'static [u8], close: bool) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.write_all(data).unwrap();
+        if !close {
+            std::thread::sleep(Duration::from_secs(60));
+        }
+    });
+    port
+}
+
+fn observe(port: u16, extra: &[&str]) -> Command {
+    let port = port.to_string();
+    let mut args = vec!["observe", "--tcp", "127.0.0.1", "--port", port.as_str()];
+    args.extend_from_slice(extra);
+    taptap(&args)
+}
+
+#[test]
+fn end_of_data() {
+    let port = source(b"\x00\xff\xff\x7e\x07\x12\x01\x00\x00\x00\x00", true);
+    assert_eq!(run(observe(port, &[])), ExitCode::Success.code());
+}
+
+#[test]
+fn connect_failure() {
+    assert_eq!(run(observe(closed_port(), &[])), ExitCode::Connect.code());
+    assert_eq!(
+        run(taptap(&[
+            "peek-frames",
+            "--tcp",
+            "127.0.0.1",
+            "--port",
+            &closed_port().to_string()
+        ])),
+        ExitCode::Connect.code()
+    );
+    assert_eq!(
+        run(taptap(&[
+            "ctl",
+            "dump-counters",
+            "--control",
+            &format!("127.0.0.1:{}", closed_port())
+        ])),
+        ExitCode::Connect.code()
+    );
+}
+
+#[test]
+fn config_error() {
+    assert_eq!(
+        run(observe(
+            closed_port(),
+            &["--diagnostics", "/nonexistent/taptap/diagnostics.json"]
+        )),
+        ExitCode::Config.code()
+    );
+    assert_eq!(
+        run(observe(closed_port(), &["--fail-on", "no-bytes:60"])),
+        ExitCode::Config.code()
+    );
+}
+
+#[test]
+fn durations() {
+    // Zero turns off slot clock restoration, but isn't a gap between reports
+    let port = source(b"\x00\xff\xff\x7e\x07\x12\x01\x00\x00\x00\x00", true);
+    assert_eq!(
+        run(observe(port, &["--slot-clock-restore", "0"])),
+        ExitCode::Success.code()
+    );
+    assert_eq!(
+        run(observe(closed_port(), &["--node-gap", "0"])),
+        ExitCode::Config.code()
+    );
+
+    // Durations too long to represent are rejected rather than wrapping
+    assert_eq!(
+        run(observe(
+            closed_port(),
+            &["--node-gap", "999999999999999999d"]
+        )),
+        ExitCode::Config.code()
+    );
+    assert_eq!(
+        run(taptap(&[
+            "capture-merge",
+            "nonexistent.taptap",
+            "-o",
+            "merged.taptap",
+            "--from",
+            "99999999999999999s"
+        ])),
+        ExitCode::Config.code()
+    );
+}
+
+#[test]
+fn fail_on_no_frames() {
+    // Bytes, but nothing resembling a frame
+    let port = source(b"\x55\x55\x55\x55", false);
+    let started = Instant::now();
+    assert_eq!(
+        run(observe(port, &["--fail-on", "no-frames:1"])),
+        ExitCode::NoData.code()
+    );
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[test]
+fn capture_to_end_of_data() {
+    let data = b"\x00\xff\xff\x7e\x07\x12\x01\x00\x00\x00\x00";
+    let port = source(data, true);
+    let path = std::env::temp_dir().join(format!("taptap-cli-{}.taptap", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let port = port.to_string();
+    let args = ["capture", "--tcp", "127.0.0.1", "--port", &port, "--out"];
+    let mut command = taptap(&args);
+    command.arg(&path);
+    assert_eq!(run(command), ExitCode::Success.code());
+
+    // The capture holds everything the source sent
+    let file = std::fs::File::open(&path).unwrap();
+    let captured: Vec<u8> = taptap::capture::Reader::new(file)
+        .unwrap()
+        .flat_map(|record| record.unwrap().0)
+        .collect();
+    assert_eq!(captured, data);
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Write a capture of an enumeration, a node table walk, and a few power reports.
+fn scenario_capture(path: &std::path::Path) {
+    let node = Node {
+        id: NodeID::try_from(2).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]),
+    };
+    let gateway = Gateway {
+        id: GatewayID::try_from(0x1201).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+        version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r".into(),
+        nodes: vec![node],
+    };
+    let scenario = Scenario {
+        gateways: vec![gateway],
+        power_reports: (0..3u16)
+            .map(|round| PowerReport {
+                gateway_id: GatewayID::try_from(0x1201).unwrap(),
+                node_id: node.id,
+                // 4000 slots is 20 seconds
+                slot_counter: SlotCounter::from(round * 4000),
+                measurement: Measurement {
+                    voltage_in: 30.0,
+                    voltage_out: 29.0,
+                    current: 6.5,
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                },
+            })
+            .collect(),
+        ..Scenario::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200))
+    };
+
+    let stream = scenario.encode();
+    let mut writer = taptap::capture::Writer::new(std::fs::File::create(path).unwrap()).unwrap();
+    for (i, &(offset, time)) in stream.times.iter().enumerate() {
+        let end = stream
+            .times
+            .get(i + 1)
+            .map_or(stream.bytes.len(), |(end, _)| *end);
+        writer.write(&stream.bytes[offset..end], time).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+/// Run a command to completion, returning its standard output.
+fn stdout(mut command: Command) -> Vec<u8> {
+    let output = command.stdout(Stdio::piped()).output().unwrap();
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn capture_source() {
+    let path =
+        std::env::temp_dir().join(format!("taptap-cli-source-{}.taptap", std::process::id()));
+    scenario_capture(&path);
+    let path = path.to_str().unwrap();
+
+    // Observing a capture emits exactly what replaying it does, timestamped as it was captured
+    let observed = stdout(taptap(&["observe", "--capture", path]));
+    assert_eq!(observed, stdout(taptap(&["replay", "--file", path])));
+    let observed = String::from_utf8(observed).unwrap();
+    assert_eq!(observed.matches("\"voltage_in\"").count(), 3);
+    assert!(observed.contains("\"timestamp\":\"2024-08-2"));
+
+    let frames = stdout(taptap(&["peek-frames", "--capture", path]));
+    assert!(!frames.is_empty());
+    assert_eq!(
+        run(taptap(&[
+            "peek-frames",
+            "--tcp",
+            "127.0.0.1",
+            "--capture",
+            path
+        ])),
+        ExitCode::Config.code()
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn csv() {
+    let dir = std::env::temp_dir().join(format!("taptap-cli-csv-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("capture.taptap");
+    scenario_capture(&path);
+    let path = path.to_str().unwrap();
+
+    // A header, then a row for each power report and nothing else
+    let csv = stdout(taptap(&["observe", "--capture", path, "--format", "csv"]));
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", csv);
+    assert_eq!(lines[0], taptap::output::csv::HEADER);
+    assert!(
+        lines[1].ends_with(",4609,2,4-A20002S,30,29,6.5,195,1,25,120"),
+        "{}",
+        csv
+    );
+
+    // To a file for each day, with the other events still written to stdout
+    let pattern = dir.join("out-%Y-%m-%d.csv");
+    let events = stdout(taptap(&[
+        "observe",
+        "--capture",
+        path,
+        "--format",
+        "csv",
+        "--output",
+        pattern.to_str().unwrap(),
+    ]));
+    let events = String::from_utf8(events).unwrap();
+    assert!(!events.contains("\"voltage_in\""));
+    assert!(events.contains("\"nodes\""), "{}", events);
+    let files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("out-2024-08-"))
+        .collect();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    assert_eq!(std::fs::read_to_string(dir.join(&files[0])).unwrap(), csv);
+
+    assert_eq!(
+        run(taptap(&["observe", "--capture", path, "--format", "xml"])),
+        2
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn several_sources() {
+    // A gateway on each bus, with IDs which collide
+    let bus = |address: u8| {
+        let node = Node {
+            id: NodeID::try_from(2).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, address]),
+        };
+        let scenario = Scenario {
+            enumerate: false,
+            walk_node_tables: false,
+            gateways: vec![Gateway {
+                id: GatewayID::try_from(0x1201).unwrap(),
+                address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, address]),
+                version: "Mgate Version G8.59\r".into(),
+                nodes: vec![node],
+            }],
+            power_reports: (0..3u16)
+                .map(|round| PowerReport {
+                    gateway_id: GatewayID::try_from(0x1201).unwrap(),
+                    node_id: node.id,
+                    slot_counter: SlotCounter::from(round * 4000),
+                    measurement: Measurement {
+                        voltage_in: 30.0,
+                        voltage_out: 29.0,
+                        current: 6.5,
+                        dc_dc_duty_cycle: 1.0,
+                        temperature: 25.0,
+                        rssi: RSSI(120),
+                    },
+                })
+                .collect(),
+            ..Scenario::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200))
+        };
+        let bytes: &'static [u8] = scenario.encode().bytes.leak();
+        format!("127.0.0.1:{}", source(bytes, true))
+    };
+    let (a, b) = (bus(1), bus(2));
+
+    // One stream, whose power reports say which bus they came from
+    let output = stdout(taptap(&["observe", "--tcp", &a, "--tcp", &b]));
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.matches("\"voltage_in\"").count(), 6);
+    for source in [a, b] {
+        let label = format!("\"source\":\"tcp:{}\"", source);
+        assert_eq!(output.matches(&label).count(), 3, "{}", output);
+    }
+
+    // Other commands read from only one
+    let port = closed_port().to_string();
+    assert_eq!(
+        run(taptap(&[
+            "peek-frames",
+            "--tcp",
+            "127.0.0.1",
+            "--tcp",
+            "127.0.0.2",
+            "--port",
+            &port
+        ])),
+        ExitCode::Config.code()
+    );
+}
+
+#[test]
+fn doctor() {
+    let path =
+        std::env::temp_dir().join(format!("taptap-cli-doctor-{}.taptap", std::process::id()));
+    scenario_capture(&path);
+    let path = path.to_str().unwrap();
+
+    let report = stdout(taptap(&["doctor", "--capture", path, "--json"]));
+    let report: serde_json::Value = serde_json::from_slice(&report).unwrap();
+    assert_eq!(report["power_reports"], 3);
+    assert_eq!(report["enumeration_seen"], true);
+    assert_eq!(report["gateways"].as_array().unwrap().len(), 1);
+    assert!(report["findings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|finding| finding["status"] == "ok"));
+    std::fs::remove_file(path).unwrap();
+
+    // A source which sends nothing is a problem
+    let port = source(b"", true).to_string();
+    assert_eq!(
+        run(taptap(&[
+            "doctor",
+            "--tcp",
+            "127.0.0.1",
+            "--port",
+            &port,
+            "--duration",
+            "5s"
+        ])),
+        ExitCode::Failure.code()
+    );
+}
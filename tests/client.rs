@@ -0,0 +1,178 @@
+use std::io::{ErrorKind, Read};
+use std::time::{Duration, SystemTime};
+use taptap::capture;
+use taptap::config::{ConnectionMode, TcpConnectionConfig};
+use taptap::gateway::GatewayID;
+use taptap::observer::clock::ManualClock;
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+use taptap::testing::MockConnection;
+use taptap::Client;
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+/// A gateway with five nodes, each reporting once.
+fn scenario() -> Scenario {
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let nodes: Vec<Node> = (2..7)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let power_reports = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| PowerReport {
+            gateway_id,
+            node_id: node.id,
+            slot_counter: SlotCounter::from(i as u16 * 500),
+            measurement: Measurement {
+                voltage_in: 30.0,
+                voltage_out: 29.0,
+                current: 6.5,
+                dc_dc_duty_cycle: 1.0,
+                temperature: 25.0,
+                rssi: RSSI(120),
+            },
+        })
+        .collect();
+    Scenario {
+        gateways: vec![Gateway {
+            id: gateway_id,
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+            version: "Mgate Version G8.59\r".into(),
+            nodes,
+        }],
+        power_reports,
+        ..Scenario::new(start())
+    }
+}
+
+/// A capture of a scenario, as `taptap capture` would have written it.
+fn capture(scenario: &Scenario) -> Vec<u8> {
+    let stream = scenario.encode();
+    let mut writer = capture::Writer::new(Vec::new()).unwrap();
+    for (i, &(offset, time)) in stream.times.iter().enumerate() {
+        let end = stream
+            .times
+            .get(i + 1)
+            .map_or(stream.bytes.len(), |(end, _)| *end);
+        writer.write(&stream.bytes[offset..end], time).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+/// A connection replaying a capture, setting a clock to the time at which each part was captured.
+struct CaptureConnection<R: Read> {
+    records: capture::Reader<R>,
+    clock: ManualClock,
+    pending: Vec<u8>,
+}
+
+impl<R: Read> Read for CaptureConnection<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.records.next() {
+                Some(record) => {
+                    let (data, timestamp) = record?;
+                    self.clock.set(timestamp);
+                    self.pending = data;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+#[test]
+fn replay_capture() {
+    let scenario = scenario();
+    let capture = capture(&scenario);
+    let clock = ManualClock::new(start());
+    let conn = CaptureConnection {
+        records: capture::Reader::new(std::io::Cursor::new(capture)).unwrap(),
+        clock: clock.clone(),
+        pending: Vec::new(),
+    };
+
+    let client = Client::builder().reader(conn).clock(clock).spawn();
+    let events = client.collect::<std::io::Result<Vec<_>>>().unwrap();
+    assert_eq!(events, scenario.expected_events());
+}
+
+#[test]
+fn error_is_last_item() {
+    let scenario = scenario();
+    let clock = ManualClock::new(start());
+    let stream = scenario.encode();
+    let conn = MockConnection::new()
+        .with_clock(clock.clone())
+        .then_read(stream.bytes)
+        .then_error(ErrorKind::ConnectionReset);
+
+    let mut items: Vec<_> = Client::builder()
+        .reader(conn)
+        .clock(clock)
+        .spawn()
+        .collect();
+    let Some(Err(e)) = items.pop() else {
+        panic!("expected an error: {:?}", items);
+    };
+    assert_eq!(e.kind(), ErrorKind::ConnectionReset);
+
+    // Everything before the error was delivered
+    assert_eq!(items.len(), scenario.expected_events().len());
+    assert!(items.iter().all(Result::is_ok));
+}
+
+#[test]
+fn connect_failure() {
+    // Find a port with nothing listening on it
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let mut client = Client::builder()
+        .source(TcpConnectionConfig {
+            hostname: "127.0.0.1".into(),
+            port,
+            mode: ConnectionMode::ReadOnly,
+            reconnect: false,
+            idle_timeout_secs: None,
+        })
+        .spawn();
+    assert!(matches!(client.next(), Some(Err(_))));
+    assert!(client.next().is_none());
+}
+
+#[test]
+fn stop() {
+    /// A source which never ends.
+    struct Idle;
+    impl Read for Idle {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(1));
+            Err(ErrorKind::TimedOut.into())
+        }
+    }
+
+    let mut client = Client::builder().reader(Idle).spawn();
+    let stop = client.stop_handle();
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        stop.cancel();
+    });
+
+    assert!(client.next().is_none());
+    stopper.join().unwrap();
+}
@@ -0,0 +1,72 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use taptap::control::{self, Command, Server};
+
+/// Send `command` from another thread, handling it with `handler` until the reply arrives.
+fn round_trip(
+    server: &Server,
+    command: Command,
+    mut handler: impl FnMut(Command) -> String,
+) -> String {
+    let addr = server.local_addr();
+    let client = std::thread::spawn(move || control::send(addr, command).unwrap());
+
+    let start = Instant::now();
+    while !client.is_finished() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        server.handle_pending(&mut handler);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    client.join().unwrap()
+}
+
+#[test]
+fn commands() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+
+    let mut handled = Vec::new();
+    for command in Command::ALL {
+        let reply = round_trip(&server, command, |command| {
+            handled.push(command);
+            format!("handled {}", command)
+        });
+        assert_eq!(reply, format!("handled {}\n", command));
+    }
+    assert_eq!(handled, Command::ALL);
+}
+
+#[test]
+fn dump_counters() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let mut rx = taptap::pipeline(taptap::observer::Observer::default());
+    rx.extend_from_slice(b"\x00\xff\x7e\x07\x12\x01");
+
+    let reply = round_trip(&server, Command::DumpCounters, |_| {
+        serde_json::to_string(&taptap::Counters::snapshot(&rx)).unwrap()
+    });
+    let counters: taptap::Counters = serde_json::from_str(&reply).unwrap();
+    assert_eq!(counters, taptap::Counters::snapshot(&rx));
+}
+
+#[test]
+fn unknown_command() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream.write_all(b"reticulate-splines\n").unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).unwrap();
+    assert_eq!(reply, "error: unknown command \"reticulate-splines\"\n");
+
+    // Nothing reaches the handler
+    server.handle_pending(|command| panic!("unexpected {}", command));
+}
+
+#[test]
+fn command_names() {
+    for command in Command::ALL {
+        assert_eq!(command.to_string().parse(), Ok(command));
+    }
+    assert!("dump".parse::<Command>().is_err());
+}
@@ -0,0 +1,179 @@
+//! Polling gateways in place of a controller, against a mock gateway.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use taptap::gateway::link::{Address, Frame, Type};
+use taptap::gateway::transport::{
+    Controller, ControllerConfig, ControllerError, ReceiveRequest, ReceiveResponse, RequestConfig,
+};
+use taptap::gateway::GatewayID;
+use taptap::observer::event::Event;
+use taptap::observer::{EventSink, Observer};
+use taptap::pv::application::PacketType;
+use taptap::pv::link::DSN;
+use taptap::pv::network::ReceivedPacketHeader;
+use taptap::pv::physical::RSSI;
+use taptap::pv::{NodeID, ShortAddress, SlotCounter};
+use taptap::testing::roundtrip::Measurement;
+use taptap::testing::{MockConnection, MockGateway};
+use zerocopy::{FromBytes, IntoBytes};
+
+fn gateway_id() -> GatewayID {
+    GatewayID::try_from(0x1201).unwrap()
+}
+
+fn config() -> ControllerConfig {
+    ControllerConfig {
+        poll_interval: Duration::from_millis(10),
+        ping_interval: Duration::from_secs(60),
+        listen: Duration::from_millis(20),
+        request: RequestConfig {
+            timeout: Duration::from_millis(20),
+            retries: 1,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+impl EventSink for Recorder {
+    fn event(&mut self, event: Event) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+/// A gateway which answers pings, and answers each receive request with a power report from one
+/// node, advancing its packet number each time.
+fn gateway(packet_numbers: Arc<Mutex<Vec<u16>>>) -> MockGateway {
+    let mut packet_number = 0x0100u16;
+    MockGateway::new(move |frame| {
+        if frame.address != Address::To(gateway_id()) {
+            return None;
+        }
+        match frame.frame_type {
+            Type::PING_REQUEST => Some(Frame {
+                address: Address::From(gateway_id()),
+                frame_type: Type::PING_RESPONSE,
+                payload: frame.payload.clone(),
+            }),
+            Type::RECEIVE_REQUEST => {
+                let request = ReceiveRequest::ref_from_bytes(&frame.payload).unwrap();
+                packet_numbers
+                    .lock()
+                    .unwrap()
+                    .push(request.packet_number.get());
+                packet_number += 1;
+
+                let slot_counter = SlotCounter::from(0x1000 + packet_number);
+                let report = Measurement {
+                    voltage_in: 30.0,
+                    voltage_out: 29.0,
+                    current: 6.5,
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                }
+                .encode(slot_counter);
+                let mut packet = ReceivedPacketHeader {
+                    packet_type: PacketType::POWER_REPORT,
+                    node_address: NodeID::try_from(2).unwrap().into(),
+                    short_address: ShortAddress(0x0000.into()),
+                    dsn: DSN(packet_number as u8),
+                    data_length: report.as_bytes().len() as u8,
+                }
+                .as_bytes()
+                .to_vec();
+                packet.extend_from_slice(report.as_bytes());
+
+                let response = ReceiveResponse {
+                    rx_buffers_used: Some(0x00),
+                    tx_buffers_free: Some(0x0E),
+                    unknown_a: None,
+                    unknown_b: None,
+                    packet_number_high: Some(packet_number.to_be_bytes()[0]),
+                    packet_number,
+                    slot_counter,
+                };
+                Some(Frame {
+                    address: Address::From(gateway_id()),
+                    frame_type: Type::RECEIVE_RESPONSE,
+                    payload: response.encode(&packet),
+                })
+            }
+            _ => None,
+        }
+    })
+}
+
+#[test]
+fn polls_and_reports_power() {
+    let packet_numbers = Arc::new(Mutex::new(Vec::new()));
+    let mut controller = Controller::new(gateway(packet_numbers.clone()), config(), [gateway_id()]);
+    controller.check_bus_idle().unwrap();
+
+    let events = Recorder::default();
+    let mut observer = Observer::default();
+    observer.set_event_sink(events.clone());
+    let mut rx = taptap::pipeline(observer);
+
+    let start = Instant::now();
+    for i in 0..3 {
+        controller
+            .poll(start + config().poll_interval * i, rx.sink_mut())
+            .unwrap();
+    }
+
+    // Each receive request acknowledges the previous response's packet number
+    assert_eq!(*packet_numbers.lock().unwrap(), [0x0000, 0x0101, 0x0102]);
+    assert_eq!(controller.packet_number(gateway_id()), Some(0x0103));
+
+    // Pinged once, since the ping interval hasn't elapsed since
+    let counters = controller.requester().counters();
+    assert_eq!(counters.requests, 4);
+    assert_eq!(counters.responses, 4);
+
+    let power_reports = events
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| matches!(event, Event::PowerReport(_)))
+        .count();
+    assert_eq!(power_reports, 3);
+}
+
+#[test]
+fn tolerates_silent_gateways() {
+    let mut controller = Controller::new(MockGateway::new(|_| None), config(), [gateway_id()]);
+    let mut frames: Vec<Frame> = Vec::new();
+    controller.poll(Instant::now(), &mut frames).unwrap();
+
+    // The requests were made, and passed on, but nothing answered
+    assert_eq!(frames.len(), 2);
+    assert!(frames
+        .iter()
+        .all(|frame| frame.address == Address::To(gateway_id())));
+    assert_eq!(controller.requester().counters().timeouts, 2);
+    assert_eq!(controller.packet_number(gateway_id()), Some(0));
+}
+
+#[test]
+fn refuses_a_busy_bus() {
+    // Another controller's ping request, already on the bus
+    let ping = [
+        0x00, 0xFF, 0xFF, 0x7E, 0x07, 0x12, 0x01, 0x0B, 0x00, 0x01, 0xFE, 0x83, 0x7E, 0x08,
+    ];
+    let conn = MockConnection::new().then_read(ping);
+    let writes = conn.write_log();
+    let mut controller = Controller::new(conn, config(), [gateway_id()]);
+
+    assert!(matches!(
+        controller.check_bus_idle(),
+        Err(ControllerError::OtherController {
+            address: Address::To(_),
+            frame_type: Type::PING_REQUEST,
+        })
+    ));
+    assert!(writes.contents().is_empty());
+}
@@ -0,0 +1,61 @@
+//! Each feature builds on its own, as `cargo hack --each-feature` would check.
+//!
+//! Checking every combination takes minutes, so this is ignored by default. Run it with
+//! `cargo test --test feature_matrix -- --ignored`.
+
+use std::path::Path;
+use std::process::Command;
+
+/// Every feature listed in `Cargo.toml`, each of which must build with no others.
+const FEATURES: &[&str] = &[
+    "parsers",
+    "serde",
+    "schema",
+    "observer",
+    "capture",
+    "serialport",
+    "active",
+    "mqtt",
+    "metrics",
+    "web",
+    "tokio",
+    "tokio-serial",
+    "cli",
+];
+
+fn check(features: Option<&str>) -> bool {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command
+        .arg("check")
+        .arg("--all-targets")
+        .arg("--manifest-path")
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        .env("RUSTFLAGS", "-D warnings")
+        // Use a separate target directory, so as not to wait on the lock held by `cargo test`
+        .env(
+            "CARGO_TARGET_DIR",
+            Path::new(env!("CARGO_TARGET_TMPDIR")).join("feature-matrix"),
+        );
+    if let Some(features) = features {
+        command.args(["--no-default-features", "--features", features]);
+    }
+
+    let status = command.status().expect("running cargo");
+    status.success()
+}
+
+#[test]
+#[ignore]
+fn each_feature() {
+    let mut failures = Vec::new();
+    for features in [None, Some("")]
+        .into_iter()
+        .chain(FEATURES.iter().copied().map(Some))
+    {
+        if !check(features) {
+            failures.push(features.map_or("default".to_string(), |f| format!("{:?}", f)));
+        }
+    }
+    assert!(failures.is_empty(), "failed to build: {:?}", failures);
+}
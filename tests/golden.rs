@@ -0,0 +1,178 @@
+//! Decoded output of the bundled fixtures, compared byte for byte against golden files.
+//!
+//! Each capture in `examples/fixtures` is replayed with `--deterministic`, and its output compared
+//! to the file of the same name in `tests/golden`. When a change to decoding is intended, set
+//! `TAPTAP_UPDATE_GOLDEN=1` to rewrite the golden files, and review the diff:
+//!
+//! ```text
+//! TAPTAP_UPDATE_GOLDEN=1 cargo test --features cli --test golden
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const UPDATE: &str = "TAPTAP_UPDATE_GOLDEN";
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures");
+    let mut fixtures: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "taptap"))
+        .collect();
+    fixtures.sort();
+    fixtures
+}
+
+fn replay(fixture: &Path, tz: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_taptap"))
+        .args([
+            "--quiet",
+            "replay",
+            "--deterministic",
+            "--diagnostics",
+            "stdout",
+        ])
+        .arg("--file")
+        .arg(fixture)
+        .env("TZ", tz)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "replaying {:?} failed", fixture);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn golden() {
+    let fixtures = fixtures();
+    assert!(!fixtures.is_empty());
+
+    for fixture in fixtures {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(fixture.file_stem().unwrap())
+            .with_extension("jsonl");
+        let output = replay(&fixture, "UTC");
+
+        if std::env::var_os(UPDATE).is_some() {
+            std::fs::write(&golden, &output).unwrap();
+            continue;
+        }
+
+        let expected = std::fs::read_to_string(&golden).unwrap_or_else(|e| {
+            panic!("reading {:?}: {}; set {}=1 to create it", golden, e, UPDATE)
+        });
+        assert!(
+            output == expected,
+            "replaying {:?} no longer matches {:?}; set {}=1 to update it if this is intended\n{}",
+            fixture,
+            golden,
+            UPDATE,
+            diff(&expected, &output),
+        );
+    }
+}
+
+#[test]
+fn independent_of_time_zone() {
+    for fixture in fixtures() {
+        assert_eq!(
+            replay(&fixture, "UTC"),
+            replay(&fixture, "Pacific/Chatham"),
+            "replaying {:?}",
+            fixture
+        );
+    }
+}
+
+/// Compare the sample fixture against a baseline, returning the exit code and output.
+fn compare(baseline: &str, allow: &[&str]) -> (i32, String) {
+    let path = std::env::temp_dir().join(format!(
+        "taptap-compare-{}-{}.jsonl",
+        std::process::id(),
+        allow.join("-")
+    ));
+    std::fs::write(&path, baseline).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_taptap"))
+        .args(["--quiet", "compare", "--file"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/sample.taptap"))
+        .arg("--baseline")
+        .arg(&path)
+        .args(allow.iter().flat_map(|allow| ["--allow", allow]))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).ok();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn compare_against_baseline() {
+    let golden = std::fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/sample.jsonl"),
+    )
+    .unwrap();
+    let events = golden.lines().count();
+
+    // The golden file is the baseline the current code produces
+    let (code, output) = compare(&golden, &[]);
+    assert_eq!(code, 0, "{}", output);
+    assert_eq!(
+        output.trim_end(),
+        format!("{} unchanged, 0 changed, 0 added, 0 removed", events)
+    );
+
+    // Drop one power report from the baseline, and change another's current
+    let mut lines: Vec<String> = golden.lines().map(String::from).collect();
+    let reports: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].contains("\"voltage_in\""))
+        .collect();
+    lines[reports[1]] = lines[reports[1]].replace("\"current\":", "\"current\":1");
+    lines.remove(reports[0]);
+    let baseline = lines.join("\n");
+
+    let (code, output) = compare(&baseline, &[]);
+    assert_eq!(code, 1, "{}", output);
+    assert!(output.contains("\n    current: 1"), "{}", output);
+    assert!(
+        output.trim_end().ends_with(&format!(
+            "{} unchanged, 1 changed, 1 added, 0 removed",
+            events - 2
+        )),
+        "{}",
+        output
+    );
+
+    // Expected kinds of difference pass
+    assert_eq!(compare(&baseline, &["changed,added"]).0, 0);
+    assert_eq!(compare(&baseline, &["changed"]).0, 1);
+}
+
+/// The lines which differ, for a readable failure.
+fn diff(expected: &str, actual: &str) -> String {
+    let mut expected = expected.lines();
+    let mut actual = actual.lines();
+    let mut diff = String::new();
+    for line in 1.. {
+        match (expected.next(), actual.next()) {
+            (None, None) => break,
+            (e, a) if e == a => {}
+            (e, a) => {
+                diff += &format!("line {}:\n", line);
+                if let Some(e) = e {
+                    diff += &format!("- {}\n", e);
+                }
+                if let Some(a) = a {
+                    diff += &format!("+ {}\n", a);
+                }
+            }
+        }
+    }
+    diff
+}
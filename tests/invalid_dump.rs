@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+use taptap::gateway::link::{self, Address, Frame};
+use taptap::gateway::transport::{InvalidFrameReason, ReceiveRequest, ReceiveResponse};
+use taptap::gateway::GatewayID;
+use taptap::observer::invalid_dump::{Invalid, InvalidDump, InvalidDumpRecord};
+use taptap::observer::Observer;
+use taptap::pv::link::DSN;
+use taptap::pv::network::ReceivedPacketHeader;
+use taptap::pv::{NodeID, PacketType, ShortAddress, SlotCounter};
+use zerocopy::IntoBytes;
+
+fn gateway_id() -> GatewayID {
+    GatewayID::try_from(0x1201).unwrap()
+}
+
+fn receive_request(packet_number: u16) -> Vec<u8> {
+    Frame::from((gateway_id(), &ReceiveRequest::new(packet_number))).encode()
+}
+
+fn receive_response(payload: Vec<u8>) -> Vec<u8> {
+    Frame {
+        address: Address::From(gateway_id()),
+        frame_type: link::Type::RECEIVE_RESPONSE,
+        payload,
+    }
+    .encode()
+}
+
+/// Traffic with one of each kind of problem, in the chunks in which it was read.
+fn chunks() -> Vec<Vec<u8>> {
+    // A receive response whose status type is unknown
+    let malformed = [
+        receive_request(0x0101),
+        receive_response(vec![0x12, 0x34, 0x00]),
+    ]
+    .concat();
+
+    // A frame with one bit flipped in its checksum, which precedes the closing `7e 08`
+    let mut corrupted = receive_request(0x0102);
+    let index = corrupted.len() - 3;
+    corrupted[index] ^= 0x01;
+
+    // A power report three bytes long, carried in an otherwise valid receive response
+    let data = [0x01, 0x02, 0x03];
+    let mut packet = ReceivedPacketHeader {
+        packet_type: PacketType::POWER_REPORT,
+        node_address: NodeID::try_from(2).unwrap().into(),
+        short_address: ShortAddress(0x0000.into()),
+        dsn: DSN(1),
+        data_length: data.len() as u8,
+    }
+    .as_bytes()
+    .to_vec();
+    packet.extend_from_slice(&data);
+    let response = ReceiveResponse {
+        rx_buffers_used: Some(0x00),
+        tx_buffers_free: Some(0x0E),
+        unknown_a: None,
+        unknown_b: None,
+        packet_number_high: Some(0x01),
+        packet_number: 0x0103,
+        slot_counter: SlotCounter::from(0x0100),
+    };
+    let truncated = [
+        receive_request(0x0103),
+        receive_response(response.encode(&packet)),
+    ]
+    .concat();
+
+    vec![malformed, corrupted, truncated]
+}
+
+fn replay(bytes: &[u8]) -> taptap::Pipeline<Observer> {
+    let mut rx = taptap::pipeline(Observer::default());
+    rx.extend_from_slice(bytes);
+    rx
+}
+
+#[test]
+fn roundtrip() {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("taptap-invalid-dump-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut rx = taptap::pipeline(Observer::default());
+    let observer = rx.sink_mut().sink_mut().sink_mut();
+    observer.set_invalid_dump(InvalidDump::new(&dir, 10).unwrap());
+    for chunk in chunks() {
+        rx.sink_mut().sink_mut().sink_mut().bytes_received(&chunk);
+        rx.extend_from_slice(&chunk);
+    }
+
+    let mut records: Vec<(String, InvalidDumpRecord)> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            let record = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            (name, record)
+        })
+        .collect();
+    records.sort_by(|(a, _), (b, _)| a.cmp(b));
+    assert_eq!(records.len(), 3);
+    for (name, _) in &records {
+        assert!(name.ends_with(".json"), "{}", name);
+    }
+    let [(frame_name, frame), (checksum_name, checksum), (packet_name, packet)] =
+        <[_; 3]>::try_from(records).unwrap();
+    assert!(frame_name.ends_with("-0000-frame.json"), "{}", frame_name);
+    assert!(
+        checksum_name.ends_with("-0001-checksum.json"),
+        "{}",
+        checksum_name
+    );
+    assert!(
+        packet_name.ends_with("-0002-packet.json"),
+        "{}",
+        packet_name
+    );
+
+    // The malformed receive response reproduces from its context, which includes the request the
+    // transport layer needs to expect it
+    let Invalid::Frame { reason, .. } = frame.invalid else {
+        panic!("{:?}", frame.invalid);
+    };
+    assert_eq!(reason, InvalidFrameReason::Malformed);
+    assert_eq!(
+        frame.invalid.frame().unwrap(),
+        Frame {
+            address: Address::From(gateway_id()),
+            frame_type: link::Type::RECEIVE_RESPONSE,
+            payload: vec![0x12, 0x34, 0x00],
+        }
+    );
+    let rx = replay(&frame.context_bytes().unwrap());
+    assert_eq!(
+        rx.sink().sink().sink().counters().invalid_frames,
+        [(InvalidFrameReason::Malformed, 1)].into()
+    );
+
+    // The corrupted frame reproduces by itself
+    let mut link_rx = link::Receiver::new(Vec::<Frame>::new());
+    link_rx.extend_from_slice(&checksum.invalid.bytes().unwrap());
+    assert_eq!(link_rx.counters().checksums, 1);
+    assert_eq!(link_rx.sink(), &Vec::<Frame>::new());
+    assert!(checksum
+        .context_bytes()
+        .unwrap()
+        .ends_with(&checksum.invalid.bytes().unwrap()));
+
+    // The truncated power report reproduces from its context
+    assert_eq!(
+        packet.invalid,
+        Invalid::Packet {
+            gateway: gateway_id(),
+            node: NodeID::try_from(2).unwrap(),
+            packet_type: PacketType::POWER_REPORT.0,
+            data: "010203".into(),
+        }
+    );
+    let rx = replay(&packet.context_bytes().unwrap());
+    assert_eq!(rx.sink().sink().counters().invalid_power_reports, 1);
+    assert_eq!(rx.sink().sink().counters().power_reports, 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn max_files() {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("taptap-invalid-dump-max-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut rx = taptap::pipeline(Observer::default());
+    let observer = rx.sink_mut().sink_mut().sink_mut();
+    observer.set_invalid_dump(InvalidDump::new(&dir, 2).unwrap());
+    for chunk in chunks() {
+        rx.sink_mut().sink_mut().sink_mut().bytes_received(&chunk);
+        rx.extend_from_slice(&chunk);
+    }
+
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
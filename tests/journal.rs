@@ -0,0 +1,150 @@
+//! Journaling an observer's events, querying them, and warm-starting from a checkpoint.
+
+use std::time::{Duration, Instant, SystemTime};
+use taptap::barcode::Barcode;
+use taptap::gateway::GatewayID;
+use taptap::observer::clock::ManualClock;
+use taptap::observer::event::{Event, EventKind};
+use taptap::observer::journal::{self, Journal, NodeFilter, Query, Record, Retention};
+use taptap::observer::rate_limit::RateLimits;
+use taptap::observer::{diagnostic, Config, Observer};
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+fn node_address(id: u16) -> LongAddress {
+    LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8])
+}
+
+fn slot_counter(absolute_slot: u32) -> SlotCounter {
+    let absolute_slot = absolute_slot % 48000;
+    let epoch = (absolute_slot / 12000) as u16;
+    let slot_number = (absolute_slot % 12000) as u16;
+    SlotCounter::from(epoch << 14 | slot_number)
+}
+
+/// Two nodes reporting every 20 seconds, after an enumeration and a node table walk.
+fn scenario() -> Scenario {
+    let nodes: Vec<Node> = (2..4u16)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: node_address(id),
+        })
+        .collect();
+    let gateway = Gateway {
+        id: GatewayID::try_from(0x1201).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+        version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r".into(),
+        nodes: nodes.clone(),
+    };
+    let power_reports = (0..4u16)
+        .flat_map(|round| {
+            nodes.iter().enumerate().map(move |(i, node)| PowerReport {
+                gateway_id: GatewayID::try_from(0x1201).unwrap(),
+                node_id: node.id,
+                // 4000 slots is 20 seconds
+                slot_counter: slot_counter(u32::from(round) * 4000 + i as u32 * 2000),
+                measurement: Measurement {
+                    voltage_in: 30.0,
+                    voltage_out: 29.0,
+                    current: 6.5 + f64::from(round),
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                },
+            })
+        })
+        .collect();
+    Scenario {
+        gateways: vec![gateway],
+        power_reports,
+        ..Scenario::new(start())
+    }
+}
+
+#[test]
+fn journal() {
+    let dir = std::env::temp_dir().join(format!("taptap-journal-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let scenario = scenario();
+
+    let clock = ManualClock::new(start());
+    let mut observer = Observer::with_event_sink(Vec::<Event>::new());
+    observer.set_clock(clock.clone());
+    observer.set_config(Config {
+        rate_limits: RateLimits {
+            global: None,
+            per_node: None,
+        },
+        ..Default::default()
+    });
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    observer
+        .set_journal(Journal::open(&dir, Retention::default(), Duration::from_secs(60)).unwrap());
+    let mut rx = taptap::pipeline(observer);
+
+    let stream = scenario.encode();
+    for (i, &(offset, time)) in stream.times.iter().enumerate() {
+        let end = stream
+            .times
+            .get(i + 1)
+            .map_or(stream.bytes.len(), |(end, _)| *end);
+        clock.set(time);
+        rx.extend_from_slice(&stream.bytes[offset..end]);
+        rx.sink_mut()
+            .sink_mut()
+            .sink_mut()
+            .save_state_if_due(Instant::now());
+    }
+    let observer = rx.sink_mut().sink_mut().sink_mut();
+    observer.shutdown();
+    let state = observer.persistent_state().clone();
+    drop(rx);
+
+    // Every event was journaled
+    let events: Vec<Event> = journal::Reader::open(&dir)
+        .unwrap()
+        .filter_map(|record| match record.unwrap() {
+            Record::Event(event) => Some(event),
+            Record::Checkpoint(_) => None,
+        })
+        .collect();
+    assert_eq!(events, scenario.expected_events());
+
+    // Queries select by node and kind
+    let query = Query {
+        node: Some(Barcode::from(node_address(3)).to_string().parse().unwrap()),
+        kinds: [EventKind::PowerReport].into_iter().collect(),
+        ..Default::default()
+    };
+    let selected: Vec<&Event> = events.iter().filter(|event| query.matches(event)).collect();
+    assert_eq!(selected.len(), 4);
+    assert!(selected.iter().all(|event| matches!(
+        event,
+        Event::PowerReport(report) if report.node.address == Some(node_address(3))
+    )));
+
+    // Node IDs match the node table too
+    let query = Query {
+        node: Some(NodeFilter::Id(NodeID::try_from(2).unwrap())),
+        ..Default::default()
+    };
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, Event::NodeTable(_)) && query.matches(event)));
+
+    // The last checkpoint holds the state at shutdown, including the node table, so that an
+    // observer can start from it
+    let checkpoint = journal::last_checkpoint(&dir).unwrap().unwrap();
+    assert_eq!(checkpoint.state, state);
+    assert_eq!(
+        Observer::from_persistent_state(checkpoint.state).persistent_state(),
+        &state
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
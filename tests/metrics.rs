@@ -0,0 +1,147 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+use taptap::barcode::Barcode;
+use taptap::gateway::GatewayID;
+use taptap::observer::event::Event;
+use taptap::observer::EventSink;
+use taptap::output::metrics::{MetricsSink, Registry, Server};
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+/// Every event from a gateway whose nodes each report once.
+fn events(count: u16) -> Vec<Event> {
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let nodes: Vec<Node> = (2..2 + count)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let power_reports = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| PowerReport {
+            gateway_id,
+            node_id: node.id,
+            slot_counter: SlotCounter::from(i as u16 * 500),
+            measurement: Measurement {
+                voltage_in: 30.0,
+                voltage_out: 29.5,
+                current: 6.5,
+                dc_dc_duty_cycle: 1.0,
+                temperature: 25.0,
+                rssi: RSSI(120),
+            },
+        })
+        .collect();
+    let scenario = Scenario {
+        gateways: vec![Gateway {
+            id: gateway_id,
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+            version: "Mgate Version G8.59\r".into(),
+            nodes,
+        }],
+        power_reports,
+        ..Scenario::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200))
+    };
+    scenario.run().events
+}
+
+fn get(server: &Server, path: &str) -> String {
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: text/plain\r\n\r\n",
+        path
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serves_metrics() {
+    let events = events(3);
+    let registry = Registry::new();
+    let (tx, rx) = mpsc::channel();
+    let mut sink = MetricsSink::new(registry.clone(), tx);
+    for event in &events {
+        sink.event(event.clone());
+    }
+
+    // Every event passes through
+    drop(sink);
+    assert_eq!(rx.iter().count(), events.len());
+
+    let mut counters = taptap::Counters::default();
+    counters.link.frames = 7;
+    counters.application.power_reports = 3;
+    registry.set_counters(counters);
+
+    let server = Server::bind("127.0.0.1:0", registry).unwrap();
+    let response = get(&server, "/metrics");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+
+    // Every sample is a name, optional labels, and a number
+    for line in body.lines().filter(|line| !line.starts_with('#')) {
+        let (_, value) = line.rsplit_once(' ').unwrap();
+        assert!(value.parse::<f64>().is_ok(), "{:?}", line);
+    }
+
+    for event in &events {
+        let Event::PowerReport(report) = event else {
+            continue;
+        };
+        let labels = format!(
+            "gateway=\"4609\",node=\"{}\",barcode=\"{}\"",
+            u16::from(report.node.id),
+            Barcode::from(report.node.address.unwrap())
+        );
+        for sample in [
+            format!("taptap_node_voltage_in_volts{{{}}} 30\n", labels),
+            format!("taptap_node_voltage_out_volts{{{}}} 29.5\n", labels),
+            format!("taptap_node_current_amperes{{{}}} 6.5\n", labels),
+            format!("taptap_node_temperature_celsius{{{}}} 25\n", labels),
+            format!("taptap_node_rssi{{{}}} 120\n", labels),
+            format!("taptap_node_dc_dc_duty_cycle{{{}}} 1\n", labels),
+            format!("taptap_node_last_report_age_seconds{{{}}} ", labels),
+        ] {
+            assert!(body.contains(&sample), "missing {:?} in:\n{}", sample, body);
+        }
+    }
+    assert!(body.contains("# TYPE taptap_link_frames_total counter\ntaptap_link_frames_total 7\n"));
+    assert!(body.contains("taptap_application_power_reports_total 3\n"));
+    assert!(body.contains("taptap_transport_receive_packets_total 0\n"));
+
+    assert!(get(&server, "/").starts_with("HTTP/1.1 404 "));
+}
+
+#[test]
+fn report_age() {
+    let events = events(1);
+    let Some(Event::PowerReport(report)) = events
+        .iter()
+        .find(|event| matches!(event, Event::PowerReport(_)))
+    else {
+        unreachable!()
+    };
+    let registry = Registry::new();
+    registry.power_report(report);
+
+    let rendered = registry.render(report.timestamp + chrono::TimeDelta::milliseconds(90_500));
+    let sample = format!(
+        "taptap_node_last_report_age_seconds{{gateway=\"4609\",node=\"2\",barcode=\"{}\"}} 90.5\n",
+        Barcode::from(report.node.address.unwrap())
+    );
+    assert!(rendered.contains(&sample), "{}", rendered);
+
+    // A report from the future is no age at all
+    let rendered = registry.render(report.timestamp - chrono::TimeDelta::seconds(5));
+    assert!(rendered.contains("} 0\n"));
+}
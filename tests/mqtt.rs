@@ -0,0 +1,256 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+use taptap::barcode::Barcode;
+use taptap::gateway::GatewayID;
+use taptap::observer::event::Event;
+use taptap::observer::EventSink;
+use taptap::output::mqtt::{Config, MqttSink};
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+/// Power report events from a gateway whose nodes each report once.
+fn power_reports(count: u16) -> Vec<Event> {
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let nodes: Vec<Node> = (2..2 + count)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let power_reports = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| PowerReport {
+            gateway_id,
+            node_id: node.id,
+            slot_counter: SlotCounter::from(i as u16 * 500),
+            measurement: Measurement {
+                voltage_in: 30.0,
+                voltage_out: 29.0,
+                current: 6.5,
+                dc_dc_duty_cycle: 1.0,
+                temperature: 25.0,
+                rssi: RSSI(120),
+            },
+        })
+        .collect();
+    let scenario = Scenario {
+        gateways: vec![Gateway {
+            id: gateway_id,
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+            version: "Mgate Version G8.59\r".into(),
+            nodes,
+        }],
+        power_reports,
+        ..Scenario::new(start())
+    };
+    let events: Vec<Event> = scenario
+        .run()
+        .events
+        .into_iter()
+        .filter(|event| matches!(event, Event::PowerReport(_)))
+        .collect();
+    assert_eq!(events.len(), count as usize);
+    events
+}
+
+/// The topic to which a power report should be published.
+fn topic(event: &Event) -> String {
+    let Event::PowerReport(report) = event else {
+        unreachable!()
+    };
+    format!(
+        "taptap/4609/{}/power",
+        Barcode::from(report.node.address.unwrap())
+    )
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Packet {
+    Connect {
+        client_id: String,
+        will_topic: String,
+        will_message: String,
+    },
+    Publish {
+        topic: String,
+        payload: String,
+        retain: bool,
+    },
+    PingRequest,
+    Disconnect,
+}
+
+fn take_string(contents: &mut &[u8]) -> String {
+    let length = u16::from_be_bytes([contents[0], contents[1]]) as usize;
+    let string = String::from_utf8(contents[2..2 + length].to_vec()).unwrap();
+    *contents = &contents[2 + length..];
+    string
+}
+
+/// Read one packet from a client, or `None` if it hung up.
+fn read_packet(stream: &mut TcpStream) -> Option<Packet> {
+    let mut first = [0u8];
+    if stream.read(&mut first).ok()? == 0 {
+        return None;
+    }
+    let mut length = 0usize;
+    for shift in (0..).step_by(7) {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).unwrap();
+        length |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut contents = vec![0u8; length];
+    stream.read_exact(&mut contents).unwrap();
+    let mut contents = contents.as_slice();
+
+    Some(match first[0] >> 4 {
+        1 => {
+            assert_eq!(take_string(&mut contents), "MQTT");
+            assert_eq!(contents[0], 4);
+            let flags = contents[1];
+            assert_eq!(flags & 0x24, 0x24, "will must be set and retained");
+            contents = &contents[4..];
+            Packet::Connect {
+                client_id: take_string(&mut contents),
+                will_topic: take_string(&mut contents),
+                will_message: take_string(&mut contents),
+            }
+        }
+        3 => Packet::Publish {
+            topic: take_string(&mut contents),
+            payload: String::from_utf8(contents.to_vec()).unwrap(),
+            retain: first[0] & 0x01 != 0,
+        },
+        12 => Packet::PingRequest,
+        14 => Packet::Disconnect,
+        other => panic!("unexpected packet type {}", other),
+    })
+}
+
+/// Read packets until the client hangs up, ignoring pings.
+fn read_session(stream: &mut TcpStream) -> Vec<Packet> {
+    std::iter::from_fn(|| read_packet(stream))
+        .filter(|packet| *packet != Packet::PingRequest)
+        .collect()
+}
+
+fn publish(topic: &str, payload: &str, retain: bool) -> Packet {
+    Packet::Publish {
+        topic: topic.into(),
+        payload: payload.into(),
+        retain,
+    }
+}
+
+fn wait_for(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn publishes_power_reports() {
+    let events = power_reports(3);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let connect = read_packet(&mut stream).unwrap();
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+        (connect, read_session(&mut stream))
+    });
+
+    let mut config = Config::from_url(&format!("mqtt://127.0.0.1:{}", port), "taptap").unwrap();
+    config.client_id = "taptap-test".into();
+    let mut sink = MqttSink::new(config);
+    for event in &events {
+        sink.event(event.clone());
+    }
+    wait_for("publishing", || sink.counters().published == 3);
+    drop(sink);
+
+    let (connect, session) = broker.join().unwrap();
+    assert_eq!(
+        connect,
+        Packet::Connect {
+            client_id: "taptap-test".into(),
+            will_topic: "taptap/status".into(),
+            will_message: "offline".into(),
+        }
+    );
+
+    let mut expected = vec![publish("taptap/status", "online", true)];
+    expected.extend(
+        events
+            .iter()
+            .map(|event| publish(&topic(event), &event.to_json(), false)),
+    );
+    expected.push(publish("taptap/status", "offline", true));
+    expected.push(Packet::Disconnect);
+    assert_eq!(session, expected);
+}
+
+#[test]
+fn reconnects_and_buffers() {
+    let events = power_reports(5);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (go, wait) = mpsc::channel::<()>();
+    let broker = std::thread::spawn(move || {
+        // Refuse the first connection as unauthorized
+        let (mut stream, _) = listener.accept().unwrap();
+        read_packet(&mut stream).unwrap();
+        stream.write_all(&[0x20, 0x02, 0x00, 0x05]).unwrap();
+        drop(stream);
+
+        // Accept the next once told to
+        wait.recv().unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        read_packet(&mut stream).unwrap();
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+        read_session(&mut stream)
+    });
+
+    let mut config = Config::from_url(&format!("mqtt://127.0.0.1:{}", port), "taptap").unwrap();
+    config.max_buffered = 3;
+    config.min_backoff = Duration::from_millis(50);
+    let mut sink = MqttSink::new(config);
+    wait_for("the refused connection", || {
+        sink.counters().connection_failures >= 1
+    });
+
+    for event in &events {
+        sink.event(event.clone());
+    }
+    go.send(()).unwrap();
+    wait_for("reconnection", || sink.counters().published == 3);
+    let counters = sink.counters();
+    assert_eq!(counters.connections, 1);
+    assert_eq!(counters.dropped, 2);
+    drop(sink);
+
+    // The oldest reports were dropped, and the rest published in order
+    let session = broker.join().unwrap();
+    let mut expected = vec![publish("taptap/status", "online", true)];
+    expected.extend(
+        events[2..]
+            .iter()
+            .map(|event| publish(&topic(event), &event.to_json(), false)),
+    );
+    expected.push(publish("taptap/status", "offline", true));
+    expected.push(Packet::Disconnect);
+    assert_eq!(session, expected);
+}
@@ -0,0 +1,202 @@
+//! The prelude alone is enough to embed an observer and to implement a custom sink.
+
+use taptap::prelude::*;
+
+/// An event sink which keeps only power reports.
+#[derive(Debug, Default)]
+struct PowerReports(Vec<PowerReportEvent>);
+
+impl EventSink for PowerReports {
+    fn event(&mut self, event: Event) {
+        if let Event::PowerReport(report) = event {
+            self.0.push(report);
+        }
+    }
+}
+
+/// A sink which counts frames and commands, ignoring everything else.
+#[derive(Debug, Default)]
+struct Tally {
+    commands: usize,
+    power_reports: usize,
+}
+
+impl TransportSink for Tally {
+    fn enumeration_started(&mut self, _enumeration_gateway_id: GatewayID) {}
+    fn gateway_identity_observed(&mut self, _gateway_id: GatewayID, _address: LongAddress) {}
+    fn gateway_version_observed(&mut self, _gateway_id: GatewayID, _version: &str, _raw: &[u8]) {}
+    fn enumeration_ended(&mut self, _gateway_id: GatewayID) {}
+    fn gateway_slot_counter_captured(&mut self, _gateway_id: GatewayID) {}
+    fn gateway_slot_counter_observed(
+        &mut self,
+        _gateway_id: GatewayID,
+        _slot_counter: SlotCounter,
+    ) {
+    }
+    fn packet_received(
+        &mut self,
+        _gateway_id: GatewayID,
+        _header: &ReceivedPacketHeader,
+        _data: &[u8],
+    ) {
+    }
+    fn command_executed(
+        &mut self,
+        _gateway_id: GatewayID,
+        _request: (PacketType, &[u8]),
+        _response: (PacketType, &[u8]),
+    ) {
+        self.commands += 1;
+    }
+    fn command_timed_out(
+        &mut self,
+        _gateway_id: GatewayID,
+        _packet_type: PacketType,
+        _sequence_number: CommandSequenceNumber,
+    ) {
+    }
+    fn gateway_tx_buffers_free_observed(&mut self, _gateway_id: GatewayID, _tx_buffers_free: u8) {}
+}
+
+impl ApplicationSink for Tally {
+    fn string_request(&mut self, _gateway_id: GatewayID, _pv_node_id: NodeID, _request: LossyStr) {}
+    fn string_response(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _response: LossyStr,
+    ) {
+    }
+    fn broadcast(&mut self, _gateway_id: GatewayID, _payload: &Broadcast) {}
+    fn network_status(&mut self, _gateway_id: GatewayID, _status: &NetworkStatusResponse) {}
+    fn gateway_radio_configuration(
+        &mut self,
+        _gateway_id: GatewayID,
+        _config: &RadioConfigurationResponse,
+    ) {
+    }
+    fn pv_configuration(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _config: &PvConfigurationResponse,
+    ) {
+    }
+    fn node_table_page(
+        &mut self,
+        _gateway_id: GatewayID,
+        _start_address: NodeAddress,
+        _nodes: &[NodeTableResponseEntry],
+    ) {
+    }
+    fn topology_report(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _topology_report: &TopologyReport,
+    ) {
+    }
+    fn power_report(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _power_report: &PowerReport,
+    ) {
+        self.power_reports += 1;
+    }
+    fn packet_loss_estimated(
+        &mut self,
+        _gateway_id: GatewayID,
+        _pv_node_id: NodeID,
+        _estimated_loss_pct: f64,
+    ) {
+    }
+}
+
+#[test]
+fn observer() {
+    let mut observer = Observer::default();
+    observer.set_event_sink(PowerReports::default());
+    let mut pipeline: Pipeline = pipeline(observer);
+    pipeline.extend_from_slice(b"\x00\xff\x7e\x07\x12\x01");
+    assert_eq!(Counters::snapshot(&pipeline).link.frames, 0);
+}
+
+#[test]
+fn custom_sink() {
+    let mut pipeline: Pipeline<Tally> = pipeline(Tally::default());
+    pipeline.extend_from_slice(b"\x00\xff\x7e\x07\x12\x01");
+
+    // The layers can be named individually too
+    let application: &ApplicationReceiver<Tally> = pipeline.sink().sink();
+    let _: &TransportReceiver<ApplicationReceiver<Tally>> = pipeline.sink();
+    let _: &LinkReceiver<TransportReceiver<ApplicationReceiver<Tally>>> = &pipeline;
+    assert_eq!(application.sink().commands, 0);
+    assert_eq!(application.sink().power_reports, 0);
+}
+
+#[test]
+fn collect_events() {
+    use std::time::{Duration, SystemTime};
+    use taptap::observer::clock::ManualClock;
+    use taptap::pv::physical::RSSI;
+    use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200);
+    let node = Node {
+        id: NodeID::try_from(2).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]),
+    };
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let scenario = Scenario {
+        gateways: vec![Gateway {
+            id: gateway_id,
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+            version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r".into(),
+            nodes: vec![node],
+        }],
+        power_reports: vec![PowerReport {
+            gateway_id,
+            node_id: node.id,
+            slot_counter: SlotCounter::ZERO,
+            measurement: Measurement {
+                voltage_in: 30.0,
+                voltage_out: 29.0,
+                current: 6.5,
+                dc_dc_duty_cycle: 1.0,
+                temperature: 25.0,
+                rssi: RSSI(120),
+            },
+        }],
+        ..Scenario::new(start)
+    };
+
+    // Plug in a sink which hands events back, and collect them
+    let clock = ManualClock::new(start);
+    let (tx, events) = std::sync::mpsc::channel();
+    let mut observer = Observer::with_event_sink(tx);
+    observer.set_clock(clock.clone());
+    let mut pipeline = pipeline(observer);
+    let stream = scenario.encode();
+    for (i, &(offset, time)) in stream.times.iter().enumerate() {
+        let end = stream
+            .times
+            .get(i + 1)
+            .map_or(stream.bytes.len(), |(end, _)| *end);
+        clock.set(time);
+        pipeline.extend_from_slice(&stream.bytes[offset..end]);
+    }
+    drop(pipeline);
+
+    let events: Vec<Event> = events.into_iter().collect();
+    let reports: Vec<&PowerReportEvent> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::PowerReport(report) => Some(report),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].node.id, node.id);
+    assert_eq!(reports[0].node.address, Some(node.address));
+}
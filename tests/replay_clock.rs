@@ -0,0 +1,162 @@
+//! Replaying a capture whose timestamps step backwards, as when NTP corrects the capturing
+//! machine's clock mid-capture.
+
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+use taptap::capture;
+use taptap::gateway::GatewayID;
+use taptap::observer::clock::{ReplayClock, ReplayClockMode};
+use taptap::observer::diagnostic::{self, Code};
+use taptap::observer::event::Event;
+use taptap::observer::Observer;
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+const STEP: Duration = Duration::from_secs(30);
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+/// A capture of one node reporting every 20 seconds, during which the clock steps back by
+/// [`STEP`] partway through the fourth report.
+fn capture() -> Vec<u8> {
+    let node = Node {
+        id: NodeID::try_from(2).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, 0x02]),
+    };
+    let gateway = Gateway {
+        id: GatewayID::try_from(0x1201).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+        version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r".into(),
+        nodes: vec![node],
+    };
+    let scenario = Scenario {
+        enumerate: false,
+        walk_node_tables: false,
+        power_reports: (0..8u16)
+            .map(|i| PowerReport {
+                gateway_id: gateway.id,
+                node_id: node.id,
+                // 4000 slots is 20 seconds
+                slot_counter: SlotCounter::from((i * 4000 / 12000) << 14 | (i * 4000 % 12000)),
+                measurement: Measurement {
+                    voltage_in: 30.0,
+                    voltage_out: 29.0,
+                    current: 6.5,
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                },
+            })
+            .collect(),
+        gateways: vec![gateway],
+        ..Scenario::new(start())
+    };
+
+    let stream = scenario.encode();
+    let step_at = start() + Duration::from_secs(60);
+    let mut writer = capture::Writer::new(Vec::new()).unwrap();
+    for (i, &(offset, time)) in stream.times.iter().enumerate() {
+        let end = stream
+            .times
+            .get(i + 1)
+            .map_or(stream.bytes.len(), |(end, _)| *end);
+        let bytes = &stream.bytes[offset..end];
+        if time < step_at {
+            writer.write(bytes, time).unwrap();
+        } else if time == step_at {
+            let (before, after) = bytes.split_at(bytes.len() / 2);
+            writer.write(before, time).unwrap();
+            writer.write(after, time - STEP).unwrap();
+        } else {
+            writer.write(bytes, time - STEP).unwrap();
+        }
+    }
+    writer.finish().unwrap()
+}
+
+/// Replay the capture, returning the timestamps of power reports and the diagnostics.
+fn replay(mode: ReplayClockMode) -> (Vec<SystemTime>, Vec<Event>) {
+    let mut clock = ReplayClock::new(mode);
+    let (tx, events) = mpsc::channel();
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clock());
+    observer.set_event_sink(tx);
+    observer.set_diagnostics_output(diagnostic::Output::Events);
+    let mut rx = taptap::pipeline(observer);
+
+    let capture = capture();
+    for record in capture::Reader::new(capture.as_slice()).unwrap() {
+        let (data, timestamp) = record.unwrap();
+        if let Some(step) = clock.set(timestamp) {
+            rx.sink_mut()
+                .sink_mut()
+                .sink_mut()
+                .capture_clock_stepped(&step, mode == ReplayClockMode::Smooth);
+        }
+        rx.extend_from_slice(&data);
+    }
+    drop(rx);
+
+    let (reports, diagnostics): (Vec<_>, Vec<_>) = events
+        .into_iter()
+        .partition(|event| matches!(event, Event::PowerReport(_)));
+    let timestamps = reports
+        .into_iter()
+        .map(|event| match event {
+            Event::PowerReport(report) => report.timestamp.into(),
+            _ => unreachable!(),
+        })
+        .collect();
+    (timestamps, diagnostics)
+}
+
+fn assert_step_diagnosed(diagnostics: &[Event], smoothed: bool) {
+    let steps: Vec<_> = diagnostics
+        .iter()
+        .filter_map(|event| match event {
+            Event::Diagnostic(diagnostic) if diagnostic.code == Code::CaptureClockStepped => {
+                Some(diagnostic)
+            }
+            _ => None,
+        })
+        .collect();
+    let [diagnostic] = steps.as_slice() else {
+        panic!("expected one step: {:?}", diagnostics);
+    };
+    assert_eq!(diagnostic.context["step_seconds"], STEP.as_secs_f64());
+    assert_eq!(diagnostic.context["smoothed"], smoothed);
+    assert!(diagnostic.message.contains("30.000s"));
+}
+
+#[test]
+fn raw() {
+    let (timestamps, diagnostics) = replay(ReplayClockMode::Raw);
+    assert_step_diagnosed(&diagnostics, false);
+
+    assert_eq!(timestamps.len(), 8);
+    assert!(timestamps.windows(2).any(|pair| pair[1] < pair[0]));
+}
+
+#[test]
+fn smooth() {
+    let (timestamps, diagnostics) = replay(ReplayClockMode::Smooth);
+    assert_step_diagnosed(&diagnostics, true);
+
+    assert_eq!(timestamps.len(), 8);
+    assert!(
+        timestamps.windows(2).all(|pair| pair[1] >= pair[0]),
+        "{:?}",
+        timestamps
+    );
+
+    // Once caught up, timestamps match the capture's again
+    let last = *timestamps.last().unwrap();
+    let expected = start() + Duration::from_secs(7 * 20) - STEP;
+    let error = last
+        .duration_since(expected)
+        .unwrap_or_else(|e| e.duration());
+    assert!(error < Duration::from_secs(1), "{:?}", error);
+}
@@ -0,0 +1,143 @@
+//! Sending requests to a gateway and awaiting its responses, against a mock gateway.
+
+use std::time::Duration;
+use taptap::gateway::link::{Address, Frame, Type};
+use taptap::gateway::transport::{
+    CommandRequest, CommandResponse, RequestConfig, RequestError, Requester,
+};
+use taptap::gateway::GatewayID;
+use taptap::pv::application::PacketType;
+use taptap::testing::{MockConnection, MockGateway};
+use zerocopy::{FromBytes, IntoBytes};
+
+fn gateway_id() -> GatewayID {
+    GatewayID::try_from(0x1201).unwrap()
+}
+
+fn config() -> RequestConfig {
+    RequestConfig {
+        timeout: Duration::from_millis(20),
+        retries: 2,
+    }
+}
+
+/// A gateway which answers pings, after ignoring the first `ignore` of them.
+fn pinged(mut ignore: usize) -> MockGateway {
+    MockGateway::new(move |frame| {
+        if frame.frame_type != Type::PING_REQUEST || frame.address != Address::To(gateway_id()) {
+            return None;
+        }
+        if ignore > 0 {
+            ignore -= 1;
+            return None;
+        }
+        Some(Frame {
+            address: Address::From(gateway_id()),
+            frame_type: Type::PING_RESPONSE,
+            payload: frame.payload.clone(),
+        })
+    })
+}
+
+#[test]
+fn ping() {
+    let gateway = pinged(0);
+    let writes = gateway.write_log();
+    let mut requester = Requester::new(gateway, config());
+
+    requester.ping(gateway_id()).unwrap();
+
+    // The same ping request a controller sends
+    assert_eq!(
+        writes.contents(),
+        [0x00, 0xFF, 0xFF, 0x7E, 0x07, 0x12, 0x01, 0x0B, 0x00, 0x01, 0xFE, 0x83, 0x7E, 0x08]
+    );
+    assert_eq!(requester.counters().requests, 1);
+    assert_eq!(requester.counters().responses, 1);
+    assert_eq!(requester.counters().retransmissions, 0);
+}
+
+#[test]
+fn retries() {
+    let mut requester = Requester::new(pinged(2), config());
+    requester.ping(gateway_id()).unwrap();
+    assert_eq!(requester.counters().retransmissions, 2);
+
+    let mut requester = Requester::new(pinged(3), config());
+    assert!(matches!(
+        requester.ping(gateway_id()),
+        Err(RequestError::TimedOut { attempts: 3 })
+    ));
+    assert_eq!(requester.counters().timeouts, 1);
+}
+
+#[test]
+fn command_sequence_numbers() {
+    // A gateway which first answers with a stale sequence number
+    let mut stale = true;
+    let gateway = MockGateway::new(move |frame| {
+        if frame.frame_type != Type::COMMAND_REQUEST {
+            return None;
+        }
+        let (request, data) = CommandRequest::ref_from_prefix(&frame.payload).unwrap();
+        let mut sequence_number = request.sequence_number;
+        if std::mem::take(&mut stale) {
+            sequence_number.0 = sequence_number.0.wrapping_sub(1);
+        }
+
+        let mut payload = CommandResponse {
+            unknown_1: 0x00,
+            tx_buffers_free: 0x0F,
+            unknown_2: 0x00,
+            packet_type: PacketType::STRING_RESPONSE,
+            command_sequence_number: sequence_number,
+        }
+        .as_bytes()
+        .to_vec();
+        payload.extend_from_slice(data);
+        Some(Frame {
+            address: Address::From(gateway_id()),
+            frame_type: Type::COMMAND_RESPONSE,
+            payload,
+        })
+    });
+    let mut requester = Requester::new(gateway, config());
+
+    let response = requester
+        .command(gateway_id(), PacketType::STRING_REQUEST, b"hello")
+        .unwrap();
+    assert_eq!(response, (PacketType::STRING_RESPONSE, b"hello".to_vec()));
+    assert_eq!(requester.counters().retransmissions, 1);
+    assert_eq!(requester.counters().unmatched_frames, 1);
+
+    // The next command takes the next sequence number
+    requester
+        .command(gateway_id(), PacketType::STRING_REQUEST, b"again")
+        .unwrap();
+    assert_eq!(requester.counters().retransmissions, 1);
+}
+
+#[test]
+fn version() {
+    let gateway = MockGateway::new(|frame| {
+        (frame.frame_type == Type::VERSION_REQUEST).then(|| Frame {
+            address: Address::From(gateway_id()),
+            frame_type: Type::VERSION_RESPONSE,
+            payload: b"Mgate Version G8.59\r".to_vec(),
+        })
+    });
+    let mut requester = Requester::new(gateway, config());
+    assert_eq!(
+        requester.version(gateway_id()).unwrap(),
+        b"Mgate Version G8.59\r"
+    );
+}
+
+#[test]
+fn read_only() {
+    let mut requester = Requester::new(MockConnection::new().readonly(), config());
+    assert!(matches!(
+        requester.ping(gateway_id()),
+        Err(RequestError::ReadOnly)
+    ));
+}
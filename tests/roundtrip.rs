@@ -0,0 +1,490 @@
+use std::time::{Duration, SystemTime};
+use taptap::gateway::GatewayID;
+use taptap::observer::config::DuplicateAddresses;
+use taptap::observer::diagnostic::Code;
+use taptap::observer::event::{Event, EventKind};
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{
+    Gateway, Measurement, Node, PowerReport, Scenario, StringResponse,
+};
+use taptap::testing::MockConnection;
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+fn gateway(id: u16, nodes: u16) -> Gateway {
+    Gateway {
+        id: GatewayID::try_from(id).unwrap(),
+        address: LongAddress([
+            0x04,
+            0xC0,
+            0x5B,
+            0x30,
+            0x00,
+            0x02,
+            (id >> 8) as u8,
+            id as u8,
+        ]),
+        version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r".into(),
+        nodes: (2..2 + nodes)
+            .map(|id| Node {
+                id: NodeID::try_from(id).unwrap(),
+                address: LongAddress([
+                    0x04,
+                    0xC0,
+                    0x5B,
+                    0x40,
+                    0x00,
+                    0xA2,
+                    (id >> 8) as u8,
+                    id as u8,
+                ]),
+            })
+            .collect(),
+    }
+}
+
+fn measurement(i: u16) -> Measurement {
+    Measurement {
+        voltage_in: 30.0 + i as f64 * 0.05,
+        voltage_out: 29.0 + i as f64 * 0.1,
+        current: 6.5 + i as f64 * 0.005,
+        dc_dc_duty_cycle: 1.0,
+        temperature: 25.0 - i as f64 * 0.3,
+        rssi: RSSI(120 + i as u8),
+    }
+}
+
+/// One power report from each node of each gateway, twenty seconds apart.
+fn power_reports(gateways: &[Gateway]) -> Vec<PowerReport> {
+    let mut slot = 0u32;
+    let mut reports = Vec::new();
+    for gateway in gateways {
+        for node in &gateway.nodes {
+            reports.push(PowerReport {
+                gateway_id: gateway.id,
+                node_id: node.id,
+                slot_counter: slot_counter(slot),
+                measurement: measurement(reports.len() as u16),
+            });
+            slot += 4000;
+        }
+    }
+    reports
+}
+
+fn slot_counter(absolute_slot: u32) -> SlotCounter {
+    let absolute_slot = absolute_slot % 48000;
+    let epoch = (absolute_slot / 12000) as u16;
+    let slot_number = (absolute_slot % 12000) as u16;
+    SlotCounter::from(epoch << 14 | slot_number)
+}
+
+#[test]
+fn power_reports_only() {
+    let gateways = vec![gateway(0x1201, 3)];
+    let scenario = Scenario {
+        enumerate: false,
+        walk_node_tables: false,
+        power_reports: power_reports(&gateways),
+        gateways,
+        ..Scenario::new(start())
+    };
+
+    let outcome = scenario.assert_roundtrip();
+    assert_eq!(outcome.events.len(), 3);
+    for event in &outcome.events {
+        let Event::PowerReport(event) = event else {
+            panic!("unexpected event: {:?}", event);
+        };
+        assert_eq!(event.gateway.address, None);
+        assert_eq!(event.node.address, None);
+    }
+}
+
+#[test]
+fn enumeration_and_node_table() {
+    let gateways = vec![gateway(0x1201, 30)];
+    let scenario = Scenario {
+        power_reports: power_reports(&gateways),
+        gateways,
+        ..Scenario::new(start())
+    };
+
+    let outcome = scenario.assert_roundtrip();
+
+    // The node table walk is reported page by page, and then as a whole
+    let (node_table_events, power_reports) = outcome.events.split_at(4);
+    for (event, entries) in node_table_events[..3].iter().zip([12, 24, 30]) {
+        let Event::NodeTableProgress(event) = event else {
+            panic!("unexpected event: {:?}", event);
+        };
+        assert_eq!(event.entries_so_far, entries);
+    }
+    let Event::NodeTable(node_table) = &node_table_events[3] else {
+        panic!("unexpected event: {:?}", node_table_events[3]);
+    };
+    assert_eq!(node_table.nodes.len(), 30);
+
+    assert_eq!(power_reports.len(), 30);
+    for event in power_reports {
+        let Event::PowerReport(event) = event else {
+            panic!("unexpected event: {:?}", event);
+        };
+        assert!(event.gateway.address.is_some());
+        assert!(event.node.address.is_some());
+    }
+    assert_eq!(outcome.counters.transport.enumeration_end_responses, 1);
+}
+
+#[test]
+fn multiple_gateways_across_slot_counter_wrap() {
+    // 2 × 40 reports, twenty seconds apart, spans the four minute slot counter period many times
+    let gateways = vec![gateway(0x1201, 40), gateway(0x1202, 40)];
+    let scenario = Scenario {
+        power_reports: power_reports(&gateways),
+        gateways,
+        ..Scenario::new(start())
+    };
+
+    let outcome = scenario.assert_roundtrip();
+    assert_eq!(
+        outcome
+            .events
+            .iter()
+            .filter(|event| matches!(event, Event::PowerReport(_)))
+            .count(),
+        80
+    );
+    assert_eq!(outcome.counters.transport.packet_number_resyncs, 0);
+}
+
+#[test]
+fn node_missing_from_node_table() {
+    let gateways = vec![gateway(0x1201, 2)];
+    let mut power_reports = power_reports(&gateways);
+    power_reports.push(PowerReport {
+        gateway_id: gateways[0].id,
+        node_id: NodeID::try_from(100).unwrap(),
+        slot_counter: slot_counter(8000 + 4000),
+        measurement: measurement(0),
+    });
+    let scenario = Scenario {
+        power_reports,
+        gateways,
+        ..Scenario::new(start())
+    };
+
+    let outcome = scenario.assert_roundtrip();
+    let Some(Event::PowerReport(event)) = outcome.events.last() else {
+        panic!("expected a power report");
+    };
+    assert_eq!(event.node.address, None);
+}
+
+#[test]
+fn read_loop_over_mock_connection() {
+    use std::io::Read;
+    use taptap::observer::clock::ManualClock;
+    use taptap::observer::rate_limit::RateLimits;
+    use taptap::observer::{diagnostic, Config, Observer};
+    use taptap::{gateway, pv};
+
+    let gateways = vec![gateway(0x1201, 5)];
+    let scenario = Scenario {
+        power_reports: power_reports(&gateways),
+        gateways,
+        ..Scenario::new(start())
+    };
+
+    // Read from a connection until EOF, as `taptap observe` does
+    let clock = ManualClock::new(scenario.start);
+    let mut conn: Box<dyn gateway::physical::Connection> = Box::new(MockConnection::from_stream(
+        &scenario.encode(),
+        clock.clone(),
+    ));
+    let mut observer = Observer::default();
+    observer.set_clock(clock);
+    observer.set_config(Config {
+        rate_limits: RateLimits {
+            global: None,
+            per_node: None,
+        },
+        ..Default::default()
+    });
+    let (tx, events) = std::sync::mpsc::channel();
+    observer.set_event_sink(tx);
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+        pv::application::Receiver::new(observer),
+    ));
+
+    let mut buffer = [0u8; 1024];
+    let mut reads = 0;
+    loop {
+        let n = conn.read(&mut buffer).unwrap();
+        if n == 0 {
+            break;
+        }
+        reads += 1;
+        rx.extend_from_slice(&buffer[..n]);
+    }
+    rx.sink_mut().sink_mut().sink_mut().shutdown();
+
+    assert!(reads > 1);
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        scenario.expected_events()
+    );
+    assert_eq!(rx.counters().checksums, 0);
+    assert_eq!(rx.sink().counters().packet_number_resyncs, 0);
+}
+
+#[test]
+fn console_output_under_contention() {
+    use std::io::Write;
+    use taptap::console::Console;
+    use taptap::observer::clock::ManualClock;
+    use taptap::observer::rate_limit::RateLimits;
+    use taptap::observer::{diagnostic, Config, Observer};
+    use taptap::testing::WriteLog;
+    use taptap::{gateway, pv};
+
+    let gateways = vec![gateway(0x1201, 20), gateway(0x1202, 20)];
+    let scenario = Scenario {
+        power_reports: power_reports(&gateways),
+        gateways,
+        ..Scenario::new(start())
+    };
+    // The clock stands still, so no node is seen to go quiet
+    let expected_events = scenario
+        .expected_events()
+        .iter()
+        .filter(|event| event.kind() != EventKind::NodeGap)
+        .count();
+
+    // Events and logs both lead to the same terminal
+    let terminal = WriteLog::default();
+    let console = Console::new(terminal.clone(), terminal.clone());
+
+    let pipeline = {
+        let console = console.clone();
+        std::thread::spawn(move || {
+            let clock = ManualClock::new(scenario.start);
+            let mut observer = Observer::default();
+            observer.set_clock(clock.clone());
+            observer.set_config(Config {
+                rate_limits: RateLimits {
+                    global: None,
+                    per_node: None,
+                },
+                ..Default::default()
+            });
+            observer.set_event_sink(console.out());
+            observer.set_diagnostics_output(diagnostic::Output::Writer(Box::new(console.err())));
+            let mut rx = gateway::link::Receiver::new(gateway::transport::Receiver::new(
+                pv::application::Receiver::new(observer),
+            ));
+
+            let stream = scenario.encode();
+            for chunk in stream.bytes.chunks(7) {
+                rx.extend_from_slice(chunk);
+                std::thread::yield_now();
+            }
+        })
+    };
+
+    // Meanwhile, something logs in dribs and drabs
+    let logger = {
+        let mut err = console.err();
+        std::thread::spawn(move || {
+            for i in 0..200 {
+                let line = format!("[INFO taptap] log line {}\n", i);
+                for chunk in line.as_bytes().chunks(2) {
+                    err.write_all(chunk).unwrap();
+                    std::thread::yield_now();
+                }
+            }
+        })
+    };
+
+    pipeline.join().unwrap();
+    logger.join().unwrap();
+
+    let contents = String::from_utf8(terminal.contents()).unwrap();
+    assert!(contents.ends_with('\n'));
+    let mut events = 0;
+    let mut logs = 0;
+    for line in contents.lines() {
+        if line.starts_with("[INFO taptap] log line ") {
+            logs += 1;
+        } else {
+            serde_json::from_str::<serde_json::Value>(line)
+                .unwrap_or_else(|e| panic!("interleaved line {:?}: {}", line, e));
+            events += 1;
+        }
+    }
+    assert_eq!(logs, 200);
+    assert_eq!(events, expected_events);
+}
+
+/// A node which appears under two gateways, reporting through each in turn.
+fn dual_ownership(duplicate_addresses: DuplicateAddresses) -> (Scenario, Vec<GatewayID>) {
+    let gateways = vec![gateway(0x1201, 1), gateway(0x1202, 1)];
+    assert_eq!(gateways[0].nodes[0].address, gateways[1].nodes[0].address);
+
+    let power_reports = (0..12)
+        .map(|i| PowerReport {
+            gateway_id: gateways[i % 2].id,
+            node_id: gateways[i % 2].nodes[0].id,
+            slot_counter: slot_counter(i as u32 * 4000),
+            measurement: measurement(i as u16),
+        })
+        .collect();
+    let ids = gateways.iter().map(|gateway| gateway.id).collect();
+    let scenario = Scenario {
+        power_reports,
+        gateways,
+        duplicate_addresses,
+        ..Scenario::new(start())
+    };
+    (scenario, ids)
+}
+
+fn home_gateways(outcome: &taptap::testing::roundtrip::Outcome) -> Vec<(GatewayID, GatewayID)> {
+    outcome
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            Event::PowerReport(report) => Some((report.gateway.id, report.node.home_gateway?)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn duplicate_address_follow_reports() {
+    let (scenario, _) = dual_ownership(DuplicateAddresses::FollowReports);
+    let outcome = scenario.run();
+    assert_eq!(outcome.events, scenario.expected_events());
+
+    // Every report is emitted, and the node's home follows it
+    let homes = home_gateways(&outcome);
+    assert_eq!(homes.len(), 12);
+    assert!(homes.iter().all(|(via, home)| via == home));
+
+    // Moving back and forth is reported once
+    assert_eq!(
+        outcome
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.code == Code::NodeGatewayFlapping)
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn duplicate_address_home_only() {
+    let (scenario, gateway_ids) = dual_ownership(DuplicateAddresses::HomeOnly);
+    let outcome = scenario.run();
+
+    // Only reports through the first gateway to report the node are emitted
+    assert_eq!(
+        home_gateways(&outcome),
+        vec![(gateway_ids[0], gateway_ids[0]); 6]
+    );
+    assert!(outcome
+        .diagnostics
+        .iter()
+        .all(|diagnostic| diagnostic.code != Code::NodeGatewayFlapping));
+}
+
+#[test]
+fn node_firmware_inventory() {
+    let gateways = vec![gateway(0x1201, 3)];
+    let gateway_id = gateways[0].id;
+    let string_responses = [
+        (2, "Mnode Version K8.0120 (2D)\r"),
+        (
+            2,
+            "!Info 0000 15 0000 0981 00 0000 0000 FF 00 0000 0FFF 000 2",
+        ),
+        (3, "Mnode Version K8.0120 (2D)\r"),
+        (2, "Mnode Version K8.0120 (2D)\r"),
+        (3, "Mnode Version K8.0130 (2D)\r"),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, (node_id, response))| StringResponse {
+        gateway_id,
+        node_id: NodeID::try_from(node_id).unwrap(),
+        slot_counter: slot_counter(12000 + i as u32 * 1000),
+        response: response.into(),
+    })
+    .collect();
+    let scenario = Scenario {
+        power_reports: power_reports(&gateways),
+        string_responses,
+        gateways,
+        ..Scenario::new(start())
+    };
+
+    let outcome = scenario.assert_roundtrip();
+
+    // Repeating a known firmware version says nothing new
+    let identities: Vec<_> = outcome
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            Event::NodeIdentity(event) => Some((u16::from(event.node.id), event.firmware.clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        identities,
+        vec![
+            (2, Some("Mnode Version K8.0120 (2D)".into())),
+            (2, Some("Mnode Version K8.0120 (2D)".into())),
+            (3, Some("Mnode Version K8.0120 (2D)".into())),
+            (3, Some("Mnode Version K8.0130 (2D)".into())),
+        ]
+    );
+
+    // Only the differing response is a change
+    let [diagnostic] = &outcome.diagnostics[..] else {
+        panic!("unexpected diagnostics: {:?}", outcome.diagnostics);
+    };
+    assert_eq!(diagnostic.code, Code::NodeFirmwareChanged);
+    assert_eq!(
+        diagnostic.context["previous_firmware"],
+        "Mnode Version K8.0120 (2D)"
+    );
+    assert_eq!(diagnostic.context["firmware"], "Mnode Version K8.0130 (2D)");
+
+    let inventory: Vec<_> = outcome
+        .snapshot
+        .nodes
+        .iter()
+        .map(|node| {
+            (
+                u16::from(node.id),
+                node.firmware.as_deref(),
+                node.responses
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        inventory,
+        vec![
+            (2, Some("Mnode Version K8.0120 (2D)"), vec!["Info"]),
+            (3, Some("Mnode Version K8.0130 (2D)"), vec![]),
+            (4, None, vec![]),
+        ]
+    );
+}
@@ -0,0 +1,167 @@
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+use taptap::gateway::physical::tcp::{Connection, IdleTimeout};
+use taptap::gateway::GatewayID;
+use taptap::observer::clock::ManualClock;
+use taptap::observer::event::Event;
+use taptap::observer::Observer;
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::run::{run_observe, spawn_observe, CancellationToken, Stopped};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+use taptap::testing::MockConnection;
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+/// A gateway with twenty nodes, each reporting once.
+fn scenario() -> Scenario {
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let nodes: Vec<Node> = (2..22)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let power_reports = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| PowerReport {
+            gateway_id,
+            node_id: node.id,
+            slot_counter: SlotCounter::from(i as u16 * 500),
+            measurement: Measurement {
+                voltage_in: 30.0,
+                voltage_out: 29.0,
+                current: 6.5,
+                dc_dc_duty_cycle: 1.0,
+                temperature: 25.0,
+                rssi: RSSI(120),
+            },
+        })
+        .collect();
+    Scenario {
+        enumerate: false,
+        walk_node_tables: false,
+        gateways: vec![Gateway {
+            id: gateway_id,
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+            version: "Mgate Version G8.59\r".into(),
+            nodes,
+        }],
+        power_reports,
+        ..Scenario::new(start())
+    }
+}
+
+/// An observer delivering its events to a channel, and a clock for a connection to drive.
+fn observer() -> (Observer, ManualClock, std::sync::mpsc::Receiver<Event>) {
+    let clock = ManualClock::new(start());
+    let (tx, events) = std::sync::mpsc::channel();
+    let mut observer = Observer::with_event_sink(tx);
+    observer.set_clock(clock.clone());
+    (observer, clock, events)
+}
+
+/// A source which cancels a token after a given number of reads.
+struct CancelAfter<R> {
+    source: R,
+    reads: usize,
+    cancel: CancellationToken,
+}
+
+impl<R: Read> Read for CancelAfter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads = self.reads.saturating_sub(1);
+        if self.reads == 0 {
+            self.cancel.cancel();
+        }
+        self.source.read(buf)
+    }
+}
+
+#[test]
+fn end_of_input() {
+    let scenario = scenario();
+    let (observer, clock, events) = observer();
+    let conn = MockConnection::from_stream(&scenario.encode(), clock);
+
+    let finished = spawn_observe(conn, observer, CancellationToken::new())
+        .join()
+        .unwrap()
+        .unwrap();
+    assert_eq!(finished.stopped, Stopped::EndOfInput);
+    drop(finished);
+
+    assert_eq!(
+        events.iter().collect::<Vec<_>>(),
+        scenario.expected_events()
+    );
+}
+
+#[test]
+fn cancelled_mid_way() {
+    let scenario = scenario();
+    let expected = scenario.expected_events();
+    let (observer, clock, events) = observer();
+    let cancel = CancellationToken::new();
+    let source = CancelAfter {
+        source: MockConnection::from_stream(&scenario.encode(), clock),
+        reads: 10,
+        cancel: cancel.clone(),
+    };
+
+    let finished = run_observe(source, observer, &cancel).unwrap();
+    assert_eq!(finished.stopped, Stopped::Cancelled);
+    let counters = taptap::Counters::snapshot(&finished.pipeline);
+    drop(finished);
+
+    // Some but not all of the events were emitted, exactly as they would have been otherwise
+    let events: Vec<_> = events.iter().collect();
+    assert!(!events.is_empty());
+    assert!(events.len() < expected.len());
+    assert_eq!(events, expected[..events.len()]);
+    assert_eq!(counters.application.power_reports, events.len() as u64);
+}
+
+#[test]
+fn cancelled_from_another_thread() {
+    /// A source which never ends.
+    struct Idle;
+    impl Read for Idle {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(1));
+            Err(std::io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    let cancel = CancellationToken::new();
+    let handle = spawn_observe(Idle, Observer::with_event_sink(Vec::new()), cancel.clone());
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(!handle.is_finished());
+
+    cancel.cancel();
+    let finished = handle.join().unwrap().unwrap();
+    assert_eq!(finished.stopped, Stopped::Cancelled);
+}
+
+#[test]
+fn idle_timeout() {
+    // A TCP connection which idles out is lost, unlike a serial port which merely timed out
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut conn = Connection::connect(listener.local_addr().unwrap(), true).unwrap();
+    let (_stream, _) = listener.accept().unwrap();
+    conn.set_idle_timeout(Some(Duration::from_millis(50)))
+        .unwrap();
+
+    let e = spawn_observe(
+        conn,
+        Observer::with_event_sink(Vec::new()),
+        CancellationToken::new(),
+    )
+    .join()
+    .unwrap()
+    .unwrap_err();
+    assert!(IdleTimeout::is(&e));
+}
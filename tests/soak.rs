@@ -0,0 +1,121 @@
+//! Soaking the full pipeline with simulated traffic, checking its invariants throughout.
+
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+use taptap::gateway::GatewayID;
+use taptap::observer::clock::ManualClock;
+use taptap::observer::{diagnostic, Observer};
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::soak::{Invariant, Report, Soak};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+const NODES: u16 = 10;
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+fn slot_counter(absolute_slot: u32) -> SlotCounter {
+    let absolute_slot = absolute_slot % 48000;
+    let epoch = (absolute_slot / 12000) as u16;
+    let slot_number = (absolute_slot % 12000) as u16;
+    SlotCounter::from(epoch << 14 | slot_number)
+}
+
+/// Every node reporting every 20 seconds for `rounds` rounds.
+fn scenario(rounds: u32) -> Scenario {
+    let nodes: Vec<Node> = (2..2 + NODES)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let gateway = Gateway {
+        id: GatewayID::try_from(0x1201).unwrap(),
+        address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+        version: "Mgate Version G8.59\rJul  6 2020\r16:51:51\rGW-H158.4.3S0.12\r".into(),
+        nodes: nodes.clone(),
+    };
+    let power_reports = (0..rounds)
+        .flat_map(|round| {
+            nodes.iter().enumerate().map(move |(i, node)| PowerReport {
+                gateway_id: GatewayID::try_from(0x1201).unwrap(),
+                node_id: node.id,
+                // 4000 slots is 20 seconds
+                slot_counter: slot_counter(round * 4000 + i as u32 * (4000 / NODES as u32)),
+                measurement: Measurement {
+                    voltage_in: 30.0,
+                    voltage_out: 29.0,
+                    current: 6.5,
+                    dc_dc_duty_cycle: 1.0,
+                    temperature: 25.0,
+                    rssi: RSSI(120),
+                },
+            })
+        })
+        .collect();
+    Scenario {
+        gateways: vec![gateway],
+        power_reports,
+        ..Scenario::new(start())
+    }
+}
+
+/// Run a scenario through the pipeline under a soak, optionally with the capture clock stepping
+/// back by some amount after some capture time.
+fn soak(scenario: &Scenario, step: Option<(Duration, Duration)>) -> Report {
+    let clock = ManualClock::new(start());
+    let (tx, events) = mpsc::channel();
+    let mut observer = Observer::default();
+    observer.set_clock(clock.clone());
+    observer.set_event_sink(tx);
+    observer.set_diagnostics_output(diagnostic::Output::Discard);
+    let mut rx = taptap::pipeline(observer);
+
+    let mut soak = Soak::default();
+    let stream = scenario.encode();
+    let mut at = start();
+    for (i, &(offset, time)) in stream.times.iter().enumerate() {
+        let end = stream
+            .times
+            .get(i + 1)
+            .map_or(stream.bytes.len(), |(end, _)| *end);
+        at = match step {
+            Some((after, step)) if time >= start() + after => time - step,
+            _ => time,
+        };
+        clock.set(at);
+        rx.extend_from_slice(&stream.bytes[offset..end]);
+        for event in events.try_iter() {
+            soak.event(&event, at);
+        }
+        soak.sample(&rx, at);
+    }
+    soak.finish(&rx, at)
+}
+
+#[test]
+fn steady() {
+    // Two hours of reports
+    let report = soak(&scenario(360), None);
+    assert!(report.passed(), "{}", report);
+    assert!(report.events > 360 * NODES as u64, "{}", report.events);
+    assert!(report.samples >= 100, "{}", report.samples);
+}
+
+#[test]
+fn capture_clock_steps_backwards() {
+    let report = soak(
+        &scenario(60),
+        Some((Duration::from_secs(600), Duration::from_secs(60))),
+    );
+    assert!(!report.passed());
+    assert_eq!(
+        report.counts.keys().copied().collect::<Vec<_>>(),
+        vec![Invariant::EventOrder]
+    );
+    // Every node steps back once
+    assert_eq!(report.counts[&Invariant::EventOrder], NODES as u64);
+    assert!(report.to_string().contains("event_order    FAILED"));
+}
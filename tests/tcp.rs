@@ -0,0 +1,74 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+use taptap::gateway::physical::tcp::{Connection, IdleTimeout, ReconnectingConnection};
+
+fn read_some(conn: &mut impl Read) -> Vec<u8> {
+    let mut buffer = [0u8; 64];
+    let n = conn.read(&mut buffer).unwrap();
+    buffer[..n].to_vec()
+}
+
+#[test]
+fn idle_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut conn = Connection::connect(listener.local_addr().unwrap(), true).unwrap();
+    let (_stream, _) = listener.accept().unwrap();
+
+    conn.set_idle_timeout(Some(Duration::from_millis(50)))
+        .unwrap();
+    let e = conn.read(&mut [0u8; 16]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(IdleTimeout::is(&e));
+
+    // Read-only connections refuse to write
+    assert_eq!(
+        conn.write(b"hi").unwrap_err().kind(),
+        ErrorKind::Unsupported
+    );
+}
+
+#[test]
+fn reconnects() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        // Close the first connection after sending some data
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"abc").unwrap();
+        drop(stream);
+
+        // Wedge the second, sending nothing until the client gives up on it
+        let (mut wedged, _) = listener.accept().unwrap();
+
+        // Serve the third
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"def").unwrap();
+        wedged.write_all(b"stale").ok();
+        stream
+    });
+
+    let mut conn =
+        ReconnectingConnection::connect("127.0.0.1", port, false, Some(Duration::from_millis(200)))
+            .unwrap();
+    conn.set_backoff(Duration::from_millis(10), Duration::from_millis(40));
+    assert_eq!(read_some(&mut conn), b"abc");
+    assert_eq!(read_some(&mut conn), b"def");
+    assert_eq!(conn.reconnections(), 2);
+
+    // Writes go to the current connection
+    let mut stream = server.join().unwrap();
+    conn.write_all(b"ping").unwrap();
+    let mut received = [0u8; 4];
+    stream.read_exact(&mut received).unwrap();
+    assert_eq!(&received, b"ping");
+}
+
+#[test]
+fn first_connection_fails_fast() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    assert!(ReconnectingConnection::connect("127.0.0.1", port, true, None).is_err());
+}
@@ -0,0 +1,193 @@
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime};
+use taptap::config::{ConnectionMode, TcpConnectionConfig};
+use taptap::gateway::GatewayID;
+use taptap::observer::clock::ManualClock;
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+use taptap::Client;
+use tokio::io::{AsyncRead, AsyncWriteExt, DuplexStream, ReadBuf};
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200)
+}
+
+/// A gateway with five nodes, each reporting once.
+fn scenario() -> Scenario {
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let nodes: Vec<Node> = (2..7)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let power_reports = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| PowerReport {
+            gateway_id,
+            node_id: node.id,
+            slot_counter: SlotCounter::from(i as u16 * 500),
+            measurement: Measurement {
+                voltage_in: 30.0,
+                voltage_out: 29.0,
+                current: 6.5,
+                dc_dc_duty_cycle: 1.0,
+                temperature: 25.0,
+                rssi: RSSI(120),
+            },
+        })
+        .collect();
+    Scenario {
+        gateways: vec![Gateway {
+            id: gateway_id,
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+            version: "Mgate Version G8.59\r".into(),
+            nodes,
+        }],
+        power_reports,
+        ..Scenario::new(start())
+    }
+}
+
+/// The reading end of a pipe, setting a clock to the time at which each part of a stream appeared.
+struct Timed {
+    pipe: DuplexStream,
+    times: Vec<(usize, SystemTime)>,
+    offset: usize,
+    clock: ManualClock,
+}
+
+impl AsyncRead for Timed {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+
+        // Read no further than the next change of time
+        let next = this
+            .times
+            .partition_point(|(offset, _)| *offset <= this.offset);
+        if let Some((_, time)) = next.checked_sub(1).map(|i| this.times[i]) {
+            this.clock.set(time);
+        }
+        let limit = this
+            .times
+            .get(next)
+            .map_or(usize::MAX, |(offset, _)| offset - this.offset);
+
+        let mut part = [0u8; 256];
+        let len = part.len().min(buf.remaining()).min(limit);
+        let mut part = ReadBuf::new(&mut part[..len]);
+        ready!(Pin::new(&mut this.pipe).poll_read(cx, &mut part))?;
+        buf.put_slice(part.filled());
+        this.offset += part.filled().len();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn duplex() {
+    let scenario = scenario();
+    let stream = scenario.encode();
+    let clock = ManualClock::new(start());
+
+    // Write the stream through a small pipe, so that it arrives in pieces
+    let (mut tx, rx) = tokio::io::duplex(64);
+    let reader = Timed {
+        pipe: rx,
+        times: stream.times,
+        offset: 0,
+        clock: clock.clone(),
+    };
+    let writer = tokio::spawn(async move { tx.write_all(&stream.bytes).await });
+
+    let mut client = Client::builder()
+        .async_reader(reader)
+        .clock(clock)
+        .spawn_async();
+    let mut events = Vec::new();
+    while let Some(event) = client.next_event().await {
+        events.push(event.unwrap());
+    }
+    writer.await.unwrap().unwrap();
+    assert_eq!(events, scenario.expected_events());
+}
+
+#[tokio::test]
+async fn blocking_reader() {
+    let scenario = scenario();
+    let clock = ManualClock::new(start());
+    let conn = taptap::testing::MockConnection::from_stream(&scenario.encode(), clock.clone());
+
+    // A blocking reader works too, on the blocking thread pool
+    let mut client = Client::builder().reader(conn).clock(clock).spawn_async();
+    let mut events = Vec::new();
+    while let Some(event) = client.next_event().await {
+        events.push(event.unwrap());
+    }
+    assert_eq!(events, scenario.expected_events());
+}
+
+#[tokio::test]
+async fn connect_failure() {
+    // Find a port with nothing listening on it
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = TcpConnectionConfig {
+        hostname: "127.0.0.1".into(),
+        port,
+        mode: ConnectionMode::ReadOnly,
+        reconnect: false,
+        idle_timeout_secs: None,
+    };
+
+    let mut client = Client::builder().source(config.clone()).spawn_async();
+    assert!(matches!(client.next_event().await, Some(Err(_))));
+    assert!(client.next_event().await.is_none());
+
+    // Reconnecting isn't available asynchronously
+    let mut client = Client::builder()
+        .source(TcpConnectionConfig {
+            reconnect: true,
+            ..config
+        })
+        .spawn_async();
+    let Some(Err(e)) = client.next_event().await else {
+        panic!("expected an error");
+    };
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+}
+
+#[tokio::test]
+async fn stop() {
+    // A pipe which stays open without ever being written
+    let (_tx, rx) = tokio::io::duplex(64);
+
+    let mut client = Client::builder().async_reader(rx).spawn_async();
+    let stop = client.stop_handle();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stop.cancel();
+    });
+
+    assert!(client.next_event().await.is_none());
+}
+
+#[test]
+fn async_reader_requires_async_client() {
+    let (_tx, rx) = tokio::io::duplex(64);
+    let mut client = Client::builder().async_reader(rx).spawn();
+    let Some(Err(e)) = client.next() else {
+        panic!("expected an error");
+    };
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+}
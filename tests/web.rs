@@ -0,0 +1,142 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+use taptap::gateway::GatewayID;
+use taptap::observer::event::Event;
+use taptap::observer::EventSink;
+use taptap::output::web::{Hub, Server, WebSink};
+use taptap::pv::physical::RSSI;
+use taptap::pv::{LongAddress, NodeID, SlotCounter};
+use taptap::testing::roundtrip::{Gateway, Measurement, Node, PowerReport, Scenario};
+
+/// Every event from a gateway whose nodes each report once, and the observer's final snapshot.
+fn run(count: u16) -> taptap::testing::roundtrip::Outcome {
+    let gateway_id = GatewayID::try_from(0x1201).unwrap();
+    let nodes: Vec<Node> = (2..2 + count)
+        .map(|id| Node {
+            id: NodeID::try_from(id).unwrap(),
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x40, 0x00, 0xA2, 0x00, id as u8]),
+        })
+        .collect();
+    let power_reports = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| PowerReport {
+            gateway_id,
+            node_id: node.id,
+            slot_counter: SlotCounter::from(i as u16 * 500),
+            measurement: Measurement {
+                voltage_in: 30.0,
+                voltage_out: 29.5,
+                current: 6.5,
+                dc_dc_duty_cycle: 1.0,
+                temperature: 25.0,
+                rssi: RSSI(120),
+            },
+        })
+        .collect();
+    let scenario = Scenario {
+        gateways: vec![Gateway {
+            id: gateway_id,
+            address: LongAddress([0x04, 0xC0, 0x5B, 0x30, 0x00, 0x02, 0x12, 0x01]),
+            version: "Mgate Version G8.59\r".into(),
+            nodes,
+        }],
+        power_reports,
+        ..Scenario::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1724497200))
+    };
+    scenario.run()
+}
+
+fn request(server: &Server, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    stream
+}
+
+fn get(server: &Server, path: &str) -> String {
+    let mut response = String::new();
+    request(server, path).read_to_string(&mut response).unwrap();
+    response
+}
+
+/// Read an event stream's headers, then its next `count` events.
+fn read_events(reader: &mut impl BufRead, count: usize) -> Vec<String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+    }
+    let mut events = Vec::new();
+    while events.len() < count {
+        line.clear();
+        assert_ne!(reader.read_line(&mut line).unwrap(), 0, "stream ended");
+        if let Some(data) = line.strip_prefix("data: ") {
+            events.push(data.trim_end().to_string());
+        }
+    }
+    events
+}
+
+#[test]
+fn serves_dashboard_and_state() {
+    let outcome = run(3);
+    let hub = Hub::new();
+    let server = Server::bind("127.0.0.1:0", hub.clone()).unwrap();
+
+    let response = get(&server, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: text/html"));
+    assert!(response.contains("EventSource(\"/events\")"));
+
+    // There's no state until the owner of the observer provides one
+    assert!(get(&server, "/state").starts_with("HTTP/1.1 503 "));
+    hub.refresh_state(Instant::now(), || outcome.snapshot.clone());
+    let response = get(&server, "/state");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert_eq!(body, serde_json::to_string(&outcome.snapshot).unwrap());
+
+    // Refreshing again straight away is skipped
+    hub.refresh_state(Instant::now(), || unreachable!());
+
+    assert!(get(&server, "/nope").starts_with("HTTP/1.1 404 "));
+}
+
+#[test]
+fn streams_events() {
+    let events = run(3).events;
+    let hub = Hub::with_capacity(4);
+    let (tx, rx) = mpsc::channel();
+    let mut sink = WebSink::new(hub.clone(), tx);
+    for event in &events {
+        sink.event(event.clone());
+    }
+    assert_eq!(rx.try_iter().count(), events.len());
+    assert_eq!(hub.recent_len(), 4);
+
+    // A new subscriber gets the most recent events first
+    let server = Server::bind("127.0.0.1:0", hub.clone()).unwrap();
+    let mut reader = BufReader::new(request(&server, "/events"));
+    let expected: Vec<String> = events[events.len() - 4..]
+        .iter()
+        .map(Event::to_json)
+        .collect();
+    assert_eq!(read_events(&mut reader, 4), expected);
+
+    // ...then each new event as it happens
+    sink.event(events[0].clone());
+    let mut line = String::new();
+    while !line.starts_with("data: ") {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+    }
+    assert_eq!(line.trim_end(), format!("data: {}", events[0].to_json()));
+}